@@ -0,0 +1,333 @@
+//! Dashboard for Framework laptops
+//!
+//! Shows the firmware versions, battery and power draw, temperatures and fan
+//! speeds, and has sliders for the charge limit and keyboard backlight. All
+//! hardware access goes through framework_lib, just like `framework_tool`.
+//!
+//! The EC is polled on a background thread, so that a slow EC doesn't freeze
+//! the window. Accessing the EC needs the same permissions as `framework_tool`.
+
+// Don't open a console window next to the GUI on Windows
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+
+use framework_lib::chromium_ec::CrosEc;
+use framework_lib::output::StringSink;
+use framework_lib::power::{self, PowerInfo};
+use framework_lib::report::{BiosVersionReport, EcVersionReport, PdVersionsReport};
+
+/// How often the EC is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many samples the graphs show, 10 minutes at the poll interval
+const HISTORY_LEN: usize = 600;
+
+/// Readings of a single poll
+struct Sample {
+    /// Seconds since the dashboard was started
+    time: f64,
+    power: Option<PowerInfo>,
+    temps: Vec<(&'static str, Option<u8>)>,
+    fans: Vec<u16>,
+}
+
+/// State that the poll thread updates and the UI shows
+#[derive(Default)]
+struct Shared {
+    /// Output of the version reports, as `framework_tool --versions` shows it
+    versions: Option<String>,
+    history: VecDeque<Sample>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Tab {
+    Versions,
+    Power,
+    Thermal,
+    Settings,
+}
+
+struct Dashboard {
+    ec: CrosEc,
+    shared: Arc<Mutex<Shared>>,
+    tab: Tab,
+    /// Current value of the sliders, None if the EC doesn't support it
+    charge_limit: Option<u8>,
+    kblight: Option<u8>,
+    /// Result of the last change from the settings tab
+    status: Option<String>,
+}
+
+fn read_versions(ec: &CrosEc) -> String {
+    let mut out = StringSink::default();
+    BiosVersionReport::read().render(&mut out);
+    EcVersionReport::read(ec, false).render(&mut out, false);
+    PdVersionsReport::read(ec).render(&mut out);
+    out.buffer
+}
+
+fn read_sample(ec: &CrosEc, start: Instant) -> Sample {
+    Sample {
+        time: start.elapsed().as_secs_f64(),
+        power: power::power_info(ec),
+        temps: power::get_temperatures(ec).unwrap_or_default(),
+        fans: power::get_fan_speeds(ec).unwrap_or_default(),
+    }
+}
+
+/// Poll the EC forever, waking up the UI after each sample
+fn poll(ec: CrosEc, shared: Arc<Mutex<Shared>>, ctx: egui::Context) {
+    let versions = read_versions(&ec);
+    shared.lock().unwrap().versions = Some(versions);
+
+    let start = Instant::now();
+    loop {
+        let sample = read_sample(&ec, start);
+        {
+            let mut shared = shared.lock().unwrap();
+            if shared.history.len() == HISTORY_LEN {
+                shared.history.pop_front();
+            }
+            shared.history.push_back(sample);
+        }
+        ctx.request_repaint();
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn format_minutes(minutes: u32) -> String {
+    format!("{}h {:02}min", minutes / 60, minutes % 60)
+}
+
+/// Line for the graph of one value over time
+fn line(history: &VecDeque<Sample>, name: &str, value: impl Fn(&Sample) -> Option<f64>) -> Line {
+    let points: PlotPoints = history
+        .iter()
+        .filter_map(|sample| value(sample).map(|y| [sample.time, y]))
+        .collect();
+    Line::new(points).name(name)
+}
+
+impl Dashboard {
+    fn new(cc: &eframe::CreationContext) -> Self {
+        let ec = CrosEc::new();
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let poll_ec = ec.clone();
+        let poll_shared = shared.clone();
+        let ctx = cc.egui_ctx.clone();
+        thread::spawn(move || poll(poll_ec, poll_shared, ctx));
+
+        Dashboard {
+            charge_limit: ec.get_charge_limit().ok().map(|(_min, max)| max),
+            kblight: ec.get_keyboard_backlight().ok(),
+            ec,
+            shared,
+            tab: Tab::Versions,
+            status: None,
+        }
+    }
+
+    fn versions(&self, ui: &mut egui::Ui, shared: &Shared) {
+        match &shared.versions {
+            Some(versions) => {
+                ui.monospace(versions);
+            }
+            None => {
+                ui.spinner();
+            }
+        }
+    }
+
+    fn power(&self, ui: &mut egui::Ui, shared: &Shared) {
+        let Some(info) = shared.history.back().and_then(|s| s.power.as_ref()) else {
+            ui.label("Failed to read power information");
+            return;
+        };
+        egui::Grid::new("power").striped(true).show(ui, |ui| {
+            ui.label("AC adapter");
+            ui.label(if info.ac_present {
+                "Connected"
+            } else {
+                "Not connected"
+            });
+            ui.end_row();
+
+            let Some(battery) = &info.battery else {
+                ui.label("Battery");
+                ui.label("No battery");
+                ui.end_row();
+                return;
+            };
+            ui.label("Battery");
+            ui.label(format!("{}%", battery.charge_percentage));
+            ui.end_row();
+            ui.label("State");
+            ui.label(if battery.charging {
+                "Charging"
+            } else if battery.discharging {
+                "Discharging"
+            } else {
+                "Idle"
+            });
+            ui.end_row();
+            ui.label("Power");
+            ui.label(format!("{:.1} W", battery.power_mw() as f32 / 1000.0));
+            ui.end_row();
+            if let Some(minutes) = battery.time_to_empty_min() {
+                ui.label("Time to empty");
+                ui.label(format_minutes(minutes));
+                ui.end_row();
+            }
+            if let Some(minutes) = battery.time_to_full_min() {
+                ui.label("Time to full");
+                ui.label(format_minutes(minutes));
+                ui.end_row();
+            }
+            ui.label("Cycle count");
+            ui.label(battery.cycle_count.to_string());
+            ui.end_row();
+        });
+
+        if info.battery.is_none() {
+            return;
+        }
+        ui.separator();
+        let battery = |sample: &Sample| sample.power.as_ref()?.battery.clone();
+        Plot::new("battery")
+            .legend(Legend::default())
+            .height(ui.available_height())
+            .include_y(0.0)
+            .include_y(100.0)
+            .show(ui, |plot| {
+                plot.line(line(&shared.history, "Charge (%)", |s| {
+                    battery(s).map(|b| b.charge_percentage as f64)
+                }));
+                plot.line(line(&shared.history, "Power (W)", |s| {
+                    battery(s).map(|b| b.power_mw() as f64 / 1000.0)
+                }));
+            });
+    }
+
+    fn thermal(&self, ui: &mut egui::Ui, shared: &Shared) {
+        let Some(sample) = shared.history.back() else {
+            ui.spinner();
+            return;
+        };
+        egui::Grid::new("thermal").striped(true).show(ui, |ui| {
+            for (name, temp) in &sample.temps {
+                ui.label(*name);
+                ui.label(match temp {
+                    Some(celsius) => format!("{} °C", celsius),
+                    None => "Not available".to_string(),
+                });
+                ui.end_row();
+            }
+            for (i, rpm) in sample.fans.iter().enumerate() {
+                ui.label(format!("Fan {}", i));
+                ui.label(format!("{} RPM", rpm));
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+        Plot::new("thermal")
+            .legend(Legend::default())
+            .height(ui.available_height())
+            .include_y(0.0)
+            .show(ui, |plot| {
+                for (i, (name, _)) in sample.temps.iter().enumerate() {
+                    plot.line(line(&shared.history, name, |s| {
+                        s.temps.get(i)?.1.map(|celsius| celsius as f64)
+                    }));
+                }
+            });
+    }
+
+    fn settings(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Battery charge limit");
+        match &mut self.charge_limit {
+            Some(limit) => {
+                let res = ui.add(egui::Slider::new(limit, 25..=100).suffix("%"));
+                // Only write to the EC once the user let go of the slider
+                if res.drag_stopped() || (res.changed() && !res.dragged()) {
+                    let limit = *limit;
+                    self.status = Some(
+                        match self
+                            .ec
+                            .get_charge_limit()
+                            .and_then(|(min, _max)| self.ec.set_charge_limit(min, limit))
+                        {
+                            Ok(()) => format!("Charge limit set to {}%", limit),
+                            Err(err) => format!("Failed to set charge limit: {:?}", err),
+                        },
+                    );
+                }
+            }
+            None => {
+                ui.label("Not supported on this system");
+            }
+        }
+
+        ui.add_space(10.0);
+        ui.heading("Keyboard backlight");
+        match &mut self.kblight {
+            Some(percent) => {
+                let res = ui.add(egui::Slider::new(percent, 0..=100).suffix("%"));
+                if res.changed() {
+                    self.ec.set_keyboard_backlight(*percent);
+                }
+            }
+            None => {
+                ui.label("Not supported on this system");
+            }
+        }
+
+        if let Some(status) = &self.status {
+            ui.add_space(10.0);
+            ui.label(status);
+        }
+    }
+}
+
+impl eframe::App for Dashboard {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Versions, "Versions");
+                ui.selectable_value(&mut self.tab, Tab::Power, "Power");
+                ui.selectable_value(&mut self.tab, Tab::Thermal, "Thermal");
+                ui.selectable_value(&mut self.tab, Tab::Settings, "Settings");
+            });
+        });
+
+        let shared = self.shared.clone();
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let shared = shared.lock().unwrap();
+            match self.tab {
+                Tab::Versions => self.versions(ui, &shared),
+                Tab::Power => self.power(ui, &shared),
+                Tab::Thermal => self.thermal(ui, &shared),
+                Tab::Settings => self.settings(ui),
+            }
+        });
+    }
+}
+
+fn main() -> eframe::Result {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([640.0, 480.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "Framework System",
+        options,
+        Box::new(|cc| Ok(Box::new(Dashboard::new(cc)))),
+    )
+}
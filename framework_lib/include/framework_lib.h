@@ -0,0 +1,105 @@
+#ifndef FRAMEWORK_LIB_H
+#define FRAMEWORK_LIB_H
+
+/* Generated with cbindgen from src/ffi.rs, don't edit manually */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ * Result of a function, same values as the exit codes of `framework_tool`
+ */
+typedef enum FwStatus {
+  FwStatus_Ok = 0,
+  /*
+   * Failed for a reason not covered by the other codes
+   */
+  FwStatus_Failure = 1,
+  /*
+   * Invalid argument, e.g. a NULL pointer or value out of range
+   */
+  FwStatus_InvalidArgument = 2,
+  /*
+   * Could not communicate with the EC
+   */
+  FwStatus_EcUnreachable = 3,
+  /*
+   * Not allowed to access the EC, usually have to be root/administrator
+   */
+  FwStatus_Permission = 4,
+  /*
+   * Firmware read back after flashing doesn't match the file
+   */
+  FwStatus_FlashVerify = 5,
+  /*
+   * Not supported by the system or its firmware
+   */
+  FwStatus_Unsupported = 6,
+} FwStatus;
+
+/*
+ * Called with the number of bytes done and the total, while flashing
+ */
+typedef void (*FwProgressCallback)(uintptr_t done, uintptr_t total, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ * Versions of BIOS, EC and PD firmware as a JSON object
+ *
+ * Versions that can't be read are `null`. Free the result with `fw_free_string`.
+ */
+char *fw_get_versions_json(void);
+
+/*
+ * AC and battery status as a JSON object
+ *
+ * Returns NULL if the EC can't be read. Free the result with `fw_free_string`.
+ */
+char *fw_get_power_info_json(void);
+
+/*
+ * Set the maximum battery charge limit in percent, between 25 and 100
+ */
+enum FwStatus fw_set_charge_limit(uint8_t max);
+
+/*
+ * Flash the EC firmware file at `path`
+ *
+ * `flash_type` is 0 to flash everything, 1 for only RO and 2 for only RW.
+ * `progress` is optional and called from the same thread, with `user_data`
+ * passed through.
+ *
+ * Same as `framework_tool --flash-ec`, flashing the EC from the OS is not
+ * supported yet. EC communication from the OS can't be trusted to reliably
+ * flash the EC without risk of damage, so every valid `flash_type` returns
+ * `FwStatus::Unsupported`.
+ *
+ * # Safety
+ *
+ * `path` must be a valid, NUL terminated string.
+ */
+enum FwStatus fw_flash_ec(const char *path,
+                          uint32_t flash_type,
+                          FwProgressCallback _progress,
+                          void *_user_data);
+
+/*
+ * Free a string returned by the library
+ *
+ * # Safety
+ *
+ * `s` must have been returned by one of the functions of this library and
+ * not been freed before. NULL is ignored.
+ */
+void fw_free_string(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FRAMEWORK_LIB_H */
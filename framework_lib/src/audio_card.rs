@@ -1,5 +1,6 @@
 use core::time::Duration;
 
+use crate::devices::{self, Devices};
 use crate::util;
 use rusb::{DeviceHandle, Direction, GlobalContext, Recipient, RequestType};
 
@@ -37,16 +38,20 @@ struct HidCapeMessage {
 /// Unfortunately this needs to open the USB device and claim the interface,
 /// as well as detach currently connected kernel drivers.
 /// This is most likely the case because it's using the Consumer Control usage page.
-pub fn check_synaptics_fw_version() {
+pub fn check_synaptics_fw_version(devices: &Devices) {
     let mut audio_cards = 0;
-    for dev in rusb::devices().unwrap().iter() {
-        let dev_descriptor = dev.device_descriptor().unwrap();
-        if dev_descriptor.vendor_id() != FRAMEWORK_VID
-            || dev_descriptor.product_id() != AUDIO_CARD_PID
-        {
-            continue;
-        }
-        let handle = dev.open().unwrap();
+    for dev in devices.usb(FRAMEWORK_VID, AUDIO_CARD_PID) {
+        let handle = match dev.open() {
+            Ok(handle) => handle,
+            Err(rusb::Error::Access) => {
+                devices::print_permission_hint("Audio Expansion Card");
+                continue;
+            }
+            Err(err) => {
+                error!("Couldn't open Framework Audio Card - {:?}", err);
+                continue;
+            }
+        };
 
         let interface_number = if let Some(num) = find_hid_interface(&handle) {
             num
@@ -111,7 +116,10 @@ pub fn check_synaptics_fw_version() {
             .product_string_index()
             .and_then(|x| handle.read_string_descriptor_ascii(x).ok());
         println!("  bcdDevice:        {}", dev_descriptor.device_version());
-        println!("  iSerial:          {:?}", i_serial.unwrap_or_default());
+        println!(
+            "  iSerial:          {:?}",
+            util::redact_serial(&i_serial.unwrap_or_default())
+        );
         println!("  iProduct          {:?}", i_product.unwrap_or_default());
     }
 
@@ -0,0 +1,168 @@
+//! Keep a record of commands that change the state of the system
+//!
+//! On shared machines, admins need to know who flashed firmware or changed
+//! settings, and when. Every such command appends one line of JSON to the
+//! audit log, so it can be processed with tools like `jq`. Commands that only
+//! read are not recorded.
+//!
+//! Failing to write the log is reported, but doesn't stop the command.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::util::ConfigFile;
+
+const AUDIT_LOG_NAME: &str = "framework_tool_audit.log";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// User that ran the command, if known
+    pub user: Option<String>,
+    /// Short name of the operation, like `flash_ec`
+    pub operation: String,
+    /// Arguments of the command, without the program name
+    pub args: Vec<String>,
+    /// Exit code of the command, see `commandline::ExitCode`
+    pub exit_code: i32,
+}
+
+impl AuditRecord {
+    /// Record of an operation that just finished, by the current user
+    pub fn new(operation: &str, args: Vec<String>, exit_code: i32) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        // With sudo, the user that actually ran it is more interesting than root
+        let user = ["SUDO_USER", "USER", "USERNAME"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok());
+        AuditRecord {
+            timestamp,
+            user,
+            operation: operation.to_string(),
+            args,
+            exit_code,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|x| json_string(x)).collect();
+        let user = match &self.user {
+            Some(user) => json_string(user),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"time\": \"{}\", \"user\": {}, \"operation\": {}, \"args\": [{}], \"exit_code\": {}}}",
+            fmt_utc(self.timestamp),
+            user,
+            json_string(&self.operation),
+            args.join(", "),
+            self.exit_code
+        )
+    }
+}
+
+/// Quote and escape a string for JSON
+pub(crate) fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 date and time in UTC
+fn fmt_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+    // Convert days to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Where the audit log is written, unless the config file says otherwise
+pub fn default_path() -> PathBuf {
+    #[cfg(windows)]
+    if let Some(dir) = std::env::var_os("ProgramData") {
+        return PathBuf::from(dir).join(AUDIT_LOG_NAME);
+    }
+    PathBuf::from("/var/log").join(AUDIT_LOG_NAME)
+}
+
+/// Append the record to the audit log, creating it if necessary
+pub fn append(path: &Path, record: &AuditRecord) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", record.to_json())
+}
+
+/// Append the record to the audit log from the config file, unless it's disabled
+pub fn write(record: &AuditRecord) {
+    let config = ConfigFile::load();
+    if config.audit_log == Some(false) {
+        return;
+    }
+    let path = config
+        .audit_log_path
+        .map(PathBuf::from)
+        .unwrap_or_else(default_path);
+    if let Err(err) = append(&path, record) {
+        warn!("Failed to write audit log {}: {:?}", path.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_record() {
+        let record = AuditRecord {
+            timestamp: 1_700_000_000,
+            user: Some("alice".to_string()),
+            operation: "flash_ec".to_string(),
+            args: vec!["--flash-ec".to_string(), "C:\\ec \"new\".bin".to_string()],
+            exit_code: 0,
+        };
+        assert_eq!(
+            record.to_json(),
+            "{\"time\": \"2023-11-14T22:13:20Z\", \"user\": \"alice\", \"operation\": \"flash_ec\", \
+             \"args\": [\"--flash-ec\", \"C:\\\\ec \\\"new\\\".bin\"], \"exit_code\": 0}"
+        );
+    }
+
+    #[test]
+    fn format_utc() {
+        assert_eq!(fmt_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(fmt_utc(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(fmt_utc(1_709_251_199), "2024-02-29T23:59:59Z");
+    }
+}
@@ -0,0 +1,146 @@
+//! Get and set selected BIOS settings from the OS
+//!
+//! The Insyde H2O BIOS keeps its setup options in a UEFI variable, whose layout
+//! is not documented and differs between BIOS versions. Writing to it blindly
+//! could leave the system unbootable. Only settings that the BIOS mirrors to
+//! the EC are supported, they take effect immediately.
+//!
+//! Changes don't persist. The BIOS sends its setup option to the EC again on
+//! every boot, overriding what was set here.
+//!
+//! Each platform has an allowlist of the settings that its BIOS and EC support.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+use core::prelude::v1::derive;
+
+use crate::chromium_ec::{CrosEc, EcResult};
+use crate::util::Platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiosSetting {
+    /// Emulate a PS/2 keyboard and touchpad, for OSes without I2C HID drivers
+    Ps2Emulation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BiosSettingValue {
+    Bool(bool),
+}
+
+impl fmt::Display for BiosSettingValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BiosSettingValue::Bool(true) => write!(f, "enabled"),
+            BiosSettingValue::Bool(false) => write!(f, "disabled"),
+        }
+    }
+}
+
+const ALL_SETTINGS: &[BiosSetting] = &[BiosSetting::Ps2Emulation];
+
+impl BiosSetting {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BiosSetting::Ps2Emulation => "ps2_emulation",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            BiosSetting::Ps2Emulation => "PS/2 keyboard and touchpad emulation (on/off)",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_SETTINGS.iter().find(|s| s.name() == name).copied()
+    }
+
+    /// Parse and validate a value given by the user
+    pub fn parse_value(&self, value: &str) -> Result<BiosSettingValue, String> {
+        match self {
+            BiosSetting::Ps2Emulation => match value {
+                "on" | "enable" | "enabled" | "1" => Ok(BiosSettingValue::Bool(true)),
+                "off" | "disable" | "disabled" | "0" => Ok(BiosSettingValue::Bool(false)),
+                _ => Err(format!("Invalid value: '{}', must be on or off", value)),
+            },
+        }
+    }
+
+    /// Read the current value, None if it can't be read back
+    pub fn get(&self, _ec: &CrosEc) -> EcResult<Option<BiosSettingValue>> {
+        match self {
+            BiosSetting::Ps2Emulation => Ok(None),
+        }
+    }
+
+    /// Takes effect immediately, until the next boot
+    pub fn set(&self, ec: &CrosEc, value: BiosSettingValue) -> EcResult<()> {
+        match (self, value) {
+            (BiosSetting::Ps2Emulation, BiosSettingValue::Bool(enable)) => {
+                ec.set_ps2_emulation(enable)
+            }
+        }
+    }
+}
+
+/// Settings that are supported on the platform
+pub fn platform_settings(platform: &Platform) -> &'static [BiosSetting] {
+    match platform {
+        Platform::IntelGen11 | Platform::IntelGen12 | Platform::IntelGen13 => {
+            &[BiosSetting::Ps2Emulation]
+        }
+        Platform::IntelCoreUltra1
+        | Platform::Framework13Amd
+        | Platform::Framework16
+        | Platform::DesktopAmdAi300
+        | Platform::GenericFramework(..) => &[],
+    }
+}
+
+/// Look up a setting by name and make sure that the platform supports it
+pub fn find_setting(platform: &Platform, name: &str) -> Result<BiosSetting, String> {
+    let setting = BiosSetting::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = ALL_SETTINGS.iter().map(|s| s.name()).collect();
+        format!(
+            "Unknown BIOS setting '{}'. Known settings: {}",
+            name,
+            names.join(", ")
+        )
+    })?;
+    if !platform_settings(platform).contains(&setting) {
+        return Err(format!("{} is not supported on {:?}", name, platform));
+    }
+    Ok(setting)
+}
+
+/// Format the current value for display
+pub fn fmt_value(value: &EcResult<Option<BiosSettingValue>>) -> String {
+    match value {
+        Ok(Some(value)) => value.to_string(),
+        Ok(None) => "Unknown (write-only)".to_string(),
+        Err(err) => format!("Failed to read ({:?})", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_validate() {
+        assert_eq!(BiosSetting::from_name("charge_limit"), None);
+
+        let ps2 = BiosSetting::from_name("ps2_emulation").unwrap();
+        assert_eq!(ps2.parse_value("off"), Ok(BiosSettingValue::Bool(false)));
+        assert!(ps2.parse_value("maybe").is_err());
+
+        assert_eq!(
+            find_setting(&Platform::IntelGen12, "ps2_emulation"),
+            Ok(BiosSetting::Ps2Emulation)
+        );
+        assert!(find_setting(&Platform::Framework16, "ps2_emulation").is_err());
+    }
+}
@@ -7,9 +7,13 @@
 //! with the specified GUID.
 //!
 //! Currently NOT implemented is parsing capsules with mutiple header structs!
+//!
+//! Capsules can also be built, to create test capsules or Windows UX capsules
+//! with a custom image.
 
 use std::prelude::v1::*;
 
+use alloc::vec;
 use core::prelude::rust_2021::derive;
 #[cfg(all(not(feature = "uefi"), feature = "std"))]
 use std::fs::File;
@@ -21,6 +25,8 @@ use crate::guid::Guid;
 #[cfg(feature = "uefi")]
 use uefi::Guid;
 
+use crate::units;
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(C)]
 pub struct EfiCapsuleHeader {
@@ -91,9 +97,15 @@ enum ImageType {
     Bitmap = 0,
 }
 
-const CAPSULE_FLAGS_PERSIST_ACROSS_RESET: u32 = 0x00010000;
-const CAPSULE_FLAGS_POPULATE_SYSTEM_TABLE: u32 = 0x00020000;
-const CAPSULE_FLAGS_INITIATE_RESET: u32 = 0x00040000;
+pub const CAPSULE_FLAGS_PERSIST_ACROSS_RESET: u32 = 0x00010000;
+pub const CAPSULE_FLAGS_POPULATE_SYSTEM_TABLE: u32 = 0x00020000;
+pub const CAPSULE_FLAGS_INITIATE_RESET: u32 = 0x00040000;
+
+/// Version of the display payload that EDK2 understands
+const DISPLAY_PAYLOAD_VERSION: u8 = 1;
+
+/// Size of the BMP file header and BITMAPINFOHEADER
+const BMP_HEADER_SIZE: usize = 14 + 40;
 
 fn print_capsule_flags(flags: u32) {
     if flags & CAPSULE_FLAGS_PERSIST_ACROSS_RESET != 0 {
@@ -137,10 +149,9 @@ pub fn print_capsule_header(header: &EfiCapsuleHeader) {
     }
     println!("  Flags:      {:>20}", format!("0x{:X}", header.flags));
     print_capsule_flags(header.flags);
-    println!("  Capsule Size: {:>18} B", header.capsule_image_size);
     println!(
-        "  Capsule Size: {:>18} KB",
-        header.capsule_image_size / 1024
+        "  Capsule Size: {:>18}",
+        units::fmt_size(header.capsule_image_size as usize)
     );
 }
 
@@ -169,8 +180,193 @@ pub fn print_ux_header(header: &DisplayCapsule) {
     println!("    Offset X:   {:>20}", { ux_header.offset_x });
     println!("    Offset Y:   {:>20}", { ux_header.offset_y });
     let image_size = header.capsule_header.capsule_image_size as usize - header_len;
-    println!("    Calculcated Size: {:>14} B", image_size);
-    println!("    Calculcated Size: {:>14} KB", image_size / 1024);
+    println!("    Calculcated Size: {:>14}", units::fmt_size(image_size));
+}
+
+/// Wrap the payload in a capsule header
+///
+/// The payload is opaque, it's up to the caller that it matches the GUID.
+pub fn build_capsule(guid: Guid, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let header_size = std::mem::size_of::<EfiCapsuleHeader>();
+    let capsule_size = (header_size + payload.len()) as u32;
+
+    let mut data = Vec::with_capacity(capsule_size as usize);
+    data.extend_from_slice(&guid.to_bytes());
+    data.extend_from_slice(&(header_size as u32).to_le_bytes());
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&capsule_size.to_le_bytes());
+    data.extend_from_slice(payload);
+    data
+}
+
+/// Build a Windows UX capsule, that shows the BMP image while updating
+///
+/// The offsets are where the top left corner of the image is drawn on the screen.
+pub fn build_ux_capsule(bmp: &[u8], offset_x: u32, offset_y: u32) -> Result<Vec<u8>, String> {
+    if !bmp.starts_with(b"BM") {
+        return Err("Image is not a BMP file".to_string());
+    }
+
+    let mut payload = vec![
+        DISPLAY_PAYLOAD_VERSION,
+        // Checksum, filled in below
+        0,
+        ImageType::Bitmap as u8,
+        // Reserved
+        0,
+    ];
+    // Mode
+    payload.extend_from_slice(&0u32.to_le_bytes());
+    payload.extend_from_slice(&offset_x.to_le_bytes());
+    payload.extend_from_slice(&offset_y.to_le_bytes());
+    payload.extend_from_slice(bmp);
+
+    let mut data = build_capsule(
+        crate::esrt::WINUX_GUID,
+        CAPSULE_FLAGS_PERSIST_ACROSS_RESET,
+        &payload,
+    );
+    // All bytes of the capsule must add up to zero
+    let sum = data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+    let checksum_offset = std::mem::size_of::<EfiCapsuleHeader>() + 1;
+    data[checksum_offset] = 0u8.wrapping_sub(sum);
+    Ok(data)
+}
+
+/// Width and height in pixels of a BMP image
+pub fn bmp_dimensions(bmp: &[u8]) -> Option<(u32, u32)> {
+    if !bmp.starts_with(b"BM") || bmp.len() < BMP_HEADER_SIZE {
+        return None;
+    }
+    let width = i32::from_le_bytes(bmp[18..22].try_into().unwrap());
+    // Negative if the rows are stored top-down
+    let height = i32::from_le_bytes(bmp[22..26].try_into().unwrap());
+    Some((width.unsigned_abs(), height.unsigned_abs()))
+}
+
+/// Encode an image as 24-bit BMP
+///
+/// `pixels` are RGB, row by row from the top.
+pub fn encode_bmp(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    // Rows are padded to a multiple of 4 bytes
+    let row_size = (width as usize * 3).div_ceil(4) * 4;
+    let image_size = row_size * height as usize;
+
+    let mut bmp = Vec::with_capacity(BMP_HEADER_SIZE + image_size);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&((BMP_HEADER_SIZE + image_size) as u32).to_le_bytes());
+    // Reserved
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&(BMP_HEADER_SIZE as u32).to_le_bytes());
+    // BITMAPINFOHEADER
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(height as i32).to_le_bytes());
+    // Planes and bits per pixel
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&24u16.to_le_bytes());
+    // No compression
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&(image_size as u32).to_le_bytes());
+    // 72 DPI horizontally and vertically, in pixels per meter
+    bmp.extend_from_slice(&2835u32.to_le_bytes());
+    bmp.extend_from_slice(&2835u32.to_le_bytes());
+    // All colors used and important
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+
+    // Rows are stored from the bottom, pixels as BGR
+    for row in pixels.chunks(width as usize * 3).rev() {
+        for rgb in row.chunks(3) {
+            bmp.extend_from_slice(&[rgb[2], rgb[1], rgb[0]]);
+        }
+        bmp.resize(bmp.len() + row_size - row.len(), 0);
+    }
+    bmp
+}
+
+/// Where to show an image of the given size during the update
+///
+/// Centered horizontally, in the lower part of the screen, below the progress bar.
+pub fn ux_image_offset(image: (u32, u32), resolution: (u32, u32)) -> (u32, u32) {
+    let (width, height) = image;
+    let (screen_width, screen_height) = resolution;
+    let x = screen_width.saturating_sub(width) / 2;
+    let y = (screen_height * 7 / 8)
+        .saturating_sub(height / 2)
+        .min(screen_height.saturating_sub(height));
+    (x, y)
+}
+
+/// Find problems that would prevent the firmware from showing the image of a UX capsule
+///
+/// If the resolution of the screen is known, it's also checked that the image fits.
+pub fn validate_ux_capsule(data: &[u8], resolution: Option<(u32, u32)>) -> Vec<String> {
+    let header_len = std::mem::size_of::<DisplayCapsule>();
+    if data.len() < header_len {
+        return vec!["Too small to be a UX capsule".to_string()];
+    }
+    let header = parse_ux_header(data);
+    let payload = header.image_payload;
+
+    let mut problems = vec![];
+    if data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x)) != 0 {
+        problems.push("Checksum is invalid".to_string());
+    }
+    if payload.version != DISPLAY_PAYLOAD_VERSION {
+        problems.push(format!("Unsupported version {}", payload.version));
+    }
+    if payload.image_type != ImageType::Bitmap as u8 {
+        problems.push(format!("Unsupported image type {}", payload.image_type));
+    }
+    let Some((width, height)) = bmp_dimensions(&data[header_len..]) else {
+        problems.push("Image is not a BMP file".to_string());
+        return problems;
+    };
+    if let Some((screen_width, screen_height)) = resolution {
+        let (offset_x, offset_y) = (payload.offset_x, payload.offset_y);
+        if offset_x as u64 + width as u64 > screen_width as u64
+            || offset_y as u64 + height as u64 > screen_height as u64
+        {
+            problems.push(format!(
+                "Image of {}x{} at {},{} doesn't fit on the screen of {}x{}",
+                width, height, offset_x, offset_y, screen_width, screen_height
+            ));
+        }
+    }
+    problems
+}
+
+/// Convert a PNG image to a 24-bit BMP, that can be used in a UX capsule
+///
+/// Transparent parts are shown as black, like the background during the update.
+#[cfg(feature = "commandline")]
+pub fn png_to_bmp(png_data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut decoder = png::Decoder::new(png_data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|err| format!("Invalid PNG: {}", err))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .map_err(|err| format!("Invalid PNG: {}", err))?;
+
+    let channels = info.color_type.samples();
+    let rgb: Vec<u8> = buf[..info.buffer_size()]
+        .chunks(channels)
+        .flat_map(|pixel| {
+            let (color, alpha) = match pixel {
+                [gray] => ([*gray; 3], 255),
+                [gray, alpha] => ([*gray; 3], *alpha),
+                [r, g, b] => ([*r, *g, *b], 255),
+                [r, g, b, alpha] => ([*r, *g, *b], *alpha),
+                _ => unreachable!(),
+            };
+            color.map(|c| (c as u16 * alpha as u16 / 255) as u8)
+        })
+        .collect();
+    Ok(encode_bmp(info.width, info.height, &rgb))
 }
 
 /// Extract the image data from the display capsule to a file
@@ -218,6 +414,8 @@ mod tests {
 
         assert_eq!(cap.capsule_guid, esrt::WINUX_GUID);
         let ux_header = parse_ux_header(&data);
+        let sum = data.iter().fold(0u8, |sum, x| sum.wrapping_add(*x));
+        assert_eq!(sum, 0);
         assert_eq!(
             ux_header,
             DisplayCapsule {
@@ -234,4 +432,71 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn build_and_parse_capsule() {
+        let payload = [1, 2, 3, 4];
+        let data = build_capsule(esrt::MTL_BIOS_GUID, CAPSULE_FLAGS_INITIATE_RESET, &payload);
+        assert_eq!(data.len(), 32);
+        assert_eq!(
+            parse_capsule_header(&data),
+            Some(EfiCapsuleHeader {
+                capsule_guid: esrt::MTL_BIOS_GUID,
+                header_size: 28,
+                flags: CAPSULE_FLAGS_INITIATE_RESET,
+                capsule_image_size: 32,
+            })
+        );
+        assert_eq!(&data[28..], &payload);
+    }
+
+    #[test]
+    fn build_ux_capsule_roundtrip() {
+        let mut capsule_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        capsule_path.push("test_bins/winux.bin");
+        let data = fs::read(capsule_path).unwrap();
+
+        let header_len = std::mem::size_of::<DisplayCapsule>();
+        let rebuilt = build_ux_capsule(&data[header_len..], 0, 1228).unwrap();
+        assert_eq!(rebuilt, data);
+
+        assert!(build_ux_capsule(&[0, 1, 2], 0, 0).is_err());
+    }
+
+    #[test]
+    fn validate_ux_capsules() {
+        let mut capsule_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        capsule_path.push("test_bins/winux.bin");
+        let data = fs::read(capsule_path).unwrap();
+
+        let header_len = std::mem::size_of::<DisplayCapsule>();
+        assert_eq!(bmp_dimensions(&data[header_len..]), Some((2256, 75)));
+        assert!(validate_ux_capsule(&data, None).is_empty());
+        assert!(validate_ux_capsule(&data, Some((2256, 1504))).is_empty());
+        assert_eq!(validate_ux_capsule(&data, Some((1920, 1080))).len(), 1);
+
+        let mut broken = data.clone();
+        broken[100] ^= 1;
+        assert_eq!(
+            validate_ux_capsule(&broken, None),
+            vec!["Checksum is invalid".to_string()]
+        );
+    }
+
+    #[test]
+    fn encode_bmp_image() {
+        // 2x2, red and green on top, blue and white at the bottom
+        let pixels = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        let bmp = encode_bmp(2, 2, &pixels);
+        assert_eq!(bmp.len(), BMP_HEADER_SIZE + 2 * 8);
+        assert_eq!(bmp_dimensions(&bmp), Some((2, 2)));
+        // Bottom row first, BGR and padded to 8 bytes
+        assert_eq!(
+            &bmp[BMP_HEADER_SIZE..],
+            &[255, 0, 0, 255, 255, 255, 0, 0, 0, 0, 255, 0, 255, 0, 0, 0]
+        );
+
+        assert_eq!(ux_image_offset((2256, 75), (2256, 1504)), (0, 1279));
+        assert_eq!(ux_image_offset((800, 600), (1920, 1080)), (560, 480));
+    }
 }
@@ -65,3 +65,35 @@ pub fn find_pd_in_bios_cap(data: &[u8]) -> Option<&[u8]> {
         None
     }
 }
+
+/// Find the biggest UEFI firmware volume in the capsule, that's the BIOS
+///
+/// Firmware volumes are identified by the `_FVH` signature in their header.
+/// The signature is at offset 0x28 and the length of the volume at offset 0x20.
+pub fn find_bios_fv_in_bios_cap(data: &[u8]) -> Option<&[u8]> {
+    let needle = b"_FVH";
+    let signature_offset = 0x28;
+    let length_offset = 0x20;
+
+    let mut biggest: Option<&[u8]> = None;
+    let mut search_start = 0;
+    while let Some(found) = util::find_sequence(&data[search_start..], needle) {
+        let signature = search_start + found;
+        search_start = signature + needle.len();
+
+        let Some(start) = signature.checked_sub(signature_offset) else {
+            continue;
+        };
+        let length = &data[start + length_offset..start + length_offset + 8];
+        let length = usize::try_from(u64::from_le_bytes(length.try_into().ok()?)).ok()?;
+        // A length this big can't be right, the file is corrupted
+        let end = start.checked_add(length)?;
+        if length < signature_offset || end > data.len() {
+            continue;
+        }
+        if biggest.map(|fv| length > fv.len()).unwrap_or(true) {
+            biggest = Some(&data[start..end]);
+        }
+    }
+    biggest
+}
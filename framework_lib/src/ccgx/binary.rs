@@ -31,7 +31,8 @@ use alloc::vec::Vec;
 #[cfg(feature = "uefi")]
 use core::prelude::rust_2021::derive;
 
-use crate::ccgx::{AppVersion, BaseVersion};
+use crate::ccgx::{AppVersion, BaseVersion, FwChecksum};
+use crate::units;
 
 use super::*;
 
@@ -80,6 +81,35 @@ pub struct PdFirmware {
     pub size: usize,
     /// How many bytes are in a row
     pub row_size: usize,
+    /// Checksum of the firmware, from the metadata
+    pub checksum: FwChecksum,
+    /// Whether the firmware in the file matches the checksum
+    pub checksum_valid: bool,
+}
+
+impl PdFirmwareFile {
+    /// Whether both firmwares in the file are intact
+    pub fn checksums_valid(&self) -> bool {
+        self.backup_fw.checksum_valid && self.main_fw.checksum_valid
+    }
+
+    /// Check if the file can be flashed onto a PD controller
+    ///
+    /// Returns the reason, if it can't.
+    pub fn check_compatible(&self, controller_silicon_id: u16) -> Result<(), String> {
+        if !self.checksums_valid() {
+            return Err("Firmware checksum mismatch, the file is corrupted".to_string());
+        }
+        for fw in [&self.backup_fw, &self.main_fw] {
+            if fw.silicon_id != controller_silicon_id {
+                return Err(format!(
+                    "Firmware is for silicon ID {:#06x}, but controller is {:#06x}",
+                    fw.silicon_id, controller_silicon_id
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
 // Hexdump
@@ -94,20 +124,22 @@ pub struct PdFirmware {
 // 000ffd0 0001 0000 ffff 4359 0001 0000 0000 0000
 // 000ffe0 0000 0000 0000 0000 0000 0000 0000 0000
 
-/// Read metadata to find FW binary location
-/// Returns row_start, fw_size
+/// Read metadata to find FW binary location and checksum
+/// The start is always returned as a row number
 fn read_metadata(
     file_buffer: &[u8],
     flash_row_size: usize,
     metadata_offset: u32,
     ccgx: SiliconId,
-) -> Option<(u32, u32)> {
+) -> Option<FwMetadata> {
     let buffer = read_256_bytes(file_buffer, metadata_offset, flash_row_size)?;
     match ccgx {
         SiliconId::Ccg3 => parse_metadata_ccg3(&buffer),
         SiliconId::Ccg5 | SiliconId::Ccg6 => parse_metadata_cyacd(&buffer),
-        SiliconId::Ccg8 => parse_metadata_cyacd2(&buffer)
-            .map(|(fw_row_start, fw_size)| (fw_row_start / (flash_row_size as u32), fw_size)),
+        SiliconId::Ccg8 => parse_metadata_cyacd2(&buffer).map(|metadata| FwMetadata {
+            start: metadata.start / (flash_row_size as u32),
+            ..metadata
+        }),
     }
 }
 
@@ -139,8 +171,9 @@ fn read_version(
     metadata_offset: u32,
     ccgx: SiliconId,
 ) -> Option<PdFirmware> {
-    let (fw_row_start, fw_size) =
-        read_metadata(file_buffer, flash_row_size, metadata_offset, ccgx)?;
+    let metadata = read_metadata(file_buffer, flash_row_size, metadata_offset, ccgx)?;
+    let fw_row_start = metadata.start;
+    let fw_size = metadata.size;
     let data = read_256_bytes(file_buffer, fw_row_start, flash_row_size)?;
     trace!("First row of firmware: {:X?}", data);
     let data = &data[FW_VERSION_OFFSET..];
@@ -155,6 +188,15 @@ fn read_version(
     let fw_silicon_id = version_info.silicon_id;
     let fw_silicon_family = version_info.silicon_family;
 
+    let fw_start = (fw_row_start as usize) * flash_row_size;
+    let checksum_valid = file_buffer
+        .get(fw_start..fw_start + fw_size as usize)
+        .map(|fw| metadata.checksum.verify(fw))
+        .unwrap_or(false);
+    if !checksum_valid {
+        debug!("Checksum mismatch of FW at row {}", fw_row_start);
+    }
+
     Some(PdFirmware {
         silicon_id: fw_silicon_id,
         silicon_family: fw_silicon_family,
@@ -163,6 +205,8 @@ fn read_version(
         start_row: fw_row_start,
         size: fw_size as usize,
         row_size: flash_row_size,
+        checksum: metadata.checksum,
+        checksum_valid,
     })
 }
 
@@ -186,14 +230,20 @@ pub fn print_fw(fw: &PdFirmware) {
     let silicon_family = format!("{:#06x}", fw.silicon_family);
     println!("  Silicon ID: {:>20}", silicon_id);
     println!("  Silicon Family: {:>16}", silicon_family);
+    let app_type = format!("{:?}", fw.app_version.application);
+    println!("  App Type:   {:>20}", app_type);
     // TODO: Why does the padding not work? I shouldn't have to manually pad it
     println!("  Version:                  {:>20}", fw.app_version);
     println!("  Base Ver:                 {:>20}", fw.base_version);
     println!("  Row size:   {:>20} B", fw.row_size);
     println!("  Start Row:  {:>20}", fw.start_row);
     println!("  Rows:       {:>20}", fw.size / fw.row_size);
-    println!("  Size:       {:>20} B", fw.size);
-    println!("  Size:       {:>20} KB", fw.size / 1024);
+    println!("  Size:       {:>20}", units::fmt_size(fw.size));
+    println!("  Checksum:   {:>20}", fw.checksum.to_string());
+    println!(
+        "  Checksum Valid: {:>16}",
+        if fw.checksum_valid { "Yes" } else { "No" }
+    );
 }
 
 #[cfg(test)]
@@ -240,6 +290,8 @@ mod tests {
                         start_row: 48,
                         size: 58624,
                         row_size: 128,
+                        checksum: FwChecksum::Sum8(0xD4),
+                        checksum_valid: true,
                     },
                     main_fw: PdFirmware {
                         silicon_id: 0x11AD,
@@ -259,6 +311,8 @@ mod tests {
                         start_row: 512,
                         size: 58624,
                         row_size: 128,
+                        checksum: FwChecksum::Sum8(0xE9),
+                        checksum_valid: true,
                     },
                 }
             })
@@ -302,6 +356,8 @@ mod tests {
                         start_row: 163,
                         size: 88832,
                         row_size: 256,
+                        checksum: FwChecksum::Sum8(0x76),
+                        checksum_valid: true,
                     },
                     main_fw: PdFirmware {
                         silicon_id: 0x11B1,
@@ -321,6 +377,8 @@ mod tests {
                         start_row: 20,
                         size: 36352,
                         row_size: 256,
+                        checksum: FwChecksum::Sum8(0x86),
+                        checksum_valid: true,
                     },
                 }
             })
@@ -364,6 +422,8 @@ mod tests {
                         start_row: 22,
                         size: 12160,
                         row_size: 128,
+                        checksum: FwChecksum::Sum8(0xB2),
+                        checksum_valid: true,
                     },
                     main_fw: PdFirmware {
                         silicon_id: 0x11C0,
@@ -383,6 +443,8 @@ mod tests {
                         start_row: 118,
                         size: 49408,
                         row_size: 128,
+                        checksum: FwChecksum::Sum8(0xDD),
+                        checksum_valid: true,
                     },
                 }
             })
@@ -426,6 +488,8 @@ mod tests {
                         start_row: 290,
                         size: 111536,
                         row_size: 0x100,
+                        checksum: FwChecksum::Crc32c(0xC36B_C96E),
+                        checksum_valid: true,
                     },
                     main_fw: PdFirmware {
                         silicon_id: 0x11C5,
@@ -445,9 +509,30 @@ mod tests {
                         start_row: 29,
                         size: 42312,
                         row_size: 0x100,
+                        checksum: FwChecksum::Crc32c(0xF1C6_DF9F),
+                        checksum_valid: true,
                     },
                 }
             })
         );
     }
+
+    #[test]
+    fn detects_corrupted_binary() {
+        let mut pd_bin_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        pd_bin_path.push("test_bins/fl16-pd-0.0.03.bin");
+
+        let mut data = fs::read(pd_bin_path).unwrap();
+        let versions = read_versions(&data, SiliconId::Ccg8).unwrap();
+        assert_eq!(versions.check_compatible(0x11C5), Ok(()));
+        assert!(versions.check_compatible(0x11C0).is_err());
+
+        // Flip a bit in the main firmware
+        let main_start = versions.main_fw.start_row as usize * versions.main_fw.row_size;
+        data[main_start + 0x200] ^= 0x01;
+        let versions = read_versions(&data, SiliconId::Ccg8).unwrap();
+        assert!(versions.backup_fw.checksum_valid);
+        assert!(!versions.main_fw.checksum_valid);
+        assert!(versions.check_compatible(0x11C5).is_err());
+    }
 }
@@ -11,8 +11,10 @@ use core::prelude::rust_2021::derive;
 
 use crate::ccgx::{AppVersion, BaseVersion, ControllerVersion};
 use crate::chromium_ec::command::EcCommands;
-use crate::chromium_ec::{CrosEc, CrosEcDriver, EcError, EcResult};
-use crate::util::{self, assert_win_len, Config, Platform};
+use crate::chromium_ec::{
+    check_response_len, CrosEc, CrosEcDriver, EcError, EcResult, EcResultExt,
+};
+use crate::util::{self, Config, Platform};
 use std::mem::size_of;
 
 use super::*;
@@ -44,7 +46,10 @@ impl PdPort {
             (Platform::GenericFramework((left, _), _, _), PdPort::Left01) => *left,
             (Platform::GenericFramework((_, right), _, _), PdPort::Right23) => *right,
             // Framework AMD Platforms (CCG8)
-            (Platform::Framework13Amd | Platform::Framework16, PdPort::Left01) => 0x42,
+            (
+                Platform::Framework13Amd | Platform::Framework16 | Platform::DesktopAmdAi300,
+                PdPort::Left01,
+            ) => 0x42,
             (Platform::Framework13Amd | Platform::Framework16, PdPort::Right23) => 0x40,
             // Framework Intel Platforms (CCG5 and CCG6)
             (_, PdPort::Left01) => 0x08,
@@ -71,10 +76,12 @@ impl PdPort {
                 Platform::Framework13Amd | Platform::Framework16 | Platform::IntelCoreUltra1,
                 PdPort::Right23,
             ) => 2,
-            // (_, _) => Err(EcError::DeviceError(format!(
-            //     "Unsupported platform: {:?} {:?}",
-            //     platform, self
-            // )))?,
+            // Only has a PD controller for the ports on the back, see PlatformSpec::has_pd23
+            (Platform::DesktopAmdAi300, PdPort::Left01) => 1,
+            (Platform::DesktopAmdAi300, PdPort::Right23) => Err(EcError::DeviceError(format!(
+                "No PD controller for ports 2 and 3 on {:?}",
+                platform
+            )))?,
         })
     }
 }
@@ -124,7 +131,7 @@ impl EcI2cPassthruResponse {
             ));
         }
         if self.i2c_status & (1 << 1) > 0 {
-            return Err(EcError::DeviceError("I2C Transfer timeout".to_string()));
+            return Err(EcError::Timeout("I2C Transfer timeout".to_string()));
         }
         // I'm not aware of any other errors, but there might be.
         // But I don't think multiple errors can be indicated at the same time
@@ -241,12 +248,9 @@ impl PdController {
             let chunk_len = std::cmp::min(MAX_I2C_CHUNK, remaining.into());
             let offset = addr + data.len() as u16;
             let i2c_response = self.i2c_read(offset, chunk_len as u16)?;
-            if let Err(EcError::DeviceError(err)) = i2c_response.is_successful() {
-                return Err(EcError::DeviceError(format!(
-                    "I2C read was not successful: {:?}",
-                    err
-                )));
-            }
+            i2c_response
+                .is_successful()
+                .context("I2C read was not successful")?;
             data.extend(i2c_response.data);
         }
 
@@ -255,7 +259,7 @@ impl PdController {
 
     pub fn get_silicon_id(&self) -> EcResult<u16> {
         let data = self.ccgx_read(ControlRegisters::SiliconId, 2)?;
-        assert_win_len(data.len(), 2);
+        check_response_len(&data, 2)?;
         Ok(u16::from_le_bytes([data[0], data[1]]))
     }
 
@@ -268,7 +272,7 @@ impl PdController {
         let fw_mode = match FwMode::try_from(byte & 0b0000_0011) {
             Ok(mode) => mode,
             Err(err_byte) => {
-                return Err(EcError::DeviceError(format!(
+                return Err(EcError::ProtocolMismatch(format!(
                     "FW Mode invalid: {}",
                     err_byte
                 )))
@@ -305,43 +309,4 @@ impl PdController {
             app: AppVersion::from(&data[4..]),
         })
     }
-
-    pub fn print_fw_info(&self) {
-        let data = self.ccgx_read(ControlRegisters::BootLoaderVersion, 8);
-        let data = match data {
-            Ok(data) => data,
-            Err(err) => {
-                println!("Failed to get PD Info: {:?}", err);
-                return;
-            }
-        };
-
-        assert_win_len(data.len(), 8);
-        let base_ver = BaseVersion::from(&data[..4]);
-        let app_ver = AppVersion::from(&data[4..]);
-        println!(
-            "  Bootloader Version:   Base: {},  App: {}",
-            base_ver, app_ver
-        );
-
-        let data = self.ccgx_read(ControlRegisters::Firmware1Version, 8);
-        let data = data.unwrap();
-        assert_win_len(data.len(), 8);
-        let base_ver = BaseVersion::from(&data[..4]);
-        let app_ver = AppVersion::from(&data[4..]);
-        println!(
-            "  FW1 (Backup) Version: Base: {},  App: {}",
-            base_ver, app_ver
-        );
-
-        let data = self.ccgx_read(ControlRegisters::Firmware2Version, 8);
-        let data = data.unwrap();
-        assert_win_len(data.len(), 8);
-        let base_ver = BaseVersion::from(&data[..4]);
-        let app_ver = AppVersion::from(&data[4..]);
-        println!(
-            "  FW2 (Main)   Version: Base: {},  App: {}",
-            base_ver, app_ver
-        );
-    }
 }
@@ -3,6 +3,7 @@ use hidapi::{DeviceInfo, HidApi, HidDevice, HidError};
 use crate::ccgx;
 use crate::ccgx::device::{decode_flash_row_size, FwMode};
 use crate::ccgx::{BaseVersion, SiliconId};
+use crate::devices::{self, Devices};
 use crate::os_specific;
 use crate::util;
 
@@ -247,6 +248,72 @@ pub fn device_name(vid: u16, pid: u16) -> Option<&'static str> {
     }
 }
 
+/// Where the card is plugged in, as USB bus and port path, like `3-1.2`
+///
+/// HID doesn't expose the USB topology, so look for the USB device with the
+/// same serial number. Which expansion slot the path belongs to depends on the
+/// mainboard, but it stays the same as long as the card isn't moved.
+pub fn usb_location(devices: &Devices, dev_info: &DeviceInfo) -> Option<String> {
+    #[cfg(feature = "rusb")]
+    {
+        let candidates = devices.usb(dev_info.vendor_id(), dev_info.product_id());
+        // Reading the serial number needs to open the device, avoid it if possible
+        let dev = if let [dev] = candidates[..] {
+            dev
+        } else {
+            let sn = dev_info.serial_number()?;
+            candidates
+                .into_iter()
+                .find(|dev| usb_serial_number(dev).as_deref() == Some(sn))?
+        };
+        let ports: Vec<String> = dev
+            .port_numbers()
+            .ok()?
+            .iter()
+            .map(|port| port.to_string())
+            .collect();
+        Some(format!("{}-{}", dev.bus_number(), ports.join(".")))
+    }
+    #[cfg(not(feature = "rusb"))]
+    {
+        let _ = (devices, dev_info);
+        None
+    }
+}
+
+#[cfg(feature = "rusb")]
+fn usb_serial_number(dev: &rusb::Device<rusb::GlobalContext>) -> Option<String> {
+    let descriptor = dev.device_descriptor().ok()?;
+    let handle = dev.open().ok()?;
+    handle.read_serial_number_string_ascii(&descriptor).ok()
+}
+
+/// Name of the card and where it's plugged in, like `HDMI Expansion Card (USB 3-1.2)`
+pub fn card_label(devices: &Devices, dev_info: &DeviceInfo) -> String {
+    let name = device_name(dev_info.vendor_id(), dev_info.product_id()).unwrap_or("Unknown Card");
+    if let Some(location) = usb_location(devices, dev_info) {
+        format!("{} (USB {})", name, location)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Open the card to talk to it, explain how to get access if that's not allowed
+pub fn open_card(api: &HidApi, dev_info: &DeviceInfo) -> Option<HidDevice> {
+    match dev_info.open_device(api) {
+        Ok(device) => Some(device),
+        Err(_) if devices::hid_permission_denied(dev_info) => {
+            let name = device_name(dev_info.vendor_id(), dev_info.product_id());
+            devices::print_permission_hint(name.unwrap_or("Expansion Card"));
+            None
+        }
+        Err(err) => {
+            error!("Failed to open Expansion Card: {}", err);
+            None
+        }
+    }
+}
+
 /// Find HDMI/DP Expansion cards, optionally filter by product ID or serial number
 pub fn find_devices(api: &HidApi, filter_devs: &[u16], sn: Option<&str>) -> Vec<DeviceInfo> {
     api.device_list()
@@ -275,13 +342,69 @@ pub fn find_devices(api: &HidApi, filter_devs: &[u16], sn: Option<&str>) -> Vec<
         .collect()
 }
 
-pub fn flash_firmware(fw_binary: &[u8]) {
+/// Check whether the firmware file should be flashed onto a card
+///
+/// Returns the reason, if it shouldn't be.
+fn check_update(
+    info: &HidFirmwareInfo,
+    file_fw: &ccgx::binary::PdFirmware,
+    force: bool,
+) -> Result<(), String> {
+    // How the card reports its silicon isn't documented. It looks like the
+    // family in the upper half and the ID in the lower half, but that's not
+    // confirmed, so a mismatch must not stop the update.
+    let silicon_id = u32::from_le_bytes(info.silicon_id);
+    let (family, id) = ((silicon_id >> 16) as u16, silicon_id as u16);
+    if family != file_fw.silicon_family || id != file_fw.silicon_id {
+        println!(
+            "  Warning: Firmware is for silicon {:04X}{:04X}, but card reports {:08X}",
+            file_fw.silicon_family, file_fw.silicon_id, silicon_id
+        );
+    }
+
+    let current = match FwMode::try_from(info.operating_mode) {
+        Ok(FwMode::BackupFw) => BaseVersion::from(info.image_1_ver.as_slice()),
+        _ => BaseVersion::from(info.image_2_ver.as_slice()),
+    };
+    let new = file_fw.base_version;
+    if new < current && !force {
+        return Err(format!(
+            "Refusing to downgrade from {} to {}. Add --force to do it anyway",
+            current, new
+        ));
+    }
+    if new == current && !force {
+        return Err(format!(
+            "Already on version {}. Add --force to flash it anyway",
+            current
+        ));
+    }
+
+    Ok(())
+}
+
+/// Update all connected DP or HDMI Expansion Cards that the firmware is for
+///
+/// With `dry_run` everything is checked, but nothing is written to the cards.
+/// Only the card at `location` is updated, if given. See `usb_location`.
+///
+/// Returns whether all cards were updated successfully.
+pub fn flash_firmware(
+    fw_binary: &[u8],
+    force: bool,
+    dry_run: bool,
+    location: Option<&str>,
+) -> bool {
     let versions = if let Some(versions) = ccgx::binary::read_versions(fw_binary, SiliconId::Ccg3) {
         versions
     } else {
         println!("Incompatible firmware. Need CCG3 firmware.");
-        return;
+        return false;
     };
+    if !versions.checksums_valid() {
+        println!("Firmware checksum mismatch, the file is corrupted.");
+        return false;
+    }
 
     // Not sure if there's a better way to check whether the firmware is for DP or HDMI card
     let dp_string = b"F\0r\0a\0m\0e\0w\0o\0r\0k\x006\x03D\0i\0s\0p\0l\0a\0y\0P\0o\0r\0t\0 \0E\0x\0p\0a\0n\0s\0i\0o\0n\0 \0C\0a\0r\0d\0";
@@ -292,7 +415,7 @@ pub fn flash_firmware(fw_binary: &[u8]) {
         [DP_CARD_PID]
     } else {
         println!("Incompatible firmware. Need DP/HDMI Expansion Card Firmware.");
-        return;
+        return false;
     };
 
     let fw1_rows = versions.backup_fw.size / versions.backup_fw.row_size;
@@ -301,44 +424,68 @@ pub fn flash_firmware(fw_binary: &[u8]) {
     println!("File Firmware:");
     println!("  {}", device_name(FRAMEWORK_VID, filter_devs[0]).unwrap());
     println!("  {}", versions.main_fw.base_version);
+    if dry_run {
+        println!("Dry run, not writing anything");
+    }
 
     // First update the one that's not currently running.
     // After updating the first image, the device restarts and boots into the other one.
     // Then we need to re-enumerate the USB devices because it'll change device id
     let mut api = HidApi::new().unwrap();
-    let devices = find_devices(&api, &filter_devs, None);
-    if devices.is_empty() {
+    let usb_devices = devices::enumerate();
+    let mut cards = find_devices(&api, &filter_devs, None);
+    if let Some(location) = location {
+        cards.retain(|dev_info| usb_location(&usb_devices, dev_info).as_deref() == Some(location));
+    }
+    if cards.is_empty() {
         println!("No compatible Expansion Card connected");
-        return;
+        return false;
     };
-    for dev_info in devices {
-        // Unfortunately the HID API doesn't allow us to introspect the USB
-        // topology because it abstracts USB, Bluetooth and other HID devices.
-        // The libusb API does allow that but it's lower level and requires
-        // root privileges on Linux.
-        // So we can't figure out which port the card is connected to.
-        // Would be nice to show that instead of the serial number.
-        // We want to show that so the user knows that multiple *different*
-        // cards are being updated.
+    let mut success = true;
+    for dev_info in cards {
+        // Show where the card is plugged in and the serial number, so the
+        // user knows that multiple *different* cards are being updated.
+        // Finding the USB port needs root privileges on Linux, the serial
+        // number is always available.
         let sn = dev_info
             .serial_number()
             .expect("Device has no serial number");
-        let dev_name = device_name(dev_info.vendor_id(), dev_info.product_id()).unwrap();
         println!();
-        println!("Updating {} with SN: {:?}", dev_name, sn);
+        println!(
+            "Updating {} with SN: {:?}",
+            card_label(&usb_devices, &dev_info),
+            util::redact_serial(sn)
+        );
 
-        let device = dev_info.open_device(&api).unwrap();
+        let Some(device) = open_card(&api, &dev_info) else {
+            success = false;
+            continue;
+        };
         magic_unlock(&device);
         let info = get_fw_info(&device);
         println!("Before Updating");
         print_fw_info(&info);
 
+        if let Err(reason) = check_update(&info, &versions.main_fw, force) {
+            println!("  Skipping: {}", reason);
+            success = false;
+            continue;
+        }
+
         println!("Updating...");
         match info.operating_mode {
             // I think in bootloader mode we can update either one first. Never tested
             0 | 2 => {
                 println!("  Updating Firmware Image 1");
-                flash_firmware_image(&device, fw_binary, FW1_START, FW1_METADATA, fw1_rows, 1);
+                flash_firmware_image(
+                    &device,
+                    fw_binary,
+                    FW1_START,
+                    FW1_METADATA,
+                    fw1_rows,
+                    1,
+                    dry_run,
+                );
 
                 // We don't actually need to update both firmware images.
                 // It'll stay on the one we updated. So it's totally fine to
@@ -351,7 +498,15 @@ pub fn flash_firmware(fw_binary: &[u8]) {
             }
             1 => {
                 println!("  Updating Firmware Image 2");
-                flash_firmware_image(&device, fw_binary, FW2_START, FW2_METADATA, fw2_rows, 2);
+                flash_firmware_image(
+                    &device,
+                    fw_binary,
+                    FW2_START,
+                    FW2_METADATA,
+                    fw2_rows,
+                    2,
+                    dry_run,
+                );
 
                 // See above
                 //let (device, _) =
@@ -363,6 +518,11 @@ pub fn flash_firmware(fw_binary: &[u8]) {
             _ => unreachable!(),
         }
 
+        if dry_run {
+            println!("  Dry run done. Card was not changed.");
+            continue;
+        }
+
         println!("  Firmware Update done.");
         let (_, info) =
             wait_to_reappear(&mut api, &filter_devs, sn).expect("Device did not reappear");
@@ -370,6 +530,8 @@ pub fn flash_firmware(fw_binary: &[u8]) {
         println!("After Updating");
         print_fw_info(&info);
     }
+
+    success
 }
 
 fn flash_firmware_image(
@@ -379,6 +541,7 @@ fn flash_firmware_image(
     metadata_row: usize,
     rows: usize,
     no: u8,
+    dry_run: bool,
 ) {
     let fw_slice = &fw_binary[start_row * ROW_SIZE..(start_row + rows) * ROW_SIZE];
     let metadata_slice = &fw_binary[metadata_row * ROW_SIZE..(metadata_row + 1) * ROW_SIZE];
@@ -387,6 +550,14 @@ fn flash_firmware_image(
 
     let _info = get_fw_info(device);
 
+    if dry_run {
+        println!(
+            "  Would write {} rows starting at {:#X} and metadata at {:#X}",
+            rows, start_row, metadata_row
+        );
+        return;
+    }
+
     let rows = fw_slice.chunks(ROW_SIZE);
     for (row_no, row) in rows.enumerate() {
         assert_eq!(row.len(), ROW_SIZE);
@@ -7,7 +7,7 @@ use core::prelude::rust_2021::derive;
 use num_derive::FromPrimitive;
 use std::fmt;
 
-use crate::chromium_ec::{CrosEc, EcResult};
+use crate::chromium_ec::{CrosEc, EcResult, EcResultExt};
 use crate::smbios;
 use crate::util::Platform;
 
@@ -33,7 +33,7 @@ const CCG8_METADATA_MAGIC: u16 = u16::from_le_bytes([b'F', b'I']); // IF (Infine
 #[derive(Debug, Copy, Clone)]
 struct CyAcdMetadata {
     /// Offset 00: Single Byte FW Checksum
-    _fw_checksum: u8,
+    fw_checksum: u8,
     /// Offset 01: FW Entry Address
     _fw_entry: u32,
     /// Offset 05: Last Flash row of Bootloader or previous firmware
@@ -62,7 +62,6 @@ struct CyAcdMetadata {
     _boot_seq: u32,
 }
 
-// TODO: Would be nice to check the checksums
 #[repr(packed)]
 #[derive(Debug, Copy, Clone)]
 struct CyAcd2Metadata {
@@ -90,13 +89,72 @@ struct CyAcd2Metadata {
     /// Offset 56: Metadata Valid field. Valid if contains ASCII "IF"
     metadata_valid: u16,
     /// Offset 58: App Fw CRC32 checksum
-    _fw_crc32: u32,
+    fw_crc32: u32,
     /// Offset 5C: Reserved
     _reserved_2: [u32; 8],
     /// Offset 7C: Metadata CRC32 checksum
+    /// Not filled in by our build, it's always 0
     _md_crc32: u32,
 }
 
+/// Checksum of a firmware image, as stored in its metadata
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FwChecksum {
+    /// Single byte, that makes the sum of all bytes of the firmware 0 (CCG3, CCG5, CCG6)
+    Sum8(u8),
+    /// CRC32C (Castagnoli) of the firmware (CCG8)
+    Crc32c(u32),
+}
+
+impl FwChecksum {
+    /// Check whether the firmware image matches the checksum
+    pub fn verify(&self, fw: &[u8]) -> bool {
+        match self {
+            FwChecksum::Sum8(checksum) => {
+                fw.iter().fold(*checksum, |sum, x| sum.wrapping_add(*x)) == 0
+            }
+            FwChecksum::Crc32c(crc) => crc32c(fw) == *crc,
+        }
+    }
+}
+
+impl fmt::Display for FwChecksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FwChecksum::Sum8(checksum) => write!(f, "{:#04x} (8-bit sum)", checksum),
+            FwChecksum::Crc32c(crc) => write!(f, "{:#010x} (CRC32C)", crc),
+        }
+    }
+}
+
+/// CRC32C (Castagnoli), as used by the CCG8 bootloader
+///
+/// Bitwise, since we only need it once per firmware image.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Location and checksum of a firmware image, as described by its metadata
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct FwMetadata {
+    /// First row of the firmware (CCG3, CCG5, CCG6) or start address (CCG8)
+    start: u32,
+    /// Size of the firmware in bytes
+    size: u32,
+    checksum: FwChecksum,
+}
+
 #[non_exhaustive]
 #[derive(Debug, PartialEq, FromPrimitive, Clone, Copy)]
 pub enum SiliconId {
@@ -106,7 +164,7 @@ pub enum SiliconId {
     Ccg8 = 0x3580,
 }
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, PartialOrd, Copy, Clone)]
 pub struct BaseVersion {
     /// Major part of the version. X of X.Y.Z.BB
     pub major: u8,
@@ -238,39 +296,51 @@ pub struct MainPdVersions {
 
 pub fn get_pd_controller_versions(ec: &CrosEc) -> EcResult<PdVersions> {
     Ok(PdVersions {
-        controller01: PdController::new(PdPort::Left01, ec.clone()).get_fw_versions()?,
-        controller23: PdController::new(PdPort::Right23, ec.clone()).get_fw_versions()?,
+        controller01: PdController::new(PdPort::Left01, ec.clone())
+            .get_fw_versions()
+            .context("Failed to get firmware versions of PD controller 01")?,
+        controller23: PdController::new(PdPort::Right23, ec.clone())
+            .get_fw_versions()
+            .context("Failed to get firmware versions of PD controller 23")?,
     })
 }
 
-fn parse_metadata_ccg3(buffer: &[u8]) -> Option<(u32, u32)> {
+fn parse_metadata_ccg3(buffer: &[u8]) -> Option<FwMetadata> {
     let buffer = &buffer[CCG3_METADATA_OFFSET..];
     let metadata_len = std::mem::size_of::<CyAcdMetadata>();
     let metadata: CyAcdMetadata =
         unsafe { std::ptr::read(buffer[0..metadata_len].as_ptr() as *const _) };
     trace!("Metadata: {:X?}", metadata);
     if metadata.metadata_valid == METADATA_MAGIC {
-        Some((1 + metadata.boot_last_row as u32, metadata.fw_size))
+        Some(FwMetadata {
+            start: 1 + metadata.boot_last_row as u32,
+            size: metadata.fw_size,
+            checksum: FwChecksum::Sum8(metadata.fw_checksum),
+        })
     } else {
         None
     }
 }
 
 //fn parse_metadata(buffer: &[u8; 256]) -> Option<(u32, u32)> {
-fn parse_metadata_cyacd(buffer: &[u8]) -> Option<(u32, u32)> {
+fn parse_metadata_cyacd(buffer: &[u8]) -> Option<FwMetadata> {
     let buffer = &buffer[METADATA_OFFSET..];
     let metadata_len = std::mem::size_of::<CyAcdMetadata>();
     let metadata: CyAcdMetadata =
         unsafe { std::ptr::read(buffer[0..metadata_len].as_ptr() as *const _) };
     trace!("Metadata: {:X?}", metadata);
     if metadata.metadata_valid == METADATA_MAGIC {
-        Some((1 + metadata.boot_last_row as u32, metadata.fw_size))
+        Some(FwMetadata {
+            start: 1 + metadata.boot_last_row as u32,
+            size: metadata.fw_size,
+            checksum: FwChecksum::Sum8(metadata.fw_checksum),
+        })
     } else {
         None
     }
 }
 
-fn parse_metadata_cyacd2(buffer: &[u8]) -> Option<(u32, u32)> {
+fn parse_metadata_cyacd2(buffer: &[u8]) -> Option<FwMetadata> {
     let buffer = &buffer[CCG8_METADATA_OFFSET..];
     let metadata_len = std::mem::size_of::<CyAcd2Metadata>();
     let metadata: CyAcd2Metadata =
@@ -278,7 +348,11 @@ fn parse_metadata_cyacd2(buffer: &[u8]) -> Option<(u32, u32)> {
     trace!("Metadata: {:X?}", metadata);
     if metadata.metadata_valid == CCG8_METADATA_MAGIC {
         if metadata.metadata_version == 1 {
-            Some((metadata.fw_start, metadata.fw_size))
+            Some(FwMetadata {
+                start: metadata.fw_start,
+                size: metadata.fw_size,
+                checksum: FwChecksum::Crc32c(metadata.fw_crc32),
+            })
         } else {
             println!("Unknown CCG8 metadata version");
             None
@@ -1,25 +1,173 @@
 #[cfg(feature = "uefi")]
 use core::prelude::rust_2021::derive;
 
-use alloc::format;
 use alloc::vec;
 use alloc::vec::Vec;
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
+#[cfg(not(feature = "std"))]
+use spin::Mutex;
+#[cfg(feature = "std")]
+use std::sync::Mutex;
+
+use crate::os_specific;
 use crate::util;
 
-use super::{CrosEc, CrosEcDriver, EcError, EcResult};
+use super::{check_response_len, CrosEc, CrosEcDriver, EcError, EcResponseStatus, EcResult};
+
+/// How to retry commands when the EC reports a transient condition
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// How often to retry after the first attempt failed
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every further retry
+    pub initial_backoff_ms: u64,
+    /// Stop retrying when the delays would add up to more than this
+    pub timeout_ms: u64,
+    /// How long to wait for the EC to be ready for the next command.
+    /// None waits as long as it takes. Only port I/O waits on its own.
+    pub ready_timeout_ms: Option<u64>,
+    /// Retry when the EC is too busy to handle the command
+    pub retry_busy: bool,
+    /// Retry when the EC timed out, e.g. talking to a device behind it
+    pub retry_timeout: bool,
+    /// Retry when the EC reports that a previous command is still in progress
+    pub retry_in_progress: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff_ms: 10,
+            timeout_ms: 1000,
+            ready_timeout_ms: Some(1000),
+            retry_busy: true,
+            retry_timeout: true,
+            retry_in_progress: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether the command should be sent again after failing with this error
+    pub fn should_retry(&self, err: &EcError) -> bool {
+        match err {
+            EcError::Response(EcResponseStatus::Busy) => self.retry_busy,
+            EcError::Response(EcResponseStatus::Timeout) => self.retry_timeout,
+            EcError::Response(EcResponseStatus::InProgress) => self.retry_in_progress,
+            _ => false,
+        }
+    }
+}
+
+lazy_static! {
+    static ref RETRY_POLICY: Mutex<RetryPolicy> = Mutex::new(RetryPolicy::default());
+}
+
+/// Change how all EC commands are retried
+pub fn set_retry_policy(policy: RetryPolicy) {
+    #[cfg(feature = "std")]
+    let mut current = RETRY_POLICY.lock().unwrap();
+    #[cfg(not(feature = "std"))]
+    let mut current = RETRY_POLICY.lock();
+    *current = policy;
+}
+
+pub fn retry_policy() -> RetryPolicy {
+    #[cfg(feature = "std")]
+    let policy = RETRY_POLICY.lock().unwrap();
+    #[cfg(not(feature = "std"))]
+    let policy = RETRY_POLICY.lock();
+    *policy
+}
+
+/// Run `f` with a different retry policy, then restore the previous one
+pub fn with_retry_policy<T>(policy: RetryPolicy, f: impl FnOnce() -> T) -> T {
+    let previous = retry_policy();
+    set_retry_policy(policy);
+    let res = f();
+    set_retry_policy(previous);
+    res
+}
+
+/// Whether sending the command twice has the same effect as sending it once
+///
+/// When the EC reports busy or in progress, it might have started already.
+/// Commands that advance a position, consume an entry or change state step by
+/// step must not be sent again.
+pub(crate) fn is_idempotent(command: u16) -> bool {
+    !matches!(
+        <EcCommands as FromPrimitive>::from_u16(command),
+        Some(
+            EcCommands::FlashWrite
+                | EcCommands::FlashErase
+                | EcCommands::FlashNotified
+                | EcCommands::GetNextEvent
+                | EcCommands::I2cPassthrough
+                | EcCommands::ConsoleRead
+                | EcCommands::BatteryCutOff
+                | EcCommands::Reboot
+                | EcCommands::RebootEc
+                | EcCommands::PdGetLogEntry
+                | EcCommands::UcsiPpmSet
+        )
+    )
+}
+
+/// Send the command and retry according to the current `RetryPolicy`
+fn send_command_retry(
+    ec: &CrosEc,
+    command: u16,
+    command_version: u8,
+    data: &[u8],
+) -> EcResult<Vec<u8>> {
+    let policy = retry_policy();
+    let idempotent = is_idempotent(command);
+    let mut backoff_ms = policy.initial_backoff_ms;
+    let mut waited_ms = 0;
+    let mut retries = 0;
+    loop {
+        let res = ec.send_command(command, command_version, data);
+        match res {
+            Err(err)
+                if idempotent
+                    && policy.should_retry(&err)
+                    && retries < policy.max_retries
+                    && waited_ms + backoff_ms <= policy.timeout_ms =>
+            {
+                debug!(
+                    "Command {:X?} failed with {:?}, retrying in {}ms",
+                    <EcCommands as FromPrimitive>::from_u16(command),
+                    err,
+                    backoff_ms
+                );
+                os_specific::sleep(backoff_ms * 1000);
+                waited_ms += backoff_ms;
+                backoff_ms *= 2;
+                retries += 1;
+            }
+            res => return res,
+        }
+    }
+}
 
 #[non_exhaustive]
 #[derive(Debug, FromPrimitive)]
 #[repr(u16)]
 pub enum EcCommands {
+    /// Echo back a value, useful to check that communication works
+    Hello = 0x01,
     GetVersion = 0x02,
     GetBuildInfo = 0x04,
+    /// Get vendor, name and revision of the EC chip
+    GetChipInfo = 0x05,
     /// Command to read data from EC memory map
     ReadMemMap = 0x07,
     GetCmdVersions = 0x08,
+    /// Get information about the host command protocol
+    GetProtocolInfo = 0x0B,
     FlashInfo = 0x10,
     /// Write section of EC flash
     FlashRead = 0x11,
@@ -33,9 +181,35 @@ pub enum EcCommands {
     PwmSetFanDuty = 0x0024,
     PwmSetDuty = 0x0025,
     PwmGetDuty = 0x0026,
+    /// Set color and brightness of an LED, or return it to automatic control
+    LedControl = 0x0029,
+    /// Motion sensors, lid angle and tablet mode detection
+    MotionSense = 0x002B,
+    /// Force tablet or laptop mode, regardless of the hinge angle
+    SetTabletMode = 0x0031,
+    /// Get information about keyboard, buttons and switches
+    MkbpInfo = 0x0061,
+    /// Get the oldest event that the EC queued for the host
+    GetNextEvent = 0x0067,
+    /// Get the temperatures at which the EC takes action
+    ThermalGetThreshold = 0x0050,
+    /// Let the EC control the fan speed, based on temperature
+    ThermalAutoFanCtrl = 0x0052,
+    /// Drive a GPIO, only works if the EC is unlocked
+    GpioSet = 0x92,
     GpioGet = 0x93,
+    /// Get or set state of the charger
+    ChargeState = 0xA0,
+    /// Limit the power drawn from external power sources
+    ExternalPowerLimit = 0xA2,
+    /// Get, set or clear the host event masks, like the wake masks
+    HostEvent = 0xA4,
+    /// Get or set how long the EC waits in G3 before hibernating
+    HibernationDelay = 0xA8,
     I2cPassthrough = 0x9e,
     ConsoleSnapshot = 0x97,
+    /// Disconnect the battery (ship mode)
+    BatteryCutOff = 0x99,
     ConsoleRead = 0x98,
     /// List the features supported by the firmware
     GetFeatures = 0x0D,
@@ -43,14 +217,32 @@ pub enum EcCommands {
     Reboot = 0xD1,
     /// Control EC boot
     RebootEc = 0xD2,
+    /// Set power role of a USB-C port
+    UsbPdControl = 0x101,
     /// Get information about PD controller power
     UsbPdPowerInfo = 0x103,
+    /// Get the rollback version of RW and the minimum that RO accepts
+    RollbackInfo = 0x112,
+    /// Get identity of the device attached to a USB-C port, from PD discovery
+    UsbPdDiscovery = 0x113,
+    /// Get the oldest entry of the PD event log and remove it
+    PdGetLogEntry = 0x115,
+    /// Get time since EC boot and reset causes
+    GetUptimeInfo = 0x121,
+    /// Write to the UCSI data structure of the PPM in the EC
+    UcsiPpmSet = 0x140,
+    /// Read from the UCSI data structure of the PPM in the EC
+    UcsiPpmGet = 0x141,
 
     // Framework specific commands
     /// Configure the behavior of the flash notify
     FlashNotified = 0x3E01,
     /// Change charge limit
     ChargeLimitControl = 0x3E03,
+    /// Disable or enable PS/2 keyboard and mouse emulation
+    DisablePs2Emulation = 0x3E08,
+    /// Read or change the scancodes that keys of the matrix send
+    UpdateKeyboardMatrix = 0x3E0C,
     /// Get/Set Fingerprint LED brightness
     FpLedLevelControl = 0x3E0E,
     /// Get information about the current chassis open/close status
@@ -135,8 +327,12 @@ pub trait EcRequestRaw<R> {
             buffer[params.len()..].copy_from_slice(extra_data);
             buffer
         };
-        let response =
-            ec.send_command(Self::command_id_u16(), Self::command_version(), &request)?;
+        let response = send_command_retry(
+            ec,
+            Self::command_id_u16(),
+            Self::command_version(),
+            &request,
+        )?;
         trace!(
             "send_command<{:X?}>",
             <EcCommands as FromPrimitive>::from_u16(Self::command_id_u16())
@@ -159,18 +355,7 @@ pub trait EcRequestRaw<R> {
         Self: Sized,
     {
         let response = self.send_command_vec_extra(ec, extra_data)?;
-        // TODO: The Windows driver seems to return 20 more bytes than expected
-        #[cfg(feature = "win_driver")]
-        let expected = response.len() != std::mem::size_of::<R>() + 20;
-        #[cfg(not(feature = "win_driver"))]
-        let expected = response.len() != std::mem::size_of::<R>();
-        if expected {
-            return Err(EcError::DeviceError(format!(
-                "Returned data size ({}) is not the expted size: {}",
-                response.len(),
-                std::mem::size_of::<R>()
-            )));
-        }
+        check_response_len(&response, std::mem::size_of::<R>())?;
         let val: R = unsafe { std::ptr::read(response.as_ptr() as *const _) };
         Ok(val)
     }
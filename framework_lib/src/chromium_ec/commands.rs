@@ -6,6 +6,22 @@ use super::{command::*, input_deck::INPUT_DECK_SLOTS};
 #[cfg(feature = "uefi")]
 use core::prelude::rust_2021::derive;
 
+#[repr(C, packed)]
+pub struct EcRequestHello {
+    pub in_data: u32,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseHello {
+    /// Should be in_data + 0x01020304
+    pub out_data: u32,
+}
+impl EcRequest<EcResponseHello> for EcRequestHello {
+    fn command_id() -> EcCommands {
+        EcCommands::Hello
+    }
+}
+
 #[repr(C, packed)]
 pub struct EcRequestGetVersion {}
 
@@ -26,6 +42,24 @@ impl EcRequest<EcResponseGetVersion> for EcRequestGetVersion {
     }
 }
 
+#[repr(C, packed)]
+pub struct EcRequestGetChipInfo {}
+
+#[repr(C, packed)]
+pub struct EcResponseGetChipInfo {
+    /// Null-terminated name of the chip vendor
+    pub vendor: [u8; 32],
+    /// Null-terminated name of the chip
+    pub name: [u8; 32],
+    /// Null-terminated chip revision
+    pub revision: [u8; 32],
+}
+impl EcRequest<EcResponseGetChipInfo> for EcRequestGetChipInfo {
+    fn command_id() -> EcCommands {
+        EcCommands::GetChipInfo
+    }
+}
+
 #[repr(C, packed)]
 pub struct EcRequestGetCmdVersionsV0 {
     pub cmd: u8,
@@ -62,6 +96,24 @@ impl EcRequest<EcResponseGetCmdVersionsV1> for EcRequestGetCmdVersionsV1 {
     }
 }
 
+#[repr(C, packed)]
+pub struct EcRequestGetProtocolInfo {}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EcResponseGetProtocolInfo {
+    /// Bitmask of supported protocol versions (1 << 3 means v3 is supported)
+    pub protocol_versions: u32,
+    pub max_request_packet_size: u16,
+    pub max_response_packet_size: u16,
+    pub flags: u32,
+}
+impl EcRequest<EcResponseGetProtocolInfo> for EcRequestGetProtocolInfo {
+    fn command_id() -> EcCommands {
+        EcCommands::GetProtocolInfo
+    }
+}
+
 pub struct EcRequestFlashInfo {}
 
 #[repr(C, packed)]
@@ -225,6 +277,165 @@ impl EcRequest<EcResponsePwmGetDuty> for EcRequestPwmGetDuty {
     }
 }
 
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcLedId {
+    BatteryLed = 0,
+    /// On Framework laptops, the LED in the power button with fingerprint reader
+    PowerLed = 1,
+    AdapterLed = 2,
+    LeftLed = 3,
+    RightLed = 4,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcLedColor {
+    Red = 0,
+    Green = 1,
+    Blue = 2,
+    Yellow = 3,
+    White = 4,
+    Amber = 5,
+}
+
+pub const EC_LED_COLOR_COUNT: usize = 6;
+
+/// Only query the brightness range of each color, don't change the LED
+pub const EC_LED_FLAGS_QUERY: u8 = 1 << 0;
+/// Return the LED to automatic control by the EC
+pub const EC_LED_FLAGS_AUTO: u8 = 1 << 1;
+
+#[repr(C, packed)]
+pub struct EcRequestLedControl {
+    /// See enum EcLedId
+    pub led_id: u8,
+    /// EC_LED_FLAGS_*, 0 to set the brightness
+    pub flags: u8,
+    /// Brightness of each color, indexed by EcLedColor
+    pub brightness: [u8; EC_LED_COLOR_COUNT],
+}
+
+#[repr(C, packed)]
+pub struct EcResponseLedControl {
+    /// Maximum brightness of each color, 0 if the LED doesn't have it
+    pub brightness_range: [u8; EC_LED_COLOR_COUNT],
+}
+
+impl EcRequest<EcResponseLedControl> for EcRequestLedControl {
+    fn command_id() -> EcCommands {
+        EcCommands::LedControl
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+/// Set the duty cycle of all fans, disabling automatic fan control
+#[repr(C, packed)]
+pub struct EcRequestPwmSetFanDutyV0 {
+    /// Duty cycle in percent
+    pub percent: u32,
+}
+
+impl EcRequest<()> for EcRequestPwmSetFanDutyV0 {
+    fn command_id() -> EcCommands {
+        EcCommands::PwmSetFanDuty
+    }
+}
+
+/// Set the duty cycle of a single fan, disabling automatic control of that fan
+#[repr(C, packed)]
+pub struct EcRequestPwmSetFanDutyV1 {
+    /// Duty cycle in percent
+    pub percent: u32,
+    pub fan_idx: u8,
+}
+
+impl EcRequest<()> for EcRequestPwmSetFanDutyV1 {
+    fn command_id() -> EcCommands {
+        EcCommands::PwmSetFanDuty
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+/// Index into `temp_host` of EcResponseThermalGetThresholdV1
+#[repr(usize)]
+pub enum EcTempThreshold {
+    /// Host is notified
+    Warn = 0,
+    /// EC asserts PROCHOT to throttle the CPU
+    High,
+    /// EC shuts down the system
+    Halt,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestThermalGetThresholdV1 {
+    pub sensor_num: u32,
+}
+
+/// All temperatures are in Kelvin, 0 means not used
+#[repr(C, packed)]
+pub struct EcResponseThermalGetThresholdV1 {
+    /// Thresholds, see EcTempThreshold
+    pub temp_host: [u32; 4],
+    /// Below these the action of `temp_host` is reverted
+    pub temp_host_release: [u32; 4],
+    /// Fan is off at or below this
+    pub temp_fan_off: u32,
+    /// Fan is at maximum speed at or above this
+    pub temp_fan_max: u32,
+}
+
+impl EcRequest<EcResponseThermalGetThresholdV1> for EcRequestThermalGetThresholdV1 {
+    fn command_id() -> EcCommands {
+        EcCommands::ThermalGetThreshold
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+/// Enable automatic control of all fans
+#[repr(C, packed)]
+pub struct EcRequestAutoFanCtrlV0 {}
+
+impl EcRequest<()> for EcRequestAutoFanCtrlV0 {
+    fn command_id() -> EcCommands {
+        EcCommands::ThermalAutoFanCtrl
+    }
+}
+
+/// Enable automatic control of a single fan
+#[repr(C, packed)]
+pub struct EcRequestAutoFanCtrlV1 {
+    pub fan_idx: u8,
+}
+
+impl EcRequest<()> for EcRequestAutoFanCtrlV1 {
+    fn command_id() -> EcCommands {
+        EcCommands::ThermalAutoFanCtrl
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestGpioSetV0 {
+    pub name: [u8; 32],
+    pub val: u8,
+}
+
+impl EcRequest<()> for EcRequestGpioSetV0 {
+    fn command_id() -> EcCommands {
+        EcCommands::GpioSet
+    }
+}
+
 #[repr(C, packed)]
 pub struct EcRequestGpioGetV0 {
     pub name: [u8; 32],
@@ -244,6 +455,277 @@ impl EcRequest<EcResponseGpioGetV0> for EcRequestGpioGetV0 {
     }
 }
 
+/// Sub-commands of EcCommands::MotionSense
+#[repr(u8)]
+pub enum MotionSenseCmd {
+    PerformCalib = 10,
+    SensorOffset = 11,
+    LidAngle = 14,
+    TabletModeLidAngle = 17,
+}
+
+/// Lid angle, if the EC can't tell, e.g. because the lid is close to fully open
+pub const LID_ANGLE_UNRELIABLE: i16 = 500;
+/// Don't change a value, only read it
+pub const EC_MOTION_SENSE_NO_VALUE: i16 = -1;
+/// Write the offset in the request, instead of only reading it
+pub const MOTION_SENSE_SET_OFFSET: u16 = 1 << 0;
+/// Offset doesn't depend on the temperature it was calibrated at
+pub const EC_MOTION_SENSE_INVALID_CALIB_TEMP: i16 = 0x8000u16 as i16;
+
+#[repr(C, packed)]
+pub struct EcRequestMotionSenseLidAngle {
+    /// MotionSenseCmd::LidAngle
+    pub cmd: u8,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseMotionSenseLidAngle {
+    /// Degrees, or LID_ANGLE_UNRELIABLE
+    pub value: i16,
+}
+
+impl EcRequest<EcResponseMotionSenseLidAngle> for EcRequestMotionSenseLidAngle {
+    fn command_id() -> EcCommands {
+        EcCommands::MotionSense
+    }
+    fn command_version() -> u8 {
+        2
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestMotionSenseTabletMode {
+    /// MotionSenseCmd::TabletModeLidAngle
+    pub cmd: u8,
+    /// Lid angle that enters tablet mode, EC_MOTION_SENSE_NO_VALUE to only read it
+    pub lid_angle: i16,
+    /// Hysteresis in degrees, EC_MOTION_SENSE_NO_VALUE to only read it
+    pub hys_degree: i16,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseMotionSenseTabletMode {
+    pub lid_angle: i16,
+    pub hys_degree: i16,
+}
+
+impl EcRequest<EcResponseMotionSenseTabletMode> for EcRequestMotionSenseTabletMode {
+    fn command_id() -> EcCommands {
+        EcCommands::MotionSense
+    }
+    fn command_version() -> u8 {
+        2
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestMotionSensePerformCalib {
+    /// MotionSenseCmd::PerformCalib
+    pub cmd: u8,
+    pub sensor_num: u8,
+    /// 1 to calibrate, 0 to stop calibrating
+    pub enable: u8,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseMotionSensePerformCalib {
+    /// Offset for X, Y and Z that the sensor was calibrated to
+    pub offset: [i16; 3],
+}
+
+impl EcRequest<EcResponseMotionSensePerformCalib> for EcRequestMotionSensePerformCalib {
+    fn command_id() -> EcCommands {
+        EcCommands::MotionSense
+    }
+    fn command_version() -> u8 {
+        2
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestMotionSenseSensorOffset {
+    /// MotionSenseCmd::SensorOffset
+    pub cmd: u8,
+    pub sensor_num: u8,
+    /// MOTION_SENSE_SET_OFFSET to write the offset, 0 to only read it
+    pub flags: u16,
+    /// Temperature at calibration, in 1/100 degree Celsius, or EC_MOTION_SENSE_INVALID_CALIB_TEMP
+    pub temp: i16,
+    /// Offset for X, Y and Z, in the units of the sensor's raw data
+    pub offset: [i16; 3],
+}
+
+#[repr(C, packed)]
+pub struct EcResponseMotionSenseSensorOffset {
+    pub temp: i16,
+    pub offset: [i16; 3],
+}
+
+impl EcRequest<EcResponseMotionSenseSensorOffset> for EcRequestMotionSenseSensorOffset {
+    fn command_id() -> EcCommands {
+        EcCommands::MotionSense
+    }
+    fn command_version() -> u8 {
+        2
+    }
+}
+
+#[repr(u8)]
+pub enum MkbpInfoType {
+    /// Current state of the event type
+    Current = 2,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum MkbpEventType {
+    KeyMatrix = 0,
+    HostEvent = 1,
+    SensorFifo = 2,
+    Button = 3,
+    Switch = 4,
+    Fingerprint = 5,
+    Sysrq = 6,
+    HostEvent64 = 7,
+}
+
+/// Set in the event type of EcRequestGetNextEvent v2 and later, if more events are queued
+pub const EC_MKBP_HAS_MORE_EVENTS: u8 = 0x80;
+
+/// Bits in EcResponseMkbpSwitches
+pub const EC_MKBP_LID_OPEN: u32 = 1 << 0;
+pub const EC_MKBP_TABLET_MODE: u32 = 1 << 1;
+
+/// Bits of the button event
+pub const EC_MKBP_POWER_BUTTON: u32 = 1 << 0;
+pub const EC_MKBP_VOL_UP: u32 = 1 << 1;
+pub const EC_MKBP_VOL_DOWN: u32 = 1 << 2;
+
+#[repr(C, packed)]
+pub struct EcRequestMkbpInfo {
+    pub info_type: MkbpInfoType,
+    pub event_type: MkbpEventType,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseMkbpSwitches {
+    pub switches: u32,
+}
+
+impl EcRequest<EcResponseMkbpSwitches> for EcRequestMkbpInfo {
+    fn command_id() -> EcCommands {
+        EcCommands::MkbpInfo
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+/// Events that the EC reports to the host, see EC_HOST_EVENT_* in the EC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HostEventCode {
+    LidClosed = 1,
+    LidOpen = 2,
+    PowerButton = 3,
+    AcConnected = 4,
+    AcDisconnected = 5,
+    BatteryLow = 6,
+    BatteryCritical = 7,
+    KeyPressed = 13,
+    Rtc = 26,
+    Mkbp = 27,
+    /// Switched between laptop and tablet mode
+    ModeChange = 29,
+}
+
+impl HostEventCode {
+    /// Bit of the event in the host event masks
+    pub fn mask(&self) -> u64 {
+        1 << (*self as u8 - 1)
+    }
+}
+
+#[repr(u8)]
+pub enum HostEventAction {
+    Get = 0,
+    Set = 1,
+    Clear = 2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HostEventMaskType {
+    Main = 0,
+    B = 1,
+    SciMask = 2,
+    SmiMask = 3,
+    AlwaysReportMask = 4,
+    /// Events that wake the system in the current state
+    ActiveWakeMask = 5,
+    /// Events that wake the system, applied when it enters S0ix
+    LazyWakeMaskS0ix = 6,
+    /// Events that wake the system, applied when it enters S3
+    LazyWakeMaskS3 = 7,
+    /// Events that wake the system, applied when it enters S5
+    LazyWakeMaskS5 = 8,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestHostEvent {
+    /// See HostEventAction
+    pub action: u8,
+    /// See HostEventMaskType
+    pub mask_type: u8,
+    pub reserved: u16,
+    /// Mask to set or bits to clear, ignored when getting
+    pub value: u64,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseHostEvent {
+    pub value: u64,
+}
+
+impl EcRequest<EcResponseHostEvent> for EcRequestHostEvent {
+    fn command_id() -> EcCommands {
+        EcCommands::HostEvent
+    }
+}
+
+/// Returns the event type, followed by data of the size that the type needs
+///
+/// Fails with EcResponseStatus::Unavailable if there is no event.
+#[repr(C, packed)]
+pub struct EcRequestGetNextEvent {}
+
+impl EcRequest<()> for EcRequestGetNextEvent {
+    fn command_id() -> EcCommands {
+        EcCommands::GetNextEvent
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TabletModeOverride {
+    /// Let the EC decide based on the hinge angle
+    Default = 0,
+    ForceTablet = 1,
+    ForceClamshell = 2,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestSetTabletMode {
+    pub tablet_mode: u8,
+}
+
+impl EcRequest<()> for EcRequestSetTabletMode {
+    fn command_id() -> EcCommands {
+        EcCommands::SetTabletMode
+    }
+}
+
 #[repr(C, packed)]
 pub struct EcRequestReboot {}
 
@@ -253,6 +735,29 @@ impl EcRequest<()> for EcRequestReboot {
     }
 }
 
+#[repr(u8)]
+pub enum BatteryCutoffFlags {
+    /// Cut off the battery right away
+    Immediate = 0,
+    /// Cut off the battery once the system shuts down
+    AtShutdown = 0x01,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestBatteryCutoffV1 {
+    /// See enum BatteryCutoffFlags
+    pub flags: u8,
+}
+
+impl EcRequest<()> for EcRequestBatteryCutoffV1 {
+    fn command_id() -> EcCommands {
+        EcCommands::BatteryCutOff
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
 pub struct EcRequestConsoleSnapshot {}
 impl EcRequest<()> for EcRequestConsoleSnapshot {
     fn command_id() -> EcCommands {
@@ -500,6 +1005,284 @@ impl EcRequest<EcResponseUsbPdPowerInfo> for EcRequestUsbPdPowerInfo {
     }
 }
 
+#[repr(C, packed)]
+pub struct EcRequestUsbPdDiscovery {
+    pub port: u8,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseUsbPdDiscovery {
+    /// USB vendor ID of the partner, 0 if it didn't respond to Discover Identity
+    pub vid: u16,
+    pub pid: u16,
+    /// Product type from the ID header VDO
+    pub ptype: u8,
+}
+
+impl EcRequest<EcResponseUsbPdDiscovery> for EcRequestUsbPdDiscovery {
+    fn command_id() -> EcCommands {
+        EcCommands::UsbPdDiscovery
+    }
+}
+
+#[repr(u8)]
+pub enum UsbPdControlRole {
+    NoChange = 0,
+    /// Dual role, toggle between sink and source
+    ToggleOn = 1,
+    ToggleOff = 2,
+    ForceSink = 3,
+    ForceSource = 4,
+    Freeze = 5,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestUsbPdControl {
+    pub port: u8,
+    /// See enum UsbPdControlRole
+    pub role: u8,
+    /// USB mux setting, 0 to keep it unchanged
+    pub mux: u8,
+    /// Role swap to request, 0 for none
+    pub swap: u8,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseUsbPdControl {
+    pub enabled: u8,
+    /// Bit 0 set if power source, bit 1 set if data host
+    pub role: u8,
+    pub polarity: u8,
+    pub state: u8,
+}
+
+impl EcRequest<EcResponseUsbPdControl> for EcRequestUsbPdControl {
+    fn command_id() -> EcCommands {
+        EcCommands::UsbPdControl
+    }
+}
+
+/// Value of EcRequestExternalPowerLimitV1 fields to remove the limit
+pub const EC_POWER_LIMIT_NONE: u16 = 0xFFFF;
+
+#[repr(C, packed)]
+pub struct EcRequestExternalPowerLimitV1 {
+    /// Current limit in mA
+    pub current_lim: u16,
+    /// Voltage limit in mV
+    pub voltage_lim: u16,
+}
+
+impl EcRequest<()> for EcRequestExternalPowerLimitV1 {
+    fn command_id() -> EcCommands {
+        EcCommands::ExternalPowerLimit
+    }
+    fn command_version() -> u8 {
+        1
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestHibernationDelay {
+    /// New delay in seconds, or 0 to only read it
+    pub seconds: u32,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseHibernationDelay {
+    /// How long the system has been in G3, in seconds
+    pub time_g3: u32,
+    /// Seconds until the EC hibernates, if in G3
+    pub time_remaining: u32,
+    /// Seconds in G3 before hibernating
+    pub hibernate_delay: u32,
+}
+
+impl EcRequest<EcResponseHibernationDelay> for EcRequestHibernationDelay {
+    fn command_id() -> EcCommands {
+        EcCommands::HibernationDelay
+    }
+}
+
+/// Response is a PD log entry: 8 byte header, followed by the payload
+#[repr(C, packed)]
+pub struct EcRequestPdGetLogEntry {}
+
+impl EcRequest<()> for EcRequestPdGetLogEntry {
+    fn command_id() -> EcCommands {
+        EcCommands::PdGetLogEntry
+    }
+}
+
+/// Response is `size` bytes of the UCSI data structure, starting at `offset`
+#[repr(C, packed)]
+pub struct EcRequestUcsiPpmGet {
+    pub offset: u16,
+    pub size: u8,
+}
+
+impl EcRequest<()> for EcRequestUcsiPpmGet {
+    fn command_id() -> EcCommands {
+        EcCommands::UcsiPpmGet
+    }
+}
+
+/// Followed by the data to write to the UCSI data structure, starting at `offset`
+#[repr(C, packed)]
+pub struct EcRequestUcsiPpmSet {
+    pub offset: u16,
+}
+
+impl EcRequest<()> for EcRequestUcsiPpmSet {
+    fn command_id() -> EcCommands {
+        EcCommands::UcsiPpmSet
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestRollbackInfo {}
+
+#[repr(C, packed)]
+pub struct EcResponseRollbackInfo {
+    /// Incremented every time the rollback block is updated
+    pub id: i32,
+    /// RO refuses to jump to an RW with a lower rollback version
+    pub rollback_min_version: i32,
+    /// Rollback version of the RW that's currently running
+    pub rw_rollback_version: i32,
+}
+
+impl EcRequest<EcResponseRollbackInfo> for EcRequestRollbackInfo {
+    fn command_id() -> EcCommands {
+        EcCommands::RollbackInfo
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestGetUptimeInfo {}
+
+#[repr(C, packed)]
+pub struct EcResponseGetUptimeInfo {
+    /// Wraps around after about 49 days
+    pub time_since_ec_boot_ms: u32,
+    pub ap_resets_since_ec_boot: u32,
+    pub ec_reset_flags: u32,
+    /// Cause and time of the most recent AP resets
+    pub recent_ap_reset: [u32; 8],
+}
+
+impl EcRequest<EcResponseGetUptimeInfo> for EcRequestGetUptimeInfo {
+    fn command_id() -> EcCommands {
+        EcCommands::GetUptimeInfo
+    }
+}
+
+#[repr(u8)]
+pub enum ChargeStateCmd {
+    GetState = 0,
+    GetParam = 1,
+    SetParam = 2,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestChargeStateGetV0 {
+    /// See enum ChargeStateCmd
+    pub cmd: u8,
+    pub param: u32,
+}
+
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub struct EcResponseChargeStateGetV0 {
+    pub ac: u32,
+    /// Voltage requested by the charger in mV
+    pub chg_voltage: u32,
+    /// Current requested by the charger in mA
+    pub chg_current: u32,
+    /// Input current limit in mA
+    pub chg_input_current: u32,
+    /// Battery state of charge in percent
+    pub batt_state_of_charge: u32,
+}
+
+impl EcRequest<EcResponseChargeStateGetV0> for EcRequestChargeStateGetV0 {
+    fn command_id() -> EcCommands {
+        EcCommands::ChargeState
+    }
+}
+
+/// Charger parameters that can be read with ChargeStateCmd::GetParam
+/// and overridden with ChargeStateCmd::SetParam
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChargeStateParam {
+    /// Charger voltage in mV
+    ChargerVoltage = 0,
+    /// Charger current in mA
+    ChargerCurrent = 1,
+    /// Input current limit in mA
+    InputCurrentLimit = 2,
+    /// Status register of the charger chip, read-only
+    ChargerStatus = 3,
+    /// Option register of the charger chip
+    ChargerOption = 4,
+    /// Whether the system should limit its power draw, read-only
+    LimitPower = 5,
+}
+
+impl ChargeStateParam {
+    pub const ALL: &'static [ChargeStateParam] = &[
+        ChargeStateParam::ChargerVoltage,
+        ChargeStateParam::ChargerCurrent,
+        ChargeStateParam::InputCurrentLimit,
+        ChargeStateParam::ChargerStatus,
+        ChargeStateParam::ChargerOption,
+        ChargeStateParam::LimitPower,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChargeStateParam::ChargerVoltage => "charger_voltage",
+            ChargeStateParam::ChargerCurrent => "charger_current",
+            ChargeStateParam::InputCurrentLimit => "input_current",
+            ChargeStateParam::ChargerStatus => "status",
+            ChargeStateParam::ChargerOption => "option",
+            ChargeStateParam::LimitPower => "limit_power",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().find(|x| x.name() == name).copied()
+    }
+
+    /// Whether the EC lets the host override it
+    pub fn writable(&self) -> bool {
+        !matches!(
+            self,
+            ChargeStateParam::ChargerStatus | ChargeStateParam::LimitPower
+        )
+    }
+}
+
+#[repr(C, packed)]
+pub struct EcRequestChargeStateGetParamV0 {
+    /// Must be ChargeStateCmd::GetParam
+    pub cmd: u8,
+    /// See enum ChargeStateParam
+    pub param: u32,
+}
+
+#[repr(C, packed)]
+pub struct EcResponseChargeStateGetParamV0 {
+    pub value: u32,
+}
+
+impl EcRequest<EcResponseChargeStateGetParamV0> for EcRequestChargeStateGetParamV0 {
+    fn command_id() -> EcCommands {
+        EcCommands::ChargeState
+    }
+}
+
 // --- Framework Specific commands ---
 
 #[repr(C, packed)]
@@ -531,6 +1314,9 @@ impl EcRequest<EcResponseChassisOpenCheck> for EcRequestChassisOpenCheck {
     }
 }
 
+/// Set `clear_magic` to this to clear whether the chassis was ever opened
+pub const CHASSIS_INTRUSION_CLEAR_MAGIC: u8 = 0xCE;
+
 #[repr(C, packed)]
 pub struct EcRequestChassisIntrusionControl {
     pub clear_magic: u8,
@@ -582,6 +1368,7 @@ impl EcRequest<EcResponsePrivacySwitches> for EcRequestPrivacySwitches {
 }
 
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeckStateMode {
     ReadOnly = 0x00,
     Required = 0x01,
@@ -606,6 +1393,41 @@ impl EcRequest<EcResponseDeckState> for EcRequestDeckState {
     }
 }
 
+/// Most entries in one EcRequestUpdateKeyboardMatrix
+pub const KB_MATRIX_MAX_ITEMS: usize = 32;
+
+/// Position in the keyboard matrix and the PS/2 set 2 scancode it sends
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct KeyboardMatrixMap {
+    pub row: u8,
+    pub col: u8,
+    pub scanset: u16,
+}
+
+#[repr(C, packed)]
+pub struct EcRequestUpdateKeyboardMatrix {
+    /// How many entries of scan_update are used
+    pub num_items: u32,
+    /// Boolean. If not set, scanset of each entry is ignored and just read back
+    pub write: u32,
+    pub scan_update: [KeyboardMatrixMap; KB_MATRIX_MAX_ITEMS],
+}
+
+#[repr(C, packed)]
+pub struct EcResponseUpdateKeyboardMatrix {
+    pub num_items: u32,
+    pub write: u32,
+    /// Same positions as in the request, with their current scancode
+    pub scan_update: [KeyboardMatrixMap; KB_MATRIX_MAX_ITEMS],
+}
+
+impl EcRequest<EcResponseUpdateKeyboardMatrix> for EcRequestUpdateKeyboardMatrix {
+    fn command_id() -> EcCommands {
+        EcCommands::UpdateKeyboardMatrix
+    }
+}
+
 // TODO
 #[repr(C, packed)]
 pub struct EcRequestUefiAppMode {
@@ -821,7 +1643,7 @@ impl EcRequest<EcResponseChargeLimitControl> for EcRequestChargeLimitControl {
 pub const EC_CHARGE_LIMIT_RESTORE: u8 = 0x7F;
 
 #[repr(u8)]
-#[derive(Debug, FromPrimitive)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
 pub enum FpLedBrightnessLevel {
     High = 0,
     Medium = 1,
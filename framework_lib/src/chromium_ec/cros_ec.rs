@@ -1,10 +1,14 @@
-use nix::ioctl_readwrite;
+use nix::errno::Errno;
+use nix::{ioctl_readwrite, ioctl_write_int_bad, request_code_none};
 use num_traits::FromPrimitive;
+use std::io::Read;
 use std::os::unix::io::AsRawFd;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::chromium_ec::command::EcCommands;
+use crate::chromium_ec::command::{is_idempotent, retry_policy, EcCommands, RetryPolicy};
 use crate::chromium_ec::{EcError, EcResponseStatus, EcResult, EC_MEMMAP_SIZE};
+use crate::os_specific;
 use crate::util;
 
 // TODO: There's no actual limit. I hope this is enough.
@@ -57,34 +61,131 @@ struct CrosEcCommandV2 {
 
 const DEV_PATH: &str = "/dev/cros_ec";
 
+/// Maximum length of the version string that the kernel driver returns
+const VERSION_LEN: usize = 80;
+
+/// Opened kernel device
+struct Device {
+    file: std::fs::File,
+    /// Version of the kernel driver's interface, e.g. "1.0.0"
+    version: String,
+}
+
 lazy_static! {
-    static ref CROS_EC_FD: Arc<Mutex<Option<std::fs::File>>> = Arc::new(Mutex::new(None));
+    static ref CROS_EC_FD: Arc<Mutex<Option<Device>>> = Arc::new(Mutex::new(None));
 }
 
 const CROS_EC_IOC_MAGIC: u8 = 0xEC;
+// CROS_EC_DEV_IOCXCMD_V2
 ioctl_readwrite!(cros_ec_cmd, CROS_EC_IOC_MAGIC, 0, _CrosEcCommandV2);
+// CROS_EC_DEV_IOCRDMEM_V2
 ioctl_readwrite!(cros_ec_mem, CROS_EC_IOC_MAGIC, 1, CrosEcReadMem);
-// TODO: Implement polling and re-try mechanism
-//ioctl_none!(cros_ec_eventmask, CROS_EC_IOC_MAGIC, 2);
+// CROS_EC_DEV_IOCEVENTMASK_V2, takes the mask itself as the argument
+ioctl_write_int_bad!(cros_ec_eventmask, request_code_none!(CROS_EC_IOC_MAGIC, 2));
+
+/// Largest MKBP event the kernel returns, the type and its data
+const EVENT_SIZE: usize = 64;
+
+/// Sends a single command to the EC
+///
+/// Separate from the rest of the protocol handling, so that it can be tested
+/// without the kernel driver.
+trait CommandXfer {
+    fn xfer(&self, cmd: &mut CrosEcCommandV2) -> nix::Result<i32>;
+}
+
+struct KernelXfer(i32);
+
+impl CommandXfer for KernelXfer {
+    fn xfer(&self, cmd: &mut CrosEcCommandV2) -> nix::Result<i32> {
+        let cmd_ptr = cmd as *mut _ as *mut _CrosEcCommandV2;
+        unsafe { cros_ec_cmd(self.0, cmd_ptr) }
+    }
+}
 
 fn get_fildes() -> i32 {
-    let fd = CROS_EC_FD.lock().unwrap();
-    fd.as_ref().unwrap().as_raw_fd()
+    let device = CROS_EC_FD.lock().unwrap();
+    device.as_ref().unwrap().file.as_raw_fd()
 }
 
 // TODO: Also de-init
-fn init() {
+fn init() -> EcResult<()> {
     let mut device = CROS_EC_FD.lock().unwrap();
     if (*device).is_some() {
-        return;
+        return Ok(());
+    }
+    // TODO: Read memory EC_MEMMAP_ID and check if it has "EC"
+    let mut file = std::fs::File::open(DEV_PATH).map_err(|why| {
+        let msg = format!("Failed to open {}. Because: {:?}", DEV_PATH, why);
+        match why.kind() {
+            std::io::ErrorKind::NotFound => EcError::DriverUnavailable(msg),
+            std::io::ErrorKind::PermissionDenied => EcError::Permission(msg),
+            _ => EcError::Io(msg),
+        }
+    })?;
+
+    // The driver returns its version on the first read. Can only read it once.
+    let mut buf = [0; VERSION_LEN];
+    let len = file
+        .read(&mut buf)
+        .map_err(|err| EcError::Io(format!("Failed to read driver version: {:?}", err)))?;
+    let version = String::from_utf8_lossy(&buf[..len]).trim().to_string();
+    debug!("cros_ec driver version: {}", version);
+    // The v2 ioctls were introduced with version 1.0.0
+    if !version.starts_with("1.") {
+        warn!("Unexpected cros_ec driver version: {:?}", version);
     }
-    match std::fs::File::open(DEV_PATH) {
-        Err(why) => println!("Failed to open {}. Because: {:?}", DEV_PATH, why),
-        Ok(file) => *device = Some(file),
+
+    *device = Some(Device { file, version });
+    Ok(())
+}
+
+/// Version of the kernel driver's interface
+pub fn driver_version() -> EcResult<String> {
+    init()?;
+    let device = CROS_EC_FD.lock().unwrap();
+    Ok(device.as_ref().unwrap().version.clone())
+}
+
+/// Wait for an MKBP event that the kernel driver received from the EC
+///
+/// The driver fetches the events as soon as the EC signals them, so they must
+/// not be fetched with a command. `mask` has a bit for each `MkbpEventType`.
+/// Returns the event type followed by its data, or None after the timeout.
+pub fn wait_event(mask: u32, timeout: Duration) -> EcResult<Option<Vec<u8>>> {
+    init()?;
+    let fd = get_fildes();
+    unsafe { cros_ec_eventmask(fd, mask as i32) }
+        .map_err(|err| EcError::Io(format!("ioctl to set event mask failed with {:?}", err)))?;
+
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
     };
-    // 2. Read max 80 bytes and check if equal to "1.0.0"
-    // 3. Make sure it's v2
-    // 4. Read memory EC_MEMMAP_ID and check if it has "EC"
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    match unsafe { libc::poll(&mut pollfd, 1, timeout_ms) } {
+        0 => return Ok(None),
+        // Interrupted by a signal, same as no event
+        res if res < 0 && Errno::last() == Errno::EINTR => return Ok(None),
+        res if res < 0 => {
+            return Err(EcError::Io(format!(
+                "Failed to wait for EC event: {:?}",
+                Errno::last()
+            )))
+        }
+        _ => {}
+    }
+
+    let mut buf = [0u8; EVENT_SIZE];
+    let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+    if len < 0 {
+        return Err(EcError::Io(format!(
+            "Failed to read EC event: {:?}",
+            Errno::last()
+        )));
+    }
+    Ok(Some(buf[..len as usize].to_vec()))
 }
 
 /// Parameters for command to read memory map
@@ -111,7 +212,7 @@ fn read_mem_via_cmd(offset: u16, length: u16) -> EcResult<Vec<u8>> {
         "Trying to read via cmd. Offset: {}, length: {}",
         offset, length
     );
-    init();
+    init()?;
 
     let cmd = EcParamsReadMemMap {
         offset: offset as u8,
@@ -122,7 +223,7 @@ fn read_mem_via_cmd(offset: u16, length: u16) -> EcResult<Vec<u8>> {
 }
 
 fn read_mem_directly(offset: u16, length: u16) -> EcResult<Vec<u8>> {
-    init();
+    init()?;
 
     let mut data = CrosEcReadMem {
         offset: offset as u32,
@@ -130,54 +231,250 @@ fn read_mem_directly(offset: u16, length: u16) -> EcResult<Vec<u8>> {
         out_buffer: [0; EC_MEMMAP_SIZE as usize],
     };
     unsafe {
-        // TODO: Check result
-        let _result = cros_ec_mem(get_fildes(), &mut data).unwrap();
+        let _result = cros_ec_mem(get_fildes(), &mut data)
+            .map_err(|err| EcError::Io(format!("ioctl to read EC memory failed with {:?}", err)))?;
     }
     Ok(data.out_buffer[0..length as usize].to_vec())
 }
 
-// TODO: Clean this up
 pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<Vec<u8>> {
-    init();
-
-    let size = std::cmp::min(IN_SIZE, data.len());
-
-    let mut cmd = CrosEcCommandV2 {
-        version: command_version as u32,
-        command: command as u32,
-        outsize: size as u32,
-        insize: IN_SIZE as u32,
-        result: 0xFF,
-        // TODO: There is no max length!!
-        // ec-tool handles this by having the out-len as a parameter
-        // I don't want to do this...
-        // Probably I should find out how much each command is expected to return and dynamically allocate that
-        data: [0; IN_SIZE],
-    };
+    init()?;
+    xfer_command(
+        &KernelXfer(get_fildes()),
+        &retry_policy(),
+        command,
+        command_version,
+        data,
+    )
+}
+
+/// Send the command, retrying when the kernel reports a transient error
+///
+/// The kernel driver already retries while the EC reports that the command is
+/// in progress. But the ioctl can fail with EAGAIN or EBUSY while another
+/// process is talking to the EC, or with EINTR when a signal arrives.
+/// It's not known whether the EC got the command already, so only idempotent
+/// commands are retried, see `is_idempotent`.
+fn xfer_command(
+    dev: &dyn CommandXfer,
+    policy: &RetryPolicy,
+    command: u16,
+    command_version: u8,
+    data: &[u8],
+) -> EcResult<Vec<u8>> {
+    if data.len() > IN_SIZE {
+        return Err(EcError::DeviceError(format!(
+            "Request of {} bytes is larger than the maximum of {} bytes",
+            data.len(),
+            IN_SIZE
+        )));
+    }
 
-    cmd.data[0..size].copy_from_slice(data);
-    let cmd_ptr = &mut cmd as *mut _ as *mut _CrosEcCommandV2;
+    let idempotent = is_idempotent(command);
+    let mut backoff_ms = policy.initial_backoff_ms;
+    let mut retries = 0;
+    loop {
+        let mut cmd = CrosEcCommandV2 {
+            version: command_version as u32,
+            command: command as u32,
+            outsize: data.len() as u32,
+            insize: IN_SIZE as u32,
+            result: 0xFF,
+            // TODO: There is no max length!!
+            // ec-tool handles this by having the out-len as a parameter
+            // I don't want to do this...
+            // Probably I should find out how much each command is expected to return and dynamically allocate that
+            data: [0; IN_SIZE],
+        };
+        cmd.data[0..data.len()].copy_from_slice(data);
+
+        let result_size = match dev.xfer(&mut cmd) {
+            Err(err @ (Errno::EAGAIN | Errno::EBUSY | Errno::EINTR))
+                if idempotent && retries < policy.max_retries =>
+            {
+                debug!(
+                    "ioctl for command {:X?} failed with {:?}, retrying in {}ms",
+                    <EcCommands as FromPrimitive>::from_u16(command),
+                    err,
+                    backoff_ms
+                );
+                os_specific::sleep(backoff_ms * 1000);
+                backoff_ms *= 2;
+                retries += 1;
+                continue;
+            }
+            Err(err) => {
+                return Err(EcError::Io(format!(
+                    "ioctl to send command to EC failed with {:?}",
+                    err
+                )))
+            }
+            Ok(result_size) => result_size,
+        };
 
-    unsafe {
-        let result = cros_ec_cmd(get_fildes(), cmd_ptr);
         let status: Option<EcResponseStatus> = FromPrimitive::from_u32(cmd.result);
-        match &status {
+        match status {
             None => return Err(EcError::UnknownResponseCode(cmd.result)),
             Some(EcResponseStatus::Success) => {}
-            Some(status) => return Err(EcError::Response(*status)),
+            Some(status) => return Err(EcError::Response(status)),
         }
 
-        match result {
-            Ok(result) => {
-                let result_size = result as usize; // How many bytes were returned
-                let result_data = &cmd.data[0..result_size];
+        // How many bytes were returned. Don't trust the driver to stay within the buffer.
+        return usize::try_from(result_size)
+            .ok()
+            .and_then(|size| cmd.data.get(0..size))
+            .map(|result_data| result_data.to_vec())
+            .ok_or_else(|| {
+                EcError::ProtocolMismatch(format!(
+                    "Driver returned {} bytes, buffer only has {}",
+                    result_size, IN_SIZE
+                ))
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// What the mock device does for one ioctl
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Errno(Errno),
+        /// EC result code and number of bytes the driver claims to return
+        Response(u32, i32),
+    }
+
+    /// Pretends to be the kernel driver and echoes the request back
+    struct MockXfer {
+        replies: RefCell<VecDeque<Reply>>,
+        calls: Cell<usize>,
+    }
+
+    impl MockXfer {
+        fn new(replies: &[Reply]) -> Self {
+            MockXfer {
+                replies: RefCell::new(replies.iter().copied().collect()),
+                calls: Cell::new(0),
+            }
+        }
+    }
+
+    impl CommandXfer for MockXfer {
+        fn xfer(&self, cmd: &mut CrosEcCommandV2) -> nix::Result<i32> {
+            self.calls.set(self.calls.get() + 1);
+            assert!(cmd.outsize <= cmd.insize);
+            assert_eq!(cmd.insize as usize, IN_SIZE);
+            let echo = Reply::Response(EcResponseStatus::Success as u32, cmd.outsize as i32);
+            match self.replies.borrow_mut().pop_front().unwrap_or(echo) {
+                Reply::Errno(err) => Err(err),
+                Reply::Response(result, size) => {
+                    cmd.result = result;
+                    Ok(size)
+                }
+            }
+        }
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff_ms: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        let dev = MockXfer::new(&[Reply::Errno(Errno::EAGAIN), Reply::Errno(Errno::EINTR)]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[1, 2, 3]);
+        assert_eq!(res, Ok(vec![1, 2, 3]));
+        assert_eq!(dev.calls.get(), 3);
+
+        // Gives up eventually
+        let dev = MockXfer::new(&[Reply::Errno(Errno::EBUSY); 10]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[]);
+        assert!(matches!(res, Err(EcError::Io(_))));
+        assert_eq!(dev.calls.get(), 4);
+
+        // Other errors aren't retried
+        let dev = MockXfer::new(&[Reply::Errno(Errno::ENOTTY)]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[]);
+        assert!(matches!(res, Err(EcError::Io(_))));
+        assert_eq!(dev.calls.get(), 1);
+
+        // Neither are commands that must not be sent twice
+        let dev = MockXfer::new(&[Reply::Errno(Errno::EINTR)]);
+        let res = xfer_command(&dev, &policy(), EcCommands::FlashWrite as u16, 0, &[]);
+        assert!(matches!(res, Err(EcError::Io(_))));
+        assert_eq!(dev.calls.get(), 1);
+    }
+
+    #[test]
+    fn ec_errors_and_bad_sizes() {
+        let dev = MockXfer::new(&[Reply::Response(
+            EcResponseStatus::InvalidParameter as u32,
+            0,
+        )]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[]);
+        assert_eq!(
+            res,
+            Err(EcError::Response(EcResponseStatus::InvalidParameter))
+        );
+
+        let dev = MockXfer::new(&[Reply::Response(0xFF, 0)]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[]);
+        assert_eq!(res, Err(EcError::UnknownResponseCode(0xFF)));
+
+        // Driver claims to return more than fits into the buffer
+        let dev = MockXfer::new(&[Reply::Response(0, IN_SIZE as i32 + 1)]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[]);
+        assert!(matches!(res, Err(EcError::ProtocolMismatch(_))));
+
+        // Request too large, must not be truncated
+        let dev = MockXfer::new(&[]);
+        let res = xfer_command(&dev, &policy(), 0x01, 0, &[0; IN_SIZE + 1]);
+        assert!(matches!(res, Err(EcError::DeviceError(_))));
+        assert_eq!(dev.calls.get(), 0);
+    }
+
+    #[test]
+    fn fuzz_replies() {
+        // Simple xorshift, so the test is deterministic
+        let mut state: u32 = 0x1234_5678;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let errnos = [Errno::EAGAIN, Errno::EBUSY, Errno::EINTR, Errno::EIO];
+
+        for _ in 0..10_000 {
+            let data: Vec<u8> = (0..next() as usize % (IN_SIZE + 8))
+                .map(|_| next() as u8)
+                .collect();
+            let replies: Vec<Reply> = (0..next() % 6)
+                .map(|_| match next() % 3 {
+                    0 => Reply::Errno(errnos[next() as usize % errnos.len()]),
+                    1 => Reply::Response(next() % 20, next() as i32 % (2 * IN_SIZE as i32)),
+                    _ => Reply::Response(0, next() as i32 % (IN_SIZE as i32 + 1)),
+                })
+                .collect();
+            let dev = MockXfer::new(&replies);
 
-                Ok(result_data.to_vec())
+            match xfer_command(&dev, &policy(), next() as u16, 0, &data) {
+                Ok(response) => {
+                    assert!(response.len() <= IN_SIZE);
+                    assert!(data.len() <= IN_SIZE);
+                }
+                Err(EcError::DeviceError(_)) => assert!(data.len() > IN_SIZE),
+                Err(_) => {}
             }
-            Err(err) => Err(EcError::DeviceError(format!(
-                "ioctl to send command to EC failed with {:?}",
-                err
-            ))),
+            assert!(dev.calls.get() <= policy().max_retries as usize + 1);
         }
     }
 }
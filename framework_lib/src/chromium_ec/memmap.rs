@@ -0,0 +1,332 @@
+//! Typed view of the memory mapped region of the EC
+//!
+//! The EC keeps the most frequently needed values, like temperatures, fan
+//! speeds and the battery state, in a small region that the host can read
+//! without sending a command. Reading all of it at once gives a consistent
+//! snapshot and avoids a round trip for every single value.
+//!
+//! Each block has a version byte and newer versions add fields. Not all
+//! Framework ECs set the version of the blocks they fill in, so only the
+//! fields that were added later depend on it.
+
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use super::{EcError, EcResult, EC_FAN_SPEED_ENTRIES};
+
+/// Maximum length of strings in memmap
+const EC_MEMMAP_TEXT_MAX: usize = 8;
+
+// The offset address of each type of data in mapped memory.
+const EC_MEMMAP_TEMP_SENSOR: usize = 0x00; // Temp sensors 0x00 - 0x0f
+const EC_MEMMAP_FAN: usize = 0x10; // Fan speeds 0x10 - 0x17
+const EC_MEMMAP_TEMP_SENSOR_B: usize = 0x18; // More temp sensors 0x18 - 0x1f
+const EC_MEMMAP_ID: usize = 0x20; // 0x20 == 'E', 0x21 == 'C'
+const EC_MEMMAP_ID_VERSION: usize = 0x22; // Version of data in 0x20 - 0x2f
+const EC_MEMMAP_THERMAL_VERSION: usize = 0x23; // Version of data in 0x00 - 0x1f
+const EC_MEMMAP_BATTERY_VERSION: usize = 0x24; // Version of data in 0x40 - 0x7f
+const EC_MEMMAP_SWITCHES_VERSION: usize = 0x25; // Version of data in 0x30 - 0x33
+const EC_MEMMAP_EVENTS_VERSION: usize = 0x26; // Version of data in 0x34 - 0x3f
+const EC_MEMMAP_HOST_CMD_FLAGS: usize = 0x27; // Host cmd interface flags (8 bits)
+                                              // Unused 0x28 - 0x2f
+const EC_MEMMAP_SWITCHES: usize = 0x30; // 8 bits
+                                        // Unused 0x31 - 0x33
+const EC_MEMMAP_HOST_EVENTS: usize = 0x34; // 64 bits
+                                           // Battery values are all 32 bits, unless otherwise noted.
+const EC_MEMMAP_BATT_VOLT: usize = 0x40; // Battery Present Voltage
+const EC_MEMMAP_BATT_RATE: usize = 0x44; // Battery Present Rate
+const EC_MEMMAP_BATT_CAP: usize = 0x48; // Battery Remaining Capacity
+const EC_MEMMAP_BATT_FLAG: usize = 0x4c; // Battery State, see below (8-bit)
+const EC_MEMMAP_BATT_COUNT: usize = 0x4d; // Battery Count (8-bit)
+const EC_MEMMAP_BATT_INDEX: usize = 0x4e; // Current Battery Data Index (8-bit)
+                                          // Unused 0x4f
+const EC_MEMMAP_BATT_DCAP: usize = 0x50; // Battery Design Capacity
+const EC_MEMMAP_BATT_DVLT: usize = 0x54; // Battery Design Voltage
+const EC_MEMMAP_BATT_LFCC: usize = 0x58; // Battery Last Full Charge Capacity
+const EC_MEMMAP_BATT_CCNT: usize = 0x5c; // Battery Cycle Count
+                                         // Strings are all 8 bytes (EC_MEMMAP_TEXT_MAX)
+const EC_MEMMAP_BATT_MFGR: usize = 0x60; // Battery Manufacturer String
+const EC_MEMMAP_BATT_MODEL: usize = 0x68; // Battery Model Number String
+const EC_MEMMAP_BATT_SERIAL: usize = 0x70; // Battery Serial Number String
+const EC_MEMMAP_BATT_TYPE: usize = 0x78; // Battery Type String
+const EC_MEMMAP_ALS: usize = 0x80; // ALS readings in lux (2 X 16 bits)
+                                   // Unused 0x84 - 0x8f
+const EC_MEMMAP_ACC_STATUS: usize = 0x90; // Accelerometer status (8 bits )
+                                          // Unused 0x91
+const EC_MEMMAP_ACC_DATA: usize = 0x92; // Accelerometers data 0x92 - 0x9f
+                                        // 0x92: u16 Lid Angle if available, LID_ANGLE_UNRELIABLE otherwise
+                                        // 0x94 - 0x99: u16 1st Accelerometer
+                                        // 0x9a - 0x9f: u16 2nd Accelerometer
+const EC_MEMMAP_GYRO_DATA: usize = 0xa0; // Gyroscope data 0xa0 - 0xa5
+                                         // Unused 0xa6 - 0xdf
+
+/// Bytes that need to be read to get all values, the rest is unused
+pub const EC_MEMMAP_USED: u16 = 0xa6;
+
+/// Fan speed in memmap if the fan isn't present
+pub const EC_FAN_SPEED_NOT_PRESENT: u16 = 0xFFFF;
+/// Fan speed in memmap if the fan should be spinning but isn't
+pub const EC_FAN_SPEED_STALLED: u16 = 0xFFFE;
+
+// Switch bit flags at EC_MEMMAP_SWITCHES
+pub const EC_SWITCH_LID_OPEN: u8 = 0x01;
+
+// Battery bit flags at EC_MEMMAP_BATT_FLAG.
+pub const EC_BATT_FLAG_AC_PRESENT: u8 = 0x01;
+pub const EC_BATT_FLAG_BATT_PRESENT: u8 = 0x02;
+pub const EC_BATT_FLAG_DISCHARGING: u8 = 0x04;
+pub const EC_BATT_FLAG_CHARGING: u8 = 0x08;
+pub const EC_BATT_FLAG_LEVEL_CRITICAL: u8 = 0x10;
+
+/// Version of each block of the memory map
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemmapVersions {
+    pub id: u8,
+    pub thermal: u8,
+    pub battery: u8,
+    pub switches: u8,
+    pub events: u8,
+}
+
+/// Raw temperatures and fan speeds
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemmapThermal {
+    /// Temperature in Kelvin minus 200, or one of the special values
+    pub temps: [u8; 16],
+    /// More temperature sensors, only since thermal version 2
+    pub temps_b: Option<[u8; 8]>,
+    /// Speed in RPM, EC_FAN_SPEED_NOT_PRESENT or EC_FAN_SPEED_STALLED
+    pub fans: [u16; EC_FAN_SPEED_ENTRIES as usize],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemmapBattery {
+    /// EC_BATT_FLAG_* bits
+    pub flags: u8,
+    /// mV
+    pub present_voltage: u32,
+    /// mA
+    pub present_rate: u32,
+    /// mAh
+    pub remaining_capacity: u32,
+    pub battery_count: u8,
+    pub current_battery_index: u8,
+    /// mAh
+    pub design_capacity: u32,
+    /// mV
+    pub design_voltage: u32,
+    /// mAh
+    pub last_full_charge_capacity: u32,
+    pub cycle_count: u32,
+    pub manufacturer: String,
+    pub model_number: String,
+    pub serial_number: String,
+    pub battery_type: String,
+}
+
+impl MemmapBattery {
+    pub fn ac_present(&self) -> bool {
+        self.flags & EC_BATT_FLAG_AC_PRESENT != 0
+    }
+
+    pub fn battery_present(&self) -> bool {
+        self.flags & EC_BATT_FLAG_BATT_PRESENT != 0
+    }
+}
+
+/// Latest readings of the motion sensors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemmapMotion {
+    pub status: u8,
+    /// Degrees, or LID_ANGLE_UNRELIABLE
+    pub lid_angle: u16,
+    /// X, Y and Z of both accelerometers
+    pub accel: [[i16; 3]; 2],
+    pub gyro: [i16; 3],
+}
+
+/// All values in the memory mapped region of the EC
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EcMemmap {
+    pub versions: MemmapVersions,
+    pub host_cmd_flags: u8,
+    pub thermal: MemmapThermal,
+    /// EC_SWITCH_* bits
+    pub switches: u8,
+    /// Pending host events
+    pub host_events: u64,
+    pub battery: MemmapBattery,
+    /// Readings of up to two ambient light sensors, in lux
+    pub als: [u16; 2],
+    pub motion: MemmapMotion,
+}
+
+fn read_u16(mem: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([mem[offset], mem[offset + 1]])
+}
+
+fn read_i16(mem: &[u8], offset: usize) -> i16 {
+    read_u16(mem, offset) as i16
+}
+
+fn read_u32(mem: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        mem[offset],
+        mem[offset + 1],
+        mem[offset + 2],
+        mem[offset + 3],
+    ])
+}
+
+fn read_string(mem: &[u8], offset: usize) -> String {
+    let bytes = &mem[offset..offset + EC_MEMMAP_TEXT_MAX];
+    String::from_utf8_lossy(bytes).replace(['\0'], "")
+}
+
+fn read_vec3(mem: &[u8], offset: usize) -> [i16; 3] {
+    [
+        read_i16(mem, offset),
+        read_i16(mem, offset + 2),
+        read_i16(mem, offset + 4),
+    ]
+}
+
+impl EcMemmap {
+    /// Decode the memory map, starting at offset 0
+    pub fn parse(mem: &[u8]) -> EcResult<Self> {
+        if mem.len() < EC_MEMMAP_USED as usize {
+            return Err(EcError::DeviceError(format!(
+                "Memory map too short: {:#X} bytes",
+                mem.len()
+            )));
+        }
+        if &mem[EC_MEMMAP_ID..EC_MEMMAP_ID + 2] != b"EC" {
+            return Err(EcError::DeviceError(
+                "Memory map doesn't start with 'EC'".into(),
+            ));
+        }
+
+        let versions = MemmapVersions {
+            id: mem[EC_MEMMAP_ID_VERSION],
+            thermal: mem[EC_MEMMAP_THERMAL_VERSION],
+            battery: mem[EC_MEMMAP_BATTERY_VERSION],
+            switches: mem[EC_MEMMAP_SWITCHES_VERSION],
+            events: mem[EC_MEMMAP_EVENTS_VERSION],
+        };
+
+        let mut thermal = MemmapThermal::default();
+        thermal
+            .temps
+            .copy_from_slice(&mem[EC_MEMMAP_TEMP_SENSOR..EC_MEMMAP_TEMP_SENSOR + 16]);
+        if versions.thermal >= 2 {
+            let mut temps_b = [0; 8];
+            temps_b.copy_from_slice(&mem[EC_MEMMAP_TEMP_SENSOR_B..EC_MEMMAP_TEMP_SENSOR_B + 8]);
+            thermal.temps_b = Some(temps_b);
+        }
+        for (i, fan) in thermal.fans.iter_mut().enumerate() {
+            *fan = read_u16(mem, EC_MEMMAP_FAN + 2 * i);
+        }
+
+        let battery = MemmapBattery {
+            flags: mem[EC_MEMMAP_BATT_FLAG],
+            present_voltage: read_u32(mem, EC_MEMMAP_BATT_VOLT),
+            present_rate: read_u32(mem, EC_MEMMAP_BATT_RATE),
+            remaining_capacity: read_u32(mem, EC_MEMMAP_BATT_CAP),
+            battery_count: mem[EC_MEMMAP_BATT_COUNT],
+            current_battery_index: mem[EC_MEMMAP_BATT_INDEX],
+            design_capacity: read_u32(mem, EC_MEMMAP_BATT_DCAP),
+            design_voltage: read_u32(mem, EC_MEMMAP_BATT_DVLT),
+            last_full_charge_capacity: read_u32(mem, EC_MEMMAP_BATT_LFCC),
+            cycle_count: read_u32(mem, EC_MEMMAP_BATT_CCNT),
+            manufacturer: read_string(mem, EC_MEMMAP_BATT_MFGR),
+            model_number: read_string(mem, EC_MEMMAP_BATT_MODEL),
+            serial_number: read_string(mem, EC_MEMMAP_BATT_SERIAL),
+            battery_type: read_string(mem, EC_MEMMAP_BATT_TYPE),
+        };
+
+        Ok(EcMemmap {
+            versions,
+            host_cmd_flags: mem[EC_MEMMAP_HOST_CMD_FLAGS],
+            thermal,
+            switches: mem[EC_MEMMAP_SWITCHES],
+            host_events: read_u32(mem, EC_MEMMAP_HOST_EVENTS) as u64
+                | (read_u32(mem, EC_MEMMAP_HOST_EVENTS + 4) as u64) << 32,
+            battery,
+            als: [
+                read_u16(mem, EC_MEMMAP_ALS),
+                read_u16(mem, EC_MEMMAP_ALS + 2),
+            ],
+            motion: MemmapMotion {
+                status: mem[EC_MEMMAP_ACC_STATUS],
+                lid_angle: read_u16(mem, EC_MEMMAP_ACC_DATA),
+                accel: [
+                    read_vec3(mem, EC_MEMMAP_ACC_DATA + 2),
+                    read_vec3(mem, EC_MEMMAP_ACC_DATA + 8),
+                ],
+                gyro: read_vec3(mem, EC_MEMMAP_GYRO_DATA),
+            },
+        })
+    }
+
+    pub fn lid_open(&self) -> bool {
+        self.switches & EC_SWITCH_LID_OPEN != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> [u8; EC_MEMMAP_USED as usize] {
+        let mut mem = [0; EC_MEMMAP_USED as usize];
+        mem[EC_MEMMAP_ID..EC_MEMMAP_ID + 2].copy_from_slice(b"EC");
+        mem[EC_MEMMAP_THERMAL_VERSION] = 1;
+        mem[EC_MEMMAP_BATTERY_VERSION] = 1;
+        mem[EC_MEMMAP_SWITCHES_VERSION] = 1;
+        mem[EC_MEMMAP_TEMP_SENSOR] = 0x66;
+        mem[EC_MEMMAP_FAN..EC_MEMMAP_FAN + 4].copy_from_slice(&[0xD0, 0x07, 0xFF, 0xFF]);
+        mem[EC_MEMMAP_SWITCHES] = EC_SWITCH_LID_OPEN;
+        mem[EC_MEMMAP_BATT_FLAG] = EC_BATT_FLAG_AC_PRESENT | EC_BATT_FLAG_BATT_PRESENT;
+        mem[EC_MEMMAP_BATT_VOLT..EC_MEMMAP_BATT_VOLT + 4].copy_from_slice(&17_200u32.to_le_bytes());
+        mem[EC_MEMMAP_BATT_MFGR..EC_MEMMAP_BATT_MFGR + 4].copy_from_slice(b"NVT\0");
+        mem[EC_MEMMAP_ALS..EC_MEMMAP_ALS + 2].copy_from_slice(&120u16.to_le_bytes());
+        mem[EC_MEMMAP_ACC_DATA + 2..EC_MEMMAP_ACC_DATA + 4].copy_from_slice(&(-5i16).to_le_bytes());
+        mem
+    }
+
+    #[test]
+    fn parse_memmap() {
+        let memmap = EcMemmap::parse(&example()).unwrap();
+        assert_eq!(memmap.thermal.temps[0], 0x66);
+        assert_eq!(memmap.thermal.temps_b, None);
+        assert_eq!(memmap.thermal.fans, [2000, EC_FAN_SPEED_NOT_PRESENT, 0, 0]);
+        assert!(memmap.lid_open());
+        let battery = memmap.battery;
+        assert!(battery.ac_present());
+        assert!(battery.battery_present());
+        assert_eq!(battery.present_voltage, 17_200);
+        assert_eq!(battery.manufacturer, "NVT");
+        assert_eq!(memmap.als, [120, 0]);
+        assert_eq!(memmap.motion.accel[0], [-5, 0, 0]);
+    }
+
+    #[test]
+    fn versioned_blocks() {
+        let mut mem = example();
+        mem[EC_MEMMAP_THERMAL_VERSION] = 2;
+        mem[EC_MEMMAP_TEMP_SENSOR_B] = 0x70;
+        let memmap = EcMemmap::parse(&mem).unwrap();
+        assert_eq!(memmap.versions.thermal, 2);
+        assert_eq!(memmap.thermal.temps_b, Some([0x70, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert!(EcMemmap::parse(&[0; 0x20]).is_err());
+        let mut mem = example();
+        mem[EC_MEMMAP_ID] = 0;
+        assert!(EcMemmap::parse(&mem).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//! Events that the EC sends on its own, through the Matrix Keyboard Protocol
+//!
+//! Despite the name, MKBP isn't only used for keyboards. The EC queues events
+//! for buttons, switches, motion sensors and more, and signals the host to
+//! fetch them with `EcRequestGetNextEvent`. See `CrosEc::next_event`.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+use num_traits::FromPrimitive;
+
+use super::commands::{MkbpEventType, EC_MKBP_HAS_MORE_EVENTS};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MkbpEvent {
+    /// State of the keyboard matrix, one bit per key
+    KeyMatrix(Vec<u8>),
+    /// Host events that happened, see `HostEventCode::mask`
+    HostEvent(u64),
+    /// The motion sensor FIFO has new data
+    SensorFifo,
+    /// Buttons that are pressed, see EC_MKBP_POWER_BUTTON
+    Button(u32),
+    /// State of the switches, see EC_MKBP_LID_OPEN and EC_MKBP_TABLET_MODE
+    Switch(u32),
+    Fingerprint(u32),
+    Sysrq(u32),
+    /// Event type that we don't know, with its raw data
+    Other(u8, Vec<u8>),
+}
+
+impl MkbpEvent {
+    /// Parse the response of `EcRequestGetNextEvent`, the event type followed by its data
+    pub fn parse(data: &[u8]) -> Option<MkbpEvent> {
+        let (&event_type, data) = data.split_first()?;
+        let event_type = event_type & !EC_MKBP_HAS_MORE_EVENTS;
+        let u32_data = || Some(u32::from_le_bytes(data.get(..4)?.try_into().ok()?));
+        let event = match MkbpEventType::from_u8(event_type) {
+            Some(MkbpEventType::KeyMatrix) => MkbpEvent::KeyMatrix(data.to_vec()),
+            Some(MkbpEventType::HostEvent) => MkbpEvent::HostEvent(u32_data()? as u64),
+            Some(MkbpEventType::HostEvent64) => {
+                MkbpEvent::HostEvent(u64::from_le_bytes(data.get(..8)?.try_into().ok()?))
+            }
+            Some(MkbpEventType::SensorFifo) => MkbpEvent::SensorFifo,
+            Some(MkbpEventType::Button) => MkbpEvent::Button(u32_data()?),
+            Some(MkbpEventType::Switch) => MkbpEvent::Switch(u32_data()?),
+            Some(MkbpEventType::Fingerprint) => MkbpEvent::Fingerprint(u32_data()?),
+            Some(MkbpEventType::Sysrq) => MkbpEvent::Sysrq(u32_data()?),
+            None => MkbpEvent::Other(event_type, data.to_vec()),
+        };
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chromium_ec::commands::{EC_MKBP_LID_OPEN, EC_MKBP_POWER_BUTTON};
+
+    #[test]
+    fn parse_events() {
+        assert_eq!(
+            MkbpEvent::parse(&[4, 0x01, 0, 0, 0]),
+            Some(MkbpEvent::Switch(EC_MKBP_LID_OPEN))
+        );
+        // Has more events bit is ignored
+        assert_eq!(
+            MkbpEvent::parse(&[0x83, 0x01, 0, 0, 0]),
+            Some(MkbpEvent::Button(EC_MKBP_POWER_BUTTON))
+        );
+        assert_eq!(
+            MkbpEvent::parse(&[7, 0x08, 0, 0, 0, 0, 0, 0, 0x01]),
+            Some(MkbpEvent::HostEvent(0x0100_0000_0000_0008))
+        );
+        assert_eq!(MkbpEvent::parse(&[2]), Some(MkbpEvent::SensorFifo));
+        assert_eq!(
+            MkbpEvent::parse(&[42, 1, 2]),
+            Some(MkbpEvent::Other(42, vec![1, 2]))
+        );
+        // Too short for the event type
+        assert_eq!(MkbpEvent::parse(&[4, 0x01]), None);
+        assert_eq!(MkbpEvent::parse(&[]), None);
+    }
+}
@@ -7,9 +7,13 @@
 //! - `cros_ec` - It uses the `cros_ec` kernel module in Linux
 //! - `portio` - It uses raw port I/O. This works on UEFI and on Linux if the system isn't in lockdown mode (SecureBoot disabled).
 //! - `windows` - It uses [DHowett's Windows driver](https://github.com/DHowett/FrameworkWindowsUtils)
+//!   or the newer open-source CrosEC driver, whichever is installed
+//!
+//! `CrosEc` can be shared between threads, commands are serialized internally.
 
 use crate::ec_binary;
 use crate::os_specific;
+use crate::output::{outln, OutputSink, StdoutSink};
 use crate::smbios;
 #[cfg(feature = "uefi")]
 use crate::uefi::shell_get_execution_break_flag;
@@ -23,25 +27,42 @@ pub mod commands;
 #[cfg(feature = "cros_ec_driver")]
 mod cros_ec;
 pub mod input_deck;
+pub mod memmap;
+pub mod mkbp;
 mod portio;
 mod portio_mec;
+#[cfg(not(feature = "uefi"))]
+pub mod trace;
 #[cfg(feature = "win_driver")]
 mod windows;
 
+use alloc::boxed::Box;
 use alloc::format;
 use alloc::string::String;
 use alloc::string::ToString;
+use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt;
 #[cfg(feature = "uefi")]
 use core::prelude::rust_2021::derive;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use num_traits::FromPrimitive;
+#[cfg(feature = "uefi")]
+use spin::{Mutex, MutexGuard};
+#[cfg(not(feature = "uefi"))]
+use std::sync::{Mutex, MutexGuard};
+#[cfg(not(feature = "uefi"))]
+use std::time::{Duration, Instant};
 
 pub use command::EcRequestRaw;
 use commands::*;
 
-use self::command::EcCommands;
+use self::command::{retry_policy, with_retry_policy, EcCommands, RetryPolicy};
 use self::input_deck::InputDeckStatus;
+use self::memmap::{EcMemmap, EC_MEMMAP_USED};
+#[cfg(not(feature = "uefi"))]
+use self::trace::TraceKind;
 
 // 512K
 pub const EC_FLASH_SIZE: usize = 512 * 1024;
@@ -54,13 +75,157 @@ const EC_MEMMAP_SIZE: u16 = 0xFF;
 const EC_MEMMAP_ID: u16 = 0x20;
 
 const FLASH_BASE: u32 = 0x0; // 0x80000
-const FLASH_RO_BASE: u32 = 0x0;
-const FLASH_RO_SIZE: u32 = 0x3C000;
-const FLASH_RW_BASE: u32 = 0x40000;
-const FLASH_RW_SIZE: u32 = 0x39000;
-const MEC_FLASH_FLAGS: u32 = 0x80000;
-const NPC_FLASH_FLAGS: u32 = 0x7F000;
-const FLASH_PROGRAM_OFFSET: u32 = 0x1000;
+
+/// Magic bytes at the beginning of the program code on MEC ECs.
+/// 'MCHP' (Microchip) in ASCII backwards
+const MCHP_MAGIC: [u8; 4] = [0x50, 0x48, 0x43, 0x4D];
+const MCHP_PROGRAM_OFFSET: u32 = 0x1000;
+
+/// Maximum number of fans that the EC supports
+pub const EC_FAN_SPEED_ENTRIES: u8 = 4;
+
+/// How often `next_event` asks the EC for events, if the driver doesn't deliver them
+pub const NEXT_EVENT_POLL_MS: u64 = 200;
+
+/// Fans that were switched to manual control by this process, one bit per fan
+///
+/// The EC has no command to read back whether a fan is controlled manually,
+/// so this only tracks `fan_set_duty` and `autofanctrl` calls of this process.
+/// Fans set by another process or an earlier run still count as automatic.
+static MANUAL_FANS: AtomicU8 = AtomicU8::new(0);
+
+/// Only one host command or memory map read can be in flight at a time
+///
+/// A host command is a sequence of port accesses or ioctls and the drivers keep
+/// global state. All `CrosEc` instances talk to the same EC, so they share the lock.
+static EC_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_ec() -> MutexGuard<'static, ()> {
+    #[cfg(feature = "uefi")]
+    return EC_LOCK.lock();
+    // Every command starts the protocol from scratch, so it's fine to continue
+    // after another thread panicked in the middle of one
+    #[cfg(not(feature = "uefi"))]
+    return EC_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+}
+
+/// Which chip the EC runs on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EcChip {
+    /// Microchip MEC, running the legacy EC firmware
+    Mec,
+    /// Nuvoton NPC, running the Zephyr based EC firmware
+    Npc,
+}
+
+impl EcChip {
+    /// Identify the chip from the strings returned by EC_CMD_GET_CHIP_INFO
+    pub fn from_chip_info(vendor: &str, name: &str) -> Option<Self> {
+        let vendor = vendor.to_lowercase();
+        let name = name.to_lowercase();
+        if vendor.contains("microchip") || name.starts_with("mec") {
+            Some(Self::Mec)
+        } else if vendor.contains("nuvoton") || name.starts_with("npc") {
+            Some(Self::Npc)
+        } else {
+            None
+        }
+    }
+}
+
+/// Location of the regions in EC flash
+///
+/// Offsets are relative to the start of the EC flash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EcFlashLayout {
+    pub chip: EcChip,
+    pub ro_base: u32,
+    pub ro_size: u32,
+    pub rw_base: u32,
+    pub rw_size: u32,
+    /// Where the EC keeps its persistent flags
+    pub flags_base: u32,
+    pub flags_size: u32,
+    /// Where the program code starts, if it can be identified by `MCHP_MAGIC`
+    pub program_offset: Option<u32>,
+}
+
+/// MEC/Legacy EC
+///
+/// | Start | End   | Size  | Region      |
+/// | 00000 | 3BFFF | 3C000 | RO Region   |
+/// | 3C000 | 3FFFF | 04000 | Preserved   |
+/// | 40000 | 78FFF | 39000 | RW Region   |
+/// | 79000 | 79FFF | 01000 | Preserved   |
+/// | 80000 | 80FFF | 01000 | Flash Flags |
+pub const MEC_FLASH_LAYOUT: EcFlashLayout = EcFlashLayout {
+    chip: EcChip::Mec,
+    ro_base: 0x0,
+    ro_size: 0x3C000,
+    rw_base: 0x40000,
+    rw_size: 0x39000,
+    flags_base: 0x80000,
+    flags_size: 0x1000,
+    program_offset: Some(MCHP_PROGRAM_OFFSET),
+};
+
+/// NPC/Zephyr
+///
+/// | Start | End   | Size  | Region      |
+/// | 00000 | 3BFFF | 3C000 | RO Region   |
+/// | 3C000 | 3FFFF | 04000 | Preserved   |
+/// | 40000 | 78FFF | 39000 | RW Region   |
+/// | 7F000 | 7FFFF | 01000 | Flash Flags |
+pub const NPC_FLASH_LAYOUT: EcFlashLayout = EcFlashLayout {
+    chip: EcChip::Npc,
+    ro_base: 0x0,
+    ro_size: 0x3C000,
+    rw_base: 0x40000,
+    rw_size: 0x39000,
+    flags_base: 0x7F000,
+    flags_size: 0x1000,
+    program_offset: None,
+};
+
+impl EcFlashLayout {
+    pub fn for_chip(chip: EcChip) -> Self {
+        match chip {
+            EcChip::Mec => MEC_FLASH_LAYOUT,
+            EcChip::Npc => NPC_FLASH_LAYOUT,
+        }
+    }
+
+    /// Offset and size of the region
+    pub fn region(&self, region: EcFlashRegion) -> (u32, u32) {
+        match region {
+            EcFlashRegion::Ro => (self.ro_base, self.ro_size),
+            EcFlashRegion::Rw => (self.rw_base, self.rw_size),
+            EcFlashRegion::Flags => (self.flags_base, self.flags_size),
+        }
+    }
+}
+
+/// Region of the EC flash, that can be erased on its own
+#[cfg_attr(feature = "commandline", derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EcFlashRegion {
+    Ro,
+    Rw,
+    Flags,
+}
+
+impl EcFlashRegion {
+    pub const ALL: &'static [EcFlashRegion] =
+        &[EcFlashRegion::Ro, EcFlashRegion::Rw, EcFlashRegion::Flags];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EcFlashRegion::Ro => "RO",
+            EcFlashRegion::Rw => "RW",
+            EcFlashRegion::Flags => "Flags",
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum EcFlashType {
@@ -82,10 +247,145 @@ pub type EcResult<T> = Result<T, EcError>;
 
 #[derive(Debug, PartialEq)]
 pub enum EcError {
+    /// EC returned an error status for the command
     Response(EcResponseStatus),
     UnknownResponseCode(u32),
     // Failed to communicate with the EC
     DeviceError(String),
+    /// Not allowed to access the EC, usually have to be root/administrator
+    Permission(String),
+    /// Selected driver is not available on this system, e.g. driver not installed
+    DriverUnavailable(String),
+    /// Error reported by the OS while talking to the driver
+    Io(String),
+    /// EC responded with data that doesn't match what the protocol expects
+    ProtocolMismatch(String),
+    /// Gave up waiting for the EC or a device behind it
+    Timeout(String),
+    /// Operation was stopped through its `CancelToken`
+    Cancelled,
+    /// Data read back after writing doesn't match what was written
+    VerifyFailed(String),
+    /// The system doesn't have the hardware for it, e.g. no battery to charge
+    Unsupported(String),
+    /// Error with a description of what was being done when it happened
+    Context {
+        context: String,
+        source: Box<EcError>,
+    },
+}
+
+impl fmt::Display for EcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EcError::Response(status) => write!(f, "EC responded with {:?}", status),
+            EcError::UnknownResponseCode(code) => {
+                write!(f, "Invalid response code from EC: {:X}", code)
+            }
+            EcError::DeviceError(msg) => write!(f, "Failed to communicate with EC: {}", msg),
+            EcError::Permission(msg) => write!(f, "Permission denied: {}", msg),
+            EcError::DriverUnavailable(msg) => write!(f, "Driver unavailable: {}", msg),
+            EcError::Io(msg) => write!(f, "I/O error: {}", msg),
+            EcError::ProtocolMismatch(msg) => write!(f, "Protocol mismatch: {}", msg),
+            EcError::Timeout(msg) => write!(f, "Timeout: {}", msg),
+            EcError::Cancelled => write!(f, "Cancelled"),
+            EcError::VerifyFailed(msg) => write!(f, "Verification failed: {}", msg),
+            EcError::Unsupported(msg) => write!(f, "Not supported: {}", msg),
+            EcError::Context { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+#[cfg(not(feature = "uefi"))]
+impl std::error::Error for EcError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EcError::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl EcError {
+    /// The innermost error, without any context
+    pub fn root_cause(&self) -> &EcError {
+        match self {
+            EcError::Context { source, .. } => source.root_cause(),
+            _ => self,
+        }
+    }
+}
+
+/// Add context to an error, describing what was being done
+pub trait EcResultExt<T> {
+    fn context<C: Into<String>>(self, context: C) -> EcResult<T>;
+}
+
+impl<T> EcResultExt<T> for EcResult<T> {
+    fn context<C: Into<String>>(self, context: C) -> EcResult<T> {
+        self.map_err(|err| EcError::Context {
+            context: context.into(),
+            source: Box::new(err),
+        })
+    }
+}
+
+/// Lets another thread stop a long running operation, like flashing
+///
+/// Clones share the same flag, so one can be kept to cancel the operation
+/// that the other was passed to.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    /// Flag of the token this is a child of
+    parent: Option<Arc<AtomicBool>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token that is cancelled together with this one, but can also be
+    /// cancelled on its own without affecting this one
+    pub fn child(&self) -> Self {
+        CancelToken {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            parent: Some(self.cancelled.clone()),
+        }
+    }
+
+    /// Whether any children of this token are still around
+    pub fn has_children(&self) -> bool {
+        // Only children and clones hold a reference to the flag
+        Arc::strong_count(&self.cancelled) > 1
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Undo the cancellation, to reuse the token for another operation
+    pub fn reset(&self) {
+        self.cancelled.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+            || self
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.load(Ordering::SeqCst))
+    }
+
+    /// Fail with `EcError::Cancelled`, if cancelled
+    pub fn check(&self) -> EcResult<()> {
+        if self.is_cancelled() {
+            Err(EcError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
 }
 
 /// Response codes returned by commands
@@ -126,8 +426,11 @@ pub fn has_mec() -> bool {
     }
 
     !matches!(
-        smbios::get_platform().unwrap(),
-        Platform::Framework13Amd | Platform::Framework16 | Platform::IntelCoreUltra1
+        platform,
+        Platform::Framework13Amd
+            | Platform::Framework16
+            | Platform::DesktopAmdAi300
+            | Platform::IntelCoreUltra1
     )
 }
 
@@ -161,12 +464,96 @@ fn available_drivers() -> Vec<CrosEcDriverType> {
     ]
 }
 
+/// Result of trying to talk to the EC with a single driver
+#[derive(Debug)]
+pub struct EcDriverProbe {
+    pub driver: CrosEcDriverType,
+    pub result: EcResult<()>,
+}
+
+/// Collects the results of trying every available driver
+///
+/// Used to give a single, consolidated diagnostic when no driver can reach
+/// the EC, instead of failing in every command later on.
+#[derive(Debug)]
+pub struct EcDriverProbeReport {
+    pub probes: Vec<EcDriverProbe>,
+}
+
+impl EcDriverProbeReport {
+    /// First driver that could successfully communicate with the EC
+    pub fn working_driver(&self) -> Option<CrosEcDriverType> {
+        self.probes
+            .iter()
+            .find(|probe| probe.result.is_ok())
+            .map(|probe| probe.driver)
+    }
+
+    pub fn print(&self) {
+        if self.working_driver().is_some() {
+            return;
+        }
+        error!("EC unavailable. Tried the following drivers:");
+        for probe in &self.probes {
+            if let Err(err) = &probe.result {
+                error!("  {:?}: {:?}", probe.driver, err);
+            }
+        }
+    }
+}
+
+/// Driver that the last probe found working, so that it isn't probed again
+static PROBED_DRIVER: Mutex<Option<CrosEcDriverType>> = Mutex::new(None);
+
+fn probed_driver() -> MutexGuard<'static, Option<CrosEcDriverType>> {
+    #[cfg(feature = "uefi")]
+    return PROBED_DRIVER.lock();
+    #[cfg(not(feature = "uefi"))]
+    return PROBED_DRIVER.lock().unwrap_or_else(|err| err.into_inner());
+}
+
+/// Check the available drivers, in order of preference, whether they can talk to the EC
+///
+/// Stops at the first one that works. The report has all drivers that were tried.
+pub fn probe_drivers() -> EcDriverProbeReport {
+    let mut probes = vec![];
+    for driver in available_drivers() {
+        let result = CrosEc { driver }.probe_mem_magic();
+        debug!("Probing EC driver {:?}: {:?}", driver, result);
+        let works = result.is_ok();
+        probes.push(EcDriverProbe { driver, result });
+        if works {
+            *probed_driver() = Some(driver);
+            break;
+        }
+    }
+    EcDriverProbeReport { probes }
+}
+
 impl CrosEc {
+    /// Use the first driver that can talk to the EC
+    ///
+    /// Drivers are only probed until one is found working, later calls reuse it.
+    /// If none can, falls back to the default driver anyway, so that commands
+    /// report their own errors. Use `CrosEc::probe` to find out why.
     pub fn new() -> CrosEc {
-        debug!("Chromium EC Driver: {:?}", available_drivers()[0]);
-        CrosEc {
-            driver: available_drivers()[0],
+        let cached = *probed_driver();
+        if let Some(driver) = cached {
+            return CrosEc { driver };
         }
+        Self::probe().0
+    }
+
+    /// Try all available drivers and use the first one that can talk to the EC
+    ///
+    /// Also returns the report of all drivers that were tried.
+    pub fn probe() -> (CrosEc, EcDriverProbeReport) {
+        let report = probe_drivers();
+        let driver = report
+            .working_driver()
+            .unwrap_or_else(|| available_drivers()[0]);
+        debug!("Chromium EC Driver: {:?}", driver);
+        (CrosEc { driver }, report)
     }
 
     pub fn with(driver: CrosEcDriverType) -> Option<CrosEc> {
@@ -177,6 +564,106 @@ impl CrosEc {
         Some(CrosEc { driver })
     }
 
+    /// Answer requests from a trace recorded with `--trace-ec`, instead of the EC
+    #[cfg(not(feature = "uefi"))]
+    pub fn replay(path: &str) -> std::io::Result<CrosEc> {
+        trace::load_replay(path)?;
+        Ok(CrosEc {
+            driver: CrosEcDriverType::Replay,
+        })
+    }
+
+    /// Which driver is used to communicate with the EC
+    pub fn driver(&self) -> CrosEcDriverType {
+        self.driver
+    }
+
+    /// Replayed traces may have been recorded on a different system
+    fn is_framework(&self) -> bool {
+        self.driver == CrosEcDriverType::Replay || smbios::is_framework()
+    }
+
+    /// Version of the OS driver, if it reports one
+    pub fn driver_version(&self) -> Option<String> {
+        match self.driver {
+            #[cfg(feature = "cros_ec_driver")]
+            CrosEcDriverType::CrosEc => cros_ec::driver_version().ok(),
+            _ => None,
+        }
+    }
+
+    /// Send the hello command and make sure the EC echoes back the expected value
+    pub fn hello(&self) -> EcResult<()> {
+        let in_data = 0xA0B0C0D0;
+        let res = EcRequestHello { in_data }.send_command(self)?;
+        let out_data = res.out_data;
+        if out_data != in_data.wrapping_add(0x01020304) {
+            return Err(EcError::DeviceError(format!(
+                "Unexpected hello response: {:#X}",
+                out_data
+            )));
+        }
+        Ok(())
+    }
+
+    /// Get the highest host command protocol version that the EC supports
+    pub fn protocol_version(&self) -> EcResult<u8> {
+        let info = EcRequestGetProtocolInfo {}.send_command(self)?;
+        let versions = info.protocol_versions;
+        if versions == 0 {
+            return Err(EcError::DeviceError(
+                "EC reported no supported protocol versions".to_string(),
+            ));
+        }
+        Ok((31 - versions.leading_zeros()) as u8)
+    }
+
+    /// Measure round-trip latency of host commands with the current driver
+    ///
+    /// Sends `iterations` hello commands and records how long each took.
+    /// Failed commands are counted but not included in the timing statistics.
+    #[cfg(not(feature = "uefi"))]
+    pub fn benchmark(&self, iterations: usize) -> EcResult<EcBenchmark> {
+        let mut samples = Vec::with_capacity(iterations);
+        let mut failures = 0;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            let cmd_start = Instant::now();
+            match self.hello() {
+                Ok(()) => samples.push(cmd_start.elapsed()),
+                Err(err) => {
+                    debug!("Hello command failed: {:?}", err);
+                    failures += 1;
+                }
+            }
+        }
+        let total = start.elapsed();
+
+        if samples.is_empty() {
+            return Err(EcError::DeviceError(format!(
+                "All {} commands failed",
+                iterations
+            )));
+        }
+        samples.sort();
+
+        let sum: Duration = samples.iter().sum();
+        let p99_index = (samples.len() - 1) * 99 / 100;
+
+        Ok(EcBenchmark {
+            driver: self.driver,
+            protocol_version: self.protocol_version().ok(),
+            iterations,
+            failures,
+            min: samples[0],
+            avg: sum / samples.len() as u32,
+            p99: samples[p99_index],
+            max: samples[samples.len() - 1],
+            total,
+        })
+    }
+
     /// Lock bus to PD controller in the beginning of flashing
     /// TODO: Perhaps I could return a struct that will lock the bus again in its destructor
     pub fn lock_pd_bus(&self, lock: bool) -> EcResult<()> {
@@ -195,27 +682,77 @@ impl CrosEc {
     }
 
     pub fn check_mem_magic(&self) -> Option<()> {
-        match self.read_memory(EC_MEMMAP_ID, 2) {
-            Some(ec_id) => {
-                if ec_id.len() != 2 {
-                    error!("  Unexpected length returned: {:?}", ec_id.len());
-                    return None;
-                }
-                if ec_id[0] != b'E' || ec_id[1] != b'C' {
-                    error!("  This machine doesn't look like it has a Framework EC");
-                    None
-                } else {
-                    println!("  Verified that Framework EC is present!");
-                    Some(())
-                }
+        match self.probe_mem_magic() {
+            Ok(()) => {
+                println!("  Verified that Framework EC is present!");
+                Some(())
             }
-            None => {
-                error!("  Failed to read EC ID from memory map");
+            Err(err) => {
+                error!("  {:?}", err);
                 None
             }
         }
     }
 
+    /// Check for the 'EC' magic bytes in the memory map, without printing anything
+    fn probe_mem_magic(&self) -> EcResult<()> {
+        let ec_id = self.read_memory_result(EC_MEMMAP_ID, 2)?;
+        if ec_id[0] != b'E' || ec_id[1] != b'C' {
+            return Err(EcError::DeviceError(
+                "This machine doesn't look like it has a Framework EC".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Same as `read_memory` but returns the reason why reading failed
+    fn read_memory_result(&self, offset: u16, length: u16) -> EcResult<Vec<u8>> {
+        if !self.is_framework() {
+            return Err(EcError::DeviceError("Not a Framework Laptop".to_string()));
+        }
+
+        debug!("read_memory(offset={:#X}, size={:#X})", offset, length);
+        if offset + length > EC_MEMMAP_SIZE {
+            return Err(EcError::DeviceError(format!(
+                "Reading beyond end of memory map: {:#X}",
+                offset + length
+            )));
+        }
+
+        let _lock = lock_ec();
+        read_full("Memory map read", length.into(), || {
+            self.read_memory_once(offset, length)
+        })
+    }
+
+    /// Read from the driver once, it might return more or less than requested
+    fn read_memory_once(&self, offset: u16, length: u16) -> EcResult<Vec<u8>> {
+        #[cfg(not(feature = "uefi"))]
+        let start = Instant::now();
+        let res = match self.driver {
+            CrosEcDriverType::Portio => portio::read_memory(offset, length),
+            #[cfg(feature = "win_driver")]
+            CrosEcDriverType::Windows => windows::read_memory(offset, length),
+            #[cfg(feature = "cros_ec_driver")]
+            CrosEcDriverType::CrosEc => cros_ec::read_memory(offset, length),
+            #[cfg(not(feature = "uefi"))]
+            CrosEcDriverType::Replay => trace::replay(TraceKind::Memory, offset, length, &[]),
+            _ => Err(EcError::DriverUnavailable(
+                "No EC driver available".to_string(),
+            )),
+        };
+        #[cfg(not(feature = "uefi"))]
+        trace::record(
+            TraceKind::Memory,
+            offset,
+            length,
+            &[],
+            &res,
+            start.elapsed(),
+        );
+        res
+    }
+
     pub fn cmd_version_supported(&self, cmd: u16, version: u8) -> EcResult<bool> {
         let res = EcRequestGetCmdVersionsV1 { cmd: cmd.into() }.send_command(self);
         let mask = if let Ok(res) = res {
@@ -228,6 +765,12 @@ impl CrosEc {
         Ok(mask & (1 << version) > 0)
     }
 
+    /// Read and decode all values of the memory mapped region at once
+    pub fn memmap(&self) -> EcResult<EcMemmap> {
+        let mem = self.read_memory_result(0x00, EC_MEMMAP_USED)?;
+        EcMemmap::parse(&mem)
+    }
+
     pub fn dump_mem_region(&self) -> Option<Vec<u8>> {
         // Crashes on Linux cros_ec driver if we read the last byte
         self.read_memory(0x00, EC_MEMMAP_SIZE - 1)
@@ -280,18 +823,81 @@ impl CrosEc {
     }
 
     pub fn set_charge_limit(&self, min: u8, max: u8) -> EcResult<()> {
+        require_battery("charge limit")?;
         // Sending bytes manually because the Set command, as opposed to the Get command,
         // does not return any data
         let limits = &[ChargeLimitControlModes::Set as u8, max, min];
         let data = self.send_command(EcCommands::ChargeLimitControl as u16, 0, limits)?;
 
-        util::assert_win_len(data.len(), 0);
+        check_response_len(&data, 0)?;
+
+        Ok(())
+    }
+
+    /// Get the current state of the charger
+    pub fn get_charge_state(&self) -> EcResult<EcResponseChargeStateGetV0> {
+        EcRequestChargeStateGetV0 {
+            cmd: ChargeStateCmd::GetState as u8,
+            param: 0,
+        }
+        .send_command(self)
+    }
+
+    /// Read one of the charger's parameters
+    pub fn get_charge_state_param(&self, param: ChargeStateParam) -> EcResult<u32> {
+        require_battery("charger parameter")?;
+        let res = EcRequestChargeStateGetParamV0 {
+            cmd: ChargeStateCmd::GetParam as u8,
+            param: param as u32,
+        }
+        .send_command(self)?;
+        Ok(res.value)
+    }
+
+    /// Get one of the host event masks, see HostEventCode for the bits
+    pub fn get_host_event_mask(&self, mask_type: HostEventMaskType) -> EcResult<u64> {
+        let res = EcRequestHostEvent {
+            action: HostEventAction::Get as u8,
+            mask_type: mask_type as u8,
+            reserved: 0,
+            value: 0,
+        }
+        .send_command(self)?;
+        Ok(res.value)
+    }
+
+    /// Replace one of the host event masks
+    pub fn set_host_event_mask(&self, mask_type: HostEventMaskType, mask: u64) -> EcResult<()> {
+        // Set doesn't return any data
+        let data = EcRequestHostEvent {
+            action: HostEventAction::Set as u8,
+            mask_type: mask_type as u8,
+            reserved: 0,
+            value: mask,
+        }
+        .send_command_vec(self)?;
+        check_response_len(&data, 0)?;
+        Ok(())
+    }
+
+    /// Override one of the charger's parameters
+    pub fn set_charge_state_param(&self, param: ChargeStateParam, value: u32) -> EcResult<()> {
+        require_battery("charger parameter")?;
+        // Sending bytes manually because the Set command, as opposed to the Get command,
+        // does not return any data
+        let mut request = vec![ChargeStateCmd::SetParam as u8];
+        request.extend_from_slice(&(param as u32).to_le_bytes());
+        request.extend_from_slice(&value.to_le_bytes());
+        let data = self.send_command(EcCommands::ChargeState as u16, 0, &request)?;
+
+        check_response_len(&data, 0)?;
 
         Ok(())
     }
 
     /// Get charge limit in percent (min, max)
     pub fn get_charge_limit(&self) -> EcResult<(u8, u8)> {
+        require_battery("charge limit")?;
         let limits = EcRequestChargeLimitControl {
             modes: ChargeLimitControlModes::Get as u8,
             max_percentage: 0xFF,
@@ -307,13 +913,26 @@ impl CrosEc {
         Ok((limits.min_percentage, limits.max_percentage))
     }
 
+    /// Enable or disable PS/2 emulation of the keyboard and touchpad
+    ///
+    /// The BIOS sets this on every boot, according to its setup option.
+    pub fn set_ps2_emulation(&self, enable: bool) -> EcResult<()> {
+        // The command doesn't return any data and the current state can't be read
+        let disable = &[!enable as u8];
+        let data = self.send_command(EcCommands::DisablePs2Emulation as u16, 0, disable)?;
+
+        check_response_len(&data, 0)?;
+
+        Ok(())
+    }
+
     pub fn set_fp_led_level(&self, level: FpLedBrightnessLevel) -> EcResult<()> {
         // Sending bytes manually because the Set command, as opposed to the Get command,
         // does not return any data
         let limits = &[level as u8, 0x00];
         let data = self.send_command(EcCommands::FpLedLevelControl as u16, 0, limits)?;
 
-        util::assert_win_len(data.len(), 0);
+        check_response_len(&data, 0)?;
 
         Ok(())
     }
@@ -350,6 +969,28 @@ impl CrosEc {
         })
     }
 
+    /// Reset the intrusion status, whether the chassis was ever opened and how often
+    ///
+    /// The EC doesn't return the status when clearing, so it must be read again afterwards.
+    pub fn clear_intrusion_status(&self) -> EcResult<()> {
+        EcRequestChassisIntrusionControl {
+            clear_magic: CHASSIS_INTRUSION_CLEAR_MAGIC,
+            clear_chassis_status: 0,
+        }
+        .send_command_vec(self)?;
+        EcRequestChassisIntrusionControl {
+            clear_magic: 0,
+            clear_chassis_status: 1,
+        }
+        .send_command_vec(self)?;
+        Ok(())
+    }
+
+    /// Get the status of the expansion bay (Framework 16 only)
+    pub fn get_expansion_bay_status(&self) -> EcResult<EcResponseExpansionBayStatus> {
+        EcRequestExpansionBayStatus {}.send_command(self)
+    }
+
     pub fn get_input_deck_status(&self) -> EcResult<InputDeckStatus> {
         let status = EcRequestDeckState {
             mode: DeckStateMode::ReadOnly,
@@ -365,6 +1006,45 @@ impl CrosEc {
         Ok(InputDeckStatus::from(status))
     }
 
+    /// How many seconds the EC waits in G3 before hibernating
+    pub fn get_hibernation_delay(&self) -> EcResult<u32> {
+        let res = EcRequestHibernationDelay { seconds: 0 }.send_command(self)?;
+        Ok(res.hibernate_delay)
+    }
+
+    /// Change how long the EC waits in G3 before hibernating, at least 1s
+    pub fn set_hibernation_delay(&self, seconds: u32) -> EcResult<u32> {
+        debug_assert!(seconds > 0);
+        let res = EcRequestHibernationDelay { seconds }.send_command(self)?;
+        Ok(res.hibernate_delay)
+    }
+
+    /// Read the scancodes of keyboard matrix positions, or change them
+    ///
+    /// The scancodes of `entries` are only used if `write` is set. Returns the
+    /// entries with the scancodes that the EC sends after the command.
+    /// Only on keyboards that the EC scans, not the Framework 16 input modules.
+    pub fn update_keyboard_matrix(
+        &self,
+        entries: &[KeyboardMatrixMap],
+        write: bool,
+    ) -> EcResult<Vec<KeyboardMatrixMap>> {
+        let mut updated = Vec::with_capacity(entries.len());
+        for chunk in entries.chunks(KB_MATRIX_MAX_ITEMS) {
+            let mut scan_update = [KeyboardMatrixMap::default(); KB_MATRIX_MAX_ITEMS];
+            scan_update[..chunk.len()].copy_from_slice(chunk);
+            let res = EcRequestUpdateKeyboardMatrix {
+                num_items: chunk.len() as u32,
+                write: write as u32,
+                scan_update,
+            }
+            .send_command(self)?;
+            let scan_update = res.scan_update;
+            updated.extend_from_slice(&scan_update[..chunk.len()]);
+        }
+        Ok(updated)
+    }
+
     /// Change the keyboard baclight brightness
     ///
     /// # Arguments
@@ -392,25 +1072,181 @@ impl CrosEc {
         Ok((kblight.duty / (PWM_MAX_DUTY / 100)) as u8)
     }
 
+    /// Maximum brightness of each color of an LED, indexed by `EcLedColor`
+    ///
+    /// Colors that the LED doesn't have are 0.
+    pub fn get_led_brightness_range(&self, led: EcLedId) -> EcResult<[u8; EC_LED_COLOR_COUNT]> {
+        let res = EcRequestLedControl {
+            led_id: led as u8,
+            flags: EC_LED_FLAGS_QUERY,
+            brightness: [0; EC_LED_COLOR_COUNT],
+        }
+        .send_command(self)?;
+        Ok(res.brightness_range)
+    }
+
+    /// Take control of an LED and set the brightness of each color
+    ///
+    /// The LED stays like this until `set_led_auto` is called.
+    pub fn set_led_brightness(
+        &self,
+        led: EcLedId,
+        brightness: [u8; EC_LED_COLOR_COUNT],
+    ) -> EcResult<()> {
+        EcRequestLedControl {
+            led_id: led as u8,
+            flags: 0,
+            brightness,
+        }
+        .send_command(self)?;
+        Ok(())
+    }
+
+    /// Let the EC control the LED again, e.g. to show the charging state
+    pub fn set_led_auto(&self, led: EcLedId) -> EcResult<()> {
+        EcRequestLedControl {
+            led_id: led as u8,
+            flags: EC_LED_FLAGS_AUTO,
+            brightness: [0; EC_LED_COLOR_COUNT],
+        }
+        .send_command(self)?;
+        Ok(())
+    }
+
+    /// Set the duty cycle of a single fan or, if no index is given, all fans
+    ///
+    /// The fan stays at this duty cycle until automatic control is enabled again.
+    ///
+    /// # Arguments
+    /// * `fan_idx` - Index of the fan, only the Framework 16 has more than one
+    /// * `percent` - An integer from 0 to 100
+    pub fn fan_set_duty(&self, fan_idx: Option<u8>, percent: u32) -> EcResult<()> {
+        if percent > 100 {
+            return Err(EcError::DeviceError(
+                "Fan duty must be between 0% and 100%".to_string(),
+            ));
+        }
+        if let Some(fan_idx) = fan_idx {
+            if fan_idx >= EC_FAN_SPEED_ENTRIES {
+                return Err(EcError::DeviceError(format!(
+                    "Invalid fan index {}",
+                    fan_idx
+                )));
+            }
+            EcRequestPwmSetFanDutyV1 { percent, fan_idx }.send_command(self)?;
+            MANUAL_FANS.fetch_or(1 << fan_idx, Ordering::SeqCst);
+        } else {
+            EcRequestPwmSetFanDutyV0 { percent }.send_command(self)?;
+            MANUAL_FANS.store(0xFF, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Let the EC control a single fan or, if no index is given, all fans
+    pub fn autofanctrl(&self, fan_idx: Option<u8>) -> EcResult<()> {
+        if let Some(fan_idx) = fan_idx {
+            if fan_idx >= EC_FAN_SPEED_ENTRIES {
+                return Err(EcError::DeviceError(format!(
+                    "Invalid fan index {}",
+                    fan_idx
+                )));
+            }
+            EcRequestAutoFanCtrlV1 { fan_idx }.send_command(self)?;
+            MANUAL_FANS.fetch_and(!(1 << fan_idx), Ordering::SeqCst);
+        } else {
+            EcRequestAutoFanCtrlV0 {}.send_command(self)?;
+            MANUAL_FANS.store(0, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Current duty cycle of a fan in percent, whether set manually or by the EC
+    ///
+    /// On Framework boards the fans are the first generic PWM channels.
+    pub fn get_fan_duty(&self, fan_idx: u8) -> EcResult<u8> {
+        let res = EcRequestPwmGetDuty {
+            pwm_type: PwmType::Generic as u8,
+            index: fan_idx,
+        }
+        .send_command(self)?;
+        Ok((res.duty as u32 * 100 / PWM_MAX_DUTY as u32) as u8)
+    }
+
+    /// Whether the fan was switched to manual control with `fan_set_duty`
+    ///
+    /// Only knows about changes made by this process, like the daemon or the
+    /// GUI. A single `framework_tool --fansetduty` run doesn't affect the
+    /// result in a later run, see `MANUAL_FANS`.
+    pub fn is_fan_manual(&self, fan_idx: u8) -> bool {
+        fan_idx < EC_FAN_SPEED_ENTRIES && MANUAL_FANS.load(Ordering::SeqCst) & (1 << fan_idx) != 0
+    }
+
+    /// Get vendor and name of the EC chip
+    pub fn chip_info(&self) -> EcResult<(String, String)> {
+        let info = EcRequestGetChipInfo {}.send_command(self)?;
+        let decode = |bytes: &[u8]| {
+            std::str::from_utf8(bytes)
+                .map(|s| s.trim_end_matches(char::from(0)).to_string())
+                .map_err(|utf8_err| {
+                    EcError::DeviceError(format!("Failed to decode chip info: {:?}", utf8_err))
+                })
+        };
+        Ok((decode(&info.vendor)?, decode(&info.name)?))
+    }
+
+    /// Find out which chip the EC runs on
+    ///
+    /// Asks the EC first. Older firmware might not support that, then decide
+    /// based on the platform. Doesn't touch the flash, reading it would need
+    /// the EC to unlock SPI access with `flash_notify`.
+    pub fn detect_chip(&self) -> EcChip {
+        match self.chip_info() {
+            Ok((vendor, name)) => {
+                debug!("EC chip vendor: {:?}, name: {:?}", vendor, name);
+                if let Some(chip) = EcChip::from_chip_info(&vendor, &name) {
+                    return chip;
+                }
+            }
+            Err(err) => debug!("Failed to get EC chip info: {:?}", err),
+        }
+
+        if smbios::get_platform().is_some() && has_mec() {
+            EcChip::Mec
+        } else {
+            EcChip::Npc
+        }
+    }
+
+    /// Layout of the EC flash, depending on which chip the EC runs on
+    pub fn flash_layout(&self) -> EcFlashLayout {
+        let layout = EcFlashLayout::for_chip(self.detect_chip());
+        debug!("EC flash layout: {:X?}", layout);
+        layout
+    }
+
     /// Overwrite RO and RW regions of EC flash
-    /// MEC/Legacy EC
-    /// | Start | End   | Size  | Region      |
-    /// | 00000 | 3BFFF | 3C000 | RO Region   |
-    /// | 3C000 | 3FFFF | 04000 | Preserved   |
-    /// | 40000 | 3C000 | 39000 | RO Region   |
-    /// | 79000 | 79FFF | 01000 | Preserved   |
-    /// | 80000 | 80FFF | 01000 | Flash Flags |
     ///
-    /// NPC/Zephyr
-    /// | Start | End   | Size  | Region      |
-    /// | 00000 | 3BFFF | 3C000 | RO Region   |
-    /// | 3C000 | 3FFFF | 04000 | Preserved   |
-    /// | 40000 | 3C000 | 39000 | RO Region   |
-    /// | 79000 | 79FFF | 01000 | Flash Flags |
+    /// Where they are depends on the EC chip, see `MEC_FLASH_LAYOUT` and `NPC_FLASH_LAYOUT`.
     pub fn reflash(&self, data: &[u8], ft: EcFlashType) -> EcResult<()> {
+        self.reflash_with(data, ft, &mut StdoutSink, &CancelToken::new())
+    }
+
+    /// Like `reflash` but report progress to `out` and stop when `cancel` is triggered
+    ///
+    /// Cancelling before a region is erased leaves the flash untouched.
+    /// Once a region is being erased, it's written and verified completely,
+    /// an erased region would leave the EC unable to boot it.
+    /// The flash is locked again before returning `EcError::Cancelled`.
+    pub fn reflash_with(
+        &self,
+        data: &[u8],
+        ft: EcFlashType,
+        out: &mut dyn OutputSink,
+        cancel: &CancelToken,
+    ) -> EcResult<()> {
         if ft == EcFlashType::Full || ft == EcFlashType::Ro {
             if let Some(version) = ec_binary::read_ec_version(data, true) {
-                println!("EC RO Version in File: {:?}", version.version);
+                outln!(out, "EC RO Version in File: {:?}", version.version);
             } else {
                 return Err(EcError::DeviceError(
                     "File does not contain valid EC RO firmware".to_string(),
@@ -419,7 +1255,8 @@ impl CrosEc {
         }
         if ft == EcFlashType::Full || ft == EcFlashType::Rw {
             if let Some(version) = ec_binary::read_ec_version(data, false) {
-                println!("EC RW Version in File: {:?}", version.version);
+                outln!(out, "EC RW Version in File: {:?}", version.version);
+                self.check_rwsig(data, version.rollback_version, out)?;
             } else {
                 return Err(EcError::DeviceError(
                     "File does not contain valid EW RO firmware".to_string(),
@@ -428,99 +1265,205 @@ impl CrosEc {
         }
 
         if ft == EcFlashType::Full || ft == EcFlashType::Ro {
-            println!("For safety reasons flashing RO firmware is disabled.");
+            outln!(out, "For safety reasons flashing RO firmware is disabled.");
             return Ok(());
         }
 
-        println!("Unlocking flash");
-        self.flash_notify(MecFlashNotify::AccessSpi)?;
-        self.flash_notify(MecFlashNotify::FirmwareStart)?;
+        let layout = self.flash_layout();
+        outln!(out, "EC chip: {:?}", layout.chip);
+        let end = std::cmp::max(
+            layout.ro_base + layout.ro_size,
+            layout.rw_base + layout.rw_size,
+        );
+        if data.len() < end as usize {
+            return Err(EcError::DeviceError(format!(
+                "File is too small for the {:?} flash layout: {} bytes, need {}",
+                layout.chip,
+                data.len(),
+                end
+            )));
+        }
+        cancel.check()?;
+
+        self.with_flash_unlocked(out, |out| {
+            self.reflash_regions(data, ft, &layout, out, cancel)
+        })?;
+
+        outln!(out, "Flashing EC done. You can reboot the EC now");
+        // TODO: Should we force a reboot if currently running one was reflashed?
+
+        Ok(())
+    }
+
+    /// Erase a single region of the EC flash and check that it's blank
+    ///
+    /// The EC can't boot an image whose region was erased, until it's flashed
+    /// again. Erasing the flags resets them to their defaults.
+    /// Same as flashing, RO can't be erased. Without it, the EC can only be
+    /// recovered with an external programmer.
+    pub fn erase_ec_region(&self, region: EcFlashRegion, out: &mut dyn OutputSink) -> EcResult<()> {
+        if region == EcFlashRegion::Ro {
+            return Err(EcError::Unsupported(
+                "For safety reasons erasing RO firmware is disabled".to_string(),
+            ));
+        }
+        let layout = self.flash_layout();
+        let (offset, size) = layout.region(region);
+
+        self.with_flash_unlocked(out, |out| {
+            outln!(out, "Erasing {} region", region.name());
+            self.erase_ec_flash(FLASH_BASE + offset, size)?;
+            outln!(out, "Verifying {} region", region.name());
+            let data = self.read_ec_flash(FLASH_BASE + offset, size)?;
+            if data.iter().all(|x| *x == 0xFF) {
+                Ok(())
+            } else {
+                Err(EcError::VerifyFailed(format!(
+                    "{} region not blank after erase",
+                    region.name()
+                )))
+            }
+        })
+    }
 
+    /// Unlock the flash, run `f` and lock it again
+    ///
+    /// The flash is locked again even if unlocking it or `f` failed, then the
+    /// first error is returned.
+    fn with_flash_unlocked<T>(
+        &self,
+        out: &mut dyn OutputSink,
+        f: impl FnOnce(&mut dyn OutputSink) -> EcResult<T>,
+    ) -> EcResult<T> {
+        outln!(out, "Unlocking flash");
+        let res = self
+            .flash_notify(MecFlashNotify::AccessSpi)
+            .and_then(|()| self.flash_notify(MecFlashNotify::FirmwareStart))
+            .and_then(|()| f(out));
+
+        outln!(out, "Locking flash");
+        let spi_done = self.flash_notify(MecFlashNotify::AccessSpiDone);
+        let firmware_done = self.flash_notify(MecFlashNotify::FirmwareDone);
+        let value = res?;
+        spi_done?;
+        firmware_done?;
+        Ok(value)
+    }
+
+    /// Make sure that RO won't refuse to jump to the new RW, if it verifies RW
+    fn check_rwsig(
+        &self,
+        data: &[u8],
+        rollback_version: u32,
+        out: &mut dyn OutputSink,
+    ) -> EcResult<()> {
+        if !matches!(self.has_feature(EcFeatureCode::RwSig), Ok(true)) {
+            return Ok(());
+        }
+        if ec_binary::read_rw_signature(data).is_none() {
+            outln!(
+                out,
+                "Warning: The EC verifies RW, but the file has no RW signature"
+            );
+        }
+        let min = match self.get_rollback_info() {
+            Ok((_current, min)) => min,
+            Err(err) => {
+                outln!(out, "Warning: Failed to read rollback version: {:?}", err);
+                return Ok(());
+            }
+        };
+        if (rollback_version as i64) < min as i64 {
+            return Err(EcError::DeviceError(format!(
+                "RW rollback version {} in file is lower than {}, RO would refuse to run it",
+                rollback_version, min
+            )));
+        }
+        Ok(())
+    }
+
+    /// Erase, write and verify the regions. Flash must be unlocked already
+    fn reflash_regions(
+        &self,
+        data: &[u8],
+        ft: EcFlashType,
+        layout: &EcFlashLayout,
+        out: &mut dyn OutputSink,
+        cancel: &CancelToken,
+    ) -> EcResult<()> {
         // TODO: Check if erase was successful
         // 1. First erase 0x10000 bytes
         // 2. Read back two rows and make sure it's all 0xFF
         // 3. Write each row (128B) individually
 
         if ft == EcFlashType::Full || ft == EcFlashType::Rw {
-            let rw_data = &data[FLASH_RW_BASE as usize..(FLASH_RW_BASE + FLASH_RW_SIZE) as usize];
+            let rw_data =
+                &data[layout.rw_base as usize..(layout.rw_base + layout.rw_size) as usize];
+            cancel.check()?;
 
-            println!("Erasing RW region");
-            self.erase_ec_flash(FLASH_BASE + FLASH_RW_BASE, FLASH_RW_SIZE)?;
+            outln!(out, "Erasing RW region");
+            self.erase_ec_flash(FLASH_BASE + layout.rw_base, layout.rw_size)?;
 
-            println!("Writing RW region");
-            self.write_ec_flash(FLASH_BASE + FLASH_RW_BASE, rw_data)?;
+            outln!(out, "Writing RW region");
+            self.write_ec_flash(FLASH_BASE + layout.rw_base, rw_data, out)?;
 
-            println!("Verifying RW region");
-            let flash_rw_data = self.read_ec_flash(FLASH_BASE + FLASH_RW_BASE, FLASH_RW_SIZE)?;
+            outln!(out, "Verifying RW region");
+            let flash_rw_data = self.read_ec_flash(FLASH_BASE + layout.rw_base, layout.rw_size)?;
             if rw_data == flash_rw_data {
-                println!("RW verify success");
+                outln!(out, "RW verify success");
             } else {
-                println!("RW verify fail");
+                outln!(out, "RW verify fail");
+                return Err(EcError::VerifyFailed("RW region".to_string()));
             }
         }
 
         if ft == EcFlashType::Full || ft == EcFlashType::Ro {
-            let ro_data = &data[FLASH_RO_BASE as usize..(FLASH_RO_BASE + FLASH_RO_SIZE) as usize];
+            let ro_data =
+                &data[layout.ro_base as usize..(layout.ro_base + layout.ro_size) as usize];
+            cancel.check()?;
 
-            println!("Erasing RO region");
-            self.erase_ec_flash(FLASH_BASE + FLASH_RO_BASE, FLASH_RO_SIZE)?;
+            outln!(out, "Erasing RO region");
+            self.erase_ec_flash(FLASH_BASE + layout.ro_base, layout.ro_size)?;
 
-            println!("Writing RO region");
-            self.write_ec_flash(FLASH_BASE + FLASH_RO_BASE, ro_data)?;
+            outln!(out, "Writing RO region");
+            self.write_ec_flash(FLASH_BASE + layout.ro_base, ro_data, out)?;
 
-            println!("Verifying RO region");
-            let flash_ro_data = self.read_ec_flash(FLASH_BASE + FLASH_RO_BASE, FLASH_RO_SIZE)?;
+            outln!(out, "Verifying RO region");
+            let flash_ro_data = self.read_ec_flash(FLASH_BASE + layout.ro_base, layout.ro_size)?;
             if ro_data == flash_ro_data {
-                println!("RO verify success");
+                outln!(out, "RO verify success");
             } else {
-                println!("RO verify fail");
+                outln!(out, "RO verify fail");
+                return Err(EcError::VerifyFailed("RO region".to_string()));
             }
         }
 
-        println!("Locking flash");
-        self.flash_notify(MecFlashNotify::AccessSpiDone)?;
-        self.flash_notify(MecFlashNotify::FirmwareDone)?;
-
-        println!("Flashing EC done. You can reboot the EC now");
-        // TODO: Should we force a reboot if currently running one was reflashed?
-
         Ok(())
     }
 
-    /// Write a big section of EC flash. Must be unlocked already
-    fn write_ec_flash(&self, addr: u32, data: &[u8]) -> EcResult<()> {
+    /// Write a big section of EC flash. Must be unlocked and erased already
+    ///
+    /// Can't be cancelled, stopping halfway would leave the region unbootable.
+    fn write_ec_flash(&self, addr: u32, data: &[u8], out: &mut dyn OutputSink) -> EcResult<()> {
         let info = EcRequestFlashInfo {}.send_command(self)?;
-        println!("Flash info: {:?}", info);
+        debug!("Flash info: {:?}", info);
         //let chunk_size = ((0x80 / info.write_ideal_size) * info.write_ideal_size) as usize;
         let chunk_size = 0x80;
 
-        let chunks = data.len() / chunk_size;
-        for chunk_no in 0..chunks {
-            let offset = chunk_no * chunk_size;
+        let mut offset = 0;
+        while offset < data.len() {
             // Current chunk might be smaller if it's the last
-            let cur_chunk_size = std::cmp::min(chunk_size, data.len() - chunk_no * chunk_size);
-
-            if chunk_no % 100 == 0 {
-                println!();
-                print!(
-                    "Writing chunk {:>4}/{:>4} ({:>6}/{:>6}): X",
-                    chunk_no,
-                    chunks,
-                    offset,
-                    cur_chunk_size * chunks
-                );
-            } else {
-                print!("X");
-            }
+            let cur_chunk_size = std::cmp::min(chunk_size, data.len() - offset);
 
             let chunk = &data[offset..offset + cur_chunk_size];
             let res = self.write_ec_flash_chunk(addr + offset as u32, chunk);
             if let Err(err) = res {
-                println!("  Failed to write chunk: {:?}", err);
+                outln!(out, "  Failed to write chunk at {:#X}: {:?}", offset, err);
                 return Err(err);
             }
+            offset += cur_chunk_size;
+            out.progress(offset, data.len());
         }
-        println!();
 
         Ok(())
     }
@@ -536,7 +1479,14 @@ impl CrosEc {
     }
 
     fn erase_ec_flash(&self, offset: u32, size: u32) -> EcResult<()> {
-        EcRequestFlashErase { offset, size }.send_command(self)
+        // Erasing a whole region takes seconds, the EC is busy until it's done
+        let policy = RetryPolicy {
+            ready_timeout_ms: None,
+            ..retry_policy()
+        };
+        with_retry_policy(policy, || {
+            EcRequestFlashErase { offset, size }.send_command(self)
+        })
     }
 
     pub fn flash_notify(&self, flag: MecFlashNotify) -> EcResult<()> {
@@ -548,13 +1498,10 @@ impl CrosEc {
     /// Maximum size to read is 0x80/128 bytes at a time
     /// Must `self.flash_notify(MecFlashNotify::AccessSpi)?;` first, otherwise it'll return all 0s
     pub fn read_ec_flash_chunk(&self, offset: u32, size: u32) -> EcResult<Vec<u8>> {
-        // TODO: Windows asserts
-        //assert!(size <= 0x80); // TODO: I think this is EC_LPC_HOST_PACKET_SIZE - size_of::<EcHostResponse>()
-        let data = EcRequestFlashRead { offset, size }.send_command_vec(self)?;
-
-        // TODO: Windows asserts because it returns more data
-        //debug_assert!(data.len() == size as usize); // Make sure we get back what was requested
-        Ok(data[..size as usize].to_vec())
+        assert!(size <= 0x80); // TODO: I think this is EC_LPC_HOST_PACKET_SIZE - size_of::<EcHostResponse>()
+        read_full("Flash read", size as usize, || {
+            EcRequestFlashRead { offset, size }.send_command_vec(self)
+        })
     }
 
     pub fn read_ec_flash(&self, offset: u32, size: u32) -> EcResult<Vec<u8>> {
@@ -631,20 +1578,27 @@ impl CrosEc {
     }
 
     pub fn test_ec_flash_read(&self) -> EcResult<()> {
+        self.test_ec_flash_read_with(&mut StdoutSink)
+    }
+
+    /// Like `test_ec_flash_read` but write the results to `out`
+    pub fn test_ec_flash_read_with(&self, out: &mut dyn OutputSink) -> EcResult<()> {
         let mut res = Ok(());
+        let layout = self.flash_layout();
+        outln!(out, "    EC chip: {:?}", layout.chip);
         // TODO: Perhaps we could have some more global flag to avoid setting and unsetting that ever time
         self.flash_notify(MecFlashNotify::AccessSpi)?;
 
         // ===== Test 1 =====
         // Read the first row of flash.
         // It's the beginning of RO firmware
-        println!("    Read first row of flash (RO FW)");
+        outln!(out, "    Read first row of flash (RO FW)");
         let data = self.read_ec_flash(0, 0x80).unwrap();
 
         debug!("{:02X?}", data);
-        println!("      {:02X?}", &data[..8]);
+        outln!(out, "      {:02X?}", &data[..8]);
         if data.iter().all(|x| *x == 0xFF) {
-            println!("      Erased!");
+            outln!(out, "      Erased!");
         }
 
         // 4 magic bytes at the beginning
@@ -652,7 +1606,7 @@ impl CrosEc {
         // TODO: Does zephyr always start like this?
         let zephyr_start = [0x5E, 0x4D, 0x3B, 0x2A];
         if data[0..4] != legacy_start && data[0..4] != zephyr_start {
-            println!("      INVALID start");
+            outln!(out, "      INVALID start");
             res = Err(EcError::DeviceError("INVALID start".to_string()));
         }
         // Legacy EC is all 0xFF until the end of the row
@@ -660,7 +1614,7 @@ impl CrosEc {
         let legacy_comp = !data[4..].iter().all(|x| *x == 0xFF);
         let zephyr_comp = !data[0x20..0x40].iter().all(|x| *x == 0x00);
         if legacy_comp && zephyr_comp {
-            println!("      INVALID end");
+            outln!(out, "      INVALID end");
             res = Err(EcError::DeviceError("INVALID end".to_string()));
         }
 
@@ -670,12 +1624,12 @@ impl CrosEc {
         //
         // Read the first row of the second half of flash
         // It's the beginning of RW firmware
-        println!("    Read first row of RW FW");
+        outln!(out, "    Read first row of RW FW");
         let data = self.read_ec_flash(0x40000, 0x80).unwrap();
 
-        println!("      {:02X?}", &data[..8]);
+        outln!(out, "      {:02X?}", &data[..8]);
         if data.iter().all(|x| *x == 0xFF) {
-            println!("      Erased!");
+            outln!(out, "      Erased!");
             res = Err(EcError::DeviceError("RW Erased".to_string()));
         }
 
@@ -685,13 +1639,13 @@ impl CrosEc {
         // let legacy_start = []; // TODO
         // let zephyr_start = [0x80, 0x7D, 0x0C, 0x20];
         // if data[0..4] != legacy_start && data[0..4] != zephyr_start {
-        //     println!("      INVALID start");
+        //     outln!(out, "      INVALID start");
         //     res = Err(EcError::DeviceError("INVALID start".to_string()));
         // }
         // let legacy_comp = !data[4..].iter().all(|x| *x == 0xFF);
         // let zephyr_comp = !data[0x20..0x2C].iter().all(|x| *x == 0x00);
         // if legacy_comp && zephyr_comp {
-        //     println!("      INVALID end");
+        //     outln!(out, "      INVALID end");
         //     res = Err(EcError::DeviceError("INVALID end".to_string()));
         // }
 
@@ -702,20 +1656,21 @@ impl CrosEc {
         // Everything before is probably a header.
         // TODO: I don't think there are magic bytes on zephyr firmware
         //
-        if has_mec() {
-            println!("    Check MCHP magic byte at start of firmware code.");
+        if let Some(program_offset) = layout.program_offset {
+            outln!(out, "    Check MCHP magic byte at start of firmware code.");
             // Make sure we can read at an offset and with arbitrary length
-            let data = self.read_ec_flash(FLASH_PROGRAM_OFFSET, 16).unwrap();
+            let data = self.read_ec_flash(program_offset, 16).unwrap();
             debug!("Expecting beginning with 50 48 43 4D ('PHCM' in ASCII)");
             debug!("{:02X?}", data);
-            println!(
+            outln!(
+                out,
                 "      {:02X?} ASCII:{:?}",
                 &data[..4],
                 core::str::from_utf8(&data[..4])
             );
 
-            if data[0..4] != [0x50, 0x48, 0x43, 0x4D] {
-                println!("      INVALID: {:02X?}", &data[0..3]);
+            if data[0..4] != MCHP_MAGIC {
+                outln!(out, "      INVALID: {:02X?}", &data[0..3]);
                 res = Err(EcError::DeviceError(format!(
                     "INVALID: {:02X?}",
                     &data[0..3]
@@ -724,22 +1679,18 @@ impl CrosEc {
         }
 
         // ===== Test 4 =====
-        println!("    Read flash flags");
-        let data = if has_mec() {
-            self.read_ec_flash(MEC_FLASH_FLAGS, 0x80).unwrap()
-        } else {
-            self.read_ec_flash(NPC_FLASH_FLAGS, 0x80).unwrap()
-        };
+        outln!(out, "    Read flash flags");
+        let data = self.read_ec_flash(layout.flags_base, 0x80).unwrap();
         let flash_flags_magic = [0xA3, 0xF1, 0x00, 0x00];
         let flash_flags_ver = [0x01, 0x0, 0x00, 0x00];
         // All 0xFF if just reflashed and not reinitialized by EC
         if data[0..4] == flash_flags_magic && data[8..12] == flash_flags_ver {
-            println!("      Valid flash flags");
+            outln!(out, "      Valid flash flags");
         } else if data.iter().all(|x| *x == 0xFF) {
-            println!("      Erased flash flags");
+            outln!(out, "      Erased flash flags");
             res = Err(EcError::DeviceError("Erased flash flags".to_string()));
         } else {
-            println!("      INVALID flash flags: {:02X?}", &data[0..12]);
+            outln!(out, "      INVALID flash flags: {:02X?}", &data[0..12]);
             // TODO: Disable error until I confirm flash flags on MEC
             // res = Err(EcError::DeviceError("INVALID flash flags".to_string()));
         }
@@ -752,7 +1703,20 @@ impl CrosEc {
     /// Requests recent console output from EC and constantly asks for more
     /// Prints the output and returns it when an error is encountered
     pub fn console_read(&self) -> EcResult<String> {
+        self.console_read_with(&mut StdoutSink, &CancelToken::new())
+    }
+
+    /// Like `console_read` but write every line to `out` and stop when `cancel` is triggered
+    ///
+    /// Returns all output read until then.
+    pub fn console_read_with(
+        &self,
+        out: &mut dyn OutputSink,
+        cancel: &CancelToken,
+    ) -> EcResult<String> {
         let mut console = String::new();
+        // Output since the last newline, not written to the sink yet
+        let mut line = String::new();
         let mut cmd = EcRequestConsoleRead {
             subcmd: ConsoleReadSubCommand::ConsoleReadRecent as u8,
         };
@@ -765,32 +1729,44 @@ impl CrosEc {
                     // Can't run it too quickly, otherwise the commands might fail
                     if data.is_empty() {
                         trace!("Empty EC response");
-                        println!("---");
+                        outln!(out, "---");
                         os_specific::sleep(1_000_000); // 1s
                     }
 
-                    let utf8 = std::str::from_utf8(&data).unwrap();
+                    let utf8 = String::from_utf8_lossy(&data);
                     let ascii = utf8
                         .replace(|c: char| !c.is_ascii(), "")
                         .replace(['\0'], "");
 
-                    print!("{}", ascii);
                     console.push_str(ascii.as_str());
+                    line.push_str(ascii.as_str());
+                    while let Some(end) = line.find('\n') {
+                        out.write_line(line[..end].trim_end_matches('\r'));
+                        line.drain(..=end);
+                    }
                 }
                 Err(err) => {
                     error!("Err: {:?}", err);
-                    return Ok(console);
+                    break;
                     //return Err(err)
                 }
             };
             cmd.subcmd = ConsoleReadSubCommand::ConsoleReadNext as u8;
 
+            if cancel.is_cancelled() {
+                break;
+            }
             // Need to explicitly handle CTRL-C termination on UEFI Shell
             #[cfg(feature = "uefi")]
             if shell_get_execution_break_flag() {
-                return Ok(console);
+                break;
             }
         }
+
+        if !line.is_empty() {
+            out.write_line(&line);
+        }
+        Ok(console)
     }
 
     pub fn console_read_one(&self) -> EcResult<String> {
@@ -799,15 +1775,30 @@ impl CrosEc {
             subcmd: ConsoleReadSubCommand::ConsoleReadRecent as u8,
         }
         .send_command_vec(self)?;
-        let utf8 = std::str::from_utf8(&data).unwrap();
+        let utf8 = String::from_utf8_lossy(&data);
         let ascii = utf8
             .replace(|c: char| !c.is_ascii(), "")
             .replace(['\0'], "");
         Ok(ascii)
     }
 
+    /// Check whether the firmware supports a feature
+    pub fn has_feature(&self, feature: EcFeatureCode) -> EcResult<bool> {
+        let data = EcRequestGetFeatures {}.send_command(self)?;
+        let i = feature as usize;
+        Ok((data.flags[i / 32] & (1 << (i % 32))) > 0)
+    }
+
+    /// Rollback version of the running RW and the minimum that RO accepts
+    ///
+    /// Only supported if RO verifies RW, see `EcFeatureCode::RwSig`.
+    pub fn get_rollback_info(&self) -> EcResult<(i32, i32)> {
+        let info = EcRequestRollbackInfo {}.send_command(self)?;
+        Ok((info.rw_rollback_version, info.rollback_min_version))
+    }
+
     /// Check features supported by the firmware
-    pub fn get_features(&self) -> EcResult<()> {
+    pub fn get_features(&self, out: &mut dyn OutputSink) -> EcResult<()> {
         let data = EcRequestGetFeatures {}.send_command(self)?;
         for i in 0..64 {
             let byte = i / 32;
@@ -816,7 +1807,7 @@ impl CrosEc {
             let feat: Option<EcFeatureCode> = FromPrimitive::from_usize(i);
 
             if let Some(feat) = feat {
-                println!("{:>2}: {:>5} {:?}", i, val, feat);
+                outln!(out, "{:>2}: {:>5} {:?}", i, val, feat);
             }
         }
 
@@ -828,6 +1819,13 @@ impl CrosEc {
         EcRequestReboot {}.send_command(self)
     }
 
+    /// Disconnect the battery, to keep it from draining during storage (ship mode)
+    ///
+    /// The system only powers on again when AC is connected.
+    pub fn battery_cutoff(&self, mode: BatteryCutoffFlags) -> EcResult<()> {
+        EcRequestBatteryCutoffV1 { flags: mode as u8 }.send_command(self)
+    }
+
     pub fn reboot_ec(&self, command: RebootEcCmd) -> EcResult<()> {
         EcRequestRebootEc {
             cmd: command as u8,
@@ -871,6 +1869,129 @@ impl CrosEc {
         .send_command(self)
     }
 
+    /// Get the angle between lid and base in degrees
+    ///
+    /// Returns None if the EC can't tell reliably, e.g. when the lid is closed or almost flat
+    pub fn get_lid_angle(&self) -> EcResult<Option<u16>> {
+        let res = EcRequestMotionSenseLidAngle {
+            cmd: MotionSenseCmd::LidAngle as u8,
+        }
+        .send_command(self)?;
+        let angle = res.value;
+        if angle == LID_ANGLE_UNRELIABLE || angle < 0 {
+            Ok(None)
+        } else {
+            Ok(Some(angle as u16))
+        }
+    }
+
+    /// Get lid angle at which the EC switches to tablet mode, and the hysteresis
+    pub fn get_tablet_mode_threshold(&self) -> EcResult<(i16, i16)> {
+        let res = EcRequestMotionSenseTabletMode {
+            cmd: MotionSenseCmd::TabletModeLidAngle as u8,
+            lid_angle: EC_MOTION_SENSE_NO_VALUE,
+            hys_degree: EC_MOTION_SENSE_NO_VALUE,
+        }
+        .send_command(self)?;
+        Ok((res.lid_angle, res.hys_degree))
+    }
+
+    /// Calibrate a motion sensor and store the resulting offset
+    ///
+    /// The system must lie still on a flat surface while calibrating.
+    /// Returns the new offset for X, Y and Z.
+    pub fn perform_sensor_calibration(&self, sensor: u8) -> EcResult<[i16; 3]> {
+        let res = EcRequestMotionSensePerformCalib {
+            cmd: MotionSenseCmd::PerformCalib as u8,
+            sensor_num: sensor,
+            enable: 1,
+        }
+        .send_command(self)
+        .context("Failed to calibrate sensor")?;
+        Ok(res.offset)
+    }
+
+    /// Get the calibration offset of a motion sensor, for X, Y and Z
+    pub fn get_sensor_offset(&self, sensor: u8) -> EcResult<[i16; 3]> {
+        let res = EcRequestMotionSenseSensorOffset {
+            cmd: MotionSenseCmd::SensorOffset as u8,
+            sensor_num: sensor,
+            flags: 0,
+            temp: EC_MOTION_SENSE_INVALID_CALIB_TEMP,
+            offset: [0; 3],
+        }
+        .send_command(self)?;
+        Ok(res.offset)
+    }
+
+    /// Override the calibration offset of a motion sensor, for X, Y and Z
+    pub fn set_sensor_offset(&self, sensor: u8, offset: [i16; 3]) -> EcResult<()> {
+        EcRequestMotionSenseSensorOffset {
+            cmd: MotionSenseCmd::SensorOffset as u8,
+            sensor_num: sensor,
+            flags: MOTION_SENSE_SET_OFFSET,
+            temp: EC_MOTION_SENSE_INVALID_CALIB_TEMP,
+            offset,
+        }
+        .send_command(self)?;
+        Ok(())
+    }
+
+    /// Current state of the switches that the EC reports through MKBP, see EC_MKBP_*
+    ///
+    /// Fails if the EC doesn't support MKBP.
+    pub fn get_mkbp_switches(&self) -> EcResult<u32> {
+        let res = EcRequestMkbpInfo {
+            info_type: MkbpInfoType::Current,
+            event_type: MkbpEventType::Switch,
+        }
+        .send_command(self)?;
+        Ok(res.switches)
+    }
+
+    /// Check whether the EC currently considers the system to be in tablet mode
+    pub fn get_tablet_mode(&self) -> EcResult<bool> {
+        Ok(self.get_mkbp_switches()? & EC_MKBP_TABLET_MODE != 0)
+    }
+
+    /// Wait for the next event that the EC sends on its own, like a switch or button change
+    ///
+    /// Returns None if there was no event within the timeout. With the Linux
+    /// kernel driver this blocks until the EC signals an event, with the other
+    /// drivers the EC is asked for events every `NEXT_EVENT_POLL_MS`.
+    /// Fails with `EcResponseStatus::InvalidCommand` if the EC doesn't support MKBP.
+    #[cfg(not(feature = "uefi"))]
+    pub fn next_event(&self, timeout: Duration) -> EcResult<Option<mkbp::MkbpEvent>> {
+        #[cfg(feature = "cros_ec_driver")]
+        if self.driver == CrosEcDriverType::CrosEc {
+            // Subscribe to all event types
+            let data = cros_ec::wait_event(u32::MAX, timeout)?;
+            return Ok(data.and_then(|data| mkbp::MkbpEvent::parse(&data)));
+        }
+
+        let start = Instant::now();
+        loop {
+            let res = EcRequestGetNextEvent {}.send_command_vec(self);
+            match res {
+                Ok(data) => return Ok(mkbp::MkbpEvent::parse(&data)),
+                Err(EcError::Response(EcResponseStatus::Unavailable)) => {}
+                Err(err) => return Err(err),
+            }
+            if start.elapsed() >= timeout {
+                return Ok(None);
+            }
+            os_specific::sleep(NEXT_EVENT_POLL_MS * 1000);
+        }
+    }
+
+    /// Force tablet or laptop mode, until it's set back to default or the EC resets
+    pub fn set_tablet_mode(&self, mode: TabletModeOverride) -> EcResult<()> {
+        EcRequestSetTabletMode {
+            tablet_mode: mode as u8,
+        }
+        .send_command(self)
+    }
+
     pub fn get_gpio(&self, name: &str) -> EcResult<bool> {
         const MAX_LEN: usize = 32;
         let mut request = EcRequestGpioGetV0 { name: [0; MAX_LEN] };
@@ -881,17 +2002,36 @@ impl CrosEc {
         let res = request.send_command(self)?;
         Ok(res.val == 1)
     }
+
+    /// Drive a GPIO high or low
+    ///
+    /// The EC refuses this, unless it is unlocked.
+    pub fn set_gpio(&self, name: &str, value: bool) -> EcResult<()> {
+        const MAX_LEN: usize = 32;
+        let mut request = EcRequestGpioSetV0 {
+            name: [0; MAX_LEN],
+            val: value as u8,
+        };
+
+        let end = MAX_LEN.min(name.len());
+        request.name[..end].copy_from_slice(&name.as_bytes()[..end]);
+
+        request.send_command(self)
+    }
 }
 
-#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[cfg_attr(feature = "commandline", derive(clap::ValueEnum))]
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub enum CrosEcDriverType {
     Portio,
     CrosEc,
     Windows,
+    /// Answers from a recorded trace, see `CrosEc::replay`
+    #[cfg_attr(feature = "commandline", value(skip))]
+    Replay,
 }
 
-#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[cfg_attr(feature = "commandline", derive(clap::ValueEnum))]
 #[derive(Clone, Debug, Copy, PartialEq)]
 pub enum HardwareDeviceType {
     BIOS,
@@ -906,24 +2046,15 @@ pub enum HardwareDeviceType {
 
 impl CrosEcDriver for CrosEc {
     fn read_memory(&self, offset: u16, length: u16) -> Option<Vec<u8>> {
-        if !smbios::is_framework() {
+        if !self.is_framework() {
             return None;
         }
-
-        debug!("read_memory(offset={:#X}, size={:#X})", offset, length);
         if offset + length > EC_MEMMAP_SIZE {
             return None;
         }
 
         // TODO: Change this function to return EcResult instead and print the error only in UI code
-        print_err(match self.driver {
-            CrosEcDriverType::Portio => portio::read_memory(offset, length),
-            #[cfg(feature = "win_driver")]
-            CrosEcDriverType::Windows => windows::read_memory(offset, length),
-            #[cfg(feature = "cros_ec_driver")]
-            CrosEcDriverType::CrosEc => cros_ec::read_memory(offset, length),
-            _ => Err(EcError::DeviceError("No EC driver available".to_string())),
-        })
+        print_err(self.read_memory_result(offset, length))
     }
     fn send_command(&self, command: u16, command_version: u8, data: &[u8]) -> EcResult<Vec<u8>> {
         debug!(
@@ -933,26 +2064,103 @@ impl CrosEcDriver for CrosEc {
             data.len()
         );
 
-        if !smbios::is_framework() {
+        if !self.is_framework() {
             return Err(EcError::DeviceError("Not a Framework Laptop".to_string()));
         }
 
-        match self.driver {
+        let _lock = lock_ec();
+        #[cfg(not(feature = "uefi"))]
+        let start = Instant::now();
+        let res = match self.driver {
             CrosEcDriverType::Portio => portio::send_command(command, command_version, data),
             #[cfg(feature = "win_driver")]
             CrosEcDriverType::Windows => windows::send_command(command, command_version, data),
             #[cfg(feature = "cros_ec_driver")]
             CrosEcDriverType::CrosEc => cros_ec::send_command(command, command_version, data),
-            _ => Err(EcError::DeviceError("No EC driver available".to_string())),
+            #[cfg(not(feature = "uefi"))]
+            CrosEcDriverType::Replay => {
+                trace::replay(TraceKind::Command, command, command_version.into(), data)
+            }
+            _ => Err(EcError::DriverUnavailable(
+                "No EC driver available".to_string(),
+            )),
+        };
+        #[cfg(not(feature = "uefi"))]
+        trace::record(
+            TraceKind::Command,
+            command,
+            command_version.into(),
+            data,
+            &res,
+            start.elapsed(),
+        );
+        res
+    }
+}
+
+/// How often to read again, when the driver returned less than requested
+const SHORT_READ_RETRIES: u32 = 3;
+
+/// Read until the driver returns all of the requested bytes
+///
+/// Drivers don't always return exactly what was requested. The Windows
+/// driver used to return more and reads can come up short while the EC is
+/// busy. Extra bytes are dropped and short reads are tried again, so `read`
+/// must not have side effects.
+fn read_full(
+    what: &str,
+    length: usize,
+    mut read: impl FnMut() -> EcResult<Vec<u8>>,
+) -> EcResult<Vec<u8>> {
+    let mut retries = 0;
+    loop {
+        let mut data = read()?;
+        if data.len() >= length {
+            if data.len() > length {
+                debug!(
+                    "{} returned {} bytes instead of {}, ignoring the rest",
+                    what,
+                    data.len(),
+                    length
+                );
+                data.truncate(length);
+            }
+            return Ok(data);
         }
+        if retries == SHORT_READ_RETRIES {
+            return Err(EcError::ProtocolMismatch(format!(
+                "{} returned only {} of {} bytes",
+                what,
+                data.len(),
+                length
+            )));
+        }
+        debug!(
+            "{} returned only {} of {} bytes, retrying",
+            what,
+            data.len(),
+            length
+        );
+        retries += 1;
     }
 }
 
+/// Make sure that the response has exactly the size that the command returns
+pub fn check_response_len(response: &[u8], expected: usize) -> EcResult<()> {
+    if response.len() != expected {
+        return Err(EcError::ProtocolMismatch(format!(
+            "Returned data size ({}) is not the expected size: {}",
+            response.len(),
+            expected
+        )));
+    }
+    Ok(())
+}
+
 /// Print the error
 pub fn print_err_ref<T>(something: &EcResult<T>) {
     match something {
         Ok(_) => {}
-        // TODO: Some errors we can handle and retry, like Busy, Timeout, InProgress, ...
         Err(EcError::Response(status)) => {
             error!("EC Response Code: {:?}", status);
         }
@@ -962,6 +2170,21 @@ pub fn print_err_ref<T>(something: &EcResult<T>) {
         Err(EcError::DeviceError(str)) => {
             error!("Failed to communicate with EC. Reason: {:?}", str);
         }
+        Err(err) => {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Charging settings only make sense if there's a battery to charge
+pub fn require_battery(what: &str) -> EcResult<()> {
+    if util::platform_spec().has_battery {
+        Ok(())
+    } else {
+        Err(EcError::Unsupported(format!(
+            "System has no battery, {} is not available",
+            what
+        )))
     }
 }
 
@@ -974,13 +2197,31 @@ pub fn print_err<T>(something: EcResult<T>) -> Option<T> {
 }
 
 /// Which of the two EC images is currently in-use
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EcCurrentImage {
     Unknown = 0,
     RO = 1,
     RW = 2,
 }
 
+/// Host command latency measured by `CrosEc::benchmark`
+#[cfg(not(feature = "uefi"))]
+#[derive(Debug)]
+pub struct EcBenchmark {
+    pub driver: CrosEcDriverType,
+    /// Highest host command protocol version, if the EC could report it
+    pub protocol_version: Option<u8>,
+    pub iterations: usize,
+    /// How many of the commands returned an error
+    pub failures: usize,
+    pub min: Duration,
+    pub avg: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    /// Wall-clock time of the entire run
+    pub total: Duration,
+}
+
 pub struct IntrusionStatus {
     /// Whether the chassis is currently open
     pub currently_open: bool,
@@ -996,3 +2237,69 @@ pub struct IntrusionStatus {
     /// That means we only know if it was opened at least once, while off, not how many times.
     pub vtr_open_count: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_full_normalizes_length() {
+        // Extra bytes are dropped
+        let res = read_full("Test", 2, || Ok(vec![b'E', b'C', 0, 0]));
+        assert_eq!(res, Ok(vec![b'E', b'C']));
+
+        // Short reads are retried
+        let mut reads = vec![vec![1, 2, 3], vec![1], vec![]];
+        let res = read_full("Test", 3, || Ok(reads.pop().unwrap()));
+        assert_eq!(res, Ok(vec![1, 2, 3]));
+
+        // Until giving up
+        let mut attempts = 0;
+        let res = read_full("Test", 3, || {
+            attempts += 1;
+            Ok(vec![1])
+        });
+        assert_eq!(
+            res,
+            Err(EcError::ProtocolMismatch(
+                "Test returned only 1 of 3 bytes".to_string()
+            ))
+        );
+        assert_eq!(attempts, SHORT_READ_RETRIES + 1);
+
+        // Errors aren't retried
+        attempts = 0;
+        let res = read_full("Test", 3, || {
+            attempts += 1;
+            Err(EcError::Response(EcResponseStatus::AccessDenied))
+        });
+        assert_eq!(res, Err(EcError::Response(EcResponseStatus::AccessDenied)));
+        assert_eq!(attempts, 1);
+
+        assert_eq!(check_response_len(&[], 0), Ok(()));
+        assert!(matches!(
+            check_response_len(&[0; 20], 0),
+            Err(EcError::ProtocolMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn cancel_child_token() {
+        let root = CancelToken::new();
+        let first = root.child();
+        assert!(root.has_children());
+
+        // Cancelling the child doesn't cancel the parent
+        first.cancel();
+        assert!(first.is_cancelled() && !root.is_cancelled());
+
+        let second = root.child();
+        root.cancel();
+        assert!(second.is_cancelled());
+
+        drop((first, second));
+        assert!(!root.has_children());
+        root.reset();
+        assert!(!root.child().is_cancelled());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::chromium_ec::command::retry_policy;
 use crate::chromium_ec::{EcError, EcResponseStatus, EcResult};
 use alloc::format;
 use alloc::string::ToString;
@@ -235,18 +236,29 @@ fn init() -> bool {
     true
 }
 
-fn wait_for_ready() {
+fn wait_for_ready() -> EcResult<()> {
     if !init() {
         // Failed to initialize
-        return;
+        return Ok(());
     }
-    // TODO: Abort after reasonable timeout
+    // Check every millisecond, at least once
+    let timeout_ms = retry_policy().ready_timeout_ms;
+    let mut waited_ms = 0;
     loop {
         let status = Pio::<u8>::new(EC_LPC_ADDR_HOST_CMD).read();
         if 0 == (status & EC_LPC_STATUS_BUSY_MASK) {
-            break;
+            return Ok(());
         }
-        os_specific::sleep(1000)
+        if let Some(timeout_ms) = timeout_ms {
+            if waited_ms >= timeout_ms {
+                return Err(EcError::Timeout(format!(
+                    "EC not ready after {}ms",
+                    timeout_ms
+                )));
+            }
+        }
+        os_specific::sleep(1000);
+        waited_ms += 1;
     }
 }
 
@@ -347,7 +359,9 @@ fn unpack_response_header(bytes: &[u8]) -> EcHostResponse {
 
 pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<Vec<u8>> {
     if !init() {
-        return Err(EcError::DeviceError("Failed to initialize".to_string()));
+        return Err(EcError::Permission(
+            "Failed to initialize port I/O. Must be root and kernel lockdown disabled".to_string(),
+        ));
     }
     let request = EcHostRequest {
         struct_version: EC_HOST_REQUEST_VERSION,
@@ -365,7 +379,7 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
     if log_enabled!(Level::Trace) {
         println!("Waiting to be ready");
     }
-    wait_for_ready();
+    wait_for_ready()?;
     if log_enabled!(Level::Trace) {
         print!("Ready, transferring request buffer: ");
     }
@@ -376,7 +390,7 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
 
     // Set the command version
     Pio::<u8>::new(EC_LPC_ADDR_HOST_CMD).write(EC_COMMAND_PROTOCOL_3);
-    wait_for_ready();
+    wait_for_ready()?;
     let res = Pio::<u8>::new(EC_LPC_ADDR_HOST_DATA).read();
     match FromPrimitive::from_u8(res) {
         None => return Err(EcError::UnknownResponseCode(res as u32)),
@@ -399,13 +413,13 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
     );
 
     if resp_header.struct_version != EC_HOST_RESPONSE_VERSION {
-        return Err(EcError::DeviceError(format!(
+        return Err(EcError::ProtocolMismatch(format!(
             "Struct version invalid. Should be {:#X}, is {:#X}",
             EC_HOST_RESPONSE_VERSION, resp_header.struct_version
         )));
     }
     if resp_header.reserved != 0 {
-        return Err(EcError::DeviceError(format!(
+        return Err(EcError::ProtocolMismatch(format!(
             "Reserved invalid. Should be 0, is {:#X}",
             { resp_header.reserved }
         )));
@@ -414,7 +428,7 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
         println!("Data Len is: {:?}", { resp_header.data_len });
     }
     if resp_header.data_len > EC_LPC_HOST_PACKET_SIZE {
-        return Err(EcError::DeviceError("Packet size too big".to_string()));
+        return Err(EcError::ProtocolMismatch("Packet size too big".to_string()));
     }
     let resp_buffer = if resp_header.data_len > 0 {
         let data = transfer_read(EC_LPC_ADDR_HOST_ARGS, 8, resp_header.data_len);
@@ -434,7 +448,9 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
 
 pub fn read_memory(offset: u16, length: u16) -> EcResult<Vec<u8>> {
     if !init() {
-        return Err(EcError::DeviceError("Failed to initialize".to_string()));
+        return Err(EcError::Permission(
+            "Failed to initialize port I/O. Must be root and kernel lockdown disabled".to_string(),
+        ));
     }
 
     if has_mec() {
@@ -0,0 +1,341 @@
+//! Record EC traffic to a file and replay it later
+//!
+//! A trace contains every host command and memory map read, with the request,
+//! the response and how long it took. When attached to a bug report, it lets
+//! the problem be reproduced without the hardware, using `--replay-ec`.
+//!
+//! The file is plain text, one line per transaction:
+//!
+//! ```text
+//! # framework_tool EC trace v1
+//! cmd 0x0002 0 ok 152 - 0102030405
+//! cmd 0x3E03 1 ec=3 88 0a00 -
+//! mem 0x0020 2 ok 12 - 4543
+//! ```
+//!
+//! The fields are: kind, command ID or memory map offset, command version or
+//! read length, result, duration in microseconds, request and response bytes in hex.
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+
+use super::{EcError, EcResponseStatus, EcResult};
+use crate::smbios;
+
+const HEADER: &str = "# framework_tool EC trace v1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceKind {
+    /// Host command
+    Command,
+    /// Read from the memory map
+    Memory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceResult {
+    Ok,
+    /// EC responded with an error code
+    Ec(u32),
+    /// Failed to talk to the EC
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub kind: TraceKind,
+    /// Command ID or memory map offset
+    pub id: u16,
+    /// Command version or number of bytes read from the memory map
+    pub version: u16,
+    pub result: TraceResult,
+    pub micros: u64,
+    pub request: Vec<u8>,
+    pub response: Vec<u8>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        return Some(vec![]);
+    }
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl TraceEntry {
+    fn new(
+        kind: TraceKind,
+        id: u16,
+        version: u16,
+        request: &[u8],
+        res: &EcResult<Vec<u8>>,
+        elapsed: Duration,
+    ) -> Self {
+        let (result, response) = match res {
+            Ok(response) => (TraceResult::Ok, response.clone()),
+            Err(EcError::Response(status)) => (TraceResult::Ec(*status as u32), vec![]),
+            Err(EcError::UnknownResponseCode(code)) => (TraceResult::Ec(*code), vec![]),
+            Err(_) => (TraceResult::Error, vec![]),
+        };
+        TraceEntry {
+            kind,
+            id,
+            version,
+            result,
+            micros: elapsed.as_micros() as u64,
+            request: request.to_vec(),
+            response,
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        let kind = match self.kind {
+            TraceKind::Command => "cmd",
+            TraceKind::Memory => "mem",
+        };
+        let result = match self.result {
+            TraceResult::Ok => "ok".to_string(),
+            TraceResult::Ec(code) => format!("ec={}", code),
+            TraceResult::Error => "err".to_string(),
+        };
+        format!(
+            "{} 0x{:04X} {} {} {} {} {}",
+            kind,
+            self.id,
+            self.version,
+            result,
+            self.micros,
+            to_hex(&self.request),
+            to_hex(&self.response)
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [kind, id, version, result, micros, request, response] = fields[..] else {
+            return None;
+        };
+        let kind = match kind {
+            "cmd" => TraceKind::Command,
+            "mem" => TraceKind::Memory,
+            _ => return None,
+        };
+        let result = match result {
+            "ok" => TraceResult::Ok,
+            "err" => TraceResult::Error,
+            _ => TraceResult::Ec(result.strip_prefix("ec=")?.parse().ok()?),
+        };
+        Some(TraceEntry {
+            kind,
+            id: u16::from_str_radix(id.strip_prefix("0x")?, 16).ok()?,
+            version: version.parse().ok()?,
+            result,
+            micros: micros.parse().ok()?,
+            request: from_hex(request)?,
+            response: from_hex(response)?,
+        })
+    }
+
+    /// What the EC returned when the trace was recorded
+    fn to_result(&self) -> EcResult<Vec<u8>> {
+        match self.result {
+            TraceResult::Ok => Ok(self.response.clone()),
+            TraceResult::Ec(code) => Err(match EcResponseStatus::from_u32(code) {
+                Some(status) => EcError::Response(status),
+                None => EcError::UnknownResponseCode(code),
+            }),
+            TraceResult::Error => Err(EcError::DeviceError(
+                "Failed to communicate with the EC when recording".to_string(),
+            )),
+        }
+    }
+}
+
+/// Trace file that's being written to
+static RECORDER: Mutex<Option<File>> = Mutex::new(None);
+
+/// Record all EC traffic to this file, from now on
+pub fn start_recording(path: &str) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "{}", HEADER)?;
+    writeln!(file, "# platform: {:?}", smbios::get_platform())?;
+    *RECORDER.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub(crate) fn record(
+    kind: TraceKind,
+    id: u16,
+    version: u16,
+    request: &[u8],
+    res: &EcResult<Vec<u8>>,
+    elapsed: Duration,
+) {
+    let mut recorder = RECORDER.lock().unwrap();
+    let Some(file) = recorder.as_mut() else {
+        return;
+    };
+    let entry = TraceEntry::new(kind, id, version, request, res, elapsed);
+    // Write each line immediately, so it's not lost if the tool crashes
+    if let Err(err) = writeln!(file, "{}", entry.to_line()) {
+        error!("Failed to write EC trace: {:?}", err);
+        *recorder = None;
+    }
+}
+
+/// Recorded transactions to answer requests from
+#[derive(Debug, Default)]
+pub struct Replay {
+    entries: Vec<TraceEntry>,
+    /// Where to start looking for the next match
+    next: usize,
+}
+
+impl Replay {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut entries = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = TraceEntry::parse(line)
+                .ok_or_else(|| format!("Line {}: Invalid trace entry '{}'", i + 1, line))?;
+            entries.push(entry);
+        }
+        Ok(Replay { entries, next: 0 })
+    }
+
+    /// Find the recorded response for the request
+    ///
+    /// Transactions are matched in the order they were recorded. When the tool
+    /// sends a request that wasn't recorded at this point, e.g. because it polls
+    /// the EC more often, the earliest matching transaction is used instead.
+    pub fn find(
+        &mut self,
+        kind: TraceKind,
+        id: u16,
+        version: u16,
+        request: &[u8],
+    ) -> Option<&TraceEntry> {
+        let len = self.entries.len();
+        let i = (self.next..len).chain(0..self.next).find(|&i| {
+            let entry = &self.entries[i];
+            entry.kind == kind
+                && entry.id == id
+                && entry.version == version
+                && entry.request == request
+        })?;
+        self.next = i + 1;
+        Some(&self.entries[i])
+    }
+}
+
+/// Trace to answer requests from, when the replay driver is used
+static REPLAY: Mutex<Option<Replay>> = Mutex::new(None);
+
+/// Answer EC requests from this trace, instead of the EC
+pub fn load_replay(path: &str) -> io::Result<()> {
+    let contents = std::fs::read_to_string(path)?;
+    let replay =
+        Replay::parse(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    debug!("Loaded {} EC transactions to replay", replay.entries.len());
+    *REPLAY.lock().unwrap() = Some(replay);
+    Ok(())
+}
+
+pub(crate) fn replay(kind: TraceKind, id: u16, version: u16, request: &[u8]) -> EcResult<Vec<u8>> {
+    let mut replay = REPLAY.lock().unwrap();
+    let replay = replay
+        .as_mut()
+        .ok_or_else(|| EcError::DriverUnavailable("No EC trace loaded".to_string()))?;
+    replay
+        .find(kind, id, version, request)
+        .map(TraceEntry::to_result)
+        .unwrap_or_else(|| {
+            Err(EcError::DeviceError(format!(
+                "No recorded response for {:?} 0x{:04X} version {}",
+                kind, id, version
+            )))
+        })
+}
+
+/// Run `f` with an EC that answers from the trace
+///
+/// The replay driver answers from a single global trace, so tests that use
+/// it have to take turns.
+#[cfg(test)]
+pub(crate) fn with_replay<T>(path: &std::path::Path, f: impl FnOnce(&super::CrosEc) -> T) -> T {
+    static TURN: Mutex<()> = Mutex::new(());
+    let _turn = TURN.lock().unwrap_or_else(|err| err.into_inner());
+    let ec = super::CrosEc::replay(path.to_str().unwrap()).unwrap();
+    f(&ec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_and_replay() {
+        let ok = TraceEntry::new(
+            TraceKind::Command,
+            0x0002,
+            0,
+            &[],
+            &Ok(vec![1, 2, 0xFF]),
+            Duration::from_micros(152),
+        );
+        assert_eq!(ok.to_line(), "cmd 0x0002 0 ok 152 - 0102ff");
+        let failed = TraceEntry::new(
+            TraceKind::Command,
+            0x3E03,
+            1,
+            &[0x0A, 0x00],
+            &Err(EcError::Response(EcResponseStatus::InvalidParameter)),
+            Duration::from_micros(88),
+        );
+        assert_eq!(failed.to_line(), "cmd 0x3E03 1 ec=3 88 0a00 -");
+        assert_eq!(TraceEntry::parse(&ok.to_line()), Some(ok.clone()));
+        assert_eq!(TraceEntry::parse(&failed.to_line()), Some(failed.clone()));
+        assert_eq!(TraceEntry::parse("cmd 0x0002 0 ok 152 - 0102f"), None);
+
+        let trace = format!(
+            "{}\n{}\n{}\nmem 0x0020 2 ok 12 - 4543\n",
+            HEADER,
+            ok.to_line(),
+            failed.to_line()
+        );
+        let mut replay = Replay::parse(&trace).unwrap();
+        let entry = replay.find(TraceKind::Memory, 0x20, 2, &[]).unwrap();
+        assert_eq!(entry.to_result(), Ok(vec![0x45, 0x43]));
+        // Wraps around to the beginning
+        let entry = replay.find(TraceKind::Command, 0x3E03, 1, &[0x0A, 0x00]);
+        assert_eq!(
+            entry.unwrap().to_result(),
+            Err(EcError::Response(EcResponseStatus::InvalidParameter))
+        );
+        // Request bytes have to match
+        assert!(replay
+            .find(TraceKind::Command, 0x3E03, 1, &[0x0B])
+            .is_none());
+
+        assert!(Replay::parse("cmd 0x0002 0").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use num::FromPrimitive;
 use std::sync::{Arc, Mutex};
 /// Implementation to talk to DHowett's Windows Chrome EC driver
+/// or the newer open-source driver with the same interface
 #[allow(unused_imports)]
 use windows::{
     core::*,
@@ -25,15 +26,27 @@ lazy_static! {
     static ref DEVICE: Arc<Mutex<Option<DevHandle>>> = Arc::new(Mutex::new(None));
 }
 
-fn init() {
+fn init() -> EcResult<()> {
     let mut device = DEVICE.lock().unwrap();
     if (*device).is_some() {
-        return;
+        return Ok(());
     }
 
-    let path = w!(r"\\.\GLOBALROOT\Device\CrosEC");
-    unsafe {
-        *device = Some(DevHandle(
+    // Both drivers implement the same IOCTL interface, they only differ in
+    // the name of the device node.
+    // The first one is from DHowett's original driver, the second one from the
+    // newer open-source driver that also works when the original one reports
+    // that it is "not enabled".
+    let paths = [
+        (
+            w!(r"\\.\GLOBALROOT\Device\CrosEC"),
+            r"\\.\GLOBALROOT\Device\CrosEC",
+        ),
+        (w!(r"\\.\CrosEC"), r"\\.\CrosEC"),
+    ];
+    let mut errors = vec![];
+    for (path, name) in paths {
+        let handle = unsafe {
             CreateFileW(
                 path,
                 FILE_GENERIC_READ.0 | FILE_GENERIC_WRITE.0,
@@ -43,13 +56,28 @@ fn init() {
                 FILE_FLAGS_AND_ATTRIBUTES(0),
                 None,
             )
-            .unwrap(),
-        ));
+        };
+        match handle {
+            Ok(handle) => {
+                debug!("Opened CrosEC device {}", name);
+                *device = Some(DevHandle(handle));
+                return Ok(());
+            }
+            Err(err) => {
+                debug!("Failed to open {}: {:?}", name, err);
+                errors.push(format!("{}: {:?}", name, err));
+            }
+        }
     }
+
+    Err(EcError::DriverUnavailable(format!(
+        "Failed to open CrosEC device. Is the driver installed? {}",
+        errors.join(", ")
+    )))
 }
 
 pub fn read_memory(offset: u16, length: u16) -> EcResult<Vec<u8>> {
-    init();
+    init()?;
     let mut rm = CrosEcReadMem {
         offset: offset as u32,
         bytes: length as u32,
@@ -59,13 +87,13 @@ pub fn read_memory(offset: u16, length: u16) -> EcResult<Vec<u8>> {
     let const_ptr = &mut rm as *const _ as *const ::core::ffi::c_void;
     let mut_ptr = &mut rm as *mut _ as *mut ::core::ffi::c_void;
     let ptr_size = std::mem::size_of::<CrosEcReadMem>() as u32;
-    let retb: u32 = 0;
+    let mut returned: u32 = 0;
     unsafe {
         let device = DEVICE.lock().unwrap();
         let device = if let Some(device) = *device {
             device
         } else {
-            return EcResult::Err(EcError::DeviceError("No EC device".to_string()));
+            return EcResult::Err(EcError::DriverUnavailable("No EC device".to_string()));
         };
         DeviceIoControl(
             device.0,
@@ -74,17 +102,21 @@ pub fn read_memory(offset: u16, length: u16) -> EcResult<Vec<u8>> {
             ptr_size,
             Some(mut_ptr),
             ptr_size,
-            Some(retb as *mut u32),
+            Some(&mut returned as *mut u32),
             None,
         )
-        .unwrap();
+        .map_err(|err| EcError::Io(format!("Failed to read EC memory: {:?}", err)))?;
     }
-    let output = &rm.buffer[..(length as usize)];
+
+    // The driver returns how much of the struct it filled in, including the
+    // offset and size in front of the data. Short reads are retried by the caller.
+    let available = (returned as usize).saturating_sub(CROSEC_READMEM_HEADER_LEN);
+    let output = &rm.buffer[..available.min(length as usize)];
     Ok(output.to_vec())
 }
 
 pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<Vec<u8>> {
-    init();
+    init()?;
 
     let mut cmd = CrosEcCommand {
         version: command_version as u32,
@@ -110,7 +142,7 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
         let device = if let Some(device) = *device {
             device
         } else {
-            return EcResult::Err(EcError::DeviceError("No EC device".to_string()));
+            return EcResult::Err(EcError::DriverUnavailable("No EC device".to_string()));
         };
         DeviceIoControl(
             device.0,
@@ -122,7 +154,7 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
             Some(&mut returned as *mut u32),
             None,
         )
-        .unwrap();
+        .map_err(|err| EcError::Io(format!("Failed to send EC command: {:?}", err)))?;
     }
 
     match FromPrimitive::from_u32(cmd.result) {
@@ -131,14 +163,29 @@ pub fn send_command(command: u16, command_version: u8, data: &[u8]) -> EcResult<
         Some(status) => return Err(EcError::Response(status)),
     }
 
-    // TODO: Figure out why that's sometimes bigger
-    let end = std::cmp::min(returned, CROSEC_CMD_MAX_REQUEST as u32);
-
-    let out_buffer = &cmd.buffer[..(end as usize)];
-    Ok(out_buffer.to_vec())
+    // Like with reading memory, the size includes the header in front of the response
+    let Some(response_len) = (returned as usize).checked_sub(CROSEC_CMD_HEADER_LEN) else {
+        return Err(EcError::ProtocolMismatch(format!(
+            "Driver returned {} bytes, less than the header",
+            returned
+        )));
+    };
+    cmd.buffer
+        .get(..response_len)
+        .map(|response| response.to_vec())
+        .ok_or_else(|| {
+            EcError::ProtocolMismatch(format!(
+                "Driver returned {} bytes, buffer only has {}",
+                response_len, CROSEC_CMD_MAX_REQUEST
+            ))
+        })
 }
 
 const CROSEC_CMD_MAX_REQUEST: usize = 0x100;
+/// Size of the fields of `CrosEcCommand` in front of the buffer
+const CROSEC_CMD_HEADER_LEN: usize = 5 * std::mem::size_of::<u32>();
+/// Size of the fields of `CrosEcReadMem` in front of the buffer
+const CROSEC_READMEM_HEADER_LEN: usize = 2 * std::mem::size_of::<u32>();
 
 const FILE_DEVICE_CROS_EMBEDDED_CONTROLLER: u32 = 0x80EC;
 
@@ -1,16 +1,21 @@
 //! Module to factor out commandline interaction
 //! This way we can use it in the regular OS commandline tool on Linux and Windows,
 //! as well as on the UEFI shell tool.
-use clap::Parser;
+use clap::{ArgGroup, CommandFactory, Parser, ValueEnum};
 
-use crate::chromium_ec::CrosEcDriverType;
+use crate::chromium_ec::{CrosEcDriverType, EcFlashRegion};
 use crate::commandline::{
-    Cli, ConsoleArg, FpBrightnessArg, HardwareDeviceType, InputDeckModeArg, RebootEcArg,
+    BatteryCutoffArg, Cli, ConsoleArg, EcSnapshotArg, ExitCode, FpBrightnessArg,
+    HardwareDeviceType, InputDeckModeArg, KbMatrixArg, PlatformArg, RebootEcArg, TabletModeArg,
+    TypecRoleArg, UnitsArg, WakeSourcesArg, WindowsServiceArg,
 };
+use crate::led;
+use crate::util::ConfigFile;
 
 /// Swiss army knife for Framework laptops
 #[derive(Parser)]
 #[command(arg_required_else_help = true)]
+#[command(group(ArgGroup::new("watchable").args(["power", "intrusion"]).multiple(true)))]
 struct ClapCli {
     #[command(flatten)]
     verbosity: clap_verbosity_flag::Verbosity,
@@ -31,12 +36,16 @@ struct ClapCli {
     #[arg(long)]
     esrt: bool,
 
+    /// Display UEFI variables about capsule update results and boot settings
+    #[arg(long)]
+    uefi_vars: bool,
+
     // Device type to compare_version string with version string on device
     #[clap(value_enum)]
     #[arg(long)]
     device: Option<HardwareDeviceType>,
 
-    // version to compare with
+    // version to compare with, optionally prefixed with an operator like >=
     #[arg(long)]
     compare_version: Option<String>,
 
@@ -44,18 +53,80 @@ struct ClapCli {
     #[arg(long)]
     power: bool,
 
+    /// With --power or --intrusion, keep sampling every MS milliseconds
+    #[clap(requires("watchable"))]
+    #[arg(long, value_name = "MS")]
+    watch: Option<u64>,
+
+    /// With --power --watch, append every sample to a CSV file
+    #[clap(requires("watch"))]
+    #[arg(long, value_name = "FILE")]
+    csv: Option<std::path::PathBuf>,
+
     /// Print thermal information (Temperatures and Fan speed)
     #[arg(long)]
     thermal: bool,
 
+    /// Log all temperatures, fan speeds and duty cycles as CSV every MS milliseconds, to FILE or stdout
+    #[clap(num_args = 1..=2, value_names = ["MS", "FILE"])]
+    #[arg(long)]
+    thermal_log: Vec<String>,
+
+    /// Show status of the expansion bay (Framework 16 only), add -v for power, temperatures and fans
+    #[arg(long)]
+    expansion_bay: bool,
+
     /// Print sensor information (ALS, G-Sensor)
     #[arg(long)]
     sensors: bool,
 
+    /// Calibrate a motion sensor, while the system lies still on a flat surface
+    #[arg(long, value_name = "SENSOR")]
+    sensor_calibrate: Option<u8>,
+
+    /// Get or set calibration offsets of a motion sensor
+    #[clap(num_args = 1..=4, value_names = ["SENSOR", "X", "Y", "Z"], allow_negative_numbers = true)]
+    #[arg(long)]
+    sensor_offsets: Vec<i16>,
+
     /// Show information about USB-C PD ports
     #[arg(long)]
     pdports: bool,
 
+    /// Show the last N (default 10) times a charger was attached or detached
+    #[arg(long, value_name = "N")]
+    ac_history: Option<Option<usize>>,
+
+    /// Force power role of a USB-C port [possible ROLE values: sink, source, drp]
+    #[clap(number_of_values = 2, value_names = ["PORT", "ROLE"])]
+    #[arg(long)]
+    typec_role: Vec<String>,
+
+    /// Limit the voltage requested from USB-C chargers, in mV
+    #[arg(long, value_name = "MV")]
+    pd_max_voltage: Option<u16>,
+
+    /// Remove the voltage limit and request new contracts from USB-C chargers
+    #[clap(conflicts_with("pd_max_voltage"))]
+    #[arg(long)]
+    pd_renegotiate: bool,
+
+    /// Test which voltages the charger on a USB-C port supplies, and whether it's stable
+    #[arg(long, value_name = "PORT")]
+    pps_test: Option<u8>,
+
+    /// Show Thunderbolt/USB4 routers (host, retimers, docks) and their firmware versions
+    #[arg(long)]
+    thunderbolt: bool,
+
+    /// Show USB-C connector status through the UCSI interface of the EC, to debug the OS UCSI driver
+    #[arg(long)]
+    ucsi: bool,
+
+    /// Show vendor, product and advertised capabilities of the attached chargers
+    #[arg(long)]
+    charger_info: bool,
+
     /// Show info from SMBIOS (Only on UEFI)
     #[arg(long)]
     info: bool,
@@ -72,6 +143,10 @@ struct ClapCli {
     #[arg(long, value_name = "UPDATE_BIN")]
     dp_hdmi_update: Option<std::path::PathBuf>,
 
+    /// Only update the card plugged in at this USB location, as shown by --dp-hdmi-info
+    #[arg(long, value_name = "LOCATION", requires = "dp_hdmi_update")]
+    dp_hdmi_card: Option<String>,
+
     /// Show details about connected Audio Expansion Cards (Needs root privileges)
     #[arg(long)]
     audio_card_info: bool,
@@ -100,6 +175,25 @@ struct ClapCli {
     #[arg(long)]
     ho2_capsule: Option<std::path::PathBuf>,
 
+    /// Extract EC, PD and BIOS firmware from the H2O capsule into a directory
+    #[clap(requires("ho2_capsule"))]
+    #[arg(long, value_name = "DIR")]
+    extract: Option<std::path::PathBuf>,
+
+    /// Detect which kind of firmware a file is (EC, PD, capsule, ...) and show its versions
+    #[arg(long)]
+    identify: Option<std::path::PathBuf>,
+
+    /// Wrap a payload in a UEFI capsule. With the Windows UX GUID the payload is a BMP
+    #[clap(number_of_values = 3, value_names = ["GUID", "PAYLOAD", "OUT"])]
+    #[arg(long)]
+    make_capsule: Vec<String>,
+
+    /// Build a Windows UX capsule from a PNG or BMP image, placed to fit the display
+    #[clap(number_of_values = 2, value_names = ["IMAGE", "OUT"])]
+    #[arg(long)]
+    make_ux_capsule: Vec<String>,
+
     /// Dump EC flash contents
     #[arg(long)]
     dump_ec_flash: Option<std::path::PathBuf>,
@@ -116,10 +210,44 @@ struct ClapCli {
     #[arg(long)]
     flash_rw_ec: Option<std::path::PathBuf>,
 
+    /// Show the regions of the EC flash, depending on the EC chip
+    #[arg(long)]
+    flash_layout: bool,
+
+    /// Erase a region of the EC flash, to recover from bad flashes (requires --force, RO can't be erased)
+    #[arg(long, value_name = "REGION")]
+    erase_ec_region: Option<EcFlashRegion>,
+
     /// Show status of intrusion switch
     #[arg(long)]
     intrusion: bool,
 
+    /// Reset intrusion switch status and counters, e.g. after servicing (requires --force)
+    #[arg(long)]
+    intrusion_clear: bool,
+
+    /// With --intrusion --watch, exit as soon as the chassis is opened
+    #[clap(requires("watch"))]
+    #[arg(long)]
+    exit_on_open: bool,
+
+    /// With --intrusion --watch, run a command whenever the chassis is opened or closed
+    #[clap(requires("watch"))]
+    #[arg(long, value_name = "PATH")]
+    hook: Option<std::path::PathBuf>,
+
+    /// Allow operations that can't be undone, or downgrades. Give twice for dangerous ones
+    #[arg(long, action = clap::ArgAction::Count)]
+    force: u8,
+
+    /// Check everything before flashing, but don't write anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Disconnect battery until AC is connected, e.g. for storage (requires --force)
+    #[arg(long, value_name = "MODE")]
+    battery_cutoff: Option<Option<BatteryCutoffArg>>,
+
     /// Show status of the input modules (Framework 16 only)
     #[arg(long)]
     inputmodules: bool,
@@ -132,23 +260,80 @@ struct ClapCli {
     #[arg(long)]
     charge_limit: Option<Option<u8>>,
 
-    /// Get GPIO value by name
+    /// Get or set how much current the charger draws from the power source, in mA
+    #[arg(long, value_name = "MA")]
+    charger_input_limit: Option<Option<u32>>,
+
+    /// List supported BIOS settings, or get or set one of them until the next boot
+    #[clap(num_args = 0..=2, value_names = ["NAME", "VALUE"])]
+    #[arg(long)]
+    bios_setting: Option<Vec<String>>,
+
+    /// List the charger parameters like voltage and current, or get or set one of them
+    #[clap(num_args = 0..=2, value_names = ["NAME", "VALUE"])]
+    #[arg(long)]
+    charge_param: Option<Vec<String>>,
+
+    /// List which events wake the system from sleep, or enable or disable one [possible ACTION values: list, enable, disable]
+    #[clap(num_args = 0..=2, value_names = ["ACTION", "SOURCE"])]
+    #[arg(long)]
+    wake_sources: Option<Vec<String>>,
+
+    /// Get GPIO value by name, or list the known GPIOs of this platform
+    #[arg(long)]
+    get_gpio: Option<Option<String>>,
+
+    /// Explain what the GPIOs of --get-gpio are for and which level is expected
     #[arg(long)]
-    get_gpio: Option<String>,
+    annotated: bool,
+
+    /// Set GPIO value by name (requires --force twice, no GPIO is known to be safe)
+    #[clap(number_of_values = 2, value_names = ["NAME", "VALUE"])]
+    #[arg(long)]
+    set_gpio: Vec<String>,
 
     /// Get or set fingerprint LED brightness
     #[arg(long)]
     fp_brightness: Option<Option<FpBrightnessArg>>,
 
+    /// Play a pattern on the power button LED, e.g. 'white:500,off:500*3' or 'amber@50:200,off:200*5'
+    #[arg(long, value_name = "SPEC")]
+    fp_led_pattern: Option<String>,
+
+    /// List LEDs and their colors, or set one to a color, off or auto. IDs: battery, power, adapter, left, right.
+    /// For example turn the left charging LED off at night with 'left off', and back with 'left auto'
+    #[clap(num_args = 0..=2, value_names = ["ID", "SETTING"])]
+    #[arg(long)]
+    led: Option<Vec<String>>,
+
     /// Set keyboard backlight percentage or get, if no value provided
     #[arg(long)]
     kblight: Option<Option<u8>>,
 
+    /// Set fan duty cycle (0-100%), for all fans or only the one with FANID
+    #[clap(num_args = 1..=2, value_names = ["FANID", "PERCENT"])]
+    #[arg(long)]
+    fansetduty: Vec<u32>,
+
+    /// Turn on automatic fan speed control, for all fans or only the one with FANID
+    #[arg(long, value_name = "FANID")]
+    autofanctrl: Option<Option<u8>>,
+
     /// Get EC console, choose whether recent or to follow the output
     #[clap(value_enum)]
     #[arg(long)]
     console: Option<ConsoleArg>,
 
+    /// With --console follow, stop after SECS seconds
+    #[clap(requires("console"))]
+    #[arg(long, value_name = "SECS")]
+    duration: Option<u64>,
+
+    /// With --console follow, stop after a line that matches REGEX
+    #[clap(requires("console"))]
+    #[arg(long, value_name = "REGEX")]
+    until: Option<String>,
+
     /// Control EC RO/RW jump
     #[clap(value_enum)]
     #[arg(long)]
@@ -163,6 +348,27 @@ struct ClapCli {
     #[arg(long)]
     driver: Option<CrosEcDriverType>,
 
+    /// How long to keep retrying EC commands that failed temporarily, in milliseconds
+    #[arg(long, value_name = "MS")]
+    ec_timeout: Option<u64>,
+
+    /// Record all communication with the EC into a file, e.g. to attach to a bug report
+    #[arg(long, value_name = "FILE")]
+    trace_ec: Option<std::path::PathBuf>,
+
+    /// Don't talk to the EC, answer from a file recorded with --trace-ec instead
+    #[arg(long, value_name = "FILE", conflicts_with = "driver")]
+    replay_ec: Option<std::path::PathBuf>,
+
+    /// Hide serial numbers in all output, to share it publicly. Versions and revisions are kept
+    #[arg(long)]
+    redact: bool,
+
+    /// Show temperatures in Celsius (metric) or Fahrenheit (imperial)
+    #[clap(value_enum)]
+    #[arg(long)]
+    units: Option<UnitsArg>,
+
     /// Specify I2C addresses of the PD chips (Advanced)
     #[clap(number_of_values = 2, requires("pd_ports"), requires("has_mec"))]
     #[arg(long)]
@@ -178,14 +384,147 @@ struct ClapCli {
     #[arg(long)]
     has_mec: Option<bool>,
 
+    /// Override platform detection, e.g. on boards with missing SMBIOS information (Advanced)
+    #[clap(value_enum, conflicts_with("pd_addrs"))]
+    #[arg(long)]
+    platform: Option<PlatformArg>,
+
     /// Run self-test to check if interaction with EC is possible
     #[arg(long, short)]
     test: bool,
+
+    /// Write versions, self-test, power, thermal and more into one Markdown or HTML file
+    #[arg(long, value_name = "FILE.md|FILE.html")]
+    report: Option<std::path::PathBuf>,
+
+    /// Watch for hardware events (AC, lid, chassis, privacy switches, expansion bay, tablet mode, charge limit), optionally run a hook for each
+    #[arg(long, value_name = "HOOK")]
+    watch_events: Option<Option<std::path::PathBuf>>,
+
+    /// Run as daemon with a D-Bus service for the desktop, and watch for hardware events like --watch-events (Linux only)
+    #[arg(long, value_name = "HOOK")]
+    daemon: Option<Option<std::path::PathBuf>>,
+
+    /// Show a tray icon to change settings through the daemon (Linux only)
+    #[arg(long)]
+    tray: bool,
+
+    /// Adjust keyboard backlight automatically: PERCENT on AC, off on battery or in bright light
+    #[arg(long, value_name = "PERCENT")]
+    kblight_auto: Option<Option<u8>>,
+
+    /// Capture hinge angle, lid/tablet GPIOs and tablet mode for 10s, to debug tablet mode detection
+    #[arg(long)]
+    tablet_debug: bool,
+
+    /// Report whenever the system enters or leaves tablet mode, and at which hinge angle
+    #[arg(long)]
+    tablet_watch: bool,
+
+    /// Switch between tablet and laptop mode, or force one. auto goes back to the hinge angle
+    #[arg(long, value_name = "MODE")]
+    tablet_mode_toggle: Option<Option<TabletModeArg>>,
+
+    /// Suspend for MINUTES and report how much battery was drained (needs root privileges on Linux)
+    #[arg(long, value_name = "MINUTES")]
+    suspend_test: Option<u64>,
+
+    /// Interactive prompt to run several commands, without initializing again for each
+    #[arg(long)]
+    shell: bool,
+
+    /// Run as privileged helper, to answer requests from unprivileged users (Linux only)
+    #[arg(long)]
+    helper: bool,
+
+    /// Print udev rules that let regular users access Expansion Cards and install them (Linux only)
+    #[arg(long)]
+    install_udev_rules: bool,
+
+    /// Install or uninstall the service that logs hardware events and telemetry to the event log (Windows only)
+    #[arg(long, value_name = "ACTION")]
+    windows_service: Option<WindowsServiceArg>,
+
+    /// Measure host command latency of the selected driver, optionally provide number of commands
+    #[arg(long)]
+    bench_ec: Option<Option<usize>>,
+
+    /// Apply settings from the config file, like the charge limit and fan duty. Useful to run at boot and after resume
+    #[arg(long)]
+    apply_config: bool,
+
+    /// Save the EC settings to a file, or restore them, e.g. after reflashing [possible ACTION values: save, restore]
+    #[clap(number_of_values = 2, value_names = ["ACTION", "FILE"])]
+    #[arg(long)]
+    ec_snapshot: Vec<String>,
+
+    /// Save the scancodes of the keyboard matrix to a file, or restore them, e.g. after reflashing [possible ACTION values: dump, restore]
+    #[clap(number_of_values = 2, value_names = ["ACTION", "FILE"])]
+    #[arg(long)]
+    kb_matrix: Vec<String>,
+
+    /// Print shell completions for the given shell
+    #[arg(long, value_name = "SHELL")]
+    generate_completions: Option<clap_complete::Shell>,
+
+    /// Print man page in roff format
+    #[arg(long)]
+    generate_man: bool,
+}
+
+/// Name of the binary, as the user calls it
+const BIN_NAME: &str = "framework_tool";
+
+/// Print completions or man page, for packagers to install
+fn generate(completions: Option<clap_complete::Shell>, man: bool) {
+    let mut cmd = ClapCli::command().name(BIN_NAME);
+    if let Some(shell) = completions {
+        clap_complete::generate(shell, &mut cmd, BIN_NAME, &mut std::io::stdout());
+    }
+    if man {
+        if let Err(err) = clap_mangen::Man::new(cmd).render(&mut std::io::stdout()) {
+            println!("Failed to generate man page: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Parse a value from the config file, the same way as clap parses the flag
+fn config_value<T: ValueEnum>(name: &str, value: &Option<String>) -> Option<T> {
+    let value = value.as_deref()?;
+    match T::from_str(value, true) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            println!("Invalid {} in config file: {}", name, err);
+            None
+        }
+    }
 }
 
 /// Parse a list of commandline arguments and return the struct
 pub fn parse(args: &[String]) -> Cli {
-    let args = ClapCli::parse_from(args);
+    try_parse(args).unwrap_or_else(|err| err.exit())
+}
+
+/// Like [`parse`], but return invalid arguments or a request for help as error, instead of exiting
+pub fn try_parse(args: &[String]) -> Result<Cli, clap::Error> {
+    let args = ClapCli::try_parse_from(args)?;
+    if args.generate_completions.is_some() || args.generate_man {
+        generate(args.generate_completions, args.generate_man);
+        std::process::exit(0);
+    }
+    let config = ConfigFile::load();
+
+    // Flags take precedence over the config file, -v and -q change the default of Error
+    let mut verbosity = args.verbosity.log_level_filter();
+    if verbosity == log::LevelFilter::Error {
+        if let Some(level) = &config.log_level {
+            match level.parse() {
+                Ok(level) => verbosity = level,
+                Err(_) => println!("Invalid log_level in config file: {}", level),
+            }
+        }
+    }
 
     let pd_addrs = match args.pd_addrs.len() {
         2 => Some((args.pd_addrs[0], args.pd_addrs[1])),
@@ -212,23 +551,140 @@ pub fn parse(args: &[String]) -> Cli {
         }
     };
 
-    Cli {
-        verbosity: args.verbosity.log_level_filter(),
+    let typec_role = match args.typec_role.as_slice() {
+        [port, role] => match (port.parse::<u8>(), TypecRoleArg::from_str(role, true)) {
+            (Ok(port), Ok(role)) => Some((port, role)),
+            _ => {
+                println!(
+                    "Invalid values for --typec-role: '{} {}'. Must be port number and sink, source or drp.",
+                    port, role
+                );
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+        _ => None,
+    };
+    let ec_snapshot = match args.ec_snapshot.as_slice() {
+        [action, path] => match EcSnapshotArg::from_str(action, true) {
+            Ok(action) => Some((action, path.clone())),
+            Err(_) => {
+                println!(
+                    "Invalid value for --ec-snapshot: '{}'. Must be save or restore.",
+                    action
+                );
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+        _ => None,
+    };
+    let kb_matrix = match args.kb_matrix.as_slice() {
+        [action, path] => match KbMatrixArg::from_str(action, true) {
+            Ok(action) => Some((action, path.clone())),
+            Err(_) => {
+                println!(
+                    "Invalid value for --kb-matrix: '{}'. Must be dump or restore.",
+                    action
+                );
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+        _ => None,
+    };
+    let wake_sources = args.wake_sources.map(|x| match x.as_slice() {
+        [] => (WakeSourcesArg::List, None),
+        [action, source @ ..] => match WakeSourcesArg::from_str(action, true) {
+            Ok(action) => (action, source.first().cloned()),
+            Err(_) => {
+                println!(
+                    "Invalid value for --wake-sources: '{}'. Must be list, enable or disable.",
+                    action
+                );
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+    });
+    let led = match args.led.map(|x| led::parse_led_args(&x)).transpose() {
+        Ok(led) => led,
+        Err(err) => {
+            println!("Invalid values for --led: {}", err);
+            std::process::exit(ExitCode::Usage.into());
+        }
+    };
+    let set_gpio = match args.set_gpio.as_slice() {
+        [name, value] => match value.as_str() {
+            "0" => Some((name.clone(), false)),
+            "1" => Some((name.clone(), true)),
+            _ => {
+                println!("Invalid value for --set-gpio: '{}'. Must be 0 or 1.", value);
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+        _ => None,
+    };
+    let sensor_offsets = match args.sensor_offsets.as_slice() {
+        [] => None,
+        [sensor] => Some((u8::try_from(*sensor).unwrap_or(u8::MAX), None)),
+        [sensor, x, y, z] => Some((u8::try_from(*sensor).unwrap_or(u8::MAX), Some([*x, *y, *z]))),
+        _ => {
+            println!(
+                "--sensor-offsets requires the sensor index, optionally followed by X, Y and Z"
+            );
+            std::process::exit(ExitCode::Usage.into());
+        }
+    };
+    let thermal_log = match args.thermal_log.as_slice() {
+        [] => None,
+        [interval, path @ ..] => match interval.parse::<u64>() {
+            Ok(interval) if interval > 0 => Some((interval, path.first().cloned())),
+            _ => {
+                println!(
+                    "Invalid value for --thermal-log: '{}'. Must be the interval in milliseconds.",
+                    interval
+                );
+                std::process::exit(ExitCode::Usage.into());
+            }
+        },
+    };
+    let fansetduty = match args.fansetduty.as_slice() {
+        [percent] => Some((None, *percent)),
+        // Out of range indices are rejected when running the command
+        [fan, percent] => Some((Some(u8::try_from(*fan).unwrap_or(u8::MAX)), *percent)),
+        _ => None,
+    };
+
+    Ok(Cli {
+        verbosity,
         versions: args.versions,
         version: args.version,
         features: args.features,
         esrt: args.esrt,
+        uefi_vars: args.uefi_vars,
         device: args.device,
         compare_version: args.compare_version,
         power: args.power,
+        watch: args.watch,
+        csv: args.csv.map(|x| x.into_os_string().into_string().unwrap()),
         thermal: args.thermal,
+        thermal_log,
+        expansion_bay: args.expansion_bay,
         sensors: args.sensors,
+        sensor_calibrate: args.sensor_calibrate,
+        sensor_offsets,
         pdports: args.pdports,
+        ac_history: args.ac_history,
+        typec_role,
+        pd_max_voltage: args.pd_max_voltage,
+        pd_renegotiate: args.pd_renegotiate,
+        pps_test: args.pps_test,
+        thunderbolt: args.thunderbolt,
+        ucsi: args.ucsi,
+        charger_info: args.charger_info,
         pd_info: args.pd_info,
         dp_hdmi_info: args.dp_hdmi_info,
         dp_hdmi_update: args
             .dp_hdmi_update
             .map(|x| x.into_os_string().into_string().unwrap()),
+        dp_hdmi_card: args.dp_hdmi_card,
         audio_card_info: args.audio_card_info,
         privacy: args.privacy,
         pd_bin: args
@@ -241,9 +697,23 @@ pub fn parse(args: &[String]) -> Cli {
             .capsule
             .map(|x| x.into_os_string().into_string().unwrap()),
         dump: args.dump.map(|x| x.into_os_string().into_string().unwrap()),
+        identify: args
+            .identify
+            .map(|x| x.into_os_string().into_string().unwrap()),
+        make_capsule: match args.make_capsule.as_slice() {
+            [guid, payload, out] => Some((guid.clone(), payload.clone(), out.clone())),
+            _ => None,
+        },
+        make_ux_capsule: match args.make_ux_capsule.as_slice() {
+            [image, out] => Some((image.clone(), out.clone())),
+            _ => None,
+        },
         ho2_capsule: args
             .ho2_capsule
             .map(|x| x.into_os_string().into_string().unwrap()),
+        extract: args
+            .extract
+            .map(|x| x.into_os_string().into_string().unwrap()),
         dump_ec_flash: args
             .dump_ec_flash
             .map(|x| x.into_os_string().into_string().unwrap()),
@@ -256,21 +726,91 @@ pub fn parse(args: &[String]) -> Cli {
         flash_rw_ec: args
             .flash_rw_ec
             .map(|x| x.into_os_string().into_string().unwrap()),
+        flash_layout: args.flash_layout,
+        erase_ec_region: args.erase_ec_region,
         intrusion: args.intrusion,
+        intrusion_clear: args.intrusion_clear,
+        exit_on_open: args.exit_on_open,
+        hook: args.hook.map(|x| x.into_os_string().into_string().unwrap()),
+        force: args.force,
+        dry_run: args.dry_run,
+        battery_cutoff: args.battery_cutoff,
         inputmodules: args.inputmodules,
         input_deck_mode: args.input_deck_mode,
         charge_limit: args.charge_limit,
+        charger_input_limit: args.charger_input_limit,
+        bios_setting: args.bios_setting.map(|x| match x.as_slice() {
+            [name] => Some((name.clone(), None)),
+            [name, value] => Some((name.clone(), Some(value.clone()))),
+            _ => None,
+        }),
+        charge_param: args.charge_param.map(|x| match x.as_slice() {
+            [name] => Some((name.clone(), None)),
+            [name, value] => Some((name.clone(), Some(value.clone()))),
+            _ => None,
+        }),
+        wake_sources,
         get_gpio: args.get_gpio,
+        annotated: args.annotated,
+        set_gpio,
         fp_brightness: args.fp_brightness,
+        fp_led_pattern: args.fp_led_pattern,
+        led,
         kblight: args.kblight,
+        fansetduty,
+        autofanctrl: args.autofanctrl,
         console: args.console,
+        duration: args.duration,
+        until: args.until,
         reboot_ec: args.reboot_ec,
         hash: args.hash.map(|x| x.into_os_string().into_string().unwrap()),
-        driver: args.driver,
+        driver: args
+            .driver
+            .or_else(|| config_value("driver", &config.driver)),
+        ec_timeout: args.ec_timeout,
+        trace_ec: args
+            .trace_ec
+            .map(|x| x.into_os_string().into_string().unwrap()),
+        replay_ec: args
+            .replay_ec
+            .map(|x| x.into_os_string().into_string().unwrap()),
+        redact: args.redact,
+        units: args.units.or_else(|| config_value("units", &config.units)),
         pd_addrs,
         pd_ports,
         has_mec: args.has_mec,
+        platform: args.platform.or_else(|| {
+            // Explicit PD configuration replaces the platform
+            if pd_addrs.is_none() {
+                config_value("platform", &config.platform)
+            } else {
+                None
+            }
+        }),
+        helper: args.helper,
+        install_udev_rules: args.install_udev_rules,
+        windows_service: args.windows_service,
+        shell: args.shell,
+        watch_events: args
+            .watch_events
+            .map(|x| x.map(|x| x.into_os_string().into_string().unwrap())),
+        daemon: args
+            .daemon
+            .map(|x| x.map(|x| x.into_os_string().into_string().unwrap())),
+        tray: args.tray,
+        kblight_auto: args.kblight_auto,
+        tablet_debug: args.tablet_debug,
+        tablet_watch: args.tablet_watch,
+        tablet_mode_toggle: args.tablet_mode_toggle,
+        suspend_test: args.suspend_test,
         test: args.test,
+        report: args
+            .report
+            .map(|x| x.into_os_string().into_string().unwrap()),
+        bench_ec: args.bench_ec,
+        apply_config: args.apply_config,
+        ec_snapshot,
+        kb_matrix,
         // TODO: Set help. Not very important because Clap handles this by itself
         help: false,
         // UEFI only for now. Don't need to handle
@@ -279,5 +819,5 @@ pub fn parse(args: &[String]) -> Cli {
         paginate: false,
         info: args.info,
         raw_command: vec![],
-    }
+    })
 }
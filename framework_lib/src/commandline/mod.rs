@@ -8,53 +8,91 @@ use alloc::string::String;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use log::Level;
-use num_traits::FromPrimitive;
 
 #[cfg(not(feature = "uefi"))]
 pub mod clap_std;
+mod shell;
 #[cfg(feature = "uefi")]
 pub mod uefi;
 
 #[cfg(not(feature = "uefi"))]
 use std::fs;
-#[cfg(all(not(feature = "uefi"), feature = "std"))]
-use std::io::prelude::*;
 
 #[cfg(feature = "rusb")]
 use crate::audio_card::check_synaptics_fw_version;
+#[cfg(not(feature = "uefi"))]
+use crate::audit;
+use crate::bios_setting;
 use crate::built_info;
 use crate::capsule;
 use crate::capsule_content::{
-    find_bios_version, find_ec_in_bios_cap, find_pd_in_bios_cap, find_retimer_version,
+    find_bios_fv_in_bios_cap, find_bios_version, find_ec_in_bios_cap, find_pd_in_bios_cap,
+    find_retimer_version,
 };
-use crate::ccgx::device::{FwMode, PdController, PdPort};
+use crate::ccgx::device::{PdController, PdPort};
 #[cfg(feature = "hidapi")]
 use crate::ccgx::hid::{check_ccg_fw_version, find_devices, DP_CARD_PID, HDMI_CARD_PID};
 use crate::ccgx::{self, SiliconId::*};
 use crate::chromium_ec;
+use crate::chromium_ec::command::{retry_policy, set_retry_policy, RetryPolicy};
+use crate::chromium_ec::commands::BatteryCutoffFlags;
+use crate::chromium_ec::commands::ChargeStateParam;
 use crate::chromium_ec::commands::DeckStateMode;
-use crate::chromium_ec::commands::FpLedBrightnessLevel;
 use crate::chromium_ec::commands::RebootEcCmd;
+use crate::chromium_ec::commands::TabletModeOverride;
+use crate::chromium_ec::commands::UsbPdControlRole;
+use crate::chromium_ec::commands::{EcLedId, FpLedBrightnessLevel};
 use crate::chromium_ec::EcResponseStatus;
-use crate::chromium_ec::{print_err, EcFlashType};
+use crate::chromium_ec::{print_err, print_err_ref, EcFlashRegion, EcFlashType};
 use crate::chromium_ec::{EcError, EcResult};
-#[cfg(feature = "linux")]
+#[cfg(any(feature = "linux", feature = "windows"))]
 use crate::csme;
+#[cfg(feature = "daemon")]
+use crate::daemon;
+use crate::devices::{self, Devices};
 use crate::ec_binary;
+use crate::ec_snapshot;
 use crate::esrt;
+#[cfg(not(feature = "uefi"))]
+use crate::events;
+use crate::firmware_file::{self, FirmwareFile};
+#[cfg(not(feature = "uefi"))]
+use crate::gpio;
+#[cfg(not(feature = "uefi"))]
+use crate::health_report::{HealthReport, ReportFormat};
+#[cfg(feature = "linux")]
+use crate::helper;
+use crate::kb_matrix;
+use crate::kblight;
+use crate::led;
+use crate::output::{outln, OutputSink, StdoutSink, UntilSink};
 use crate::power;
+use crate::report::{
+    BiosVersionReport, EcVersionReport, PdDetailsReport, PdVersionsReport, SmbiosReport,
+};
 use crate::smbios;
-use crate::smbios::ConfigDigit0;
-use crate::smbios::{dmidecode_string_val, get_smbios, is_framework};
+use crate::smbios::{get_smbios_info, is_framework};
+#[cfg(not(feature = "uefi"))]
+use crate::suspend;
+#[cfg(not(feature = "uefi"))]
+use crate::tablet;
+#[cfg(feature = "linux")]
+use crate::thunderbolt;
+#[cfg(feature = "tray")]
+use crate::tray;
+use crate::ucsi;
+#[cfg(feature = "linux")]
+use crate::udev;
 #[cfg(feature = "uefi")]
 use crate::uefi::enable_page_break;
+use crate::uefi_vars;
+use crate::units::{self, Units};
 use crate::util;
 use crate::util::{Config, Platform};
-#[cfg(feature = "hidapi")]
-use hidapi::HidApi;
+use crate::wake_source;
+#[cfg(feature = "windows")]
+use crate::windows_service;
 use sha2::{Digest, Sha256, Sha384, Sha512};
-//use smbioslib::*;
-use smbioslib::{DefinedStruct, SMBiosInformation};
 
 use crate::chromium_ec::{CrosEc, CrosEcDriverType, HardwareDeviceType};
 
@@ -95,6 +133,57 @@ impl From<FpBrightnessArg> for FpLedBrightnessLevel {
     }
 }
 
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TabletModeArg {
+    /// Detect tablet mode from the hinge angle
+    Auto,
+    Tablet,
+    Laptop,
+}
+impl From<TabletModeArg> for TabletModeOverride {
+    fn from(w: TabletModeArg) -> TabletModeOverride {
+        match w {
+            TabletModeArg::Auto => TabletModeOverride::Default,
+            TabletModeArg::Tablet => TabletModeOverride::ForceTablet,
+            TabletModeArg::Laptop => TabletModeOverride::ForceClamshell,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatteryCutoffArg {
+    Immediate,
+    AtShutdown,
+}
+impl From<BatteryCutoffArg> for BatteryCutoffFlags {
+    fn from(w: BatteryCutoffArg) -> BatteryCutoffFlags {
+        match w {
+            BatteryCutoffArg::Immediate => BatteryCutoffFlags::Immediate,
+            BatteryCutoffArg::AtShutdown => BatteryCutoffFlags::AtShutdown,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TypecRoleArg {
+    Sink,
+    Source,
+    /// Dual role, toggle between sink and source
+    Drp,
+}
+impl From<TypecRoleArg> for UsbPdControlRole {
+    fn from(w: TypecRoleArg) -> UsbPdControlRole {
+        match w {
+            TypecRoleArg::Sink => UsbPdControlRole::ForceSink,
+            TypecRoleArg::Source => UsbPdControlRole::ForceSource,
+            TypecRoleArg::Drp => UsbPdControlRole::ToggleOn,
+        }
+    }
+}
+
 #[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum InputDeckModeArg {
@@ -112,6 +201,129 @@ impl From<InputDeckModeArg> for DeckStateMode {
     }
 }
 
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EcSnapshotArg {
+    Save,
+    Restore,
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KbMatrixArg {
+    Dump,
+    Restore,
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WakeSourcesArg {
+    List,
+    Enable,
+    Disable,
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowsServiceArg {
+    Install,
+    Uninstall,
+    /// Only used by the service control manager to start it
+    Run,
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnitsArg {
+    /// Celsius
+    Metric,
+    /// Fahrenheit
+    Imperial,
+}
+impl From<UnitsArg> for Units {
+    fn from(w: UnitsArg) -> Units {
+        match w {
+            UnitsArg::Metric => Units::Metric,
+            UnitsArg::Imperial => Units::Imperial,
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "uefi"), derive(clap::ValueEnum))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlatformArg {
+    IntelGen11,
+    IntelGen12,
+    IntelGen13,
+    IntelCoreUltra1,
+    Framework13Amd,
+    Framework16,
+    DesktopAmdAi300,
+}
+impl From<PlatformArg> for Platform {
+    fn from(w: PlatformArg) -> Platform {
+        match w {
+            PlatformArg::IntelGen11 => Platform::IntelGen11,
+            PlatformArg::IntelGen12 => Platform::IntelGen12,
+            PlatformArg::IntelGen13 => Platform::IntelGen13,
+            PlatformArg::IntelCoreUltra1 => Platform::IntelCoreUltra1,
+            PlatformArg::Framework13Amd => Platform::Framework13Amd,
+            PlatformArg::Framework16 => Platform::Framework16,
+            PlatformArg::DesktopAmdAi300 => Platform::DesktopAmdAi300,
+        }
+    }
+}
+
+/// Exit code of `run_with_args`, so that scripts can tell why a command failed
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(i32)]
+pub enum ExitCode {
+    Success = 0,
+    /// Command failed, for a reason not covered by the other codes
+    Failure = 1,
+    /// Invalid combination of arguments, or only the help was printed
+    Usage = 2,
+    /// Could not communicate with the EC
+    EcUnreachable = 3,
+    /// Not allowed to access the EC, usually have to be root/administrator
+    Permission = 4,
+    /// Firmware read back after flashing doesn't match the file
+    FlashVerify = 5,
+    /// Command not supported by the system or its firmware
+    Unsupported = 6,
+}
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> i32 {
+        code as i32
+    }
+}
+impl From<&EcError> for ExitCode {
+    fn from(err: &EcError) -> ExitCode {
+        match err.root_cause() {
+            EcError::DeviceError(_)
+            | EcError::DriverUnavailable(_)
+            | EcError::Io(_)
+            | EcError::Timeout(_) => ExitCode::EcUnreachable,
+            EcError::Permission(_) => ExitCode::Permission,
+            EcError::VerifyFailed(_) => ExitCode::FlashVerify,
+            EcError::Response(EcResponseStatus::InvalidCommand) | EcError::Unsupported(_) => {
+                ExitCode::Unsupported
+            }
+            _ => ExitCode::Failure,
+        }
+    }
+}
+
+/// Print the error, if there is one, and turn the result into an exit code
+fn exit_code<T>(res: EcResult<T>) -> i32 {
+    print_err_ref(&res);
+    match &res {
+        Ok(_) => ExitCode::Success,
+        Err(err) => ExitCode::from(err),
+    }
+    .into()
+}
+
 /// Shadows `clap_std::ClapCli` with extras for UEFI
 ///
 /// The UEFI commandline currently doesn't use clap, so we need to shadow the struct.
@@ -123,41 +335,114 @@ pub struct Cli {
     pub version: bool,
     pub features: bool,
     pub esrt: bool,
+    pub uefi_vars: bool,
     pub device: Option<HardwareDeviceType>,
     pub compare_version: Option<String>,
     pub power: bool,
+    pub watch: Option<u64>,
+    pub csv: Option<String>,
     pub thermal: bool,
+    /// Interval in ms and optionally a CSV file to write to
+    pub thermal_log: Option<(u64, Option<String>)>,
+    pub expansion_bay: bool,
     pub sensors: bool,
+    pub sensor_calibrate: Option<u8>,
+    /// Sensor index and the offset for X, Y and Z to set, if any
+    pub sensor_offsets: Option<(u8, Option<[i16; 3]>)>,
     pub pdports: bool,
+    pub ac_history: Option<Option<usize>>,
+    pub typec_role: Option<(u8, TypecRoleArg)>,
+    pub pd_max_voltage: Option<u16>,
+    pub pd_renegotiate: bool,
+    pub pps_test: Option<u8>,
+    pub thunderbolt: bool,
+    pub ucsi: bool,
+    pub charger_info: bool,
     pub privacy: bool,
     pub pd_info: bool,
     pub dp_hdmi_info: bool,
     pub dp_hdmi_update: Option<String>,
+    pub dp_hdmi_card: Option<String>,
     pub audio_card_info: bool,
     pub pd_bin: Option<String>,
     pub ec_bin: Option<String>,
     pub capsule: Option<String>,
     pub dump: Option<String>,
     pub ho2_capsule: Option<String>,
+    pub identify: Option<String>,
+    /// GUID, payload file and output file
+    pub make_capsule: Option<(String, String, String)>,
+    /// Image and output file
+    pub make_ux_capsule: Option<(String, String)>,
+    pub extract: Option<String>,
     pub dump_ec_flash: Option<String>,
     pub flash_ec: Option<String>,
     pub flash_ro_ec: Option<String>,
     pub flash_rw_ec: Option<String>,
+    pub flash_layout: bool,
+    pub erase_ec_region: Option<EcFlashRegion>,
     pub driver: Option<CrosEcDriverType>,
+    pub ec_timeout: Option<u64>,
+    pub trace_ec: Option<String>,
+    pub replay_ec: Option<String>,
+    pub redact: bool,
+    pub units: Option<UnitsArg>,
     pub test: bool,
+    pub report: Option<String>,
+    pub bench_ec: Option<Option<usize>>,
+    pub apply_config: bool,
+    pub ec_snapshot: Option<(EcSnapshotArg, String)>,
+    pub kb_matrix: Option<(KbMatrixArg, String)>,
     pub intrusion: bool,
+    pub intrusion_clear: bool,
+    pub exit_on_open: bool,
+    pub hook: Option<String>,
+    /// How often --force was given
+    pub force: u8,
+    pub dry_run: bool,
+    pub battery_cutoff: Option<Option<BatteryCutoffArg>>,
     pub inputmodules: bool,
     pub input_deck_mode: Option<InputDeckModeArg>,
     pub charge_limit: Option<Option<u8>>,
-    pub get_gpio: Option<String>,
+    pub charger_input_limit: Option<Option<u32>>,
+    pub charge_param: Option<Option<(String, Option<String>)>>,
+    pub bios_setting: Option<Option<(String, Option<String>)>>,
+    pub wake_sources: Option<(WakeSourcesArg, Option<String>)>,
+    pub get_gpio: Option<Option<String>>,
+    pub annotated: bool,
+    pub set_gpio: Option<(String, bool)>,
     pub fp_brightness: Option<Option<FpBrightnessArg>>,
+    pub fp_led_pattern: Option<String>,
+    /// LED and what to set it to, otherwise list the LEDs
+    pub led: Option<Option<(EcLedId, Option<led::LedSetting>)>>,
     pub kblight: Option<Option<u8>>,
+    /// Fan index (all fans if None) and duty cycle in percent
+    pub fansetduty: Option<(Option<u8>, u32)>,
+    pub autofanctrl: Option<Option<u8>>,
     pub console: Option<ConsoleArg>,
+    /// With `--console follow`, stop after this many seconds
+    pub duration: Option<u64>,
+    /// With `--console follow`, stop after a line that matches this regular expression
+    pub until: Option<String>,
     pub reboot_ec: Option<RebootEcArg>,
     pub hash: Option<String>,
     pub pd_addrs: Option<(u16, u16)>,
     pub pd_ports: Option<(u8, u8)>,
     pub has_mec: Option<bool>,
+    pub platform: Option<PlatformArg>,
+    pub helper: bool,
+    pub install_udev_rules: bool,
+    pub windows_service: Option<WindowsServiceArg>,
+    pub shell: bool,
+    pub watch_events: Option<Option<String>>,
+    /// Run as daemon, optionally with a hook for hardware events
+    pub daemon: Option<Option<String>>,
+    pub tray: bool,
+    pub kblight_auto: Option<Option<u8>>,
+    pub tablet_debug: bool,
+    pub tablet_watch: bool,
+    pub tablet_mode_toggle: Option<Option<TabletModeArg>>,
+    pub suspend_test: Option<u64>,
     pub help: bool,
     pub info: bool,
     // UEFI only
@@ -174,21 +459,6 @@ pub fn parse(args: &[String]) -> Cli {
     return clap_std::parse(args);
 }
 
-fn print_single_pd_details(pd: &PdController) {
-    if let Ok(si) = pd.get_silicon_id() {
-        println!("  Silicon ID:     0x{:X}", si);
-    } else {
-        println!("  Failed to read Silicon ID/Family");
-    }
-    if let Ok((mode, frs)) = pd.get_device_info() {
-        println!("  Mode:           {:?}", mode);
-        println!("  Flash Row Size: {} B", frs);
-    } else {
-        println!("  Failed to device info");
-    }
-    pd.print_fw_info();
-}
-
 fn print_pd_details(ec: &CrosEc) {
     if !is_framework() {
         println!("Only supported on Framework systems");
@@ -198,49 +468,45 @@ fn print_pd_details(ec: &CrosEc) {
     let pd_23 = PdController::new(PdPort::Right23, ec.clone());
 
     println!("Left / Ports 01");
-    print_single_pd_details(&pd_01);
+    PdDetailsReport::read(&pd_01).render(&mut StdoutSink);
     println!("Right / Ports 23");
-    print_single_pd_details(&pd_23);
+    PdDetailsReport::read(&pd_23).render(&mut StdoutSink);
 }
 
 #[cfg(feature = "hidapi")]
 const NOT_SET: &str = "NOT SET";
 
 #[cfg(feature = "rusb")]
-fn print_audio_card_details() {
-    check_synaptics_fw_version();
+fn print_audio_card_details(devices: &Devices) {
+    check_synaptics_fw_version(devices);
 }
 
 #[cfg(feature = "hidapi")]
-fn print_dp_hdmi_details() {
-    match HidApi::new() {
-        Ok(api) => {
-            for dev_info in find_devices(&api, &[HDMI_CARD_PID, DP_CARD_PID], None) {
-                let vid = dev_info.vendor_id();
-                let pid = dev_info.product_id();
-
-                let device = dev_info.open_device(&api).unwrap();
-                if let Some(name) = ccgx::hid::device_name(vid, pid) {
-                    println!("{}", name);
-                }
+fn print_dp_hdmi_details(devices: &Devices) {
+    let Some(api) = devices.hid() else {
+        return;
+    };
+    for dev_info in find_devices(api, &[HDMI_CARD_PID, DP_CARD_PID], None) {
+        println!("{}", ccgx::hid::card_label(devices, &dev_info));
+        let Some(device) = ccgx::hid::open_card(api, &dev_info) else {
+            continue;
+        };
 
-                // On Windows this value is "Control Interface", probably hijacked by the kernel driver
-                debug!(
-                    "  Product String:  {}",
-                    dev_info.product_string().unwrap_or(NOT_SET)
-                );
+        // On Windows this value is "Control Interface", probably hijacked by the kernel driver
+        debug!(
+            "  Product String:  {}",
+            dev_info.product_string().unwrap_or(NOT_SET)
+        );
 
-                println!(
-                    "  Serial Number:        {}",
-                    dev_info.serial_number().unwrap_or(NOT_SET)
-                );
-                check_ccg_fw_version(&device);
-            }
-        }
-        Err(e) => {
-            eprintln!("Error: {e}");
-        }
-    };
+        println!(
+            "  Serial Number:        {}",
+            dev_info
+                .serial_number()
+                .map(util::redact_serial)
+                .unwrap_or(NOT_SET.to_string())
+        );
+        check_ccg_fw_version(&device);
+    }
 }
 
 fn print_tool_version() {
@@ -275,9 +541,8 @@ fn print_tool_version() {
     }
 }
 
-// TODO: Check if HDMI card is same
 #[cfg(feature = "hidapi")]
-fn flash_dp_hdmi_card(pd_bin_path: &str) {
+fn flash_dp_hdmi_card(pd_bin_path: &str, force: bool, dry_run: bool, card: Option<&str>) -> i32 {
     let data = match fs::read(pd_bin_path) {
         Ok(data) => Some(data),
         // TODO: Perhaps a more user-friendly error
@@ -287,185 +552,210 @@ fn flash_dp_hdmi_card(pd_bin_path: &str) {
         }
     };
     if let Some(data) = data {
-        // TODO: Check if exists, otherwise err
-        //ccgx::hid::find_device().unwrap();
-        ccgx::hid::flash_firmware(&data);
+        if ccgx::hid::flash_firmware(&data, force, dry_run, card) {
+            ExitCode::Success.into()
+        } else {
+            ExitCode::Failure.into()
+        }
     } else {
         error!("Failed to open firmware file");
+        ExitCode::Failure.into()
     }
 }
 
-fn active_mode(mode: &FwMode, reference: FwMode) -> &'static str {
-    if mode == &reference {
-        " (Active)"
-    } else {
-        ""
+/// One part of the `--versions` output
+type VersionSection<'a> = Box<dyn Fn(&mut dyn OutputSink) + Sync + 'a>;
+
+pub(crate) fn print_versions(ec: &CrosEc, out: &mut dyn OutputSink) {
+    #[allow(unused_mut)]
+    let mut sections: Vec<VersionSection> = vec![
+        Box::new(|out: &mut dyn OutputSink| BiosVersionReport::read().render(out)),
+        Box::new(|out: &mut dyn OutputSink| {
+            let verbose = log_enabled!(Level::Warn);
+            EcVersionReport::read(ec, verbose).render(out, verbose)
+        }),
+        Box::new(|out: &mut dyn OutputSink| PdVersionsReport::read(ec).render(out)),
+        Box::new(print_retimer_versions),
+    ];
+    // Only with -v, scanning all devices takes a while
+    #[cfg(feature = "linux")]
+    if log_enabled!(Level::Warn) {
+        sections.push(Box::new(|out: &mut dyn OutputSink| {
+            outln!(out, "Thunderbolt/USB4");
+            print_thunderbolt(out);
+        }));
+    }
+    #[cfg(any(feature = "linux", feature = "windows"))]
+    sections.push(Box::new(print_csme_version));
+    // In the OS, --dp-hdmi-info and --audio-card-info show more details
+    #[cfg(feature = "uefi")]
+    sections.push(Box::new(print_usb_versions));
+
+    // The sections don't depend on each other, but reading some of them takes
+    // a while. Collect them all at the same time and print them in order.
+    #[cfg(not(feature = "uefi"))]
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = sections
+            .iter()
+            .map(|section| {
+                scope.spawn(move || {
+                    let mut sink = crate::output::StringSink::default();
+                    section(&mut sink);
+                    sink.buffer
+                })
+            })
+            .collect();
+        for handle in handles {
+            match handle.join() {
+                Ok(buffer) => buffer.lines().for_each(|line| out.write_line(line)),
+                Err(err) => std::panic::resume_unwind(err),
+            }
+        }
+    });
+    #[cfg(feature = "uefi")]
+    for section in sections {
+        section(out);
     }
 }
 
-fn print_versions(ec: &CrosEc) {
-    println!("UEFI BIOS");
-    if let Some(smbios) = get_smbios() {
-        let bios_entries = smbios.collect::<SMBiosInformation>();
-        let bios = bios_entries.first().unwrap();
-        println!("  Version:        {}", bios.version());
-        println!("  Release Date:   {}", bios.release_date());
+fn print_retimer_versions(out: &mut dyn OutputSink) {
+    outln!(out, "Retimers");
+    let mut found_retimer = false;
+    if let Some(esrt) = esrt::get_esrt() {
+        for entry in &esrt.entries {
+            let side = match esrt::guid_component(&entry.fw_class) {
+                Some(esrt::FirmwareComponent::Retimer01) => "Left: ",
+                Some(esrt::FirmwareComponent::Retimer23) => "Right:",
+                _ => continue,
+            };
+            found_retimer = true;
+            outln!(
+                out,
+                "  {}          0x{:X} ({})",
+                side,
+                entry.fw_version,
+                entry.fw_version
+            );
+        }
+    }
+    if !found_retimer {
+        outln!(out, "  Unknown");
     }
+}
 
-    println!("EC Firmware");
-    let ver = print_err(ec.version_info()).unwrap_or_else(|| "UNKNOWN".to_string());
-    println!("  Build version:  {:?}", ver);
+/// Expansion Cards and other Framework USB devices, with their USB release number
+#[cfg(feature = "uefi")]
+fn print_usb_versions(out: &mut dyn OutputSink) {
+    outln!(out, "USB Devices");
+    let usb_devices: Vec<_> = crate::uefi::usb::list_devices()
+        .into_iter()
+        .filter(|dev| dev.vid == devices::FRAMEWORK_VID)
+        .collect();
+    if usb_devices.is_empty() {
+        outln!(out, "  None found");
+    }
+    for dev in usb_devices {
+        let name = devices::framework_device_name(dev.pid).unwrap_or("Unknown");
+        outln!(
+            out,
+            "  {:<27} {:04X}:{:04X} {:X}.{:02X}",
+            name,
+            dev.vid,
+            dev.pid,
+            dev.bcd_device >> 8,
+            dev.bcd_device & 0xFF
+        );
+    }
+}
 
-    if let Some((ro, rw, curr)) = ec.flash_version() {
-        println!("  RO Version:     {:?}", ro);
-        println!("  RW Version:     {:?}", rw);
-        print!("  Current image:  ");
-        if curr == chromium_ec::EcCurrentImage::RO {
-            println!("RO");
-        } else if curr == chromium_ec::EcCurrentImage::RW {
-            println!("RW");
-        } else {
-            println!("Unknown");
-        }
+#[cfg(any(feature = "linux", feature = "windows"))]
+fn print_csme_version(out: &mut dyn OutputSink) {
+    outln!(out, "CSME");
+    if let Ok(csme) = csme::get_csme_info() {
+        outln!(out, "  Enabled:        {}", csme.enabled);
+        outln!(out, "  Version:        {}", csme.main_ver);
+        outln!(out, "  Recovery Ver:   {}", csme.recovery_ver);
+        outln!(out, "  Original Ver:   {}", csme.fitc_ver);
     } else {
-        println!("  RO Version:     Unknown");
-        println!("  RW Version:     Unknown");
-        println!("  Current image:  Unknown");
+        outln!(out, "  Unknown");
     }
+}
 
-    println!("PD Controllers");
-
-    if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
-        let right = &pd_versions.controller01;
-        let left = &pd_versions.controller23;
-        println!("  Right (01)");
-        // let active_mode =
-        if let Some(Platform::IntelGen11) = smbios::get_platform() {
-            println!(
-                "    Main:       {}{}",
-                right.main_fw.base,
-                active_mode(&right.active_fw, FwMode::MainFw)
-            );
-            println!(
-                "    Backup:     {}{}",
-                right.backup_fw.base,
-                active_mode(&right.active_fw, FwMode::BackupFw)
-            );
-        } else {
-            println!(
-                "    Main:       {}{}",
-                right.main_fw.app,
-                active_mode(&right.active_fw, FwMode::MainFw)
-            );
-            println!(
-                "    Backup:     {}{}",
-                right.backup_fw.app,
-                active_mode(&right.active_fw, FwMode::BackupFw)
-            );
+fn print_thunderbolt(out: &mut dyn OutputSink) -> i32 {
+    #[cfg(feature = "linux")]
+    return match thunderbolt::get_routers() {
+        Ok(routers) => {
+            thunderbolt::print_routers(&routers, out);
+            0
         }
-        println!("  Left  (23)");
-        if let Some(Platform::IntelGen11) = smbios::get_platform() {
-            println!(
-                "    Main:       {}{}",
-                left.main_fw.base,
-                active_mode(&left.active_fw, FwMode::MainFw)
-            );
-            println!(
-                "    Backup:     {}{}",
-                left.backup_fw.base,
-                active_mode(&left.active_fw, FwMode::BackupFw)
-            );
-        } else {
-            println!(
-                "    Main:       {}{}",
-                left.main_fw.app,
-                active_mode(&left.active_fw, FwMode::MainFw)
-            );
-            println!(
-                "    Backup:     {}{}",
-                left.backup_fw.app,
-                active_mode(&left.active_fw, FwMode::BackupFw)
-            );
+        Err(err) => {
+            outln!(out, "  Failed to read Thunderbolt devices: {:?}", err);
+            1
         }
-    } else if let Ok(pd_versions) = power::read_pd_version(ec) {
-        // As fallback try to get it from the EC. But not all EC versions have this command
-        println!("  Right (01):     {}", pd_versions.controller01.app);
-        println!("  Left  (23):     {}", pd_versions.controller23.app);
-    } else {
-        println!("  Unknown")
+    };
+    #[cfg(not(feature = "linux"))]
+    {
+        outln!(
+            out,
+            "  Reading Thunderbolt/USB4 firmware is only supported on Linux"
+        );
+        1
     }
+}
 
-    println!("Retimers");
-    let mut found_retimer = false;
-    if let Some(esrt) = esrt::get_esrt() {
-        for entry in &esrt.entries {
-            match entry.fw_class {
-                esrt::TGL_RETIMER01_GUID
-                | esrt::TGL_RETIMER23_GUID
-                | esrt::ADL_RETIMER01_GUID
-                | esrt::ADL_RETIMER23_GUID
-                | esrt::RPL_RETIMER01_GUID
-                | esrt::RPL_RETIMER23_GUID
-                | esrt::MTL_RETIMER01_GUID
-                | esrt::MTL_RETIMER23_GUID => {
-                    if !found_retimer {
-                        found_retimer = true;
-                    }
-                }
-                _ => {}
-            }
-            match entry.fw_class {
-                esrt::TGL_RETIMER01_GUID
-                | esrt::ADL_RETIMER01_GUID
-                | esrt::RPL_RETIMER01_GUID
-                | esrt::MTL_RETIMER01_GUID => {
-                    println!(
-                        "  Left:           0x{:X} ({})",
-                        entry.fw_version, entry.fw_version
-                    );
-                }
-                esrt::TGL_RETIMER23_GUID
-                | esrt::ADL_RETIMER23_GUID
-                | esrt::RPL_RETIMER23_GUID
-                | esrt::MTL_RETIMER23_GUID => {
-                    println!(
-                        "  Right:          0x{:X} ({})",
-                        entry.fw_version, entry.fw_version
-                    );
+/// Let the user pick a firmware file from the drive and update with it
+///
+/// EC firmware is flashed directly, capsules are only analyzed for now.
+fn allupdate(ec: &CrosEc) -> i32 {
+    #[cfg(feature = "uefi")]
+    {
+        use crate::uefi::fs::{pick_firmware_file, FirmwareFileKind};
+
+        let kinds = [FirmwareFileKind::Capsule, FirmwareFileKind::Ec];
+        let path = if let Some(path) = pick_firmware_file(&kinds) {
+            path
+        } else {
+            return 1;
+        };
+        let name = path.rsplit('\\').next().unwrap_or(&path);
+        match FirmwareFileKind::from_name(name) {
+            Some(FirmwareFileKind::Ec) => return flash_ec(ec, &path, EcFlashType::Rw),
+            Some(FirmwareFileKind::Capsule) => {
+                let data = if let Some(data) = crate::uefi::fs::read_file(&path) {
+                    data
+                } else {
+                    return 1;
+                };
+                if analyze_capsule(&data).is_none() {
+                    println!("Capsule is invalid.");
+                    return 1;
                 }
-                _ => {}
+                println!("Only analyzed the capsule, applying capsules is not supported yet.");
+                println!("Use the BIOS updater to apply it.");
             }
+            None => return 1,
         }
+        0
     }
-    if !found_retimer {
-        println!("  Unknown");
-    }
-
-    #[cfg(feature = "linux")]
+    #[cfg(not(feature = "uefi"))]
     {
-        println!("CSME");
-        if let Ok(csme) = csme::csme_from_sysfs() {
-            println!("  Enabled:        {}", csme.enabled);
-            println!("  Version:        {}", csme.main_ver);
-            println!("  Recovery Ver:   {}", csme.recovery_ver);
-            println!("  Original Ver:   {}", csme.fitc_ver);
-        } else {
-            println!("  Unknown");
-        }
+        let _ = ec;
+        println!("--allupdate is only supported on UEFI");
+        1
     }
 }
 
 fn print_esrt() {
     if let Some(esrt) = esrt::get_esrt() {
-        esrt::print_esrt(&esrt);
+        esrt::print_esrt(&esrt, &mut StdoutSink);
     } else {
         println!("Could not find and parse ESRT table.");
     }
 }
 
-fn flash_ec(ec: &CrosEc, ec_bin_path: &str, flash_type: EcFlashType) {
+fn flash_ec(ec: &CrosEc, ec_bin_path: &str, flash_type: EcFlashType) -> i32 {
     #[cfg(feature = "uefi")]
-    let data = crate::uefi::fs::shell_read_file(ec_bin_path);
+    let data = crate::uefi::fs::read_file(ec_bin_path);
     #[cfg(not(feature = "uefi"))]
     let data: Option<Vec<u8>> = {
         let _data = match fs::read(ec_bin_path) {
@@ -485,83 +775,389 @@ fn flash_ec(ec: &CrosEc, ec_bin_path: &str, flash_type: EcFlashType) {
 
     if let Some(data) = data {
         println!("File");
-        println!("  Size:       {:>20} B", data.len());
-        println!("  Size:       {:>20} KB", data.len() / 1024);
+        println!("  Size:       {:>20}", units::fmt_size(data.len()));
         if let Err(err) = ec.reflash(&data, flash_type) {
             println!("Error: {:?}", err);
-        } else {
-            println!("Success!");
+            return ExitCode::from(&err).into();
         }
+        println!("Success!");
+        return ExitCode::Success.into();
     }
+    ExitCode::Failure.into()
 }
 
-fn dump_ec_flash(ec: &CrosEc, dump_path: &str) {
-    let flash_bin = ec.get_entire_ec_flash().unwrap();
+fn print_flash_layout(ec: &CrosEc) {
+    let layout = ec.flash_layout();
+    println!("EC Flash Layout");
+    println!("  Chip:       {:?}", layout.chip);
+    for region in EcFlashRegion::ALL {
+        let (offset, size) = layout.region(*region);
+        println!(
+            "  {:<6}      {:#07X}-{:#07X} {}",
+            region.name(),
+            offset,
+            offset + size - 1,
+            units::fmt_size(size as usize)
+        );
+    }
+}
 
-    #[cfg(all(not(feature = "uefi"), feature = "std"))]
+fn erase_ec_region(ec: &CrosEc, region: EcFlashRegion, force: u8, dry_run: bool) -> i32 {
+    let (offset, size) = ec.flash_layout().region(region);
+    println!(
+        "{} region: {:#07X}-{:#07X}",
+        region.name(),
+        offset,
+        offset + size - 1
+    );
+    // Without RO the EC can only be recovered with an external programmer
+    if region == EcFlashRegion::Ro {
+        println!("For safety reasons erasing RO firmware is disabled.");
+        return ExitCode::Unsupported.into();
+    }
+    if dry_run {
+        println!("Dry run, not erasing anything");
+        return ExitCode::Success.into();
+    }
+    if force == 0 {
+        println!(
+            "Erasing the {} region can't be undone. Add --force to do it anyway",
+            region.name()
+        );
+        return ExitCode::Usage.into();
+    }
+    // Same as flash_ec, EC communication from the OS isn't reliable enough yet
+    #[cfg(not(feature = "uefi"))]
     {
-        let mut file = fs::File::create(dump_path).unwrap();
-        file.write_all(&flash_bin).unwrap();
+        println!("Sorry, erasing EC flash from the OS is not supported yet.");
+        ExitCode::Unsupported.into()
     }
     #[cfg(feature = "uefi")]
     {
-        let ret = crate::uefi::fs::shell_write_file(dump_path, &flash_bin);
-        if ret.is_err() {
-            println!("Failed to dump EC FW image.");
+        if let Err(err) = ec.erase_ec_region(region, &mut StdoutSink) {
+            println!("Error: {:?}", err);
+            return ExitCode::from(&err).into();
         }
+        println!("Erased {} region", region.name());
+        ExitCode::Success.into()
     }
 }
 
-fn compare_version(device: Option<HardwareDeviceType>, version: String, ec: &CrosEc) -> i32 {
-    println!("Target Version {:?}", version);
+fn dump_ec_flash(ec: &CrosEc, dump_path: &str) -> i32 {
+    let flash_bin = match ec.get_entire_ec_flash() {
+        Ok(flash_bin) => flash_bin,
+        Err(err) => return exit_code::<()>(Err(err)),
+    };
 
-    if let Some(smbios) = get_smbios() {
-        let bios_entries = smbios.collect::<SMBiosInformation>();
-        let bios = bios_entries.first().unwrap();
+    if !write_file(dump_path, &flash_bin) {
+        return ExitCode::Failure.into();
+    }
+    ExitCode::Success.into()
+}
 
-        if device == Some(HardwareDeviceType::BIOS) {
-            println!("Comparing BIOS version {:?}", bios.version().to_string());
-            if version.to_uppercase() == bios.version().to_string().to_uppercase() {
-                return 0;
-            } else {
-                return 1;
-            }
+fn read_file(path: &str) -> Option<Vec<u8>> {
+    #[cfg(feature = "uefi")]
+    let data = crate::uefi::fs::read_file(path);
+    #[cfg(not(feature = "uefi"))]
+    let data = match fs::read(path) {
+        Ok(data) => Some(data),
+        Err(err) => {
+            println!("Failed to read {}: {:?}", path, err);
+            None
         }
+    };
+    data
+}
+
+fn write_file(path: &str, data: &[u8]) -> bool {
+    #[cfg(all(not(feature = "uefi"), feature = "std"))]
+    let ret = fs::write(path, data).map_err(|err| format!("{:?}", err));
+    #[cfg(feature = "uefi")]
+    let ret = crate::uefi::fs::shell_write_file(path, data).map_err(|err| format!("{:?}", err));
+
+    if let Err(err) = &ret {
+        println!("Failed to write {}: {}", path, err);
     }
+    ret.is_ok()
+}
 
-    match device {
-        Some(HardwareDeviceType::EC) => {
-            let ver = print_err(ec.version_info()).unwrap_or_else(|| "UNKNOWN".to_string());
-            println!("Comparing EC version {:?}", ver);
+/// Wrap a payload file in a capsule and write it to a file
+///
+/// With the Windows UX GUID the payload is a BMP image, that's shown during the update.
+fn make_capsule(guid: &str, payload_path: &str, out_path: &str) -> i32 {
+    let Some(guid) = esrt::guid_from_str(guid) else {
+        println!("Invalid GUID: {}", guid);
+        return ExitCode::Usage.into();
+    };
+    let Some(payload) = read_file(payload_path) else {
+        return ExitCode::Failure.into();
+    };
 
-            if ver.contains(&version) {
-                return 0;
-            } else {
-                return 1;
+    let data = if guid == esrt::WINUX_GUID {
+        match capsule::build_ux_capsule(&payload, 0, 0) {
+            Ok(data) => data,
+            Err(err) => {
+                println!("Invalid Windows UX image {}: {}", payload_path, err);
+                return ExitCode::Usage.into();
             }
         }
-        Some(HardwareDeviceType::PD0) => {
-            if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
-                let ver = pd_versions.controller01.active_fw_ver();
-                println!("Comparing PD0 version {:?}", ver);
+    } else {
+        capsule::build_capsule(guid, capsule::CAPSULE_FLAGS_PERSIST_ACROSS_RESET, &payload)
+    };
+    if !write_file(out_path, &data) {
+        return ExitCode::Failure.into();
+    }
+    println!(
+        "Wrote {} capsule ({}) to {}",
+        esrt::guid_name(&guid),
+        units::fmt_size(data.len()),
+        out_path
+    );
+    ExitCode::Success.into()
+}
 
-                if ver.contains(&version) {
-                    return 0;
-                } else {
-                    return 1;
-                }
+/// Build a Windows UX capsule that shows a PNG or BMP image during the update
+///
+/// The image is placed to fit the built-in display, if its resolution is known.
+fn make_ux_capsule(image_path: &str, out_path: &str) -> i32 {
+    let Some(image) = read_file(image_path) else {
+        return ExitCode::Failure.into();
+    };
+    #[cfg(not(feature = "uefi"))]
+    let bmp = if image.starts_with(b"BM") {
+        image
+    } else {
+        match capsule::png_to_bmp(&image) {
+            Ok(bmp) => bmp,
+            Err(err) => {
+                println!("Failed to convert {}: {}", image_path, err);
+                return ExitCode::Usage.into();
             }
         }
-        Some(HardwareDeviceType::PD1) => {
-            if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
-                let ver = pd_versions.controller23.active_fw_ver();
-                println!("Comparing PD1 version {:?}", ver);
+    };
+    #[cfg(feature = "uefi")]
+    let bmp = image;
+    let Some(size) = capsule::bmp_dimensions(&bmp) else {
+        println!("Invalid BMP image {}", image_path);
+        return ExitCode::Usage.into();
+    };
 
-                if ver.contains(&version) {
-                    return 0;
-                } else {
-                    return 1;
-                }
+    let (offset_x, offset_y) = match crate::os_specific::native_resolution() {
+        Some(resolution) if size.0 > resolution.0 || size.1 > resolution.1 => {
+            println!(
+                "Image of {}x{} is larger than the display of {}x{}",
+                size.0, size.1, resolution.0, resolution.1
+            );
+            return ExitCode::Usage.into();
+        }
+        Some(resolution) => {
+            println!("Display resolution: {}x{}", resolution.0, resolution.1);
+            capsule::ux_image_offset(size, resolution)
+        }
+        None => {
+            println!("Display resolution unknown, the image is shown in the top left corner");
+            (0, 0)
+        }
+    };
+
+    let data = match capsule::build_ux_capsule(&bmp, offset_x, offset_y) {
+        Ok(data) => data,
+        Err(err) => {
+            println!("Invalid image {}: {}", image_path, err);
+            return ExitCode::Usage.into();
+        }
+    };
+    if !write_file(out_path, &data) {
+        return ExitCode::Failure.into();
+    }
+    println!(
+        "Wrote UX capsule with a {}x{} image at {},{} to {}",
+        size.0, size.1, offset_x, offset_y, out_path
+    );
+    ExitCode::Success.into()
+}
+
+/// Write EC firmware, PD firmware and BIOS firmware volume from a H2O capsule to separate files
+fn extract_h2o_capsule(data: &[u8], dir: &str) {
+    #[cfg(all(not(feature = "uefi"), feature = "std"))]
+    if let Err(err) = fs::create_dir_all(dir) {
+        println!("Failed to create directory {}: {:?}", dir, err);
+        return;
+    }
+
+    let payloads = [
+        ("ec.bin", find_ec_in_bios_cap(data)),
+        ("pd.bin", find_pd_in_bios_cap(data)),
+        ("bios.fv", find_bios_fv_in_bios_cap(data)),
+    ];
+    println!("Extracting to {}", dir);
+    for (name, payload) in payloads {
+        #[cfg(feature = "uefi")]
+        let path = format!("{}\\{}", dir, name);
+        #[cfg(not(feature = "uefi"))]
+        let path = std::path::Path::new(dir)
+            .join(name)
+            .to_string_lossy()
+            .to_string();
+
+        if let Some(payload) = payload {
+            if write_file(&path, payload) {
+                println!("  {:<8} {:>8} B -> {}", name, payload.len(), path);
+            }
+        } else {
+            println!("  {:<8} not found", name);
+        }
+    }
+}
+
+/// Get the current version of the device as a string and in which radix its parts are
+fn device_version(device: HardwareDeviceType, ec: &CrosEc) -> Option<(String, u32)> {
+    match device {
+        HardwareDeviceType::BIOS => {
+            let version = get_smbios_info()?.bios?.version?;
+            Some((version, 10))
+        }
+        HardwareDeviceType::EC => {
+            let ver = print_err(ec.version_info())?;
+            let details = ec_binary::parse_ec_build_info(&ver).details?;
+            let (major, minor, patch) = details.semver();
+            Some((format!("{}.{}.{}", major, minor, patch), 10))
+        }
+        HardwareDeviceType::PD0 | HardwareDeviceType::PD1 => {
+            let pd_versions = print_err(ccgx::get_pd_controller_versions(ec))?;
+            let controller = if device == HardwareDeviceType::PD0 {
+                pd_versions.controller01
+            } else {
+                pd_versions.controller23
+            };
+            // PD versions are displayed in hex
+            Some((controller.active_fw_ver(), 16))
+        }
+        HardwareDeviceType::RTM01 | HardwareDeviceType::RTM23 => {
+            let esrt = esrt::get_esrt()?;
+            let component = if device == HardwareDeviceType::RTM01 {
+                esrt::FirmwareComponent::Retimer01
+            } else {
+                esrt::FirmwareComponent::Retimer23
+            };
+            let entry = esrt
+                .entries
+                .iter()
+                .find(|entry| esrt::guid_component(&entry.fw_class) == Some(component))?;
+            Some((entry.fw_version.to_string(), 10))
+        }
+        HardwareDeviceType::AcLeft | HardwareDeviceType::AcRight => {
+            let (right, left) = print_err(power::is_charging(ec))?;
+            let charging = if device == HardwareDeviceType::AcLeft {
+                left
+            } else {
+                right
+            };
+            Some(((charging as i32).to_string(), 10))
+        }
+    }
+}
+
+/// Compare the device's version with an operator like `>=`
+///
+/// Returns 0 if the comparison is true, 1 if it is false
+/// and 2 if either version couldn't be determined.
+fn compare_version_op(
+    device: Option<HardwareDeviceType>,
+    op: util::VersionOp,
+    target: &str,
+    ec: &CrosEc,
+) -> i32 {
+    let Some(device) = device else {
+        error!("Need --device to compare versions with an operator");
+        return 2;
+    };
+    let Some((current, radix)) = device_version(device, ec) else {
+        error!("Failed to get current version of {:?}", device);
+        return 2;
+    };
+    println!("Comparing {:?} version {:?}", device, current);
+
+    let current_parts = util::parse_version_parts(&current, radix);
+    let target_parts = util::parse_version_parts(target, radix);
+    match (current_parts, target_parts) {
+        (Some(current), Some(target)) => {
+            if op.matches(util::compare_version_parts(&current, &target)) {
+                0
+            } else {
+                1
+            }
+        }
+        (None, _) => {
+            error!("Failed to parse current version {:?}", current);
+            2
+        }
+        (_, None) => {
+            error!("Failed to parse target version {:?}", target);
+            2
+        }
+    }
+}
+
+fn compare_version(device: Option<HardwareDeviceType>, version: String, ec: &CrosEc) -> i32 {
+    println!("Target Version {:?}", version);
+
+    if let (Some(op), target) = util::parse_version_op(&version) {
+        return compare_version_op(device, op, target, ec);
+    }
+
+    if let Some(bios_version) = get_smbios_info().and_then(|info| info.bios?.version) {
+        if device == Some(HardwareDeviceType::BIOS) {
+            println!("Comparing BIOS version {:?}", bios_version);
+            if version.to_uppercase() == bios_version.to_uppercase() {
+                return 0;
+            } else {
+                return 1;
+            }
+        }
+    }
+
+    match device {
+        Some(HardwareDeviceType::EC) => {
+            let ver = print_err(ec.version_info()).unwrap_or_else(|| "UNKNOWN".to_string());
+            println!("Comparing EC version {:?}", ver);
+
+            // If both are semantic versions, compare them as such.
+            // Otherwise fall back to checking if the target is part of the build string
+            let info = ec_binary::parse_ec_build_info(&ver);
+            let target = util::parse_semver(&version);
+            let matches = match (info.details, target) {
+                (Some(details), Some(target)) => details.semver() == target,
+                _ => ver.contains(&version),
+            };
+            if matches {
+                return 0;
+            } else {
+                return 1;
+            }
+        }
+        Some(HardwareDeviceType::PD0) => {
+            if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
+                let ver = pd_versions.controller01.active_fw_ver();
+                println!("Comparing PD0 version {:?}", ver);
+
+                if ver.contains(&version) {
+                    return 0;
+                } else {
+                    return 1;
+                }
+            }
+        }
+        Some(HardwareDeviceType::PD1) => {
+            if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
+                let ver = pd_versions.controller23.active_fw_ver();
+                println!("Comparing PD1 version {:?}", ver);
+
+                if ver.contains(&version) {
+                    return 0;
+                } else {
+                    return 1;
+                }
             }
         }
         Some(HardwareDeviceType::AcLeft) => {
@@ -599,25 +1195,20 @@ fn compare_version(device: Option<HardwareDeviceType>, version: String, ec: &Cro
 
     if let Some(esrt) = esrt::get_esrt() {
         for entry in &esrt.entries {
-            match entry.fw_class {
-                esrt::TGL_RETIMER01_GUID | esrt::ADL_RETIMER01_GUID | esrt::RPL_RETIMER01_GUID => {
-                    if device == Some(HardwareDeviceType::RTM01) {
-                        println!("Comparing RTM01 version {:?}", entry.fw_version.to_string());
-
-                        if entry.fw_version.to_string().contains(&version) {
-                            return 0;
-                        }
-                    }
-                }
-                esrt::TGL_RETIMER23_GUID | esrt::ADL_RETIMER23_GUID | esrt::RPL_RETIMER23_GUID => {
-                    if device == Some(HardwareDeviceType::RTM23) {
-                        println!("Comparing RTM23 version {:?}", entry.fw_version.to_string());
-                        if entry.fw_version.to_string().contains(&version) {
-                            return 0;
-                        }
-                    }
+            let (rtm, name) = match esrt::guid_component(&entry.fw_class) {
+                Some(esrt::FirmwareComponent::Retimer01) => (HardwareDeviceType::RTM01, "RTM01"),
+                Some(esrt::FirmwareComponent::Retimer23) => (HardwareDeviceType::RTM23, "RTM23"),
+                _ => continue,
+            };
+            if device == Some(rtm) {
+                println!(
+                    "Comparing {} version {:?}",
+                    name,
+                    entry.fw_version.to_string()
+                );
+                if entry.fw_version.to_string().contains(&version) {
+                    return 0;
                 }
-                _ => {}
             }
         }
     }
@@ -625,6 +1216,193 @@ fn compare_version(device: Option<HardwareDeviceType>, version: String, ec: &Cro
     1
 }
 
+/// Read-only commands that can be combined in one invocation
+///
+/// When several are requested, they run in the order of this enum, not in the
+/// order of the arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Report {
+    Versions,
+    Features,
+    Esrt,
+    UefiVars,
+    Info,
+    Power,
+    Thermal,
+    ExpansionBay,
+    Sensors,
+    PdPorts,
+    PdInfo,
+    Intrusion,
+    InputModules,
+    Privacy,
+    DpHdmiInfo,
+    AudioCardInfo,
+    Thunderbolt,
+    Ucsi,
+    ChargerInfo,
+}
+
+fn requested_reports(args: &Cli) -> Vec<Report> {
+    [
+        (args.versions, Report::Versions),
+        (args.features, Report::Features),
+        (args.esrt, Report::Esrt),
+        (args.uefi_vars, Report::UefiVars),
+        (args.info, Report::Info),
+        // With --watch they run forever
+        (args.power && args.watch.is_none(), Report::Power),
+        (args.thermal, Report::Thermal),
+        (args.expansion_bay, Report::ExpansionBay),
+        (args.sensors, Report::Sensors),
+        (args.pdports, Report::PdPorts),
+        (args.pd_info, Report::PdInfo),
+        (args.intrusion && args.watch.is_none(), Report::Intrusion),
+        (args.inputmodules, Report::InputModules),
+        (args.privacy, Report::Privacy),
+        (args.dp_hdmi_info, Report::DpHdmiInfo),
+        (args.audio_card_info, Report::AudioCardInfo),
+        (args.thunderbolt, Report::Thunderbolt),
+        (args.ucsi, Report::Ucsi),
+        (args.charger_info, Report::ChargerInfo),
+    ]
+    .iter()
+    .filter(|(requested, _)| *requested)
+    .map(|(_, report)| *report)
+    .collect()
+}
+
+fn run_report(report: Report, ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    run_report_with(report, ec, &devices::enumerate(), out)
+}
+
+/// Same as `run_report` but look up USB and HID devices in an existing scan
+fn run_report_with(
+    report: Report,
+    ec: &CrosEc,
+    devices: &Devices,
+    out: &mut dyn OutputSink,
+) -> i32 {
+    match report {
+        Report::Versions => print_versions(ec, out),
+        Report::Features => return exit_code(ec.get_features(out)),
+        Report::Esrt => print_esrt(),
+        Report::UefiVars => {
+            if !uefi_vars::print_uefi_vars(out) {
+                println!("Could not read UEFI variables. Make sure to run as root/administrator.");
+                return ExitCode::Failure.into();
+            }
+        }
+        Report::Info => SmbiosReport::read().render(out),
+        Report::Power => return power::get_and_print_power_info(ec, out),
+        Report::Thermal => return exit_code(power::print_thermal(ec, out)),
+        Report::ExpansionBay => return exit_code(power::print_expansion_bay(ec, out)),
+        Report::Sensors => return exit_code(power::print_sensors(ec, out)),
+        Report::PdPorts => power::get_and_print_pd_info(ec, out),
+        Report::PdInfo => print_pd_details(ec),
+        Report::Intrusion => return print_intrusion(ec, out),
+        Report::InputModules => return print_input_modules(ec),
+        Report::Privacy => return print_privacy(ec),
+        Report::DpHdmiInfo => {
+            #[cfg(feature = "hidapi")]
+            print_dp_hdmi_details(devices);
+        }
+        Report::AudioCardInfo => {
+            #[cfg(feature = "rusb")]
+            print_audio_card_details(devices);
+        }
+        Report::Thunderbolt => {
+            outln!(out, "Thunderbolt/USB4");
+            return print_thunderbolt(out);
+        }
+        Report::Ucsi => return ucsi::print_ucsi(ec, out),
+        Report::ChargerInfo => return power::print_charger_info(ec, out),
+    }
+    ExitCode::Success.into()
+}
+
+/// Run all reports, one after the other, separated by an empty line
+///
+/// A failing report doesn't stop the others. The first failure is returned.
+fn run_reports(reports: &[Report], ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    let mut ret: i32 = ExitCode::Success.into();
+    // Reports that look for the same devices shouldn't scan again
+    let devices = devices::enumerate();
+    for (i, report) in reports.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let res = run_report_with(*report, ec, &devices, out);
+        if ret == i32::from(ExitCode::Success) {
+            ret = res;
+        }
+    }
+    ret
+}
+
+fn print_intrusion(ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    outln!(out, "Chassis status:");
+    let res = ec.get_intrusion_status();
+    if let Ok(status) = &res {
+        outln!(
+            out,
+            "  Coin cell ever removed:   {}",
+            status.coin_cell_ever_removed
+        );
+        outln!(out, "  Chassis currently open:   {}", status.currently_open);
+        outln!(out, "  Chassis ever opened:      {}", status.ever_opened);
+        outln!(
+            out,
+            "  Chassis opened:           {} times",
+            status.total_opened
+        );
+        outln!(
+            out,
+            "  Chassis opened while off: {} times",
+            status.vtr_open_count
+        );
+    } else {
+        outln!(out, "  Unable to tell");
+    }
+    exit_code(res)
+}
+
+fn print_input_modules(ec: &CrosEc) -> i32 {
+    println!("Input Module Status:");
+    let res = ec.get_input_deck_status();
+    if let Ok(status) = &res {
+        println!("Input Deck State: {:?}", status.state);
+        println!("Touchpad present: {:?}", status.touchpad_present);
+        println!("Positions:");
+        println!("  Pos 0: {:?}", status.top_row.pos0);
+        println!("  Pos 1: {:?}", status.top_row.pos1);
+        println!("  Pos 2: {:?}", status.top_row.pos2);
+        println!("  Pos 3: {:?}", status.top_row.pos3);
+        println!("  Pos 4: {:?}", status.top_row.pos4);
+    } else {
+        println!("  Unable to tell");
+    }
+    exit_code(res)
+}
+
+fn print_privacy(ec: &CrosEc) -> i32 {
+    let res = ec.get_privacy_info();
+    if let Ok((mic, cam)) = res {
+        println!("Privacy Slider (Black = Device Connected; Red = Device Disconnected)");
+        println!(
+            "  Microphone:  {}",
+            if mic { "Connected" } else { "Disconnected" }
+        );
+        println!(
+            "  Camera:      {}",
+            if cam { "Connected" } else { "Disconnected" }
+        );
+    } else {
+        println!("Not all EC versions support this comand.")
+    };
+    exit_code(res)
+}
+
 pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
     #[cfg(feature = "uefi")]
     {
@@ -645,7 +1423,9 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
     }
 
     // Must be run before any application code to set the config
-    if args.pd_addrs.is_some() && args.pd_ports.is_some() && args.has_mec.is_some() {
+    if let Some(platform) = args.platform {
+        Config::set(platform.into());
+    } else if args.pd_addrs.is_some() && args.pd_ports.is_some() && args.has_mec.is_some() {
         let platform = Platform::GenericFramework(
             args.pd_addrs.unwrap(),
             args.pd_ports.unwrap(),
@@ -654,183 +1434,584 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
         Config::set(platform);
     }
 
-    let ec = if let Some(driver) = args.driver {
+    if let Some(timeout_ms) = args.ec_timeout {
+        set_retry_policy(RetryPolicy {
+            timeout_ms,
+            ..retry_policy()
+        });
+    }
+
+    util::set_redact(args.redact);
+    if let Some(units) = args.units {
+        units::set_units(units.into());
+    }
+
+    #[cfg(feature = "linux")]
+    if args.helper {
+        return helper::serve(helper::SOCKET_PATH);
+    }
+    // Runs as the regular user, the daemon accesses the EC
+    if args.tray {
+        #[cfg(feature = "tray")]
+        return tray::run();
+        #[cfg(not(feature = "tray"))]
+        {
+            println!("The tray icon is only supported on Linux, when built with the tray feature");
+            return ExitCode::Unsupported.into();
+        }
+    }
+    if args.install_udev_rules {
+        #[cfg(feature = "linux")]
+        {
+            let exit_code = if udev::install_rules(args.force > 0) {
+                ExitCode::Success.into()
+            } else {
+                ExitCode::Failure.into()
+            };
+            write_audit_log(args, exit_code);
+            return exit_code;
+        }
+        #[cfg(not(feature = "linux"))]
+        {
+            println!("udev rules are only supported on Linux");
+            return ExitCode::Unsupported.into();
+        }
+    }
+    if let Some(action) = args.windows_service {
+        #[cfg(feature = "windows")]
+        return match action {
+            WindowsServiceArg::Install => windows_service::install(),
+            WindowsServiceArg::Uninstall => windows_service::uninstall(),
+            WindowsServiceArg::Run => windows_service::run(),
+        };
+        #[cfg(not(feature = "windows"))]
+        {
+            let _ = action;
+            println!("The service is only supported on Windows");
+            return ExitCode::Unsupported.into();
+        }
+    }
+    // Regular users can't access the EC, let the privileged helper handle it, if it's running
+    #[cfg(feature = "linux")]
+    if !nix::unistd::Uid::effective().is_root()
+        && args.trace_ec.is_none()
+        && args.replay_ec.is_none()
+        // The helper doesn't know which output to hide
+        && !args.redact
+    {
+        match helper::HelperRequest::from_cli(args) {
+            Ok(Some(request)) => {
+                if let Some(exit_code) = helper::forward(&request) {
+                    return exit_code;
+                }
+            }
+            Ok(None) => {}
+            // Running directly needs root, say why the helper isn't used
+            Err(reason) if helper::is_running() => {
+                println!("Not using the privileged helper, {}", reason)
+            }
+            Err(_) => {}
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    if let Some(path) = &args.trace_ec {
+        if let Err(err) = chromium_ec::trace::start_recording(path) {
+            error!("Failed to create EC trace {}: {:?}", path, err);
+            return ExitCode::Failure.into();
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    let replay = if let Some(path) = &args.replay_ec {
+        match CrosEc::replay(path) {
+            Ok(ec) => Some(ec),
+            Err(err) => {
+                error!("Failed to load EC trace {}: {:?}", path, err);
+                return ExitCode::Failure.into();
+            }
+        }
+    } else {
+        None
+    };
+    #[cfg(feature = "uefi")]
+    let replay = None;
+
+    let ec = if let Some(ec) = replay {
+        ec
+    } else if let Some(driver) = args.driver {
         if let Some(driver) = CrosEc::with(driver) {
             driver
         } else {
             println!("Selected driver {:?} not available.", driver);
-            return 1;
+            return ExitCode::EcUnreachable.into();
+        }
+    } else {
+        let (ec, report) = CrosEc::probe();
+        // Only complain once here, rather than in every command that needs the EC
+        if is_framework() {
+            report.print();
         }
+        ec
+    };
+
+    if args.shell {
+        return shell::run(&ec);
+    }
+
+    let exit_code = run_command(args, &ec, _allupdate);
+    #[cfg(not(feature = "uefi"))]
+    write_audit_log(args, exit_code);
+    exit_code
+}
+
+/// Record the command in the audit log, if it changes the system
+#[cfg(not(feature = "uefi"))]
+fn write_audit_log(args: &Cli, exit_code: i32) {
+    if let (Some(operation), None) = (audited_operation(args), &args.replay_ec) {
+        let args = std::env::args().skip(1).collect();
+        audit::write(&audit::AuditRecord::new(operation, args, exit_code));
+    }
+}
+
+/// Name of the operation for the audit log, if the command changes the system
+///
+/// Commands that only read are not recorded. Neither are flashing and erasing
+/// the EC, they're always refused in the OS, see `flash_ec`.
+#[cfg(not(feature = "uefi"))]
+fn audited_operation(args: &Cli) -> Option<&'static str> {
+    let operation = if args.install_udev_rules {
+        "install_udev_rules"
+    } else if args.dp_hdmi_update.is_some() && !args.dry_run {
+        "dp_hdmi_update"
+    } else if let Some(Some(_)) = args.charge_limit {
+        "charge_limit"
+    } else if let Some(Some(_)) = args.charger_input_limit {
+        "charger_input_limit"
+    } else if args.fansetduty.is_some() || args.autofanctrl.is_some() {
+        "fan"
+    } else if args.set_gpio.is_some() {
+        "set_gpio"
+    } else if args.reboot_ec.is_some() {
+        "reboot_ec"
+    } else if args.battery_cutoff.is_some() {
+        "battery_cutoff"
+    } else if args.intrusion_clear {
+        "intrusion_clear"
+    } else if args.typec_role.is_some() || args.pd_max_voltage.is_some() || args.pd_renegotiate {
+        "pd"
+    } else if let Some(Some((_, Some(_)))) = args.bios_setting {
+        "bios_setting"
+    } else if let Some(Some((_, Some(_)))) = args.charge_param {
+        "charge_param"
+    } else if matches!(
+        args.wake_sources,
+        Some((WakeSourcesArg::Enable | WakeSourcesArg::Disable, _))
+    ) {
+        "wake_sources"
+    } else if let Some((EcSnapshotArg::Restore, _)) = args.ec_snapshot {
+        "ec_snapshot_restore"
+    } else if let Some((KbMatrixArg::Restore, _)) = args.kb_matrix {
+        "kb_matrix_restore"
+    } else if args.apply_config {
+        "apply_config"
+    } else if let Some(Some(_)) = args.kblight {
+        "kblight"
+    } else if args.kblight_auto.is_some() {
+        "kblight_auto"
+    } else if let Some(Some(_)) = args.fp_brightness {
+        "fp_brightness"
+    } else if args.fp_led_pattern.is_some() {
+        "fp_led_pattern"
+    } else if let Some(Some((_, Some(_)))) = args.led {
+        "led"
+    } else if args.input_deck_mode.is_some() {
+        "input_deck_mode"
+    } else if args.tablet_mode_toggle.is_some() {
+        "tablet_mode_toggle"
+    } else if args.sensor_calibrate.is_some() {
+        "sensor_calibrate"
     } else {
-        CrosEc::new()
+        return None;
     };
+    Some(operation)
+}
 
+/// Run the command selected by the arguments, with an EC that's already set up
+pub fn run_command(args: &Cli, ec: &CrosEc, _allupdate: bool) -> i32 {
     #[cfg(feature = "uefi")]
     if args.paginate {
         enable_page_break();
     }
 
+    // Where the output of commands goes, when they support it
+    let mut out = StdoutSink;
+    let reports = requested_reports(args);
+
     if args.help {
         // Only print with uefi feature here because without clap will already
         // have printed the help by itself.
         #[cfg(feature = "uefi")]
         print_help(_allupdate);
-        return 2;
+        return ExitCode::Usage.into();
+    } else if reports.len() > 1 {
+        return run_reports(&reports, ec, &mut out);
     } else if args.versions {
-        print_versions(&ec);
+        return run_report(Report::Versions, ec, &mut out);
     } else if args.version {
         print_tool_version();
     } else if args.features {
-        ec.get_features().unwrap();
+        return run_report(Report::Features, ec, &mut out);
     } else if args.esrt {
-        print_esrt();
+        return run_report(Report::Esrt, ec, &mut out);
+    } else if args.uefi_vars {
+        return run_report(Report::UefiVars, ec, &mut out);
     } else if let Some(compare_version_ver) = &args.compare_version {
-        let compare_ret = compare_version(args.device, compare_version_ver.to_string(), &ec);
+        let compare_ret = compare_version(args.device, compare_version_ver.to_string(), ec);
         println!("Comparison Result:  {}", compare_ret);
         return compare_ret;
-    } else if args.intrusion {
-        println!("Chassis status:");
-        if let Some(status) = print_err(ec.get_intrusion_status()) {
-            println!(
-                "  Coin cell ever removed:   {}",
-                status.coin_cell_ever_removed
-            );
-            println!("  Chassis currently open:   {}", status.currently_open);
-            println!("  Chassis ever opened:      {}", status.ever_opened);
-            println!("  Chassis opened:           {} times", status.total_opened);
-            println!(
-                "  Chassis opened while off: {} times",
-                status.vtr_open_count
-            );
-        } else {
-            println!("  Unable to tell");
+    } else if args.intrusion_clear {
+        if args.force == 0 {
+            println!("Clearing the intrusion status can't be undone. Add --force to do it anyway");
+            return ExitCode::Usage.into();
         }
-    } else if args.inputmodules {
-        println!("Input Module Status:");
-        if let Some(status) = print_err(ec.get_input_deck_status()) {
-            println!("Input Deck State: {:?}", status.state);
-            println!("Touchpad present: {:?}", status.touchpad_present);
-            println!("Positions:");
-            println!("  Pos 0: {:?}", status.top_row.pos0);
-            println!("  Pos 1: {:?}", status.top_row.pos1);
-            println!("  Pos 2: {:?}", status.top_row.pos2);
-            println!("  Pos 3: {:?}", status.top_row.pos3);
-            println!("  Pos 4: {:?}", status.top_row.pos4);
-        } else {
-            println!("  Unable to tell");
+        if let Err(err) = ec.clear_intrusion_status() {
+            error!("Failed to clear intrusion status: {:?}", err);
+            return ExitCode::from(&err).into();
+        }
+        println!("Intrusion status cleared");
+    } else if let Some(mode) = args.battery_cutoff {
+        if args.force == 0 {
+            println!("Battery cut-off turns off the system until AC is connected. Add --force to do it anyway");
+            return ExitCode::Usage.into();
+        }
+        let mode = mode.unwrap_or(BatteryCutoffArg::Immediate);
+        if let Err(err) = ec.battery_cutoff(mode.into()) {
+            error!("Failed to cut off battery: {:?}", err);
+            return ExitCode::from(&err).into();
         }
+        match mode {
+            BatteryCutoffArg::Immediate => println!("Battery cut off, shut down now"),
+            BatteryCutoffArg::AtShutdown => println!("Battery will be cut off at shutdown"),
+        }
+    } else if let (true, Some(interval)) = (args.intrusion, args.watch) {
+        #[cfg(not(feature = "uefi"))]
+        return exit_code(events::watch_intrusion(
+            ec,
+            interval,
+            args.hook.as_deref(),
+            args.exit_on_open,
+        ));
+        #[cfg(feature = "uefi")]
+        {
+            let _ = interval;
+            println!("Watching the intrusion switch is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if args.intrusion {
+        return run_report(Report::Intrusion, ec, &mut out);
+    } else if args.inputmodules {
+        return run_report(Report::InputModules, ec, &mut out);
     } else if let Some(mode) = &args.input_deck_mode {
         println!("Set mode to: {:?}", mode);
-        ec.set_input_deck_mode((*mode).into()).unwrap();
+        return exit_code(ec.set_input_deck_mode((*mode).into()));
+    } else if args.apply_config {
+        return apply_config(ec);
+    } else if let Some((action, path)) = &args.ec_snapshot {
+        return handle_ec_snapshot(ec, *action, path);
+    } else if let Some((action, path)) = &args.kb_matrix {
+        return handle_kb_matrix(ec, *action, path);
     } else if let Some(maybe_limit) = args.charge_limit {
-        print_err(handle_charge_limit(&ec, maybe_limit));
+        return exit_code(handle_charge_limit(ec, maybe_limit));
+    } else if let Some(maybe_limit) = args.charger_input_limit {
+        let res = handle_charger_input_limit(ec, maybe_limit);
+        if let Err(EcError::Response(EcResponseStatus::AccessDenied)) = res {
+            println!("The EC is locked, it only allows changing the charger when unlocked");
+        }
+        return exit_code(res);
+    } else if let Some(setting) = &args.bios_setting {
+        return handle_bios_setting(ec, setting.as_ref());
+    } else if let Some((action, source)) = &args.wake_sources {
+        return handle_wake_sources(ec, *action, source.as_deref());
+    } else if let Some(param) = &args.charge_param {
+        return handle_charge_param(ec, param.as_ref(), args.force);
     } else if let Some(gpio_name) = &args.get_gpio {
+        let Some(gpio_name) = gpio_name.as_deref().filter(|_| !args.annotated) else {
+            let platform = smbios::get_platform();
+            gpio::print_annotated(ec, platform, gpio_name.as_deref(), &mut StdoutSink);
+            return 0;
+        };
         print!("Getting GPIO value {}: ", gpio_name);
-        if let Ok(value) = ec.get_gpio(gpio_name) {
-            println!("{:?}", value);
-        } else {
-            println!("Not found");
+        match ec.get_gpio(gpio_name) {
+            Ok(value) => println!("{:?}", value),
+            Err(err) => {
+                println!("Not found");
+                return ExitCode::from(&err).into();
+            }
         }
+    } else if let Some((gpio_name, value)) = &args.set_gpio {
+        return set_gpio(ec, gpio_name, *value, args.force);
     } else if let Some(maybe_brightness) = &args.fp_brightness {
-        print_err(handle_fp_brightness(&ec, *maybe_brightness));
+        return exit_code(handle_fp_brightness(ec, *maybe_brightness));
+    } else if let Some(spec) = &args.fp_led_pattern {
+        return play_led_pattern(ec, spec);
+    } else if let Some(led) = args.led {
+        return exit_code(handle_led(ec, led));
+    } else if let Some(mode) = args.tablet_mode_toggle {
+        return exit_code(handle_tablet_mode(ec, mode));
+    } else if args.fansetduty.is_some() || args.autofanctrl.is_some() {
+        return fan_control(ec, args.fansetduty, args.autofanctrl);
     } else if let Some(Some(kblight)) = args.kblight {
         assert!(kblight <= 100);
         ec.set_keyboard_backlight(kblight);
     } else if let Some(None) = args.kblight {
         print!("Keyboard backlight: ");
-        if let Some(percentage) = print_err(ec.get_keyboard_backlight()) {
+        let res = ec.get_keyboard_backlight();
+        if let Ok(percentage) = &res {
             println!("{}%", percentage);
         } else {
             println!("Unable to tell");
         }
+        return exit_code(res);
     } else if let Some(console_arg) = &args.console {
         match console_arg {
-            ConsoleArg::Follow => {
-                // Ignore result because we only finish when it crashes
-                let _res = ec.console_read();
-            }
+            ConsoleArg::Follow => return console_follow(ec, args.duration, args.until.as_deref()),
             ConsoleArg::Recent => match ec.console_read_one() {
                 Ok(output) => println!("{}", output),
-                Err(err) => println!("Failed to read console: {:?}", err),
+                Err(err) => {
+                    println!("Failed to read console: {:?}", err);
+                    return ExitCode::from(&err).into();
+                }
             },
         }
     } else if let Some(reboot_arg) = &args.reboot_ec {
-        match reboot_arg {
-            RebootEcArg::Reboot => match ec.reboot_ec(RebootEcCmd::ColdReboot) {
-                Ok(_) => {}
-                Err(err) => println!("Failed: {:?}", err),
-            },
-            RebootEcArg::JumpRo => match ec.jump_ro() {
-                Ok(_) => {}
-                Err(err) => println!("Failed: {:?}", err),
-            },
-            RebootEcArg::JumpRw => match ec.jump_rw() {
-                Ok(_) => {}
-                Err(err) => println!("Failed: {:?}", err),
-            },
-            RebootEcArg::CancelJump => match ec.cancel_jump() {
-                Ok(_) => {}
-                Err(err) => println!("Failed: {:?}", err),
-            },
-            RebootEcArg::DisableJump => match ec.disable_jump() {
-                Ok(_) => {}
-                Err(err) => println!("Failed: {:?}", err),
-            },
+        let res = match reboot_arg {
+            RebootEcArg::Reboot => ec.reboot_ec(RebootEcCmd::ColdReboot),
+            RebootEcArg::JumpRo => ec.jump_ro(),
+            RebootEcArg::JumpRw => ec.jump_rw(),
+            RebootEcArg::CancelJump => ec.cancel_jump(),
+            RebootEcArg::DisableJump => ec.disable_jump(),
+        };
+        if let Err(err) = &res {
+            println!("Failed: {:?}", err);
+            return ExitCode::from(err).into();
         }
     } else if args.test {
         println!("Self-Test");
-        let result = selftest(&ec);
+        let result = selftest(ec, &mut out);
         if result.is_none() {
             println!("FAILED!!");
-            return 1;
+            return ExitCode::Failure.into();
         }
-    } else if args.power {
-        return power::get_and_print_power_info(&ec);
-    } else if args.thermal {
-        power::print_thermal(&ec);
-    } else if args.sensors {
-        power::print_sensors(&ec);
-    } else if args.pdports {
-        power::get_and_print_pd_info(&ec);
-    } else if args.info {
-        smbios_info();
-    } else if args.pd_info {
-        print_pd_details(&ec);
-    } else if args.dp_hdmi_info {
-        #[cfg(feature = "hidapi")]
-        print_dp_hdmi_details();
-    } else if let Some(pd_bin_path) = &args.dp_hdmi_update {
-        #[cfg(feature = "hidapi")]
-        flash_dp_hdmi_card(pd_bin_path);
-        #[cfg(not(feature = "hidapi"))]
-        let _ = pd_bin_path;
-    } else if args.audio_card_info {
-        #[cfg(feature = "rusb")]
-        print_audio_card_details();
-    } else if args.privacy {
-        if let Some((mic, cam)) = print_err(ec.get_privacy_info()) {
-            println!("Privacy Slider (Black = Device Connected; Red = Device Disconnected)");
-            println!(
-                "  Microphone:  {}",
-                if mic { "Connected" } else { "Disconnected" }
-            );
-            println!(
-                "  Camera:      {}",
-                if cam { "Connected" } else { "Disconnected" }
-            );
-        } else {
-            println!("Not all EC versions support this comand.")
-        };
-    // TODO:
-    //} else if arg == "-raw-command" {
-    //    raw_command(&args[1..]);
-    } else if let Some(pd_bin_path) = &args.pd_bin {
+    } else if let Some(path) = &args.report {
+        #[cfg(not(feature = "uefi"))]
+        return write_health_report(ec, path);
         #[cfg(feature = "uefi")]
-        let data: Option<Vec<u8>> = crate::uefi::fs::shell_read_file(pd_bin_path);
+        {
+            let _ = path;
+            println!("Writing a report is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let Some(iterations) = args.bench_ec {
         #[cfg(not(feature = "uefi"))]
-        let data = match fs::read(pd_bin_path) {
-            Ok(data) => Some(data),
-            // TODO: Perhaps a more user-friendly error
+        return bench_ec(ec, iterations.unwrap_or(1000));
+        #[cfg(feature = "uefi")]
+        {
+            let _ = iterations;
+            println!("Benchmarking is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let Some(on_ac) = args.kblight_auto {
+        if on_ac.map(|x| x > 100).unwrap_or(false) {
+            println!("Keyboard backlight must be between 0% and 100%");
+            return ExitCode::Usage.into();
+        }
+        #[cfg(not(feature = "uefi"))]
+        {
+            let defaults = kblight::KblightAutoConfig::default();
+            let config = kblight::KblightAutoConfig {
+                on_ac: on_ac.unwrap_or(defaults.on_ac),
+                ..defaults
+            };
+            kblight::run(ec, &config, &crate::os_specific::ctrl_c_token());
+        }
+        #[cfg(feature = "uefi")]
+        {
+            println!("Automatic keyboard backlight is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let Some(minutes) = args.suspend_test {
+        if minutes == 0 {
+            println!("Need to suspend for at least one minute");
+            return ExitCode::Usage.into();
+        }
+        #[cfg(not(feature = "uefi"))]
+        match suspend::run(ec, minutes, &mut out) {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::Failure.into(),
+            Err(err) => {
+                println!("Failed to measure suspend drain: {}", err);
+                return ExitCode::Failure.into();
+            }
+        }
+        #[cfg(feature = "uefi")]
+        {
+            println!("Suspending is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if args.tablet_debug {
+        #[cfg(not(feature = "uefi"))]
+        {
+            println!(
+                "Capturing for {}s, rotate the lid through the angles that misbehave",
+                tablet::CAPTURE_DURATION_MS / 1000
+            );
+            let threshold = print_err(ec.get_tablet_mode_threshold());
+            let samples =
+                tablet::capture(ec, tablet::CAPTURE_DURATION_MS, tablet::SAMPLE_INTERVAL_MS);
+            tablet::print_report(&samples, threshold, &mut out);
+        }
+        #[cfg(feature = "uefi")]
+        {
+            println!("Tablet mode debugging is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if args.tablet_watch {
+        #[cfg(not(feature = "uefi"))]
+        {
+            println!("Watching tablet mode, press Ctrl-C to stop");
+            return exit_code(tablet::watch(ec, tablet::SAMPLE_INTERVAL_MS, &mut out));
+        }
+        #[cfg(feature = "uefi")]
+        {
+            println!("Watching tablet mode is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let Some(hook) = &args.watch_events {
+        #[cfg(not(feature = "uefi"))]
+        {
+            let config = util::ConfigFile::load();
+            let enabled = watched_events(&config);
+            events::watch(
+                ec,
+                hook.as_deref(),
+                config.watch_interval_ms,
+                enabled.as_deref(),
+            );
+        }
+        #[cfg(feature = "uefi")]
+        let _ = hook;
+    } else if let Some(hook) = &args.daemon {
+        #[cfg(feature = "daemon")]
+        {
+            let config = util::ConfigFile::load();
+            let enabled = watched_events(&config);
+            return daemon::run(
+                ec,
+                hook.as_deref(),
+                config.watch_interval_ms,
+                enabled.as_deref(),
+                config.kblight_sync.unwrap_or(false),
+            );
+        }
+        #[cfg(not(feature = "daemon"))]
+        {
+            let _ = hook;
+            println!("The daemon is only supported on Linux, when built with the daemon feature");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let (true, Some(interval)) = (args.power, args.watch) {
+        #[cfg(not(feature = "uefi"))]
+        return power::watch_power(ec, interval, args.csv.as_deref());
+        #[cfg(feature = "uefi")]
+        {
+            let _ = interval;
+            println!("Watching power is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if let Some((interval, path)) = &args.thermal_log {
+        #[cfg(not(feature = "uefi"))]
+        return power::thermal_log(ec, *interval, path.as_deref());
+        #[cfg(feature = "uefi")]
+        {
+            let _ = (interval, path);
+            println!("Thermal logging is not supported on UEFI");
+            return ExitCode::Unsupported.into();
+        }
+    } else if args.power {
+        return run_report(Report::Power, ec, &mut out);
+    } else if args.thermal {
+        return run_report(Report::Thermal, ec, &mut out);
+    } else if args.expansion_bay {
+        return run_report(Report::ExpansionBay, ec, &mut out);
+    } else if args.sensors {
+        return run_report(Report::Sensors, ec, &mut out);
+    } else if let Some(sensor) = args.sensor_calibrate {
+        return calibrate_sensor(ec, sensor);
+    } else if let Some((sensor, offset)) = args.sensor_offsets {
+        return sensor_offsets(ec, sensor, offset);
+    } else if args.pdports {
+        return run_report(Report::PdPorts, ec, &mut out);
+    } else if let Some(count) = args.ac_history {
+        // Enough to cover a day of plugging in and out
+        return power::print_ac_history(ec, count.unwrap_or(10), &mut out);
+    } else if let Some((port, role)) = args.typec_role {
+        return set_typec_role(ec, port, role);
+    } else if let Some(millivolts) = args.pd_max_voltage {
+        println!("Limiting sink voltage to {} mV", millivolts);
+        return exit_code(power::set_pd_max_voltage(ec, Some(millivolts)));
+    } else if args.pd_renegotiate {
+        println!("Removing sink voltage limit and renegotiating");
+        return exit_code(power::set_pd_max_voltage(ec, None));
+    } else if args.allupdate {
+        return allupdate(ec);
+    } else if args.thunderbolt {
+        return run_report(Report::Thunderbolt, ec, &mut out);
+    } else if args.ucsi {
+        return run_report(Report::Ucsi, ec, &mut out);
+    } else if args.charger_info {
+        return run_report(Report::ChargerInfo, ec, &mut out);
+    } else if args.info {
+        return run_report(Report::Info, ec, &mut out);
+    } else if args.pd_info {
+        return run_report(Report::PdInfo, ec, &mut out);
+    } else if args.dp_hdmi_info {
+        return run_report(Report::DpHdmiInfo, ec, &mut out);
+    } else if let Some(pd_bin_path) = &args.dp_hdmi_update {
+        #[cfg(feature = "hidapi")]
+        return flash_dp_hdmi_card(
+            pd_bin_path,
+            args.force > 0,
+            args.dry_run,
+            args.dp_hdmi_card.as_deref(),
+        );
+        #[cfg(not(feature = "hidapi"))]
+        let _ = pd_bin_path;
+    } else if args.audio_card_info {
+        return run_report(Report::AudioCardInfo, ec, &mut out);
+    } else if args.privacy {
+        return run_report(Report::Privacy, ec, &mut out);
+    // TODO:
+    //} else if arg == "-raw-command" {
+    //    raw_command(&args[1..]);
+    } else if let Some(port) = args.pps_test {
+        // Ctrl-C stops the test, and still removes the voltage limit
+        #[cfg(not(feature = "uefi"))]
+        let cancel = crate::os_specific::ctrl_c_token();
+        #[cfg(feature = "uefi")]
+        let cancel = chromium_ec::CancelToken::new();
+        return match power::print_charger_test(ec, port, &cancel, &mut out) {
+            Ok(true) => ExitCode::Success.into(),
+            Ok(false) => ExitCode::Failure.into(),
+            res => exit_code(res),
+        };
+    } else if let Some(pd_bin_path) = &args.pd_bin {
+        #[cfg(feature = "uefi")]
+        let data: Option<Vec<u8>> = crate::uefi::fs::read_file(pd_bin_path);
+        #[cfg(not(feature = "uefi"))]
+        let data = match fs::read(pd_bin_path) {
+            Ok(data) => Some(data),
+            // TODO: Perhaps a more user-friendly error
             Err(e) => {
                 println!("Error {:?}", e);
                 None
@@ -839,13 +2020,23 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
 
         if let Some(data) = data {
             println!("File");
-            println!("  Size:       {:>20} B", data.len());
-            println!("  Size:       {:>20} KB", data.len() / 1024);
-            analyze_ccgx_pd_fw(&data);
+            println!("  Size:       {:>20}", units::fmt_size(data.len()));
+            match analyze_ccgx_pd_fw(&data) {
+                // CCG3 is on the DP/HDMI expansion cards, not on the mainboard
+                Some((Ccg3, _)) => {}
+                Some((_, versions)) => {
+                    if is_framework() {
+                        print_pd_flash_verdict(ec, &versions);
+                    }
+                }
+                None => return ExitCode::Failure.into(),
+            }
+        } else {
+            return ExitCode::Failure.into();
         }
     } else if let Some(ec_bin_path) = &args.ec_bin {
         #[cfg(feature = "uefi")]
-        let data: Option<Vec<u8>> = crate::uefi::fs::shell_read_file(ec_bin_path);
+        let data: Option<Vec<u8>> = crate::uefi::fs::read_file(ec_bin_path);
         #[cfg(not(feature = "uefi"))]
         let data = match fs::read(ec_bin_path) {
             Ok(data) => Some(data),
@@ -858,13 +2049,14 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
 
         if let Some(data) = data {
             println!("File");
-            println!("  Size:       {:>20} B", data.len());
-            println!("  Size:       {:>20} KB", data.len() / 1024);
+            println!("  Size:       {:>20}", units::fmt_size(data.len()));
             analyze_ec_fw(&data);
+        } else {
+            return ExitCode::Failure.into();
         }
     } else if let Some(capsule_path) = &args.capsule {
         #[cfg(feature = "uefi")]
-        let data: Option<Vec<u8>> = crate::uefi::fs::shell_read_file(capsule_path);
+        let data: Option<Vec<u8>> = crate::uefi::fs::read_file(capsule_path);
         #[cfg(not(feature = "uefi"))]
         let data = match fs::read(capsule_path) {
             Ok(data) => Some(data),
@@ -877,10 +2069,13 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
 
         if let Some(data) = data {
             println!("File");
-            println!("  Size:       {:>20} B", data.len());
-            println!("  Size:       {:>20} KB", data.len() / 1024);
+            println!("  Size:       {:>20}", units::fmt_size(data.len()));
             if let Some(header) = analyze_capsule(&data) {
                 if header.capsule_guid == esrt::WINUX_GUID {
+                    let resolution = crate::os_specific::native_resolution();
+                    for problem in capsule::validate_ux_capsule(&data, resolution) {
+                        println!("  Warning: {}", problem);
+                    }
                     let ux_header = capsule::parse_ux_header(&data);
                     if let Some(dump_path) = &args.dump {
                         // TODO: Better error handling, rather than just panicking
@@ -889,11 +2084,20 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
                 }
             } else {
                 println!("Capsule is invalid.");
+                return ExitCode::Failure.into();
             }
+        } else {
+            return ExitCode::Failure.into();
         }
+    } else if let Some(path) = &args.identify {
+        return identify_firmware_file(path);
+    } else if let Some((guid, payload_path, out_path)) = &args.make_capsule {
+        return make_capsule(guid, payload_path, out_path);
+    } else if let Some((image_path, out_path)) = &args.make_ux_capsule {
+        return make_ux_capsule(image_path, out_path);
     } else if let Some(capsule_path) = &args.ho2_capsule {
         #[cfg(feature = "uefi")]
-        let data = crate::uefi::fs::shell_read_file(capsule_path);
+        let data = crate::uefi::fs::read_file(capsule_path);
         #[cfg(not(feature = "uefi"))]
         let data = match fs::read(capsule_path) {
             Ok(data) => Some(data),
@@ -906,33 +2110,32 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
 
         if let Some(data) = data {
             println!("File");
-            println!("  Size:       {:>20} B", data.len());
-            println!("  Size:       {:>20} KB", data.len() / 1024);
-            if let Some(cap) = find_bios_version(&data) {
-                println!("  BIOS Platform:{:>18}", cap.platform);
-                println!("  BIOS Version: {:>18}", cap.version);
-            }
-            if let Some(ec_bin) = find_ec_in_bios_cap(&data) {
-                analyze_ec_fw(ec_bin);
-            }
-            if let Some(pd_bin) = find_pd_in_bios_cap(&data) {
-                analyze_ccgx_pd_fw(pd_bin);
+            println!("  Size:       {:>20}", units::fmt_size(data.len()));
+            analyze_h2o_capsule(&data);
+            if let Some(extract_dir) = &args.extract {
+                extract_h2o_capsule(&data, extract_dir);
             }
+        } else {
+            return ExitCode::Failure.into();
         }
     } else if let Some(dump_path) = &args.dump_ec_flash {
         println!("Dumping to {}", dump_path);
         // TODO: Should have progress indicator
-        dump_ec_flash(&ec, dump_path);
+        return dump_ec_flash(ec, dump_path);
     } else if let Some(ec_bin_path) = &args.flash_ec {
-        flash_ec(&ec, ec_bin_path, EcFlashType::Full);
+        return flash_ec(ec, ec_bin_path, EcFlashType::Full);
     } else if let Some(ec_bin_path) = &args.flash_ro_ec {
-        flash_ec(&ec, ec_bin_path, EcFlashType::Ro);
+        return flash_ec(ec, ec_bin_path, EcFlashType::Ro);
     } else if let Some(ec_bin_path) = &args.flash_rw_ec {
-        flash_ec(&ec, ec_bin_path, EcFlashType::Rw);
+        return flash_ec(ec, ec_bin_path, EcFlashType::Rw);
+    } else if args.flash_layout {
+        print_flash_layout(ec);
+    } else if let Some(region) = args.erase_ec_region {
+        return erase_ec_region(ec, region, args.force, args.dry_run);
     } else if let Some(hash_file) = &args.hash {
         println!("Hashing file: {}", hash_file);
         #[cfg(feature = "uefi")]
-        let data = crate::uefi::fs::shell_read_file(hash_file);
+        let data = crate::uefi::fs::read_file(hash_file);
         #[cfg(not(feature = "uefi"))]
         let data = match fs::read(hash_file) {
             Ok(data) => Some(data),
@@ -944,13 +2147,14 @@ pub fn run_with_args(args: &Cli, _allupdate: bool) -> i32 {
         };
         if let Some(data) = data {
             println!("File");
-            println!("  Size:       {:>20} B", data.len());
-            println!("  Size:       {:>20} KB", data.len() / 1024);
+            println!("  Size:       {:>20}", units::fmt_size(data.len()));
             hash(&data);
+        } else {
+            return ExitCode::Failure.into();
         }
     }
 
-    0
+    ExitCode::Success.into()
 }
 
 // Only on UEFI. Clap prints this by itself
@@ -968,33 +2172,68 @@ Options:
       --version              Show tool version information (Add -vv for more detailed information)
       --features             Show features support by the firmware
       --esrt                 Display the UEFI ESRT table
+      --shell                Interactive prompt to run several commands, without initializing again for each
+      --uefi-vars            Display UEFI variables about capsule update results and boot settings
       --device <DEVICE>      Device used to compare firmware version [possible values: bios, ec, pd0, pd1, rtm01, rtm23]
-      --compare-version      Version string used to match firmware version (use with --device)
+      --compare-version      Version string used to match firmware version (use with --device). Can start with <, <=, ==, !=, >= or >
       --power                Show current power status (battery and AC)
       --thermal              Print thermal information (Temperatures and Fan speed)
+      --expansion-bay        Show status of the expansion bay (Framework 16 only), add -v for power, temperatures and fans
       --sensors              Print sensor information (ALS, G-Sensor)
+      --sensor-calibrate <SENSOR>  Calibrate a motion sensor, while the system lies still on a flat surface
+      --sensor-offsets <SENSOR> [<X> <Y> <Z>]  Get or set calibration offsets of a motion sensor
       --pdports              Show information about USB-C PD ports
+      --ac-history [<N>]     Show the last N (default 10) times a charger was attached or detached
+      --typec-role <PORT> <ROLE>  Force power role of a USB-C port [possible values: sink, source, drp]
+      --pd-max-voltage <MV>  Limit the voltage requested from USB-C chargers, in mV
+      --pd-renegotiate       Remove the voltage limit and request new contracts from USB-C chargers
+      --pps-test <PORT>      Test which voltages the charger on a USB-C port supplies, and whether it's stable
+      --thunderbolt          Show Thunderbolt/USB4 routers and their firmware versions
+      --ucsi                 Show USB-C connector status through the UCSI interface of the EC
+      --charger-info         Show vendor, product and advertised capabilities of the attached chargers
       --info                 Show info from SMBIOS (Only on UEFI)
       --pd-info              Show details about the PD controllers
       --privacy              Show privacy switch statuses (camera and microphone)
       --pd-bin <PD_BIN>      Parse versions from PD firmware binary file
       --ec-bin <EC_BIN>      Parse versions from EC firmware binary file
-      --capsule <CAPSULE>    Parse UEFI Capsule information from binary file
+      --capsule [<CAPSULE>]  Parse UEFI Capsule information from binary file, pick one from the drive if not provided
       --dump <DUMP>          Dump extracted UX capsule bitmap image to a file
       --ho2-capsule <HO2_CAPSULE>      Parse UEFI Capsule information from binary file
+      --extract <DIR>                  With --ho2-capsule, write EC, PD and BIOS firmware to separate files
       --dump-ec-flash <DUMP_EC_FLASH>  Dump EC flash contents
-      --flash-ec <FLASH_EC>            Flash EC with new firmware from file
+      --identify <FILE>      Detect which kind of firmware a file is and show its versions
+      --make-capsule <GUID> <PAYLOAD> <OUT>  Wrap a payload in a UEFI capsule. With the Windows UX GUID the payload is a BMP
+      --make-ux-capsule <IMAGE> <OUT>  Build a Windows UX capsule from a BMP image, placed to fit the display
+      --flash-ec [<FLASH_EC>]          Flash EC with new firmware from file, pick one from the drive if not provided
       --flash-ro-ec <FLASH_EC>         Flash EC with new firmware from file
-      --flash-rw-ec <FLASH_EC>         Flash EC with new firmware from file
+      --flash-rw-ec [<FLASH_EC>]       Flash EC with new firmware from file, pick one from the drive if not provided
       --reboot-ec            Control EC RO/RW jump [possible values: reboot, jump-ro, jump-rw, cancel-jump, disable-jump]
       --intrusion            Show status of intrusion switch
+      --intrusion-clear      Reset intrusion switch status and counters (requires --force)
+      --force                Allow operations that can't be undone, give twice for dangerous ones
+      --battery-cutoff [<MODE>]  Disconnect battery until AC is connected, e.g. for storage (requires --force) [possible values: immediate, at-shutdown]
       --inputmodules         Show status of the input modules (Framework 16 only)
       --input-deck-mode      Set input deck power mode [possible values: auto, off, on] (Framework 16 only)
       --charge-limit [<VAL>] Get or set battery charge limit (Percentage number as arg, e.g. '100')
-      --get-gpio <GET_GPIO>  Get GPIO value by name
+      --ec-snapshot <save|restore> <FILE>  Save the EC settings to a file, or restore them, e.g. after reflashing
+      --charger-input-limit [<MA>]  Get or set how much current the charger draws from the power source, in mA
+      --bios-setting [<NAME>] [<VALUE>]  List supported BIOS settings, or get or set one of them until the next boot
+      --get-gpio [<GET_GPIO>]  Get GPIO value by name, or list the known GPIOs of this platform
+      --annotated        Explain what the GPIOs of --get-gpio are for and which level is expected
+      --set-gpio <NAME> <0|1>  Set GPIO value by name (requires --force twice, no GPIO is known to be safe)
       --fp-brightness [<VAL>]Get or set fingerprint LED brightness level [possible values: high, medium, low]
+      --fp-led-pattern <SPEC>  Play a pattern on the power button LED, e.g. 'white:500,off:500*3'
+      --led [<ID>] [<SETTING>]  List LEDs and their colors, or set one to a color, off or auto, e.g. 'left off'
       --kblight [<KBLIGHT>]  Set keyboard backlight percentage or get, if no value provided
+      --tablet-mode-toggle [<MODE>]  Switch between tablet and laptop mode, or force one [possible values: auto, tablet, laptop]
+      --fansetduty [<FANID>] <PERCENT>  Set fan duty cycle (0-100%), for all fans or only the one with FANID
+      --autofanctrl [<FANID>]           Turn on automatic fan speed control, for all fans or only the one with FANID
       --console <CONSOLE>    Get EC console, choose whether recent or to follow the output [possible values: recent, follow]
+      --until <REGEX>        With --console follow, stop after a line that matches REGEX
+      --platform <PLATFORM>  Override platform detection [possible values: intel-gen11, intel-gen12, intel-gen13, intel-core-ultra1, framework13-amd, framework16, desktop-amd-ai300]
+      --ec-timeout <MS>      How long to keep retrying EC commands that failed temporarily, in milliseconds
+      --redact               Hide serial numbers in all output, to share it publicly
+      --units <UNITS>        Show temperatures in Celsius or Fahrenheit [possible values: metric, imperial]
       --hash <HASH>          Hash a file of arbitrary data
   -t, --test                 Run self-test to check if interaction with EC is possible
   -h, --help                 Print help information
@@ -1004,7 +2243,7 @@ Options:
     if updater {
         println!(
             r#"
-        --allupdate   - Run procedure to update everything (Involves some manual steps)
+        --allupdate   - Pick a firmware file from the drive, flash EC firmware or analyze a capsule
     "#
         );
     }
@@ -1040,173 +2279,192 @@ fn hash(data: &[u8]) {
     util::print_buffer_short(sha512);
 }
 
-fn selftest(ec: &CrosEc) -> Option<()> {
+/// Print the result of a self-test step, None if it failed
+fn selftest_step(out: &mut dyn OutputSink, name: &str, ok: bool) -> Option<()> {
+    outln!(out, "  {} - {}", name, if ok { "OK" } else { "FAILED" });
+    ok.then_some(())
+}
+
+fn selftest(ec: &CrosEc, out: &mut dyn OutputSink) -> Option<()> {
     if let Some(platform) = smbios::get_platform() {
-        println!("  SMBIOS Platform:     {:?}", platform);
+        outln!(out, "  SMBIOS Platform:     {:?}", platform);
     } else {
-        println!("  SMBIOS Platform:     Unknown");
-        println!();
-        println!("Specify custom platform parameters with --pd-ports --pd-addrs --has-mec");
+        outln!(out, "  SMBIOS Platform:     Unknown");
+        outln!(out);
+        outln!(
+            out,
+            "Specify custom platform parameters with --pd-ports --pd-addrs --has-mec"
+        );
         return None;
     };
 
-    println!("  Dump EC memory region");
+    outln!(out, "  Dump EC memory region");
     if let Some(mem) = ec.dump_mem_region() {
-        util::print_multiline_buffer(&mem, 0);
+        for line in util::format_multiline_buffer(&mem, 0) {
+            outln!(out, "{}", line);
+        }
     } else {
-        println!("    Failed to read EC memory region")
+        outln!(out, "    Failed to read EC memory region")
     }
 
-    println!("  Checking EC memory mapped magic bytes");
+    outln!(out, "  Checking EC memory mapped magic bytes");
     ec.check_mem_magic()?;
 
-    println!("  Reading EC Build Version");
+    outln!(out, "  Reading EC Build Version");
     print_err(ec.version_info())?;
 
-    print!("  Reading EC Flash by EC");
-    ec.flash_version()?;
-    println!(" - OK");
+    let ok = ec.flash_version().is_some();
+    selftest_step(out, "Reading EC Flash by EC", ok)?;
 
-    println!("  Reading EC Flash directly - See below");
-    ec.test_ec_flash_read().ok()?;
+    outln!(out, "  Reading EC Flash directly - See below");
+    ec.test_ec_flash_read_with(out).ok()?;
 
-    print!("  Getting power info from EC");
-    power::power_info(ec)?;
-    println!(" - OK");
+    let ok = power::power_info(ec).is_some();
+    selftest_step(out, "Getting power info from EC", ok)?;
 
-    println!("  Getting AC info from EC");
-    // All our laptops have at least 4 PD ports so far
-    if power::get_pd_info(ec, 4).iter().any(|x| x.is_err()) {
-        println!("    Failed to get PD Info from EC");
+    let spec = util::platform_spec();
+    outln!(out, "  Getting AC info from EC");
+    let ports = spec.pd_ports.len() as u8;
+    if power::get_pd_info(ec, ports).iter().any(|x| x.is_err()) {
+        outln!(out, "    Failed to get PD Info from EC");
         return None;
     }
 
-    print!("Reading PD Version from EC");
-    if let Err(err) = power::read_pd_version(ec) {
+    match power::read_pd_version(ec) {
+        Ok(_) => outln!(out, "Reading PD Version from EC - OK"),
         // TGL does not have this command, so we have to ignore it
-        if err != EcError::Response(EcResponseStatus::InvalidCommand) {
-            println!();
-            println!("Err: {:?}", err);
-        } else {
-            println!(" - Skipped");
+        Err(EcError::Response(EcResponseStatus::InvalidCommand)) => {
+            outln!(out, "Reading PD Version from EC - Skipped")
+        }
+        Err(err) => {
+            outln!(out, "Reading PD Version from EC");
+            outln!(out, "Err: {:?}", err);
         }
-    } else {
-        println!(" - OK");
     }
 
-    let pd_01 = PdController::new(PdPort::Left01, ec.clone());
-    let pd_23 = PdController::new(PdPort::Right23, ec.clone());
-    print!("  Getting PD01 info through I2C tunnel");
-    print_err(pd_01.get_silicon_id())?;
-    print_err(pd_01.get_device_info())?;
-    print_err(pd_01.get_fw_versions())?;
-    println!(" - OK");
-    print!("  Getting PD23 info through I2C tunnel");
-    print_err(pd_23.get_silicon_id())?;
-    print_err(pd_23.get_device_info())?;
-    print_err(pd_23.get_fw_versions())?;
-    println!(" - OK");
+    let mut controllers = vec![("PD01", PdController::new(PdPort::Left01, ec.clone()))];
+    if spec.has_pd23 {
+        controllers.push(("PD23", PdController::new(PdPort::Right23, ec.clone())));
+    }
+    for (name, pd) in controllers {
+        let ok = print_err(pd.get_silicon_id()).is_some()
+            && print_err(pd.get_device_info()).is_some()
+            && print_err(pd.get_fw_versions()).is_some();
+        selftest_step(
+            out,
+            &format!("Getting {} info through I2C tunnel", name),
+            ok,
+        )?;
+    }
 
     Some(())
 }
 
-fn smbios_info() {
-    println!("Summary");
-    println!("  Is Framework: {}", is_framework());
-    if let Some(platform) = smbios::get_platform() {
-        println!("  Platform:     {:?}", platform);
-    } else {
-        println!("  Platform:     Unknown",);
+/// Run the most important diagnostics and write them all into one file
+#[cfg(not(feature = "uefi"))]
+fn write_health_report(ec: &CrosEc, path: &str) -> i32 {
+    let Some(format) = ReportFormat::from_path(path) else {
+        println!("Unknown report format, the file must end with .md or .html");
+        return ExitCode::Usage.into();
+    };
+    let platform = smbios::get_platform();
+    let mut report = match platform {
+        Some(platform) => HealthReport::new(&format!("Framework System Report - {:?}", platform)),
+        None => HealthReport::new("Framework System Report"),
+    };
+    let success = |code: i32| code == i32::from(ExitCode::Success);
+
+    println!("Collecting report");
+    report.add_section("Versions", |out| {
+        print_versions(ec, out);
+        true
+    });
+    report.add_section("Self-Test", |out| selftest(ec, out).is_some());
+    report.add_section("Power", |out| success(run_report(Report::Power, ec, out)));
+    report.add_section("Thermal", |out| {
+        success(run_report(Report::Thermal, ec, out))
+    });
+    report.add_section("USB-C Ports", |out| {
+        success(run_report(Report::PdPorts, ec, out))
+    });
+    report.add_section("Intrusion", |out| {
+        success(run_report(Report::Intrusion, ec, out))
+    });
+    if platform == Some(Platform::Framework16) {
+        report.add_section("Expansion Bay", |out| {
+            success(run_report(Report::ExpansionBay, ec, out))
+        });
     }
+    report.add_section("EC Console", |out| match ec.console_read_one() {
+        Ok(console) => {
+            for line in console.lines() {
+                outln!(out, "{}", line);
+            }
+            true
+        }
+        Err(err) => {
+            outln!(out, "Failed to read console: {:?}", err);
+            false
+        }
+    });
 
-    let smbios = get_smbios();
-    if smbios.is_none() {
-        error!("Failed to find SMBIOS");
-        return;
+    if let Err(err) = std::fs::write(path, report.render(format)) {
+        println!("Failed to write {}: {:?}", path, err);
+        return ExitCode::Failure.into();
     }
-    for undefined_struct in smbios.unwrap().iter() {
-        match undefined_struct.defined_struct() {
-            DefinedStruct::Information(data) => {
-                println!("BIOS Information");
-                if let Some(vendor) = dmidecode_string_val(&data.vendor()) {
-                    println!("  Vendor:       {}", vendor);
-                }
-                if let Some(version) = dmidecode_string_val(&data.version()) {
-                    println!("  Version:      {}", version);
-                }
-                if let Some(release_date) = dmidecode_string_val(&data.release_date()) {
-                    println!("  Release Date: {}", release_date);
-                }
-            }
-            DefinedStruct::SystemInformation(data) => {
-                println!("System Information");
-                if let Some(version) = dmidecode_string_val(&data.version()) {
-                    // Assumes it's ASCII, which is guaranteed by SMBIOS
-                    let config_digit0 = &version[0..1];
-                    let config_digit0 = u8::from_str_radix(config_digit0, 16);
-                    if let Ok(version_config) =
-                        config_digit0.map(<ConfigDigit0 as FromPrimitive>::from_u8)
-                    {
-                        println!("  Version:      {:?} ({})", version_config, version);
-                    } else {
-                        println!("  Version:      '{}'", version);
-                    }
-                }
-                if let Some(manufacturer) = dmidecode_string_val(&data.manufacturer()) {
-                    println!("  Manufacturer: {}", manufacturer);
-                }
-                if let Some(product_name) = dmidecode_string_val(&data.product_name()) {
-                    println!("  Product Name: {}", product_name);
-                }
-                if let Some(wake_up_type) = data.wakeup_type() {
-                    println!("  Wake-Up-Type: {:?}", wake_up_type.value);
-                }
-                if let Some(sku_number) = dmidecode_string_val(&data.sku_number()) {
-                    println!("  SKU Number:   {}", sku_number);
-                }
-                if let Some(sn) = dmidecode_string_val(&data.serial_number()) {
-                    println!("  Serial Number:{}", sn);
-                }
-                if let Some(family) = dmidecode_string_val(&data.family()) {
-                    println!("  Family:       {}", family);
-                }
-            }
-            DefinedStruct::SystemChassisInformation(data) => {
-                println!("System Chassis Information");
-                if let Some(chassis) = data.chassis_type() {
-                    println!("  Type:         {}", chassis);
-                }
-            }
-            DefinedStruct::BaseBoardInformation(data) => {
-                println!("BaseBoard Information");
-                if let Some(version) = dmidecode_string_val(&data.version()) {
-                    // Assumes it's ASCII, which is guaranteed by SMBIOS
-                    let config_digit0 = &version[0..1];
-                    let config_digit0 = u8::from_str_radix(config_digit0, 16);
-                    if let Ok(version_config) =
-                        config_digit0.map(<ConfigDigit0 as FromPrimitive>::from_u8)
-                    {
-                        println!("  Version:      {:?} ({})", version_config, version);
-                    } else {
-                        println!("  Version:      '{}'", version);
-                    }
-                }
-                if let Some(manufacturer) = dmidecode_string_val(&data.manufacturer()) {
-                    println!("  Manufacturer: {}", manufacturer);
-                }
-                if let Some(product_name) = dmidecode_string_val(&data.product()) {
-                    println!("  Product:      {}", product_name);
-                }
-                if let Some(sn) = dmidecode_string_val(&data.serial_number()) {
-                    println!("  Serial Number:{}", sn);
-                }
-            }
-            _ => {}
+    let failed: Vec<&str> = report
+        .sections
+        .iter()
+        .filter(|section| !section.ok)
+        .map(|section| section.title.as_str())
+        .collect();
+    println!("Report written to {}", path);
+    if !failed.is_empty() {
+        println!("Failed sections: {}", failed.join(", "));
+    }
+    ExitCode::Success.into()
+}
+
+#[cfg(not(feature = "uefi"))]
+fn bench_ec(ec: &CrosEc, iterations: usize) -> i32 {
+    println!("Benchmarking {} host commands", iterations);
+    let bench = match ec.benchmark(iterations) {
+        Ok(bench) => bench,
+        Err(err) => {
+            println!("Failed to benchmark EC: {:?}", err);
+            return 1;
         }
+    };
+
+    println!("  Driver:           {:?}", bench.driver);
+    if let Some(version) = bench.protocol_version {
+        println!("  Protocol Version: v{}", version);
+    } else {
+        println!("  Protocol Version: Unknown");
+    }
+    println!("  Commands:         {}", bench.iterations);
+    println!("  Failed:           {}", bench.failures);
+    println!("  Latency min:      {:?}", bench.min);
+    println!("  Latency avg:      {:?}", bench.avg);
+    println!("  Latency p99:      {:?}", bench.p99);
+    println!("  Latency max:      {:?}", bench.max);
+    let succeeded = bench.iterations - bench.failures;
+    println!(
+        "  Throughput:       {:.1} commands/s",
+        succeeded as f64 / bench.total.as_secs_f64()
+    );
+
+    if bench.failures > 0 {
+        1
+    } else {
+        0
     }
 }
 
-fn analyze_ccgx_pd_fw(data: &[u8]) {
+/// Print information about a PD firmware binary
+///
+/// Returns which controller the firmware is for, and the parsed file
+fn analyze_ccgx_pd_fw(data: &[u8]) -> Option<(ccgx::SiliconId, ccgx::binary::PdFirmwareFile)> {
     if let Some(versions) = ccgx::binary::read_versions(data, Ccg3) {
         println!("Detected CCG3 firmware");
         println!("FW 1");
@@ -1214,6 +2472,7 @@ fn analyze_ccgx_pd_fw(data: &[u8]) {
 
         println!("FW 2");
         ccgx::binary::print_fw(&versions.main_fw);
+        Some((Ccg3, versions))
     } else if let Some(versions) = ccgx::binary::read_versions(data, Ccg8) {
         println!("Detected CCG8 firmware");
         println!("FW 1");
@@ -1221,6 +2480,7 @@ fn analyze_ccgx_pd_fw(data: &[u8]) {
 
         println!("FW 2");
         ccgx::binary::print_fw(&versions.main_fw);
+        Some((Ccg8, versions))
     } else if let Some(versions) = ccgx::binary::read_versions(data, Ccg5) {
         println!("Detected CCG5 firmware");
         println!("FW 1");
@@ -1228,7 +2488,7 @@ fn analyze_ccgx_pd_fw(data: &[u8]) {
 
         println!("FW 2");
         ccgx::binary::print_fw(&versions.main_fw);
-        return;
+        Some((Ccg5, versions))
     } else if let Some(versions) = ccgx::binary::read_versions(data, Ccg6) {
         println!("Detected CCG6 firmware");
         println!("FW 1 (Backup)");
@@ -1236,9 +2496,41 @@ fn analyze_ccgx_pd_fw(data: &[u8]) {
 
         println!("FW 2 (Main)");
         ccgx::binary::print_fw(&versions.main_fw);
-        return;
+        Some((Ccg6, versions))
+    } else {
+        println!("Failed to read versions");
+        None
+    }
+}
+
+/// Check if a PD firmware file fits the PD controllers of this system
+///
+/// Both controllers are checked, since they're flashed with the same file.
+fn print_pd_flash_verdict(ec: &CrosEc, file: &ccgx::binary::PdFirmwareFile) {
+    let platform = smbios::get_platform()
+        .map(|p| format!("{:?}", p))
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    let mut reason = None;
+    for port in [PdPort::Left01, PdPort::Right23] {
+        let name = format!("{:?}", port);
+        let pd = PdController::new(port, ec.clone());
+        let res = match pd.get_silicon_id() {
+            Ok(silicon_id) => file.check_compatible(silicon_id),
+            Err(err) => Err(format!("Failed to read silicon ID: {:?}", err)),
+        };
+        if let Err(err) = res {
+            reason = Some(format!("{}: {}", name, err));
+            break;
+        }
+    }
+
+    println!("Verdict");
+    if let Some(reason) = reason {
+        println!("  NOT safe to flash on platform {}", platform);
+        println!("  {}", reason);
     } else {
-        println!("Failed to read versions")
+        println!("  Safe to flash on platform {}", platform);
     }
 }
 
@@ -1255,65 +2547,135 @@ pub fn analyze_ec_fw(data: &[u8]) {
     } else {
         println!("Failed to read version")
     }
+    ec_binary::print_rwsig(data);
+}
+
+/// Print the BIOS version and the EC and PD firmware included in a H2O capsule
+fn analyze_h2o_capsule(data: &[u8]) {
+    if let Some(cap) = find_bios_version(data) {
+        println!("  BIOS Platform:{:>18}", cap.platform);
+        println!("  BIOS Version: {:>18}", cap.version);
+    }
+    if let Some(ec_bin) = find_ec_in_bios_cap(data) {
+        analyze_ec_fw(ec_bin);
+    }
+    if let Some(pd_bin) = find_pd_in_bios_cap(data) {
+        analyze_ccgx_pd_fw(pd_bin);
+    }
+}
+
+/// Detect what kind of firmware a file is and print its versions
+fn identify_firmware_file(path: &str) -> i32 {
+    let Some(data) = read_file(path) else {
+        return ExitCode::Failure.into();
+    };
+    println!("File");
+    println!("  Size:       {:>20}", units::fmt_size(data.len()));
+    let Some(kind) = firmware_file::identify(&data) else {
+        println!("  Firmware: {:>30}", "Unknown");
+        return ExitCode::Failure.into();
+    };
+    println!("  Firmware: {:>30}", kind.description());
+    match kind {
+        FirmwareFile::Capsule => {
+            analyze_capsule(&data);
+        }
+        FirmwareFile::H2oCapsule => analyze_h2o_capsule(&data),
+        FirmwareFile::Ec => analyze_ec_fw(&data),
+        FirmwareFile::Pd(_) => {
+            analyze_ccgx_pd_fw(&data);
+        }
+        FirmwareFile::Uf2(_) => println!("  UF2 files have no version information"),
+    }
+    ExitCode::Success.into()
 }
 
 pub fn analyze_capsule(data: &[u8]) -> Option<capsule::EfiCapsuleHeader> {
     let header = capsule::parse_capsule_header(data)?;
     capsule::print_capsule_header(&header);
 
-    match header.capsule_guid {
-        esrt::TGL_BIOS_GUID => {
-            println!("  Type:         Framework TGL Insyde BIOS");
-        }
-        esrt::ADL_BIOS_GUID => {
-            println!("  Type:         Framework ADL Insyde BIOS");
-        }
-        esrt::RPL_BIOS_GUID => {
-            println!("  Type:         Framework RPL Insyde BIOS");
-        }
-        esrt::TGL_RETIMER01_GUID => {
-            println!("  Type:    Framework TGL Retimer01 (Left)");
-        }
-        esrt::TGL_RETIMER23_GUID => {
-            println!("  Type:   Framework TGL Retimer23 (Right)");
-        }
-        esrt::ADL_RETIMER01_GUID => {
-            println!("  Type:    Framework ADL Retimer01 (Left)");
-        }
-        esrt::ADL_RETIMER23_GUID => {
-            println!("  Type:   Framework ADL Retimer23 (Right)");
-        }
-        esrt::RPL_RETIMER01_GUID => {
-            println!("  Type:    Framework RPL Retimer01 (Left)");
+    let component = esrt::guid_component(&header.capsule_guid);
+    println!("  Type: {:>34}", esrt::guid_name(&header.capsule_guid));
+
+    match component {
+        Some(esrt::FirmwareComponent::Bios) => {
+            if let Some(cap) = find_bios_version(data) {
+                println!("  BIOS Platform:{:>18}", cap.platform);
+                println!("  BIOS Version: {:>18}", cap.version);
+            }
         }
-        esrt::RPL_RETIMER23_GUID => {
-            println!("  Type:   Framework RPL Retimer23 (Right)");
+        Some(esrt::FirmwareComponent::Retimer01) | Some(esrt::FirmwareComponent::Retimer23) => {
+            if let Some(ver) = find_retimer_version(data) {
+                println!("  Version:      {:>18?}", ver);
+            }
         }
-        esrt::WINUX_GUID => {
-            println!("  Type:            Windows UX capsule");
+        Some(esrt::FirmwareComponent::WinUx) => {
             let ux_header = capsule::parse_ux_header(data);
             capsule::print_ux_header(&ux_header);
         }
-        _ => {
-            println!("  Type:                      Unknown");
-        }
+        _ => {}
     }
 
-    match esrt::match_guid_kind(&header.capsule_guid) {
-        esrt::FrameworkGuidKind::TglRetimer01
-        | esrt::FrameworkGuidKind::TglRetimer23
-        | esrt::FrameworkGuidKind::AdlRetimer01
-        | esrt::FrameworkGuidKind::AdlRetimer23
-        | esrt::FrameworkGuidKind::RplRetimer01
-        | esrt::FrameworkGuidKind::RplRetimer23 => {
-            if let Some(ver) = find_retimer_version(data) {
-                println!("  Version:      {:>18?}", ver);
-            }
+    Some(header)
+}
+
+/// Drive a GPIO, needs --force twice
+///
+/// There's no list of GPIOs that can be driven with a single --force. The EC
+/// only drives outputs and none of them are known to be safe to change.
+fn set_gpio(ec: &CrosEc, name: &str, value: bool, force: u8) -> i32 {
+    if force < 2 {
+        println!("Driving GPIOs can damage hardware. Add --force twice to do it anyway");
+        return ExitCode::Usage.into();
+    }
+    println!("Setting GPIO {} to {}", name, value as u8);
+    let res = ec.set_gpio(name, value);
+    if let Err(EcError::Response(EcResponseStatus::AccessDenied)) = res {
+        println!("The EC is locked, it only allows setting GPIOs when unlocked");
+    }
+    exit_code(res)
+}
+
+/// Follow the EC console until Ctrl-C, the duration has passed or a line matches `until`
+///
+/// Fails if `until` is given but no line matched it.
+fn console_follow(ec: &CrosEc, duration: Option<u64>, until: Option<&str>) -> i32 {
+    let until = match until.map(regex::Regex::new).transpose() {
+        Ok(until) => until,
+        Err(err) => {
+            println!("Invalid value for --until: {}", err);
+            return ExitCode::Usage.into();
         }
-        _ => {}
+    };
+
+    #[cfg(not(feature = "uefi"))]
+    let cancel = crate::os_specific::ctrl_c_token();
+    // The UEFI shell has its own break flag, that the console loop checks
+    #[cfg(feature = "uefi")]
+    let cancel = chromium_ec::CancelToken::new();
+
+    #[cfg(not(feature = "uefi"))]
+    if let Some(secs) = duration {
+        let cancel = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_secs(secs));
+            cancel.cancel();
+        });
     }
+    #[cfg(feature = "uefi")]
+    let _ = duration;
 
-    Some(header)
+    let mut stdout = StdoutSink;
+    let Some(pattern) = until else {
+        return exit_code(ec.console_read_with(&mut stdout, &cancel));
+    };
+    let mut out = UntilSink::new(&mut stdout, pattern, cancel.clone());
+    let res = ec.console_read_with(&mut out, &cancel);
+    if res.is_ok() && !out.matched() {
+        println!("Stopped before any line matched --until");
+        return ExitCode::Failure.into();
+    }
+    exit_code(res)
 }
 
 fn handle_charge_limit(ec: &CrosEc, maybe_limit: Option<u8>) -> EcResult<()> {
@@ -1338,6 +2700,462 @@ fn handle_charge_limit(ec: &CrosEc, maybe_limit: Option<u8>) -> EcResult<()> {
     Ok(())
 }
 
+fn handle_charger_input_limit(ec: &CrosEc, maybe_limit: Option<u32>) -> EcResult<()> {
+    chromium_ec::require_battery("charger input limit")?;
+    if let Some(milliamps) = maybe_limit {
+        power::set_charger_input_limit(ec, milliamps)?;
+    }
+
+    let limit = ec.get_charge_state_param(ChargeStateParam::InputCurrentLimit)?;
+    println!("Input current limit: {} mA", limit);
+
+    Ok(())
+}
+
+/// List the BIOS settings supported on this platform, or get or set one
+fn handle_bios_setting(ec: &CrosEc, setting: Option<&(String, Option<String>)>) -> i32 {
+    let Some(platform) = smbios::get_platform() else {
+        println!("Unknown platform, can't tell which BIOS settings are supported");
+        return ExitCode::Unsupported.into();
+    };
+
+    let Some((name, value)) = setting else {
+        let settings = bios_setting::platform_settings(&platform);
+        if settings.is_empty() {
+            println!("No BIOS settings supported on {:?}", platform);
+            return ExitCode::Unsupported.into();
+        }
+        for setting in settings {
+            println!(
+                "  {:<16} {:<22} {}",
+                setting.name(),
+                bios_setting::fmt_value(&setting.get(ec)),
+                setting.description()
+            );
+        }
+        return ExitCode::Success.into();
+    };
+
+    let setting = match bios_setting::find_setting(&platform, name) {
+        Ok(setting) => setting,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::Usage.into();
+        }
+    };
+    if let Some(value) = value {
+        let value = match setting.parse_value(value) {
+            Ok(value) => value,
+            Err(err) => {
+                println!("{}", err);
+                return ExitCode::Usage.into();
+            }
+        };
+        let res = setting.set(ec, value);
+        if res.is_err() {
+            return exit_code(res);
+        }
+        println!("Set {} to {}", setting.name(), value);
+    } else {
+        println!(
+            "{}: {}",
+            setting.name(),
+            bios_setting::fmt_value(&setting.get(ec))
+        );
+    }
+    ExitCode::Success.into()
+}
+
+/// List which events wake the system from sleep, or enable or disable one
+fn handle_wake_sources(ec: &CrosEc, action: WakeSourcesArg, source: Option<&str>) -> i32 {
+    if action == WakeSourcesArg::List {
+        let masks = match wake_source::get_masks(ec) {
+            Ok(masks) => masks,
+            Err(err) => {
+                error!("Failed to read wake masks: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        };
+        print!("  {:<18}", "Source");
+        for (state, _) in &masks {
+            print!(" {:<6}", state);
+        }
+        println!();
+        for source in wake_source::ALL_SOURCES {
+            print!("  {:<18}", source.name());
+            for (_, mask) in &masks {
+                let enabled = match mask {
+                    Some(mask) if mask & source.mask() != 0 => "on",
+                    Some(_) => "off",
+                    None => "-",
+                };
+                print!(" {:<6}", enabled);
+            }
+            println!(" {}", source.description());
+        }
+        if masks.iter().any(|(_, mask)| mask.is_none()) {
+            println!("  - The EC uses the default of the board");
+        }
+        return ExitCode::Success.into();
+    }
+
+    let Some(name) = source else {
+        println!("--wake-sources enable and disable require a source, e.g. lid_open");
+        return ExitCode::Usage.into();
+    };
+    let source = match wake_source::find_source(name) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::Usage.into();
+        }
+    };
+    let enable = action == WakeSourcesArg::Enable;
+    if let Err(err) = wake_source::set_source(ec, source, enable) {
+        error!("Failed to update wake masks: {:?}", err);
+        return ExitCode::from(&err).into();
+    }
+    println!(
+        "{} {} as wake source, until the EC reboots",
+        if enable { "Enabled" } else { "Disabled" },
+        source.name()
+    );
+    ExitCode::Success.into()
+}
+
+/// List the parameters of the charger, or get or set one
+fn handle_charge_param(ec: &CrosEc, param: Option<&(String, Option<String>)>, force: u8) -> i32 {
+    if let Err(err) = chromium_ec::require_battery("charger parameter") {
+        return exit_code::<()>(Err(err));
+    }
+    let Some((name, value)) = param else {
+        for param in ChargeStateParam::ALL {
+            let value = match ec.get_charge_state_param(*param) {
+                Ok(value) => power::fmt_charge_param(*param, value),
+                Err(_) => "Unknown".to_string(),
+            };
+            println!(
+                "  {:<16} {:<12} {}",
+                param.name(),
+                value,
+                if param.writable() { "" } else { "(read-only)" }
+            );
+        }
+        return ExitCode::Success.into();
+    };
+
+    let Some(param) = ChargeStateParam::from_name(name) else {
+        let names: Vec<&str> = ChargeStateParam::ALL.iter().map(|x| x.name()).collect();
+        println!(
+            "Unknown charger parameter '{}'. Known parameters: {}",
+            name,
+            names.join(", ")
+        );
+        return ExitCode::Usage.into();
+    };
+    if let Some(value) = value {
+        let value = match power::parse_charge_param(param, value) {
+            Ok(value) => value,
+            Err(err) => {
+                println!("{}", err);
+                return ExitCode::Usage.into();
+            }
+        };
+        // The input current limit is safe, it only ever reduces what's drawn from the charger
+        if matches!(
+            param,
+            ChargeStateParam::ChargerVoltage
+                | ChargeStateParam::ChargerCurrent
+                | ChargeStateParam::ChargerOption
+        ) && force == 0
+        {
+            println!(
+                "Setting {} overrides how the EC charges the battery. Add --force to do it anyway",
+                param.name()
+            );
+            return ExitCode::Usage.into();
+        }
+        let res = ec.set_charge_state_param(param, value);
+        if res.is_err() {
+            return exit_code(res);
+        }
+        println!(
+            "Set {} to {}",
+            param.name(),
+            power::fmt_charge_param(param, value)
+        );
+    } else {
+        let res = ec.get_charge_state_param(param);
+        match res {
+            Ok(value) => println!(
+                "{}: {}",
+                param.name(),
+                power::fmt_charge_param(param, value)
+            ),
+            Err(_) => return exit_code(res),
+        }
+    }
+    ExitCode::Success.into()
+}
+
+/// Names of the events to report, from the config file. None for all
+#[cfg(not(feature = "uefi"))]
+fn watched_events(config: &util::ConfigFile) -> Option<Vec<String>> {
+    config.watch_events.as_ref().map(|names| {
+        let names: Vec<String> = names.split(',').map(|x| x.trim().to_string()).collect();
+        for name in names
+            .iter()
+            .filter(|x| !events::EVENT_NAMES.contains(&x.as_str()))
+        {
+            println!(
+                "Unknown event {} in config file. Known events: {}",
+                name,
+                events::EVENT_NAMES.join(", ")
+            );
+        }
+        names
+    })
+}
+
+/// Apply the persistent settings from the config file
+fn apply_config(ec: &CrosEc) -> i32 {
+    #[cfg(feature = "std")]
+    {
+        let config = util::ConfigFile::load();
+        if config.charge_limit.is_none() && config.fan_duty.is_none() {
+            println!("Nothing to apply from config file");
+            return 0;
+        }
+        if let Some(limit) = config.charge_limit {
+            println!("Setting charge limit to {}%", limit);
+            let res = handle_charge_limit(ec, Some(limit));
+            if res.is_err() {
+                return exit_code(res);
+            }
+        }
+        if let Some(duty) = config.fan_duty {
+            let code = fan_control(ec, Some((None, duty as u32)), None);
+            if code != 0 {
+                return code;
+            }
+        }
+        0
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        let _ = ec;
+        println!("Config file is not supported on UEFI");
+        1
+    }
+}
+
+/// Save the EC settings to a file, or apply them from one
+fn handle_ec_snapshot(ec: &CrosEc, action: EcSnapshotArg, path: &str) -> i32 {
+    if action == EcSnapshotArg::Save {
+        let snapshot = ec_snapshot::save(ec);
+        if !write_file(path, snapshot.to_json().as_bytes()) {
+            return ExitCode::Failure.into();
+        }
+        println!("Saved EC settings to {}", path);
+        return ExitCode::Success.into();
+    }
+
+    let Some(data) = read_file(path) else {
+        return ExitCode::Failure.into();
+    };
+    let snapshot = match ec_snapshot::EcSnapshot::from_json(&String::from_utf8_lossy(&data)) {
+        Ok(snapshot) => snapshot,
+        Err(err) => {
+            println!("Invalid snapshot {}: {}", path, err);
+            return ExitCode::Usage.into();
+        }
+    };
+
+    let mut code = ExitCode::Success;
+    for (name, res) in ec_snapshot::restore(ec, &snapshot) {
+        match res {
+            Ok(()) => println!("  {:<16} Restored", name),
+            Err(err) => {
+                println!("  {:<16} Failed: {:?}", name, err);
+                code = ExitCode::from(&err);
+            }
+        }
+    }
+    code.into()
+}
+
+/// Save the scancodes of the keyboard matrix to a file, or apply them from one
+fn handle_kb_matrix(ec: &CrosEc, action: KbMatrixArg, path: &str) -> i32 {
+    if action == KbMatrixArg::Dump {
+        let entries = match kb_matrix::dump(ec) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to read keyboard matrix: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        };
+        if !write_file(path, kb_matrix::to_text(&entries).as_bytes()) {
+            return ExitCode::Failure.into();
+        }
+        println!(
+            "Saved {} keyboard matrix entries to {}",
+            entries.len(),
+            path
+        );
+        return ExitCode::Success.into();
+    }
+
+    let Some(data) = read_file(path) else {
+        return ExitCode::Failure.into();
+    };
+    let entries = match kb_matrix::from_text(&String::from_utf8_lossy(&data)) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("Invalid keyboard matrix {}: {}", path, err);
+            return ExitCode::Usage.into();
+        }
+    };
+    if let Err(err) = kb_matrix::restore(ec, &entries) {
+        error!("Failed to update keyboard matrix: {:?}", err);
+        return ExitCode::from(&err).into();
+    }
+    println!("Restored {} keyboard matrix entries", entries.len());
+    ExitCode::Success.into()
+}
+
+fn set_typec_role(ec: &CrosEc, port: u8, role: TypecRoleArg) -> i32 {
+    // All our platforms have 4 PD ports so far
+    if port >= 4 {
+        println!("Invalid USB-C port {}, must be between 0 and 3", port);
+        return ExitCode::Usage.into();
+    }
+    let res = power::set_pd_port_role(ec, port, role.into());
+    if let Ok(source) = res {
+        let role = if source { "source" } else { "sink" };
+        println!("USB-C Port {} is now a power {}", port, role);
+    }
+    exit_code(res)
+}
+
+fn calibrate_sensor(ec: &CrosEc, sensor: u8) -> i32 {
+    println!(
+        "Calibrating sensor {}, keep the system still and flat",
+        sensor
+    );
+    let res = ec.perform_sensor_calibration(sensor);
+    if let Ok([x, y, z]) = res {
+        println!("New offset: X: {}, Y: {}, Z: {}", x, y, z);
+    }
+    exit_code(res)
+}
+
+/// Show the calibration offsets of a motion sensor, after setting them if provided
+fn sensor_offsets(ec: &CrosEc, sensor: u8, offset: Option<[i16; 3]>) -> i32 {
+    if let Some(offset) = offset {
+        let res = ec.set_sensor_offset(sensor, offset);
+        if res.is_err() {
+            return exit_code(res);
+        }
+    }
+    let res = ec.get_sensor_offset(sensor);
+    if let Ok([x, y, z]) = res {
+        println!("Sensor {} offset: X: {}, Y: {}, Z: {}", sensor, x, y, z);
+    }
+    exit_code(res)
+}
+
+/// Switch fans to a fixed duty cycle or back to automatic control
+///
+/// Both can be combined to control the two fans of the Framework 16 separately,
+/// e.g. `--fansetduty 1 60 --autofanctrl 0`.
+fn fan_control(ec: &CrosEc, duty: Option<(Option<u8>, u32)>, auto: Option<Option<u8>>) -> i32 {
+    if let (Some((duty_fan, _)), Some(auto_fan)) = (duty, auto) {
+        if duty_fan.is_none() || auto_fan.is_none() || duty_fan == auto_fan {
+            println!("A fan can't be in manual and automatic control at the same time");
+            return ExitCode::Usage.into();
+        }
+    }
+    if let Some((_, percent)) = duty {
+        if percent > 100 {
+            println!("Fan duty must be between 0% and 100%");
+            return ExitCode::Usage.into();
+        }
+    }
+    if let Some(fans) = power::get_fan_speeds(ec) {
+        let selected = [duty.and_then(|(fan, _)| fan), auto.flatten()];
+        for fan in selected.iter().flatten() {
+            if *fan as usize >= fans.len() {
+                println!(
+                    "Fan {} not present, this system has {} fans",
+                    fan,
+                    fans.len()
+                );
+                return ExitCode::Usage.into();
+            }
+        }
+    }
+
+    if let Some((fan, percent)) = duty {
+        let res = ec.fan_set_duty(fan, percent);
+        if res.is_err() {
+            return exit_code(res);
+        }
+        match fan {
+            Some(fan) => println!("Set fan {} duty to {}%", fan, percent),
+            None => println!("Set duty of all fans to {}%", percent),
+        }
+    }
+    if let Some(fan) = auto {
+        let res = ec.autofanctrl(fan);
+        if res.is_err() {
+            return exit_code(res);
+        }
+        match fan {
+            Some(fan) => println!("Enabled automatic control of fan {}", fan),
+            None => println!("Enabled automatic control of all fans"),
+        }
+    }
+    ExitCode::Success.into()
+}
+
+fn play_led_pattern(ec: &CrosEc, spec: &str) -> i32 {
+    let pattern = match led::LedPattern::parse(spec) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            println!("Invalid LED pattern: {}", err);
+            return ExitCode::Usage.into();
+        }
+    };
+    println!(
+        "Playing pattern on the power button LED for {} ms",
+        pattern.duration_ms()
+    );
+    exit_code(led::play(ec, EcLedId::PowerLed, &pattern))
+}
+
+fn handle_led(ec: &CrosEc, led: Option<(EcLedId, Option<led::LedSetting>)>) -> EcResult<()> {
+    if let Some((id, Some(setting))) = led {
+        return led::set(ec, id, setting);
+    }
+    led::print_leds(ec, led.map(|(id, _)| id), &mut StdoutSink)
+}
+
+/// Force tablet or laptop mode, or switch to the other one if no mode is given
+fn handle_tablet_mode(ec: &CrosEc, mode: Option<TabletModeArg>) -> EcResult<()> {
+    let mode = match mode {
+        Some(mode) => mode,
+        None if ec.get_tablet_mode()? => TabletModeArg::Laptop,
+        None => TabletModeArg::Tablet,
+    };
+    ec.set_tablet_mode(mode.into())?;
+    match mode {
+        TabletModeArg::Auto => println!("Tablet mode: auto (based on the hinge angle)"),
+        TabletModeArg::Tablet => println!("Tablet mode: forced on, until set to auto"),
+        TabletModeArg::Laptop => println!("Tablet mode: forced off, until set to auto"),
+    }
+    Ok(())
+}
+
 fn handle_fp_brightness(ec: &CrosEc, maybe_brightness: Option<FpBrightnessArg>) -> EcResult<()> {
     if let Some(brightness) = maybe_brightness {
         ec.set_fp_led_level(brightness.into())?;
@@ -0,0 +1,117 @@
+//! Interactive prompt to run commands one after another
+//!
+//! The EC driver is only probed once and SMBIOS is only parsed once, which
+//! makes running many commands much faster than starting the tool each time.
+//! Especially on UEFI.
+//!
+//! Commands are the same as the commandline options, but without the dashes:
+//!
+//! ```text
+//! > power
+//! > kblight 50
+//! > console recent
+//! ```
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::chromium_ec::CrosEc;
+use crate::commandline::{run_command, ExitCode};
+
+/// Read one line from the user, None if there's no more input
+#[cfg(not(feature = "uefi"))]
+fn read_line() -> Option<String> {
+    use std::io::Write;
+
+    print!("> ");
+    let _ = std::io::stdout().flush();
+    let mut line = String::new();
+    match std::io::stdin().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+/// Read one line from the user, None if there's no more input
+#[cfg(feature = "uefi")]
+fn read_line() -> Option<String> {
+    print!("> ");
+    crate::uefi::read_line()
+}
+
+/// Turn a line like `kblight 50` into the arguments `--kblight 50`
+fn line_to_args(line: &str) -> Vec<String> {
+    let mut words = line.split_whitespace();
+    let mut args = vec!["framework_tool".to_string()];
+    if let Some(command) = words.next() {
+        if command.starts_with('-') {
+            args.push(command.to_string());
+        } else {
+            args.push(format!("--{}", command));
+        }
+    }
+    args.extend(words.map(|x| x.to_string()));
+    args
+}
+
+#[cfg(not(feature = "uefi"))]
+fn parse(args: &[String]) -> Option<super::Cli> {
+    match super::clap_std::try_parse(args) {
+        Ok(cli) => Some(cli),
+        Err(err) => {
+            // Prints the help, if that was requested
+            let _ = err.print();
+            None
+        }
+    }
+}
+
+#[cfg(feature = "uefi")]
+fn parse(args: &[String]) -> Option<super::Cli> {
+    Some(super::uefi::parse(args))
+}
+
+/// Keep reading and running commands, until the user exits
+pub fn run(ec: &CrosEc) -> i32 {
+    println!("Type a command without the leading dashes, e.g. 'power' or 'kblight 50'");
+    println!("Type 'help' to see all commands and 'exit' to quit");
+    while let Some(line) = read_line() {
+        match line.trim() {
+            "" => continue,
+            "exit" | "quit" => break,
+            "shell" => {
+                println!("Already in the shell");
+                continue;
+            }
+            line => {
+                let Some(args) = parse(&line_to_args(line)) else {
+                    continue;
+                };
+                let code = run_command(&args, ec, false);
+                if code != i32::from(ExitCode::Success) && !args.help {
+                    println!("Exit code: {}", code);
+                }
+            }
+        }
+    }
+    ExitCode::Success.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_line() {
+        assert_eq!(
+            line_to_args("kblight 50"),
+            vec!["framework_tool", "--kblight", "50"]
+        );
+        assert_eq!(
+            line_to_args("  --power  --watch 1000 "),
+            vec!["framework_tool", "--power", "--watch", "1000"]
+        );
+    }
+}
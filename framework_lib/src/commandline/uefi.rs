@@ -1,4 +1,4 @@
-use alloc::string::String;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 
@@ -9,10 +9,50 @@ use uefi::proto::shell_params::*;
 use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams, SearchType};
 use uefi::Identify;
 
-use crate::chromium_ec::{CrosEcDriverType, HardwareDeviceType};
-use crate::commandline::Cli;
+use crate::chromium_ec::{CrosEcDriverType, EcFlashRegion, HardwareDeviceType};
+use crate::commandline::{run_with_args, Cli};
+use crate::led;
+use crate::uefi::fs::{pick_firmware_file, FirmwareFileKind};
+use crate::uefi::{clear_screen, select, wait_for_key};
 
-use super::{ConsoleArg, FpBrightnessArg, InputDeckModeArg, RebootEcArg};
+use super::{
+    BatteryCutoffArg, ConsoleArg, EcSnapshotArg, FpBrightnessArg, InputDeckModeArg, KbMatrixArg,
+    PlatformArg, RebootEcArg, TabletModeArg, TypecRoleArg, UnitsArg, WakeSourcesArg,
+};
+
+/// Entries of the interactive menu and the arguments they run the tool with
+const MENU: &[(&str, &[&str])] = &[
+    ("Show firmware versions", &["--versions"]),
+    ("Run self-test", &["--test"]),
+    ("Flash EC firmware", &["--flash-rw-ec"]),
+    (
+        "Dump EC flash to ec_dump.bin",
+        &["--dump-ec-flash", "ec_dump.bin"],
+    ),
+    ("Show power information", &["--power"]),
+    ("Exit", &[]),
+];
+
+/// Show a menu of common actions, for when no arguments were provided
+///
+/// Keeps showing it after every action until the user chooses to exit.
+pub fn run_menu() -> i32 {
+    let items: Vec<String> = MENU.iter().map(|(name, _)| name.to_string()).collect();
+    loop {
+        let title = "Framework System - Select an action";
+        let args = match select(title, &items) {
+            Some(i) if !MENU[i].1.is_empty() => MENU[i].1,
+            _ => return 0,
+        };
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        clear_screen();
+        run_with_args(&parse(&args), false);
+
+        println!();
+        println!("Press any key to return to the menu");
+        wait_for_key();
+    }
+}
 
 /// Get commandline arguments from UEFI environment
 pub fn get_args(boot_services: &BootServices) -> Vec<String> {
@@ -59,15 +99,32 @@ pub fn parse(args: &[String]) -> Cli {
         version: false,
         features: false,
         esrt: false,
+        uefi_vars: false,
         device: None,
         compare_version: None,
         power: false,
+        // Needs a timer, not implemented on UEFI
+        watch: None,
+        csv: None,
         thermal: false,
+        thermal_log: None,
+        expansion_bay: false,
         sensors: false,
+        sensor_calibrate: None,
+        sensor_offsets: None,
         pdports: false,
+        ac_history: None,
+        typec_role: None,
+        pd_max_voltage: None,
+        pd_renegotiate: false,
+        pps_test: None,
+        thunderbolt: false,
+        ucsi: false,
+        charger_info: false,
         pd_info: false,
         dp_hdmi_info: false,
         dp_hdmi_update: None,
+        dp_hdmi_card: None,
         audio_card_info: false,
         privacy: false,
         pd_bin: None,
@@ -76,25 +133,78 @@ pub fn parse(args: &[String]) -> Cli {
         flash_ec: None,
         flash_ro_ec: None,
         flash_rw_ec: None,
+        flash_layout: false,
+        erase_ec_region: None,
         capsule: None,
         dump: None,
         ho2_capsule: None,
+        identify: None,
+        make_capsule: None,
+        make_ux_capsule: None,
+        extract: None,
         intrusion: false,
+        intrusion_clear: false,
+        // Watching is not supported on UEFI
+        exit_on_open: false,
+        hook: None,
+        force: 0,
+        dry_run: false,
+        battery_cutoff: None,
         inputmodules: false,
         input_deck_mode: None,
         charge_limit: None,
+        charger_input_limit: None,
+        charge_param: None,
+        bios_setting: None,
+        wake_sources: None,
         get_gpio: None,
+        annotated: false,
+        set_gpio: None,
         fp_brightness: None,
+        fp_led_pattern: None,
+        led: None,
         kblight: None,
+        fansetduty: None,
+        autofanctrl: None,
         console: None,
+        // No timer to stop after a duration on UEFI
+        duration: None,
+        until: None,
         reboot_ec: None,
         hash: None,
         // This is the only driver that works on UEFI
         driver: Some(CrosEcDriverType::Portio),
+        ec_timeout: None,
+        trace_ec: None,
+        replay_ec: None,
+        redact: false,
+        units: None,
         pd_addrs: None,
         pd_ports: None,
         has_mec: None,
+        platform: None,
+        helper: false,
+        install_udev_rules: false,
+        windows_service: None,
+        shell: false,
+        // Needs to spawn processes, not supported on UEFI
+        watch_events: None,
+        // Runs forever in the background, not useful on UEFI
+        daemon: None,
+        tray: false,
+        kblight_auto: None,
+        tablet_debug: false,
+        tablet_watch: false,
+        tablet_mode_toggle: None,
+        suspend_test: None,
         test: false,
+        report: None,
+        // Needs a timer, not implemented on UEFI
+        bench_ec: None,
+        // No config file on UEFI
+        apply_config: false,
+        ec_snapshot: None,
+        kb_matrix: None,
         help: false,
         allupdate: false,
         info: false,
@@ -130,21 +240,142 @@ pub fn parse(args: &[String]) -> Cli {
         } else if arg == "-b" {
             cli.paginate = true;
             found_an_option = true;
+        } else if arg == "--shell" {
+            cli.shell = true;
+            found_an_option = true;
         } else if arg == "--esrt" {
             cli.esrt = true;
             found_an_option = true;
+        } else if arg == "--uefi-vars" {
+            cli.uefi_vars = true;
+            found_an_option = true;
         } else if arg == "--power" {
             cli.power = true;
             found_an_option = true;
         } else if arg == "--thermal" {
             cli.thermal = true;
             found_an_option = true;
+        } else if arg == "--expansion-bay" {
+            cli.expansion_bay = true;
+            found_an_option = true;
         } else if arg == "--sensors" {
             cli.sensors = true;
             found_an_option = true;
+        } else if arg == "--sensor-calibrate" {
+            cli.sensor_calibrate = if args.len() > i + 1 {
+                if let Ok(sensor) = args[i + 1].parse::<u8>() {
+                    Some(sensor)
+                } else {
+                    println!(
+                        "Invalid value for --sensor-calibrate: '{}'. Must be a sensor index.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("--sensor-calibrate requires the sensor index");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--sensor-offsets" {
+            let values: Vec<i16> = args[i + 1..]
+                .iter()
+                .take(4)
+                .map_while(|x| x.parse::<i16>().ok())
+                .collect();
+            cli.sensor_offsets = match values.as_slice() {
+                [sensor, x, y, z] => {
+                    Some((u8::try_from(*sensor).unwrap_or(u8::MAX), Some([*x, *y, *z])))
+                }
+                [sensor, ..] => Some((u8::try_from(*sensor).unwrap_or(u8::MAX), None)),
+                [] => {
+                    println!("--sensor-offsets requires the sensor index, optionally followed by X, Y and Z");
+                    None
+                }
+            };
+            found_an_option = true;
         } else if arg == "--pdports" {
             cli.pdports = true;
             found_an_option = true;
+        } else if arg == "--ac-history" {
+            cli.ac_history = if args.len() > i + 1 {
+                if let Ok(count) = args[i + 1].parse::<usize>() {
+                    Some(Some(count))
+                } else {
+                    Some(None)
+                }
+            } else {
+                Some(None)
+            };
+            found_an_option = true;
+        } else if arg == "--typec-role" {
+            cli.typec_role = if args.len() > i + 2 {
+                let port = args[i + 1].parse::<u8>();
+                let role = match args[i + 2].as_str() {
+                    "sink" => Some(TypecRoleArg::Sink),
+                    "source" => Some(TypecRoleArg::Source),
+                    "drp" => Some(TypecRoleArg::Drp),
+                    _ => None,
+                };
+                if let (Ok(port), Some(role)) = (port, role) {
+                    Some((port, role))
+                } else {
+                    println!(
+                        "Invalid values for --typec-role: '{} {}'. Must be port number and sink, source or drp.",
+                        args[i + 1],
+                        args[i + 2]
+                    );
+                    None
+                }
+            } else {
+                println!("--typec-role requires two arguments, the port and the role");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--pd-max-voltage" {
+            cli.pd_max_voltage = if args.len() > i + 1 {
+                if let Ok(millivolts) = args[i + 1].parse::<u16>() {
+                    Some(millivolts)
+                } else {
+                    println!(
+                        "Invalid value for --pd-max-voltage: '{}'. Must be millivolts.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("--pd-max-voltage requires the voltage in millivolts");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--pd-renegotiate" {
+            cli.pd_renegotiate = true;
+            found_an_option = true;
+        } else if arg == "--pps-test" {
+            cli.pps_test = if args.len() > i + 1 {
+                if let Ok(port) = args[i + 1].parse::<u8>() {
+                    Some(port)
+                } else {
+                    println!(
+                        "Invalid value for --pps-test: '{}'. Must be a port number.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("--pps-test requires the port number");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--thunderbolt" {
+            cli.thunderbolt = true;
+            found_an_option = true;
+        } else if arg == "--ucsi" {
+            cli.ucsi = true;
+            found_an_option = true;
+        } else if arg == "--charger-info" {
+            cli.charger_info = true;
+            found_an_option = true;
         } else if arg == "--allupdate" {
             cli.allupdate = true;
             found_an_option = true;
@@ -154,6 +385,37 @@ pub fn parse(args: &[String]) -> Cli {
         } else if arg == "--intrusion" {
             cli.intrusion = true;
             found_an_option = true;
+        } else if arg == "--intrusion-clear" {
+            cli.intrusion_clear = true;
+            found_an_option = true;
+        } else if arg == "--battery-cutoff" {
+            cli.battery_cutoff = if args.len() > i + 1 && args[i + 1] == "at-shutdown" {
+                Some(Some(BatteryCutoffArg::AtShutdown))
+            } else if args.len() > i + 1 && args[i + 1] == "immediate" {
+                Some(Some(BatteryCutoffArg::Immediate))
+            } else {
+                Some(None)
+            };
+            found_an_option = true;
+        } else if arg == "--redact" {
+            cli.redact = true;
+        } else if arg == "--units" {
+            cli.units = if args.len() > i + 1 {
+                match args[i + 1].as_str() {
+                    "metric" => Some(UnitsArg::Metric),
+                    "imperial" => Some(UnitsArg::Imperial),
+                    _ => {
+                        println!("Invalid value for --units: {}", args[i + 1]);
+                        None
+                    }
+                }
+            } else {
+                println!("Need to provide a value for --units");
+                None
+            };
+        } else if arg == "--force" {
+            cli.force = cli.force.saturating_add(1);
+            found_an_option = true;
         } else if arg == "--inputmodules" {
             cli.inputmodules = true;
             found_an_option = true;
@@ -177,6 +439,48 @@ pub fn parse(args: &[String]) -> Cli {
                 None
             };
             found_an_option = true;
+        } else if arg == "--ec-snapshot" {
+            cli.ec_snapshot = if args.len() > i + 2 {
+                let action = match args[i + 1].as_str() {
+                    "save" => Some(EcSnapshotArg::Save),
+                    "restore" => Some(EcSnapshotArg::Restore),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    Some((action, args[i + 2].clone()))
+                } else {
+                    println!(
+                        "Invalid value for --ec-snapshot: '{}'. Must be save or restore.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("--ec-snapshot requires two arguments, save or restore and the file");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--kb-matrix" {
+            cli.kb_matrix = if args.len() > i + 2 {
+                let action = match args[i + 1].as_str() {
+                    "dump" => Some(KbMatrixArg::Dump),
+                    "restore" => Some(KbMatrixArg::Restore),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    Some((action, args[i + 2].clone()))
+                } else {
+                    println!(
+                        "Invalid value for --kb-matrix: '{}'. Must be dump or restore.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("--kb-matrix requires two arguments, dump or restore and the file");
+                None
+            };
+            found_an_option = true;
         } else if arg == "--charge-limit" {
             cli.charge_limit = if args.len() > i + 1 {
                 if let Ok(percent) = args[i + 1].parse::<u8>() {
@@ -192,10 +496,99 @@ pub fn parse(args: &[String]) -> Cli {
                 Some(None)
             };
             found_an_option = true;
+        } else if arg == "--charger-input-limit" {
+            cli.charger_input_limit = if args.len() > i + 1 {
+                if let Ok(milliamps) = args[i + 1].parse::<u32>() {
+                    Some(Some(milliamps))
+                } else {
+                    println!(
+                        "Invalid value for --charger-input-limit: '{}'. Must be milliamps.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                Some(None)
+            };
+            found_an_option = true;
+        } else if arg == "--bios-setting" {
+            let values: Vec<String> = args[i + 1..]
+                .iter()
+                .take(2)
+                .take_while(|x| !x.starts_with("--"))
+                .cloned()
+                .collect();
+            cli.bios_setting = match values.as_slice() {
+                [name] => Some(Some((name.clone(), None))),
+                [name, value] => Some(Some((name.clone(), Some(value.clone())))),
+                _ => Some(None),
+            };
+            found_an_option = true;
+        } else if arg == "--charge-param" {
+            let values: Vec<String> = args[i + 1..]
+                .iter()
+                .take(2)
+                .take_while(|x| !x.starts_with("--"))
+                .cloned()
+                .collect();
+            cli.charge_param = match values.as_slice() {
+                [name] => Some(Some((name.clone(), None))),
+                [name, value] => Some(Some((name.clone(), Some(value.clone())))),
+                _ => Some(None),
+            };
+            found_an_option = true;
+        } else if arg == "--wake-sources" {
+            let values: Vec<String> = args[i + 1..]
+                .iter()
+                .take(2)
+                .take_while(|x| !x.starts_with("--"))
+                .cloned()
+                .collect();
+            cli.wake_sources = match values.as_slice() {
+                [] => Some((WakeSourcesArg::List, None)),
+                [action, source @ ..] => {
+                    let action = match action.as_str() {
+                        "list" => Some(WakeSourcesArg::List),
+                        "enable" => Some(WakeSourcesArg::Enable),
+                        "disable" => Some(WakeSourcesArg::Disable),
+                        _ => None,
+                    };
+                    if let Some(action) = action {
+                        Some((action, source.first().cloned()))
+                    } else {
+                        println!(
+                            "Invalid value for --wake-sources: '{}'. Must be list, enable or disable.",
+                            values[0]
+                        );
+                        None
+                    }
+                }
+            };
+            found_an_option = true;
         } else if arg == "--get-gpio" {
-            cli.get_gpio = if args.len() > i + 1 {
-                Some(args[i + 1].clone())
+            cli.get_gpio = if args.len() > i + 1 && !args[i + 1].starts_with("--") {
+                Some(Some(args[i + 1].clone()))
             } else {
+                Some(None)
+            };
+            found_an_option = true;
+        } else if arg == "--annotated" {
+            cli.annotated = true;
+        } else if arg == "--set-gpio" {
+            cli.set_gpio = if args.len() > i + 2 {
+                match args[i + 2].as_str() {
+                    "0" => Some((args[i + 1].clone(), false)),
+                    "1" => Some((args[i + 1].clone(), true)),
+                    _ => {
+                        println!(
+                            "Invalid value for --set-gpio: '{}'. Must be 0 or 1.",
+                            args[i + 2]
+                        );
+                        None
+                    }
+                }
+            } else {
+                println!("--set-gpio requires two arguments, the name and the value");
                 None
             };
             found_an_option = true;
@@ -214,6 +607,47 @@ pub fn parse(args: &[String]) -> Cli {
                 Some(None)
             };
             found_an_option = true;
+        } else if arg == "--fansetduty" {
+            let values: Vec<u32> = args[i + 1..]
+                .iter()
+                .take(2)
+                .map_while(|x| x.parse::<u32>().ok())
+                .collect();
+            cli.fansetduty = match values.as_slice() {
+                [fan, percent] => Some((Some(u8::try_from(*fan).unwrap_or(u8::MAX)), *percent)),
+                [percent] => Some((None, *percent)),
+                _ => {
+                    println!("--fansetduty requires the duty in percent, optionally preceded by the fan index");
+                    None
+                }
+            };
+            found_an_option = true;
+        } else if arg == "--autofanctrl" {
+            cli.autofanctrl = if args.len() > i + 1 {
+                if let Ok(fan) = args[i + 1].parse::<u8>() {
+                    Some(Some(fan))
+                } else {
+                    Some(None)
+                }
+            } else {
+                Some(None)
+            };
+            found_an_option = true;
+        } else if arg == "--ec-timeout" {
+            cli.ec_timeout = if args.len() > i + 1 {
+                if let Ok(timeout) = args[i + 1].parse::<u64>() {
+                    Some(timeout)
+                } else {
+                    println!(
+                        "Invalid value for --ec-timeout: '{}'. Must be milliseconds.",
+                        args[i + 1]
+                    );
+                    None
+                }
+            } else {
+                println!("Need to provide a value for --ec-timeout");
+                None
+            };
         } else if arg == "--fp-brightness" {
             cli.fp_brightness = if args.len() > i + 1 {
                 let fp_brightness_arg = &args[i + 1];
@@ -231,6 +665,44 @@ pub fn parse(args: &[String]) -> Cli {
                 Some(None)
             };
             found_an_option = true;
+        } else if arg == "--fp-led-pattern" {
+            cli.fp_led_pattern = if args.len() > i + 1 {
+                Some(args[i + 1].clone())
+            } else {
+                println!("--fp-led-pattern requires the pattern, e.g. 'white:500,off:500*3'");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--led" {
+            let values: Vec<&str> = args[i + 1..]
+                .iter()
+                .take(2)
+                .take_while(|x| !x.starts_with("--"))
+                .map(|x| x.as_str())
+                .collect();
+            cli.led = match led::parse_led_args(&values) {
+                Ok(led) => Some(led),
+                Err(err) => {
+                    println!("Invalid values for --led: {}", err);
+                    None
+                }
+            };
+            found_an_option = true;
+        } else if arg == "--tablet-mode-toggle" {
+            cli.tablet_mode_toggle = if args.len() > i + 1 && !args[i + 1].starts_with("--") {
+                match args[i + 1].as_str() {
+                    "auto" => Some(Some(TabletModeArg::Auto)),
+                    "tablet" => Some(Some(TabletModeArg::Tablet)),
+                    "laptop" => Some(Some(TabletModeArg::Laptop)),
+                    mode_arg => {
+                        println!("Invalid value for --tablet-mode-toggle: {}", mode_arg);
+                        None
+                    }
+                }
+            } else {
+                Some(None)
+            };
+            found_an_option = true;
         } else if arg == "--console" {
             cli.console = if args.len() > i + 1 {
                 let console_arg = &args[i + 1];
@@ -247,6 +719,13 @@ pub fn parse(args: &[String]) -> Cli {
                 None
             };
             found_an_option = true;
+        } else if arg == "--until" {
+            cli.until = if args.len() > i + 1 {
+                Some(args[i + 1].clone())
+            } else {
+                println!("Need to provide a value for --until. The regular expression to wait for");
+                None
+            };
         } else if arg == "--reboot-ec" {
             cli.reboot_ec = if args.len() > i + 1 {
                 let reboot_ec_arg = &args[i + 1];
@@ -301,8 +780,8 @@ pub fn parse(args: &[String]) -> Cli {
             cli.capsule = if args.len() > i + 1 {
                 Some(args[i + 1].clone())
             } else {
-                println!("--capsule requires extra argument to denote input file");
-                None
+                // Let the user choose from the firmware files that we can find
+                pick_firmware_file(&[FirmwareFileKind::Capsule])
             };
             found_an_option = true;
         } else if arg == "--dump" {
@@ -321,6 +800,42 @@ pub fn parse(args: &[String]) -> Cli {
                 None
             };
             found_an_option = true;
+        } else if arg == "--identify" {
+            cli.identify = if args.len() > i + 1 {
+                Some(args[i + 1].clone())
+            } else {
+                println!("--identify requires extra argument to denote input file");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--make-capsule" {
+            cli.make_capsule = if args.len() > i + 3 {
+                Some((
+                    args[i + 1].clone(),
+                    args[i + 2].clone(),
+                    args[i + 3].clone(),
+                ))
+            } else {
+                println!("--make-capsule requires three arguments, the GUID, the payload and the output file");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--make-ux-capsule" {
+            cli.make_ux_capsule = if args.len() > i + 2 {
+                Some((args[i + 1].clone(), args[i + 2].clone()))
+            } else {
+                println!("--make-ux-capsule requires two arguments, the image and the output file");
+                None
+            };
+            found_an_option = true;
+        } else if arg == "--extract" {
+            cli.extract = if args.len() > i + 1 {
+                Some(args[i + 1].clone())
+            } else {
+                println!("--extract requires extra argument to denote output directory");
+                None
+            };
+            found_an_option = true;
         } else if arg == "--dump-ec-flash" {
             cli.dump_ec_flash = if args.len() > i + 1 {
                 Some(args[i + 1].clone())
@@ -333,8 +848,8 @@ pub fn parse(args: &[String]) -> Cli {
             cli.flash_ec = if args.len() > i + 1 {
                 Some(args[i + 1].clone())
             } else {
-                println!("--flash-ec requires extra argument to denote input file");
-                None
+                // Let the user choose from the firmware files that we can find
+                pick_firmware_file(&[FirmwareFileKind::Ec])
             };
             found_an_option = true;
         } else if arg == "--flash-ro-ec" {
@@ -349,10 +864,34 @@ pub fn parse(args: &[String]) -> Cli {
             cli.flash_rw_ec = if args.len() > i + 1 {
                 Some(args[i + 1].clone())
             } else {
-                println!("--flash-rw-ec requires extra argument to denote input file");
+                pick_firmware_file(&[FirmwareFileKind::Ec])
+            };
+            found_an_option = true;
+        } else if arg == "--flash-layout" {
+            cli.flash_layout = true;
+            found_an_option = true;
+        } else if arg == "--erase-ec-region" {
+            cli.erase_ec_region = if args.len() > i + 1 {
+                let region = match args[i + 1].as_str() {
+                    "ro" => Some(EcFlashRegion::Ro),
+                    "rw" => Some(EcFlashRegion::Rw),
+                    "flags" => Some(EcFlashRegion::Flags),
+                    _ => None,
+                };
+                if region.is_none() {
+                    println!(
+                        "Invalid value for --erase-ec-region: '{}'. Must be ro, rw or flags.",
+                        args[i + 1]
+                    );
+                }
+                region
+            } else {
+                println!("--erase-ec-region requires extra argument, ro, rw or flags");
                 None
             };
             found_an_option = true;
+        } else if arg == "--dry-run" {
+            cli.dry_run = true;
         } else if arg == "--hash" {
             cli.hash = if args.len() > i + 1 {
                 Some(args[i + 1].clone())
@@ -415,6 +954,31 @@ pub fn parse(args: &[String]) -> Cli {
                 None
             };
             found_an_option = true;
+        } else if arg == "--platform" {
+            cli.platform = if args.len() > i + 1 {
+                let platform_arg = &args[i + 1];
+                if platform_arg == "intel-gen11" {
+                    Some(PlatformArg::IntelGen11)
+                } else if platform_arg == "intel-gen12" {
+                    Some(PlatformArg::IntelGen12)
+                } else if platform_arg == "intel-gen13" {
+                    Some(PlatformArg::IntelGen13)
+                } else if platform_arg == "intel-core-ultra1" {
+                    Some(PlatformArg::IntelCoreUltra1)
+                } else if platform_arg == "framework13-amd" {
+                    Some(PlatformArg::Framework13Amd)
+                } else if platform_arg == "framework16" {
+                    Some(PlatformArg::Framework16)
+                } else if platform_arg == "desktop-amd-ai300" {
+                    Some(PlatformArg::DesktopAmdAi300)
+                } else {
+                    println!("Invalid value for --platform: {}", platform_arg);
+                    None
+                }
+            } else {
+                println!("Need to provide a value for --platform");
+                None
+            };
         } else if arg == "--raw-command" {
             cli.raw_command = args[1..].to_vec();
         } else if arg == "--compare-version" {
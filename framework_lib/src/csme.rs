@@ -1,15 +1,22 @@
 //! Get CSME information from the running system
 //!
-//! Currently only works on Linux (from sysfs).
+//! On Linux from sysfs, on Windows by asking the firmware through the
+//! Intel MEI/HECI driver.
 
 use core::fmt;
 #[cfg(feature = "linux")]
 use std::fs;
-#[cfg(feature = "linux")]
+#[cfg(any(feature = "linux", feature = "windows"))]
 use std::io;
 #[cfg(feature = "linux")]
 use std::path::Path;
 
+#[cfg(feature = "windows")]
+use windows::{
+    core::*, Win32::Devices::DeviceAndDriverInstallation::*, Win32::Foundation::*,
+    Win32::Storage::FileSystem::*, Win32::System::IO::*,
+};
+
 pub struct CsmeInfo {
     /// Whether the CSME is currently enabled or not
     pub enabled: bool,
@@ -132,3 +139,162 @@ pub fn csme_from_sysfs() -> io::Result<CsmeInfo> {
         ))
     }
 }
+
+/// Device interface of the Intel MEI/HECI driver (GUID_DEVINTERFACE_HECI)
+#[cfg(feature = "windows")]
+const HECI_INTERFACE_GUID: GUID = GUID::from_u128(0xE2D1FF34_3458_49A9_88DA_8E6915CE9BE5);
+/// MKHI (ME Kernel Host Interface) client, which reports the firmware version
+#[cfg(feature = "windows")]
+const MKHI_CLIENT_GUID: GUID = GUID::from_u128(0x8E6A6715_9ABC_4043_88EF_9E39C6F63E0F);
+/// IOCTL_TEEDRIVER_CONNECT_CLIENT
+#[cfg(feature = "windows")]
+const IOCTL_HECI_CONNECT_CLIENT: u32 = 0x8000E004;
+
+#[cfg(feature = "windows")]
+const MKHI_GROUP_ID_GEN: u8 = 0xFF;
+#[cfg(feature = "windows")]
+const MKHI_GET_FW_VERSION: u8 = 0x02;
+/// Set in the command of the response
+#[cfg(feature = "windows")]
+const MKHI_RESPONSE: u8 = 0x80;
+/// Header + code, recovery and FITC version with 4 u16 each
+#[cfg(feature = "windows")]
+const MKHI_FW_VERSION_RESPONSE_LEN: usize = 4 + 3 * 8;
+
+/// Parse a version from the MKHI GET_FW_VERSION response (minor, major, build, hotfix)
+#[cfg(feature = "windows")]
+fn parse_mkhi_version(data: &[u8]) -> CsmeVersion {
+    let field = |i: usize| u16::from_le_bytes([data[i * 2], data[i * 2 + 1]]) as u32;
+    CsmeVersion {
+        platform: 0,
+        minor: field(0),
+        major: field(1),
+        buildno: field(2),
+        hotfix: field(3),
+    }
+}
+
+/// Find the device path of the first present HECI device
+#[cfg(feature = "windows")]
+fn heci_device_path() -> io::Result<Vec<u16>> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "No Intel MEI/HECI device found");
+    let mut len: u32 = 0;
+    let ret = unsafe {
+        CM_Get_Device_Interface_List_SizeW(
+            &mut len,
+            &HECI_INTERFACE_GUID,
+            PCWSTR::null(),
+            CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+        )
+    };
+    if ret != CR_SUCCESS || len <= 1 {
+        return Err(not_found());
+    }
+
+    // List of null-terminated strings, terminated by an empty string
+    let mut buffer = vec![0u16; len as usize];
+    let ret = unsafe {
+        CM_Get_Device_Interface_ListW(
+            &HECI_INTERFACE_GUID,
+            PCWSTR::null(),
+            &mut buffer,
+            CM_GET_DEVICE_INTERFACE_LIST_PRESENT,
+        )
+    };
+    if ret != CR_SUCCESS {
+        return Err(not_found());
+    }
+    let end = buffer.iter().position(|c| *c == 0).unwrap_or(0);
+    if end == 0 {
+        return Err(not_found());
+    }
+    buffer.truncate(end + 1);
+    Ok(buffer)
+}
+
+#[cfg(feature = "windows")]
+fn win_err(err: Error) -> io::Error {
+    io::Error::other(format!("{:?}", err))
+}
+
+/// Connect to the MKHI client and ask for the firmware version
+#[cfg(feature = "windows")]
+fn mkhi_get_fw_version(handle: HANDLE) -> io::Result<CsmeInfo> {
+    // Response contains the maximum message length and protocol version
+    let mut connect_response = [0u8; 16];
+    let mut returned: u32 = 0;
+    unsafe {
+        DeviceIoControl(
+            handle,
+            IOCTL_HECI_CONNECT_CLIENT,
+            Some(&MKHI_CLIENT_GUID as *const _ as *const ::core::ffi::c_void),
+            std::mem::size_of::<GUID>() as u32,
+            Some(connect_response.as_mut_ptr() as *mut ::core::ffi::c_void),
+            connect_response.len() as u32,
+            Some(&mut returned),
+            None,
+        )
+    }
+    .map_err(win_err)?;
+
+    let request = [MKHI_GROUP_ID_GEN, MKHI_GET_FW_VERSION, 0x00, 0x00];
+    let mut written: u32 = 0;
+    unsafe { WriteFile(handle, Some(&request[..]), Some(&mut written), None) }.map_err(win_err)?;
+
+    let mut response = [0u8; 512];
+    let mut read: u32 = 0;
+    unsafe { ReadFile(handle, Some(&mut response[..]), Some(&mut read), None) }.map_err(win_err)?;
+
+    let response = &response[..read as usize];
+    if response.len() < MKHI_FW_VERSION_RESPONSE_LEN
+        || response[0] != MKHI_GROUP_ID_GEN
+        || response[1] != (MKHI_GET_FW_VERSION | MKHI_RESPONSE)
+        || response[3] != 0
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected MKHI response: {:02X?}", response),
+        ));
+    }
+
+    Ok(CsmeInfo {
+        // If it answers, it's enabled
+        enabled: true,
+        main_ver: parse_mkhi_version(&response[4..12]),
+        recovery_ver: parse_mkhi_version(&response[12..20]),
+        fitc_ver: parse_mkhi_version(&response[20..28]),
+    })
+}
+
+/// Ask the CSME firmware for its version through the Intel MEI/HECI driver
+#[cfg(feature = "windows")]
+pub fn csme_from_heci() -> io::Result<CsmeInfo> {
+    let path = heci_device_path()?;
+    let handle = unsafe {
+        CreateFileW(
+            PCWSTR(path.as_ptr()),
+            FILE_GENERIC_READ.0 | FILE_GENERIC_WRITE.0,
+            FILE_SHARE_READ | FILE_SHARE_WRITE,
+            None,
+            OPEN_EXISTING,
+            FILE_FLAGS_AND_ATTRIBUTES(0),
+            None,
+        )
+    }
+    .map_err(win_err)?;
+
+    let res = mkhi_get_fw_version(handle);
+    unsafe {
+        let _ = CloseHandle(handle);
+    }
+    res
+}
+
+/// Get CSME information from the OS specific interface
+#[cfg(any(feature = "linux", feature = "windows"))]
+pub fn get_csme_info() -> io::Result<CsmeInfo> {
+    #[cfg(feature = "linux")]
+    return csme_from_sysfs();
+    #[cfg(all(feature = "windows", not(feature = "linux")))]
+    return csme_from_heci();
+}
@@ -0,0 +1,396 @@
+//! Daemon that lets the desktop change settings and reacts to hardware events
+//!
+//! Runs as root (e.g. as a systemd service) and owns `BUS_NAME` on the system
+//! bus. Desktop components, like the tray icon of `--tray`, use it to change
+//! the charge limit, fan duty and keyboard backlight sync, without running
+//! anything as root themselves. Reading is allowed for everyone. Changing
+//! settings needs authorization through polkit, with the same action as the
+//! helper.
+//!
+//! Desktop environments can also read the power state and firmware versions
+//! and subscribe to the signals, instead of running `framework_tool` with sudo.
+//! `busctl introspect org.frameworkcomputer.Tool /org/frameworkcomputer/Tool`
+//! lists the whole interface.
+//!
+//! In the background the EC is watched for hardware events, like with
+//! `--watch-events`, and the hook is run for each event. For example to rotate
+//! the screen when entering tablet mode. Every event is also sent as the
+//! `HardwareEvent` signal, tablet mode and privacy switches have their own
+//! signals. While keyboard backlight sync is enabled, the backlight follows AC
+//! and ambient light, like with `--kblight-auto`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use zbus::blocking::connection;
+use zbus::fdo;
+use zbus::message::Header;
+use zbus::names::BusName;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::Value;
+use zbus::{interface, Connection};
+
+use crate::audit::{self, AuditRecord};
+use crate::chromium_ec::{CrosEc, EcError};
+use crate::commandline::ExitCode;
+use crate::events::{self, HardwareEvent, HardwareState, StateWaiter};
+use crate::helper::POLKIT_WRITE_ACTION;
+use crate::kblight::{self, KblightAuto, KblightAutoConfig};
+use crate::power;
+use crate::report::{BiosVersionReport, EcVersionReport, PdVersionsReport};
+
+/// Name that the daemon owns on the system bus
+pub const BUS_NAME: &str = "org.frameworkcomputer.Tool";
+/// Path of the object that implements the `BUS_NAME` interface
+pub const OBJECT_PATH: &str = "/org/frameworkcomputer/Tool";
+
+/// Lets polkit show an authentication dialog, if the user has to authenticate
+const POLKIT_ALLOW_USER_INTERACTION: u32 = 1;
+
+/// Settings that the daemon keeps track of
+#[derive(Debug, Default)]
+struct DaemonState {
+    /// Backlight follows AC and ambient light
+    kblight_sync: bool,
+    /// Duty that the fans were set to through the daemon, None if the EC controls them
+    fan_duty: Option<u8>,
+}
+
+fn lock(state: &Mutex<DaemonState>) -> MutexGuard<'_, DaemonState> {
+    state.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn failed(err: EcError) -> fdo::Error {
+    fdo::Error::Failed(format!("{:?}", err))
+}
+
+/// Unique name of the client that sent the message
+fn sender(header: &Header<'_>) -> fdo::Result<String> {
+    header
+        .sender()
+        .map(|sender| sender.to_string())
+        .ok_or_else(|| fdo::Error::AccessDenied("Unknown sender".to_string()))
+}
+
+/// Ask polkit whether the client may change settings
+///
+/// The client is identified by its bus name, which, unlike a PID, can't be
+/// reused by another process while the check runs.
+async fn authorize(conn: &Connection, header: &Header<'_>) -> fdo::Result<()> {
+    let sender = sender(header)?;
+    let subject_details = HashMap::from([("name", Value::from(sender.as_str()))]);
+    let subject = ("system-bus-name", subject_details);
+    let details: HashMap<&str, &str> = HashMap::new();
+    let reply = conn
+        .call_method(
+            Some("org.freedesktop.PolicyKit1"),
+            "/org/freedesktop/PolicyKit1/Authority",
+            Some("org.freedesktop.PolicyKit1.Authority"),
+            "CheckAuthorization",
+            &(
+                subject,
+                POLKIT_WRITE_ACTION,
+                details,
+                POLKIT_ALLOW_USER_INTERACTION,
+                "",
+            ),
+        )
+        .await?;
+    let (authorized, _challenge, _details): (bool, bool, HashMap<String, String>) =
+        reply.body().deserialize()?;
+    if authorized {
+        Ok(())
+    } else {
+        Err(fdo::Error::AccessDenied(
+            "Not authorized to change settings".to_string(),
+        ))
+    }
+}
+
+/// Record a change in the audit log, with the user that asked for it
+async fn write_audit_log(
+    conn: &Connection,
+    header: &Header<'_>,
+    operation: &str,
+    args: Vec<String>,
+    exit_code: i32,
+) {
+    let mut record = AuditRecord::new(operation, args, exit_code);
+    if let (Ok(sender), Ok(dbus)) = (sender(header), fdo::DBusProxy::new(conn).await) {
+        if let Ok(name) = BusName::try_from(sender.as_str()) {
+            if let Ok(uid) = dbus.get_connection_unix_user(name).await {
+                record.user = Some(format!("uid {}", uid));
+            }
+        }
+    }
+    audit::write(&record);
+}
+
+/// Implementation of the `BUS_NAME` interface
+struct Tool {
+    ec: CrosEc,
+    state: Arc<Mutex<DaemonState>>,
+}
+
+#[interface(name = "org.frameworkcomputer.Tool")]
+impl Tool {
+    /// AC and battery state
+    ///
+    /// Always has `ac_present`. With a battery also `battery_percent`,
+    /// `charging`, `discharging`, `power_mw` and `cycle_count`, and
+    /// `time_to_empty_min` or `time_to_full_min` while (dis)charging.
+    fn get_power_info(&self) -> fdo::Result<HashMap<&'static str, Value<'static>>> {
+        let info = power::power_info(&self.ec)
+            .ok_or_else(|| fdo::Error::Failed("Failed to read power information".to_string()))?;
+        let mut dict = HashMap::from([("ac_present", Value::from(info.ac_present))]);
+        if let Some(battery) = info.battery {
+            dict.insert("battery_percent", Value::from(battery.charge_percentage));
+            dict.insert("charging", Value::from(battery.charging));
+            dict.insert("discharging", Value::from(battery.discharging));
+            dict.insert("power_mw", Value::from(battery.power_mw()));
+            dict.insert("cycle_count", Value::from(battery.cycle_count));
+            if let Some(minutes) = battery.time_to_empty_min() {
+                dict.insert("time_to_empty_min", Value::from(minutes));
+            }
+            if let Some(minutes) = battery.time_to_full_min() {
+                dict.insert("time_to_full_min", Value::from(minutes));
+            }
+        }
+        Ok(dict)
+    }
+
+    /// Firmware versions, as `--versions` shows them
+    ///
+    /// Has `bios`, `ec_ro`, `ec_rw`, `ec_current` (`RO` or `RW`), `pd_right`
+    /// and `pd_left`, for the versions that could be read.
+    fn get_versions(&self) -> HashMap<&'static str, String> {
+        let mut versions = HashMap::new();
+        if let Some(version) = BiosVersionReport::read().bios.and_then(|bios| bios.version) {
+            versions.insert("bios", version);
+        }
+        if let Some((ro, rw, current)) = EcVersionReport::read(&self.ec, false).flash_version {
+            versions.insert("ec_ro", ro);
+            versions.insert("ec_rw", rw);
+            versions.insert("ec_current", format!("{:?}", current));
+        }
+        match PdVersionsReport::read(&self.ec) {
+            PdVersionsReport::Controllers { right, left } => {
+                versions.insert("pd_right", right.main);
+                if let Some(left) = left {
+                    versions.insert("pd_left", left.main);
+                }
+            }
+            PdVersionsReport::FromEc { right, left } => {
+                versions.insert("pd_right", right);
+                if let Some(left) = left {
+                    versions.insert("pd_left", left);
+                }
+            }
+            PdVersionsReport::Unknown => {}
+        }
+        versions
+    }
+
+    /// Maximum charge limit in percent
+    fn get_charge_limit(&self) -> fdo::Result<u8> {
+        let (_min, max) = self.ec.get_charge_limit().map_err(failed)?;
+        Ok(max)
+    }
+
+    /// Set the maximum charge limit in percent, between 25 and 100
+    async fn set_charge_limit(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        limit: u8,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header).await?;
+        if !(25..=100).contains(&limit) {
+            return Err(fdo::Error::InvalidArgs(
+                "Charge limit must be between 25% and 100%".to_string(),
+            ));
+        }
+        let res = self
+            .ec
+            .get_charge_limit()
+            .and_then(|(min, _max)| self.ec.set_charge_limit(min, limit));
+        let exit_code = match &res {
+            Ok(()) => ExitCode::Success.into(),
+            Err(err) => ExitCode::from(err).into(),
+        };
+        write_audit_log(
+            conn,
+            &header,
+            "charge_limit",
+            vec![limit.to_string()],
+            exit_code,
+        )
+        .await;
+        res.map_err(failed)
+    }
+
+    /// Duty in percent that the fans were set to through the daemon, -1 if the EC controls them
+    ///
+    /// The EC can't report whether its fans are controlled manually, so changes
+    /// by other tools aren't reflected.
+    fn get_fan_duty(&self) -> i32 {
+        lock(&self.state).fan_duty.map_or(-1, i32::from)
+    }
+
+    /// Set all fans to a fixed duty in percent
+    async fn set_fan_duty(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        percent: u8,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header).await?;
+        self.ec.fan_set_duty(None, percent as u32).map_err(failed)?;
+        lock(&self.state).fan_duty = Some(percent);
+        Ok(())
+    }
+
+    /// Let the EC control the fans again
+    async fn set_fan_auto(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header).await?;
+        self.ec.autofanctrl(None).map_err(failed)?;
+        lock(&self.state).fan_duty = None;
+        Ok(())
+    }
+
+    /// Whether the keyboard backlight follows AC and ambient light
+    fn get_keyboard_backlight_sync(&self) -> bool {
+        lock(&self.state).kblight_sync
+    }
+
+    async fn set_keyboard_backlight_sync(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        enabled: bool,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header).await?;
+        lock(&self.state).kblight_sync = enabled;
+        Ok(())
+    }
+
+    /// Any hardware event, with the same name and state as passed to the hook
+    #[zbus(signal)]
+    async fn hardware_event(
+        emitter: &SignalEmitter<'_>,
+        name: &str,
+        state: &str,
+    ) -> zbus::Result<()>;
+
+    /// Entered (true) or left (false) tablet mode
+    #[zbus(signal)]
+    async fn tablet_mode_changed(
+        emitter: &SignalEmitter<'_>,
+        tablet_mode: bool,
+    ) -> zbus::Result<()>;
+
+    /// Privacy switch of the `microphone` or `camera` turned on (true) or off (false)
+    #[zbus(signal)]
+    async fn privacy_switch_changed(
+        emitter: &SignalEmitter<'_>,
+        switch: &str,
+        connected: bool,
+    ) -> zbus::Result<()>;
+}
+
+/// Send the signals for a hardware event
+async fn emit_signals(emitter: &SignalEmitter<'_>, event: &HardwareEvent) -> zbus::Result<()> {
+    Tool::hardware_event(emitter, event.name(), event.state()).await?;
+    match event {
+        HardwareEvent::TabletMode(tablet_mode) => {
+            Tool::tablet_mode_changed(emitter, *tablet_mode).await
+        }
+        HardwareEvent::Microphone(connected) | HardwareEvent::Camera(connected) => {
+            Tool::privacy_switch_changed(emitter, event.name(), *connected).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Serve `BUS_NAME` and watch the EC forever
+///
+/// The hook, interval and enabled events are the same as for `events::watch`.
+/// Signals are sent for all events, even those that aren't enabled.
+/// Returns only if the name can't be registered on the system bus.
+pub fn run(
+    ec: &CrosEc,
+    hook: Option<&str>,
+    interval_ms: Option<u64>,
+    enabled: Option<&[String]>,
+    kblight_sync: bool,
+) -> i32 {
+    let state = Arc::new(Mutex::new(DaemonState {
+        kblight_sync,
+        fan_duty: None,
+    }));
+    let tool = Tool {
+        ec: ec.clone(),
+        state: state.clone(),
+    };
+    let conn = connection::Builder::system()
+        .and_then(|builder| builder.name(BUS_NAME))
+        .and_then(|builder| builder.serve_at(OBJECT_PATH, tool))
+        .and_then(|builder| builder.build());
+    // Requests are handled on the connection's own thread, as long as it's alive
+    let conn = match conn {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!(
+                "Failed to register {} on the system bus: {:?}",
+                BUS_NAME, err
+            );
+            return ExitCode::Failure.into();
+        }
+    };
+    info!("Registered {} on the system bus", BUS_NAME);
+    let emitter = match SignalEmitter::new(conn.inner(), OBJECT_PATH) {
+        Ok(emitter) => emitter,
+        Err(err) => {
+            error!("Invalid object path {}: {:?}", OBJECT_PATH, err);
+            return ExitCode::Failure.into();
+        }
+    };
+
+    // The backlight needs to follow AC and ambient light quickly, even if the EC sends events
+    let waiter = StateWaiter::new(ec, Some(interval_ms.unwrap_or(kblight::POLL_INTERVAL_MS)));
+    let mut hardware = HardwareState::read(ec);
+    debug!("Initial state: {:?}", hardware);
+    let mut backlight: Option<KblightAuto> = None;
+
+    loop {
+        waiter.wait(ec);
+
+        let new_hardware = HardwareState::read(ec);
+        for event in hardware.events(&new_hardware) {
+            if let Err(err) = zbus::block_on(emit_signals(&emitter, &event)) {
+                error!("Failed to send signal for {}: {:?}", event.name(), err);
+            }
+            if !enabled.map_or(true, |names| names.iter().any(|x| x == event.name())) {
+                continue;
+            }
+            info!("{}: {}", event.name(), event.state());
+            if let Some(hook) = hook {
+                events::run_hook(hook, &event);
+            }
+        }
+        hardware = new_hardware;
+
+        // Start from the current brightness, the user might have changed it in the meantime
+        if lock(&state).kblight_sync {
+            backlight
+                .get_or_insert_with(|| KblightAuto::new(ec, KblightAutoConfig::default()))
+                .update(ec);
+        } else {
+            backlight = None;
+        }
+    }
+}
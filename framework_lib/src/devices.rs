@@ -0,0 +1,121 @@
+//! Scan USB and HID devices once and share the result between all checks
+//!
+//! Every scan takes a while and opening a device while another scan is still
+//! holding it can fail with "device busy". So instead of each module scanning
+//! by itself, they all look up their devices in the same `Devices`.
+
+#[cfg(feature = "rusb")]
+use alloc::vec::Vec;
+#[cfg(any(feature = "rusb", feature = "hidapi"))]
+use core::cell::OnceCell;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "hidapi")]
+use hidapi::{DeviceInfo, HidApi};
+#[cfg(feature = "rusb")]
+use rusb::{Device, GlobalContext};
+
+pub const FRAMEWORK_VID: u16 = 0x32AC;
+
+/// USB devices made by Framework that the tool knows about, by product ID
+pub const FRAMEWORK_DEVICES: &[(u16, &str)] = &[
+    (0x0002, "HDMI Expansion Card"),
+    (0x0003, "DisplayPort Expansion Card"),
+    (0x0010, "Audio Expansion Card"),
+];
+
+/// Name of a Framework USB device
+pub fn framework_device_name(pid: u16) -> Option<&'static str> {
+    FRAMEWORK_DEVICES
+        .iter()
+        .find(|(known_pid, _)| *known_pid == pid)
+        .map(|(_, name)| *name)
+}
+
+/// Devices connected to the system
+///
+/// Nothing is scanned until a check asks for devices, then each bus is
+/// scanned only once.
+#[derive(Default)]
+pub struct Devices {
+    #[cfg(feature = "rusb")]
+    usb: OnceCell<Vec<Device<GlobalContext>>>,
+    #[cfg(feature = "hidapi")]
+    hid: OnceCell<Option<HidApi>>,
+}
+
+/// Start a new shared scan
+///
+/// Devices that are connected or disconnected afterwards are not noticed.
+pub fn enumerate() -> Devices {
+    Devices::default()
+}
+
+impl Devices {
+    /// All USB devices with the given vendor and product ID
+    #[cfg(feature = "rusb")]
+    pub fn usb(&self, vid: u16, pid: u16) -> Vec<&Device<GlobalContext>> {
+        let devices = self.usb.get_or_init(|| match rusb::devices() {
+            Ok(list) => list.iter().collect(),
+            Err(err) => {
+                error!("Failed to list USB devices: {:?}", err);
+                Vec::new()
+            }
+        });
+        devices
+            .iter()
+            .filter(|dev| {
+                dev.device_descriptor()
+                    .map(|desc| desc.vendor_id() == vid && desc.product_id() == pid)
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// HID API with the list of all HID devices, `None` if it can't be opened
+    #[cfg(feature = "hidapi")]
+    pub fn hid(&self) -> Option<&HidApi> {
+        self.hid
+            .get_or_init(|| match HidApi::new() {
+                Ok(api) => Some(api),
+                Err(err) => {
+                    error!("Failed to list HID devices: {}", err);
+                    None
+                }
+            })
+            .as_ref()
+    }
+}
+
+/// Whether the HID device node can't be opened, because of missing permissions
+#[cfg(feature = "hidapi")]
+pub fn hid_permission_denied(dev_info: &DeviceInfo) -> bool {
+    #[cfg(target_os = "linux")]
+    if let Ok(path) = dev_info.path().to_str() {
+        let res = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path);
+        return matches!(res, Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied);
+    }
+    let _ = dev_info;
+    false
+}
+
+static PERMISSION_HINT_SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Explain how to get access to a device that couldn't be opened
+///
+/// Only shown once, even if several devices failed.
+pub fn print_permission_hint(name: &str) {
+    println!("Permission denied to open {}.", name);
+    if PERMISSION_HINT_SHOWN.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    #[cfg(feature = "linux")]
+    println!("Run as root, or allow regular users to access it with: sudo framework_tool --install-udev-rules");
+    #[cfg(not(feature = "linux"))]
+    println!("Run as root or administrator.");
+}
@@ -14,9 +14,19 @@ const EC_RW_VER_OFFSET: usize = 0x402f0;
 const EC_RO_VER_OFFSET_ZEPHYR: usize = 0x00180;
 const EC_RW_VER_OFFSET_ZEPHYR: usize = 0x40140;
 pub const EC_LEN: usize = 0x8_0000;
+/// RO is in the first half of the binary, RW in the second
+const EC_RW_OFFSET: usize = EC_LEN / 2;
+
+// Magic numbers of the vboot 2.1 structures, "VB2S" and "VB2P"
+const VB21_MAGIC_SIGNATURE: u32 = 0x5332_4256;
+const VB21_MAGIC_PACKED_KEY: u32 = 0x5032_4256;
+/// Size of `struct vb21_signature` and `struct vb21_packed_key` without the data
+const VB21_FIXED_SIZE: usize = 60;
 
 use regex;
 
+use crate::units;
+
 #[cfg(feature = "uefi")]
 use core::prelude::rust_2021::derive;
 
@@ -56,6 +66,69 @@ pub struct ImageVersionDetails {
     pub commit: String,
 }
 
+impl ImageVersionDetails {
+    /// Version as (major, minor, patch) tuple, so it can be compared
+    pub fn semver(&self) -> (u32, u32, u32) {
+        (self.major, self.minor, self.patch)
+    }
+}
+
+/// Information from the EC build string, as returned by `CrosEc::version_info`
+#[derive(Debug, PartialEq)]
+pub struct EcVersionInfo {
+    /// Version part of the build string, example: hx30_v0.0.1-7a61a89
+    pub version: String,
+    /// Board, version and commit. None if the version has an unknown format
+    pub details: Option<ImageVersionDetails>,
+    /// Date and time of the build, example: 2022-06-02 15:27:58
+    pub build_date: Option<String>,
+    /// Who built it, example: dhowett@host
+    pub builder: Option<String>,
+}
+
+/// Parse the EC build string into its components
+///
+/// # Examples
+///
+/// ```
+/// use framework_lib::ec_binary::*;
+/// let info = parse_ec_build_info("hx30_v0.0.1-7a61a89 2022-06-02 15:27:58 dhowett@host");
+/// assert_eq!(info.version, "hx30_v0.0.1-7a61a89");
+/// assert_eq!(info.details.unwrap().semver(), (0, 0, 1));
+/// assert_eq!(info.build_date, Some("2022-06-02 15:27:58".to_string()));
+/// assert_eq!(info.builder, Some("dhowett@host".to_string()));
+///
+/// // Unknown format, at least the version is there
+/// let info = parse_ec_build_info("custom");
+/// assert_eq!(info.version, "custom");
+/// assert_eq!(info.details, None);
+/// assert_eq!(info.build_date, None);
+/// ```
+pub fn parse_ec_build_info(build_info: &str) -> EcVersionInfo {
+    let build_info = build_info.trim();
+    let caps = BUILD_INFO_RE.captures(build_info);
+    let group = |i: usize| {
+        caps.as_ref()
+            .and_then(|caps| caps.get(i))
+            .map(|x| x.as_str().to_string())
+    };
+    let version = group(1).unwrap_or_else(|| build_info.to_string());
+
+    EcVersionInfo {
+        details: parse_ec_version_str(&version),
+        version,
+        build_date: group(2),
+        builder: group(3),
+    }
+}
+
+lazy_static! {
+    static ref BUILD_INFO_RE: regex::Regex = regex::Regex::new(
+        r"^([^ \t]+)(?:[ \t]+([0-9]{4}-[0-9]{2}-[0-9]{2} [0-9]{2}:[0-9]{2}:[0-9]{2}))?(?:[ \t]+([^ \t]+))?",
+    )
+    .unwrap();
+}
+
 /// Print pretty information about the EC version
 pub fn print_ec_version(ver: &ImageVersionData, ro: bool) {
     println!("EC");
@@ -69,8 +142,115 @@ pub fn print_ec_version(ver: &ImageVersionData, ro: bool) {
     );
     println!("  Version:    {:>20}", version);
     println!("  Commit:     {:>20}", ver.details.commit);
-    println!("  Size:       {:>20} B", ver.size);
-    println!("  Size:       {:>20} KB", ver.size / 1024);
+    println!("  Size:       {:>20}", units::fmt_size(ver.size as usize));
+}
+
+/// Signature of the RW firmware or the public key in RO, to verify it with
+///
+/// Only present in firmware that verifies RW before jumping to it (RWSIG).
+/// Defined in EC code as `struct vb21_signature` and `struct vb21_packed_key`.
+#[derive(Debug, PartialEq)]
+pub struct RwSigInfo {
+    /// Absolute offset of the structure inside the entire EC binary
+    pub offset: usize,
+    /// `enum vb2_signature_algorithm`
+    pub sig_alg: u16,
+    /// `enum vb2_hash_algorithm`
+    pub hash_alg: u16,
+    /// Version of the public key, None for the signature
+    pub key_version: Option<u32>,
+}
+
+impl RwSigInfo {
+    pub fn algorithm(&self) -> String {
+        let sig = match self.sig_alg {
+            0 => "None",
+            1 => "RSA1024",
+            2 => "RSA2048",
+            3 => "RSA4096",
+            4 => "RSA8192",
+            5 => "RSA2048-EXP3",
+            6 => "RSA3072-EXP3",
+            _ => "Unknown",
+        };
+        let hash = match self.hash_alg {
+            0 => "None",
+            1 => "SHA1",
+            2 => "SHA256",
+            3 => "SHA512",
+            _ => "Unknown",
+        };
+        format!("{} {}", sig, hash)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Find a vboot 2.1 structure with the magic in the region, they're 4 byte aligned
+fn find_vb21(data: &[u8], start: usize, end: usize, magic: u32) -> Option<usize> {
+    let end = core::cmp::min(end, data.len()).checked_sub(VB21_FIXED_SIZE)?;
+    (start..=end).step_by(4).find(|&offset| {
+        // Common header: magic, version, total_size, fixed_size, desc_offset, desc_size
+        read_u32(data, offset) == magic
+            && read_u32(data, offset + 12) as usize >= VB21_FIXED_SIZE
+            && read_u32(data, offset + 8) >= read_u32(data, offset + 12)
+    })
+}
+
+/// Read the signature at the end of RW, if the firmware has one
+pub fn read_rw_signature(data: &[u8]) -> Option<RwSigInfo> {
+    let offset = find_vb21(data, EC_RW_OFFSET, EC_LEN, VB21_MAGIC_SIGNATURE)?;
+    Some(RwSigInfo {
+        offset,
+        sig_alg: read_u16(data, offset + 36),
+        hash_alg: read_u16(data, offset + 38),
+        key_version: None,
+    })
+}
+
+/// Read the public key at the end of RO, that RW has to be signed with
+pub fn read_ro_public_key(data: &[u8]) -> Option<RwSigInfo> {
+    let offset = find_vb21(data, 0, EC_RW_OFFSET, VB21_MAGIC_PACKED_KEY)?;
+    Some(RwSigInfo {
+        offset,
+        sig_alg: read_u16(data, offset + 32),
+        hash_alg: read_u16(data, offset + 34),
+        key_version: Some(read_u32(data, offset + 36)),
+    })
+}
+
+/// Print the RW signature and the RO public key, if the firmware has them
+pub fn print_rwsig(data: &[u8]) {
+    println!("RW Signature");
+    let key = read_ro_public_key(data);
+    let sig = read_rw_signature(data);
+    match &key {
+        Some(key) => {
+            println!("  RO Key:     {:>20}", key.algorithm());
+            println!("  Key Version:{:>20}", key.key_version.unwrap_or(0));
+        }
+        None => println!("  RO Key:     {:>20}", "None"),
+    }
+    match &sig {
+        Some(sig) => println!("  RW Sig:     {:>20}", sig.algorithm()),
+        None => println!("  RW Sig:     {:>20}", "None"),
+    }
+    if let (Some(key), Some(sig)) = (&key, &sig) {
+        if (key.sig_alg, key.hash_alg) != (sig.sig_alg, sig.hash_alg) {
+            println!("  Warning: RW is signed with a different algorithm than the RO key");
+        }
+    }
 }
 
 fn parse_ec_version(data: &_ImageVersionData) -> Option<ImageVersionData> {
@@ -289,6 +469,39 @@ mod tests {
         assert_eq!(expected, read_ec_version(&data, true));
     }
 
+    #[test]
+    fn can_parse_rwsig() {
+        let mut data = vec![0xFF; EC_LEN];
+        let key = 0x3_FC00;
+        data[key..key + 4].copy_from_slice(&VB21_MAGIC_PACKED_KEY.to_le_bytes());
+        data[key + 8..key + 12].copy_from_slice(&1024u32.to_le_bytes());
+        data[key + 12..key + 16].copy_from_slice(&(VB21_FIXED_SIZE as u32).to_le_bytes());
+        data[key + 32..key + 34].copy_from_slice(&6u16.to_le_bytes());
+        data[key + 34..key + 36].copy_from_slice(&2u16.to_le_bytes());
+        data[key + 36..key + 40].copy_from_slice(&1u32.to_le_bytes());
+        let sig = 0x7_FC00;
+        data[sig..sig + 4].copy_from_slice(&VB21_MAGIC_SIGNATURE.to_le_bytes());
+        data[sig + 8..sig + 12].copy_from_slice(&1024u32.to_le_bytes());
+        data[sig + 12..sig + 16].copy_from_slice(&(VB21_FIXED_SIZE as u32).to_le_bytes());
+        data[sig + 36..sig + 38].copy_from_slice(&6u16.to_le_bytes());
+        data[sig + 38..sig + 40].copy_from_slice(&2u16.to_le_bytes());
+
+        let key_info = read_ro_public_key(&data).unwrap();
+        assert_eq!(key_info.offset, key);
+        assert_eq!(key_info.key_version, Some(1));
+        assert_eq!(key_info.algorithm(), "RSA3072-EXP3 SHA256");
+        let sig_info = read_rw_signature(&data).unwrap();
+        assert_eq!(sig_info.offset, sig);
+        assert_eq!(sig_info.algorithm(), "RSA3072-EXP3 SHA256");
+
+        // Framework firmware doesn't use RWSIG yet
+        let mut ec_bin_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        ec_bin_path.push("test_bins/amd-fl16-ec-3.03.bin");
+        let data = fs::read(ec_bin_path).unwrap();
+        assert_eq!(read_rw_signature(&data), None);
+        assert_eq!(read_ro_public_key(&data), None);
+    }
+
     #[test]
     // Make sure it doesn't crash when reading an invalid binary
     // Cargo.toml is significantly smaller than ec.bin
@@ -0,0 +1,268 @@
+//! Save the tunable EC state to a file and apply it again later
+//!
+//! Reflashing the EC or disconnecting the battery resets all settings to their
+//! defaults. A snapshot is a small JSON file, settings that couldn't be read
+//! are stored as `null` and skipped when restoring.
+//!
+//! The EC doesn't report whether the fans are under manual control, so the fan
+//! mode isn't part of the snapshot.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::chromium_ec::commands::{DeckStateMode, FpLedBrightnessLevel};
+use crate::chromium_ec::input_deck::InputDeckState;
+use crate::chromium_ec::{CrosEc, EcResult};
+
+/// Bumped when the meaning of existing fields changes
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Brightness in percent that the EC uses for each fingerprint LED level
+const FP_LED_LEVELS: &[(FpLedBrightnessLevel, u8)] = &[
+    (FpLedBrightnessLevel::High, 55),
+    (FpLedBrightnessLevel::Medium, 40),
+    (FpLedBrightnessLevel::Low, 15),
+];
+
+const FP_LED_LEVEL_NAMES: &[(FpLedBrightnessLevel, &str)] = &[
+    (FpLedBrightnessLevel::High, "high"),
+    (FpLedBrightnessLevel::Medium, "medium"),
+    (FpLedBrightnessLevel::Low, "low"),
+];
+
+const DECK_MODE_NAMES: &[(DeckStateMode, &str)] = &[
+    (DeckStateMode::Required, "auto"),
+    (DeckStateMode::ForceOn, "on"),
+    (DeckStateMode::ForceOff, "off"),
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EcSnapshot {
+    /// Minimum and maximum battery charge in percent
+    pub charge_limit: Option<(u8, u8)>,
+    pub fp_led_level: Option<FpLedBrightnessLevel>,
+    /// Keyboard backlight brightness in percent
+    pub kblight: Option<u8>,
+    /// Seconds in G3 before the EC hibernates
+    pub hibernate_delay: Option<u32>,
+    /// Only on Framework 16
+    pub input_deck_mode: Option<DeckStateMode>,
+}
+
+fn read<T>(name: &str, res: EcResult<T>) -> Option<T> {
+    match res {
+        Ok(value) => Some(value),
+        Err(err) => {
+            debug!("Failed to read {}: {:?}", name, err);
+            None
+        }
+    }
+}
+
+/// Level that's closest to the brightness the EC reports
+fn fp_led_level(percent: u8) -> FpLedBrightnessLevel {
+    FP_LED_LEVELS
+        .iter()
+        .min_by_key(|(_, level_percent)| level_percent.abs_diff(percent))
+        .map(|(level, _)| *level)
+        .unwrap()
+}
+
+fn name_of<T: PartialEq>(names: &[(T, &'static str)], value: &T) -> &'static str {
+    names.iter().find(|(x, _)| x == value).unwrap().1
+}
+
+fn from_name<T: Copy>(names: &[(T, &str)], name: &str) -> Option<T> {
+    names.iter().find(|(_, x)| *x == name).map(|(x, _)| *x)
+}
+
+/// Read all settings from the EC
+pub fn save(ec: &CrosEc) -> EcSnapshot {
+    EcSnapshot {
+        charge_limit: read("charge limit", ec.get_charge_limit()),
+        fp_led_level: read("fingerprint LED level", ec.get_fp_led_level()).map(fp_led_level),
+        kblight: read("keyboard backlight", ec.get_keyboard_backlight()),
+        hibernate_delay: read("hibernate delay", ec.get_hibernation_delay()),
+        input_deck_mode: read("input deck mode", ec.get_input_deck_status()).map(|status| {
+            match status.state {
+                InputDeckState::ForceOn => DeckStateMode::ForceOn,
+                InputDeckState::ForceOff => DeckStateMode::ForceOff,
+                _ => DeckStateMode::Required,
+            }
+        }),
+    }
+}
+
+/// Apply all settings of the snapshot to the EC
+///
+/// Keeps going if one fails and returns the result of each setting.
+pub fn restore(ec: &CrosEc, snapshot: &EcSnapshot) -> Vec<(&'static str, EcResult<()>)> {
+    let mut results = vec![];
+    if let Some((min, max)) = snapshot.charge_limit {
+        results.push(("charge_limit", ec.set_charge_limit(min, max)));
+    }
+    if let Some(level) = snapshot.fp_led_level {
+        results.push(("fp_led_level", ec.set_fp_led_level(level)));
+    }
+    if let Some(percent) = snapshot.kblight {
+        ec.set_keyboard_backlight(percent.min(100));
+        results.push(("kblight", Ok(())));
+    }
+    if let Some(seconds) = snapshot.hibernate_delay.filter(|x| *x > 0) {
+        let res = ec.set_hibernation_delay(seconds).map(|_| ());
+        results.push(("hibernate_delay", res));
+    }
+    if let Some(mode) = snapshot.input_deck_mode {
+        let res = ec.set_input_deck_mode(mode).map(|_| ());
+        results.push(("input_deck_mode", res));
+    }
+    results
+}
+
+impl EcSnapshot {
+    pub fn to_json(&self) -> String {
+        let number = |x: Option<u32>| x.map(|x| x.to_string());
+        let string = |x: Option<&str>| x.map(|x| format!("\"{}\"", x));
+        let fields = [
+            ("version", number(Some(SNAPSHOT_VERSION))),
+            (
+                "charge_limit_min",
+                number(self.charge_limit.map(|(min, _)| min as u32)),
+            ),
+            (
+                "charge_limit_max",
+                number(self.charge_limit.map(|(_, max)| max as u32)),
+            ),
+            (
+                "fp_led_level",
+                string(self.fp_led_level.map(|x| name_of(FP_LED_LEVEL_NAMES, &x))),
+            ),
+            ("kblight", number(self.kblight.map(|x| x as u32))),
+            ("hibernate_delay", number(self.hibernate_delay)),
+            (
+                "input_deck_mode",
+                string(self.input_deck_mode.map(|x| name_of(DECK_MODE_NAMES, &x))),
+            ),
+        ];
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(key, value)| format!("  \"{}\": {}", key, value.as_deref().unwrap_or("null")))
+            .collect();
+        format!("{{\n{}\n}}\n", fields.join(",\n"))
+    }
+
+    /// Parse a snapshot written by `to_json`
+    ///
+    /// Only flat objects with numbers, strings without escapes and `null`
+    /// are supported, which is all that `to_json` writes.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let body = json
+            .trim()
+            .strip_prefix('{')
+            .and_then(|x| x.strip_suffix('}'))
+            .ok_or_else(|| "Expected a JSON object".to_string())?;
+
+        let mut snapshot = EcSnapshot::default();
+        let (mut min, mut max) = (None, None);
+        let mut version = None;
+        for field in body.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (key, value) = field
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid field: {}", field))?;
+            let key = unquote(key.trim()).ok_or_else(|| format!("Invalid key: {}", key))?;
+            let value = value.trim();
+            if value == "null" {
+                continue;
+            }
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("{} must be a number", key))
+            };
+            let percent = || -> Result<u8, String> {
+                u8::try_from(number()?)
+                    .ok()
+                    .filter(|x| *x <= 100)
+                    .ok_or_else(|| format!("{} must be a percentage", key))
+            };
+            let name = || unquote(value).ok_or_else(|| format!("{} must be a string", key));
+            let invalid = || format!("Invalid {}: {}", key, value);
+            match key {
+                "version" => version = Some(number()?),
+                "charge_limit_min" => min = Some(percent()?),
+                "charge_limit_max" => max = Some(percent()?),
+                "fp_led_level" => {
+                    snapshot.fp_led_level =
+                        Some(from_name(FP_LED_LEVEL_NAMES, name()?).ok_or_else(invalid)?)
+                }
+                "kblight" => snapshot.kblight = Some(percent()?),
+                "hibernate_delay" => snapshot.hibernate_delay = Some(number()?),
+                "input_deck_mode" => {
+                    snapshot.input_deck_mode =
+                        Some(from_name(DECK_MODE_NAMES, name()?).ok_or_else(invalid)?)
+                }
+                // Might be from a newer version
+                _ => debug!("Ignoring unknown key {}", key),
+            }
+        }
+        if version != Some(SNAPSHOT_VERSION) {
+            return Err(format!(
+                "Unsupported snapshot version {:?}, expected {}",
+                version, SNAPSHOT_VERSION
+            ));
+        }
+        if let (Some(min), Some(max)) = (min, max) {
+            snapshot.charge_limit = Some((min, max));
+        }
+        Ok(snapshot)
+    }
+}
+
+fn unquote(s: &str) -> Option<&str> {
+    s.strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_roundtrip() {
+        let snapshot = EcSnapshot {
+            charge_limit: Some((0, 80)),
+            fp_led_level: Some(FpLedBrightnessLevel::Low),
+            kblight: Some(50),
+            hibernate_delay: None,
+            input_deck_mode: Some(DeckStateMode::ForceOn),
+        };
+        let json = snapshot.to_json();
+        assert!(json.contains("  \"charge_limit_max\": 80,\n"));
+        assert!(json.contains("  \"hibernate_delay\": null,\n"));
+        assert!(json.contains("  \"input_deck_mode\": \"on\"\n"));
+        assert_eq!(EcSnapshot::from_json(&json), Ok(snapshot));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(EcSnapshot::from_json("").is_err());
+        assert!(EcSnapshot::from_json("{\"kblight\": 50}").is_err());
+        assert!(EcSnapshot::from_json("{\"version\": 1, \"kblight\": 101}").is_err());
+        assert!(EcSnapshot::from_json("{\"version\": 1, \"fp_led_level\": \"max\"}").is_err());
+        assert_eq!(
+            EcSnapshot::from_json("{\"version\": 1, \"fan_mode\": \"auto\"}"),
+            Ok(EcSnapshot::default())
+        );
+    }
+
+    #[test]
+    fn closest_fp_led_level() {
+        assert_eq!(fp_led_level(55), FpLedBrightnessLevel::High);
+        assert_eq!(fp_led_level(100), FpLedBrightnessLevel::High);
+        assert_eq!(fp_led_level(35), FpLedBrightnessLevel::Medium);
+        assert_eq!(fp_led_level(0), FpLedBrightnessLevel::Low);
+    }
+}
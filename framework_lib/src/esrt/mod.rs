@@ -16,6 +16,8 @@ use std::prelude::v1::*;
 
 #[cfg(not(feature = "uefi"))]
 use crate::guid::Guid;
+use crate::output::{outln, OutputSink};
+use crate::util::Platform;
 use core::prelude::v1::derive;
 #[cfg(not(feature = "uefi"))]
 use guid_macros::guid;
@@ -38,40 +40,13 @@ use std::os::fd::AsRawFd;
 #[cfg(target_os = "freebsd")]
 use std::os::unix::fs::OpenOptionsExt;
 
-/// Decode from GUID string version
-///
-/// # Examples
-/// ```
-/// use framework_lib::esrt::*;
-/// use framework_lib::guid::*;
-///
-/// let valid_guid = Guid::from_values(0xA9C91B0C, 0xC0B8, 0x463D, 0xA7DA, 0xA5D6EC646333);
-/// // Works with lower-case
-/// let guid = guid_from_str("a9c91b0c-c0b8-463d-a7da-a5d6ec646333");
-/// assert_eq!(guid, Some(valid_guid));
-/// // And upper-case
-/// let guid = guid_from_str("A9C91B0C-C0B8-463D-A7DA-A5D6EC646333");
-/// assert_eq!(guid, Some(valid_guid));
-///
-/// let guid = guid_from_str("invalid-guid");
-/// assert_eq!(guid, None);
-/// ```
+#[cfg(not(feature = "uefi"))]
+pub use crate::guid::guid_from_str;
+
+/// Decode from GUID string version, see [`crate::guid::guid_from_str`]
+#[cfg(feature = "uefi")]
 pub fn guid_from_str(string: &str) -> Option<Guid> {
-    let string = string.strip_suffix('\n').unwrap_or(string);
-    let sections: Vec<&str> = string.split('-').collect();
-    let time_low = u32::from_str_radix(sections[0], 16).ok()?;
-    let time_mid = u16::from_str_radix(sections[1], 16).ok()?;
-    let time_high_and_version = u16::from_str_radix(sections[2], 16).ok()?;
-    let clock_seq_and_variant = u16::from_str_radix(sections[3], 16).ok()?;
-    let node = u64::from_str_radix(sections[4], 16).ok()?;
-
-    Some(Guid::from_values(
-        time_low,
-        time_mid,
-        time_high_and_version,
-        clock_seq_and_variant,
-        node,
-    ))
+    crate::guid::guid_from_str(string).map(|guid| Guid::from_bytes(guid.to_bytes()))
 }
 
 pub const TGL_BIOS_GUID: Guid = guid!("b3bdb2e4-c5cb-5c1b-bdc3-e6fc132462ff");
@@ -99,51 +74,84 @@ pub const MTL_CSME_GUID: Guid = guid!("32d8d677-eebc-4947-8f8a-0693a45240e5");
 /// gWindowsUxCapsuleGuid from MdePkg/MdePkg.dec
 pub const WINUX_GUID: Guid = guid!("3b8c8162-188c-46a4-aec9-be43f1d65697");
 
-#[derive(Debug)]
-pub enum FrameworkGuidKind {
-    TglBios,
-    AdlBios,
-    RplBios,
-    MtlBios,
-    TglRetimer01,
-    TglRetimer23,
-    AdlRetimer01,
-    AdlRetimer23,
-    RplRetimer01,
-    RplRetimer23,
-    MtlRetimer01,
-    MtlRetimer23,
-    RplCsme,
-    MtlCsme,
-    Fl16Bios,
-    Amd13Bios,
+/// What a firmware GUID updates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareComponent {
+    Bios,
+    /// Retimer of the left ports
+    Retimer01,
+    /// Retimer of the right ports
+    Retimer23,
+    Csme,
+    /// Image that's shown while the capsule is applied
     WinUx,
-    Unknown,
 }
 
-pub fn match_guid_kind(guid: &Guid) -> FrameworkGuidKind {
-    match *guid {
-        TGL_BIOS_GUID => FrameworkGuidKind::TglBios,
-        ADL_BIOS_GUID => FrameworkGuidKind::AdlBios,
-        RPL_BIOS_GUID => FrameworkGuidKind::RplBios,
-        MTL_BIOS_GUID => FrameworkGuidKind::MtlBios,
-        FL16_BIOS_GUID => FrameworkGuidKind::Fl16Bios,
-        AMD13_BIOS_GUID => FrameworkGuidKind::Amd13Bios,
-        TGL_RETIMER01_GUID => FrameworkGuidKind::TglRetimer01,
-        TGL_RETIMER23_GUID => FrameworkGuidKind::TglRetimer23,
-        ADL_RETIMER01_GUID => FrameworkGuidKind::AdlRetimer01,
-        ADL_RETIMER23_GUID => FrameworkGuidKind::AdlRetimer23,
-        RPL_RETIMER01_GUID => FrameworkGuidKind::RplRetimer01,
-        RPL_RETIMER23_GUID => FrameworkGuidKind::RplRetimer23,
-        MTL_RETIMER01_GUID => FrameworkGuidKind::MtlRetimer01,
-        MTL_RETIMER23_GUID => FrameworkGuidKind::MtlRetimer23,
-        RPL_CSME_GUID => FrameworkGuidKind::RplCsme,
-        MTL_CSME_GUID => FrameworkGuidKind::MtlCsme,
-        WINUX_GUID => FrameworkGuidKind::WinUx,
-        _ => FrameworkGuidKind::Unknown,
+/// Known GUID of the ESRT or of a capsule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FirmwareGuid {
+    pub guid: Guid,
+    /// None if it's used on all platforms
+    pub platform: Option<Platform>,
+    pub component: FirmwareComponent,
+    pub name: &'static str,
+}
+
+const fn fw(
+    guid: Guid,
+    platform: Option<Platform>,
+    component: FirmwareComponent,
+    name: &'static str,
+) -> FirmwareGuid {
+    FirmwareGuid {
+        guid,
+        platform,
+        component,
+        name,
     }
 }
 
+/// All known firmware GUIDs
+///
+/// Supporting a new platform only needs a row for each of its components.
+/// The GUIDs of the Framework 13 AMD Ryzen AI 300 and Framework Desktop
+/// firmware aren't known yet, their capsules and ESRT entries show as unknown.
+#[rustfmt::skip]
+pub const FIRMWARE_GUIDS: &[FirmwareGuid] = &[
+    fw(TGL_BIOS_GUID, Some(Platform::IntelGen11), FirmwareComponent::Bios, "Framework TGL Insyde BIOS"),
+    fw(ADL_BIOS_GUID, Some(Platform::IntelGen12), FirmwareComponent::Bios, "Framework ADL Insyde BIOS"),
+    fw(RPL_BIOS_GUID, Some(Platform::IntelGen13), FirmwareComponent::Bios, "Framework RPL Insyde BIOS"),
+    fw(MTL_BIOS_GUID, Some(Platform::IntelCoreUltra1), FirmwareComponent::Bios, "Framework MTL Insyde BIOS"),
+    fw(FL16_BIOS_GUID, Some(Platform::Framework16), FirmwareComponent::Bios, "Framework 16 Insyde BIOS"),
+    fw(AMD13_BIOS_GUID, Some(Platform::Framework13Amd), FirmwareComponent::Bios, "Framework 13 AMD Insyde BIOS"),
+    fw(TGL_RETIMER01_GUID, Some(Platform::IntelGen11), FirmwareComponent::Retimer01, "Framework TGL Retimer01 (Left)"),
+    fw(TGL_RETIMER23_GUID, Some(Platform::IntelGen11), FirmwareComponent::Retimer23, "Framework TGL Retimer23 (Right)"),
+    fw(ADL_RETIMER01_GUID, Some(Platform::IntelGen12), FirmwareComponent::Retimer01, "Framework ADL Retimer01 (Left)"),
+    fw(ADL_RETIMER23_GUID, Some(Platform::IntelGen12), FirmwareComponent::Retimer23, "Framework ADL Retimer23 (Right)"),
+    fw(RPL_RETIMER01_GUID, Some(Platform::IntelGen13), FirmwareComponent::Retimer01, "Framework RPL Retimer01 (Left)"),
+    fw(RPL_RETIMER23_GUID, Some(Platform::IntelGen13), FirmwareComponent::Retimer23, "Framework RPL Retimer23 (Right)"),
+    fw(MTL_RETIMER01_GUID, Some(Platform::IntelCoreUltra1), FirmwareComponent::Retimer01, "Framework MTL Retimer01 (Left)"),
+    fw(MTL_RETIMER23_GUID, Some(Platform::IntelCoreUltra1), FirmwareComponent::Retimer23, "Framework MTL Retimer23 (Right)"),
+    fw(RPL_CSME_GUID, Some(Platform::IntelGen13), FirmwareComponent::Csme, "Framework RPL CSME"),
+    fw(MTL_CSME_GUID, Some(Platform::IntelCoreUltra1), FirmwareComponent::Csme, "Framework MTL CSME"),
+    fw(WINUX_GUID, None, FirmwareComponent::WinUx, "Windows UX capsule"),
+];
+
+/// Look up a GUID in the table of known firmware GUIDs
+pub fn find_firmware_guid(guid: &Guid) -> Option<&'static FirmwareGuid> {
+    FIRMWARE_GUIDS.iter().find(|fw| fw.guid == *guid)
+}
+
+/// Name of the firmware that the GUID belongs to, for display
+pub fn guid_name(guid: &Guid) -> &'static str {
+    find_firmware_guid(guid).map_or("Unknown", |fw| fw.name)
+}
+
+/// Which component the GUID updates, if it's known
+pub fn guid_component(guid: &Guid) -> Option<FirmwareComponent> {
+    find_firmware_guid(guid).map(|fw| fw.component)
+}
+
 #[repr(packed)]
 struct _Esrt {
     resource_count: u32,
@@ -225,37 +233,46 @@ pub struct EsrtResourceEntry {
     pub last_attempt_status: u32,
 }
 
-pub fn print_esrt(esrt: &Esrt) {
-    println!("ESRT Table");
-    println!("  ResourceCount:        {}", esrt.resource_count);
-    println!("  ResourceCountMax:     {}", esrt.resource_count_max);
-    println!("  ResourceVersion:      {}", esrt.resource_version);
+pub fn print_esrt(esrt: &Esrt, out: &mut dyn OutputSink) {
+    outln!(out, "ESRT Table");
+    outln!(out, "  ResourceCount:        {}", esrt.resource_count);
+    outln!(out, "  ResourceCountMax:     {}", esrt.resource_count_max);
+    outln!(out, "  ResourceVersion:      {}", esrt.resource_version);
 
     for (i, entry) in esrt.entries.iter().enumerate() {
-        println!("ESRT Entry {}", i);
-        println!("  GUID:                 {}", entry.fw_class);
-        println!(
-            "  GUID:                 {:?}",
-            match_guid_kind(&entry.fw_class)
+        outln!(out, "ESRT Entry {}", i);
+        outln!(out, "  GUID:                 {}", entry.fw_class);
+        outln!(
+            out,
+            "  GUID:                 {}",
+            guid_name(&entry.fw_class)
         );
-        println!(
+        outln!(
+            out,
             "  Type:                 {:?}",
             ResourceType::from_int(entry.fw_type)
         );
-        println!(
+        outln!(
+            out,
             "  Version:              0x{:X} ({})",
-            entry.fw_version, entry.fw_version
+            entry.fw_version,
+            entry.fw_version
         );
-        println!(
+        outln!(
+            out,
             "  Min FW Version:       0x{:X} ({})",
-            entry.lowest_supported_fw_version, entry.lowest_supported_fw_version
+            entry.lowest_supported_fw_version,
+            entry.lowest_supported_fw_version
         );
-        println!("  Capsule Flags:        0x{:X}", entry.capsule_flags);
-        println!(
+        outln!(out, "  Capsule Flags:        0x{:X}", entry.capsule_flags);
+        outln!(
+            out,
             "  Last Attempt Version: 0x{:X} ({})",
-            entry.last_attempt_version, entry.last_attempt_version
+            entry.last_attempt_version,
+            entry.last_attempt_version
         );
-        println!(
+        outln!(
+            out,
             "  Last Attempt Status:  {:?}",
             UpdateStatus::from_int(entry.last_attempt_status)
         );
@@ -359,10 +376,10 @@ pub fn get_esrt() -> Option<Esrt> {
                 let ver_str = caps.get(2).unwrap().as_str().to_string();
 
                 let guid = guid_from_str(&guid_str).unwrap();
-                let guid_kind = match_guid_kind(&guid);
+                let guid_name = guid_name(&guid);
                 let ver = u32::from_str_radix(&ver_str, 16).unwrap();
                 debug!("ESRT Entry {}", i);
-                debug!("  Name:    {:?}", guid_kind);
+                debug!("  Name:    {}", guid_name);
                 debug!("  GUID:    {}", guid_str);
                 debug!("  Version: {:X} ({})", ver, ver);
 
@@ -490,3 +507,47 @@ unsafe fn esrt_from_buf(ptr: *const u8) -> Option<Esrt> {
 
     Some(esrt)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firmware_guids_unique() {
+        for (i, fw) in FIRMWARE_GUIDS.iter().enumerate() {
+            assert_eq!(find_firmware_guid(&fw.guid), Some(&FIRMWARE_GUIDS[i]));
+        }
+        assert_eq!(
+            guid_name(&MTL_RETIMER23_GUID),
+            "Framework MTL Retimer23 (Right)"
+        );
+        assert_eq!(guid_name(&SYSTEM_RESOURCE_TABLE_GUID), "Unknown");
+    }
+
+    #[cfg(all(not(feature = "uefi"), feature = "std", feature = "linux"))]
+    #[test]
+    fn esrt_of_fixtures() {
+        use framework_test_support::FIXTURES;
+
+        for fixture in FIXTURES {
+            let esrt = esrt_from_sysfs(&fixture.esrt_dir().join("entries")).unwrap();
+            assert_eq!(esrt.resource_count as usize, esrt.entries.len());
+            // Every system has a BIOS capsule for its platform
+            let bios = esrt
+                .entries
+                .iter()
+                .filter_map(|entry| find_firmware_guid(&entry.fw_class))
+                .find(|fw| fw.component == FirmwareComponent::Bios)
+                .unwrap();
+            assert_eq!(
+                format!("{:?}", bios.platform),
+                format!("Some({})", fixture.platform)
+            );
+            for entry in &esrt.entries {
+                let fw = find_firmware_guid(&entry.fw_class);
+                assert!(fw.is_some(), "{}: {}", fixture.name, entry.fw_class);
+                assert_eq!(fw.unwrap().platform, bios.platform);
+            }
+        }
+    }
+}
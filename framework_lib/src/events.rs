@@ -0,0 +1,391 @@
+//! Watch for hardware events and run a user-provided hook when they happen
+//!
+//! The EC state is read periodically and compared to the previous state. If
+//! the EC sends MKBP events, like for the lid or tablet mode, the state is read
+//! right after each event and only rarely otherwise.
+//! For every change the hook is executed with the following environment variables:
+//!
+//! - `FRAMEWORK_EVENT` - What changed, e.g. `ac` or `chassis`
+//! - `FRAMEWORK_EVENT_STATE` - The new state, e.g. `connected` or `open`
+//!
+//! The poll interval and which events are reported can be set in the config file.
+
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::chromium_ec::memmap::{MemmapBattery, EC_BATT_FLAG_CHARGING};
+use crate::chromium_ec::mkbp::MkbpEvent;
+use crate::chromium_ec::{CrosEc, EcResult};
+use crate::os_specific;
+use crate::util;
+
+/// How often to poll the EC for changes, unless configured otherwise
+pub const POLL_INTERVAL_MS: u64 = 1000;
+
+/// How often to poll if the EC sends events, unless configured otherwise
+///
+/// Only the states that don't have an event, like the privacy switches or the
+/// charge limit, depend on it.
+pub const MKBP_POLL_INTERVAL_MS: u64 = 10_000;
+
+/// Names of all events, as passed to the hook
+pub const EVENT_NAMES: &[&str] = &[
+    "ac",
+    "lid",
+    "chassis",
+    "microphone",
+    "camera",
+    "expansion_bay",
+    "tablet_mode",
+    "charge_limit",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HardwareEvent {
+    /// AC adapter connected or disconnected
+    Ac(bool),
+    /// Lid opened or closed
+    Lid(bool),
+    /// Chassis (intrusion switch) opened or closed
+    Chassis(bool),
+    /// Microphone privacy switch connected or disconnected
+    Microphone(bool),
+    /// Camera privacy switch connected or disconnected
+    Camera(bool),
+    /// Module inserted or removed from the expansion bay (Framework 16 only)
+    ExpansionBay(bool),
+    /// Entered or left tablet mode (convertibles only)
+    TabletMode(bool),
+    /// Charging stopped because the battery reached the charge limit, or resumed
+    ChargeLimit(bool),
+}
+
+impl HardwareEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ac(_) => "ac",
+            Self::Lid(_) => "lid",
+            Self::Chassis(_) => "chassis",
+            Self::Microphone(_) => "microphone",
+            Self::Camera(_) => "camera",
+            Self::ExpansionBay(_) => "expansion_bay",
+            Self::TabletMode(_) => "tablet_mode",
+            Self::ChargeLimit(_) => "charge_limit",
+        }
+    }
+
+    pub fn state(&self) -> &'static str {
+        match self {
+            Self::Ac(true) | Self::Microphone(true) | Self::Camera(true) => "connected",
+            Self::Ac(false) | Self::Microphone(false) | Self::Camera(false) => "disconnected",
+            Self::Lid(true) | Self::Chassis(true) => "open",
+            Self::Lid(false) | Self::Chassis(false) => "closed",
+            Self::ExpansionBay(true) => "inserted",
+            Self::ExpansionBay(false) => "removed",
+            Self::TabletMode(true) => "tablet",
+            Self::TabletMode(false) => "laptop",
+            Self::ChargeLimit(true) => "reached",
+            Self::ChargeLimit(false) => "cleared",
+        }
+    }
+}
+
+/// Snapshot of all the states that we watch
+///
+/// Each is None if the system doesn't support reading it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HardwareState {
+    pub ac_present: Option<bool>,
+    pub lid_open: Option<bool>,
+    pub chassis_open: Option<bool>,
+    pub microphone: Option<bool>,
+    pub camera: Option<bool>,
+    pub expansion_bay_module: Option<bool>,
+    pub tablet_mode: Option<bool>,
+    pub charge_limit_reached: Option<bool>,
+}
+
+/// Whether the EC holds off charging, because the battery is at the charge limit
+fn charge_limit_reached(battery: &MemmapBattery, max_percent: u8) -> bool {
+    if !battery.ac_present() || battery.last_full_charge_capacity == 0 {
+        return false;
+    }
+    let percent = 100 * battery.remaining_capacity / battery.last_full_charge_capacity;
+    let charging = battery.flags & EC_BATT_FLAG_CHARGING != 0;
+    max_percent < 100 && !charging && percent >= max_percent as u32
+}
+
+/// Old and new value of a state, and the event for when it changes
+type StateChange = (Option<bool>, Option<bool>, fn(bool) -> HardwareEvent);
+
+impl HardwareState {
+    pub fn read(ec: &CrosEc) -> Self {
+        let (microphone, camera) = match ec.get_privacy_info() {
+            Ok((mic, cam)) => (Some(mic), Some(cam)),
+            Err(_) => (None, None),
+        };
+        let memmap = ec.memmap().ok();
+        let has_lid = util::platform_spec().has_lid;
+        HardwareState {
+            ac_present: memmap.as_ref().map(|m| m.battery.ac_present()),
+            lid_open: memmap.as_ref().filter(|_| has_lid).map(|m| m.lid_open()),
+            chassis_open: ec
+                .get_intrusion_status()
+                .ok()
+                .map(|status| status.currently_open),
+            microphone,
+            camera,
+            expansion_bay_module: ec
+                .get_expansion_bay_status()
+                .ok()
+                .map(|status| status.expansion_bay_board().is_ok()),
+            tablet_mode: ec.get_tablet_mode().ok(),
+            charge_limit_reached: memmap
+                .as_ref()
+                .filter(|m| m.battery.battery_present())
+                .zip(ec.get_charge_limit().ok())
+                .map(|(m, (_min, max))| charge_limit_reached(&m.battery, max)),
+        }
+    }
+
+    /// Which events happened to get from this state to the new state
+    ///
+    /// A value changing from or to unknown is not considered an event.
+    pub fn events(&self, new: &HardwareState) -> Vec<HardwareEvent> {
+        let changes: [StateChange; 8] = [
+            (self.ac_present, new.ac_present, HardwareEvent::Ac),
+            (self.lid_open, new.lid_open, HardwareEvent::Lid),
+            (self.chassis_open, new.chassis_open, HardwareEvent::Chassis),
+            (self.microphone, new.microphone, HardwareEvent::Microphone),
+            (self.camera, new.camera, HardwareEvent::Camera),
+            (
+                self.expansion_bay_module,
+                new.expansion_bay_module,
+                HardwareEvent::ExpansionBay,
+            ),
+            (self.tablet_mode, new.tablet_mode, HardwareEvent::TabletMode),
+            (
+                self.charge_limit_reached,
+                new.charge_limit_reached,
+                HardwareEvent::ChargeLimit,
+            ),
+        ];
+        changes
+            .iter()
+            .filter_map(|(old, new, event)| match (old, new) {
+                (Some(old), Some(new)) if old != new => Some(event(*new)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Waits until the hardware state might have changed
+pub struct StateWaiter {
+    mkbp: bool,
+    interval: Duration,
+}
+
+impl StateWaiter {
+    /// Default poll interval depends on whether the EC sends events
+    pub fn new(ec: &CrosEc, interval_ms: Option<u64>) -> Self {
+        let mkbp = ec.get_mkbp_switches().is_ok();
+        debug!("EC sends MKBP events: {}", mkbp);
+        let default_ms = if mkbp {
+            MKBP_POLL_INTERVAL_MS
+        } else {
+            POLL_INTERVAL_MS
+        };
+        StateWaiter {
+            mkbp,
+            interval: Duration::from_millis(interval_ms.unwrap_or(default_ms)),
+        }
+    }
+
+    /// Wait for an EC event, or until the poll interval is over
+    pub fn wait(&self, ec: &CrosEc) {
+        let start = Instant::now();
+        if self.mkbp {
+            loop {
+                let Some(timeout) = self.interval.checked_sub(start.elapsed()) else {
+                    return;
+                };
+                match ec.next_event(timeout) {
+                    // Sensor data and key presses don't change any of the states
+                    Ok(Some(MkbpEvent::SensorFifo | MkbpEvent::KeyMatrix(_))) => continue,
+                    Ok(Some(event)) => {
+                        debug!("EC event: {:?}", event);
+                        return;
+                    }
+                    Ok(None) => return,
+                    Err(err) => {
+                        debug!("Failed to get EC event: {:?}", err);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(remaining) = self.interval.checked_sub(start.elapsed()) {
+            os_specific::sleep(remaining.as_micros() as u64);
+        }
+    }
+}
+
+/// Run the hook, with the event in its environment variables
+pub fn run_hook(hook: &str, event: &HardwareEvent) {
+    let status = Command::new(hook)
+        .env("FRAMEWORK_EVENT", event.name())
+        .env("FRAMEWORK_EVENT_STATE", event.state())
+        .status();
+    match status {
+        Ok(status) if !status.success() => {
+            error!("Hook {} failed with {}", hook, status);
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!("Failed to run hook {}: {:?}", hook, err);
+        }
+    }
+}
+
+/// Watch the EC forever and report every event, optionally running the hook
+///
+/// If `interval_ms` isn't given, the default depends on whether the EC sends
+/// events. If `enabled` is given, only events with those names are reported.
+pub fn watch(
+    ec: &CrosEc,
+    hook: Option<&str>,
+    interval_ms: Option<u64>,
+    enabled: Option<&[String]>,
+) {
+    let waiter = StateWaiter::new(ec, interval_ms);
+    let mut state = HardwareState::read(ec);
+    debug!("Initial state: {:?}", state);
+
+    loop {
+        waiter.wait(ec);
+
+        let new_state = HardwareState::read(ec);
+        let events = state.events(&new_state);
+        for event in events
+            .iter()
+            .filter(|e| enabled.map_or(true, |names| names.iter().any(|x| x == e.name())))
+        {
+            println!("{}: {}", event.name(), event.state());
+            if let Some(hook) = hook {
+                run_hook(hook, event);
+            }
+        }
+        state = new_state;
+    }
+}
+
+/// Poll only the intrusion switch and report whenever the chassis is opened or closed
+///
+/// Rings the terminal bell when the chassis is opened. If `exit_on_open` is set,
+/// returns as soon as the chassis is open, otherwise runs forever.
+pub fn watch_intrusion(
+    ec: &CrosEc,
+    interval_ms: u64,
+    hook: Option<&str>,
+    exit_on_open: bool,
+) -> EcResult<()> {
+    let start = Instant::now();
+    let mut open = ec.get_intrusion_status()?.currently_open;
+    println!("Chassis {}", HardwareEvent::Chassis(open).state());
+
+    loop {
+        if open && exit_on_open {
+            return Ok(());
+        }
+        os_specific::sleep(interval_ms * 1000);
+
+        // Keep watching, the EC might just be busy
+        let new_open = match ec.get_intrusion_status() {
+            Ok(status) => status.currently_open,
+            Err(err) => {
+                error!("Failed to read intrusion status: {:?}", err);
+                continue;
+            }
+        };
+        if new_open == open {
+            continue;
+        }
+        open = new_open;
+
+        let event = HardwareEvent::Chassis(open);
+        let bell = if open { "\x07" } else { "" };
+        println!(
+            "[{:>8.1}s] Chassis {}{}",
+            start.elapsed().as_secs_f32(),
+            event.state(),
+            bell
+        );
+        if let Some(hook) = hook {
+            run_hook(hook, &event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chromium_ec::memmap::EC_BATT_FLAG_AC_PRESENT;
+
+    #[test]
+    fn detect_events() {
+        let old = HardwareState {
+            ac_present: Some(false),
+            lid_open: Some(true),
+            chassis_open: Some(false),
+            microphone: Some(true),
+            camera: None,
+            expansion_bay_module: None,
+            tablet_mode: Some(false),
+            charge_limit_reached: None,
+        };
+        let new = HardwareState {
+            ac_present: Some(true),
+            chassis_open: Some(true),
+            camera: Some(true),
+            tablet_mode: Some(true),
+            ..old.clone()
+        };
+
+        assert!(old.events(&old).is_empty());
+        assert_eq!(
+            old.events(&new),
+            vec![
+                HardwareEvent::Ac(true),
+                HardwareEvent::Chassis(true),
+                HardwareEvent::TabletMode(true)
+            ]
+        );
+        assert_eq!(
+            new.events(&old),
+            vec![
+                HardwareEvent::Ac(false),
+                HardwareEvent::Chassis(false),
+                HardwareEvent::TabletMode(false)
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_charge_limit() {
+        let mut battery = MemmapBattery {
+            flags: EC_BATT_FLAG_AC_PRESENT,
+            remaining_capacity: 3200,
+            last_full_charge_capacity: 4000,
+            ..Default::default()
+        };
+        assert!(charge_limit_reached(&battery, 80));
+        assert!(!charge_limit_reached(&battery, 100));
+        assert!(!charge_limit_reached(&battery, 90));
+
+        battery.flags |= EC_BATT_FLAG_CHARGING;
+        assert!(!charge_limit_reached(&battery, 80));
+        battery.flags = 0;
+        assert!(!charge_limit_reached(&battery, 80));
+    }
+}
@@ -0,0 +1,300 @@
+//! C interface to the most common functions of the library
+//!
+//! Lets applications that aren't written in Rust, for example in C or Python
+//! with ctypes, use the library without running `framework_tool`. Build it as
+//! a shared library with:
+//!
+//! ```sh
+//! cargo rustc -p framework_lib --features ffi --crate-type cdylib --release
+//! ```
+//!
+//! The header `include/framework_lib.h` is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/framework_lib.h`.
+//!
+//! Functions that return a status use the same values as the exit codes of
+//! the commandline tool. Strings returned by the library are JSON and have to
+//! be freed with `fw_free_string`. A panic inside the library doesn't unwind
+//! into the caller, it's returned as `FwStatus::Failure` or NULL.
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::audit::json_string;
+use crate::chromium_ec::{CrosEc, EcCurrentImage, EcError, EcResponseStatus};
+use crate::power;
+use crate::smbios;
+
+/// Result of a function, same values as the exit codes of `framework_tool`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FwStatus {
+    Ok = 0,
+    /// Failed for a reason not covered by the other codes
+    Failure = 1,
+    /// Invalid argument, e.g. a NULL pointer or value out of range
+    InvalidArgument = 2,
+    /// Could not communicate with the EC
+    EcUnreachable = 3,
+    /// Not allowed to access the EC, usually have to be root/administrator
+    Permission = 4,
+    /// Firmware read back after flashing doesn't match the file
+    FlashVerify = 5,
+    /// Not supported by the system or its firmware
+    Unsupported = 6,
+}
+
+impl From<&EcError> for FwStatus {
+    fn from(err: &EcError) -> FwStatus {
+        match err.root_cause() {
+            EcError::DeviceError(_)
+            | EcError::DriverUnavailable(_)
+            | EcError::Io(_)
+            | EcError::Timeout(_) => FwStatus::EcUnreachable,
+            EcError::Permission(_) => FwStatus::Permission,
+            EcError::VerifyFailed(_) => FwStatus::FlashVerify,
+            EcError::Response(EcResponseStatus::InvalidCommand) | EcError::Unsupported(_) => {
+                FwStatus::Unsupported
+            }
+            _ => FwStatus::Failure,
+        }
+    }
+}
+
+/// Called with the number of bytes done and the total, while flashing
+pub type FwProgressCallback = extern "C" fn(done: usize, total: usize, user_data: *mut c_void);
+
+/// Run `f` and return `on_panic` if it panics
+///
+/// Unwinding into C is undefined behavior and the library has asserts and
+/// unwraps. The panic message is still printed by the panic hook.
+fn catch_panic<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    // JSON never has NUL bytes, json_string escapes them
+    CString::new(s).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+fn versions_json(ec: &CrosEc) -> String {
+    let bios = smbios::get_smbios_info().and_then(|info| info.bios);
+    let bios_version = bios.as_ref().and_then(|bios| bios.version.as_deref());
+    let bios_date = bios.as_ref().and_then(|bios| bios.release_date.as_deref());
+
+    let (ec_ro, ec_rw, ec_current) = match ec.flash_version() {
+        Some((ro, rw, current)) => (Some(ro), Some(rw), Some(current)),
+        None => (None, None, None),
+    };
+    let ec_current = ec_current.map(|current| match current {
+        EcCurrentImage::RO => "RO",
+        EcCurrentImage::RW => "RW",
+        EcCurrentImage::Unknown => "Unknown",
+    });
+
+    let pd = power::read_pd_version(ec).ok();
+    let pd01 = pd.as_ref().map(|pd| pd.controller01.app.to_string());
+    let pd23 = pd.as_ref().map(|pd| pd.controller23.app.to_string());
+
+    format!(
+        "{{\"bios_version\": {}, \"bios_date\": {}, \"ec_ro\": {}, \"ec_rw\": {}, \"ec_current\": {}, \"pd01\": {}, \"pd23\": {}}}",
+        json_option(bios_version),
+        json_option(bios_date),
+        json_option(ec_ro.as_deref()),
+        json_option(ec_rw.as_deref()),
+        json_option(ec_current),
+        json_option(pd01.as_deref()),
+        json_option(pd23.as_deref()),
+    )
+}
+
+fn power_info_json(info: &power::PowerInfo) -> String {
+    let battery = match &info.battery {
+        Some(battery) => format!(
+            "{{\"charge_percentage\": {}, \"charging\": {}, \"discharging\": {}, \"cycle_count\": {}, \
+             \"voltage_mv\": {}, \"rate_ma\": {}, \"remaining_capacity_mah\": {}, \
+             \"last_full_charge_capacity_mah\": {}, \"design_capacity_mah\": {}}}",
+            battery.charge_percentage,
+            battery.charging,
+            battery.discharging,
+            battery.cycle_count,
+            battery.present_voltage,
+            battery.present_rate,
+            battery.remaining_capacity,
+            battery.last_full_charge_capacity,
+            battery.design_capacity,
+        ),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"ac_present\": {}, \"battery\": {}}}",
+        info.ac_present, battery
+    )
+}
+
+/// Versions of BIOS, EC and PD firmware as a JSON object
+///
+/// Versions that can't be read are `null`. Free the result with `fw_free_string`.
+#[no_mangle]
+pub extern "C" fn fw_get_versions_json() -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || {
+        into_c_string(versions_json(&CrosEc::new()))
+    })
+}
+
+/// AC and battery status as a JSON object
+///
+/// Returns NULL if the EC can't be read. Free the result with `fw_free_string`.
+#[no_mangle]
+pub extern "C" fn fw_get_power_info_json() -> *mut c_char {
+    catch_panic(std::ptr::null_mut(), || {
+        match power::power_info(&CrosEc::new()) {
+            Some(info) => into_c_string(power_info_json(&info)),
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Set the maximum battery charge limit in percent, between 25 and 100
+#[no_mangle]
+pub extern "C" fn fw_set_charge_limit(max: u8) -> FwStatus {
+    if !(25..=100).contains(&max) {
+        return FwStatus::InvalidArgument;
+    }
+    catch_panic(FwStatus::Failure, || {
+        let ec = CrosEc::new();
+        let res = ec
+            .get_charge_limit()
+            .and_then(|(min, _max)| ec.set_charge_limit(min, max));
+        match res {
+            Ok(()) => FwStatus::Ok,
+            Err(err) => FwStatus::from(&err),
+        }
+    })
+}
+
+/// Flash the EC firmware file at `path`
+///
+/// `flash_type` is 0 to flash everything, 1 for only RO and 2 for only RW.
+/// `progress` is optional and called from the same thread, with `user_data`
+/// passed through.
+///
+/// Same as `framework_tool --flash-ec`, flashing the EC from the OS is not
+/// supported yet. EC communication from the OS can't be trusted to reliably
+/// flash the EC without risk of damage, so every valid `flash_type` returns
+/// `FwStatus::Unsupported`.
+///
+/// # Safety
+///
+/// `path` must be a valid, NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn fw_flash_ec(
+    path: *const c_char,
+    flash_type: u32,
+    _progress: Option<FwProgressCallback>,
+    _user_data: *mut c_void,
+) -> FwStatus {
+    if path.is_null() {
+        return FwStatus::InvalidArgument;
+    }
+    if CStr::from_ptr(path).to_str().is_err() || flash_type > 2 {
+        return FwStatus::InvalidArgument;
+    }
+    error!("Flashing EC from the OS is not supported yet");
+    FwStatus::Unsupported
+}
+
+/// Free a string returned by the library
+///
+/// # Safety
+///
+/// `s` must have been returned by one of the functions of this library and
+/// not been freed before. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn fw_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        catch_panic((), || drop(CString::from_raw(s)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::{BatteryInformation, PowerInfo};
+
+    #[test]
+    fn format_power_info() {
+        let info = PowerInfo {
+            ac_present: true,
+            battery: None,
+        };
+        assert_eq!(
+            power_info_json(&info),
+            "{\"ac_present\": true, \"battery\": null}"
+        );
+
+        let battery = BatteryInformation {
+            present_voltage: 17_000,
+            present_rate: 1_000,
+            remaining_capacity: 2_000,
+            battery_count: 1,
+            current_battery_index: 0,
+            design_capacity: 3_572,
+            design_voltage: 15_480,
+            last_full_charge_capacity: 3_400,
+            cycle_count: 42,
+            charge_percentage: 58,
+            manufacturer: String::new(),
+            model_number: String::new(),
+            serial_number: String::new(),
+            battery_type: String::new(),
+            discharging: false,
+            charging: true,
+            level_critical: false,
+        };
+        let info = PowerInfo {
+            ac_present: true,
+            battery: Some(battery),
+        };
+        let json = power_info_json(&info);
+        assert!(
+            json.starts_with("{\"ac_present\": true, \"battery\": {\"charge_percentage\": 58, ")
+        );
+        assert!(json.ends_with("\"design_capacity_mah\": 3572}}"));
+    }
+
+    #[test]
+    fn free_strings() {
+        let s = into_c_string("{\"a\": 1}".to_string());
+        assert_eq!(unsafe { CStr::from_ptr(s) }.to_str(), Ok("{\"a\": 1}"));
+        unsafe {
+            fw_free_string(s);
+            fw_free_string(std::ptr::null_mut());
+        }
+        assert_eq!(fw_set_charge_limit(101), FwStatus::InvalidArgument);
+    }
+
+    #[test]
+    fn refuse_flashing() {
+        let path = CString::new("ec.bin").unwrap();
+        for flash_type in 0..=2 {
+            let status =
+                unsafe { fw_flash_ec(path.as_ptr(), flash_type, None, std::ptr::null_mut()) };
+            assert_eq!(status, FwStatus::Unsupported);
+        }
+        let status = unsafe { fw_flash_ec(path.as_ptr(), 3, None, std::ptr::null_mut()) };
+        assert_eq!(status, FwStatus::InvalidArgument);
+    }
+
+    #[test]
+    fn panic_is_failure() {
+        assert_eq!(
+            catch_panic(FwStatus::Failure, || panic!("test")),
+            FwStatus::Failure
+        );
+    }
+}
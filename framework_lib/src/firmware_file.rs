@@ -0,0 +1,169 @@
+//! Figure out what kind of firmware a file contains
+//!
+//! Firmware files are often just called `something.bin`. Each kind is
+//! recognized by the same checks that its parser does, so a file that's
+//! identified here can also be parsed by the matching command.
+
+use core::convert::TryInto;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::capsule;
+use crate::capsule_content;
+use crate::ccgx::binary as ccgx_binary;
+use crate::ccgx::SiliconId;
+use crate::ec_binary;
+
+/// Magic numbers at the start of every UF2 block
+const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+/// Set if the block has the family ID instead of the file size
+const UF2_FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const UF2_FAMILY_RP2040: u32 = 0xE48B_FF56;
+
+/// Same order as `--pd-bin` tries them, the checks of some overlap
+const PD_SILICONS: &[SiliconId] = &[
+    SiliconId::Ccg3,
+    SiliconId::Ccg8,
+    SiliconId::Ccg5,
+    SiliconId::Ccg6,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirmwareFile {
+    /// UEFI capsule, for example BIOS, retimer or Windows UX
+    Capsule,
+    /// InsydeH2O BIOS update capsule, which includes the EC and PD firmware
+    H2oCapsule,
+    /// EC firmware image
+    Ec,
+    /// PD controller firmware, for the given controller
+    Pd(SiliconId),
+    /// UF2 firmware for a microcontroller, like the RP2040 of the Framework 16
+    /// input modules. Has the family ID, if the file includes it
+    Uf2(Option<u32>),
+}
+
+impl FirmwareFile {
+    pub fn description(&self) -> &'static str {
+        match self {
+            FirmwareFile::Capsule => "UEFI capsule",
+            FirmwareFile::H2oCapsule => "InsydeH2O BIOS capsule",
+            FirmwareFile::Ec => "EC firmware",
+            FirmwareFile::Pd(SiliconId::Ccg3) => "CCG3 PD firmware",
+            FirmwareFile::Pd(SiliconId::Ccg5) => "CCG5 PD firmware",
+            FirmwareFile::Pd(SiliconId::Ccg6) => "CCG6 PD firmware",
+            FirmwareFile::Pd(SiliconId::Ccg8) => "CCG8 PD firmware",
+            FirmwareFile::Uf2(Some(UF2_FAMILY_RP2040)) => "RP2040 UF2 firmware (input module)",
+            FirmwareFile::Uf2(_) => "UF2 firmware",
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn is_capsule(data: &[u8]) -> bool {
+    data.len() >= core::mem::size_of::<capsule::EfiCapsuleHeader>()
+        && capsule::parse_capsule_header(data).is_some()
+}
+
+fn uf2_family(data: &[u8]) -> Option<Option<u32>> {
+    if read_u32(data, 0)? != UF2_MAGIC_START0 || read_u32(data, 4)? != UF2_MAGIC_START1 {
+        return None;
+    }
+    let flags = read_u32(data, 8)?;
+    if flags & UF2_FLAG_FAMILY_ID != 0 {
+        Some(read_u32(data, 28))
+    } else {
+        Some(None)
+    }
+}
+
+/// Identify the kind of firmware by its content
+///
+/// Returns None if the file isn't any of the known kinds.
+pub fn identify(data: &[u8]) -> Option<FirmwareFile> {
+    // Has to come before the H2O capsule, BIOS capsules have the same marker
+    if is_capsule(data) {
+        return Some(FirmwareFile::Capsule);
+    }
+    if capsule_content::find_bios_version(data).is_some() {
+        return Some(FirmwareFile::H2oCapsule);
+    }
+    if ec_binary::read_ec_version(data, true).is_some()
+        || ec_binary::read_ec_version(data, false).is_some()
+    {
+        return Some(FirmwareFile::Ec);
+    }
+    if let Some(silicon) = PD_SILICONS
+        .iter()
+        .find(|silicon| ccgx_binary::read_versions(data, **silicon).is_some())
+    {
+        return Some(FirmwareFile::Pd(*silicon));
+    }
+    if let Some(family) = uf2_family(data) {
+        return Some(FirmwareFile::Uf2(family));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn identify_test_bin(name: &str) -> Option<FirmwareFile> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("test_bins");
+        path.push(name);
+        identify(&fs::read(path).unwrap())
+    }
+
+    #[test]
+    fn identify_test_bins() {
+        assert_eq!(identify_test_bin("winux.bin"), Some(FirmwareFile::Capsule));
+        assert_eq!(
+            identify_test_bin("adl-ec-0.0.1.bin"),
+            Some(FirmwareFile::Ec)
+        );
+        assert_eq!(
+            identify_test_bin("amd-fl16-ec-3.03.bin"),
+            Some(FirmwareFile::Ec)
+        );
+        assert_eq!(
+            identify_test_bin("dp-pd-3.0.17.100.bin"),
+            Some(FirmwareFile::Pd(SiliconId::Ccg3))
+        );
+        assert_eq!(
+            identify_test_bin("tgl-pd-3.8.0.bin"),
+            Some(FirmwareFile::Pd(SiliconId::Ccg5))
+        );
+        assert_eq!(
+            identify_test_bin("adl-pd-0.1.33.bin"),
+            Some(FirmwareFile::Pd(SiliconId::Ccg6))
+        );
+        assert_eq!(
+            identify_test_bin("fl16-pd-0.0.03.bin"),
+            Some(FirmwareFile::Pd(SiliconId::Ccg8))
+        );
+    }
+
+    #[test]
+    fn identify_uf2() {
+        let mut block = [0u8; 512];
+        block[0..4].copy_from_slice(&UF2_MAGIC_START0.to_le_bytes());
+        block[4..8].copy_from_slice(&UF2_MAGIC_START1.to_le_bytes());
+        block[8..12].copy_from_slice(&UF2_FLAG_FAMILY_ID.to_le_bytes());
+        block[28..32].copy_from_slice(&UF2_FAMILY_RP2040.to_le_bytes());
+        let kind = identify(&block).unwrap();
+        assert_eq!(kind, FirmwareFile::Uf2(Some(UF2_FAMILY_RP2040)));
+        assert_eq!(kind.description(), "RP2040 UF2 firmware (input module)");
+
+        assert_eq!(identify(&[0u8; 16]), None);
+        assert_eq!(identify(&[]), None);
+    }
+}
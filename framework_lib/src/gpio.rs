@@ -0,0 +1,213 @@
+//! Explain what the EC GPIOs of each platform are for
+//!
+//! The EC only knows GPIOs by their schematic names, like `lid_sw_l`. This
+//! adds a description and the level that's expected during normal use in
+//! laptop mode, so that a user can tell whether a reading is suspicious.
+//! GPIOs that end in `_l` are active low.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::chromium_ec::{CrosEc, EcResult};
+use crate::output::{outln, OutputSink};
+use crate::util::Platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpioInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Level during normal use, None if it depends on how the system is used
+    pub expected: Option<bool>,
+}
+
+const fn gpio(name: &'static str, description: &'static str, expected: Option<bool>) -> GpioInfo {
+    GpioInfo {
+        name,
+        description,
+        expected,
+    }
+}
+
+/// GPIOs that all Framework laptops have, the desktop too except for the lid switch
+const COMMON_GPIOS: &[GpioInfo] = &[
+    gpio(
+        "lid_sw_l",
+        "Lid switch, low when the lid is closed",
+        Some(true),
+    ),
+    gpio("ac_present", "High when a charger is attached", None),
+    gpio(
+        "on_off_btn_l",
+        "Power button, low while pressed",
+        Some(true),
+    ),
+    gpio(
+        "chassis_open_l",
+        "Chassis intrusion switch, low when the bottom cover is removed",
+        Some(true),
+    ),
+    gpio(
+        "h_prochot_l",
+        "Low when the EC throttles the CPU, e.g. when overheating",
+        Some(true),
+    ),
+];
+
+const FRAMEWORK16_GPIOS: &[GpioInfo] = &[gpio(
+    "sleep_l",
+    "Tells the input modules that the system is in standby, low while asleep",
+    Some(true),
+)];
+
+/// Platforms that aren't known yet might be convertibles
+const CONVERTIBLE_GPIOS: &[GpioInfo] = &[gpio(
+    "tablet_mode_l",
+    "Hall sensor, low when the screen is folded into tablet mode",
+    Some(true),
+)];
+
+/// GPIOs that are known to exist on the platform
+pub fn known_gpios(platform: Option<Platform>) -> Vec<&'static GpioInfo> {
+    let specific: &[GpioInfo] = match platform {
+        Some(Platform::Framework16) => FRAMEWORK16_GPIOS,
+        Some(Platform::IntelGen11)
+        | Some(Platform::IntelGen12)
+        | Some(Platform::IntelGen13)
+        | Some(Platform::IntelCoreUltra1)
+        | Some(Platform::Framework13Amd)
+        | Some(Platform::DesktopAmdAi300) => &[],
+        Some(Platform::GenericFramework(..)) | None => CONVERTIBLE_GPIOS,
+    };
+    let has_lid = platform.map_or(true, |platform| platform.spec().has_lid);
+    COMMON_GPIOS
+        .iter()
+        .filter(|x| has_lid || x.name != "lid_sw_l")
+        .chain(specific)
+        .collect()
+}
+
+pub fn find_gpio(platform: Option<Platform>, name: &str) -> Option<&'static GpioInfo> {
+    known_gpios(platform).into_iter().find(|x| x.name == name)
+}
+
+/// Guess the platform from the GPIOs that the EC knows
+///
+/// For when SMBIOS doesn't identify it, e.g. with a new BIOS that has a
+/// different product name. Only the Framework 16 EC has `sleep_l`.
+pub fn guess_platform(ec: &CrosEc) -> Option<Platform> {
+    if ec.get_gpio("sleep_l").is_ok() {
+        Some(Platform::Framework16)
+    } else {
+        None
+    }
+}
+
+fn fmt_level(value: bool) -> &'static str {
+    if value {
+        "high"
+    } else {
+        "low"
+    }
+}
+
+fn fmt_annotated(name: &str, value: &EcResult<bool>, info: Option<&GpioInfo>) -> String {
+    let value = match value {
+        Ok(value) => fmt_level(*value),
+        Err(_) => "not present",
+    };
+    let Some(info) = info else {
+        return format!("{:<16} {:<11} Unknown GPIO", name, value);
+    };
+    let expected = match info.expected {
+        Some(expected) => format!("(expected {})", fmt_level(expected)),
+        None => String::new(),
+    };
+    format!(
+        "{:<16} {:<11} {:<15} {}",
+        name, value, expected, info.description
+    )
+}
+
+/// Show GPIOs with their descriptions
+///
+/// Only the given one, or else all that are known for the platform.
+/// If the platform isn't known, it's guessed from the GPIOs.
+pub fn print_annotated(
+    ec: &CrosEc,
+    platform: Option<Platform>,
+    name: Option<&str>,
+    out: &mut dyn OutputSink,
+) {
+    let platform = platform.or_else(|| guess_platform(ec));
+    if let Some(name) = name {
+        let value = ec.get_gpio(name);
+        outln!(
+            out,
+            "{}",
+            fmt_annotated(name, &value, find_gpio(platform, name))
+        );
+        return;
+    }
+    for info in known_gpios(platform) {
+        let value = ec.get_gpio(info.name);
+        outln!(out, "{}", fmt_annotated(info.name, &value, Some(info)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chromium_ec::EcError;
+
+    #[test]
+    fn platform_gpios() {
+        let fw16 = known_gpios(Some(Platform::Framework16));
+        assert!(fw16.iter().any(|x| x.name == "sleep_l"));
+        assert!(!fw16.iter().any(|x| x.name == "tablet_mode_l"));
+        assert!(find_gpio(None, "tablet_mode_l").is_some());
+        assert!(find_gpio(Some(Platform::Framework13Amd), "lid_sw_l").is_some());
+        assert!(find_gpio(Some(Platform::Framework13Amd), "sleep_l").is_none());
+        assert!(find_gpio(Some(Platform::DesktopAmdAi300), "lid_sw_l").is_none());
+    }
+
+    #[test]
+    fn format_annotated() {
+        let lid = find_gpio(None, "lid_sw_l");
+        assert_eq!(
+            fmt_annotated("lid_sw_l", &Ok(false), lid),
+            "lid_sw_l         low         (expected high) Lid switch, low when the lid is closed"
+        );
+        let err = Err(EcError::DeviceError(String::from("Not found")));
+        assert_eq!(
+            fmt_annotated("foo", &err, None),
+            "foo              not present Unknown GPIO"
+        );
+    }
+
+    #[test]
+    fn guess_platform_of_fixtures() {
+        use crate::chromium_ec::trace::with_replay;
+        use crate::output::StringSink;
+        use framework_test_support::FIXTURES;
+
+        for fixture in FIXTURES {
+            let is_framework16 = fixture.platform == "Framework16";
+            let platform = with_replay(&fixture.ec_trace(), guess_platform);
+            assert_eq!(platform.is_some(), is_framework16, "{}", fixture.name);
+
+            let mut out = StringSink::default();
+            with_replay(&fixture.ec_trace(), |ec| {
+                print_annotated(ec, None, Some("sleep_l"), &mut out)
+            });
+            let expected = if is_framework16 {
+                "sleep_l          high        (expected high) Tells the input modules"
+            } else {
+                "sleep_l          not present Unknown GPIO"
+            };
+            assert!(out.buffer.starts_with(expected), "{}", out.buffer);
+        }
+    }
+}
@@ -111,6 +111,78 @@ impl fmt::Display for Guid {
     }
 }
 
+/// Value of a hexadecimal digit, in either case
+const fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a GUID string, like `12345678-9abc-def0-1234-56789abcdef0`
+///
+/// Upper case and the form in braces, `{12345678-9ABC-DEF0-1234-56789ABCDEF0}`,
+/// are accepted too. A trailing newline, like in sysfs files, is ignored.
+/// Unlike the `guid!` macro this works at runtime, but it's a const fn so it
+/// can be used in constants as well.
+///
+/// # Examples
+/// ```
+/// use framework_lib::guid::*;
+///
+/// let valid_guid = Guid::from_values(0xA9C91B0C, 0xC0B8, 0x463D, 0xA7DA, 0xA5D6EC646333);
+/// // Works with lower-case
+/// let guid = guid_from_str("a9c91b0c-c0b8-463d-a7da-a5d6ec646333");
+/// assert_eq!(guid, Some(valid_guid));
+/// // And upper-case
+/// let guid = guid_from_str("A9C91B0C-C0B8-463D-A7DA-A5D6EC646333");
+/// assert_eq!(guid, Some(valid_guid));
+///
+/// let guid = guid_from_str("invalid-guid");
+/// assert_eq!(guid, None);
+/// ```
+pub const fn guid_from_str(string: &str) -> Option<Guid> {
+    let mut bytes = string.as_bytes();
+    if let [rest @ .., b'\n'] = bytes {
+        bytes = rest;
+    }
+    if let [b'{', rest @ .., b'}'] = bytes {
+        bytes = rest;
+    }
+    if bytes.len() != 36 {
+        return None;
+    }
+
+    // Time low, time mid, time high, clock seq, node
+    let mut values = [0u64; 5];
+    let mut field = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if i == 8 || i == 13 || i == 18 || i == 23 {
+            if bytes[i] != b'-' {
+                return None;
+            }
+            field += 1;
+        } else {
+            match hex_value(bytes[i]) {
+                Some(digit) => values[field] = values[field] * 16 + digit as u64,
+                None => return None,
+            }
+        }
+        i += 1;
+    }
+
+    Some(Guid::from_values(
+        values[0] as u32,
+        values[1] as u16,
+        values[2] as u16,
+        values[3] as u16,
+        values[4],
+    ))
+}
+
 /// Several entities in the UEFI specification can be referred to by their GUID,
 /// this trait is a building block to interface them in uefi-rs.
 ///
@@ -154,6 +226,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_guid_macro_forms() {
+        let guid = Guid::from_values(0x12345678, 0x9abc, 0xdef0, 0x1234, 0x56789abcdef0);
+        assert_eq!(guid!("12345678-9ABC-DEF0-1234-56789ABCDEF0"), guid);
+        assert_eq!(guid!("{12345678-9abc-def0-1234-56789abcdef0}"), guid);
+    }
+
+    #[test]
+    fn test_guid_from_str() {
+        let guid = Guid::from_values(0x12345678, 0x9abc, 0xdef0, 0x1234, 0x56789abcdef0);
+        const PARSED: Option<Guid> = guid_from_str("12345678-9abc-def0-1234-56789abcdef0");
+        assert_eq!(PARSED, Some(guid));
+        assert_eq!(
+            guid_from_str("{12345678-9ABC-DEF0-1234-56789ABCDEF0}"),
+            Some(guid)
+        );
+        assert_eq!(
+            guid_from_str("12345678-9abc-def0-1234-56789abcdef0\n"),
+            Some(guid)
+        );
+
+        assert_eq!(guid_from_str("12-34"), None);
+        assert_eq!(guid_from_str("{12345678-9abc-def0-1234-56789abcdef0"), None);
+        assert_eq!(guid_from_str("12345678-9abc-def0-1234+56789abcdef0"), None);
+        assert_eq!(guid_from_str("1234567g-9abc-def0-1234-56789abcdef0"), None);
+    }
+
     #[test]
     fn test_to_from_bytes() {
         #[rustfmt::skip]
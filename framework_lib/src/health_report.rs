@@ -0,0 +1,292 @@
+//! Combine the output of several commands into one document
+//!
+//! Used to create a single file with the state of the system that can be
+//! attached to a support ticket. Every section collects what a command wrote
+//! to its `OutputSink`, which is then rendered as Markdown or HTML.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::output::{OutputMessage, OutputSink};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+impl ReportFormat {
+    /// Pick the format based on the file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = path.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Some(ReportFormat::Markdown),
+            "html" | "htm" => Some(ReportFormat::Html),
+            _ => None,
+        }
+    }
+}
+
+/// Output of a single command
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportSection {
+    pub title: String,
+    pub ok: bool,
+    pub content: Vec<OutputMessage>,
+}
+
+/// Collects the output of one section
+#[derive(Default)]
+struct SectionSink {
+    content: Vec<OutputMessage>,
+}
+
+impl OutputSink for SectionSink {
+    fn write_line(&mut self, line: &str) {
+        self.content.push(OutputMessage::Line(line.to_string()));
+    }
+
+    fn write_table(&mut self, rows: &[(&str, String)]) {
+        let rows = rows
+            .iter()
+            .map(|(label, value)| (label.to_string(), value.clone()))
+            .collect();
+        self.content.push(OutputMessage::Table(rows));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HealthReport {
+    pub title: String,
+    pub sections: Vec<ReportSection>,
+}
+
+impl HealthReport {
+    pub fn new(title: &str) -> Self {
+        HealthReport {
+            title: title.to_string(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Run a command and add what it writes as a new section
+    ///
+    /// The command returns whether it succeeded.
+    pub fn add_section<F>(&mut self, title: &str, command: F)
+    where
+        F: FnOnce(&mut dyn OutputSink) -> bool,
+    {
+        let mut sink = SectionSink::default();
+        let ok = command(&mut sink);
+        self.sections.push(ReportSection {
+            title: title.to_string(),
+            ok,
+            content: sink.content,
+        });
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Markdown => self.render_markdown(),
+            ReportFormat::Html => self.render_html(),
+        }
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut doc = format!("# {}\n\n", self.title);
+        doc.push_str("| Section | Result |\n|---|---|\n");
+        for section in &self.sections {
+            doc.push_str(&format!(
+                "| {} | {} |\n",
+                escape_markdown(&section.title),
+                result_text(section.ok)
+            ));
+        }
+
+        for section in &self.sections {
+            doc.push_str(&format!("\n## {}\n", section.title));
+            for block in blocks(&section.content) {
+                match block {
+                    Block::Lines(lines) => {
+                        doc.push_str("\n```text\n");
+                        for line in lines {
+                            doc.push_str(line);
+                            doc.push('\n');
+                        }
+                        doc.push_str("```\n");
+                    }
+                    Block::Table(rows) => {
+                        doc.push_str("\n| | |\n|---|---|\n");
+                        for (label, value) in rows {
+                            doc.push_str(&format!(
+                                "| {} | {} |\n",
+                                escape_markdown(label),
+                                escape_markdown(value)
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        doc
+    }
+
+    fn render_html(&self) -> String {
+        let title = escape_html(&self.title);
+        let mut doc = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n{}</head>\n<body>\n<h1>{}</h1>\n",
+            title, HTML_STYLE, title
+        );
+        doc.push_str("<table>\n<tr><th>Section</th><th>Result</th></tr>\n");
+        for section in &self.sections {
+            doc.push_str(&format!(
+                "<tr><td>{}</td><td class=\"{}\">{}</td></tr>\n",
+                escape_html(&section.title),
+                if section.ok { "ok" } else { "failed" },
+                result_text(section.ok)
+            ));
+        }
+        doc.push_str("</table>\n");
+
+        for section in &self.sections {
+            doc.push_str(&format!("<h2>{}</h2>\n", escape_html(&section.title)));
+            for block in blocks(&section.content) {
+                match block {
+                    Block::Lines(lines) => {
+                        doc.push_str("<pre>");
+                        for line in lines {
+                            doc.push_str(&escape_html(line));
+                            doc.push('\n');
+                        }
+                        doc.push_str("</pre>\n");
+                    }
+                    Block::Table(rows) => {
+                        doc.push_str("<table>\n");
+                        for (label, value) in rows {
+                            doc.push_str(&format!(
+                                "<tr><td>{}</td><td>{}</td></tr>\n",
+                                escape_html(label),
+                                escape_html(value)
+                            ));
+                        }
+                        doc.push_str("</table>\n");
+                    }
+                }
+            }
+        }
+        doc.push_str("</body>\n</html>\n");
+        doc
+    }
+}
+
+const HTML_STYLE: &str = "<style>
+body { font-family: sans-serif; }
+td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+table { border-collapse: collapse; }
+.ok { color: green; }
+.failed { color: red; }
+</style>
+";
+
+/// Consecutive lines are rendered together, tables separately
+enum Block<'a> {
+    Lines(Vec<&'a str>),
+    Table(&'a [(String, String)]),
+}
+
+fn blocks(content: &[OutputMessage]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    for message in content {
+        match message {
+            OutputMessage::Line(line) => {
+                if let Some(Block::Lines(lines)) = blocks.last_mut() {
+                    lines.push(line.as_str());
+                } else {
+                    blocks.push(Block::Lines(alloc::vec![line.as_str()]));
+                }
+            }
+            OutputMessage::Table(rows) => blocks.push(Block::Table(rows)),
+            OutputMessage::Progress(_, _) => {}
+        }
+    }
+    blocks
+}
+
+fn result_text(ok: bool) -> &'static str {
+    if ok {
+        "OK"
+    } else {
+        "Failed"
+    }
+}
+
+fn escape_markdown(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::outln;
+
+    fn example() -> HealthReport {
+        let mut report = HealthReport::new("Report");
+        report.add_section("Power", |out| {
+            outln!(out, "Battery <low>");
+            outln!(out, "  Charging");
+            out.write_table(&[("AC | USB-C", "Connected".to_string())]);
+            true
+        });
+        report.add_section("Intrusion", |_out| false);
+        report
+    }
+
+    #[test]
+    fn render_markdown() {
+        assert_eq!(
+            example().render(ReportFormat::Markdown),
+            "# Report\n\n\
+             | Section | Result |\n|---|---|\n\
+             | Power | OK |\n\
+             | Intrusion | Failed |\n\
+             \n## Power\n\
+             \n```text\nBattery <low>\n  Charging\n```\n\
+             \n| | |\n|---|---|\n\
+             | AC \\| USB-C | Connected |\n\
+             \n## Intrusion\n"
+        );
+    }
+
+    #[test]
+    fn render_html() {
+        let html = example().render(ReportFormat::Html);
+        assert!(html.contains("<td class=\"failed\">Failed</td>"));
+        assert!(html.contains("<pre>Battery &lt;low&gt;\n  Charging\n</pre>"));
+        assert!(html.contains("<tr><td>AC | USB-C</td><td>Connected</td></tr>"));
+        assert!(html.ends_with("</html>\n"));
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(
+            ReportFormat::from_path("report.MD"),
+            Some(ReportFormat::Markdown)
+        );
+        assert_eq!(
+            ReportFormat::from_path("/tmp/a.b/report.html"),
+            Some(ReportFormat::Html)
+        );
+        assert_eq!(ReportFormat::from_path("report.txt"), None);
+        assert_eq!(ReportFormat::from_path("report"), None);
+    }
+}
@@ -0,0 +1,413 @@
+//! Privileged helper to let unprivileged users query the hardware
+//!
+//! Talking to the EC needs root. Instead of running the whole tool as root,
+//! the helper runs as root (e.g. as a systemd service) and listens on a local
+//! socket. When `framework_tool` is run by a regular user, supported commands
+//! are forwarded to the helper.
+//!
+//! Read-only queries are allowed for everyone. Commands that change settings
+//! need authorization through polkit, using the action `POLKIT_WRITE_ACTION`.
+//!
+//! The protocol is line based. The client sends a single line with the command,
+//! the helper responds with the output of the command, followed by a line
+//! `EXIT <code>` with the exit code. Every client is handled on its own thread,
+//! so that one waiting for polkit authorization doesn't hold up the others.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::audit::{self, AuditRecord};
+use crate::chromium_ec::CrosEc;
+use crate::commandline::{self, Cli, ExitCode};
+use crate::esrt;
+use crate::output::{outln, OutputSink, StringSink};
+use crate::power;
+
+/// Where the helper listens for requests
+pub const SOCKET_PATH: &str = "/run/framework_tool.sock";
+
+/// Polkit action that users need to be authorized for to change settings
+pub const POLKIT_WRITE_ACTION: &str = "org.frameworkcomputer.framework-tool.write";
+
+/// How long a client may take to send its request
+///
+/// A client that never sends anything must not keep its thread around forever.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Commands that the helper can run on behalf of the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum HelperRequest {
+    Versions,
+    Power,
+    Thermal,
+    Sensors,
+    PdPorts,
+    Esrt,
+    Features,
+    /// Set the maximum charge limit in percent
+    ChargeLimit(u8),
+    /// Set the keyboard backlight brightness in percent
+    Kblight(u8),
+}
+
+impl HelperRequest {
+    /// Find out which command the user wants to run, if the helper can run it
+    ///
+    /// The helper runs a single command, at its own log level. If it can't run
+    /// everything that was asked for, returns why, instead of dropping part of
+    /// the output.
+    pub fn from_cli(args: &Cli) -> Result<Option<Self>, &'static str> {
+        let mut requests = vec![];
+        if args.versions {
+            requests.push(Self::Versions);
+        }
+        if args.power {
+            requests.push(Self::Power);
+        }
+        if args.thermal {
+            requests.push(Self::Thermal);
+        }
+        if args.sensors {
+            requests.push(Self::Sensors);
+        }
+        if args.pdports {
+            requests.push(Self::PdPorts);
+        }
+        if args.esrt {
+            requests.push(Self::Esrt);
+        }
+        if args.features {
+            requests.push(Self::Features);
+        }
+        if let Some(Some(limit)) = args.charge_limit {
+            requests.push(Self::ChargeLimit(limit));
+        }
+        if let Some(Some(percent)) = args.kblight {
+            requests.push(Self::Kblight(percent));
+        }
+
+        match requests.len() {
+            0 => Ok(None),
+            1 if args.verbosity > log::LevelFilter::Error => {
+                Err("it doesn't support more verbose output (-v)")
+            }
+            1 => Ok(requests.pop()),
+            _ => Err("it only runs one command at a time"),
+        }
+    }
+
+    /// Whether the command changes any settings and needs authorization
+    pub fn is_write(&self) -> bool {
+        matches!(self, Self::ChargeLimit(_) | Self::Kblight(_))
+    }
+
+    /// Name of the operation in the audit log, same as when run directly
+    fn audited_operation(&self) -> Option<&'static str> {
+        match self {
+            Self::ChargeLimit(_) => Some("charge_limit"),
+            Self::Kblight(_) => Some("kblight"),
+            _ => None,
+        }
+    }
+
+    pub fn to_line(&self) -> String {
+        match self {
+            Self::Versions => "versions".to_string(),
+            Self::Power => "power".to_string(),
+            Self::Thermal => "thermal".to_string(),
+            Self::Sensors => "sensors".to_string(),
+            Self::PdPorts => "pdports".to_string(),
+            Self::Esrt => "esrt".to_string(),
+            Self::Features => "features".to_string(),
+            Self::ChargeLimit(limit) => format!("charge-limit {}", limit),
+            Self::Kblight(percent) => format!("kblight {}", percent),
+        }
+    }
+
+    pub fn from_line(line: &str) -> Option<Self> {
+        let mut parts = line.split_whitespace();
+        let request = match (parts.next()?, parts.next()) {
+            ("versions", None) => Self::Versions,
+            ("power", None) => Self::Power,
+            ("thermal", None) => Self::Thermal,
+            ("sensors", None) => Self::Sensors,
+            ("pdports", None) => Self::PdPorts,
+            ("esrt", None) => Self::Esrt,
+            ("features", None) => Self::Features,
+            ("charge-limit", Some(limit)) => Self::ChargeLimit(limit.parse().ok()?),
+            ("kblight", Some(percent)) => Self::Kblight(percent.parse().ok()?),
+            _ => return None,
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(request)
+    }
+}
+
+/// Whether the helper seems to be running
+pub fn is_running() -> bool {
+    Path::new(SOCKET_PATH).exists()
+}
+
+/// Send the request to the helper and print its output
+///
+/// Returns the exit code, or None if the helper isn't running.
+pub fn forward(request: &HelperRequest) -> Option<i32> {
+    if !is_running() {
+        return None;
+    }
+    let mut stream = match UnixStream::connect(SOCKET_PATH) {
+        Ok(stream) => stream,
+        Err(err) => {
+            debug!("Failed to connect to helper at {}: {:?}", SOCKET_PATH, err);
+            return None;
+        }
+    };
+    debug!("Forwarding {:?} to helper", request);
+    writeln!(stream, "{}", request.to_line()).ok()?;
+
+    let mut exit_code = 1;
+    for line in BufReader::new(stream).lines() {
+        let line = line.ok()?;
+        if let Some(code) = line.strip_prefix("EXIT ") {
+            exit_code = code.parse().unwrap_or(1);
+            break;
+        }
+        println!("{}", line);
+    }
+    Some(exit_code)
+}
+
+/// Run the helper, handling requests until the process is killed
+pub fn serve(socket_path: &str) -> i32 {
+    // Remove stale socket from a previous run
+    let _ = std::fs::remove_file(socket_path);
+    let listener = match UnixListener::bind(socket_path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to listen on {}: {:?}", socket_path, err);
+            return 1;
+        }
+    };
+    // Everyone may connect, authorization is checked per request
+    let permissions = std::fs::Permissions::from_mode(0o666);
+    if let Err(err) = std::fs::set_permissions(socket_path, permissions) {
+        error!("Failed to set permissions on {}: {:?}", socket_path, err);
+        return 1;
+    }
+    info!("Listening on {}", socket_path);
+
+    let ec = CrosEc::new();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let ec = ec.clone();
+                std::thread::spawn(move || handle_client(&ec, stream));
+            }
+            Err(err) => error!("Failed to accept connection: {:?}", err),
+        }
+    }
+    0
+}
+
+fn handle_client(ec: &CrosEc, mut stream: UnixStream) {
+    if let Err(err) = stream.set_read_timeout(Some(READ_TIMEOUT)) {
+        error!("Failed to set read timeout: {:?}", err);
+        return;
+    }
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(&stream).read_line(&mut line) {
+        debug!("Failed to read request: {:?}", err);
+        return;
+    }
+
+    let mut out = StringSink::default();
+    let exit_code = match HelperRequest::from_line(line.trim()) {
+        Some(request) => {
+            if request.is_write() && !authorized(&stream) {
+                outln!(out, "Not authorized to change settings");
+                ExitCode::Permission.into()
+            } else {
+                debug!("Handling {:?}", request);
+                let exit_code = handle_request(ec, &request, &mut out);
+                if let Some(operation) = request.audited_operation() {
+                    write_audit_log(&stream, operation, &request, exit_code);
+                }
+                exit_code
+            }
+        }
+        None => {
+            outln!(out, "Command not supported by helper: {:?}", line.trim());
+            1
+        }
+    };
+
+    let _ = stream.write_all(out.buffer.as_bytes());
+    let _ = writeln!(stream, "EXIT {}", exit_code);
+}
+
+fn handle_request(ec: &CrosEc, request: &HelperRequest, out: &mut dyn OutputSink) -> i32 {
+    match request {
+        HelperRequest::Versions => commandline::print_versions(ec, out),
+        HelperRequest::Power => return power::get_and_print_power_info(ec, out),
+        HelperRequest::Thermal => {
+            if let Err(err) = power::print_thermal(ec, out) {
+                outln!(out, "Failed to read temperatures: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        }
+        HelperRequest::Sensors => {
+            if let Err(err) = power::print_sensors(ec, out) {
+                outln!(out, "Failed to read sensors: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        }
+        HelperRequest::PdPorts => power::get_and_print_pd_info(ec, out),
+        HelperRequest::Esrt => {
+            if let Some(esrt) = esrt::get_esrt() {
+                esrt::print_esrt(&esrt, out);
+            } else {
+                outln!(out, "Could not find and parse ESRT table.");
+                return 1;
+            }
+        }
+        HelperRequest::Features => {
+            if let Err(err) = ec.get_features(out) {
+                outln!(out, "Failed to get features: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        }
+        HelperRequest::ChargeLimit(limit) => {
+            if *limit < 25 || *limit > 100 {
+                outln!(out, "Charge limit must be between 25% and 100%");
+                return 1;
+            }
+            let res = ec
+                .get_charge_limit()
+                .and_then(|(min, _max)| ec.set_charge_limit(min, *limit));
+            if let Err(err) = res {
+                outln!(out, "Failed to set charge limit: {:?}", err);
+                return ExitCode::from(&err).into();
+            }
+        }
+        HelperRequest::Kblight(percent) => {
+            if *percent > 100 {
+                outln!(out, "Keyboard backlight must be between 0% and 100%");
+                return 1;
+            }
+            ec.set_keyboard_backlight(*percent);
+        }
+    }
+    0
+}
+
+/// Record a write request in the audit log, with the user that sent it
+fn write_audit_log(stream: &UnixStream, operation: &str, request: &HelperRequest, exit_code: i32) {
+    let mut record = AuditRecord::new(operation, vec![request.to_line()], exit_code);
+    record.user = peer_credentials(stream).map(|cred| format!("uid {}", cred.uid));
+    audit::write(&record);
+}
+
+/// Process ID and user of the process on the other end
+fn peer_credentials(stream: &UnixStream) -> Option<libc::ucred> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        error!("Failed to get credentials of client");
+        return None;
+    }
+    Some(cred)
+}
+
+/// Start time of the process in clock ticks since boot, from `/proc/<pid>/stat`
+fn parse_start_time(stat: &str) -> Option<u64> {
+    // The command name in the second field can contain spaces and parentheses.
+    // The start time is the 22nd field, the 20th after the command name.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Check with polkit whether the process on the other end may change settings
+fn authorized(stream: &UnixStream) -> bool {
+    let Some(cred) = peer_credentials(stream) else {
+        return false;
+    };
+    if cred.uid == 0 {
+        return true;
+    }
+
+    // With only the PID, the client could exit and another process could get
+    // its PID before polkit looks it up. polkit rejects the check, if the start
+    // time or user of the process with that PID don't match.
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", cred.pid));
+    let Some(start_time) = stat.ok().as_deref().and_then(parse_start_time) else {
+        error!("Failed to read start time of process {}", cred.pid);
+        return false;
+    };
+    let process = format!("{},{},{}", cred.pid, start_time, cred.uid);
+
+    // pkcheck shows an authentication dialog in the user's session, if needed
+    let status = Command::new("pkcheck")
+        .args([
+            "--action-id",
+            POLKIT_WRITE_ACTION,
+            "--process",
+            &process,
+            "--allow-user-interaction",
+        ])
+        .status();
+    match status {
+        Ok(status) => status.success(),
+        Err(err) => {
+            error!("Failed to run pkcheck: {:?}", err);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_time_of_process() {
+        let stat = "1234 (my (weird) cmd) S 1 1234 1234 0 -1 4194560 1000 0 0 0 10 5 0 0 20 0 1 0 98765 12345678 200 18446744073709551615";
+        assert_eq!(parse_start_time(stat), Some(98765));
+        assert_eq!(parse_start_time("1234 (cmd) S 1"), None);
+        assert_eq!(parse_start_time(""), None);
+    }
+
+    #[test]
+    fn request_lines() {
+        for request in [
+            HelperRequest::Versions,
+            HelperRequest::Power,
+            HelperRequest::Thermal,
+            HelperRequest::ChargeLimit(80),
+            HelperRequest::Kblight(30),
+        ] {
+            assert_eq!(HelperRequest::from_line(&request.to_line()), Some(request));
+        }
+        assert_eq!(HelperRequest::from_line("charge-limit"), None);
+        assert_eq!(HelperRequest::from_line("power extra"), None);
+    }
+}
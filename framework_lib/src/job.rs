@@ -0,0 +1,120 @@
+//! Run long operations on a background thread, so they can be cancelled
+//!
+//! Flashing the EC or following its console can take a long time.
+//! A GUI or daemon can start them as a `Job`, receive output and progress
+//! through a channel and cancel them at any time.
+//!
+//! ```no_run
+//! use framework_lib::chromium_ec::{CrosEc, EcFlashType};
+//! use framework_lib::job::FlashJob;
+//! use framework_lib::output::OutputMessage;
+//!
+//! let data = std::fs::read("ec.bin").unwrap();
+//! let job = FlashJob::flash_ec(CrosEc::new(), data, EcFlashType::Rw);
+//! for msg in job.messages() {
+//!     if let OutputMessage::Progress(done, total) = msg {
+//!         println!("{}/{}", done, total);
+//!     }
+//! }
+//! job.wait().unwrap();
+//! ```
+
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+use crate::chromium_ec::{CancelToken, CrosEc, EcError, EcFlashType, EcResult};
+use crate::output::{ChannelSink, OutputMessage};
+
+/// Operation running on a background thread
+pub struct Job<T> {
+    cancel: CancelToken,
+    messages: Receiver<OutputMessage>,
+    handle: JoinHandle<EcResult<T>>,
+}
+
+/// Flashing the EC, see `CrosEc::reflash_with`
+pub type FlashJob = Job<()>;
+/// Following the EC console, see `CrosEc::console_read_with`
+pub type ConsoleJob = Job<String>;
+
+impl<T: Send + 'static> Job<T> {
+    /// Run the operation on a new thread
+    ///
+    /// It gets a sink to write its output to and should regularly check the token.
+    pub fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce(&mut ChannelSink, &CancelToken) -> EcResult<T> + Send + 'static,
+    {
+        let cancel = CancelToken::new();
+        let (sender, messages) = mpsc::channel();
+        let job_cancel = cancel.clone();
+        let handle = thread::spawn(move || f(&mut ChannelSink::new(sender), &job_cancel));
+        Job {
+            cancel,
+            messages,
+            handle,
+        }
+    }
+
+    /// Ask the operation to stop
+    ///
+    /// It stops at the next point where that's safe, `wait` returns when it did.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Output and progress of the operation
+    ///
+    /// Iterating over it blocks until the operation has finished.
+    pub fn messages(&self) -> &Receiver<OutputMessage> {
+        &self.messages
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Block until the operation has finished and get its result
+    pub fn wait(self) -> EcResult<T> {
+        self.handle
+            .join()
+            .unwrap_or_else(|_| Err(EcError::DeviceError("Job panicked".to_string())))
+    }
+}
+
+impl FlashJob {
+    /// Flash the EC firmware in the background
+    pub fn flash_ec(ec: CrosEc, data: Vec<u8>, ft: EcFlashType) -> Self {
+        Job::spawn(move |out, cancel| ec.reflash_with(&data, ft, out, cancel))
+    }
+}
+
+impl ConsoleJob {
+    /// Follow the EC console in the background, until cancelled
+    pub fn follow_console(ec: CrosEc) -> Self {
+        Job::spawn(move |out, cancel| ec.console_read_with(out, cancel))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputSink;
+
+    #[test]
+    fn cancel_job() {
+        let job = Job::spawn(|out, cancel| {
+            out.write_line("started");
+            while !cancel.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            Err::<(), _>(EcError::Cancelled)
+        });
+        assert_eq!(
+            job.messages().recv(),
+            Ok(OutputMessage::Line("started".to_string()))
+        );
+        job.cancel();
+        assert_eq!(job.wait(), Err(EcError::Cancelled));
+    }
+}
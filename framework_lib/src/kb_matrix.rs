@@ -0,0 +1,113 @@
+//! Save the keyboard matrix scancodes to a file and apply them again later
+//!
+//! Keys can be remapped by changing the scancode that the EC sends for a
+//! position in the keyboard matrix. The EC forgets the changes when it's
+//! reflashed, so they can be dumped to a file, shared with other machines of
+//! the same model and restored.
+//!
+//! The file has one entry per line, `<ROW> <COL> <SCANCODE>` with the PS/2 set
+//! 2 scancode in hex. Empty lines and lines starting with `#` are ignored.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::chromium_ec::commands::KeyboardMatrixMap;
+use crate::chromium_ec::{CrosEc, EcResult};
+
+/// Size of the keyboard matrix that the EC scans
+pub const ROWS: u8 = 8;
+pub const COLS: u8 = 16;
+
+/// Read the scancodes of all positions of the matrix
+pub fn dump(ec: &CrosEc) -> EcResult<Vec<KeyboardMatrixMap>> {
+    let mut positions = Vec::with_capacity(ROWS as usize * COLS as usize);
+    for row in 0..ROWS {
+        for col in 0..COLS {
+            positions.push(KeyboardMatrixMap {
+                row,
+                col,
+                scanset: 0,
+            });
+        }
+    }
+    ec.update_keyboard_matrix(&positions, false)
+}
+
+/// Write the scancodes of the entries, other positions stay unchanged
+pub fn restore(ec: &CrosEc, entries: &[KeyboardMatrixMap]) -> EcResult<()> {
+    ec.update_keyboard_matrix(entries, true)?;
+    Ok(())
+}
+
+pub fn to_text(entries: &[KeyboardMatrixMap]) -> String {
+    let mut text = String::from("# row col scancode\n");
+    for entry in entries {
+        let scanset = entry.scanset;
+        text.push_str(&format!("{} {} 0x{:04X}\n", entry.row, entry.col, scanset));
+    }
+    text
+}
+
+pub fn from_text(text: &str) -> Result<Vec<KeyboardMatrixMap>, String> {
+    let mut entries = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || format!("Line {}: expected <ROW> <COL> <SCANCODE>", i + 1);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [row, col, scanset] = fields.as_slice() else {
+            return Err(invalid());
+        };
+        let row: u8 = row.parse().map_err(|_| invalid())?;
+        let col: u8 = col.parse().map_err(|_| invalid())?;
+        let scanset = scanset.trim_start_matches("0x").trim_start_matches("0X");
+        let scanset = u16::from_str_radix(scanset, 16).map_err(|_| invalid())?;
+        if row >= ROWS || col >= COLS {
+            return Err(format!(
+                "Line {}: position {},{} is outside the {}x{} matrix",
+                i + 1,
+                row,
+                col,
+                ROWS,
+                COLS
+            ));
+        }
+        entries.push(KeyboardMatrixMap { row, col, scanset });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_roundtrip() {
+        let entries = vec![
+            KeyboardMatrixMap {
+                row: 0,
+                col: 1,
+                scanset: 0x0076,
+            },
+            KeyboardMatrixMap {
+                row: 7,
+                col: 15,
+                scanset: 0xE011,
+            },
+        ];
+        let text = to_text(&entries);
+        assert_eq!(text, "# row col scancode\n0 1 0x0076\n7 15 0xE011\n");
+        assert_eq!(from_text(&text), Ok(entries));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(from_text("\n# comment\n"), Ok(vec![]));
+        assert!(from_text("0 1").is_err());
+        assert!(from_text("0 1 zz").is_err());
+        assert!(from_text("8 0 0x0076").is_err());
+    }
+}
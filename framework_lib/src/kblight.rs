@@ -0,0 +1,131 @@
+//! Automatically adjust the keyboard backlight
+//!
+//! The EC state is polled periodically. The backlight is turned off on battery
+//! and in bright surroundings, otherwise it's set to the configured brightness.
+//! Changes fade in and out, instead of switching abruptly.
+
+use crate::chromium_ec::{CancelToken, CrosEc};
+use crate::os_specific;
+
+/// How often to poll the EC for AC and ALS changes
+pub const POLL_INTERVAL_MS: u64 = 1000;
+/// How long each step of a fade takes
+const FADE_STEP_MS: u64 = 20;
+/// How much the brightness changes in each step of a fade, in percent
+const FADE_STEP_PERCENT: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KblightAutoConfig {
+    /// Brightness in percent, when on AC
+    pub on_ac: u8,
+    /// Brightness in percent, when on battery
+    pub on_battery: u8,
+    /// Backlight is turned off, when the ambient light is at least this bright
+    pub off_above_lux: u32,
+}
+
+impl Default for KblightAutoConfig {
+    fn default() -> Self {
+        KblightAutoConfig {
+            on_ac: 50,
+            on_battery: 0,
+            off_above_lux: 200,
+        }
+    }
+}
+
+impl KblightAutoConfig {
+    /// Brightness in percent that the backlight should have
+    ///
+    /// Systems without ALS only consider the AC state.
+    pub fn target(&self, ac_present: bool, lux: Option<u32>) -> u8 {
+        if lux.map(|lux| lux >= self.off_above_lux).unwrap_or(false) {
+            0
+        } else if ac_present {
+            self.on_ac
+        } else {
+            self.on_battery
+        }
+    }
+}
+
+/// Gradually change the backlight brightness from `from` to `to`
+fn fade(ec: &CrosEc, from: u8, to: u8) {
+    let mut current = from;
+    while current != to {
+        current = if current < to {
+            to.min(current.saturating_add(FADE_STEP_PERCENT))
+        } else {
+            to.max(current.saturating_sub(FADE_STEP_PERCENT))
+        };
+        ec.set_keyboard_backlight(current);
+        os_specific::sleep(FADE_STEP_MS * 1000);
+    }
+}
+
+/// Keeps the backlight in line with AC and ambient light, one poll at a time
+pub struct KblightAuto {
+    config: KblightAutoConfig,
+    /// Brightness in percent that the backlight was last set to
+    current: u8,
+}
+
+impl KblightAuto {
+    pub fn new(ec: &CrosEc, config: KblightAutoConfig) -> Self {
+        let current = ec.get_keyboard_backlight().unwrap_or(0);
+        debug!("Initial brightness: {}%", current);
+        KblightAuto { config, current }
+    }
+
+    /// Check AC and ambient light once and fade to the new brightness, if needed
+    pub fn update(&mut self, ec: &CrosEc) {
+        let memmap = ec.memmap().ok();
+        // Assume AC if we can't tell, to not turn off the backlight for no reason
+        let ac_present = memmap
+            .as_ref()
+            .map(|m| m.battery.ac_present())
+            .unwrap_or(true);
+        let als = memmap.map(|m| m.als[0] as u32);
+        let target = self.config.target(ac_present, als);
+        if target != self.current {
+            debug!(
+                "AC: {}, changing brightness from {}% to {}%",
+                ac_present, self.current, target
+            );
+            fade(ec, self.current, target);
+            self.current = target;
+        }
+    }
+}
+
+/// Poll the EC and adjust the backlight whenever AC or ambient light change
+///
+/// Runs until `cancel` is triggered.
+pub fn run(ec: &CrosEc, config: &KblightAutoConfig, cancel: &CancelToken) {
+    let mut auto = KblightAuto::new(ec, *config);
+    while !cancel.is_cancelled() {
+        auto.update(ec);
+        os_specific::sleep(POLL_INTERVAL_MS * 1000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_brightness() {
+        let config = KblightAutoConfig::default();
+        assert_eq!(config.target(true, None), 50);
+        assert_eq!(config.target(false, None), 0);
+        assert_eq!(config.target(true, Some(10)), 50);
+        assert_eq!(config.target(true, Some(500)), 0);
+
+        let config = KblightAutoConfig {
+            on_battery: 20,
+            ..config
+        };
+        assert_eq!(config.target(false, Some(10)), 20);
+        assert_eq!(config.target(false, Some(200)), 0);
+    }
+}
@@ -0,0 +1,387 @@
+//! Control the power button and charging LEDs
+//!
+//! The EC only lets the host set a fixed color and brightness, or hand control
+//! back to the EC. An LED can be kept in a fixed state, e.g. off at night, or
+//! play a pattern, by changing it step by step from the host. Afterwards the
+//! EC takes over again and shows the system state.
+//!
+//! Patterns are written as comma separated steps, each a color with optional
+//! brightness in percent and how long to show it, in milliseconds. The whole
+//! pattern can be repeated. For example blinking white three times:
+//!
+//! ```text
+//! white:500,off:500*3
+//! ```
+//!
+//! Or pulsing amber: `amber@20:200,amber@60:200,amber:200,amber@60:200`
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::chromium_ec::commands::{EcLedColor, EcLedId, EC_LED_COLOR_COUNT};
+use crate::chromium_ec::{CrosEc, EcError, EcResponseStatus, EcResult};
+use crate::os_specific;
+use crate::output::{outln, OutputSink};
+
+/// Longest a single step can last, to not get stuck on a typo
+const MAX_STEP_MS: u32 = 10_000;
+const MAX_REPEAT: u32 = 100;
+
+const ALL_LEDS: &[EcLedId] = &[
+    EcLedId::BatteryLed,
+    EcLedId::PowerLed,
+    EcLedId::AdapterLed,
+    EcLedId::LeftLed,
+    EcLedId::RightLed,
+];
+
+const ALL_COLORS: &[EcLedColor] = &[
+    EcLedColor::Red,
+    EcLedColor::Green,
+    EcLedColor::Blue,
+    EcLedColor::Yellow,
+    EcLedColor::White,
+    EcLedColor::Amber,
+];
+
+fn led_name(led: EcLedId) -> &'static str {
+    match led {
+        EcLedId::BatteryLed => "battery",
+        EcLedId::PowerLed => "power",
+        EcLedId::AdapterLed => "adapter",
+        EcLedId::LeftLed => "left",
+        EcLedId::RightLed => "right",
+    }
+}
+
+fn color_name(color: EcLedColor) -> &'static str {
+    match color {
+        EcLedColor::Red => "red",
+        EcLedColor::Green => "green",
+        EcLedColor::Blue => "blue",
+        EcLedColor::Yellow => "yellow",
+        EcLedColor::White => "white",
+        EcLedColor::Amber => "amber",
+    }
+}
+
+fn parse_led(name: &str) -> Option<EcLedId> {
+    ALL_LEDS.iter().find(|led| led_name(**led) == name).copied()
+}
+
+fn parse_color(name: &str) -> Option<EcLedColor> {
+    ALL_COLORS
+        .iter()
+        .find(|color| color_name(**color) == name)
+        .copied()
+}
+
+/// What to do with an LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedSetting {
+    /// Let the EC control it
+    Auto,
+    /// Keep it at a fixed color and brightness, `off` is a step without color
+    Fixed(LedStep),
+}
+
+impl LedSetting {
+    /// Parse `auto`, `off` or a color with optional brightness, like `amber@50`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if spec == "auto" {
+            return Ok(LedSetting::Auto);
+        }
+        // Same as a step, it just doesn't end
+        LedStep::parse(&format!("{}:0", spec)).map(LedSetting::Fixed)
+    }
+}
+
+/// Parse the arguments of `--led [<LED>] [<SETTING>]`
+///
+/// Returns None if no LED is given, to list all of them.
+pub fn parse_led_args<S: AsRef<str>>(
+    args: &[S],
+) -> Result<Option<(EcLedId, Option<LedSetting>)>, String> {
+    let Some(name) = args.first() else {
+        return Ok(None);
+    };
+    let name = name.as_ref();
+    let led = parse_led(name).ok_or_else(|| {
+        format!(
+            "Unknown LED '{}', must be battery, power, adapter, left or right",
+            name
+        )
+    })?;
+    let setting = match args.get(1) {
+        Some(setting) => Some(LedSetting::parse(setting.as_ref())?),
+        None => None,
+    };
+    Ok(Some((led, setting)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LedStep {
+    /// None to turn the LED off
+    pub color: Option<EcLedColor>,
+    /// Brightness in percent of what the color supports
+    pub brightness: u8,
+    pub duration_ms: u32,
+}
+
+impl LedStep {
+    /// Parse a single step like `white:500`, `amber@50:200` or `off:1000`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (color, duration) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("Missing duration in step '{}'", spec))?;
+        let duration_ms = duration
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid duration in step '{}'", spec))?;
+        if duration_ms > MAX_STEP_MS {
+            return Err(format!(
+                "Step '{}' is too long, at most {} ms are allowed",
+                spec, MAX_STEP_MS
+            ));
+        }
+
+        let (color, brightness) = match color.split_once('@') {
+            Some((color, brightness)) => {
+                let brightness = brightness
+                    .trim_end_matches('%')
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|x| *x <= 100)
+                    .ok_or_else(|| format!("Invalid brightness in step '{}'", spec))?;
+                (color, brightness)
+            }
+            None => (color, 100),
+        };
+        let color = if color == "off" {
+            None
+        } else {
+            Some(parse_color(color).ok_or_else(|| {
+                format!(
+                    "Unknown color '{}', must be off, red, green, blue, yellow, white or amber",
+                    color
+                )
+            })?)
+        };
+
+        Ok(LedStep {
+            color,
+            brightness,
+            duration_ms,
+        })
+    }
+
+    /// Brightness of each color, scaled to what the LED supports
+    fn brightness(&self, range: &[u8; EC_LED_COLOR_COUNT]) -> [u8; EC_LED_COLOR_COUNT] {
+        let mut brightness = [0; EC_LED_COLOR_COUNT];
+        if let Some(color) = self.color {
+            let max = range[color as usize] as u32;
+            brightness[color as usize] = (max * self.brightness as u32).div_ceil(100) as u8;
+        }
+        brightness
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedPattern {
+    pub steps: Vec<LedStep>,
+    /// How often to play all steps
+    pub repeat: u32,
+}
+
+impl LedPattern {
+    /// Parse a pattern like `white:500,off:500*3`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (steps, repeat) = match spec.rsplit_once('*') {
+            Some((steps, repeat)) => {
+                let repeat = repeat
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|x| (1..=MAX_REPEAT).contains(x))
+                    .ok_or_else(|| format!("Invalid repeat count, must be 1 to {}", MAX_REPEAT))?;
+                (steps, repeat)
+            }
+            None => (spec, 1),
+        };
+        let steps = steps
+            .split(',')
+            .map(|step| LedStep::parse(step.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(LedPattern { steps, repeat })
+    }
+
+    /// How long playing the whole pattern takes
+    pub fn duration_ms(&self) -> u64 {
+        let once: u64 = self.steps.iter().map(|x| x.duration_ms as u64).sum();
+        once * self.repeat as u64
+    }
+}
+
+/// Colors that the LED supports, with their maximum brightness
+///
+/// None if the EC doesn't have the LED.
+pub fn supported_colors(ec: &CrosEc, led: EcLedId) -> EcResult<Option<Vec<(EcLedColor, u8)>>> {
+    let range = match ec.get_led_brightness_range(led) {
+        Ok(range) => range,
+        Err(EcError::Response(EcResponseStatus::InvalidParameter)) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    Ok(Some(
+        ALL_COLORS
+            .iter()
+            .filter(|color| range[**color as usize] > 0)
+            .map(|color| (*color, range[*color as usize]))
+            .collect(),
+    ))
+}
+
+/// List the colors of one LED, or of all that the EC has
+pub fn print_leds(ec: &CrosEc, led: Option<EcLedId>, out: &mut dyn OutputSink) -> EcResult<()> {
+    for led in ALL_LEDS
+        .iter()
+        .filter(|x| led.map_or(true, |led| led == **x))
+    {
+        let colors = match supported_colors(ec, *led)? {
+            Some(colors) => colors
+                .iter()
+                .map(|(color, max)| format!("{} (max {})", color_name(*color), max))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => String::from("not present"),
+        };
+        outln!(out, "{:<8} {}", format!("{}:", led_name(*led)), colors);
+    }
+    Ok(())
+}
+
+/// Keep an LED at a fixed color or let the EC control it again
+///
+/// A fixed LED stays like this until it's set to auto, or the EC resets.
+pub fn set(ec: &CrosEc, led: EcLedId, setting: LedSetting) -> EcResult<()> {
+    match setting {
+        LedSetting::Auto => ec.set_led_auto(led),
+        LedSetting::Fixed(step) => {
+            let range = ec.get_led_brightness_range(led)?;
+            check_supported(led, &range, &step)?;
+            ec.set_led_brightness(led, step.brightness(&range))
+        }
+    }
+}
+
+fn check_supported(led: EcLedId, range: &[u8; EC_LED_COLOR_COUNT], step: &LedStep) -> EcResult<()> {
+    match step.color {
+        Some(color) if range[color as usize] == 0 => Err(EcError::DeviceError(format!(
+            "The {} LED doesn't support {}",
+            led_name(led),
+            color_name(color)
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Play a pattern on an LED and return it to automatic control afterwards
+pub fn play(ec: &CrosEc, led: EcLedId, pattern: &LedPattern) -> EcResult<()> {
+    let range = ec.get_led_brightness_range(led)?;
+    for step in &pattern.steps {
+        check_supported(led, &range, step)?;
+    }
+
+    let res = (|| -> EcResult<()> {
+        for _ in 0..pattern.repeat {
+            for step in &pattern.steps {
+                ec.set_led_brightness(led, step.brightness(&range))?;
+                os_specific::sleep(step.duration_ms as u64 * 1000);
+            }
+        }
+        Ok(())
+    })();
+    // Even if a step failed, don't leave the LED stuck
+    let auto = ec.set_led_auto(led);
+    res.and(auto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pattern() {
+        let pattern = LedPattern::parse("white:500, amber@50:200,off:100*3").unwrap();
+        assert_eq!(pattern.repeat, 3);
+        assert_eq!(
+            pattern.steps,
+            vec![
+                LedStep {
+                    color: Some(EcLedColor::White),
+                    brightness: 100,
+                    duration_ms: 500
+                },
+                LedStep {
+                    color: Some(EcLedColor::Amber),
+                    brightness: 50,
+                    duration_ms: 200
+                },
+                LedStep {
+                    color: None,
+                    brightness: 100,
+                    duration_ms: 100
+                },
+            ]
+        );
+        assert_eq!(pattern.duration_ms(), 2400);
+        assert_eq!(LedPattern::parse("red:1").unwrap().repeat, 1);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(LedPattern::parse("white").is_err());
+        assert!(LedPattern::parse("purple:100").is_err());
+        assert!(LedPattern::parse("white@101:100").is_err());
+        assert!(LedPattern::parse("white:100*0").is_err());
+        assert!(LedPattern::parse("white:60000").is_err());
+        assert!(LedPattern::parse("white:100,").is_err());
+    }
+
+    #[test]
+    fn parse_led_setting() {
+        assert_eq!(parse_led_args::<&str>(&[]), Ok(None));
+        assert_eq!(
+            parse_led_args(&["left"]),
+            Ok(Some((EcLedId::LeftLed, None)))
+        );
+        assert_eq!(
+            parse_led_args(&["right", "auto"]),
+            Ok(Some((EcLedId::RightLed, Some(LedSetting::Auto))))
+        );
+        assert_eq!(
+            parse_led_args(&["right", "amber@50"]),
+            Ok(Some((
+                EcLedId::RightLed,
+                Some(LedSetting::Fixed(LedStep {
+                    color: Some(EcLedColor::Amber),
+                    brightness: 50,
+                    duration_ms: 0
+                }))
+            )))
+        );
+        assert!(parse_led_args(&["top", "off"]).is_err());
+        assert!(parse_led_args(&["left", "purple"]).is_err());
+    }
+
+    #[test]
+    fn scale_brightness() {
+        let range = [0, 0, 0, 0, 100, 255];
+        let step = LedStep::parse("amber@50:1").unwrap();
+        assert_eq!(step.brightness(&range), [0, 0, 0, 0, 0, 128]);
+        let step = LedStep::parse("white@1:1").unwrap();
+        assert_eq!(step.brightness(&range), [0, 0, 0, 0, 1, 0]);
+        let step = LedStep::parse("off:1").unwrap();
+        assert_eq!(step.brightness(&range), [0; EC_LED_COLOR_COUNT]);
+    }
+}
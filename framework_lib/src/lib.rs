@@ -19,22 +19,63 @@ pub mod audio_card;
 #[macro_use]
 extern crate uefi_services;
 
+#[cfg(not(feature = "uefi"))]
+pub mod audit;
+pub mod bios_setting;
 pub mod capsule;
 pub mod capsule_content;
 pub mod ccgx;
 pub mod chromium_ec;
+#[cfg(any(feature = "commandline", feature = "uefi"))]
 pub mod commandline;
 pub mod csme;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod devices;
 pub mod ec_binary;
+pub mod ec_snapshot;
 pub mod esrt;
 #[cfg(not(feature = "uefi"))]
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod firmware_file;
+pub mod gpio;
+#[cfg(not(feature = "uefi"))]
 pub mod guid;
+pub mod health_report;
+#[cfg(feature = "linux")]
+pub mod helper;
+#[cfg(not(feature = "uefi"))]
+pub mod job;
+pub mod kb_matrix;
+#[cfg(not(feature = "uefi"))]
+pub mod kblight;
+pub mod led;
 mod os_specific;
+pub mod output;
 pub mod power;
+pub mod report;
 pub mod smbios;
+#[cfg(not(feature = "uefi"))]
+pub mod suspend;
+#[cfg(not(feature = "uefi"))]
+pub mod tablet;
+#[cfg(feature = "linux")]
+pub mod thunderbolt;
+#[cfg(feature = "tray")]
+pub mod tray;
+pub mod ucsi;
+#[cfg(feature = "linux")]
+pub mod udev;
 #[cfg(feature = "uefi")]
 pub mod uefi;
+pub mod uefi_vars;
+pub mod units;
 mod util;
+pub mod wake_source;
+#[cfg(feature = "windows")]
+pub mod windows_service;
 
 pub mod built_info {
     // The file has been placed there by the build script.
@@ -1,8 +1,17 @@
 //! Helper functions that need OS/platform specific implementations
 
+#[cfg(feature = "commandline")]
+use std::sync::{Once, OnceLock};
 #[cfg(not(feature = "uefi"))]
 use std::{thread, time};
 
+#[cfg(feature = "commandline")]
+use crate::chromium_ec::CancelToken;
+
+/// Cancelled by Ctrl-C, the parent of the tokens from `ctrl_c_token`
+#[cfg(feature = "commandline")]
+static CTRL_C: OnceLock<CancelToken> = OnceLock::new();
+
 /// Sleep a number of microseconds
 pub fn sleep(micros: u64) {
     #[cfg(not(feature = "uefi"))]
@@ -19,3 +28,106 @@ pub fn sleep(micros: u64) {
         bs.stall(micros as usize);
     }
 }
+
+/// Native resolution of the built-in display, width and height in pixels
+///
+/// Only implemented on Linux, where it's the preferred mode of the eDP connector.
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn native_resolution() -> Option<(u32, u32)> {
+    #[cfg(all(feature = "linux", target_os = "linux"))]
+    {
+        for entry in std::fs::read_dir("/sys/class/drm").ok()?.flatten() {
+            let name = entry.file_name();
+            if !name.to_string_lossy().contains("-eDP-") {
+                continue;
+            }
+            // The first mode is the preferred one
+            let Ok(modes) = std::fs::read_to_string(entry.path().join("modes")) else {
+                continue;
+            };
+            let Some((width, height)) = modes.lines().next().and_then(|x| x.split_once('x')) else {
+                continue;
+            };
+            // Interlaced modes end in i
+            let height = height.trim_end_matches('i');
+            if let (Ok(width), Ok(height)) = (width.parse(), height.parse()) {
+                return Some((width, height));
+            }
+        }
+    }
+    None
+}
+
+/// Token for one operation, that is cancelled when the user presses Ctrl-C
+///
+/// Call it once per operation and drop the token when the operation is done.
+/// The first call replaces the default handler, which kills the process, so
+/// that a long running operation can stop cleanly. While no operation is
+/// running, or if Ctrl-C is pressed a second time because the operation is
+/// stuck, the process is killed as usual.
+/// Only implemented on Linux, FreeBSD and Windows, elsewhere the token is never cancelled.
+#[cfg(feature = "commandline")]
+pub fn ctrl_c_token() -> CancelToken {
+    static INSTALL: Once = Once::new();
+    let root = CTRL_C.get_or_init(CancelToken::new);
+    // Ctrl-C that stopped an earlier operation doesn't stop this one
+    if !root.has_children() {
+        root.reset();
+    }
+    INSTALL.call_once(install_ctrl_c_handler);
+    root.child()
+}
+
+/// Cancel the running operations, returns false if there are none to cancel
+#[cfg(any(all(feature = "unix", unix), all(feature = "windows", windows)))]
+fn on_ctrl_c() -> bool {
+    match CTRL_C.get() {
+        Some(root) if root.has_children() && !root.is_cancelled() => {
+            root.cancel();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(all(feature = "unix", unix))]
+fn install_ctrl_c_handler() {
+    extern "C" fn handler(signal: libc::c_int) {
+        // Stays installed for the next operation. Otherwise kill the process
+        // like the default handler would, all calls are async-signal-safe.
+        if !on_ctrl_c() {
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+        }
+    }
+    let handler = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGINT, handler) } == libc::SIG_ERR {
+        debug!("Failed to install Ctrl-C handler");
+    }
+}
+
+#[cfg(all(feature = "windows", windows))]
+fn install_ctrl_c_handler() {
+    use windows::Win32::Foundation::{BOOL, FALSE, TRUE};
+    use windows::Win32::System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+
+    unsafe extern "system" fn handler(ctrl_type: u32) -> BOOL {
+        // Not handling it lets the default handler terminate the process
+        if (ctrl_type == CTRL_C_EVENT || ctrl_type == CTRL_BREAK_EVENT) && on_ctrl_c() {
+            TRUE
+        } else {
+            FALSE
+        }
+    }
+    if let Err(err) = unsafe { SetConsoleCtrlHandler(Some(handler), true) } {
+        debug!("Failed to install Ctrl-C handler: {:?}", err);
+    }
+}
+
+#[cfg(all(
+    feature = "commandline",
+    not(any(all(feature = "unix", unix), all(feature = "windows", windows)))
+))]
+fn install_ctrl_c_handler() {}
@@ -0,0 +1,196 @@
+//! Where human readable output of the library goes
+//!
+//! The commandline tools print everything to stdout, but a GUI frontend
+//! wants to render the same information in its own widgets.
+//! Functions that print a lot take an `OutputSink` so the caller can decide.
+
+use alloc::format;
+use alloc::string::String;
+#[cfg(not(feature = "uefi"))]
+use alloc::string::ToString;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+#[cfg(not(feature = "uefi"))]
+use std::sync::mpsc::Sender;
+
+use regex::Regex;
+
+use crate::chromium_ec::CancelToken;
+
+/// Format a line and write it to an `OutputSink`, just like `println!`
+macro_rules! outln {
+    ($out:expr) => {
+        $out.write_line("")
+    };
+    ($out:expr, $($arg:tt)*) => {
+        $out.write_line(&alloc::format!($($arg)*))
+    };
+}
+pub(crate) use outln;
+
+pub trait OutputSink {
+    /// Write a single line of text, without trailing newline
+    fn write_line(&mut self, line: &str);
+
+    /// Write rows of label and value, lined up in two columns
+    fn write_table(&mut self, rows: &[(&str, String)]) {
+        let width = rows.iter().map(|(label, _)| label.len()).max().unwrap_or(0) + 1;
+        for (label, value) in rows {
+            let label = format!("{}:", label);
+            self.write_line(&format!("  {:width$} {}", label, value, width = width));
+        }
+    }
+
+    /// Report progress of a long running operation
+    fn progress(&mut self, _done: usize, _total: usize) {}
+}
+
+/// Print everything to stdout, like the commandline tool always did
+#[derive(Default)]
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn write_line(&mut self, line: &str) {
+        println!("{}", line);
+    }
+
+    /// Keep updating the same line, until done
+    fn progress(&mut self, done: usize, total: usize) {
+        let percent = (done * 100).checked_div(total).unwrap_or(100);
+        print!("\r  {:>7}/{:>7} ({:>3}%)", done, total, percent);
+        if done >= total {
+            println!();
+        }
+        #[cfg(not(feature = "uefi"))]
+        {
+            use std::io::Write;
+            let _ = std::io::stdout().flush();
+        }
+    }
+}
+
+/// Collect all output in a string
+#[derive(Default)]
+pub struct StringSink {
+    pub buffer: String,
+}
+
+impl OutputSink for StringSink {
+    fn write_line(&mut self, line: &str) {
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+    }
+}
+
+/// Pass lines on to another sink and cancel the token once one matches a pattern
+///
+/// Lets `--console follow --until` stop when the expected output appears.
+pub struct UntilSink<'a> {
+    inner: &'a mut dyn OutputSink,
+    pattern: Regex,
+    cancel: CancelToken,
+    matched: bool,
+}
+
+impl<'a> UntilSink<'a> {
+    pub fn new(inner: &'a mut dyn OutputSink, pattern: Regex, cancel: CancelToken) -> Self {
+        UntilSink {
+            inner,
+            pattern,
+            cancel,
+            matched: false,
+        }
+    }
+
+    /// Whether any line matched the pattern
+    pub fn matched(&self) -> bool {
+        self.matched
+    }
+}
+
+impl OutputSink for UntilSink<'_> {
+    fn write_line(&mut self, line: &str) {
+        self.inner.write_line(line);
+        if !self.matched && self.pattern.is_match(line) {
+            self.matched = true;
+            self.cancel.cancel();
+        }
+    }
+
+    fn progress(&mut self, done: usize, total: usize) {
+        self.inner.progress(done, total);
+    }
+}
+
+/// Single piece of output, as sent by `ChannelSink`
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputMessage {
+    Line(String),
+    Table(Vec<(String, String)>),
+    Progress(usize, usize),
+}
+
+/// Send output to another thread, for example the UI thread of a GUI
+///
+/// Sending silently stops when the receiver has hung up.
+#[cfg(not(feature = "uefi"))]
+pub struct ChannelSink {
+    sender: Sender<OutputMessage>,
+}
+
+#[cfg(not(feature = "uefi"))]
+impl ChannelSink {
+    pub fn new(sender: Sender<OutputMessage>) -> Self {
+        ChannelSink { sender }
+    }
+}
+
+#[cfg(not(feature = "uefi"))]
+impl OutputSink for ChannelSink {
+    fn write_line(&mut self, line: &str) {
+        let _ = self.sender.send(OutputMessage::Line(line.to_string()));
+    }
+
+    fn write_table(&mut self, rows: &[(&str, String)]) {
+        let rows = rows
+            .iter()
+            .map(|(label, value)| (label.to_string(), value.clone()))
+            .collect();
+        let _ = self.sender.send(OutputMessage::Table(rows));
+    }
+
+    fn progress(&mut self, done: usize, total: usize) {
+        let _ = self.sender.send(OutputMessage::Progress(done, total));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn until_sink_cancels() {
+        let mut inner = StringSink::default();
+        let cancel = CancelToken::new();
+        let pattern = Regex::new(r"^\[[\d.]+ Battery \d+%").unwrap();
+        let mut out = UntilSink::new(&mut inner, pattern, cancel.clone());
+
+        out.write_line("[12.345 power state 3 = S0, in 0x0003]");
+        assert!(!out.matched());
+        assert!(!cancel.is_cancelled());
+
+        out.write_line("[12.400 Battery 89% (Display 90.2 %) / 3h:12 to full]");
+        assert!(out.matched());
+        assert!(cancel.is_cancelled());
+
+        // Everything is still passed on
+        out.write_line("---");
+        assert_eq!(
+            inner.buffer,
+            "[12.345 power state 3 = S0, in 0x0003]\n\
+             [12.400 Battery 89% (Display 90.2 %) / 3h:12 to full]\n---\n"
+        );
+    }
+}
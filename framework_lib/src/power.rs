@@ -1,74 +1,36 @@
 //! Get information about system power (battery, AC, PD ports)
 
+use alloc::format;
 use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
-use core::convert::TryInto;
 use core::fmt;
 use core::prelude::v1::derive;
 use log::Level;
 
 use crate::ccgx::{AppVersion, Application, BaseVersion, ControllerVersion, MainPdVersions};
 use crate::chromium_ec::command::EcRequestRaw;
-use crate::chromium_ec::commands::{EcRequestReadPdVersion, EcRequestUsbPdPowerInfo};
-use crate::chromium_ec::{print_err_ref, CrosEc, CrosEcDriver, EcResult};
+use crate::chromium_ec::commands::{
+    ChargeStateParam, EcFeatureCode, EcRequestExternalPowerLimitV1, EcRequestGetUptimeInfo,
+    EcRequestPdGetLogEntry, EcRequestReadPdVersion, EcRequestThermalGetThresholdV1,
+    EcRequestUsbPdControl, EcRequestUsbPdDiscovery, EcRequestUsbPdPowerInfo,
+    EcResponseExpansionBayStatus, EcTempThreshold, ExpansionBayBoard, UsbPdControlRole,
+    EC_POWER_LIMIT_NONE,
+};
+use crate::chromium_ec::memmap::{
+    EcMemmap, EC_BATT_FLAG_CHARGING, EC_BATT_FLAG_DISCHARGING, EC_BATT_FLAG_LEVEL_CRITICAL,
+    EC_FAN_SPEED_NOT_PRESENT, EC_FAN_SPEED_STALLED,
+};
+use crate::chromium_ec::{
+    print_err, print_err_ref, CancelToken, CrosEc, EcError, EcResult, EcResultExt,
+};
+use crate::devices::FRAMEWORK_VID;
+use crate::os_specific;
+use crate::output::{outln, OutputSink};
 use crate::smbios;
-use crate::smbios::get_platform;
-use crate::util::Platform;
-
-/// Maximum length of strings in memmap
-const EC_MEMMAP_TEXT_MAX: u16 = 8;
-
-// The offset address of each type of data in mapped memory.
-// TODO: Move non-power values to other modules
-const EC_MEMMAP_TEMP_SENSOR: u16 = 0x00; // Temp sensors 0x00 - 0x0f
-const EC_MEMMAP_FAN: u16 = 0x10; // Fan speeds 0x10 - 0x17
-const _EC_MEMMAP_TEMP_SENSOR_B: u16 = 0x18; // More temp sensors 0x18 - 0x1f
-const _EC_MEMMAP_ID: u16 = 0x2120; // 0x20 == 'E', 0x21 == 'C'
-const EC_MEMMAP_ID_VERSION: u16 = 0x22; // Version of data in 0x20 - 0x2f
-const EC_MEMMAP_THERMAL_VERSION: u16 = 0x23; // Version of data in 0x00 - 0x1f
-const EC_MEMMAP_BATTERY_VERSION: u16 = 0x24; // Version of data in 0x40 - 0x7f
-const EC_MEMMAP_SWITCHES_VERSION: u16 = 0x25; // Version of data in 0x30 - 0x33
-const EC_MEMMAP_EVENTS_VERSION: u16 = 0x26; // Version of data in 0x34 - 0x3f
-const _EC_MEMMAP_HOST_CMD_FLAGS: u16 = 0x27; // Host cmd interface flags (8 bits)
-                                             // Unused 0x28 - 0x2f
-const _EC_MEMMAP_SWITCHES: u16 = 0x30; // 8 bits
-                                       // Unused 0x31 - 0x33
-const _EC_MEMMAP_HOST_EVENTS: u16 = 0x34; // 64 bits
-                                          // Battery values are all 32 bits, unless otherwise noted.
-const EC_MEMMAP_BATT_VOLT: u16 = 0x40; // Battery Present Voltage
-const EC_MEMMAP_BATT_RATE: u16 = 0x44; // Battery Present Rate
-const EC_MEMMAP_BATT_CAP: u16 = 0x48; // Battery Remaining Capacity
-const EC_MEMMAP_BATT_FLAG: u16 = 0x4c; // Battery State, see below (8-bit)
-const EC_MEMMAP_BATT_COUNT: u16 = 0x4d; // Battery Count (8-bit)
-const EC_MEMMAP_BATT_INDEX: u16 = 0x4e; // Current Battery Data Index (8-bit)
-                                        // Unused 0x4f
-const EC_MEMMAP_BATT_DCAP: u16 = 0x50; // Battery Design Capacity
-const EC_MEMMAP_BATT_DVLT: u16 = 0x54; // Battery Design Voltage
-const EC_MEMMAP_BATT_LFCC: u16 = 0x58; // Battery Last Full Charge Capacity
-const EC_MEMMAP_BATT_CCNT: u16 = 0x5c; // Battery Cycle Count
-                                       // Strings are all 8 bytes (EC_MEMMAP_TEXT_MAX)
-const EC_MEMMAP_BATT_MFGR: u16 = 0x60; // Battery Manufacturer String
-const EC_MEMMAP_BATT_MODEL: u16 = 0x68; // Battery Model Number String
-const EC_MEMMAP_BATT_SERIAL: u16 = 0x70; // Battery Serial Number String
-const EC_MEMMAP_BATT_TYPE: u16 = 0x78; // Battery Type String
-const EC_MEMMAP_ALS: u16 = 0x80; // ALS readings in lux (2 X 16 bits)
-                                 // Unused 0x84 - 0x8f
-const _EC_MEMMAP_ACC_STATUS: u16 = 0x90; // Accelerometer status (8 bits )
-                                         // Unused 0x91
-const _EC_MEMMAP_ACC_DATA: u16 = 0x92; // Accelerometers data 0x92 - 0x9f
-                                       // 0x92: u16Lid Angle if available, LID_ANGLE_UNRELIABLE otherwise
-                                       // 0x94 - 0x99: u161st Accelerometer
-                                       // 0x9a - 0x9f: u162nd Accelerometer
-const _EC_MEMMAP_GYRO_DATA: u16 = 0xa0; // Gyroscope data 0xa0 - 0xa5
-                                        // Unused 0xa6 - 0xdf
-
-// Battery bit flags at EC_MEMMAP_BATT_FLAG.
-const EC_BATT_FLAG_AC_PRESENT: u8 = 0x01;
-const EC_BATT_FLAG_BATT_PRESENT: u8 = 0x02;
-const EC_BATT_FLAG_DISCHARGING: u8 = 0x04;
-const EC_BATT_FLAG_CHARGING: u8 = 0x08;
-const EC_BATT_FLAG_LEVEL_CRITICAL: u8 = 0x10;
+use crate::ucsi;
+use crate::units;
+use crate::util::{self, Platform};
 
 #[derive(Debug)]
 enum TempSensor {
@@ -92,7 +54,7 @@ impl From<u8> for TempSensor {
 impl fmt::Display for TempSensor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if let TempSensor::Ok(t) = self {
-            write!(f, "{} C", t)
+            write!(f, "{}", units::fmt_temp(*t as i32))
         } else {
             write!(f, "{:?}", self)
         }
@@ -123,6 +85,36 @@ pub struct BatteryInformation {
     pub level_critical: bool,
 }
 
+impl BatteryInformation {
+    /// Power flowing into or out of the battery, in mW
+    pub fn power_mw(&self) -> u32 {
+        self.present_voltage * self.present_rate / 1000
+    }
+
+    /// Estimated minutes until the battery is empty, at the current rate
+    pub fn time_to_empty_min(&self) -> Option<u32> {
+        if !self.discharging || self.present_rate == 0 {
+            return None;
+        }
+        Some(self.remaining_capacity * 60 / self.present_rate)
+    }
+
+    /// Estimated minutes until the battery is full, at the current rate
+    pub fn time_to_full_min(&self) -> Option<u32> {
+        if !self.charging || self.present_rate == 0 {
+            return None;
+        }
+        let missing = self
+            .last_full_charge_capacity
+            .saturating_sub(self.remaining_capacity);
+        Some(missing * 60 / self.present_rate)
+    }
+}
+
+fn format_minutes(minutes: u32) -> String {
+    format!("{}h {:02}min", minutes / 60, minutes % 60)
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PowerInfo {
     pub ac_present: bool,
@@ -163,138 +155,353 @@ impl From<PowerInfo> for ReducedPowerInfo {
     }
 }
 
-fn read_string(ec: &CrosEc, address: u16) -> String {
-    let bytes = ec.read_memory(address, EC_MEMMAP_TEXT_MAX).unwrap();
-    String::from_utf8_lossy(bytes.as_slice()).replace(['\0'], "")
+/// Not supported on TGL EC
+pub fn get_als_reading(ec: &CrosEc) -> Option<u32> {
+    Some(print_err(ec.memmap())?.als[0] as u32)
 }
 
-fn read_u32(ec: &CrosEc, address: u16) -> u32 {
-    let bytes = ec.read_memory(address, 4).unwrap();
-    if bytes.len() != 4 {
-        debug_assert!(
-            bytes.len() == 4,
-            "Tried to read 4 bytes but got {}",
-            bytes.len()
-        );
-        error!("Unexpected length returned: {:?} instead of 4", bytes.len());
-        return 0;
+/// Whether the lid is currently open, None on systems without a lid
+pub fn is_lid_open(ec: &CrosEc) -> Option<bool> {
+    if !util::platform_spec().has_lid {
+        return None;
     }
-    u32::from_ne_bytes(bytes[0..4].try_into().unwrap())
+    Some(print_err(ec.memmap())?.lid_open())
 }
 
-pub fn print_memmap_version_info(ec: &CrosEc) {
-    // TODO: I don't think these are very useful
-    let _id_ver = ec.read_memory(EC_MEMMAP_ID_VERSION, 2).unwrap(); /* Version of data in 0x20 - 0x2f */
-    let _thermal_ver = ec.read_memory(EC_MEMMAP_THERMAL_VERSION, 2).unwrap(); /* Version of data in 0x00 - 0x1f */
-    let _battery_ver = ec.read_memory(EC_MEMMAP_BATTERY_VERSION, 2).unwrap(); /* Version of data in 0x40 - 0x7f */
-    let _switches_ver = ec.read_memory(EC_MEMMAP_SWITCHES_VERSION, 2).unwrap(); /* Version of data in 0x30 - 0x33 */
-    let _events_ver = ec.read_memory(EC_MEMMAP_EVENTS_VERSION, 2).unwrap();
+pub fn print_sensors(ec: &CrosEc, out: &mut dyn OutputSink) -> EcResult<()> {
+    let als_int = ec.memmap()?.als[0] as u32;
+    outln!(out, "ALS: {:>4} Lux", als_int);
+    Ok(())
 }
 
-/// Not supported on TGL EC
-pub fn get_als_reading(ec: &CrosEc) -> Option<u32> {
-    let als = ec.read_memory(EC_MEMMAP_ALS, 0x04)?;
-    Some(u32::from_le_bytes([als[0], als[1], als[2], als[3]]))
+/// Names of the temperature sensors of the platform, in the order of the memory map
+fn temp_sensor_names(platform: Option<Platform>) -> &'static [&'static str] {
+    match platform {
+        Some(Platform::IntelGen11) | Some(Platform::IntelGen12) | Some(Platform::IntelGen13) => &[
+            "F75303_Local",
+            "F75303_CPU",
+            "F75303_DDR",
+            "Battery",
+            "PECI",
+            "F57397_VCCGT",
+        ],
+        Some(Platform::Framework13Amd) => &["F75303_Local", "F75303_CPU", "F75303_DDR", "APU"],
+        Some(Platform::Framework16) => &[
+            "F75303_Local",
+            "F75303_CPU",
+            "F75303_DDR",
+            "APU",
+            "dGPU VR",
+            "dGPU VRAM",
+            "dGPU AMB",
+            "dGPU temp",
+        ],
+        _ => &[
+            "Temp 0", "Temp 1", "Temp 2", "Temp 3", "Temp 4", "Temp 5", "Temp 6", "Temp 7",
+        ],
+    }
 }
 
-pub fn print_sensors(ec: &CrosEc) {
-    let als_int = get_als_reading(ec).unwrap();
-    println!("ALS: {:>4} Lux", als_int);
+/// Temperature of each sensor in Celsius, None if the sensor can't be read
+pub fn get_temperatures(ec: &CrosEc) -> Option<Vec<(&'static str, Option<u8>)>> {
+    let memmap = print_err(ec.memmap())?;
+    let names = temp_sensor_names(smbios::get_platform());
+    let temps = names
+        .iter()
+        .zip(memmap.thermal.temps)
+        .map(|(name, temp)| match TempSensor::from(temp) {
+            TempSensor::Ok(celsius) => (*name, Some(celsius)),
+            _ => (*name, None),
+        })
+        .collect();
+    Some(temps)
 }
 
-pub fn print_thermal(ec: &CrosEc) {
-    let temps = ec.read_memory(EC_MEMMAP_TEMP_SENSOR, 0x0F).unwrap();
-    let fans = ec.read_memory(EC_MEMMAP_FAN, 0x08).unwrap();
+pub fn print_thermal(ec: &CrosEc, out: &mut dyn OutputSink) -> EcResult<()> {
+    let memmap = ec.memmap()?;
+    let temps = memmap.thermal.temps;
+    let fans = fan_status(&memmap);
 
     let platform = smbios::get_platform();
-    match platform {
-        Some(Platform::IntelGen11) | Some(Platform::IntelGen12) | Some(Platform::IntelGen13) => {
-            println!("  F75303_Local: {:>4}", TempSensor::from(temps[0]));
-            println!("  F75303_CPU:   {:>4}", TempSensor::from(temps[1]));
-            println!("  F75303_DDR:   {:>4}", TempSensor::from(temps[2]));
-            println!("  Battery:      {:>4}", TempSensor::from(temps[3]));
-            println!("  PECI:         {:>4}", TempSensor::from(temps[4]));
-            println!("  F57397_VCCGT: {:>4}", TempSensor::from(temps[5]));
-        }
-        Some(Platform::Framework13Amd | Platform::Framework16) => {
-            println!("  F75303_Local: {:>4}", TempSensor::from(temps[0]));
-            println!("  F75303_CPU:   {:>4}", TempSensor::from(temps[1]));
-            println!("  F75303_DDR:   {:>4}", TempSensor::from(temps[2]));
-            println!("  APU:          {:>4}", TempSensor::from(temps[3]));
-            if matches!(platform, Some(Platform::Framework16)) {
-                println!("  dGPU VR:      {:>4}", TempSensor::from(temps[4]));
-                println!("  dGPU VRAM:    {:>4}", TempSensor::from(temps[5]));
-                println!("  dGPU AMB:     {:>4}", TempSensor::from(temps[6]));
-                println!("  dGPU temp:    {:>4}", TempSensor::from(temps[7]));
+    for (name, temp) in temp_sensor_names(platform).iter().zip(temps) {
+        outln!(
+            out,
+            "  {:<13} {:>4}",
+            format!("{}:", name),
+            TempSensor::from(temp)
+        );
+    }
+
+    // Show all fans of the platform, even if the EC doesn't report them,
+    // e.g. when the Framework 16 has no expansion bay module
+    let fan_count = core::cmp::max(util::platform_spec().fans as usize, fans.len());
+    for i in 0..fan_count {
+        let status = match fans.get(i) {
+            // Only known for fans that this process changed, e.g. in the daemon
+            Some(fan) if ec.is_fan_manual(i as u8) => format!("{} (manual)", fan),
+            Some(fan) => format!("{}", fan),
+            None => String::from("Not present"),
+        };
+        if fan_count == 1 {
+            outln!(out, "  Fan Speed:    {}", status);
+        } else {
+            outln!(out, "  Fan {} Speed:  {}", i, status);
+        }
+    }
+
+    if matches!(platform, Some(Platform::Framework16)) {
+        if let Ok(status) = ec.get_expansion_bay_status() {
+            outln!(out, "  Bay Power:    {}", expansion_bay_power(&status));
+        }
+    }
+
+    if log_enabled!(Level::Warn) {
+        print_thermal_limits(ec, &temps, out);
+    }
+    Ok(())
+}
+
+/// State of the power rails of the expansion bay module
+fn expansion_bay_power(status: &EcResponseExpansionBayStatus) -> &'static str {
+    match (status.module_enabled(), status.module_fault()) {
+        (_, true) => "Fault",
+        (true, false) => "On",
+        (false, false) => "Off",
+    }
+}
+
+/// Show the status of the Framework 16 expansion bay
+///
+/// With verbose logging, also show power, temperatures and fans of the module.
+pub fn print_expansion_bay(ec: &CrosEc, out: &mut dyn OutputSink) -> EcResult<()> {
+    let status = ec.get_expansion_bay_status()?;
+    let board = status.expansion_bay_board();
+    outln!(out, "Expansion Bay");
+    outln!(out, "  Enabled:       {}", status.module_enabled());
+    outln!(out, "  No fault:      {}", !status.module_fault());
+    outln!(out, "  Door closed:   {}", status.hatch_switch_closed());
+    match &board {
+        Ok(board) => outln!(out, "  Board:         {:?}", board),
+        Err(issue) => outln!(out, "  Board:         {:?}", issue),
+    }
+
+    if !log_enabled!(Level::Warn) {
+        return Ok(());
+    }
+    outln!(out, "  Power Rails:   {}", expansion_bay_power(&status));
+    // Only the GPU module has sensors, all of them are powered by the module
+    if matches!(
+        board,
+        Ok(ExpansionBayBoard::DualInterposer | ExpansionBayBoard::SingleInterposer)
+    ) {
+        let temps = ec
+            .memmap()
+            .context("Failed to read temperatures")?
+            .thermal
+            .temps;
+        outln!(out, "  dGPU VR:       {}", TempSensor::from(temps[4]));
+        outln!(out, "  dGPU VRAM:     {}", TempSensor::from(temps[5]));
+        outln!(out, "  dGPU AMB:      {}", TempSensor::from(temps[6]));
+        outln!(out, "  dGPU temp:     {}", TempSensor::from(temps[7]));
+    }
+    // Both fans are part of the expansion bay module
+    if board.is_ok() {
+        for (i, fan) in get_fan_status(ec).unwrap_or_default().iter().enumerate() {
+            outln!(out, "  Fan {} Speed:   {}", i, fan);
+        }
+    }
+    Ok(())
+}
+
+/// Temperatures in Celsius at which the EC takes action, for one sensor
+///
+/// Each is None if the EC doesn't take that action for the sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalLimits {
+    /// Host is notified
+    pub warn: Option<i32>,
+    /// EC asserts PROCHOT to throttle the CPU
+    pub prochot: Option<i32>,
+    /// EC shuts down the system
+    pub halt: Option<i32>,
+    /// Fan is off at or below this
+    pub fan_off: Option<i32>,
+    /// Fan is at maximum speed at or above this
+    pub fan_max: Option<i32>,
+}
+
+pub fn get_thermal_limits(ec: &CrosEc, sensor: u8) -> EcResult<ThermalLimits> {
+    let res = EcRequestThermalGetThresholdV1 {
+        sensor_num: sensor as u32,
+    }
+    .send_command(ec)?;
+    let celsius = |kelvin: u32| {
+        if kelvin == 0 {
+            None
+        } else {
+            Some(kelvin as i32 - 273)
+        }
+    };
+    let temp_host = res.temp_host;
+    Ok(ThermalLimits {
+        warn: celsius(temp_host[EcTempThreshold::Warn as usize]),
+        prochot: celsius(temp_host[EcTempThreshold::High as usize]),
+        halt: celsius(temp_host[EcTempThreshold::Halt as usize]),
+        fan_off: celsius(res.temp_fan_off),
+        fan_max: celsius(res.temp_fan_max),
+    })
+}
+
+/// Print thermal limits of all sensors and whether the EC is throttling the CPU
+///
+/// The EC doesn't report the state of PROCHOT, but it asserts it when any
+/// sensor is above its limit. If it isn't, throttling comes from the OS or CPU.
+fn print_thermal_limits(ec: &CrosEc, temps: &[u8], out: &mut dyn OutputSink) {
+    let limit = |x: Option<i32>| {
+        x.map(|t| format!("{:>5}", units::fmt_temp(t)))
+            .unwrap_or_else(|| String::from("  -  "))
+    };
+
+    outln!(
+        out,
+        "  Thermal Limits:  Warn  PROCHOT  Shutdown  Fan off  Fan max"
+    );
+    let mut prochot = None;
+    for (sensor, temp) in temps.iter().enumerate() {
+        let TempSensor::Ok(temp) = TempSensor::from(*temp) else {
+            continue;
+        };
+        let limits = match get_thermal_limits(ec, sensor as u8) {
+            Ok(limits) => limits,
+            Err(err) => {
+                debug!(
+                    "Failed to get thermal limits of sensor {}: {:?}",
+                    sensor, err
+                );
+                continue;
             }
+        };
+        outln!(
+            out,
+            "    Sensor {:<2}      {}  {}    {}     {}    {}",
+            sensor,
+            limit(limits.warn),
+            limit(limits.prochot),
+            limit(limits.halt),
+            limit(limits.fan_off),
+            limit(limits.fan_max)
+        );
+        if let Some(prochot_temp) = limits.prochot {
+            let above = temp as i32 >= prochot_temp;
+            prochot = Some(prochot.unwrap_or(false) || above);
+        }
+    }
+
+    let status = match prochot {
+        Some(true) => "Asserted by EC (over temperature)",
+        Some(false) => "Not asserted by EC",
+        None => "Unknown",
+    };
+    outln!(out, "  Thermal PROCHOT: {}", status);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanStatus {
+    Rpm(u16),
+    /// EC drives the fan, but it isn't spinning
+    Stalled,
+}
+
+impl FanStatus {
+    pub fn rpm(&self) -> u16 {
+        match self {
+            FanStatus::Rpm(rpm) => *rpm,
+            FanStatus::Stalled => 0,
         }
-        _ => {
-            println!("  Temp 0:       {:>4}", TempSensor::from(temps[0]));
-            println!("  Temp 1:       {:>4}", TempSensor::from(temps[1]));
-            println!("  Temp 2:       {:>4}", TempSensor::from(temps[2]));
-            println!("  Temp 3:       {:>4}", TempSensor::from(temps[3]));
-            println!("  Temp 4:       {:>4}", TempSensor::from(temps[4]));
-            println!("  Temp 5:       {:>4}", TempSensor::from(temps[5]));
-            println!("  Temp 6:       {:>4}", TempSensor::from(temps[6]));
-            println!("  Temp 7:       {:>4}", TempSensor::from(temps[7]));
+    }
+}
+
+impl fmt::Display for FanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanStatus::Rpm(rpm) => write!(f, "{:>4} RPM", rpm),
+            FanStatus::Stalled => write!(f, "   0 RPM (STALLED)"),
         }
     }
+}
+
+fn fan_status(memmap: &EcMemmap) -> Vec<FanStatus> {
+    memmap
+        .thermal
+        .fans
+        .iter()
+        .take_while(|rpm| **rpm != EC_FAN_SPEED_NOT_PRESENT)
+        .map(|rpm| {
+            if *rpm == EC_FAN_SPEED_STALLED {
+                FanStatus::Stalled
+            } else {
+                FanStatus::Rpm(*rpm)
+            }
+        })
+        .collect()
+}
 
-    let fan0 = u16::from_le_bytes([fans[0], fans[1]]);
-    println!("  Fan Speed:    {:>4} RPM", fan0);
+/// Status of all fans that are present
+pub fn get_fan_status(ec: &CrosEc) -> Option<Vec<FanStatus>> {
+    Some(fan_status(&print_err(ec.memmap())?))
+}
+
+/// Speed of all fans that are present, in RPM
+///
+/// Stalled fans are reported with 0 RPM.
+pub fn get_fan_speeds(ec: &CrosEc) -> Option<Vec<u16>> {
+    Some(get_fan_status(ec)?.iter().map(FanStatus::rpm).collect())
 }
 
 // TODO: Use Result
 pub fn power_info(ec: &CrosEc) -> Option<PowerInfo> {
-    let battery_flag = ec.read_memory(EC_MEMMAP_BATT_FLAG, 1)?[0];
-    debug!("AC/Battery flag: {:#X}", battery_flag);
-    let battery_lfcc = read_u32(ec, EC_MEMMAP_BATT_LFCC);
-    let battery_cap = read_u32(ec, EC_MEMMAP_BATT_CAP);
-
-    let present_voltage = read_u32(ec, EC_MEMMAP_BATT_VOLT);
-    let present_rate = read_u32(ec, EC_MEMMAP_BATT_RATE);
-    let _remaining_capacity = read_u32(ec, EC_MEMMAP_BATT_CAP); // TODO: Why didn't I use this?
-    let battery_count = ec.read_memory(EC_MEMMAP_BATT_COUNT, 1).unwrap()[0]; // 8 bit
-    let current_battery_index = ec.read_memory(EC_MEMMAP_BATT_INDEX, 1).unwrap()[0]; // 8 bit
-    let design_capacity = read_u32(ec, EC_MEMMAP_BATT_DCAP);
-    let design_voltage = read_u32(ec, EC_MEMMAP_BATT_DVLT);
-    let cycle_count = read_u32(ec, EC_MEMMAP_BATT_CCNT);
-
-    let manufacturer = read_string(ec, EC_MEMMAP_BATT_MFGR);
-    let model_number = read_string(ec, EC_MEMMAP_BATT_MODEL);
-    let serial_number = read_string(ec, EC_MEMMAP_BATT_SERIAL);
-    let battery_type = read_string(ec, EC_MEMMAP_BATT_TYPE);
-
-    Some(PowerInfo {
-        ac_present: 0 != (battery_flag & EC_BATT_FLAG_AC_PRESENT),
-        battery: if 0 != (battery_flag & EC_BATT_FLAG_BATT_PRESENT) {
+    Some(power_info_from(print_err(ec.memmap())?))
+}
+
+/// Battery and AC state from the memory map
+pub fn power_info_from(memmap: EcMemmap) -> PowerInfo {
+    let battery = memmap.battery;
+    debug!("AC/Battery flag: {:#X}", battery.flags);
+
+    PowerInfo {
+        ac_present: battery.ac_present(),
+        battery: if battery.battery_present() {
             Some(BatteryInformation {
                 // TODO: Add some more information
-                present_voltage,
-                present_rate,
-                remaining_capacity: battery_cap,
-                battery_count,
-                current_battery_index,
-                design_capacity,
-                design_voltage,
-                last_full_charge_capacity: battery_lfcc,
-                cycle_count,
-
-                charge_percentage: (100 * battery_cap) / battery_lfcc,
-
-                manufacturer,
-                model_number,
-                serial_number,
-                battery_type,
+                present_voltage: battery.present_voltage,
+                present_rate: battery.present_rate,
+                remaining_capacity: battery.remaining_capacity,
+                battery_count: battery.battery_count,
+                current_battery_index: battery.current_battery_index,
+                design_capacity: battery.design_capacity,
+                design_voltage: battery.design_voltage,
+                last_full_charge_capacity: battery.last_full_charge_capacity,
+                cycle_count: battery.cycle_count,
+
+                // LFCC is 0 until the battery has been read by the EC
+                charge_percentage: (100 * battery.remaining_capacity)
+                    .checked_div(battery.last_full_charge_capacity)
+                    .unwrap_or(0),
+
                 // TODO: Can both be true/falses at the same time?
-                discharging: 0 != (battery_flag & EC_BATT_FLAG_DISCHARGING),
-                charging: 0 != (battery_flag & EC_BATT_FLAG_CHARGING),
-                level_critical: 0 != (battery_flag & EC_BATT_FLAG_LEVEL_CRITICAL),
+                discharging: 0 != (battery.flags & EC_BATT_FLAG_DISCHARGING),
+                charging: 0 != (battery.flags & EC_BATT_FLAG_CHARGING),
+                level_critical: 0 != (battery.flags & EC_BATT_FLAG_LEVEL_CRITICAL),
+
+                manufacturer: battery.manufacturer,
+                model_number: battery.model_number,
+                serial_number: battery.serial_number,
+                battery_type: battery.battery_type,
             })
         } else {
             None
         },
-    })
+    }
 }
 
 // When no battery is present and we're running on AC
@@ -310,96 +517,352 @@ pub fn is_standalone(ec: &CrosEc) -> bool {
     }
 }
 
-pub fn get_and_print_power_info(ec: &CrosEc) -> i32 {
+/// Power that the charger negotiated with the system, in mW
+///
+/// If multiple chargers are connected, the system draws power from the strongest one.
+pub fn charger_power_mw(ec: &CrosEc) -> Option<u32> {
+    get_pd_info(ec, util::platform_spec().pd_ports.len() as u8)
+        .into_iter()
+        .flatten()
+        .filter(|info| info.role == UsbPowerRoles::Sink)
+        .map(|info| info.max_power / 1000)
+        .max()
+}
+
+/// Power Data Object, a voltage and current that a USB PD source offers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pdo {
+    Fixed {
+        voltage_mv: u32,
+        current_ma: u32,
+    },
+    Battery {
+        min_mv: u32,
+        max_mv: u32,
+        power_mw: u32,
+    },
+    Variable {
+        min_mv: u32,
+        max_mv: u32,
+        current_ma: u32,
+    },
+    /// Programmable Power Supply, the sink can request any voltage in the range
+    Pps {
+        min_mv: u32,
+        max_mv: u32,
+        current_ma: u32,
+    },
+    Unknown(u32),
+}
+
+impl Pdo {
+    /// Decode a PDO, see USB PD spec, chapter 6.4.1
+    pub fn from_raw(pdo: u32) -> Self {
+        let max_mv = ((pdo >> 20) & 0x3FF) * 50;
+        let min_mv = ((pdo >> 10) & 0x3FF) * 50;
+        match pdo >> 30 {
+            0 => Pdo::Fixed {
+                voltage_mv: min_mv,
+                current_ma: (pdo & 0x3FF) * 10,
+            },
+            1 => Pdo::Battery {
+                min_mv,
+                max_mv,
+                power_mw: (pdo & 0x3FF) * 250,
+            },
+            2 => Pdo::Variable {
+                min_mv,
+                max_mv,
+                current_ma: (pdo & 0x3FF) * 10,
+            },
+            _ if (pdo >> 28) & 0x3 == 0 => Pdo::Pps {
+                min_mv: ((pdo >> 8) & 0xFF) * 100,
+                max_mv: ((pdo >> 17) & 0xFF) * 100,
+                current_ma: (pdo & 0x7F) * 50,
+            },
+            _ => Pdo::Unknown(pdo),
+        }
+    }
+}
+
+fn volts(mv: u32) -> String {
+    format!("{}.{:01} V", mv / 1000, mv % 1000 / 100)
+}
+
+fn amps(ma: u32) -> String {
+    format!("{}.{:02} A", ma / 1000, ma % 1000 / 10)
+}
+
+impl fmt::Display for Pdo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Pdo::Fixed {
+                voltage_mv,
+                current_ma,
+            } => write!(f, "Fixed     {}, {}", volts(voltage_mv), amps(current_ma)),
+            Pdo::Battery {
+                min_mv,
+                max_mv,
+                power_mw,
+            } => write!(
+                f,
+                "Battery   {} - {}, {}",
+                volts(min_mv),
+                volts(max_mv),
+                units::fmt_power(power_mw)
+            ),
+            Pdo::Variable {
+                min_mv,
+                max_mv,
+                current_ma,
+            } => write!(
+                f,
+                "Variable  {} - {}, {}",
+                volts(min_mv),
+                volts(max_mv),
+                amps(current_ma)
+            ),
+            Pdo::Pps {
+                min_mv,
+                max_mv,
+                current_ma,
+            } => write!(
+                f,
+                "PPS       {} - {}, {}",
+                volts(min_mv),
+                volts(max_mv),
+                amps(current_ma)
+            ),
+            Pdo::Unknown(pdo) => write!(f, "Unknown   {:#010X}", pdo),
+        }
+    }
+}
+
+/// Identity of the device attached to a USB-C port, from PD discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdPartnerIdentity {
+    pub vid: u16,
+    pub pid: u16,
+    pub product_type: u8,
+}
+
+impl PdPartnerIdentity {
+    pub fn product_type_name(&self) -> &'static str {
+        match self.product_type {
+            1 => "Hub",
+            2 => "Peripheral",
+            3 => "Power Brick",
+            5 => "Alternate Mode Adapter",
+            _ => "Undefined",
+        }
+    }
+}
+
+/// None if the partner didn't tell its identity, which many chargers don't
+pub fn get_pd_partner_identity(ec: &CrosEc, port: u8) -> EcResult<Option<PdPartnerIdentity>> {
+    let res = EcRequestUsbPdDiscovery { port }.send_command(ec)?;
+    if res.vid == 0 {
+        return Ok(None);
+    }
+    Ok(Some(PdPartnerIdentity {
+        vid: res.vid,
+        pid: res.pid,
+        product_type: res.ptype,
+    }))
+}
+
+/// Show identity and source capabilities of the attached chargers
+///
+/// Capabilities are only available if the EC firmware supports UCSI.
+pub fn print_charger_info(ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    let has_ucsi = matches!(ec.has_feature(EcFeatureCode::UcsiPpm), Ok(true));
+    let mut found = false;
+    let ports = util::platform_spec().pd_ports.len() as u8;
+    for (port, info) in get_pd_info(ec, ports).into_iter().enumerate() {
+        let Ok(info) = info else {
+            continue;
+        };
+        if !matches!(
+            info.role,
+            UsbPowerRoles::Sink | UsbPowerRoles::SinkNotCharging
+        ) {
+            continue;
+        }
+        found = true;
+        let mw = info.max_power / 1000;
+        outln!(out, "USB-C Port {}", port);
+        outln!(out, "  Charging Type:  {:?}", info.charging_type);
+        outln!(out, "  Negotiated:     {}", units::fmt_power(mw));
+
+        match get_pd_partner_identity(ec, port as u8) {
+            Ok(Some(identity)) => {
+                let vendor = if identity.vid == FRAMEWORK_VID {
+                    " (Framework)"
+                } else {
+                    ""
+                };
+                outln!(out, "  Vendor ID:      {:#06X}{}", identity.vid, vendor);
+                outln!(out, "  Product ID:     {:#06X}", identity.pid);
+                outln!(out, "  Product Type:   {}", identity.product_type_name());
+            }
+            Ok(None) => outln!(out, "  Identity:       Not reported by the charger"),
+            Err(err) => {
+                debug!("Failed to get PD identity of port {}: {:?}", port, err);
+                outln!(out, "  Identity:       Unknown");
+            }
+        }
+
+        if !has_ucsi {
+            continue;
+        }
+        // The PPM numbers connectors in the same order as the EC, but starting from 1
+        match ucsi::get_partner_source_pdos(ec, port as u8 + 1) {
+            Ok(pdos) => {
+                outln!(out, "  Capabilities:");
+                for pdo in pdos {
+                    outln!(out, "    {}", Pdo::from_raw(pdo));
+                }
+            }
+            Err(err) => print_err_ref::<()>(&Err(err)),
+        }
+    }
+
+    if !found {
+        outln!(out, "No charger attached");
+        return 1;
+    }
+    0
+}
+
+/// Print AC and battery status
+///
+/// Fails if the battery is missing, unless the platform doesn't have one.
+pub fn get_and_print_power_info(ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    let has_battery = util::platform_spec().has_battery;
     if let Some(power_info) = power_info(ec) {
-        print_battery_information(&power_info);
-        if let Some(_battery) = &power_info.battery {
+        let charger_mw = if power_info.ac_present {
+            charger_power_mw(ec)
+        } else {
+            None
+        };
+        print_battery_information(&power_info, charger_mw, has_battery, out);
+        if power_info.battery.is_some() || !has_battery {
             return 0;
         }
     }
     1
 }
 
-fn print_battery_information(power_info: &PowerInfo) {
-    print!("  AC is:            ");
-    if power_info.ac_present {
-        println!("connected");
-    } else {
-        println!("not connected");
+fn print_battery_information(
+    power_info: &PowerInfo,
+    charger_mw: Option<u32>,
+    has_battery: bool,
+    out: &mut dyn OutputSink,
+) {
+    match (power_info.ac_present, charger_mw) {
+        (true, Some(mw)) => outln!(
+            out,
+            "  AC is:            connected ({})",
+            units::fmt_power(mw)
+        ),
+        (true, None) => outln!(out, "  AC is:            connected"),
+        (false, _) => outln!(out, "  AC is:            not connected"),
     }
 
-    print!("  Battery is:       ");
     if let Some(battery) = &power_info.battery {
-        println!("connected");
-        println!(
+        outln!(out, "  Battery is:       connected");
+        outln!(
+            out,
             "  Battery LFCC:     {:#?} mAh (Last Full Charge Capacity)",
             battery.last_full_charge_capacity
         );
-        println!("  Battery Capacity: {} mAh", battery.remaining_capacity);
+        outln!(
+            out,
+            "  Battery Capacity: {} mAh",
+            battery.remaining_capacity
+        );
         let wah = battery.remaining_capacity * battery.present_voltage / 1000;
-        println!("                    {}.{:2} Wh", wah / 1000, wah % 1000);
-        println!("  Charge level:     {:?}%", battery.charge_percentage);
+        outln!(out, "                    {}", units::fmt_energy(wah));
+        outln!(out, "  Charge level:     {:?}%", battery.charge_percentage);
+        if battery.charging || battery.discharging {
+            let mw = battery.power_mw();
+            outln!(
+                out,
+                "  Battery Power:    {} {}",
+                units::fmt_power(mw),
+                if battery.charging {
+                    "charging"
+                } else {
+                    "discharging"
+                }
+            );
+        }
+        if let Some(minutes) = battery.time_to_empty_min() {
+            outln!(out, "  Time to empty:    {}", format_minutes(minutes));
+        }
+        if let Some(minutes) = battery.time_to_full_min() {
+            outln!(out, "  Time to full:     {}", format_minutes(minutes));
+        }
 
         if log_enabled!(Level::Info) {
-            println!("  Manufacturer:     {}", battery.manufacturer);
-            println!("  Model Number:     {}", battery.model_number);
-            println!("  Serial Number:    {}", battery.serial_number);
-            println!("  Battery Type:     {}", battery.battery_type);
-
-            println!(
-                "  Present Voltage:  {}.{} V",
-                battery.present_voltage / 1000,
-                battery.present_voltage % 1000
+            outln!(out, "  Manufacturer:     {}", battery.manufacturer);
+            outln!(out, "  Model Number:     {}", battery.model_number);
+            outln!(
+                out,
+                "  Serial Number:    {}",
+                util::redact_serial(&battery.serial_number)
+            );
+            outln!(out, "  Battery Type:     {}", battery.battery_type);
+
+            outln!(
+                out,
+                "  Present Voltage:  {}",
+                units::fmt_voltage(battery.present_voltage)
             );
-            println!("  Present Rate:     {} mA", battery.present_rate);
+            outln!(out, "  Present Rate:     {} mA", battery.present_rate);
             // We only have a single battery in all our systems
             // Both values are always 0
             // println!("  Battery Count:    {}", battery.battery_count);
             // println!("  Current Battery#: {}", battery.current_battery_index);
 
-            println!("  Design Capacity:  {} mAh", battery.design_capacity);
+            outln!(out, "  Design Capacity:  {} mAh", battery.design_capacity);
             let design_wah = battery.design_capacity * battery.design_voltage / 1000;
-            println!(
-                "                    {}.{} Wh",
-                design_wah / 1000,
-                design_wah % 1000
+            outln!(out, "                    {}", units::fmt_energy(design_wah));
+            outln!(
+                out,
+                "  Design Voltage:   {}",
+                units::fmt_voltage(battery.design_voltage)
             );
-            println!(
-                "  Design Voltage:   {}.{} V",
-                battery.design_voltage / 1000,
-                battery.design_voltage % 1000
-            );
-            println!("  Cycle Count:      {}", battery.cycle_count);
+            outln!(out, "  Cycle Count:      {}", battery.cycle_count);
         }
 
         if battery.discharging {
-            println!("  Battery discharging");
+            outln!(out, "  Battery discharging");
         }
         if battery.charging {
-            println!("  Battery charging");
+            outln!(out, "  Battery charging");
         }
         if battery.level_critical {
-            println!("  Battery level CRITICAL!");
+            outln!(out, "  Battery level CRITICAL!");
         }
+    } else if has_battery {
+        outln!(out, "  Battery is:       not connected");
     } else {
-        println!("not connected");
+        outln!(out, "  No battery");
     }
 }
 
 pub fn check_update_ready(power_info: &PowerInfo) -> bool {
     // Checking if battery/AC conditions are enough for FW update
     // Either standalone mode or AC+20% charge
-    if power_info.battery.is_none()
-        || (power_info.ac_present && power_info.battery.as_ref().unwrap().charge_percentage > 20)
-    {
-        true
-    } else {
-        println!("Please plug in AC. If the battery is connected, charge it to at least 20% before proceeding.");
-        println!(
-            "Current charge is: {}%",
-            power_info.battery.as_ref().unwrap().charge_percentage
-        );
-        false
+    match &power_info.battery {
+        None => true,
+        Some(battery) if power_info.ac_present && battery.charge_percentage > 20 => true,
+        Some(battery) => {
+            println!("Please plug in AC. If the battery is connected, charge it to at least 20% before proceeding.");
+            println!("Current charge is: {}%", battery.charge_percentage);
+            false
+        }
     }
 }
 
@@ -494,71 +957,381 @@ pub fn get_pd_info(ec: &CrosEc, ports: u8) -> Vec<EcResult<UsbPdPowerInfo>> {
     info
 }
 
-pub fn get_and_print_pd_info(ec: &CrosEc) {
-    let fl16 = Some(crate::util::Platform::Framework16) == get_platform();
-    let ports = 4; // All our platforms have 4 PD ports so far
-    let infos = get_pd_info(ec, ports);
-    for (port, info) in infos.iter().enumerate().take(ports.into()) {
-        println!(
-            "USB-C Port {} ({}):",
-            port,
-            match port {
-                0 => "Right Back",
-                1 => "Right Front",
-                2 =>
-                    if fl16 {
-                        "Left Middle"
-                    } else {
-                        "Left Front"
-                    },
-                3 =>
-                    if fl16 {
-                        "Left Middle"
-                    } else {
-                        "Left Back"
-                    },
-                _ => "??",
+/// Type of PD log entries, that are logged when the charging state of a port changes
+const PD_EVENT_MCU_CHARGE: u8 = 0x01;
+/// Type of PD log entry, when the log is empty
+const PD_EVENT_NO_ENTRY: u8 = 0xFF;
+/// Log timestamps are in units of 1024us
+const PD_LOG_TIMESTAMP_SHIFT: u64 = 10;
+/// Don't read more entries than that, in case the EC keeps logging
+const PD_LOG_MAX_ENTRIES: usize = 256;
+
+/// Charger being attached or detached, from the PD event log of the EC
+#[derive(Debug, Clone, PartialEq)]
+pub struct AcHistoryEntry {
+    /// Milliseconds since EC boot
+    pub timestamp_ms: u64,
+    pub port: u8,
+    pub attached: bool,
+    /// Negotiated voltage in mV
+    pub voltage_mv: u16,
+    /// Negotiated current in mA
+    pub current_ma: u16,
+}
+
+impl AcHistoryEntry {
+    pub fn watts(&self) -> u32 {
+        self.voltage_mv as u32 * self.current_ma as u32 / 1_000_000
+    }
+
+    /// Parse a PD log entry, None if it's not about charging
+    pub fn from_pd_log(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 || data[4] != PD_EVENT_MCU_CHARGE {
+            return None;
+        }
+        let timestamp = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as u64;
+        let size_port = data[5];
+        let flags = u16::from_le_bytes([data[6], data[7]]);
+        let payload = &data[8..];
+        // Payload is struct usb_chg_measures
+        if ((size_port & 0x1F) as usize) < 8 || payload.len() < 8 {
+            return None;
+        }
+        let role = flags & 0x07;
+        Some(AcHistoryEntry {
+            timestamp_ms: (timestamp << PD_LOG_TIMESTAMP_SHIFT) / 1000,
+            port: size_port >> 5,
+            attached: role == UsbPowerRoles::Sink as u16
+                || role == UsbPowerRoles::SinkNotCharging as u16,
+            voltage_mv: u16::from_le_bytes([payload[0], payload[1]]),
+            current_ma: u16::from_le_bytes([payload[4], payload[5]]),
+        })
+    }
+}
+
+/// Read attach and detach events of chargers from the PD event log
+///
+/// Reading removes the entries from the log in the EC, so they can only be read once.
+/// Only changes of the attached state are returned, oldest first.
+pub fn get_ac_history(ec: &CrosEc) -> EcResult<Vec<AcHistoryEntry>> {
+    let mut history: Vec<AcHistoryEntry> = vec![];
+    for _ in 0..PD_LOG_MAX_ENTRIES {
+        let data = EcRequestPdGetLogEntry {}
+            .send_command_vec(ec)
+            .context("Failed to read PD log")?;
+        if data.len() < 8 || data[4] == PD_EVENT_NO_ENTRY {
+            break;
+        }
+        let Some(entry) = AcHistoryEntry::from_pd_log(&data) else {
+            continue;
+        };
+        let changed = history
+            .iter()
+            .rev()
+            .find(|x| x.port == entry.port)
+            .map(|x| x.attached != entry.attached)
+            .unwrap_or(true);
+        if changed {
+            history.push(entry);
+        }
+    }
+    Ok(history)
+}
+
+/// Print the last `count` attach and detach events of chargers
+pub fn print_ac_history(ec: &CrosEc, count: usize, out: &mut dyn OutputSink) -> i32 {
+    let history = match get_ac_history(ec) {
+        Ok(history) => history,
+        Err(err) => {
+            print_err_ref::<()>(&Err(err));
+            outln!(
+                out,
+                "The EC firmware doesn't keep a history. Use --watch-events to record AC changes."
+            );
+            return 1;
+        }
+    };
+    if history.is_empty() {
+        outln!(out, "No attach or detach events logged since the last read");
+        return 0;
+    }
+    let uptime_ms = EcRequestGetUptimeInfo {}
+        .send_command(ec)
+        .map(|x| x.time_since_ec_boot_ms as u64)
+        .ok();
+
+    for entry in history.iter().rev().take(count).rev() {
+        let time = if let Some(uptime_ms) = uptime_ms {
+            format!(
+                "{:>8} s ago",
+                uptime_ms.saturating_sub(entry.timestamp_ms) / 1000
+            )
+        } else {
+            format!("{:>8} s after EC boot", entry.timestamp_ms / 1000)
+        };
+        if entry.attached {
+            outln!(
+                out,
+                "  {}  Port {}  Attached  {:>3} W ({} mV, {} mA)",
+                time,
+                entry.port,
+                entry.watts(),
+                entry.voltage_mv,
+                entry.current_ma
+            );
+        } else {
+            outln!(out, "  {}  Port {}  Detached", time, entry.port);
+        }
+    }
+    0
+}
+
+/// Force the power role of a USB-C port
+///
+/// Returns whether the port is now a power source.
+///
+/// How much current a port advertises as a source (1.5A or 3A) can't be
+/// changed. The EC decides that based on the power budget of all ports and
+/// configures the PD controllers itself, there's no host command for it.
+/// The advertised current is shown by `--pdports`, as the maximum current.
+pub fn set_pd_port_role(ec: &CrosEc, port: u8, role: UsbPdControlRole) -> EcResult<bool> {
+    let res = EcRequestUsbPdControl {
+        port,
+        role: role as u8,
+        mux: 0,
+        swap: 0,
+    }
+    .send_command(ec)
+    .context(format!("Failed to set power role of port {}", port))?;
+    Ok(res.role & 0x01 != 0)
+}
+
+/// Limit the voltage that the laptop requests as a sink, or remove the limit
+///
+/// The EC requests a new contract from sources on all ports that are affected.
+/// Also when the limit is removed.
+pub fn set_pd_max_voltage(ec: &CrosEc, millivolts: Option<u16>) -> EcResult<()> {
+    EcRequestExternalPowerLimitV1 {
+        current_lim: EC_POWER_LIMIT_NONE,
+        voltage_lim: millivolts.unwrap_or(EC_POWER_LIMIT_NONE),
+    }
+    .send_command(ec)
+    .context("Failed to set external power limit")
+}
+
+/// Chargers can't regulate the input current below this, in mA
+pub const MIN_CHARGER_INPUT_LIMIT_MA: u32 = 500;
+
+/// Limit how much current the charger draws from the external power source
+///
+/// Useful with weak USB-C sources that brown out when the laptop draws all
+/// that they advertise. The EC sets a new limit whenever a charger is attached.
+pub fn set_charger_input_limit(ec: &CrosEc, milliamps: u32) -> EcResult<()> {
+    if milliamps < MIN_CHARGER_INPUT_LIMIT_MA {
+        return Err(EcError::DeviceError(format!(
+            "Input current limit must be at least {} mA",
+            MIN_CHARGER_INPUT_LIMIT_MA
+        )));
+    }
+    ec.set_charge_state_param(ChargeStateParam::InputCurrentLimit, milliamps)
+        .context("Failed to set charger input current limit")
+}
+
+/// Format the value of a charger parameter in its unit
+pub fn fmt_charge_param(param: ChargeStateParam, value: u32) -> String {
+    match param {
+        ChargeStateParam::ChargerVoltage => format!("{} mV", value),
+        ChargeStateParam::ChargerCurrent | ChargeStateParam::InputCurrentLimit => {
+            format!("{} mA", value)
+        }
+        ChargeStateParam::ChargerStatus | ChargeStateParam::ChargerOption => {
+            format!("0x{:04X}", value)
+        }
+        ChargeStateParam::LimitPower => String::from(if value != 0 { "yes" } else { "no" }),
+    }
+}
+
+/// Parse a value for a charger parameter, registers can be given in hex with `0x`
+pub fn parse_charge_param(param: ChargeStateParam, value: &str) -> Result<u32, String> {
+    if !param.writable() {
+        return Err(format!("{} is read-only", param.name()));
+    }
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) if param == ChargeStateParam::ChargerOption => u32::from_str_radix(hex, 16),
+        _ => value.parse::<u32>(),
+    };
+    parsed.map_err(|_| format!("Invalid value for {}: '{}'", param.name(), value))
+}
+
+/// Voltages that --pps-test limits the charger to, one after the other, in mV
+pub const CHARGER_TEST_VOLTAGES: [u16; 6] = [5000, 9000, 12000, 15000, 18000, 20000];
+/// How long to wait for the new contract to be negotiated and the voltage to settle
+const CHARGER_TEST_SETTLE_MS: u64 = 3000;
+/// How much the measured voltage may deviate from the negotiated one, in percent
+const CHARGER_TEST_TOLERANCE_PERCENT: u32 = 5;
+
+/// What a charger did, when limited to a certain voltage
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargerTestStep {
+    /// Voltage limit, that the EC may request at most
+    pub limit_mv: u16,
+    /// Voltage of the contract that the charger agreed to
+    pub negotiated_mv: u16,
+    /// Voltage that the charger IC measures on the port
+    pub measured_mv: u16,
+    /// Current that the charger allows at the negotiated voltage
+    pub current_max_ma: u16,
+    /// Whether the port was still charging
+    pub charging: bool,
+}
+
+impl ChargerTestStep {
+    /// Whether the charger provides what it agreed to
+    ///
+    /// Chargers that don't offer a voltage fall back to a lower one, which is fine.
+    pub fn is_ok(&self) -> bool {
+        let tolerance = self.negotiated_mv as u32 * CHARGER_TEST_TOLERANCE_PERCENT / 100;
+        self.charging
+            && self.negotiated_mv <= self.limit_mv
+            && (self.measured_mv as u32).abs_diff(self.negotiated_mv as u32) <= tolerance
+    }
+}
+
+/// Step through different voltage limits and check what the charger on a port does
+///
+/// The EC requests the best fixed or programmable (PPS) supply within the limit.
+/// The limit is removed afterwards, also when something fails or `cancel` is triggered.
+pub fn test_charger(ec: &CrosEc, port: u8, cancel: &CancelToken) -> EcResult<Vec<ChargerTestStep>> {
+    let info = check_ac(ec, port)?;
+    if info.role != UsbPowerRoles::Sink {
+        return Err(EcError::DeviceError(format!(
+            "Port {} is not charging from a charger, but: {:?}",
+            port, info.role
+        )));
+    }
+
+    let mut steps = vec![];
+    let mut res = Ok(());
+    for limit_mv in CHARGER_TEST_VOLTAGES {
+        debug!("Limiting charger to {} mV", limit_mv);
+        res = set_pd_max_voltage(ec, Some(limit_mv));
+        if res.is_err() {
+            break;
+        }
+        // Wait in small steps, to stop soon after being cancelled
+        for _ in 0..CHARGER_TEST_SETTLE_MS / 100 {
+            if cancel.is_cancelled() {
+                break;
             }
+            os_specific::sleep(100_000);
+        }
+        res = cancel.check();
+        if res.is_err() {
+            break;
+        }
+        match check_ac(ec, port) {
+            Ok(info) => steps.push(ChargerTestStep {
+                limit_mv,
+                negotiated_mv: info.meas.voltage_max,
+                measured_mv: info.meas.voltage_now,
+                current_max_ma: info.meas.current_max,
+                charging: info.role == UsbPowerRoles::Sink,
+            }),
+            Err(err) => {
+                res = Err(err);
+                break;
+            }
+        }
+    }
+
+    // Always go back to normal, before reporting any error.
+    // The first error is the interesting one, if both failed.
+    let reset = set_pd_max_voltage(ec, None);
+    if let (Err(_), Err(err)) = (&res, &reset) {
+        error!("Failed to remove the voltage limit: {:?}", err);
+    }
+    res?;
+    reset?;
+    Ok(steps)
+}
+
+pub fn print_charger_test(
+    ec: &CrosEc,
+    port: u8,
+    cancel: &CancelToken,
+    out: &mut dyn OutputSink,
+) -> EcResult<bool> {
+    outln!(
+        out,
+        "Testing charger on port {}, this takes about {}s",
+        port,
+        CHARGER_TEST_VOLTAGES.len() as u64 * CHARGER_TEST_SETTLE_MS / 1000
+    );
+    let steps = test_charger(ec, port, cancel)?;
+
+    outln!(
+        out,
+        "  {:>8} {:>11} {:>9} {:>9} {:>6}",
+        "Limit",
+        "Negotiated",
+        "Measured",
+        "Current",
+        "Result"
+    );
+    for step in &steps {
+        outln!(
+            out,
+            "  {:>6}mV {:>9}mV {:>7}mV {:>7}mA {:>6}",
+            step.limit_mv,
+            step.negotiated_mv,
+            step.measured_mv,
+            step.current_max_ma,
+            if step.is_ok() { "OK" } else { "FAIL" }
         );
+    }
+    Ok(steps.iter().all(ChargerTestStep::is_ok))
+}
+
+pub fn get_and_print_pd_info(ec: &CrosEc, out: &mut dyn OutputSink) {
+    let port_names = util::platform_spec().pd_ports;
+    let infos = get_pd_info(ec, port_names.len() as u8);
+    for (port, (info, name)) in infos.iter().zip(port_names).enumerate() {
+        outln!(out, "USB-C Port {} ({}):", port, name);
         print_err_ref(info);
 
         // TODO: I haven't checked the encoding/endianness of these numbers. They're likely incorrectly decoded
         if let Ok(info) = info {
-            println!("  Role:          {:?}", info.role);
+            outln!(out, "  Role:          {:?}", info.role);
 
-            println!("  Charging Type: {:?}", info.charging_type);
+            outln!(out, "  Charging Type: {:?}", info.charging_type);
 
             let volt_max = { info.meas.voltage_max };
             let volt_now = { info.meas.voltage_now };
-            println!(
-                "  Voltage Now:   {}.{} V, Max: {}.{} V",
-                volt_now / 1000,
-                volt_now % 1000,
-                volt_max / 1000,
-                volt_max % 1000,
+            outln!(
+                out,
+                "  Voltage Now:   {}, Max: {}",
+                units::fmt_voltage(volt_now as u32),
+                units::fmt_voltage(volt_max as u32),
             );
 
             let cur_lim = { info.meas.current_lim };
             let cur_max = { info.meas.current_max };
-            println!("  Current Lim:   {} mA, Max: {} mA", cur_lim, cur_max);
-            println!(
+            outln!(out, "  Current Lim:   {} mA, Max: {} mA", cur_lim, cur_max);
+            outln!(
+                out,
                 "  Dual Role:     {}",
                 if info.dualrole { "DRP" } else { "Charger" }
             );
             let max_power_mw = { info.max_power } / 1000;
-            println!(
-                "  Max Power:     {}.{} W",
-                max_power_mw / 1000,
-                max_power_mw % 1000
-            );
+            outln!(out, "  Max Power:     {}", units::fmt_power(max_power_mw));
         } else {
-            println!("  Role:          Unknown");
-            println!("  Charging Type: Unknown");
+            outln!(out, "  Role:          Unknown");
+            outln!(out, "  Charging Type: Unknown");
 
-            println!("  Voltage Max:   Unknown, Now: Unknown");
-            println!("  Current Max:   Unknown, Lim: Unknown");
-            println!("  Dual Role:     Unknown");
-            println!("  Max Power:     Unknown");
+            outln!(out, "  Voltage Max:   Unknown, Now: Unknown");
+            outln!(out, "  Current Max:   Unknown, Lim: Unknown");
+            outln!(out, "  Dual Role:     Unknown");
+            outln!(out, "  Max Power:     Unknown");
         }
     }
 }
@@ -593,7 +1366,9 @@ fn parse_pd_ver(data: &[u8; 8]) -> ControllerVersion {
 // NOTE: Only works on ADL at the moment!
 // TODO: Not on TGL, need to check if RPL and later have it.
 pub fn read_pd_version(ec: &CrosEc) -> EcResult<MainPdVersions> {
-    let info = EcRequestReadPdVersion {}.send_command(ec)?;
+    let info = EcRequestReadPdVersion {}
+        .send_command(ec)
+        .context("Failed to read PD versions from EC")?;
 
     Ok(MainPdVersions {
         controller01: parse_pd_ver(&info.controller01),
@@ -612,3 +1387,452 @@ pub fn standalone_mode(ec: &CrosEc) -> bool {
         true
     }
 }
+
+/// Columns of the CSV file written by `watch_power`
+#[cfg(not(feature = "uefi"))]
+const POWER_CSV_HEADER: &str = "time_ms,ac,battery_mv,battery_rate_ma,battery_percent,\
+charger_mv,charger_ma,charger_input_ma,\
+port0_role,port0_mv,port0_ma,port0_max_mw,\
+port1_role,port1_mv,port1_ma,port1_max_mw,\
+port2_role,port2_mv,port2_ma,port2_max_mw,\
+port3_role,port3_mv,port3_ma,port3_max_mw";
+
+/// Continuously sample power telemetry until the process is killed
+///
+/// Every `interval_ms` battery, charger and PD port information is read and
+/// printed as a single line. If `csv_path` is given, each sample is also
+/// appended to that file as a CSV row.
+#[cfg(not(feature = "uefi"))]
+pub fn watch_power(ec: &CrosEc, interval_ms: u64, csv_path: Option<&str>) -> i32 {
+    use std::io::Write;
+    use std::time::Instant;
+
+    let mut csv = if let Some(path) = csv_path {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path);
+        match file {
+            Ok(mut file) => {
+                // Only write the header when starting a new file
+                let empty = file.metadata().map(|m| m.len() == 0).unwrap_or(false);
+                if empty {
+                    if let Err(err) = writeln!(file, "{}", POWER_CSV_HEADER) {
+                        error!("Failed to write to {}: {:?}", path, err);
+                        return 1;
+                    }
+                }
+                Some(file)
+            }
+            Err(err) => {
+                error!("Failed to open {}: {:?}", path, err);
+                return 1;
+            }
+        }
+    } else {
+        None
+    };
+
+    let start = Instant::now();
+    loop {
+        let time_ms = start.elapsed().as_millis();
+        let power = power_info(ec);
+        let charger = ec.get_charge_state().ok();
+        let ports = get_pd_info(ec, 4);
+
+        let mut row: Vec<String> = vec![time_ms.to_string()];
+        let mut line = format!("{:>8} ms", time_ms);
+
+        match &power {
+            Some(info) => {
+                row.push((info.ac_present as u8).to_string());
+                line.push_str(if info.ac_present { " AC" } else { " DC" });
+            }
+            None => row.push(String::new()),
+        }
+        match power.as_ref().and_then(|info| info.battery.as_ref()) {
+            Some(battery) => {
+                row.push(battery.present_voltage.to_string());
+                row.push(battery.present_rate.to_string());
+                row.push(battery.charge_percentage.to_string());
+                line.push_str(&format!(
+                    " | Battery {} mV {} mA {}%",
+                    battery.present_voltage, battery.present_rate, battery.charge_percentage
+                ));
+            }
+            None => row.extend([String::new(), String::new(), String::new()]),
+        }
+        match charger {
+            Some(charger) => {
+                let (mv, ma, input_ma) = (
+                    charger.chg_voltage,
+                    charger.chg_current,
+                    charger.chg_input_current,
+                );
+                row.push(mv.to_string());
+                row.push(ma.to_string());
+                row.push(input_ma.to_string());
+                line.push_str(&format!(
+                    " | Charger {} mV {} mA (input {} mA)",
+                    mv, ma, input_ma
+                ));
+            }
+            None => row.extend([String::new(), String::new(), String::new()]),
+        }
+        for (port, info) in ports.iter().enumerate() {
+            match info {
+                Ok(info) => {
+                    let mv = info.meas.voltage_now;
+                    let ma = info.meas.current_lim;
+                    let max_mw = info.max_power / 1000;
+                    row.push(format!("{:?}", info.role));
+                    row.push(mv.to_string());
+                    row.push(ma.to_string());
+                    row.push(max_mw.to_string());
+                    if info.role != UsbPowerRoles::Disconnected {
+                        line.push_str(&format!(
+                            " | Port {} {:?} {} mV {} mA",
+                            port, info.role, mv, ma
+                        ));
+                    }
+                }
+                Err(_) => row.extend([String::new(), String::new(), String::new(), String::new()]),
+            }
+        }
+
+        println!("{}", line);
+        if let Some(file) = &mut csv {
+            if let Err(err) = writeln!(file, "{}", row.join(",")) {
+                error!("Failed to write CSV row: {:?}", err);
+                return 1;
+            }
+        }
+
+        os_specific::sleep(interval_ms * 1000);
+    }
+}
+
+/// CSV header of `thermal_log`, with a column for each temperature sensor and fan
+#[cfg(not(feature = "uefi"))]
+fn thermal_csv_header(sensors: &[&str], fans: usize) -> String {
+    let mut columns = vec![String::from("timestamp"), String::from("time_ms")];
+    for name in sensors {
+        columns.push(format!("{}_c", name.to_lowercase().replace(' ', "_")));
+    }
+    for fan in 0..fans {
+        columns.push(format!("fan{}_rpm", fan));
+        columns.push(format!("fan{}_duty", fan));
+    }
+    columns.join(",")
+}
+
+/// Log all temperatures, fan speeds and duty cycles until the process is killed
+///
+/// Every `interval_ms` a CSV row is written, to `csv_path` if given, otherwise
+/// to stdout. Temperatures are in Celsius, duty cycles in percent. Sensors
+/// that can't be read are left empty.
+#[cfg(not(feature = "uefi"))]
+pub fn thermal_log(ec: &CrosEc, interval_ms: u64, csv_path: Option<&str>) -> i32 {
+    use std::io::Write;
+    use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+    let sensors = temp_sensor_names(smbios::get_platform());
+    let Some(fans) = get_fan_status(ec).map(|fans| fans.len()) else {
+        return 1;
+    };
+
+    let mut out: Box<dyn Write> = if let Some(path) = csv_path {
+        match std::fs::File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                error!("Failed to open {}: {:?}", path, err);
+                return 1;
+            }
+        }
+    } else {
+        Box::new(std::io::stdout())
+    };
+    if let Err(err) = writeln!(out, "{}", thermal_csv_header(sensors, fans)) {
+        error!("Failed to write CSV header: {:?}", err);
+        return 1;
+    }
+
+    let start = Instant::now();
+    loop {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis())
+            .unwrap_or(0);
+        let time_ms = start.elapsed().as_millis();
+        let memmap = ec.memmap().ok();
+
+        let mut row: Vec<String> = vec![
+            format!("{}.{:03}", timestamp / 1000, timestamp % 1000),
+            time_ms.to_string(),
+        ];
+        for sensor in 0..sensors.len() {
+            match memmap
+                .as_ref()
+                .map(|m| TempSensor::from(m.thermal.temps[sensor]))
+            {
+                Some(TempSensor::Ok(temp)) => row.push(temp.to_string()),
+                _ => row.push(String::new()),
+            }
+        }
+        let fan_speeds = memmap.as_ref().map(fan_status).unwrap_or_default();
+        for fan in 0..fans {
+            match fan_speeds.get(fan) {
+                Some(status) => row.push(status.rpm().to_string()),
+                None => row.push(String::new()),
+            }
+            match ec.get_fan_duty(fan as u8) {
+                Ok(duty) => row.push(duty.to_string()),
+                Err(_) => row.push(String::new()),
+            }
+        }
+
+        if let Err(err) = writeln!(out, "{}", row.join(",")).and_then(|_| out.flush()) {
+            error!("Failed to write CSV row: {:?}", err);
+            return 1;
+        }
+        if csv_path.is_some() {
+            println!("{:>8} ms {}", time_ms, row[2..].join(" "));
+        }
+
+        os_specific::sleep(interval_ms * 1000);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn charge_params() {
+        let param = ChargeStateParam::from_name("option").unwrap();
+        assert_eq!(parse_charge_param(param, "0x8104"), Ok(0x8104));
+        assert_eq!(fmt_charge_param(param, 0x8104), "0x8104");
+        let param = ChargeStateParam::from_name("charger_voltage").unwrap();
+        assert_eq!(parse_charge_param(param, "17600"), Ok(17600));
+        assert!(parse_charge_param(param, "0x10").is_err());
+        assert_eq!(fmt_charge_param(param, 17600), "17600 mV");
+        assert!(parse_charge_param(ChargeStateParam::ChargerStatus, "0").is_err());
+        assert_eq!(ChargeStateParam::from_name("otg"), None);
+    }
+
+    #[test]
+    fn memmap_of_fixtures() {
+        use crate::chromium_ec::trace::with_replay;
+        use framework_test_support::fixture;
+
+        // Number of fans, battery design capacity and model
+        let expected = [
+            ("framework13-intel-11th-gen", 1, 3572, "FRANGWAT"),
+            ("framework13-intel-12th-gen", 1, 3572, "FRANGWAT"),
+            ("framework13-intel-13th-gen", 1, 3915, "FRANGWAT"),
+            ("framework13-intel-core-ultra-1", 1, 3915, "FRANGWAT"),
+            ("framework13-amd-7040", 1, 3915, "FRANGWAT"),
+            ("framework16-amd-7040", 2, 5491, "FRANBBAT"),
+        ];
+        for (name, fans, design_capacity, model) in expected {
+            let fixture = fixture(name);
+            let memmap = with_replay(&fixture.ec_trace(), |ec| ec.memmap()).unwrap();
+            assert!(memmap.lid_open(), "{}", name);
+            assert_eq!(fan_status(&memmap).len(), fans, "{}", name);
+
+            // Sensors are listed without gaps and each of them has a name. Not
+            // every platform has a list of names, so there can be fewer sensors.
+            // Some, like the dGPU ones, are only powered while in use.
+            let platform = smbios::platform_from_product_name(fixture.product_name);
+            let temps: Vec<TempSensor> = memmap.thermal.temps.map(TempSensor::from).into();
+            let present = temps
+                .iter()
+                .take_while(|t| !matches!(t, TempSensor::NotPresent))
+                .count();
+            assert!(present > 0, "{}", name);
+            assert!(present <= temp_sensor_names(platform).len(), "{}", name);
+            for temp in &temps[..present] {
+                assert!(
+                    matches!(temp, TempSensor::Ok(_) | TempSensor::NotPowered),
+                    "{}: {:?}",
+                    name,
+                    temp
+                );
+            }
+            assert!(temps[present..]
+                .iter()
+                .all(|t| matches!(t, TempSensor::NotPresent)));
+
+            let info = power_info_from(memmap);
+            assert!(info.ac_present);
+            let battery = info.battery.unwrap();
+            assert!(battery.charging && !battery.discharging);
+            assert_eq!(battery.design_capacity, design_capacity);
+            assert_eq!(battery.model_number, model);
+        }
+    }
+
+    #[test]
+    fn power_info_without_battery() {
+        use crate::output::StringSink;
+
+        let info = PowerInfo {
+            ac_present: true,
+            battery: None,
+        };
+        let mut out = StringSink::default();
+        print_battery_information(&info, Some(180_000), false, &mut out);
+        assert_eq!(
+            out.buffer,
+            format!(
+                "  AC is:            connected ({})\n  No battery\n",
+                units::fmt_power(180_000)
+            )
+        );
+
+        let mut out = StringSink::default();
+        print_battery_information(&info, None, true, &mut out);
+        assert_eq!(
+            out.buffer,
+            "  AC is:            connected\n  Battery is:       not connected\n"
+        );
+    }
+
+    #[test]
+    fn thermal_log_header() {
+        assert_eq!(
+            thermal_csv_header(temp_sensor_names(Some(Platform::Framework13Amd)), 1),
+            "timestamp,time_ms,f75303_local_c,f75303_cpu_c,f75303_ddr_c,apu_c,fan0_rpm,fan0_duty"
+        );
+        assert_eq!(
+            thermal_csv_header(temp_sensor_names(None), 0),
+            "timestamp,time_ms,temp_0_c,temp_1_c,temp_2_c,temp_3_c,temp_4_c,temp_5_c,temp_6_c,temp_7_c"
+        );
+    }
+
+    #[test]
+    fn decode_pdos() {
+        // 20V 5A, with the dual role and USB communication bits set
+        let fixed = Pdo::from_raw(0x2600_0000 | 400 << 10 | 500);
+        assert_eq!(
+            fixed,
+            Pdo::Fixed {
+                voltage_mv: 20_000,
+                current_ma: 5_000
+            }
+        );
+        assert_eq!(fixed.to_string(), "Fixed     20.0 V, 5.00 A");
+
+        let pps = Pdo::from_raw(0xC000_0000 | 210 << 17 | 33 << 8 | 100);
+        assert_eq!(
+            pps,
+            Pdo::Pps {
+                min_mv: 3_300,
+                max_mv: 21_000,
+                current_ma: 5_000
+            }
+        );
+        assert_eq!(pps.to_string(), "PPS       3.3 V - 21.0 V, 5.00 A");
+
+        // EPR adjustable voltage supply isn't decoded
+        assert_eq!(Pdo::from_raw(0xD000_0000), Pdo::Unknown(0xD000_0000));
+    }
+
+    #[test]
+    fn battery_power_and_time() {
+        let mut battery = BatteryInformation {
+            present_voltage: 15_400,
+            present_rate: 1_000,
+            remaining_capacity: 2_500,
+            battery_count: 0,
+            current_battery_index: 0,
+            design_capacity: 3_915,
+            design_voltage: 15_480,
+            last_full_charge_capacity: 3_500,
+            cycle_count: 10,
+            charge_percentage: 71,
+            manufacturer: String::new(),
+            model_number: String::new(),
+            serial_number: String::new(),
+            battery_type: String::new(),
+            discharging: true,
+            charging: false,
+            level_critical: false,
+        };
+        assert_eq!(battery.power_mw(), 15_400);
+        assert_eq!(battery.time_to_empty_min(), Some(150));
+        assert_eq!(battery.time_to_full_min(), None);
+        assert_eq!(format_minutes(150), "2h 30min");
+
+        battery.discharging = false;
+        battery.charging = true;
+        assert_eq!(battery.time_to_empty_min(), None);
+        assert_eq!(battery.time_to_full_min(), Some(60));
+
+        battery.present_rate = 0;
+        assert_eq!(battery.time_to_full_min(), None);
+    }
+
+    #[test]
+    fn parse_pd_log_charge_entry() {
+        // Timestamp, type, port 1 with 8 byte payload, sink role
+        let mut data = vec![0x00, 0x04, 0x00, 0x00];
+        data.extend_from_slice(&[PD_EVENT_MCU_CHARGE, (1 << 5) | 8, 0x02, 0x00]);
+        // Voltage max/now: 20V, current max/limit: 3.25A
+        data.extend_from_slice(&[0x20, 0x4E, 0x10, 0x4E, 0xB2, 0x0C, 0xB2, 0x0C]);
+        let entry = AcHistoryEntry::from_pd_log(&data).unwrap();
+        assert_eq!(
+            entry,
+            AcHistoryEntry {
+                timestamp_ms: 1048,
+                port: 1,
+                attached: true,
+                voltage_mv: 20000,
+                current_ma: 3250,
+            }
+        );
+        assert_eq!(entry.watts(), 65);
+
+        let mut detached = data.clone();
+        detached[6] = 0x00;
+        assert!(!AcHistoryEntry::from_pd_log(&detached).unwrap().attached);
+
+        let mut other = data;
+        other[4] = 0x02;
+        assert_eq!(AcHistoryEntry::from_pd_log(&other), None);
+    }
+
+    #[test]
+    fn charger_test_step_result() {
+        let step = ChargerTestStep {
+            limit_mv: 15000,
+            negotiated_mv: 15000,
+            measured_mv: 14700,
+            current_max_ma: 3000,
+            charging: true,
+        };
+        assert!(step.is_ok());
+
+        // No 12V supply, falling back to 9V is fine
+        let fallback = ChargerTestStep {
+            limit_mv: 12000,
+            negotiated_mv: 9000,
+            measured_mv: 9100,
+            ..step.clone()
+        };
+        assert!(fallback.is_ok());
+
+        // Voltage sags way below what was agreed to
+        let sagging = ChargerTestStep {
+            measured_mv: 13500,
+            ..step.clone()
+        };
+        assert!(!sagging.is_ok());
+
+        let stopped = ChargerTestStep {
+            charging: false,
+            ..step
+        };
+        assert!(!stopped.is_ok());
+    }
+}
@@ -0,0 +1,1218 @@
+//! Information about the system that the commandline tool shows
+//!
+//! Reading from the system and printing are kept separate, so that the output
+//! can be tested with data from real machines and other frontends can get the
+//! same information without parsing text.
+//! Each report has a `read` function that gathers the data and a `render`
+//! function that writes it to an `OutputSink` exactly like the tool prints it.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+#[cfg(not(feature = "uefi"))]
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+use num_traits::FromPrimitive;
+
+#[cfg(not(feature = "uefi"))]
+use crate::audit::json_string;
+use crate::ccgx::device::{FwMode, PdController, PdPort};
+use crate::ccgx::{self, ControllerFirmwares};
+use crate::chromium_ec::commands::EcFeatureCode;
+use crate::chromium_ec::{print_err, CrosEc, CrosEcDriverType, EcCurrentImage};
+use crate::ec_binary;
+use crate::output::{outln, OutputSink};
+use crate::power;
+use crate::smbios::{self, decode, BiosInfo, ConfigDigit0, SmbiosInfo};
+use crate::util::{self, Platform};
+
+/// BIOS section of `--versions`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiosVersionReport {
+    /// None if SMBIOS couldn't be read
+    pub bios: Option<BiosInfo>,
+}
+
+impl BiosVersionReport {
+    pub fn read() -> Self {
+        BiosVersionReport {
+            bios: smbios::get_smbios_info().and_then(|info| info.bios),
+        }
+    }
+
+    pub fn render(&self, out: &mut dyn OutputSink) {
+        outln!(out, "UEFI BIOS");
+        if let Some(bios) = &self.bios {
+            outln!(out, "  Version:        {}", or_unknown(&bios.version));
+            outln!(out, "  Release Date:   {}", or_unknown(&bios.release_date));
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    pub fn to_json(&self) -> String {
+        let bios = self.bios.as_ref();
+        format!(
+            "{{\"version\": {}, \"release_date\": {}}}",
+            json_option(bios.and_then(|bios| bios.version.as_deref())),
+            json_option(bios.and_then(|bios| bios.release_date.as_deref())),
+        )
+    }
+}
+
+/// EC section of `--versions`
+#[derive(Debug, PartialEq)]
+pub struct EcVersionReport {
+    /// Build info of the running image, example: hx30_v0.0.1-7a61a89 2022-06-02 15:27:58 dhowett@host
+    pub build_info: Option<String>,
+    pub driver: CrosEcDriverType,
+    pub driver_version: Option<String>,
+    /// RO version, RW version and which of them is running
+    pub flash_version: Option<(String, String, EcCurrentImage)>,
+    /// Rollback version of RW and the minimum that RO accepts
+    ///
+    /// Only read in verbose mode and if RO verifies RW, None inside if that failed.
+    pub rollback: Option<Option<(i32, i32)>>,
+}
+
+impl EcVersionReport {
+    /// Some information is only read with `verbose`, because it takes extra EC commands
+    pub fn read(ec: &CrosEc, verbose: bool) -> Self {
+        // Only relevant if RO verifies RW before jumping to it
+        let rollback = if verbose && matches!(ec.has_feature(EcFeatureCode::RwSig), Ok(true)) {
+            Some(ec.get_rollback_info().ok())
+        } else {
+            None
+        };
+        EcVersionReport {
+            build_info: print_err(ec.version_info()),
+            driver: ec.driver(),
+            driver_version: ec.driver_version(),
+            flash_version: ec.flash_version(),
+            rollback,
+        }
+    }
+
+    pub fn render(&self, out: &mut dyn OutputSink, verbose: bool) {
+        outln!(out, "EC Firmware");
+        let ver = self.build_info.as_deref().unwrap_or("UNKNOWN");
+        outln!(out, "  Build version:  {:?}", ver);
+        if verbose {
+            if let Some(driver_version) = &self.driver_version {
+                outln!(
+                    out,
+                    "  Driver:         {:?} {}",
+                    self.driver,
+                    driver_version
+                );
+            } else {
+                outln!(out, "  Driver:         {:?}", self.driver);
+            }
+            let info = ec_binary::parse_ec_build_info(ver);
+            if let Some(details) = &info.details {
+                outln!(out, "  Board:          {}", details.platform);
+                outln!(
+                    out,
+                    "  Version:        {}.{}.{}",
+                    details.major,
+                    details.minor,
+                    details.patch
+                );
+                outln!(out, "  Commit:         {}", details.commit);
+            }
+            if let Some(build_date) = &info.build_date {
+                outln!(out, "  Build date:     {}", build_date);
+            }
+            if let Some(builder) = &info.builder {
+                outln!(out, "  Builder:        {}", builder);
+            }
+        }
+
+        if let Some((ro, rw, curr)) = &self.flash_version {
+            outln!(out, "  RO Version:     {:?}", ro);
+            outln!(out, "  RW Version:     {:?}", rw);
+            outln!(out, "  Current image:  {}", image_name(curr));
+        } else {
+            outln!(out, "  RO Version:     Unknown");
+            outln!(out, "  RW Version:     Unknown");
+            outln!(out, "  Current image:  Unknown");
+        }
+
+        match self.rollback {
+            Some(Some((rw, min))) => outln!(out, "  RW Rollback:    {} (minimum {})", rw, min),
+            Some(None) => outln!(out, "  RW Rollback:    Unknown"),
+            None => {}
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    pub fn to_json(&self) -> String {
+        let flash = self.flash_version.as_ref();
+        let rollback = match self.rollback {
+            Some(Some((rw, min))) => format!("{{\"version\": {}, \"minimum\": {}}}", rw, min),
+            _ => "null".to_string(),
+        };
+        format!(
+            "{{\"build_info\": {}, \"driver\": {}, \"driver_version\": {}, \"ro_version\": {}, \"rw_version\": {}, \"current_image\": {}, \"rollback\": {}}}",
+            json_option(self.build_info.as_deref()),
+            json_string(&format!("{:?}", self.driver)),
+            json_option(self.driver_version.as_deref()),
+            json_option(flash.map(|(ro, _, _)| ro.as_str())),
+            json_option(flash.map(|(_, rw, _)| rw.as_str())),
+            json_option(flash.map(|(_, _, curr)| image_name(curr))),
+            rollback,
+        )
+    }
+}
+
+fn image_name(image: &EcCurrentImage) -> &'static str {
+    match image {
+        EcCurrentImage::RO => "RO",
+        EcCurrentImage::RW => "RW",
+        EcCurrentImage::Unknown => "Unknown",
+    }
+}
+
+/// Versions of the two firmware images of one PD controller
+#[derive(Debug, PartialEq)]
+pub struct PdControllerVersions {
+    pub main: String,
+    pub backup: String,
+    /// Which of them is running
+    pub active: FwMode,
+}
+
+impl PdControllerVersions {
+    /// Shows the version that the firmware of the platform is known by
+    pub fn new(firmwares: ControllerFirmwares, platform: Option<Platform>) -> Self {
+        // 11th Gen Intel is versioned by the base version, the others by the application version
+        let (main, backup) = if platform == Some(Platform::IntelGen11) {
+            (
+                firmwares.main_fw.base.to_string(),
+                firmwares.backup_fw.base.to_string(),
+            )
+        } else {
+            (
+                firmwares.main_fw.app.to_string(),
+                firmwares.backup_fw.app.to_string(),
+            )
+        };
+        PdControllerVersions {
+            main,
+            backup,
+            active: firmwares.active_fw,
+        }
+    }
+
+    fn render(&self, out: &mut dyn OutputSink) {
+        outln!(
+            out,
+            "    Main:       {}{}",
+            self.main,
+            active_mode(&self.active, FwMode::MainFw)
+        );
+        outln!(
+            out,
+            "    Backup:     {}{}",
+            self.backup,
+            active_mode(&self.active, FwMode::BackupFw)
+        );
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"main\": {}, \"backup\": {}, \"active\": {}}}",
+            json_string(&self.main),
+            json_string(&self.backup),
+            json_string(&format!("{:?}", self.active)),
+        )
+    }
+}
+
+fn active_mode(mode: &FwMode, reference: FwMode) -> &'static str {
+    if mode == &reference {
+        " (Active)"
+    } else {
+        ""
+    }
+}
+
+/// PD section of `--versions`
+#[derive(Debug, PartialEq)]
+pub enum PdVersionsReport {
+    /// Read from the PD controllers
+    Controllers {
+        /// Ports 0 and 1
+        right: PdControllerVersions,
+        /// Ports 2 and 3, None if the platform has no second controller
+        left: Option<PdControllerVersions>,
+    },
+    /// Only the version of the main firmware, as the EC reports it
+    FromEc {
+        right: String,
+        left: Option<String>,
+    },
+    Unknown,
+}
+
+impl PdVersionsReport {
+    pub fn read(ec: &CrosEc) -> Self {
+        let platform = smbios::get_platform();
+        if !util::platform_spec().has_pd23 {
+            return match PdController::new(PdPort::Left01, ec.clone()).get_fw_versions() {
+                Ok(firmwares) => PdVersionsReport::Controllers {
+                    right: PdControllerVersions::new(firmwares, platform),
+                    left: None,
+                },
+                Err(_) => match power::read_pd_version(ec) {
+                    Ok(pd_versions) => PdVersionsReport::FromEc {
+                        right: pd_versions.controller01.app.to_string(),
+                        left: None,
+                    },
+                    Err(_) => PdVersionsReport::Unknown,
+                },
+            };
+        }
+
+        if let Ok(pd_versions) = ccgx::get_pd_controller_versions(ec) {
+            PdVersionsReport::Controllers {
+                right: PdControllerVersions::new(pd_versions.controller01, platform),
+                left: Some(PdControllerVersions::new(
+                    pd_versions.controller23,
+                    platform,
+                )),
+            }
+        } else if let Ok(pd_versions) = power::read_pd_version(ec) {
+            // As fallback try to get it from the EC. But not all EC versions have this command
+            PdVersionsReport::FromEc {
+                right: pd_versions.controller01.app.to_string(),
+                left: Some(pd_versions.controller23.app.to_string()),
+            }
+        } else {
+            PdVersionsReport::Unknown
+        }
+    }
+
+    pub fn render(&self, out: &mut dyn OutputSink) {
+        outln!(out, "PD Controllers");
+        match self {
+            PdVersionsReport::Controllers { right, left } => {
+                if let Some(left) = left {
+                    outln!(out, "  Right (01)");
+                    right.render(out);
+                    outln!(out, "  Left  (23)");
+                    left.render(out);
+                } else {
+                    outln!(out, "  Back  (01)");
+                    right.render(out);
+                }
+            }
+            PdVersionsReport::FromEc { right, left } => {
+                if let Some(left) = left {
+                    outln!(out, "  Right (01):     {}", right);
+                    outln!(out, "  Left  (23):     {}", left);
+                } else {
+                    outln!(out, "  Back  (01):     {}", right);
+                }
+            }
+            PdVersionsReport::Unknown => outln!(out, "  Unknown"),
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    pub fn to_json(&self) -> String {
+        match self {
+            PdVersionsReport::Controllers { right, left } => format!(
+                "{{\"right\": {}, \"left\": {}}}",
+                right.to_json(),
+                left.as_ref()
+                    .map_or_else(|| "null".to_string(), PdControllerVersions::to_json)
+            ),
+            PdVersionsReport::FromEc { right, left } => format!(
+                "{{\"right\": {{\"main\": {}}}, \"left\": {}}}",
+                json_string(right),
+                left.as_ref().map_or_else(
+                    || "null".to_string(),
+                    |left| format!("{{\"main\": {}}}", json_string(left))
+                )
+            ),
+            PdVersionsReport::Unknown => "null".to_string(),
+        }
+    }
+}
+
+/// Details of one PD controller, as shown by `--pd-info`
+#[derive(Debug, PartialEq)]
+pub struct PdDetailsReport {
+    pub silicon_id: Option<u16>,
+    /// Running firmware and flash row size in bytes
+    pub device_info: Option<(FwMode, u16)>,
+    pub firmwares: Option<ControllerFirmwares>,
+}
+
+impl PdDetailsReport {
+    pub fn read(pd: &PdController) -> Self {
+        PdDetailsReport {
+            silicon_id: pd.get_silicon_id().ok(),
+            device_info: pd.get_device_info().ok(),
+            firmwares: pd.get_fw_versions().ok(),
+        }
+    }
+
+    pub fn render(&self, out: &mut dyn OutputSink) {
+        if let Some(si) = self.silicon_id {
+            outln!(out, "  Silicon ID:     0x{:X}", si);
+        } else {
+            outln!(out, "  Failed to read Silicon ID/Family");
+        }
+        if let Some((mode, frs)) = &self.device_info {
+            outln!(out, "  Mode:           {:?}", mode);
+            outln!(out, "  Flash Row Size: {} B", frs);
+        } else {
+            outln!(out, "  Failed to device info");
+        }
+        if let Some(fw) = &self.firmwares {
+            outln!(
+                out,
+                "  Bootloader Version:   Base: {},  App: {}",
+                fw.bootloader.base,
+                fw.bootloader.app
+            );
+            outln!(
+                out,
+                "  FW1 (Backup) Version: Base: {},  App: {}",
+                fw.backup_fw.base,
+                fw.backup_fw.app
+            );
+            outln!(
+                out,
+                "  FW2 (Main)   Version: Base: {},  App: {}",
+                fw.main_fw.base,
+                fw.main_fw.app
+            );
+        } else {
+            outln!(out, "  Failed to get PD Info");
+        }
+    }
+
+    #[cfg(not(feature = "uefi"))]
+    pub fn to_json(&self) -> String {
+        let silicon_id = match self.silicon_id {
+            Some(si) => json_string(&format!("0x{:X}", si)),
+            None => "null".to_string(),
+        };
+        let (mode, row_size) = match &self.device_info {
+            Some((mode, frs)) => (json_string(&format!("{:?}", mode)), frs.to_string()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let firmwares = match &self.firmwares {
+            Some(fw) => format!(
+                "{{\"bootloader\": {}, \"backup\": {}, \"main\": {}}}",
+                controller_version_json(&fw.bootloader),
+                controller_version_json(&fw.backup_fw),
+                controller_version_json(&fw.main_fw),
+            ),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"silicon_id\": {}, \"mode\": {}, \"flash_row_size\": {}, \"firmwares\": {}}}",
+            silicon_id, mode, row_size, firmwares
+        )
+    }
+}
+
+#[cfg(not(feature = "uefi"))]
+fn controller_version_json(version: &ccgx::ControllerVersion) -> String {
+    format!(
+        "{{\"base\": {}, \"app\": {}}}",
+        json_string(&version.base.to_string()),
+        json_string(&version.app.to_string())
+    )
+}
+
+/// Summary of the system from SMBIOS, as shown by `--info`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmbiosReport {
+    pub is_framework: bool,
+    pub platform: Option<Platform>,
+    /// None if SMBIOS couldn't be read
+    pub info: Option<SmbiosInfo>,
+}
+
+impl SmbiosReport {
+    pub fn read() -> Self {
+        SmbiosReport {
+            is_framework: smbios::is_framework(),
+            platform: smbios::get_platform(),
+            info: smbios::get_smbios_info(),
+        }
+    }
+
+    pub fn render(&self, out: &mut dyn OutputSink) {
+        outln!(out, "Summary");
+        outln!(out, "  Is Framework: {}", self.is_framework);
+        if let Some(platform) = self.platform {
+            outln!(out, "  Platform:     {:?}", platform);
+        } else {
+            outln!(out, "  Platform:     Unknown");
+        }
+
+        let Some(info) = &self.info else {
+            error!("Failed to find SMBIOS");
+            return;
+        };
+        if let Some(bios) = &info.bios {
+            outln!(out, "BIOS Information");
+            if let Some(vendor) = &bios.vendor {
+                outln!(out, "  Vendor:       {}", vendor);
+            }
+            if let Some(version) = &bios.version {
+                outln!(out, "  Version:      {}", version);
+            }
+            if let Some(release_date) = &bios.release_date {
+                outln!(out, "  Release Date: {}", release_date);
+            }
+        }
+        if let Some(system) = &info.system {
+            outln!(out, "System Information");
+            if let Some(version) = &system.version {
+                outln!(out, "  Version:      {}", fmt_config_version(version));
+            }
+            if let Some(manufacturer) = &system.manufacturer {
+                outln!(out, "  Manufacturer: {}", manufacturer);
+            }
+            if let Some(product_name) = &system.product_name {
+                outln!(out, "  Product Name: {}", product_name);
+            }
+            if let Some(wakeup_type) = system.wakeup_type {
+                outln!(
+                    out,
+                    "  Wake-Up-Type: {}",
+                    decode::wakeup_type_name(wakeup_type)
+                );
+            }
+            if let Some(sku_number) = &system.sku_number {
+                outln!(out, "  SKU Number:   {}", sku_number);
+            }
+            if let Some(sn) = &system.serial_number {
+                outln!(out, "  Serial Number:{}", util::redact_serial(sn));
+            }
+            if let Some(family) = &system.family {
+                outln!(out, "  Family:       {}", family);
+            }
+        }
+        if let Some(chassis) = &info.chassis {
+            outln!(out, "System Chassis Information");
+            if let Some(chassis_type) = chassis.chassis_type {
+                outln!(
+                    out,
+                    "  Type:         {}",
+                    decode::chassis_type_name(chassis_type)
+                );
+            }
+        }
+        if let Some(baseboard) = &info.baseboard {
+            outln!(out, "BaseBoard Information");
+            if let Some(version) = &baseboard.version {
+                outln!(out, "  Version:      {}", fmt_config_version(version));
+            }
+            if let Some(manufacturer) = &baseboard.manufacturer {
+                outln!(out, "  Manufacturer: {}", manufacturer);
+            }
+            if let Some(product_name) = &baseboard.product {
+                outln!(out, "  Product:      {}", product_name);
+            }
+            if let Some(sn) = &baseboard.serial_number {
+                outln!(out, "  Serial Number:{}", util::redact_serial(sn));
+            }
+        }
+        for memory in &info.memory {
+            outln!(out, "Memory Device");
+            if let Some(locator) = &memory.device_locator {
+                outln!(out, "  Locator:      {}", locator);
+            }
+            match memory.size_mb {
+                Some(0) => outln!(out, "  Size:         No Module Installed"),
+                Some(size_mb) => outln!(out, "  Size:         {} MB", size_mb),
+                None => outln!(out, "  Size:         Unknown"),
+            }
+            if let Some(speed) = memory.speed_mts {
+                outln!(out, "  Speed:        {} MT/s", speed);
+            }
+            if let Some(manufacturer) = &memory.manufacturer {
+                outln!(out, "  Manufacturer: {}", manufacturer);
+            }
+            if let Some(part_number) = &memory.part_number {
+                outln!(out, "  Part Number:  {}", part_number);
+            }
+        }
+    }
+
+    /// Serial numbers are redacted just like in the text output
+    #[cfg(not(feature = "uefi"))]
+    pub fn to_json(&self) -> String {
+        let platform = self.platform.map(|platform| format!("{:?}", platform));
+        let info = self.info.clone().unwrap_or_default();
+        let serial = |sn: &Option<String>| sn.as_deref().map(util::redact_serial);
+
+        let bios = match &info.bios {
+            Some(bios) => format!(
+                "{{\"vendor\": {}, \"version\": {}, \"release_date\": {}}}",
+                json_option(bios.vendor.as_deref()),
+                json_option(bios.version.as_deref()),
+                json_option(bios.release_date.as_deref()),
+            ),
+            None => "null".to_string(),
+        };
+        let system = match &info.system {
+            Some(system) => format!(
+                "{{\"manufacturer\": {}, \"product_name\": {}, \"version\": {}, \"serial_number\": {}, \"wakeup_type\": {}, \"sku_number\": {}, \"family\": {}}}",
+                json_option(system.manufacturer.as_deref()),
+                json_option(system.product_name.as_deref()),
+                json_option(system.version.as_deref()),
+                json_option(serial(&system.serial_number).as_deref()),
+                json_option(system.wakeup_type.map(decode::wakeup_type_name)),
+                json_option(system.sku_number.as_deref()),
+                json_option(system.family.as_deref()),
+            ),
+            None => "null".to_string(),
+        };
+        let chassis = match &info.chassis {
+            Some(chassis) => format!(
+                "{{\"type\": {}}}",
+                json_option(chassis.chassis_type.map(decode::chassis_type_name))
+            ),
+            None => "null".to_string(),
+        };
+        let baseboard = match &info.baseboard {
+            Some(baseboard) => format!(
+                "{{\"manufacturer\": {}, \"product\": {}, \"version\": {}, \"serial_number\": {}}}",
+                json_option(baseboard.manufacturer.as_deref()),
+                json_option(baseboard.product.as_deref()),
+                json_option(baseboard.version.as_deref()),
+                json_option(serial(&baseboard.serial_number).as_deref()),
+            ),
+            None => "null".to_string(),
+        };
+        let memory: Vec<String> = info
+            .memory
+            .iter()
+            .map(|memory| {
+                format!(
+                    "{{\"locator\": {}, \"size_mb\": {}, \"speed_mts\": {}, \"manufacturer\": {}, \"part_number\": {}}}",
+                    json_option(memory.device_locator.as_deref()),
+                    json_number(memory.size_mb),
+                    json_number(memory.speed_mts),
+                    json_option(memory.manufacturer.as_deref()),
+                    json_option(memory.part_number.as_deref()),
+                )
+            })
+            .collect();
+        format!(
+            "{{\"is_framework\": {}, \"platform\": {}, \"bios\": {}, \"system\": {}, \"chassis\": {}, \"baseboard\": {}, \"memory\": [{}]}}",
+            self.is_framework,
+            json_option(platform.as_deref()),
+            bios,
+            system,
+            chassis,
+            baseboard,
+            memory.join(", "),
+        )
+    }
+}
+
+/// Version from SMBIOS, which has the config digit of the build phase first
+pub fn fmt_config_version(version: &str) -> String {
+    // Assumes it's ASCII, which is guaranteed by SMBIOS
+    let config_digit0 = version.get(0..1).unwrap_or("");
+    let config_digit0 = u8::from_str_radix(config_digit0, 16);
+    if let Ok(Some(version_config)) = config_digit0.map(<ConfigDigit0 as FromPrimitive>::from_u8) {
+        format!("{:?} ({})", version_config, version)
+    } else {
+        format!("'{}'", version)
+    }
+}
+
+fn or_unknown(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("Unknown")
+}
+
+#[cfg(not(feature = "uefi"))]
+fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+#[cfg(not(feature = "uefi"))]
+fn json_number(value: Option<u32>) -> String {
+    value.map_or_else(|| "null".to_string(), |x| x.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ccgx::binary::read_versions;
+    use crate::ccgx::{ControllerVersion, SiliconId};
+    use crate::ec_binary::read_ec_version;
+    use crate::output::StringSink;
+    use crate::smbios::{BaseBoardInfo, ChassisInfo, MemoryDevice, SystemInfo};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn read_test_bin(name: &str) -> Vec<u8> {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("test_bins");
+        path.push(name);
+        fs::read(path).unwrap()
+    }
+
+    fn render(f: impl FnOnce(&mut dyn OutputSink)) -> String {
+        let mut sink = StringSink::default();
+        f(&mut sink);
+        sink.buffer
+    }
+
+    fn bios(version: &str, release_date: &str) -> BiosInfo {
+        BiosInfo {
+            vendor: Some("INSYDE Corp.".to_string()),
+            version: Some(version.to_string()),
+            release_date: Some(release_date.to_string()),
+        }
+    }
+
+    fn system(product_name: &str, sku: &str, family: &str) -> SystemInfo {
+        SystemInfo {
+            manufacturer: Some("Framework".to_string()),
+            product_name: Some(product_name.to_string()),
+            version: Some("A6".to_string()),
+            serial_number: Some("FRANBMCPA6123400AB".to_string()),
+            wakeup_type: Some(0x06),
+            sku_number: Some(sku.to_string()),
+            family: Some(family.to_string()),
+        }
+    }
+
+    fn memory(locator: &str, size_mb: u32, speed_mts: u32, part_number: &str) -> MemoryDevice {
+        MemoryDevice {
+            size_mb: Some(size_mb),
+            device_locator: Some(locator.to_string()),
+            bank_locator: Some("BANK 0".to_string()),
+            speed_mts: Some(speed_mts),
+            manufacturer: Some("Crucial Technology".to_string()),
+            serial_number: Some("E7A1B2C3".to_string()),
+            part_number: Some(part_number.to_string()),
+        }
+    }
+
+    /// Framework Laptop 13, 11th Gen Intel Core, with one slot empty
+    fn fl13_tgl() -> SmbiosInfo {
+        SmbiosInfo {
+            bios: Some(bios("03.19", "05/29/2023")),
+            system: Some(system("Laptop", "FRANBMCP0A", "FRANBMCP")),
+            baseboard: Some(BaseBoardInfo {
+                manufacturer: Some("Framework".to_string()),
+                product: Some("FRANBMCP0A".to_string()),
+                version: Some("A6".to_string()),
+                serial_number: Some("FRANBMCPA6123400AB".to_string()),
+            }),
+            chassis: Some(ChassisInfo {
+                manufacturer: Some("Framework".to_string()),
+                chassis_type: Some(0x0A),
+            }),
+            memory: vec![
+                memory(
+                    "Controller0-ChannelA-DIMM0",
+                    16384,
+                    3200,
+                    "CT16G4SFRA32A.M16FR",
+                ),
+                MemoryDevice {
+                    size_mb: Some(0),
+                    device_locator: Some("Controller1-ChannelA-DIMM0".to_string()),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    /// Framework Laptop 16, AMD Ryzen 7040 Series, from an early BIOS that
+    /// didn't have the baseboard version yet
+    fn fl16() -> SmbiosInfo {
+        SmbiosInfo {
+            bios: Some(bios("03.03", "03/27/2024")),
+            system: Some(system(
+                "Laptop 16 (AMD Ryzen 7040 Series)",
+                "FRAGACCP08",
+                "Laptop",
+            )),
+            baseboard: Some(BaseBoardInfo {
+                manufacturer: Some("Framework".to_string()),
+                product: Some("FRANMZCP08".to_string()),
+                version: Some("".to_string()),
+                serial_number: None,
+            }),
+            chassis: None,
+            memory: vec![
+                memory("DIMM 0", 32768, 5600, "CT32G56C46S5.M16B1"),
+                memory("DIMM 1", 32768, 5600, "CT32G56C46S5.M16B1"),
+            ],
+        }
+    }
+
+    fn ec_report(test_bin: &str, build_date: &str) -> EcVersionReport {
+        let version = read_ec_version(&read_test_bin(test_bin), false)
+            .unwrap()
+            .version;
+        EcVersionReport {
+            build_info: Some(format!("{} {} runner@buildhost", version, build_date)),
+            driver: CrosEcDriverType::Portio,
+            driver_version: None,
+            flash_version: Some((version.clone(), version, EcCurrentImage::RW)),
+            rollback: None,
+        }
+    }
+
+    fn pd_firmwares(test_bin: &str, silicon: SiliconId, active_fw: FwMode) -> ControllerFirmwares {
+        let versions = read_versions(&read_test_bin(test_bin), silicon).unwrap();
+        let version = |fw: &ccgx::binary::PdFirmware| ControllerVersion {
+            base: fw.base_version,
+            app: fw.app_version,
+        };
+        ControllerFirmwares {
+            active_fw,
+            bootloader: version(&versions.backup_fw),
+            backup_fw: version(&versions.backup_fw),
+            main_fw: version(&versions.main_fw),
+        }
+    }
+
+    #[test]
+    fn render_smbios_fl13_tgl() {
+        let report = SmbiosReport {
+            is_framework: true,
+            platform: Some(Platform::IntelGen11),
+            info: Some(fl13_tgl()),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "Summary
+  Is Framework: true
+  Platform:     IntelGen11
+BIOS Information
+  Vendor:       INSYDE Corp.
+  Version:      03.19
+  Release Date: 05/29/2023
+System Information
+  Version:      MassProduction (A6)
+  Manufacturer: Framework
+  Product Name: Laptop
+  Wake-Up-Type: Power Switch
+  SKU Number:   FRANBMCP0A
+  Serial Number:FRANBMCPA6123400AB
+  Family:       FRANBMCP
+System Chassis Information
+  Type:         Notebook
+BaseBoard Information
+  Version:      MassProduction (A6)
+  Manufacturer: Framework
+  Product:      FRANBMCP0A
+  Serial Number:FRANBMCPA6123400AB
+Memory Device
+  Locator:      Controller0-ChannelA-DIMM0
+  Size:         16384 MB
+  Speed:        3200 MT/s
+  Manufacturer: Crucial Technology
+  Part Number:  CT16G4SFRA32A.M16FR
+Memory Device
+  Locator:      Controller1-ChannelA-DIMM0
+  Size:         No Module Installed
+"
+        );
+    }
+
+    #[test]
+    fn render_smbios_fl16() {
+        let report = SmbiosReport {
+            is_framework: true,
+            platform: Some(Platform::Framework16),
+            info: Some(fl16()),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "Summary
+  Is Framework: true
+  Platform:     Framework16
+BIOS Information
+  Vendor:       INSYDE Corp.
+  Version:      03.03
+  Release Date: 03/27/2024
+System Information
+  Version:      MassProduction (A6)
+  Manufacturer: Framework
+  Product Name: Laptop 16 (AMD Ryzen 7040 Series)
+  Wake-Up-Type: Power Switch
+  SKU Number:   FRAGACCP08
+  Serial Number:FRANBMCPA6123400AB
+  Family:       Laptop
+BaseBoard Information
+  Version:      ''
+  Manufacturer: Framework
+  Product:      FRANMZCP08
+Memory Device
+  Locator:      DIMM 0
+  Size:         32768 MB
+  Speed:        5600 MT/s
+  Manufacturer: Crucial Technology
+  Part Number:  CT32G56C46S5.M16B1
+Memory Device
+  Locator:      DIMM 1
+  Size:         32768 MB
+  Speed:        5600 MT/s
+  Manufacturer: Crucial Technology
+  Part Number:  CT32G56C46S5.M16B1
+"
+        );
+    }
+
+    #[test]
+    fn render_smbios_missing() {
+        let report = SmbiosReport {
+            is_framework: false,
+            platform: None,
+            info: None,
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "Summary\n  Is Framework: false\n  Platform:     Unknown\n"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"is_framework\": false, \"platform\": null, \"bios\": null, \"system\": null, \
+             \"chassis\": null, \"baseboard\": null, \"memory\": []}"
+        );
+    }
+
+    #[test]
+    fn smbios_json() {
+        let report = SmbiosReport {
+            is_framework: true,
+            platform: Some(Platform::Framework16),
+            info: Some(fl16()),
+        };
+        let json = report.to_json();
+        assert!(json.starts_with(
+            "{\"is_framework\": true, \"platform\": \"Framework16\", \"bios\": {\"vendor\": \"INSYDE Corp.\", \
+             \"version\": \"03.03\", \"release_date\": \"03/27/2024\"}, "
+        ));
+        assert!(json.contains("\"wakeup_type\": \"Power Switch\""));
+        assert!(json.contains("\"chassis\": null"));
+        assert!(json.ends_with(
+            "{\"locator\": \"DIMM 1\", \"size_mb\": 32768, \"speed_mts\": 5600, \
+             \"manufacturer\": \"Crucial Technology\", \"part_number\": \"CT32G56C46S5.M16B1\"}]}"
+        ));
+    }
+
+    #[test]
+    fn render_bios_version() {
+        let report = BiosVersionReport {
+            bios: fl13_tgl().bios,
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "UEFI BIOS\n  Version:        03.19\n  Release Date:   05/29/2023\n"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"version\": \"03.19\", \"release_date\": \"05/29/2023\"}"
+        );
+
+        let report = BiosVersionReport { bios: None };
+        assert_eq!(render(|out| report.render(out)), "UEFI BIOS\n");
+        assert_eq!(
+            report.to_json(),
+            "{\"version\": null, \"release_date\": null}"
+        );
+    }
+
+    #[test]
+    fn render_ec_version_adl() {
+        let report = ec_report("adl-ec-0.0.1.bin", "2022-06-02 15:27:58");
+        assert_eq!(
+            render(|out| report.render(out, false)),
+            "EC Firmware
+  Build version:  \"hx30_v0.0.1-7a61a89 2022-06-02 15:27:58 runner@buildhost\"
+  RO Version:     \"hx30_v0.0.1-7a61a89\"
+  RW Version:     \"hx30_v0.0.1-7a61a89\"
+  Current image:  RW
+"
+        );
+    }
+
+    #[test]
+    fn render_ec_version_amd_verbose() {
+        let mut report = ec_report("amd-fl13-ec-3.05.bin", "2024-03-14 10:01:12");
+        report.driver = CrosEcDriverType::CrosEc;
+        report.driver_version = Some("1.0".to_string());
+        report.rollback = Some(Some((2, 1)));
+        assert_eq!(
+            render(|out| report.render(out, true)),
+            "EC Firmware
+  Build version:  \"azalea_v3.4.113353-ec:b4c1fb,os 2024-03-14 10:01:12 runner@buildhost\"
+  Driver:         CrosEc 1.0
+  Board:          azalea
+  Version:        3.4.113353
+  Commit:         b4c1fb
+  Build date:     2024-03-14 10:01:12
+  Builder:        runner@buildhost
+  RO Version:     \"azalea_v3.4.113353-ec:b4c1fb,os\"
+  RW Version:     \"azalea_v3.4.113353-ec:b4c1fb,os\"
+  Current image:  RW
+  RW Rollback:    2 (minimum 1)
+"
+        );
+    }
+
+    #[test]
+    fn render_ec_version_unknown() {
+        let report = EcVersionReport {
+            build_info: None,
+            driver: CrosEcDriverType::Windows,
+            driver_version: None,
+            flash_version: None,
+            rollback: Some(None),
+        };
+        assert_eq!(
+            render(|out| report.render(out, true)),
+            "EC Firmware
+  Build version:  \"UNKNOWN\"
+  Driver:         Windows
+  RO Version:     Unknown
+  RW Version:     Unknown
+  Current image:  Unknown
+  RW Rollback:    Unknown
+"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"build_info\": null, \"driver\": \"Windows\", \"driver_version\": null, \"ro_version\": null, \
+             \"rw_version\": null, \"current_image\": null, \"rollback\": null}"
+        );
+    }
+
+    #[test]
+    fn ec_version_json() {
+        let report = ec_report("amd-fl16-ec-3.03.bin", "2024-03-14 10:01:12");
+        assert_eq!(
+            report.to_json(),
+            "{\"build_info\": \"lotus_v3.4.113353-ec:b4c1fb,os: 2024-03-14 10:01:12 runner@buildhost\", \
+             \"driver\": \"Portio\", \"driver_version\": null, \
+             \"ro_version\": \"lotus_v3.4.113353-ec:b4c1fb,os:\", \
+             \"rw_version\": \"lotus_v3.4.113353-ec:b4c1fb,os:\", \
+             \"current_image\": \"RW\", \"rollback\": null}"
+        );
+    }
+
+    #[test]
+    fn render_pd_versions_tgl() {
+        // 11th Gen Intel shows the base version
+        let platform = Some(Platform::IntelGen11);
+        let report = PdVersionsReport::Controllers {
+            right: PdControllerVersions::new(
+                pd_firmwares("tgl-pd-3.8.0.bin", SiliconId::Ccg5, FwMode::MainFw),
+                platform,
+            ),
+            left: Some(PdControllerVersions::new(
+                pd_firmwares("tgl-pd-3.8.0.bin", SiliconId::Ccg5, FwMode::BackupFw),
+                platform,
+            )),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "PD Controllers
+  Right (01)
+    Main:       3.4.0.A0F (Active)
+    Backup:     3.4.0.A0F
+  Left  (23)
+    Main:       3.4.0.A0F
+    Backup:     3.4.0.A0F (Active)
+"
+        );
+    }
+
+    #[test]
+    fn render_pd_versions_adl_fl16() {
+        let report = PdVersionsReport::Controllers {
+            right: PdControllerVersions::new(
+                pd_firmwares("adl-pd-0.1.33.bin", SiliconId::Ccg6, FwMode::MainFw),
+                Some(Platform::IntelGen12),
+            ),
+            left: Some(PdControllerVersions::new(
+                pd_firmwares("fl16-pd-0.0.03.bin", SiliconId::Ccg8, FwMode::BootLoader),
+                Some(Platform::Framework16),
+            )),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "PD Controllers
+  Right (01)
+    Main:       0.1.21 (Active)
+    Backup:     0.1.21
+  Left  (23)
+    Main:       0.0.03
+    Backup:     0.0.03
+"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"right\": {\"main\": \"0.1.21\", \"backup\": \"0.1.21\", \"active\": \"MainFw\"}, \
+             \"left\": {\"main\": \"0.0.03\", \"backup\": \"0.0.03\", \"active\": \"BootLoader\"}}"
+        );
+    }
+
+    #[test]
+    fn render_pd_versions_fallback() {
+        let report = PdVersionsReport::FromEc {
+            right: "0.1.21".to_string(),
+            left: Some("0.1.21".to_string()),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "PD Controllers\n  Right (01):     0.1.21\n  Left  (23):     0.1.21\n"
+        );
+        let report = PdVersionsReport::FromEc {
+            right: "0.1.21".to_string(),
+            left: None,
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "PD Controllers\n  Back  (01):     0.1.21\n"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"right\": {\"main\": \"0.1.21\"}, \"left\": null}"
+        );
+        assert_eq!(
+            render(|out| PdVersionsReport::Unknown.render(out)),
+            "PD Controllers\n  Unknown\n"
+        );
+        assert_eq!(PdVersionsReport::Unknown.to_json(), "null");
+    }
+
+    #[test]
+    fn render_pd_details() {
+        let report = PdDetailsReport {
+            silicon_id: Some(SiliconId::Ccg6 as u16),
+            device_info: Some((FwMode::MainFw, 128)),
+            firmwares: Some(pd_firmwares(
+                "adl-pd-0.1.33.bin",
+                SiliconId::Ccg6,
+                FwMode::MainFw,
+            )),
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "  Silicon ID:     0x3000
+  Mode:           MainFw
+  Flash Row Size: 128 B
+  Bootloader Version:   Base: 3.4.0.1A9,  App: 0.1.21
+  FW1 (Backup) Version: Base: 3.4.0.1A9,  App: 0.1.21
+  FW2 (Main)   Version: Base: 3.4.0.1A9,  App: 0.1.21
+"
+        );
+        assert_eq!(
+            report.to_json(),
+            "{\"silicon_id\": \"0x3000\", \"mode\": \"MainFw\", \"flash_row_size\": 128, \"firmwares\": \
+             {\"bootloader\": {\"base\": \"3.4.0.1A9\", \"app\": \"0.1.21\"}, \
+             \"backup\": {\"base\": \"3.4.0.1A9\", \"app\": \"0.1.21\"}, \
+             \"main\": {\"base\": \"3.4.0.1A9\", \"app\": \"0.1.21\"}}}"
+        );
+
+        let report = PdDetailsReport {
+            silicon_id: None,
+            device_info: None,
+            firmwares: None,
+        };
+        assert_eq!(
+            render(|out| report.render(out)),
+            "  Failed to read Silicon ID/Family\n  Failed to device info\n  Failed to get PD Info\n"
+        );
+    }
+
+    #[test]
+    fn config_version() {
+        assert_eq!(fmt_config_version("A6"), "MassProduction (A6)");
+        assert_eq!(fmt_config_version("7"), "Dvt1 (7)");
+        assert_eq!(fmt_config_version("X1"), "'X1'");
+        assert_eq!(fmt_config_version(""), "''");
+    }
+
+    #[test]
+    fn versions_of_fixtures() {
+        use crate::chromium_ec::trace::with_replay;
+        use framework_test_support::fixture;
+
+        // BIOS, EC RO/RW and PD app versions
+        let expected = [
+            (
+                "framework13-intel-11th-gen",
+                "03.19",
+                "hx20_v0.0.1-ca5bb3c",
+                "3.8.00",
+            ),
+            (
+                "framework13-intel-12th-gen",
+                "03.18",
+                "hx30_v0.0.1-7a61a89",
+                "0.1.21",
+            ),
+            (
+                "framework13-intel-13th-gen",
+                "03.05",
+                "hx30_v0.0.1-f2fd9ac",
+                "0.1.21",
+            ),
+            (
+                "framework13-intel-core-ultra-1",
+                "03.03",
+                "marigold_v0.0.1-94e4b3d",
+                "0.0.06",
+            ),
+            (
+                "framework13-amd-7040",
+                "03.05",
+                "azalea_v3.4.113353-ec:b4c1fb,os",
+                "0.0.05",
+            ),
+            (
+                "framework16-amd-7040",
+                "03.03",
+                "lotus_v3.4.113353-ec:b4c1fb,os:",
+                "0.0.03",
+            ),
+        ];
+        for (name, bios_version, ec_version, pd_version) in expected {
+            let fixture = fixture(name);
+            let bios = BiosVersionReport {
+                bios: SmbiosInfo::parse(&fixture.smbios_table()).bios,
+            };
+            assert!(render(|out| bios.render(out))
+                .starts_with(&format!("UEFI BIOS\n  Version:        {}\n", bios_version)));
+
+            let (ec, pd) = with_replay(&fixture.ec_trace(), |ec| {
+                (EcVersionReport::read(ec, false), power::read_pd_version(ec))
+            });
+            assert_eq!(ec.driver, CrosEcDriverType::Replay);
+            assert!(ec.build_info.as_ref().unwrap().starts_with(ec_version));
+            assert_eq!(
+                ec.flash_version,
+                Some((
+                    ec_version.to_string(),
+                    ec_version.to_string(),
+                    EcCurrentImage::RW
+                ))
+            );
+            let pd = pd.unwrap();
+            assert_eq!(pd.controller01.app.to_string(), pd_version);
+            assert_eq!(pd.controller23.app.to_string(), pd_version);
+        }
+    }
+}
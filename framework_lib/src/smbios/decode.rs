@@ -0,0 +1,390 @@
+//! Decode the SMBIOS structures that the tool needs
+//!
+//! Works on the raw table and doesn't need std or smbioslib, so that the UEFI
+//! shell decodes exactly the same information as the OS tools.
+//! On the OS the structures from smbioslib are decoded through the same `SmbiosStruct` trait.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+pub const TYPE_BIOS_INFORMATION: u8 = 0;
+pub const TYPE_SYSTEM_INFORMATION: u8 = 1;
+pub const TYPE_BASEBOARD_INFORMATION: u8 = 2;
+pub const TYPE_CHASSIS_INFORMATION: u8 = 3;
+pub const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END_OF_TABLE: u8 = 127;
+
+/// Access to the fields of a single SMBIOS structure
+///
+/// Offsets are from the start of the structure, including the header.
+pub trait SmbiosStruct {
+    fn struct_type(&self) -> u8;
+    fn byte(&self, offset: usize) -> Option<u8>;
+    fn word(&self, offset: usize) -> Option<u16>;
+    fn dword(&self, offset: usize) -> Option<u32>;
+    /// String referenced by the byte at the offset, formatted like dmidecode does
+    fn string(&self, offset: usize) -> Option<String>;
+}
+
+/// Structure as found in the raw table
+pub struct RawStruct<'a> {
+    /// Formatted section, including the header
+    formatted: &'a [u8],
+    strings: Vec<&'a [u8]>,
+}
+
+impl SmbiosStruct for RawStruct<'_> {
+    fn struct_type(&self) -> u8 {
+        self.formatted[0]
+    }
+
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    fn word(&self, offset: usize) -> Option<u16> {
+        let bytes = self.formatted.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn dword(&self, offset: usize) -> Option<u32> {
+        let bytes = self.formatted.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&self, offset: usize) -> Option<String> {
+        let index = self.byte(offset)? as usize;
+        if index == 0 {
+            return Some("Not Specified".to_string());
+        }
+        match self.strings.get(index - 1) {
+            Some([]) => Some("Not Specified".to_string()),
+            Some(s) => Some(String::from_utf8_lossy(s).to_string()),
+            None => Some("<BAD INDEX>".to_string()),
+        }
+    }
+}
+
+/// Split the raw table into its structures
+///
+/// Stops at the end-of-table structure or when the table is truncated.
+pub fn raw_structs(table: &[u8]) -> Vec<RawStruct<'_>> {
+    let mut structs = vec![];
+    let mut offset = 0;
+    while offset + 4 <= table.len() {
+        let struct_type = table[offset];
+        let length = table[offset + 1] as usize;
+        if length < 4 || offset + length > table.len() {
+            break;
+        }
+        let formatted = &table[offset..offset + length];
+
+        // Strings follow the formatted section, each terminated by NUL.
+        // The set is terminated by another NUL, which is the only one if there are no strings.
+        let mut strings = vec![];
+        let mut pos = offset + length;
+        if table.get(pos) == Some(&0) {
+            pos += 1;
+        } else {
+            while let Some(len) = table
+                .get(pos..)
+                .and_then(|s| s.iter().position(|&b| b == 0))
+            {
+                if len == 0 {
+                    break;
+                }
+                strings.push(&table[pos..pos + len]);
+                pos += len + 1;
+            }
+        }
+        // Skip terminating NUL
+        pos += 1;
+
+        structs.push(RawStruct { formatted, strings });
+        if struct_type == TYPE_END_OF_TABLE {
+            break;
+        }
+        offset = pos;
+    }
+    structs
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BiosInfo {
+    pub vendor: Option<String>,
+    pub version: Option<String>,
+    pub release_date: Option<String>,
+}
+
+impl BiosInfo {
+    pub fn decode(s: &dyn SmbiosStruct) -> Self {
+        BiosInfo {
+            vendor: s.string(0x04),
+            version: s.string(0x05),
+            release_date: s.string(0x08),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SystemInfo {
+    pub manufacturer: Option<String>,
+    pub product_name: Option<String>,
+    pub version: Option<String>,
+    pub serial_number: Option<String>,
+    pub wakeup_type: Option<u8>,
+    pub sku_number: Option<String>,
+    pub family: Option<String>,
+}
+
+impl SystemInfo {
+    pub fn decode(s: &dyn SmbiosStruct) -> Self {
+        SystemInfo {
+            manufacturer: s.string(0x04),
+            product_name: s.string(0x05),
+            version: s.string(0x06),
+            serial_number: s.string(0x07),
+            wakeup_type: s.byte(0x18),
+            sku_number: s.string(0x19),
+            family: s.string(0x1A),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BaseBoardInfo {
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl BaseBoardInfo {
+    pub fn decode(s: &dyn SmbiosStruct) -> Self {
+        BaseBoardInfo {
+            manufacturer: s.string(0x04),
+            product: s.string(0x05),
+            version: s.string(0x06),
+            serial_number: s.string(0x07),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChassisInfo {
+    pub manufacturer: Option<String>,
+    pub chassis_type: Option<u8>,
+}
+
+impl ChassisInfo {
+    pub fn decode(s: &dyn SmbiosStruct) -> Self {
+        ChassisInfo {
+            manufacturer: s.string(0x04),
+            // Bit 7 is the chassis lock
+            chassis_type: s.byte(0x05).map(|x| x & 0x7F),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryDevice {
+    /// None if unknown, 0 if no module is installed
+    pub size_mb: Option<u32>,
+    pub device_locator: Option<String>,
+    pub bank_locator: Option<String>,
+    /// Maximum speed of the module in MT/s
+    pub speed_mts: Option<u32>,
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
+    pub part_number: Option<String>,
+}
+
+impl MemoryDevice {
+    pub fn decode(s: &dyn SmbiosStruct) -> Self {
+        let size_mb = match s.word(0x0C) {
+            None | Some(0xFFFF) => None,
+            // Too big, real size is in extended size field
+            Some(0x7FFF) => s.dword(0x1C).map(|x| x & 0x7FFF_FFFF),
+            // Size in KB
+            Some(size) if size & 0x8000 != 0 => Some((size & 0x7FFF) as u32 / 1024),
+            Some(size) => Some(size as u32),
+        };
+        let speed_mts = match s.word(0x15) {
+            None | Some(0) => None,
+            // Too fast, real speed is in extended speed field
+            Some(0xFFFF) => s.dword(0x54),
+            Some(speed) => Some(speed as u32),
+        };
+        MemoryDevice {
+            size_mb,
+            device_locator: s.string(0x10),
+            bank_locator: s.string(0x11),
+            speed_mts,
+            manufacturer: s.string(0x17),
+            serial_number: s.string(0x18),
+            part_number: s.string(0x1A),
+        }
+    }
+}
+
+/// Everything the tool needs from SMBIOS
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SmbiosInfo {
+    pub bios: Option<BiosInfo>,
+    pub system: Option<SystemInfo>,
+    pub baseboard: Option<BaseBoardInfo>,
+    pub chassis: Option<ChassisInfo>,
+    pub memory: Vec<MemoryDevice>,
+}
+
+impl SmbiosInfo {
+    /// Decode from the raw SMBIOS table, without the entrypoint structure
+    pub fn parse(table: &[u8]) -> Self {
+        let mut info = SmbiosInfo::default();
+        for s in raw_structs(table) {
+            info.insert(&s);
+        }
+        info
+    }
+
+    /// Decode a structure, if it's of a type we need
+    ///
+    /// Only the first of each type is kept, except for memory devices.
+    pub fn insert(&mut self, s: &dyn SmbiosStruct) {
+        match s.struct_type() {
+            TYPE_BIOS_INFORMATION if self.bios.is_none() => {
+                self.bios = Some(BiosInfo::decode(s));
+            }
+            TYPE_SYSTEM_INFORMATION if self.system.is_none() => {
+                self.system = Some(SystemInfo::decode(s));
+            }
+            TYPE_BASEBOARD_INFORMATION if self.baseboard.is_none() => {
+                self.baseboard = Some(BaseBoardInfo::decode(s));
+            }
+            TYPE_CHASSIS_INFORMATION if self.chassis.is_none() => {
+                self.chassis = Some(ChassisInfo::decode(s));
+            }
+            TYPE_MEMORY_DEVICE => self.memory.push(MemoryDevice::decode(s)),
+            _ => {}
+        }
+    }
+}
+
+/// Name of the wake-up type in System Information (Type 1)
+pub fn wakeup_type_name(wakeup_type: u8) -> &'static str {
+    match wakeup_type {
+        0x01 => "Other",
+        0x02 => "Unknown",
+        0x03 => "APM Timer",
+        0x04 => "Modem Ring",
+        0x05 => "LAN Remote",
+        0x06 => "Power Switch",
+        0x07 => "PCI PME#",
+        0x08 => "AC Power Restored",
+        _ => "Reserved",
+    }
+}
+
+/// Name of the chassis type in System Enclosure (Type 3)
+pub fn chassis_type_name(chassis_type: u8) -> &'static str {
+    match chassis_type {
+        0x01 => "Other",
+        0x02 => "Unknown",
+        0x03 => "Desktop",
+        0x04 => "Low Profile Desktop",
+        0x05 => "Pizza Box",
+        0x06 => "Mini Tower",
+        0x07 => "Tower",
+        0x08 => "Portable",
+        0x09 => "Laptop",
+        0x0A => "Notebook",
+        0x0B => "Hand Held",
+        0x0C => "Docking Station",
+        0x0D => "All in One",
+        0x0E => "Sub Notebook",
+        0x0F => "Space-saving",
+        0x10 => "Lunch Box",
+        0x11 => "Main Server Chassis",
+        0x12 => "Expansion Chassis",
+        0x13 => "SubChassis",
+        0x14 => "Bus Expansion Chassis",
+        0x15 => "Peripheral Chassis",
+        0x16 => "RAID Chassis",
+        0x17 => "Rack Mount Chassis",
+        0x18 => "Sealed-case PC",
+        0x19 => "Multi-system chassis",
+        0x1A => "Compact PCI",
+        0x1B => "Advanced TCA",
+        0x1C => "Blade",
+        0x1D => "Blade Enclosure",
+        0x1E => "Tablet",
+        0x1F => "Convertible",
+        0x20 => "Detachable",
+        0x21 => "IoT Gateway",
+        0x22 => "Embedded PC",
+        0x23 => "Mini PC",
+        0x24 => "Stick PC",
+        _ => "Reserved",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_table() {
+        let mut table = vec![];
+        // Type 0 with vendor, version and an unset release date
+        table.extend([0, 0x12, 0x00, 0x00, 1, 2, 0, 0, 0]);
+        table.extend([0; 0x12 - 9]);
+        table.extend(b"INSYDE Corp.\x0003.05\x00\x00");
+        // Type 1 with manufacturer and product name
+        let mut system = vec![1, 0x1B, 0x01, 0x00, 1, 2, 0, 0];
+        system.resize(0x1B, 0);
+        system[0x18] = 0x06;
+        table.extend(system);
+        table.extend(b"Framework\x00Laptop 13 (AMD Ryzen 7040Series)\x00\x00");
+        // Type 17 with 16GB at 5600MT/s, without strings
+        let mut memory = vec![17, 0x28, 0x02, 0x00];
+        memory.resize(0x28, 0);
+        memory[0x0C..0x0E].copy_from_slice(&16384u16.to_le_bytes());
+        memory[0x15..0x17].copy_from_slice(&5600u16.to_le_bytes());
+        table.extend(memory);
+        table.extend([0, 0]);
+        // End of table
+        table.extend([127, 4, 0x03, 0x00, 0, 0]);
+
+        let info = SmbiosInfo::parse(&table);
+        let bios = info.bios.unwrap();
+        assert_eq!(bios.vendor.as_deref(), Some("INSYDE Corp."));
+        assert_eq!(bios.version.as_deref(), Some("03.05"));
+        assert_eq!(bios.release_date.as_deref(), Some("Not Specified"));
+
+        let system = info.system.unwrap();
+        assert_eq!(system.manufacturer.as_deref(), Some("Framework"));
+        assert_eq!(
+            system.product_name.as_deref(),
+            Some("Laptop 13 (AMD Ryzen 7040Series)")
+        );
+        assert_eq!(
+            system.wakeup_type.map(wakeup_type_name),
+            Some("Power Switch")
+        );
+
+        assert!(info.baseboard.is_none());
+        assert_eq!(info.memory.len(), 1);
+        assert_eq!(info.memory[0].size_mb, Some(16384));
+        assert_eq!(info.memory[0].speed_mts, Some(5600));
+    }
+
+    #[test]
+    fn truncated_table() {
+        // Length claims more than there is
+        let table = [0, 0x18, 0x00, 0x00, 1];
+        assert_eq!(SmbiosInfo::parse(&table), SmbiosInfo::default());
+    }
+}
@@ -2,25 +2,31 @@
 
 use std::prelude::v1::*;
 
-#[cfg(all(not(feature = "uefi"), not(target_os = "freebsd")))]
+#[cfg(all(feature = "smbios", not(feature = "uefi"), not(target_os = "freebsd")))]
 use std::io::ErrorKind;
 
 use crate::util::{Config, Platform};
 use num_derive::FromPrimitive;
+#[cfg(all(feature = "smbios", not(feature = "uefi")))]
 use smbioslib::*;
 #[cfg(feature = "uefi")]
 use spin::Mutex;
 #[cfg(not(feature = "uefi"))]
 use std::sync::Mutex;
 
-#[cfg(target_os = "freebsd")]
+#[cfg(all(feature = "smbios", target_os = "freebsd"))]
 use std::io::{Read, Seek, SeekFrom};
 
+pub mod decode;
+
+pub use decode::{BaseBoardInfo, BiosInfo, ChassisInfo, MemoryDevice, SmbiosInfo, SystemInfo};
+
 /// Current platform. Won't ever change during the program's runtime
 static CACHED_PLATFORM: Mutex<Option<Option<Platform>>> = Mutex::new(None);
 
-// TODO: Should cache SMBIOS and values gotten from it
-// SMBIOS is fixed after boot. Oh, so maybe not cache when we're running in UEFI
+/// Decoded SMBIOS tables. They're fixed after boot, so only read them once.
+/// Reading them is slow on Windows and needs root on Linux.
+static CACHED_SMBIOS: Mutex<Option<Option<SmbiosInfo>>> = Mutex::new(None);
 
 #[repr(u8)]
 #[derive(Debug, PartialEq, FromPrimitive, Clone, Copy)]
@@ -61,23 +67,13 @@ pub fn is_framework() -> bool {
         return maker == "Framework";
     }
 
-    let smbios = if let Some(smbios) = get_smbios() {
-        smbios
-    } else {
-        return false;
-    };
-
-    for undefined_struct in smbios.iter() {
-        if let DefinedStruct::SystemInformation(data) = undefined_struct.defined_struct() {
-            if let Some(manufacturer) = dmidecode_string_val(&data.manufacturer()) {
-                return manufacturer == "Framework";
-            }
-        }
-    }
-
-    false
+    get_smbios_info()
+        .and_then(|info| info.system?.manufacturer)
+        .map(|manufacturer| manufacturer == "Framework")
+        .unwrap_or(false)
 }
 
+#[cfg(all(feature = "smbios", not(feature = "uefi")))]
 pub fn dmidecode_string_val(s: &SMBiosString) -> Option<String> {
     match s.as_ref() {
         Ok(val) if val.is_empty() => Some("Not Specified".to_owned()),
@@ -124,7 +120,7 @@ pub struct Smbios {
     pub bcd_revision: u8,
 }
 
-#[cfg(target_os = "freebsd")]
+#[cfg(all(feature = "smbios", target_os = "freebsd"))]
 pub fn get_smbios() -> Option<SMBiosData> {
     trace!("get_smbios() FreeBSD entry");
     // Get the SMBIOS entrypoint address from the kernel environment
@@ -188,17 +184,9 @@ pub fn get_smbios() -> Option<SMBiosData> {
     Some(smbios)
 }
 
-#[cfg(feature = "uefi")]
-pub fn get_smbios() -> Option<SMBiosData> {
-    trace!("get_smbios() uefi entry");
-    let data = crate::uefi::smbios_data().unwrap();
-    let version = None; // TODO: Maybe add the version here
-    let smbios = SMBiosData::from_vec_and_version(data, version);
-    Some(smbios)
-}
 // On Linux this reads either from /dev/mem or sysfs
 // On Windows from the kernel API
-#[cfg(all(not(feature = "uefi"), not(target_os = "freebsd")))]
+#[cfg(all(feature = "smbios", not(feature = "uefi"), not(target_os = "freebsd")))]
 pub fn get_smbios() -> Option<SMBiosData> {
     trace!("get_smbios() linux entry");
     match smbioslib::table_load_from_device() {
@@ -214,51 +202,88 @@ pub fn get_smbios() -> Option<SMBiosData> {
     }
 }
 
-fn get_product_name() -> Option<String> {
-    // On FreeBSD we can short-circuit and avoid parsing SMBIOS
-    #[cfg(target_os = "freebsd")]
-    if let Ok(product) = kenv_get("smbios.system.product") {
-        return Some(product);
+#[cfg(all(feature = "smbios", not(feature = "uefi")))]
+impl decode::SmbiosStruct for UndefinedStruct {
+    fn struct_type(&self) -> u8 {
+        self.header.struct_type()
     }
 
-    let smbios = get_smbios();
-    if smbios.is_none() {
-        println!("Failed to find SMBIOS");
+    fn byte(&self, offset: usize) -> Option<u8> {
+        self.get_field_byte(offset)
+    }
+
+    fn word(&self, offset: usize) -> Option<u16> {
+        self.get_field_word(offset)
+    }
+
+    fn dword(&self, offset: usize) -> Option<u32> {
+        self.get_field_dword(offset)
+    }
+
+    fn string(&self, offset: usize) -> Option<String> {
+        dmidecode_string_val(&self.get_field_string(offset))
     }
-    let mut smbios = smbios.into_iter().flatten();
-    smbios.find_map(|undefined_struct| {
-        if let DefinedStruct::SystemInformation(data) = undefined_struct.defined_struct() {
-            if let Some(product_name) = dmidecode_string_val(&data.product_name()) {
-                return Some(product_name.as_str().to_string());
-            }
-        }
-        None
-    })
 }
 
-pub fn get_platform() -> Option<Platform> {
+/// Get and decode the SMBIOS information that the tool needs
+///
+/// Decoded the same way on every OS and in UEFI.
+/// Information decoded from SMBIOS
+///
+/// The tables are only read on the first call, afterwards the cached result
+/// is returned. Safe to call from multiple threads, concurrent callers wait
+/// for the first one to finish reading.
+pub fn get_smbios_info() -> Option<SmbiosInfo> {
     #[cfg(feature = "uefi")]
-    let mut cached_platform = CACHED_PLATFORM.lock();
+    let mut cached_smbios = CACHED_SMBIOS.lock();
     #[cfg(not(feature = "uefi"))]
-    let mut cached_platform = CACHED_PLATFORM.lock().unwrap();
+    let mut cached_smbios = CACHED_SMBIOS.lock().unwrap();
 
-    if let Some(platform) = *cached_platform {
-        return platform;
+    if let Some(info) = &*cached_smbios {
+        return info.clone();
     }
 
-    if Config::is_set() {
-        // Config::get() recursively calls get_platform.
-        // Except if it's a GenericFramework platform
-        let config = Config::get();
-        let platform = &(*config).as_ref().unwrap().platform;
-        if matches!(platform, Platform::GenericFramework((_, _), (_, _), _)) {
-            return Some(*platform);
+    let info = read_smbios_info();
+    *cached_smbios = Some(info.clone());
+    info
+}
+
+fn read_smbios_info() -> Option<SmbiosInfo> {
+    #[cfg(feature = "uefi")]
+    return crate::uefi::smbios_data().map(|table| SmbiosInfo::parse(&table));
+
+    // Only the platform given by the user is known
+    #[cfg(all(not(feature = "smbios"), not(feature = "uefi")))]
+    return None;
+
+    #[cfg(all(feature = "smbios", not(feature = "uefi")))]
+    {
+        let smbios = get_smbios()?;
+        let mut info = SmbiosInfo::default();
+        for undefined_struct in smbios.iter() {
+            info.insert(undefined_struct);
         }
+        Some(info)
     }
+}
 
-    let product_name = get_product_name()?;
+fn get_product_name() -> Option<String> {
+    // On FreeBSD we can short-circuit and avoid parsing SMBIOS
+    #[cfg(target_os = "freebsd")]
+    if let Ok(product) = kenv_get("smbios.system.product") {
+        return Some(product);
+    }
+
+    let info = get_smbios_info();
+    if info.is_none() {
+        println!("Failed to find SMBIOS");
+    }
+    info?.system?.product_name
+}
 
-    let platform = match product_name.as_str() {
+/// Platform with this product name in SMBIOS
+pub fn platform_from_product_name(product_name: &str) -> Option<Platform> {
+    match product_name {
         "Laptop" => Some(Platform::IntelGen11),
         "Laptop (12th Gen Intel Core)" => Some(Platform::IntelGen12),
         "Laptop (13th Gen Intel Core)" => Some(Platform::IntelGen13),
@@ -266,8 +291,35 @@ pub fn get_platform() -> Option<Platform> {
         "Laptop 13 (AMD Ryzen 7040 Series)" => Some(Platform::Framework13Amd),
         "Laptop 13 (Intel Core Ultra Series 1)" => Some(Platform::IntelCoreUltra1),
         "Laptop 16 (AMD Ryzen 7040 Series)" => Some(Platform::Framework16),
+        "Desktop (AMD Ryzen AI Max 300 Series)" => Some(Platform::DesktopAmdAi300),
         _ => None,
-    };
+    }
+}
+
+/// Detect which platform we're running on
+///
+/// Only detected once, afterwards the cached result is returned. Can be
+/// called from any thread. A platform from `Config::set`, like `--platform`,
+/// takes precedence, even if it was set after detecting.
+pub fn get_platform() -> Option<Platform> {
+    // Platform was set explicitly by the user, or detected already.
+    // Config::get() doesn't recursively call get_platform when it's set already.
+    if Config::is_set() {
+        let config = Config::get();
+        return Some((*config).as_ref().unwrap().platform);
+    }
+
+    #[cfg(feature = "uefi")]
+    let mut cached_platform = CACHED_PLATFORM.lock();
+    #[cfg(not(feature = "uefi"))]
+    let mut cached_platform = CACHED_PLATFORM.lock().unwrap();
+
+    if let Some(platform) = *cached_platform {
+        return platform;
+    }
+
+    let product_name = get_product_name()?;
+    let platform = platform_from_product_name(&product_name);
 
     if let Some(platform) = platform {
         Config::set(platform);
@@ -306,3 +358,30 @@ fn kenv_get(name: &str) -> nix::Result<String> {
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framework_test_support::FIXTURES;
+
+    #[test]
+    fn detect_platform_of_fixtures() {
+        for fixture in FIXTURES {
+            let info = SmbiosInfo::parse(&fixture.smbios_table());
+            let system = info.system.unwrap();
+            assert_eq!(system.manufacturer.as_deref(), Some("Framework"));
+            let product_name = system.product_name.unwrap();
+            assert_eq!(product_name, fixture.product_name);
+            let platform = platform_from_product_name(&product_name);
+            assert_eq!(
+                format!("{:?}", platform),
+                format!("Some({})", fixture.platform)
+            );
+        }
+        assert_eq!(
+            platform_from_product_name("Desktop (AMD Ryzen AI Max 300 Series)"),
+            Some(Platform::DesktopAmdAi300)
+        );
+        assert_eq!(platform_from_product_name("Desktop"), None);
+    }
+}
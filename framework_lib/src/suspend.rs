@@ -0,0 +1,207 @@
+//! Measure how much battery the system drains while suspended
+//!
+//! The battery charge is read from the EC before and after suspending for a
+//! fixed time. The system is woken up by a timer, so it works unattended.
+//!
+//! - Linux: `rtcwake`, needs root privileges
+//!
+//!   This doesn't go through systemd-logind, so sleep inhibitors and
+//!   `systemd-sleep` hooks are bypassed. logind has no way to suspend with a
+//!   timed wakeup and `systemctl suspend` returns before the system is
+//!   actually suspended, so we wouldn't know when it resumed.
+//! - Windows: Waitable timer that can resume the system and `SetSuspendState`
+
+use std::io;
+#[cfg(all(feature = "linux", target_os = "linux"))]
+use std::process::Command;
+use std::time::SystemTime;
+
+use crate::chromium_ec::CrosEc;
+use crate::output::{outln, OutputSink};
+use crate::power;
+use crate::units;
+
+/// More drain than this is worth investigating, in percent per hour
+pub const ABNORMAL_DRAIN_PERCENT_PER_HOUR: f32 = 1.0;
+
+/// Battery charge at one point in time
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatterySnapshot {
+    /// Remaining capacity in mAh
+    pub remaining_mah: u32,
+    /// Last full charge capacity in mAh
+    pub full_mah: u32,
+    /// Present voltage in mV
+    pub voltage_mv: u32,
+}
+
+impl BatterySnapshot {
+    pub fn read(ec: &CrosEc) -> Option<Self> {
+        let battery = power::power_info(ec)?.battery?;
+        Some(BatterySnapshot {
+            remaining_mah: battery.remaining_capacity,
+            full_mah: battery.last_full_charge_capacity,
+            voltage_mv: battery.present_voltage,
+        })
+    }
+
+    /// Remaining energy in mWh
+    fn energy_mwh(&self) -> u64 {
+        self.remaining_mah as u64 * self.voltage_mv as u64 / 1000
+    }
+}
+
+/// How much was drained between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuspendDrain {
+    pub hours: f32,
+    pub percent_per_hour: f32,
+    /// Average power in mW
+    pub milliwatts: u32,
+}
+
+impl SuspendDrain {
+    pub fn new(before: &BatterySnapshot, after: &BatterySnapshot, hours: f32) -> Self {
+        let drained_mah = before.remaining_mah.saturating_sub(after.remaining_mah);
+        let drained_mwh = before.energy_mwh().saturating_sub(after.energy_mwh());
+        let percent = if before.full_mah == 0 {
+            0.0
+        } else {
+            drained_mah as f32 * 100.0 / before.full_mah as f32
+        };
+        SuspendDrain {
+            hours,
+            percent_per_hour: percent / hours,
+            milliwatts: (drained_mwh as f32 / hours) as u32,
+        }
+    }
+
+    pub fn is_abnormal(&self) -> bool {
+        self.percent_per_hour > ABNORMAL_DRAIN_PERCENT_PER_HOUR
+    }
+}
+
+/// Suspend the system and wake it up again after `seconds`
+///
+/// Returns after the system has resumed.
+#[cfg(all(feature = "linux", target_os = "linux"))]
+pub fn suspend_for(seconds: u64) -> io::Result<()> {
+    let status = Command::new("rtcwake")
+        .args(["-m", "mem", "-s", &seconds.to_string()])
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("rtcwake failed: {}", status)))
+    }
+}
+
+/// Suspend the system and wake it up again after `seconds`
+///
+/// Returns after the system has resumed.
+#[cfg(feature = "windows")]
+pub fn suspend_for(seconds: u64) -> io::Result<()> {
+    use windows::Win32::Foundation::CloseHandle;
+    use windows::Win32::System::Power::SetSuspendState;
+    use windows::Win32::System::Threading::{CreateWaitableTimerW, SetWaitableTimer};
+
+    let to_io = |err: windows::core::Error| io::Error::other(err);
+    unsafe {
+        let timer = CreateWaitableTimerW(None, true, None).map_err(to_io)?;
+        // Relative time, in units of 100ns
+        let due_time = -(seconds as i64) * 10_000_000;
+        SetWaitableTimer(timer, &due_time, 0, None, None, true).map_err(to_io)?;
+        // Returns once the system is running again
+        let suspended = SetSuspendState(false, false, false);
+        let err = io::Error::last_os_error();
+        let _ = CloseHandle(timer);
+        if !suspended {
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(all(feature = "linux", target_os = "linux"), feature = "windows")))]
+pub fn suspend_for(_seconds: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Suspending is not supported on this OS",
+    ))
+}
+
+/// Suspend for a number of minutes and report how much battery was drained
+///
+/// Returns whether the drain is within the normal range.
+pub fn run(ec: &CrosEc, minutes: u64, out: &mut dyn OutputSink) -> io::Result<bool> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "Failed to read battery");
+    let before = BatterySnapshot::read(ec).ok_or_else(not_found)?;
+    if power::power_info(ec).map(|x| x.ac_present).unwrap_or(false) {
+        outln!(out, "Warning: AC is connected, the battery is not drained");
+    }
+
+    outln!(out, "Suspending for {} minutes", minutes);
+    // Instant doesn't advance while suspended, the wall clock does
+    let start = SystemTime::now();
+    suspend_for(minutes * 60)?;
+    // Use the time that actually passed, the system might have woken up early
+    let elapsed = start
+        .elapsed()
+        .unwrap_or(std::time::Duration::from_secs(minutes * 60));
+    let hours = elapsed.as_secs_f32() / 3600.0;
+
+    let after = BatterySnapshot::read(ec).ok_or_else(not_found)?;
+    let drain = SuspendDrain::new(&before, &after, hours);
+    outln!(out, "Resumed after {:.1} minutes", hours * 60.0);
+    out.write_table(&[
+        (
+            "Before",
+            format!("{} mAh, {} mV", before.remaining_mah, before.voltage_mv),
+        ),
+        (
+            "After",
+            format!("{} mAh, {} mV", after.remaining_mah, after.voltage_mv),
+        ),
+        ("Drain", format!("{:.2} %/h", drain.percent_per_hour)),
+        ("Power", units::fmt_power(drain.milliwatts)),
+    ]);
+    if drain.is_abnormal() {
+        outln!(
+            out,
+            "Abnormal drain, more than {}%/h. Check if the system reaches its deepest sleep state",
+            ABNORMAL_DRAIN_PERCENT_PER_HOUR
+        );
+    }
+    Ok(!drain.is_abnormal())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculate_drain() {
+        let before = BatterySnapshot {
+            remaining_mah: 3000,
+            full_mah: 3500,
+            voltage_mv: 16000,
+        };
+        let after = BatterySnapshot {
+            remaining_mah: 2965,
+            voltage_mv: 15900,
+            ..before
+        };
+        // 35mAh of 3500mAh in 2 hours
+        let drain = SuspendDrain::new(&before, &after, 2.0);
+        assert!((drain.percent_per_hour - 0.5).abs() < 0.01);
+        // 48000mWh - 47143mWh
+        assert_eq!(drain.milliwatts, 428);
+        assert!(!drain.is_abnormal());
+
+        let after = BatterySnapshot {
+            remaining_mah: 2800,
+            ..before
+        };
+        assert!(SuspendDrain::new(&before, &after, 2.0).is_abnormal());
+    }
+}
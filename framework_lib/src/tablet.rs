@@ -0,0 +1,272 @@
+//! Debug tablet mode detection on convertibles like the Framework 12
+//!
+//! The hinge angle, the raw tablet mode and lid switch GPIOs and the tablet
+//! mode state of the EC are sampled for a while. Afterwards it's shown at which
+//! angles the EC switched in and out of tablet mode.
+
+use std::time::Instant;
+
+use crate::chromium_ec::{CrosEc, EcResult};
+use crate::os_specific;
+use crate::output::{outln, OutputSink};
+
+/// How long to capture by default
+pub const CAPTURE_DURATION_MS: u64 = 10_000;
+/// How often to sample during the capture
+pub const SAMPLE_INTERVAL_MS: u64 = 100;
+
+/// Active low GPIO, asserted when the hall sensor detects tablet mode
+const GPIO_TABLET_MODE_L: &str = "tablet_mode_l";
+/// Active low GPIO, asserted when the lid is closed
+const GPIO_LID_SW_L: &str = "lid_sw_l";
+
+/// Everything that is relevant to tablet mode at one point in time
+///
+/// Each is None if the EC doesn't support reading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabletSample {
+    /// Milliseconds since the start of the capture
+    pub time_ms: u64,
+    /// Raw level of the tablet_mode_l GPIO
+    pub tablet_mode_l: Option<bool>,
+    /// Raw level of the lid_sw_l GPIO
+    pub lid_sw_l: Option<bool>,
+    /// Hinge angle in degrees, None if unknown or unreliable
+    pub lid_angle: Option<u16>,
+    /// Whether the EC reports tablet mode
+    pub tablet_mode: Option<bool>,
+}
+
+impl TabletSample {
+    pub fn read(ec: &CrosEc, time_ms: u64) -> Self {
+        TabletSample {
+            time_ms,
+            tablet_mode_l: ec.get_gpio(GPIO_TABLET_MODE_L).ok(),
+            lid_sw_l: ec.get_gpio(GPIO_LID_SW_L).ok(),
+            lid_angle: ec.get_lid_angle().ok().flatten(),
+            tablet_mode: ec.get_tablet_mode().ok(),
+        }
+    }
+
+    /// Whether anything other than the time is different
+    fn state_differs(&self, other: &TabletSample) -> bool {
+        self.tablet_mode_l != other.tablet_mode_l
+            || self.lid_sw_l != other.lid_sw_l
+            || self.lid_angle != other.lid_angle
+            || self.tablet_mode != other.tablet_mode
+    }
+}
+
+/// Sample the tablet mode state periodically, for the given time
+pub fn capture(ec: &CrosEc, duration_ms: u64, interval_ms: u64) -> Vec<TabletSample> {
+    let start = Instant::now();
+    let mut samples = vec![];
+    loop {
+        let time_ms = start.elapsed().as_millis() as u64;
+        if time_ms > duration_ms {
+            break;
+        }
+        samples.push(TabletSample::read(ec, time_ms));
+        os_specific::sleep(interval_ms * 1000);
+    }
+    samples
+}
+
+/// Report whenever the EC enters or leaves tablet mode
+///
+/// Runs forever, unless the EC doesn't support reading tablet mode.
+pub fn watch(ec: &CrosEc, interval_ms: u64, out: &mut dyn OutputSink) -> EcResult<()> {
+    let start = Instant::now();
+    let mut tablet_mode = ec.get_tablet_mode()?;
+    outln!(
+        out,
+        "Currently in {} mode",
+        if tablet_mode { "tablet" } else { "laptop" }
+    );
+
+    loop {
+        os_specific::sleep(interval_ms * 1000);
+
+        let sample = TabletSample::read(ec, start.elapsed().as_millis() as u64);
+        // Keep watching, the EC might just be busy
+        match sample.tablet_mode {
+            Some(new_mode) if new_mode != tablet_mode => tablet_mode = new_mode,
+            _ => continue,
+        }
+        let angle = sample
+            .lid_angle
+            .map(|x| format!("{}°", x))
+            .unwrap_or_else(|| "unknown angle".to_string());
+        outln!(
+            out,
+            "[{:>8.1}s] {} tablet mode at {}",
+            sample.time_ms as f32 / 1000.0,
+            if tablet_mode { "Entered" } else { "Left" },
+            angle
+        );
+    }
+}
+
+/// Change of the EC's tablet mode state during a capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabletTransition {
+    pub time_ms: u64,
+    /// True when entering tablet mode, false when leaving it
+    pub entered: bool,
+    /// Hinge angle at the first sample with the new state
+    pub lid_angle: Option<u16>,
+}
+
+/// Find the samples where the EC entered or left tablet mode
+pub fn transitions(samples: &[TabletSample]) -> Vec<TabletTransition> {
+    samples
+        .windows(2)
+        .filter_map(|pair| match (pair[0].tablet_mode, pair[1].tablet_mode) {
+            (Some(old), Some(new)) if old != new => Some(TabletTransition {
+                time_ms: pair[1].time_ms,
+                entered: new,
+                lid_angle: pair[1].lid_angle,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn fmt_level(level: Option<bool>) -> String {
+    match level {
+        Some(true) => "1".to_string(),
+        Some(false) => "0".to_string(),
+        None => "?".to_string(),
+    }
+}
+
+/// Print the samples where something changed and a summary of the capture
+///
+/// `threshold` is the lid angle and hysteresis at which the EC should enter tablet mode.
+pub fn print_report(
+    samples: &[TabletSample],
+    threshold: Option<(i16, i16)>,
+    out: &mut dyn OutputSink,
+) {
+    if let Some((angle, hysteresis)) = threshold {
+        outln!(
+            out,
+            "Tablet mode threshold: {}° (hysteresis {}°)",
+            angle,
+            hysteresis
+        );
+    } else {
+        outln!(out, "Tablet mode threshold: Unknown");
+    }
+
+    outln!(out, "Samples (only showing changes):");
+    outln!(
+        out,
+        "  {:>7} {:>13} {:>8} {:>9} {:>11}",
+        "Time ms",
+        "tablet_mode_l",
+        "lid_sw_l",
+        "Angle",
+        "Tablet Mode"
+    );
+    let mut previous: Option<&TabletSample> = None;
+    for sample in samples {
+        if previous.map(|p| !p.state_differs(sample)).unwrap_or(false) {
+            continue;
+        }
+        let angle = sample
+            .lid_angle
+            .map(|x| format!("{}°", x))
+            .unwrap_or_else(|| "Unknown".to_string());
+        let tablet_mode = match sample.tablet_mode {
+            Some(true) => "Yes",
+            Some(false) => "No",
+            None => "Unknown",
+        };
+        outln!(
+            out,
+            "  {:>7} {:>13} {:>8} {:>9} {:>11}",
+            sample.time_ms,
+            fmt_level(sample.tablet_mode_l),
+            fmt_level(sample.lid_sw_l),
+            angle,
+            tablet_mode
+        );
+        previous = Some(sample);
+    }
+
+    let angles: Vec<u16> = samples.iter().filter_map(|s| s.lid_angle).collect();
+    outln!(out, "Summary:");
+    if let (Some(min), Some(max)) = (angles.iter().min(), angles.iter().max()) {
+        outln!(out, "  Angle range:  {}° - {}°", min, max);
+    } else {
+        outln!(out, "  Angle range:  Unknown");
+    }
+    let transitions = transitions(samples);
+    if transitions.is_empty() {
+        outln!(out, "  Tablet mode did not change");
+    }
+    for transition in transitions {
+        let angle = transition
+            .lid_angle
+            .map(|x| format!("{}°", x))
+            .unwrap_or_else(|| "unknown angle".to_string());
+        outln!(
+            out,
+            "  {:>5} ms: {} tablet mode at {}",
+            transition.time_ms,
+            if transition.entered {
+                "Entered"
+            } else {
+                "Left"
+            },
+            angle
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(time_ms: u64, lid_angle: u16, tablet_mode: bool) -> TabletSample {
+        TabletSample {
+            time_ms,
+            tablet_mode_l: Some(!tablet_mode),
+            lid_sw_l: Some(true),
+            lid_angle: Some(lid_angle),
+            tablet_mode: Some(tablet_mode),
+        }
+    }
+
+    #[test]
+    fn find_transitions() {
+        let samples = [
+            sample(0, 150, false),
+            sample(100, 170, false),
+            sample(200, 190, true),
+            sample(300, 200, true),
+            sample(400, 160, false),
+        ];
+        assert_eq!(
+            transitions(&samples),
+            vec![
+                TabletTransition {
+                    time_ms: 200,
+                    entered: true,
+                    lid_angle: Some(190),
+                },
+                TabletTransition {
+                    time_ms: 400,
+                    entered: false,
+                    lid_angle: Some(160),
+                },
+            ]
+        );
+
+        // Unknown state is not a transition
+        let mut unknown = sample(500, 200, true);
+        unknown.tablet_mode = None;
+        assert!(transitions(&[sample(0, 150, false), unknown]).is_empty());
+    }
+}
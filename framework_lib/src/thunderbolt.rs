@@ -0,0 +1,101 @@
+//! Get information about Thunderbolt/USB4 routers and their firmware
+//!
+//! Currently only works on Linux (from sysfs).
+//!
+//! Every router has NVM firmware, this includes the host router in the SoC,
+//! retimers and connected docks.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::output::{outln, OutputSink};
+
+/// Where the kernel exposes Thunderbolt/USB4 devices
+const SYSFS_THUNDERBOLT: &str = "/sys/bus/thunderbolt/devices";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouterKind {
+    /// Router of the host, built into the SoC
+    Host,
+    /// Connected device, like a dock
+    Device,
+    /// Retimer between host router and port
+    Retimer,
+}
+
+#[derive(Debug, Clone)]
+pub struct Router {
+    /// Name in sysfs, e.g. `0-0` for the host router of domain 0
+    pub name: String,
+    pub kind: RouterKind,
+    pub vendor: Option<String>,
+    pub device: Option<String>,
+    /// Version of the NVM firmware, e.g. `36.0`
+    pub nvm_version: Option<String>,
+    /// Thunderbolt generation, 4 means USB4
+    pub generation: Option<String>,
+}
+
+fn read_attr(dir: &Path, attr: &str) -> Option<String> {
+    fs::read_to_string(dir.join(attr))
+        .ok()
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+}
+
+/// Read all routers from a sysfs directory like `/sys/bus/thunderbolt/devices`
+pub fn routers_from_sysfs(dir: &Path) -> io::Result<Vec<Router>> {
+    let mut routers = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        // Skip domains and XDomain connections to other hosts
+        if name.starts_with("domain") || !name.contains('-') {
+            continue;
+        }
+        let path = entry.path();
+        // Only routers and retimers have NVM firmware
+        if !path.join("nvm_version").exists() && !path.join("unique_id").exists() {
+            continue;
+        }
+
+        let kind = if name.contains(':') {
+            RouterKind::Retimer
+        } else if name.ends_with("-0") {
+            RouterKind::Host
+        } else {
+            RouterKind::Device
+        };
+        routers.push(Router {
+            kind,
+            vendor: read_attr(&path, "vendor_name").or_else(|| read_attr(&path, "vendor")),
+            device: read_attr(&path, "device_name").or_else(|| read_attr(&path, "device")),
+            nvm_version: read_attr(&path, "nvm_version"),
+            generation: read_attr(&path, "generation"),
+            name,
+        });
+    }
+    routers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(routers)
+}
+
+pub fn get_routers() -> io::Result<Vec<Router>> {
+    routers_from_sysfs(Path::new(SYSFS_THUNDERBOLT))
+}
+
+pub fn print_routers(routers: &[Router], out: &mut dyn OutputSink) {
+    if routers.is_empty() {
+        outln!(out, "  No Thunderbolt/USB4 routers found");
+    }
+    for router in routers {
+        outln!(out, "  {} ({:?})", router.name, router.kind);
+        let field = |x: &Option<String>| x.as_deref().unwrap_or("Unknown").to_string();
+        out.write_table(&[
+            ("  Vendor", field(&router.vendor)),
+            ("  Device", field(&router.device)),
+            ("  Generation", field(&router.generation)),
+            ("  NVM Version", field(&router.nvm_version)),
+        ]);
+    }
+}
@@ -0,0 +1,268 @@
+//! Tray icon in the desktop session to control the daemon
+//!
+//! Shows a StatusNotifierItem, which KDE and most other desktops support (GNOME
+//! needs the AppIndicator extension). The menu changes the charge limit, fan
+//! duty and keyboard backlight sync through the daemon of `--daemon`, so the
+//! tray runs as the regular user. Polkit asks for authentication, if needed.
+
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use ksni::blocking::TrayMethods;
+use ksni::menu::{CheckmarkItem, RadioGroup, RadioItem, StandardItem, SubMenu};
+use ksni::{MenuItem, ToolTip};
+use zbus::blocking::Connection;
+use zbus::proxy;
+
+use crate::commandline::ExitCode;
+
+/// How often the settings are read again, in case something else changed them
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+/// Charge limits in percent that the menu offers
+const CHARGE_LIMITS: &[u8] = &[60, 70, 80, 90, 100];
+/// Fan duties in percent that the menu offers, None for automatic control
+const FAN_DUTIES: &[Option<u8>] = &[None, Some(25), Some(50), Some(75), Some(100)];
+
+/// Client side of the interface in `daemon`
+#[proxy(
+    interface = "org.frameworkcomputer.Tool",
+    default_service = "org.frameworkcomputer.Tool",
+    default_path = "/org/frameworkcomputer/Tool"
+)]
+trait Tool {
+    fn get_charge_limit(&self) -> zbus::Result<u8>;
+    fn set_charge_limit(&self, limit: u8) -> zbus::Result<()>;
+    fn get_fan_duty(&self) -> zbus::Result<i32>;
+    fn set_fan_duty(&self, percent: u8) -> zbus::Result<()>;
+    fn set_fan_auto(&self) -> zbus::Result<()>;
+    fn get_keyboard_backlight_sync(&self) -> zbus::Result<bool>;
+    fn set_keyboard_backlight_sync(&self, enabled: bool) -> zbus::Result<()>;
+}
+
+/// Settings as the daemon reports them
+#[derive(Debug, Clone, PartialEq)]
+struct Settings {
+    /// None if the system has no battery
+    charge_limit: Option<u8>,
+    /// None if the EC controls the fans
+    fan_duty: Option<u8>,
+    kblight_sync: bool,
+}
+
+impl Settings {
+    /// None if the daemon isn't running
+    fn read(proxy: &ToolProxyBlocking) -> Option<Self> {
+        let kblight_sync = match proxy.get_keyboard_backlight_sync() {
+            Ok(enabled) => enabled,
+            Err(err) => {
+                debug!("Failed to reach daemon: {:?}", err);
+                return None;
+            }
+        };
+        Some(Settings {
+            charge_limit: proxy.get_charge_limit().ok(),
+            fan_duty: proxy
+                .get_fan_duty()
+                .ok()
+                .and_then(|duty| u8::try_from(duty).ok()),
+            kblight_sync,
+        })
+    }
+
+    fn describe_fans(&self) -> String {
+        match self.fan_duty {
+            Some(duty) => format!("{}%", duty),
+            None => "Automatic".to_string(),
+        }
+    }
+}
+
+struct FrameworkTray {
+    proxy: ToolProxyBlocking<'static>,
+    /// None if the daemon isn't running
+    settings: Option<Settings>,
+    /// Asks to read the settings again
+    refresh: Sender<()>,
+}
+
+impl FrameworkTray {
+    /// Change a setting through the daemon in the background
+    ///
+    /// Might wait for the user to authenticate, which must not block the menu.
+    fn change(&self, change: impl FnOnce(&ToolProxyBlocking) -> zbus::Result<()> + Send + 'static) {
+        let proxy = self.proxy.clone();
+        let refresh = self.refresh.clone();
+        thread::spawn(move || {
+            if let Err(err) = change(&proxy) {
+                error!("Failed to change setting: {}", err);
+            }
+            let _ = refresh.send(());
+        });
+    }
+}
+
+impl ksni::Tray for FrameworkTray {
+    fn id(&self) -> String {
+        "framework_tool".to_string()
+    }
+
+    fn title(&self) -> String {
+        "Framework".to_string()
+    }
+
+    fn icon_name(&self) -> String {
+        "computer-laptop".to_string()
+    }
+
+    fn tool_tip(&self) -> ToolTip {
+        let description = match &self.settings {
+            Some(settings) => {
+                let limit = match settings.charge_limit {
+                    Some(limit) => format!("{}%", limit),
+                    None => "Not supported".to_string(),
+                };
+                format!(
+                    "Charge limit: {}\nFans: {}",
+                    limit,
+                    settings.describe_fans()
+                )
+            }
+            None => "Daemon not running".to_string(),
+        };
+        ToolTip {
+            title: self.title(),
+            description,
+            ..Default::default()
+        }
+    }
+
+    fn menu(&self) -> Vec<MenuItem<Self>> {
+        let Some(settings) = &self.settings else {
+            return vec![
+                StandardItem {
+                    label: "Daemon not running, start it with framework_tool --daemon".to_string(),
+                    enabled: false,
+                    ..Default::default()
+                }
+                .into(),
+                MenuItem::Separator,
+                quit_item(),
+            ];
+        };
+
+        let mut items = vec![];
+        if let Some(limit) = settings.charge_limit {
+            items.push(
+                SubMenu {
+                    label: format!("Charge limit: {}%", limit),
+                    submenu: vec![RadioGroup {
+                        selected: CHARGE_LIMITS
+                            .iter()
+                            .position(|x| *x == limit)
+                            .unwrap_or(usize::MAX),
+                        select: Box::new(|tray: &mut Self, i| {
+                            let limit = CHARGE_LIMITS[i];
+                            tray.change(move |proxy| proxy.set_charge_limit(limit));
+                        }),
+                        options: CHARGE_LIMITS
+                            .iter()
+                            .map(|limit| RadioItem {
+                                label: format!("{}%", limit),
+                                ..Default::default()
+                            })
+                            .collect(),
+                    }
+                    .into()],
+                    ..Default::default()
+                }
+                .into(),
+            );
+        }
+        items.push(
+            SubMenu {
+                label: format!("Fans: {}", settings.describe_fans()),
+                submenu: vec![RadioGroup {
+                    selected: FAN_DUTIES
+                        .iter()
+                        .position(|x| *x == settings.fan_duty)
+                        .unwrap_or(usize::MAX),
+                    select: Box::new(|tray: &mut Self, i| match FAN_DUTIES[i] {
+                        Some(duty) => tray.change(move |proxy| proxy.set_fan_duty(duty)),
+                        None => tray.change(|proxy| proxy.set_fan_auto()),
+                    }),
+                    options: FAN_DUTIES
+                        .iter()
+                        .map(|duty| RadioItem {
+                            label: match duty {
+                                Some(duty) => format!("{}%", duty),
+                                None => "Automatic".to_string(),
+                            },
+                            ..Default::default()
+                        })
+                        .collect(),
+                }
+                .into()],
+                ..Default::default()
+            }
+            .into(),
+        );
+        let kblight_sync = settings.kblight_sync;
+        items.push(
+            CheckmarkItem {
+                label: "Keyboard backlight follows AC and ambient light".to_string(),
+                checked: kblight_sync,
+                activate: Box::new(move |tray: &mut Self| {
+                    tray.change(move |proxy| proxy.set_keyboard_backlight_sync(!kblight_sync));
+                }),
+                ..Default::default()
+            }
+            .into(),
+        );
+        items.push(MenuItem::Separator);
+        items.push(quit_item());
+        items
+    }
+}
+
+fn quit_item() -> MenuItem<FrameworkTray> {
+    StandardItem {
+        label: "Quit".to_string(),
+        icon_name: "application-exit".to_string(),
+        activate: Box::new(|_| std::process::exit(0)),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Show the tray icon until the user quits it
+pub fn run() -> i32 {
+    let proxy = match Connection::system().and_then(|conn| ToolProxyBlocking::new(&conn)) {
+        Ok(proxy) => proxy,
+        Err(err) => {
+            error!("Failed to connect to the system bus: {:?}", err);
+            return ExitCode::Failure.into();
+        }
+    };
+    let (refresh, refresh_requested) = mpsc::channel();
+    let tray = FrameworkTray {
+        settings: Settings::read(&proxy),
+        proxy: proxy.clone(),
+        refresh,
+    };
+    let handle = match tray.spawn() {
+        Ok(handle) => handle,
+        Err(err) => {
+            error!("Failed to show tray icon: {:?}", err);
+            return ExitCode::Failure.into();
+        }
+    };
+
+    while !handle.is_closed() {
+        // Right after a change, or once the interval is over
+        let _ = refresh_requested.recv_timeout(REFRESH_INTERVAL);
+        let settings = Settings::read(&proxy);
+        handle.update(|tray| tray.settings = settings);
+    }
+    ExitCode::Success.into()
+}
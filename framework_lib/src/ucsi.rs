@@ -0,0 +1,431 @@
+//! Talk to the USB-C ports through UCSI, tunneled through the EC
+//!
+//! Newer EC firmware implements the UCSI PPM (Platform Policy Manager), which
+//! the `cros_ec_ucsi` driver on Linux uses. Sending the same commands directly
+//! helps to figure out whether a problem is in the OS driver or in the firmware.
+//!
+//! Only one client should talk to the PPM at a time. If the OS driver is
+//! active, the two can confuse each other.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::chromium_ec::command::EcRequestRaw;
+use crate::chromium_ec::commands::{EcFeatureCode, EcRequestUcsiPpmGet, EcRequestUcsiPpmSet};
+use crate::chromium_ec::{print_err_ref, CrosEc, EcError, EcResult, EcResultExt};
+use crate::os_specific;
+use crate::output::{outln, OutputSink};
+
+/// Offsets in the UCSI data structure
+const VERSION_OFFSET: u16 = 0x00;
+const CCI_OFFSET: u16 = 0x04;
+const CONTROL_OFFSET: u16 = 0x08;
+const MESSAGE_IN_OFFSET: u16 = 0x10;
+const MESSAGE_IN_SIZE: usize = 16;
+
+/// How often to check whether the PPM is done with a command
+const POLL_PERIOD_US: u64 = 10_000;
+const POLL_RETRIES: usize = 100;
+
+/// Command in the CONTROL register, see UCSI spec, chapter 4.5
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum UcsiCommand {
+    PpmReset = 0x01,
+    AckCcCi = 0x04,
+    GetCapability = 0x06,
+    GetConnectorCapability = 0x07,
+    GetPdos = 0x10,
+    GetConnectorStatus = 0x12,
+}
+
+/// With `AckCcCi`, acknowledge that the command completed
+const ACK_COMMAND_COMPLETE: u64 = 1 << 1;
+
+/// Command Status and Connector Change Indication, see UCSI spec, chapter 4.2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cci(pub u32);
+
+impl Cci {
+    /// Length of the response in MESSAGE_IN
+    pub fn data_len(&self) -> usize {
+        ((self.0 >> 8) & 0xFF) as usize
+    }
+    pub fn not_supported(&self) -> bool {
+        self.0 & (1 << 25) != 0
+    }
+    pub fn reset_complete(&self) -> bool {
+        self.0 & (1 << 27) != 0
+    }
+    pub fn busy(&self) -> bool {
+        self.0 & (1 << 28) != 0
+    }
+    pub fn ack_complete(&self) -> bool {
+        self.0 & (1 << 29) != 0
+    }
+    pub fn error(&self) -> bool {
+        self.0 & (1 << 30) != 0
+    }
+    pub fn command_complete(&self) -> bool {
+        self.0 & (1 << 31) != 0
+    }
+}
+
+fn ppm_read(ec: &CrosEc, offset: u16, size: usize) -> EcResult<Vec<u8>> {
+    let data = EcRequestUcsiPpmGet {
+        offset,
+        size: size as u8,
+    }
+    .send_command_vec(ec)?;
+    if data.len() < size {
+        return Err(EcError::DeviceError(format!(
+            "UCSI read returned {} bytes, expected {}",
+            data.len(),
+            size
+        )));
+    }
+    Ok(data)
+}
+
+fn ppm_write(ec: &CrosEc, offset: u16, data: &[u8]) -> EcResult<()> {
+    EcRequestUcsiPpmSet { offset }.send_command_vec_extra(ec, data)?;
+    Ok(())
+}
+
+fn read_cci(ec: &CrosEc) -> EcResult<Cci> {
+    let data = ppm_read(ec, CCI_OFFSET, 4)?;
+    Ok(Cci(u32::from_le_bytes([
+        data[0], data[1], data[2], data[3],
+    ])))
+}
+
+fn write_control(ec: &CrosEc, command: UcsiCommand, param: u64) -> EcResult<()> {
+    let control = command as u64 | (param << 16);
+    ppm_write(ec, CONTROL_OFFSET, &control.to_le_bytes())
+}
+
+/// Wait until the PPM has handled the last command
+fn wait_for(ec: &CrosEc, done: impl Fn(&Cci) -> bool) -> EcResult<Cci> {
+    for _ in 0..POLL_RETRIES {
+        let cci = read_cci(ec)?;
+        if done(&cci) {
+            return Ok(cci);
+        }
+        os_specific::sleep(POLL_PERIOD_US);
+    }
+    Err(EcError::DeviceError(
+        "Timed out waiting for UCSI command".to_string(),
+    ))
+}
+
+/// Version of the UCSI spec that the PPM implements, in BCD
+pub fn version(ec: &CrosEc) -> EcResult<u16> {
+    let data = ppm_read(ec, VERSION_OFFSET, 2).context("Failed to read UCSI version")?;
+    Ok(u16::from_le_bytes([data[0], data[1]]))
+}
+
+/// Send a command and return the response
+///
+/// `param` is the command specific part of CONTROL, starting at bit 16.
+pub fn send(ec: &CrosEc, command: UcsiCommand, param: u64) -> EcResult<Vec<u8>> {
+    write_control(ec, command, param)?;
+    let cci = wait_for(ec, |cci| cci.command_complete() && !cci.busy())?;
+
+    let response = if cci.error() || cci.not_supported() {
+        Err(EcError::DeviceError(format!(
+            "UCSI command {:?} failed, CCI: {:#010X}",
+            command, cci.0
+        )))
+    } else {
+        let len = cci.data_len().min(MESSAGE_IN_SIZE);
+        ppm_read(ec, MESSAGE_IN_OFFSET, len)
+    };
+
+    // The PPM doesn't take the next command until this one is acknowledged
+    write_control(ec, UcsiCommand::AckCcCi, ACK_COMMAND_COMPLETE)?;
+    wait_for(ec, Cci::ack_complete)?;
+    response
+}
+
+/// Reset the PPM, in case it's stuck
+pub fn reset(ec: &CrosEc) -> EcResult<()> {
+    write_control(ec, UcsiCommand::PpmReset, 0)?;
+    wait_for(ec, Cci::reset_complete)?;
+    Ok(())
+}
+
+/// Response to `GetCapability`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capability {
+    pub attributes: u32,
+    pub num_connectors: u8,
+    pub optional_features: u32,
+    pub num_alt_modes: u8,
+    /// Versions of the specs, in BCD
+    pub bc_version: u16,
+    pub pd_version: u16,
+    pub typec_version: u16,
+}
+
+impl Capability {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 16 {
+            return None;
+        }
+        Some(Capability {
+            attributes: u32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+            num_connectors: data[4] & 0x7F,
+            optional_features: u32::from_le_bytes([data[5], data[6], data[7], 0]),
+            num_alt_modes: data[8],
+            bc_version: u16::from_le_bytes([data[10], data[11]]),
+            pd_version: u16::from_le_bytes([data[12], data[13]]),
+            typec_version: u16::from_le_bytes([data[14], data[15]]),
+        })
+    }
+}
+
+pub fn get_capability(ec: &CrosEc) -> EcResult<Capability> {
+    let data = send(ec, UcsiCommand::GetCapability, 0)?;
+    Capability::from_bytes(&data)
+        .ok_or_else(|| EcError::DeviceError("Invalid UCSI capability".to_string()))
+}
+
+/// Response to `GetConnectorCapability`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectorCapability {
+    pub operation_mode: u8,
+    pub provider: bool,
+    pub consumer: bool,
+}
+
+impl ConnectorCapability {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 2 {
+            return None;
+        }
+        Some(ConnectorCapability {
+            operation_mode: data[0],
+            provider: data[1] & 0x01 != 0,
+            consumer: data[1] & 0x02 != 0,
+        })
+    }
+}
+
+/// Connectors are numbered starting from 1
+pub fn get_connector_capability(ec: &CrosEc, connector: u8) -> EcResult<ConnectorCapability> {
+    let data = send(ec, UcsiCommand::GetConnectorCapability, connector as u64)?;
+    ConnectorCapability::from_bytes(&data)
+        .ok_or_else(|| EcError::DeviceError("Invalid UCSI connector capability".to_string()))
+}
+
+/// Response to `GetConnectorStatus`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectorStatus {
+    /// Which of the fields changed since the last acknowledgement
+    pub change: u16,
+    pub power_operation_mode: u8,
+    pub connected: bool,
+    /// Whether the port provides power, as opposed to consuming it
+    pub provider: bool,
+    pub partner_flags: u8,
+    pub partner_type: u8,
+    /// Request Data Object, if a PD contract is in place
+    pub rdo: u32,
+}
+
+impl ConnectorStatus {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let status = u16::from_le_bytes([data[2], data[3]]);
+        Some(ConnectorStatus {
+            change: u16::from_le_bytes([data[0], data[1]]),
+            power_operation_mode: (status & 0x07) as u8,
+            connected: status & (1 << 3) != 0,
+            provider: status & (1 << 4) != 0,
+            partner_flags: ((status >> 5) & 0xFF) as u8,
+            partner_type: ((status >> 13) & 0x07) as u8,
+            rdo: u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+        })
+    }
+
+    pub fn power_operation_mode_name(&self) -> &'static str {
+        match self.power_operation_mode {
+            1 => "USB Default",
+            2 => "BC",
+            3 => "PD",
+            4 => "USB-C 1.5A",
+            5 => "USB-C 3A",
+            6 => "USB-C 5A",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn partner_type_name(&self) -> &'static str {
+        match self.partner_type {
+            1 => "DFP",
+            2 => "UFP",
+            3 => "Powered cable, no UFP",
+            4 => "Powered cable and UFP",
+            5 => "Debug accessory",
+            6 => "Audio accessory",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Connectors are numbered starting from 1
+pub fn get_connector_status(ec: &CrosEc, connector: u8) -> EcResult<ConnectorStatus> {
+    let data = send(ec, UcsiCommand::GetConnectorStatus, connector as u64)?;
+    ConnectorStatus::from_bytes(&data)
+        .ok_or_else(|| EcError::DeviceError("Invalid UCSI connector status".to_string()))
+}
+
+/// Maximum number of PDOs that a source can advertise
+const MAX_PDOS: u8 = 7;
+/// Maximum number of PDOs that fit in one `GetPdos` response
+const PDOS_PER_COMMAND: u8 = 4;
+
+/// Source capabilities that the partner on a connector advertises
+///
+/// Connectors are numbered starting from 1. Each PDO is still encoded, as
+/// defined by the USB PD spec.
+pub fn get_partner_source_pdos(ec: &CrosEc, connector: u8) -> EcResult<Vec<u32>> {
+    let mut pdos = Vec::new();
+    let mut offset = 0;
+    while offset < MAX_PDOS {
+        let count = PDOS_PER_COMMAND.min(MAX_PDOS - offset);
+        let param = connector as u64
+            // Partner PDOs
+            | 1 << 7
+            | (offset as u64) << 8
+            | ((count - 1) as u64) << 16
+            // Source PDOs
+            | 1 << 18;
+        let data = send(ec, UcsiCommand::GetPdos, param)?;
+        let received = data
+            .chunks_exact(4)
+            .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .take_while(|pdo| *pdo != 0);
+        let before = pdos.len();
+        pdos.extend(received);
+        if pdos.len() - before < count as usize {
+            break;
+        }
+        offset += count;
+    }
+    Ok(pdos)
+}
+
+fn bcd(version: u16) -> String {
+    format!("{:X}.{:X}", version >> 8, (version >> 4) & 0xF)
+}
+
+/// Print capabilities of the PPM and the status of all connectors
+pub fn print_ucsi(ec: &CrosEc, out: &mut dyn OutputSink) -> i32 {
+    match ec.has_feature(EcFeatureCode::UcsiPpm) {
+        Ok(true) => {}
+        Ok(false) => {
+            outln!(out, "The EC firmware doesn't support UCSI");
+            return 1;
+        }
+        Err(err) => {
+            print_err_ref::<()>(&Err(err));
+            return 1;
+        }
+    }
+
+    let res = version(ec).and_then(|version| {
+        let cap = get_capability(ec)?;
+        outln!(out, "UCSI");
+        outln!(out, "  Version:         {}", bcd(version));
+        outln!(out, "  Connectors:      {}", cap.num_connectors);
+        outln!(out, "  Alt Modes:       {}", cap.num_alt_modes);
+        outln!(out, "  Attributes:      {:#010X}", cap.attributes);
+        outln!(out, "  Features:        {:#08X}", cap.optional_features);
+        outln!(out, "  BC Version:      {}", bcd(cap.bc_version));
+        outln!(out, "  PD Version:      {}", bcd(cap.pd_version));
+        outln!(out, "  USB-C Version:   {}", bcd(cap.typec_version));
+        Ok(cap)
+    });
+    let cap = match res {
+        Ok(cap) => cap,
+        Err(err) => {
+            print_err_ref::<()>(&Err(err));
+            return 1;
+        }
+    };
+
+    let mut ret = 0;
+    for connector in 1..=cap.num_connectors {
+        outln!(out, "Connector {}", connector);
+        match get_connector_capability(ec, connector) {
+            Ok(cap) => {
+                outln!(out, "  Operation Mode:  {:#04X}", cap.operation_mode);
+                outln!(out, "  Provider:        {}", cap.provider);
+                outln!(out, "  Consumer:        {}", cap.consumer);
+            }
+            Err(err) => {
+                print_err_ref::<()>(&Err(err));
+                ret = 1;
+            }
+        }
+        match get_connector_status(ec, connector) {
+            Ok(status) => {
+                outln!(out, "  Connected:       {}", status.connected);
+                if status.connected {
+                    outln!(
+                        out,
+                        "  Power Role:      {}",
+                        if status.provider { "Source" } else { "Sink" }
+                    );
+                    outln!(
+                        out,
+                        "  Power Mode:      {}",
+                        status.power_operation_mode_name()
+                    );
+                    outln!(out, "  Partner:         {}", status.partner_type_name());
+                    outln!(out, "  Partner Flags:   {:#04X}", status.partner_flags);
+                    outln!(out, "  RDO:             {:#010X}", status.rdo);
+                }
+                outln!(out, "  Pending Changes: {:#06X}", status.change);
+            }
+            Err(err) => {
+                print_err_ref::<()>(&Err(err));
+                ret = 1;
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_connector_status() {
+        // Connected, sink, PD contract with a UFP
+        let data = [0x00, 0x00, 0x0B, 0x40, 0x00, 0x00, 0x00, 0x10];
+        let status = ConnectorStatus::from_bytes(&data).unwrap();
+        assert!(status.connected);
+        assert!(!status.provider);
+        assert_eq!(status.power_operation_mode_name(), "PD");
+        assert_eq!(status.partner_type_name(), "UFP");
+        assert_eq!(status.rdo, 0x1000_0000);
+        assert_eq!(ConnectorStatus::from_bytes(&data[..4]), None);
+    }
+
+    #[test]
+    fn cci_bits() {
+        let cci = Cci(0x8000_1000);
+        assert!(cci.command_complete());
+        assert!(!cci.error());
+        assert_eq!(cci.data_len(), 0x10);
+        assert!(Cci(1 << 29).ack_complete());
+    }
+}
@@ -0,0 +1,95 @@
+//! udev rules to let regular users access Framework USB and HID devices
+//!
+//! By default only root can open the hidraw and USB device nodes. The rules
+//! tag them with `uaccess`, so that the user logged in at the seat gets access.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::Command;
+
+use crate::devices::{FRAMEWORK_DEVICES, FRAMEWORK_VID};
+
+pub const RULES_PATH: &str = "/etc/udev/rules.d/50-framework.rules";
+
+pub fn rules() -> String {
+    let mut rules = String::from("# Generated by framework_tool --install-udev-rules\n");
+    for (pid, name) in FRAMEWORK_DEVICES {
+        rules.push_str(&format!("\n# {}\n", name));
+        for subsystem in ["hidraw", "usb"] {
+            rules.push_str(&format!(
+                "SUBSYSTEM==\"{}\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", TAG+=\"uaccess\"\n",
+                subsystem, FRAMEWORK_VID, pid
+            ));
+        }
+    }
+    rules
+}
+
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Print the rules and install them, after asking the user
+///
+/// With `force` it doesn't ask. Returns whether the rules are installed.
+pub fn install_rules(force: bool) -> bool {
+    let rules = rules();
+    println!("{}", rules);
+    if !force && !confirm(&format!("Install the rules to {}?", RULES_PATH)) {
+        println!(
+            "Not installing. Save the rules above to {} manually, or run again with --force",
+            RULES_PATH
+        );
+        return false;
+    }
+
+    if let Err(err) = std::fs::write(RULES_PATH, rules) {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            println!(
+                "Permission denied writing {}. Run again as root.",
+                RULES_PATH
+            );
+        } else {
+            println!("Failed to write {}: {:?}", RULES_PATH, err);
+        }
+        return false;
+    }
+    println!("Installed {}", RULES_PATH);
+
+    // Apply to devices that are already connected, otherwise they'd have to be replugged
+    for args in [&["control", "--reload-rules"][..], &["trigger"][..]] {
+        match Command::new("udevadm").args(args).status() {
+            Ok(status) if status.success() => {}
+            res => {
+                println!("Failed to run udevadm {}: {:?}", args.join(" "), res);
+                println!("Reconnect the devices for the rules to take effect.");
+                return true;
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdmi_card_rules() {
+        let rules = rules();
+        assert!(rules.contains(
+            "SUBSYSTEM==\"hidraw\", ATTRS{idVendor}==\"32ac\", ATTRS{idProduct}==\"0002\", TAG+=\"uaccess\"\n"
+        ));
+        assert!(rules.contains(
+            "SUBSYSTEM==\"usb\", ATTRS{idVendor}==\"32ac\", ATTRS{idProduct}==\"0010\", TAG+=\"uaccess\"\n"
+        ));
+    }
+}
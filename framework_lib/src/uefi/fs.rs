@@ -1,10 +1,17 @@
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
+use core::prelude::rust_2021::derive;
 use uefi::prelude::*;
+use uefi::proto::media::file::{Directory, File, FileAttribute, FileInfo, FileMode};
 use uefi::proto::shell::FileOpenMode;
-use uefi::Result;
+use uefi::{CString16, Result};
 
-use super::find_shell_handle;
+use super::{find_shell_handle, get_system_table, select};
+
+/// How deep to look into subdirectories for firmware files
+const MAX_SCAN_DEPTH: usize = 3;
 
 pub fn wstr(string: &str) -> Vec<u16> {
     let mut wstring = vec![];
@@ -17,6 +24,51 @@ pub fn wstr(string: &str) -> Vec<u16> {
     wstring
 }
 
+/// Read a file
+///
+/// Absolute paths without a mapping, like `\EFI\Framework\ec.bin`, are read
+/// from the volume that this application was started from. That's where
+/// `pick_file` finds them. Other paths are resolved by the shell, relative to
+/// its current directory.
+pub fn read_file(path: &str) -> Option<Vec<u8>> {
+    if path.starts_with('\\') {
+        image_read_file(path)
+    } else {
+        shell_read_file(path)
+    }
+}
+
+/// Read a file from the volume that this application was started from
+fn image_read_file(path: &str) -> Option<Vec<u8>> {
+    let bs = get_system_table().boot_services();
+    let mut fs = bs.get_image_file_system(bs.image_handle()).ok()?;
+    let mut root = fs.open_volume().ok()?;
+    let c_path = CString16::try_from(path.trim_start_matches('\\')).ok()?;
+    let file = root.open(&c_path, FileMode::Read, FileAttribute::empty());
+    let mut file = if let Some(file) = file.ok().and_then(|file| file.into_regular_file()) {
+        file
+    } else {
+        println!("Failed to open file: {}", path);
+        return None;
+    };
+    let info = file.get_boxed_info::<FileInfo>().ok()?;
+
+    let mut buffer: Vec<u8> = vec![0; info.file_size() as usize];
+    let mut read = 0;
+    while read < buffer.len() {
+        match file.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(len) => read += len,
+            Err(err) => {
+                println!("Failed to read file: {:?}", err);
+                return None;
+            }
+        }
+    }
+    buffer.truncate(read);
+    Some(buffer)
+}
+
 pub fn shell_read_file(path: &str) -> Option<Vec<u8>> {
     let shell = if let Some(shell) = find_shell_handle() {
         shell
@@ -130,3 +182,120 @@ pub fn shell_write_file(path: &str, data: &[u8]) -> Result {
 
     Status::SUCCESS.into()
 }
+
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    /// Absolute path, separated by backslashes, e.g. `\EFI\Boot\bootx64.efi`
+    pub path: String,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+fn open_dir(path: &str) -> Option<Directory> {
+    let bs = get_system_table().boot_services();
+    let mut fs = bs.get_image_file_system(bs.image_handle()).ok()?;
+    let mut root = fs.open_volume().ok()?;
+
+    let path = path.trim_matches('\\');
+    if path.is_empty() {
+        return Some(root);
+    }
+    let c_path = CString16::try_from(path).ok()?;
+    root.open(&c_path, FileMode::Read, FileAttribute::empty())
+        .ok()?
+        .into_directory()
+}
+
+/// List a directory on the volume that this application was started from
+///
+/// That's usually the ESP or the USB drive with the firmware update.
+/// The path is absolute, e.g. `\EFI\Framework`.
+pub fn list_dir(path: &str) -> Option<Vec<DirEntry>> {
+    let mut dir = open_dir(path)?;
+    let mut entries = vec![];
+    while let Ok(Some(info)) = dir.read_entry_boxed() {
+        let name = info.file_name().to_string();
+        if name == "." || name == ".." {
+            continue;
+        }
+        entries.push(DirEntry {
+            path: format!("{}\\{}", path.trim_end_matches('\\'), name),
+            size: info.file_size(),
+            is_dir: info.attribute().contains(FileAttribute::DIRECTORY),
+            name,
+        });
+    }
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Some(entries)
+}
+
+/// Kinds of firmware files that can be used for updates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FirmwareFileKind {
+    /// EC firmware binary, like `ec.bin`
+    Ec,
+    /// UEFI capsule, like `winux.cap` or a BIOS capsule
+    Capsule,
+}
+
+impl FirmwareFileKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.ends_with(".cap") {
+            Some(Self::Capsule)
+        } else if name.starts_with("ec") && name.ends_with(".bin") {
+            Some(Self::Ec)
+        } else {
+            None
+        }
+    }
+}
+
+fn scan_dir(path: &str, depth: usize, kinds: &[FirmwareFileKind], found: &mut Vec<DirEntry>) {
+    let entries = if let Some(entries) = list_dir(path) {
+        entries
+    } else {
+        return;
+    };
+    for entry in entries {
+        if entry.is_dir {
+            if depth < MAX_SCAN_DEPTH {
+                scan_dir(&entry.path, depth + 1, kinds, found);
+            }
+        } else if let Some(kind) = FirmwareFileKind::from_name(&entry.name) {
+            if kinds.contains(&kind) {
+                found.push(entry);
+            }
+        }
+    }
+}
+
+/// Find firmware files of the given kinds on the volume that this application was started from
+pub fn find_firmware_files(kinds: &[FirmwareFileKind]) -> Vec<DirEntry> {
+    let mut found = vec![];
+    scan_dir("", 0, kinds, &mut found);
+    found
+}
+
+/// Let the user select a file with the arrow keys and enter
+///
+/// Returns the path of the file, or None if the user pressed escape.
+pub fn pick_file(title: &str, files: &[DirEntry]) -> Option<String> {
+    let items: Vec<String> = files
+        .iter()
+        .map(|file| format!("{} ({} KB)", file.path, file.size / 1024))
+        .collect();
+    let selected = select(title, &items)?;
+    Some(files[selected].path.clone())
+}
+
+/// Look for firmware files and let the user pick one of them
+pub fn pick_firmware_file(kinds: &[FirmwareFileKind]) -> Option<String> {
+    let files = find_firmware_files(kinds);
+    if files.is_empty() {
+        println!("No firmware files ({:?}) found", kinds);
+        return None;
+    }
+    pick_file("Select firmware file", &files)
+}
@@ -1,5 +1,7 @@
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::slice;
+use uefi::proto::console::text::{Key, ScanCode};
 use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol, SearchType};
 
 #[allow(unused_imports)]
@@ -10,6 +12,7 @@ use uefi::table::{Boot, SystemTable};
 use uefi::Identify;
 
 pub mod fs;
+pub mod usb;
 
 pub fn get_system_table() -> &'static SystemTable<Boot> {
     unsafe { uefi_services::system_table().as_ref() }
@@ -99,6 +102,83 @@ pub fn enable_page_break() {
     }
 }
 
+/// Wait until the user presses a key and return it
+pub fn wait_for_key() -> Key {
+    let st = unsafe { uefi_services::system_table().as_mut() };
+    loop {
+        if let Ok(Some(key)) = st.stdin().read_key() {
+            return key;
+        }
+        // 10ms
+        st.boot_services().stall(10_000);
+    }
+}
+
+/// Read a line that the user typed, echoing it back
+///
+/// Returns None if the user pressed escape.
+pub fn read_line() -> Option<String> {
+    let mut line = String::new();
+    loop {
+        match wait_for_key() {
+            Key::Special(ScanCode::ESCAPE) => return None,
+            Key::Printable(c) => match char::from(c) {
+                '\r' => {
+                    println!();
+                    return Some(line);
+                }
+                // Backspace
+                '\u{8}' => {
+                    if line.pop().is_some() {
+                        print!("\u{8} \u{8}");
+                    }
+                }
+                c => {
+                    line.push(c);
+                    print!("{}", c);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+pub fn clear_screen() {
+    let st = unsafe { uefi_services::system_table().as_mut() };
+    let _ = st.stdout().clear();
+}
+
+/// Let the user choose one of the items with the arrow keys and enter
+///
+/// Returns the index of the item, or None if the user pressed escape.
+pub fn select(title: &str, items: &[String]) -> Option<usize> {
+    if items.is_empty() {
+        return None;
+    }
+    let mut selected = 0;
+    loop {
+        clear_screen();
+        println!("{}", title);
+        println!();
+        for (i, item) in items.iter().enumerate() {
+            let marker = if i == selected { ">" } else { " " };
+            println!("{} {}", marker, item);
+        }
+        println!();
+        println!("Up/Down to select, Enter to confirm, Escape to cancel");
+
+        match wait_for_key() {
+            Key::Special(ScanCode::UP) => selected = selected.saturating_sub(1),
+            Key::Special(ScanCode::DOWN) => {
+                selected = core::cmp::min(selected + 1, items.len() - 1)
+            }
+            Key::Special(ScanCode::ESCAPE) => return None,
+            Key::Printable(c) if char::from(c) == '\r' => return Some(selected),
+            _ => {}
+        }
+    }
+}
+
 #[repr(packed)]
 pub struct Smbios {
     pub anchor: [u8; 4],
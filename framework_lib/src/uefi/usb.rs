@@ -0,0 +1,123 @@
+//! List USB devices through the UEFI USB I/O protocol
+//!
+//! Only reads the descriptors, which is enough to find out which devices are
+//! connected and their version. Talking to them isn't supported.
+
+use alloc::vec::Vec;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace};
+use uefi::proto::unsafe_protocol;
+use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams, SearchType};
+use uefi::{Identify, Status};
+
+/// USB device descriptor, see USB 2.0 spec, chapter 9.6.1
+#[repr(C, packed)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(dead_code)]
+struct DeviceDescriptor {
+    length: u8,
+    descriptor_type: u8,
+    bcd_usb: u16,
+    device_class: u8,
+    device_subclass: u8,
+    device_protocol: u8,
+    max_packet_size0: u8,
+    id_vendor: u16,
+    id_product: u16,
+    bcd_device: u16,
+    str_manufacturer: u8,
+    str_product: u8,
+    str_serial_number: u8,
+    num_configurations: u8,
+}
+
+/// USB interface descriptor, see USB 2.0 spec, chapter 9.6.5
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+#[allow(dead_code)]
+struct InterfaceDescriptor {
+    length: u8,
+    descriptor_type: u8,
+    interface_number: u8,
+    alternate_setting: u8,
+    num_endpoints: u8,
+    interface_class: u8,
+    interface_subclass: u8,
+    interface_protocol: u8,
+    interface: u8,
+}
+
+/// EFI_USB_IO_PROTOCOL, see UEFI spec, chapter 17.2.4
+#[repr(C)]
+#[unsafe_protocol("2b2f68d6-0cd2-44cf-8e8b-bba20b1b5b75")]
+struct UsbIo {
+    /// Control, bulk, interrupt and isochronous transfers, not used
+    _transfer: [usize; 6],
+    get_device_descriptor:
+        unsafe extern "efiapi" fn(this: *const UsbIo, desc: *mut DeviceDescriptor) -> Status,
+    _get_config_descriptor: usize,
+    get_interface_descriptor:
+        unsafe extern "efiapi" fn(this: *const UsbIo, desc: *mut InterfaceDescriptor) -> Status,
+    /// Endpoint and string descriptors, languages and port reset, not used
+    _rest: [usize; 4],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UsbDevice {
+    pub vid: u16,
+    pub pid: u16,
+    /// Device release number, in BCD
+    pub bcd_device: u16,
+}
+
+/// All connected USB devices
+///
+/// The firmware installs the protocol once per interface, so devices with
+/// several interfaces are only listed once, by their first interface.
+pub fn list_devices() -> Vec<UsbDevice> {
+    let st = unsafe { uefi_services::system_table().as_ref() };
+    let boot_services = st.boot_services();
+    let handles = match boot_services.locate_handle_buffer(SearchType::ByProtocol(&UsbIo::GUID)) {
+        Ok(handles) => handles,
+        Err(err) => {
+            debug!("No USB I/O handles found: {:?}", err);
+            return Vec::new();
+        }
+    };
+
+    let mut devices = Vec::new();
+    for handle in &*handles {
+        let usb_io = unsafe {
+            boot_services.open_protocol::<UsbIo>(
+                OpenProtocolParams {
+                    handle: *handle,
+                    agent: boot_services.image_handle(),
+                    controller: None,
+                },
+                OpenProtocolAttributes::GetProtocol,
+            )
+        };
+        let Ok(usb_io) = usb_io else {
+            continue;
+        };
+
+        let mut interface = InterfaceDescriptor::default();
+        let status = unsafe { (usb_io.get_interface_descriptor)(&*usb_io, &mut interface) };
+        if !status.is_success() || interface.interface_number != 0 {
+            continue;
+        }
+        let mut desc = DeviceDescriptor::default();
+        let status = unsafe { (usb_io.get_device_descriptor)(&*usb_io, &mut desc) };
+        if !status.is_success() {
+            debug!("Failed to get USB device descriptor: {:?}", status);
+            continue;
+        }
+        devices.push(UsbDevice {
+            vid: desc.id_vendor,
+            pid: desc.id_product,
+            bcd_device: desc.bcd_device,
+        });
+    }
+    devices
+}
@@ -0,0 +1,451 @@
+//! Read UEFI variables that help diagnose firmware updates and boot issues
+//!
+//! The Insyde H2O BIOS reports the result of each processed capsule the same
+//! way as EDK2, in `CapsuleXXXX` variables. Together with the ESRT this shows
+//! why a BIOS update failed.
+//!
+//! - Linux: efivarfs, needs root to read some variables
+//! - Windows: `GetFirmwareEnvironmentVariableW`, needs administrator privileges
+//! - UEFI: Runtime services
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace};
+use std::prelude::v1::*;
+
+use core::fmt;
+
+use crate::esrt::guid_name;
+#[cfg(not(feature = "uefi"))]
+use crate::guid::Guid;
+use crate::output::{outln, OutputSink};
+#[cfg(not(feature = "uefi"))]
+use guid_macros::guid;
+#[cfg(feature = "uefi")]
+use uefi::{guid, Guid};
+
+/// gEfiGlobalVariableGuid from MdePkg/MdePkg.dec
+pub const EFI_GLOBAL_VARIABLE_GUID: Guid = guid!("8be4df61-93ca-11d2-aa0d-00e098032b8c");
+/// gEfiCapsuleReportGuid from MdePkg/MdePkg.dec
+pub const EFI_CAPSULE_REPORT_GUID: Guid = guid!("39b68c46-f7fb-441b-b6ec-16b0f69821f3");
+/// gEfiFmpCapsuleGuid from MdePkg/MdePkg.dec
+pub const EFI_FMP_CAPSULE_GUID: Guid = guid!("6dcbd5ed-e82d-4c44-bda1-7194199ad92a");
+
+/// Size of EFI_CAPSULE_RESULT_VARIABLE_HEADER on x86_64
+const CAPSULE_RESULT_HEADER_SIZE: usize = 48;
+/// Size of EFI_CAPSULE_RESULT_VARIABLE_FMP, without the strings
+const CAPSULE_RESULT_FMP_SIZE: usize = 20;
+/// Highest bit of EFI_STATUS on x86_64
+const EFI_ERROR_BIT: u64 = 1 << 63;
+
+/// Read the raw content of a UEFI variable, without attributes
+#[cfg(all(not(feature = "uefi"), feature = "linux", target_os = "linux"))]
+pub fn read_variable(name: &str, vendor: &Guid) -> Option<Vec<u8>> {
+    let path = format!("/sys/firmware/efi/efivars/{}-{}", name, vendor);
+    match std::fs::read(&path) {
+        // The first 4 bytes are the attributes
+        Ok(data) if data.len() >= 4 => Some(data[4..].to_vec()),
+        Ok(_) => None,
+        Err(err) => {
+            debug!("Failed to read {}: {:?}", path, err);
+            None
+        }
+    }
+}
+
+/// Read the raw content of a UEFI variable, without attributes
+#[cfg(all(not(feature = "uefi"), feature = "windows"))]
+pub fn read_variable(name: &str, vendor: &Guid) -> Option<Vec<u8>> {
+    use windows::core::HSTRING;
+    use windows::Win32::System::WindowsProgramming::GetFirmwareEnvironmentVariableW;
+
+    enable_system_environment_privilege();
+
+    let name = HSTRING::from(name);
+    let vendor = HSTRING::from(format!("{{{}}}", vendor));
+    let mut buf = vec![0u8; 4096];
+    let len = unsafe {
+        GetFirmwareEnvironmentVariableW(
+            &name,
+            &vendor,
+            Some(buf.as_mut_ptr() as *mut _),
+            buf.len() as u32,
+        )
+    };
+    if len == 0 {
+        debug!(
+            "Failed to read {}: {:?}",
+            name,
+            std::io::Error::last_os_error()
+        );
+        return None;
+    }
+    buf.truncate(len as usize);
+    Some(buf)
+}
+
+/// Reading UEFI variables on Windows needs SeSystemEnvironmentPrivilege
+///
+/// Administrators have it, but it's not enabled by default.
+#[cfg(all(not(feature = "uefi"), feature = "windows"))]
+fn enable_system_environment_privilege() {
+    use windows::core::{w, PCWSTR};
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, LUID};
+    use windows::Win32::Security::{
+        AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED,
+        TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = HANDLE::default();
+        if let Err(err) = OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+            &mut token,
+        ) {
+            debug!("Failed to open process token: {:?}", err);
+            return;
+        }
+        let mut luid = LUID::default();
+        if let Err(err) = LookupPrivilegeValueW(
+            PCWSTR::null(),
+            w!("SeSystemEnvironmentPrivilege"),
+            &mut luid,
+        ) {
+            debug!("Failed to look up privilege: {:?}", err);
+            let _ = CloseHandle(token);
+            return;
+        }
+        let privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES {
+                Luid: luid,
+                Attributes: SE_PRIVILEGE_ENABLED,
+            }],
+        };
+        if let Err(err) = AdjustTokenPrivileges(token, false, Some(&privileges), 0, None, None) {
+            debug!("Failed to enable privilege: {:?}", err);
+        }
+        let _ = CloseHandle(token);
+    }
+}
+
+/// Read the raw content of a UEFI variable, without attributes
+#[cfg(feature = "uefi")]
+pub fn read_variable(name: &str, vendor: &Guid) -> Option<Vec<u8>> {
+    use uefi::table::runtime::VariableVendor;
+    use uefi::CString16;
+
+    let rt = crate::uefi::get_system_table().runtime_services();
+    let name = CString16::try_from(name).ok()?;
+    let vendor = VariableVendor(*vendor);
+    let size = rt.get_variable_size(&name, &vendor).ok()?;
+    let mut buf = vec![0u8; size];
+    let (data, _attributes) = rt.get_variable(&name, &vendor, &mut buf).ok()?;
+    Some(data.to_vec())
+}
+
+#[cfg(not(any(
+    feature = "uefi",
+    feature = "windows",
+    all(feature = "linux", target_os = "linux")
+)))]
+pub fn read_variable(_name: &str, _vendor: &Guid) -> Option<Vec<u8>> {
+    None
+}
+
+/// EFI_TIME
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl EfiTime {
+    fn from_bytes(data: &[u8]) -> Self {
+        EfiTime {
+            year: u16::from_le_bytes([data[0], data[1]]),
+            month: data[2],
+            day: data[3],
+            hour: data[4],
+            minute: data[5],
+            second: data[6],
+        }
+    }
+}
+
+impl fmt::Display for EfiTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Human readable name of an EFI_STATUS
+pub fn efi_status_name(status: u64) -> String {
+    if status == 0 {
+        return "Success".to_string();
+    }
+    let name = match status & !EFI_ERROR_BIT {
+        1 => "Load Error",
+        2 => "Invalid Parameter",
+        3 => "Unsupported",
+        4 => "Bad Buffer Size",
+        5 => "Buffer Too Small",
+        6 => "Not Ready",
+        7 => "Device Error",
+        8 => "Write Protected",
+        9 => "Out Of Resources",
+        14 => "Not Found",
+        15 => "Access Denied",
+        21 => "Aborted",
+        25 => "Incompatible Version",
+        26 => "Security Violation",
+        27 => "CRC Error",
+        _ => "Unknown",
+    };
+    format!("{} (0x{:X})", name, status)
+}
+
+/// Details of FMP capsules, EFI_CAPSULE_RESULT_VARIABLE_FMP
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapsuleResultFmp {
+    pub version: u16,
+    pub payload_index: u8,
+    pub update_image_index: u8,
+    pub update_image_type_id: Guid,
+    pub file_name: String,
+    pub target: String,
+}
+
+/// Result of processing one capsule, EFI_CAPSULE_RESULT_VARIABLE_HEADER
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapsuleResult {
+    pub capsule_guid: Guid,
+    pub processed: EfiTime,
+    /// EFI_STATUS
+    pub status: u64,
+    pub fmp: Option<CapsuleResultFmp>,
+}
+
+impl CapsuleResult {
+    pub fn is_success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+fn guid_from_slice(data: &[u8]) -> Guid {
+    Guid::from_bytes(data[..16].try_into().unwrap())
+}
+
+/// Decode a null terminated UTF-16 string, return it and the rest of the buffer
+fn split_utf16(data: &[u8]) -> (String, &[u8]) {
+    let mut chars = vec![];
+    let mut offset = 0;
+    while offset + 2 <= data.len() {
+        let c = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        offset += 2;
+        if c == 0 {
+            break;
+        }
+        chars.push(c);
+    }
+    (String::from_utf16_lossy(&chars), &data[offset..])
+}
+
+pub fn parse_capsule_result(data: &[u8]) -> Option<CapsuleResult> {
+    if data.len() < CAPSULE_RESULT_HEADER_SIZE {
+        return None;
+    }
+    let total_size = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+    let data = &data[..total_size.clamp(CAPSULE_RESULT_HEADER_SIZE, data.len())];
+
+    let capsule_guid = guid_from_slice(&data[8..24]);
+    let fmp_data = &data[CAPSULE_RESULT_HEADER_SIZE..];
+    let fmp = if capsule_guid == EFI_FMP_CAPSULE_GUID && fmp_data.len() >= CAPSULE_RESULT_FMP_SIZE {
+        let (file_name, rest) = split_utf16(&fmp_data[CAPSULE_RESULT_FMP_SIZE..]);
+        let (target, _) = split_utf16(rest);
+        Some(CapsuleResultFmp {
+            version: u16::from_le_bytes([fmp_data[0], fmp_data[1]]),
+            payload_index: fmp_data[2],
+            update_image_index: fmp_data[3],
+            update_image_type_id: guid_from_slice(&fmp_data[4..20]),
+            file_name,
+            target,
+        })
+    } else {
+        None
+    };
+
+    Some(CapsuleResult {
+        capsule_guid,
+        processed: EfiTime::from_bytes(&data[24..40]),
+        status: u64::from_le_bytes(data[40..48].try_into().unwrap()),
+        fmp,
+    })
+}
+
+/// Parse the index out of a variable name like `Capsule000A`
+fn capsule_index(name: &str) -> Option<u16> {
+    u16::from_str_radix(name.strip_prefix("Capsule")?, 16).ok()
+}
+
+fn read_string(name: &str, vendor: &Guid) -> Option<String> {
+    read_variable(name, vendor).map(|data| split_utf16(&data).0)
+}
+
+fn read_u8(name: &str) -> Option<u8> {
+    read_variable(name, &EFI_GLOBAL_VARIABLE_GUID)?
+        .first()
+        .copied()
+}
+
+fn read_u16_list(name: &str) -> Option<Vec<u16>> {
+    let data = read_variable(name, &EFI_GLOBAL_VARIABLE_GUID)?;
+    Some(
+        data.chunks_exact(2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]))
+            .collect(),
+    )
+}
+
+fn fmt_bool(val: Option<u8>) -> String {
+    match val {
+        Some(0) => "Disabled".to_string(),
+        Some(1) => "Enabled".to_string(),
+        Some(x) => format!("Unknown ({})", x),
+        None => "Unknown".to_string(),
+    }
+}
+
+fn print_capsule_result(name: &str, result: &CapsuleResult, out: &mut dyn OutputSink) {
+    outln!(out, "  {}", name);
+    outln!(
+        out,
+        "    Capsule GUID:  {} ({})",
+        result.capsule_guid,
+        guid_name(&result.capsule_guid)
+    );
+    outln!(out, "    Processed:     {}", result.processed);
+    outln!(out, "    Status:        {}", efi_status_name(result.status));
+    if let Some(fmp) = &result.fmp {
+        outln!(
+            out,
+            "    Image Type:    {} ({})",
+            fmp.update_image_type_id,
+            guid_name(&fmp.update_image_type_id)
+        );
+        outln!(out, "    Image Index:   {}", fmp.update_image_index);
+        if !fmp.file_name.is_empty() {
+            outln!(out, "    File Name:     {}", fmp.file_name);
+        }
+        if !fmp.target.is_empty() {
+            outln!(out, "    Target:        {}", fmp.target);
+        }
+    }
+}
+
+/// Print the capsule update results and boot related variables
+///
+/// Returns false if no UEFI variables could be read at all.
+pub fn print_uefi_vars(out: &mut dyn OutputSink) -> bool {
+    let capsule_max = read_string("CapsuleMax", &EFI_CAPSULE_REPORT_GUID);
+    let capsule_last = read_string("CapsuleLast", &EFI_CAPSULE_REPORT_GUID);
+    let secure_boot = read_u8("SecureBoot");
+    let boot_current = read_u16_list("BootCurrent");
+    if capsule_max.is_none() && secure_boot.is_none() && boot_current.is_none() {
+        return false;
+    }
+
+    outln!(out, "Capsule Results");
+    outln!(
+        out,
+        "  CapsuleMax:    {}",
+        capsule_max.as_deref().unwrap_or("Unknown")
+    );
+    outln!(
+        out,
+        "  CapsuleLast:   {}",
+        capsule_last.as_deref().unwrap_or("None")
+    );
+    let max_index = capsule_max.as_deref().and_then(capsule_index);
+    // Results are numbered from 0 and wrap around after CapsuleMax
+    for i in 0..=max_index.unwrap_or(0) {
+        let name = format!("Capsule{:04X}", i);
+        let Some(data) = read_variable(&name, &EFI_CAPSULE_REPORT_GUID) else {
+            continue;
+        };
+        if let Some(result) = parse_capsule_result(&data) {
+            print_capsule_result(&name, &result, out);
+        } else {
+            outln!(out, "  {}: Invalid ({} bytes)", name, data.len());
+        }
+    }
+
+    outln!(out, "Boot");
+    outln!(out, "  Secure Boot:   {}", fmt_bool(secure_boot));
+    outln!(out, "  Setup Mode:    {}", fmt_bool(read_u8("SetupMode")));
+    if let Some(current) = boot_current.as_ref().and_then(|x| x.first()) {
+        outln!(out, "  BootCurrent:   Boot{:04X}", current);
+    } else {
+        outln!(out, "  BootCurrent:   Unknown");
+    }
+    if let Some(order) = read_u16_list("BootOrder") {
+        let order: Vec<String> = order.iter().map(|x| format!("Boot{:04X}", x)).collect();
+        outln!(out, "  BootOrder:     {}", order.join(", "));
+    }
+    if let Some(timeout) = read_u16_list("Timeout").and_then(|x| x.first().copied()) {
+        outln!(out, "  Timeout:       {}s", timeout);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fmp_capsule_result() {
+        let mut data = vec![];
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&EFI_FMP_CAPSULE_GUID.to_bytes());
+        // 2024-03-15 10:20:30
+        data.extend_from_slice(&[0xE8, 0x07, 3, 15, 10, 20, 30, 0]);
+        data.extend_from_slice(&[0; 8]);
+        // EFI_SECURITY_VIOLATION
+        data.extend_from_slice(&(EFI_ERROR_BIT | 26).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&[0, 1]);
+        data.extend_from_slice(&crate::esrt::MTL_BIOS_GUID.to_bytes());
+        for c in "bios.cap".encode_utf16().chain([0, 0]) {
+            data.extend_from_slice(&c.to_le_bytes());
+        }
+        let total_size = data.len() as u32;
+        data[0..4].copy_from_slice(&total_size.to_le_bytes());
+
+        let result = parse_capsule_result(&data).unwrap();
+        assert_eq!(result.capsule_guid, EFI_FMP_CAPSULE_GUID);
+        assert_eq!(result.processed.to_string(), "2024-03-15 10:20:30");
+        assert!(!result.is_success());
+        assert_eq!(
+            efi_status_name(result.status),
+            "Security Violation (0x800000000000001A)"
+        );
+        let fmp = result.fmp.unwrap();
+        assert_eq!(fmp.update_image_type_id, crate::esrt::MTL_BIOS_GUID);
+        assert_eq!(fmp.update_image_index, 1);
+        assert_eq!(fmp.file_name, "bios.cap");
+        assert_eq!(fmp.target, "");
+
+        assert_eq!(capsule_index("Capsule000A"), Some(10));
+        assert_eq!(parse_capsule_result(&data[..20]), None);
+    }
+}
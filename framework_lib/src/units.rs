@@ -0,0 +1,90 @@
+//! Format measurements the same way everywhere, in the units the user prefers
+//!
+//! Values are kept in the units that the firmware reports them in, like mW or
+//! Celsius, and only converted for display. Machine readable output, like CSV
+//! files, always uses the firmware units.
+
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Units {
+    /// Celsius
+    Metric = 0,
+    /// Fahrenheit
+    Imperial = 1,
+}
+
+static UNITS: AtomicU8 = AtomicU8::new(Units::Metric as u8);
+
+/// Change the units for all following output
+pub fn set_units(units: Units) {
+    UNITS.store(units as u8, Ordering::Relaxed);
+}
+
+pub fn units() -> Units {
+    match UNITS.load(Ordering::Relaxed) {
+        1 => Units::Imperial,
+        _ => Units::Metric,
+    }
+}
+
+fn fmt_temp_in(celsius: i32, units: Units) -> String {
+    match units {
+        Units::Metric => format!("{} C", celsius),
+        // Round to the nearest degree, without floats
+        Units::Imperial => format!("{} F", (celsius * 18 + 320 + 5).div_euclid(10)),
+    }
+}
+
+/// Temperature, given in Celsius
+pub fn fmt_temp(celsius: i32) -> String {
+    fmt_temp_in(celsius, units())
+}
+
+/// Voltage, given in mV, as V with two decimals
+pub fn fmt_voltage(mv: u32) -> String {
+    format!("{}.{:02} V", mv / 1000, mv % 1000 / 10)
+}
+
+/// Power, given in mW, as W with one decimal
+pub fn fmt_power(mw: u32) -> String {
+    format!("{}.{} W", mw / 1000, mw % 1000 / 100)
+}
+
+/// Energy, given in mWh, as Wh with one decimal
+pub fn fmt_energy(mwh: u32) -> String {
+    format!("{}.{} Wh", mwh / 1000, mwh % 1000 / 100)
+}
+
+/// Size of a buffer or file, in bytes and KB if it's large enough
+pub fn fmt_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else {
+        format!("{} B ({} KB)", bytes, bytes / 1024)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_units() {
+        assert_eq!(fmt_temp_in(45, Units::Metric), "45 C");
+        assert_eq!(fmt_temp_in(45, Units::Imperial), "113 F");
+        assert_eq!(fmt_temp_in(46, Units::Imperial), "115 F");
+        assert_eq!(fmt_temp_in(-40, Units::Imperial), "-40 F");
+        assert_eq!(fmt_voltage(17_050), "17.05 V");
+        assert_eq!(fmt_power(45_000), "45.0 W");
+        assert_eq!(fmt_power(12_345), "12.3 W");
+        assert_eq!(fmt_energy(55_050), "55.0 Wh");
+        assert_eq!(fmt_size(512), "512 B");
+        assert_eq!(fmt_size(256 * 1024), "262144 B (256 KB)");
+    }
+}
@@ -1,6 +1,7 @@
 //! Miscellaneous utility functions to use across modules
 
-use num::{Num, NumCast};
+use alloc::format;
+use core::sync::atomic::{AtomicBool, Ordering};
 use std::prelude::v1::*;
 
 #[cfg(feature = "uefi")]
@@ -29,11 +30,69 @@ pub enum Platform {
     Framework13Amd,
     /// Framework 16
     Framework16,
+    /// Framework Desktop - AMD Ryzen AI Max 300 Series
+    DesktopAmdAi300,
     /// Generic Framework device
     /// pd_addrs, pd_ports, has_mec
     GenericFramework((u16, u16), (u8, u8), bool),
 }
 
+/// Hardware of a platform that the EC doesn't tell us about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlatformSpec {
+    pub has_battery: bool,
+    pub has_lid: bool,
+    /// Location of each USB-C port with PD, in the order that the EC numbers them
+    pub pd_ports: &'static [&'static str],
+    /// Whether ports 2 and 3 have a PD controller, in addition to ports 0 and 1
+    pub has_pd23: bool,
+    /// Number of fans that the EC controls, including ones that might not be connected
+    pub fans: u8,
+}
+
+const LAPTOP_PD_PORTS: &[&str] = &["Right Back", "Right Front", "Left Front", "Left Back"];
+
+const LAPTOP_SPEC: PlatformSpec = PlatformSpec {
+    has_battery: true,
+    has_lid: true,
+    pd_ports: LAPTOP_PD_PORTS,
+    has_pd23: true,
+    fans: 1,
+};
+
+impl Platform {
+    pub fn spec(&self) -> PlatformSpec {
+        match self {
+            Platform::Framework16 => PlatformSpec {
+                pd_ports: &["Right Back", "Right Front", "Left Middle", "Left Middle"],
+                // Both are part of the expansion bay module
+                fans: 2,
+                ..LAPTOP_SPEC
+            },
+            // Only the two ports on the back have PD, the front ports are USB only.
+            // Both are on a single PD controller, PdPort::Left01 at 0x42 on EC
+            // I2C port 1 (see ccgx::device).
+            Platform::DesktopAmdAi300 => PlatformSpec {
+                has_battery: false,
+                has_lid: false,
+                pd_ports: &["Back", "Back"],
+                has_pd23: false,
+                // CPU fan and two system fan headers
+                fans: 3,
+            },
+            _ => LAPTOP_SPEC,
+        }
+    }
+}
+
+/// Hardware of the platform we're running on
+///
+/// All platforms so far, except for the desktop, are laptops. So if it can't
+/// be detected, it's most likely one of those.
+pub fn platform_spec() -> PlatformSpec {
+    smbios::get_platform().map_or(LAPTOP_SPEC, |platform| platform.spec())
+}
+
 #[derive(Debug)]
 pub struct Config {
     // TODO: Actually set and read this
@@ -42,18 +101,17 @@ pub struct Config {
 }
 
 impl Config {
+    /// Use this platform, instead of the one detected before or later
     pub fn set(platform: Platform) {
         #[cfg(feature = "std")]
         let mut config = CONFIG.lock().unwrap();
         #[cfg(not(feature = "std"))]
         let mut config = CONFIG.lock();
 
-        if (*config).is_none() {
-            *config = Some(Config {
-                _verbose: false,
-                platform,
-            });
-        }
+        *config = Some(Config {
+            _verbose: false,
+            platform,
+        });
     }
     pub fn is_set() -> bool {
         #[cfg(feature = "std")]
@@ -104,6 +162,204 @@ lazy_static! {
     static ref CONFIG: Arc<Mutex<Option<Config>>> = Arc::new(Mutex::new(None));
 }
 
+/// Whether to hide serial numbers in the output, see `--redact`
+static REDACT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn set_redact(redact: bool) {
+    REDACT.store(redact, Ordering::Relaxed);
+}
+
+/// Serial number to show to the user
+///
+/// When the output should be shared publicly, it's replaced by a placeholder.
+/// Hardware revisions and firmware versions are not affected.
+pub fn redact_serial(serial: &str) -> String {
+    if REDACT.load(Ordering::Relaxed) && !serial.is_empty() {
+        "<redacted>".to_string()
+    } else {
+        serial.to_string()
+    }
+}
+
+/// Name of the config file in the system and user config directories
+pub const CONFIG_FILE_NAME: &str = "framework_tool.toml";
+
+/// Defaults from the config file. Commandline flags take precedence
+///
+/// The file uses a small subset of TOML: `key = value` pairs with strings in
+/// double quotes or integers, and `#` comments. For example:
+///
+/// ```toml
+/// driver = "portio"
+/// platform = "framework13-amd"
+/// log_level = "warn"
+/// units = "imperial"
+/// # Applied by --apply-config, e.g. at boot and after resume
+/// charge_limit = 80
+/// fan_duty = 40
+/// # Used by --watch-events
+/// watch_interval_ms = 5000
+/// watch_events = "ac,charge_limit"
+/// # Used by the Windows service
+/// telemetry_interval_s = 900
+/// # Used by --daemon
+/// kblight_sync = true
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ConfigFile {
+    /// Preferred EC driver, same values as `--driver`
+    pub driver: Option<String>,
+    /// Platform override, same values as `--platform`
+    pub platform: Option<String>,
+    /// Log level when no `-v` or `-q` flag is given, e.g. `warn`
+    pub log_level: Option<String>,
+    /// Units of measurement, same values as `--units`
+    pub units: Option<String>,
+    /// Maximum charge limit in percent
+    pub charge_limit: Option<u8>,
+    /// Fixed duty cycle of all fans in percent, instead of automatic control
+    pub fan_duty: Option<u8>,
+    /// How often `--watch-events` polls the EC, by default 1s or 10s if the EC sends events
+    pub watch_interval_ms: Option<u64>,
+    /// Comma separated names of the events that `--watch-events` reports
+    pub watch_events: Option<String>,
+    /// How often the Windows service logs telemetry
+    pub telemetry_interval_s: Option<u64>,
+    /// Whether `--daemon` starts with the keyboard backlight following AC and ambient light
+    pub kblight_sync: Option<bool>,
+    /// Whether commands that change the system are recorded, enabled by default
+    pub audit_log: Option<bool>,
+    /// Where the audit log is written to
+    pub audit_log_path: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let mut config = ConfigFile::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Line {}: Expected `key = value`", i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            let string = || {
+                value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .map(|value| value.to_string())
+                    .ok_or_else(|| format!("Line {}: {} must be a string in quotes", i + 1, key))
+            };
+            match key {
+                "driver" => config.driver = Some(string()?),
+                "platform" => config.platform = Some(string()?),
+                "log_level" => config.log_level = Some(string()?),
+                "units" => config.units = Some(string()?),
+                "charge_limit" => match value.parse::<u8>() {
+                    Ok(limit) if limit <= 100 => config.charge_limit = Some(limit),
+                    _ => return Err(format!("Line {}: {} must be a percentage", i + 1, key)),
+                },
+                "fan_duty" => match value.parse::<u8>() {
+                    Ok(duty) if duty <= 100 => config.fan_duty = Some(duty),
+                    _ => return Err(format!("Line {}: {} must be a percentage", i + 1, key)),
+                },
+                "watch_interval_ms" => match value.parse::<u64>() {
+                    Ok(interval) if interval > 0 => config.watch_interval_ms = Some(interval),
+                    _ => return Err(format!("Line {}: {} must be a positive number", i + 1, key)),
+                },
+                "watch_events" => config.watch_events = Some(string()?),
+                "telemetry_interval_s" => match value.parse::<u64>() {
+                    Ok(interval) if interval > 0 => config.telemetry_interval_s = Some(interval),
+                    _ => return Err(format!("Line {}: {} must be a positive number", i + 1, key)),
+                },
+                "kblight_sync" => match value {
+                    "true" => config.kblight_sync = Some(true),
+                    "false" => config.kblight_sync = Some(false),
+                    _ => return Err(format!("Line {}: {} must be true or false", i + 1, key)),
+                },
+                "audit_log" => match value {
+                    "true" => config.audit_log = Some(true),
+                    "false" => config.audit_log = Some(false),
+                    _ => return Err(format!("Line {}: {} must be true or false", i + 1, key)),
+                },
+                "audit_log_path" => config.audit_log_path = Some(string()?),
+                // Might be from a newer version
+                _ => debug!("Line {}: Ignoring unknown key {}", i + 1, key),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Combine two config files, values from `other` take precedence
+    pub fn merge(self, other: ConfigFile) -> ConfigFile {
+        ConfigFile {
+            driver: other.driver.or(self.driver),
+            platform: other.platform.or(self.platform),
+            log_level: other.log_level.or(self.log_level),
+            units: other.units.or(self.units),
+            charge_limit: other.charge_limit.or(self.charge_limit),
+            fan_duty: other.fan_duty.or(self.fan_duty),
+            watch_interval_ms: other.watch_interval_ms.or(self.watch_interval_ms),
+            watch_events: other.watch_events.or(self.watch_events),
+            telemetry_interval_s: other.telemetry_interval_s.or(self.telemetry_interval_s),
+            kblight_sync: other.kblight_sync.or(self.kblight_sync),
+            audit_log: other.audit_log.or(self.audit_log),
+            audit_log_path: other.audit_log_path.or(self.audit_log_path),
+        }
+    }
+
+    /// Where config files are looked for. Later ones take precedence
+    #[cfg(feature = "std")]
+    pub fn paths() -> Vec<std::path::PathBuf> {
+        use std::env;
+        use std::path::PathBuf;
+
+        let mut paths = vec![];
+        #[cfg(windows)]
+        {
+            if let Some(dir) = env::var_os("ProgramData") {
+                paths.push(PathBuf::from(dir).join(CONFIG_FILE_NAME));
+            }
+            if let Some(dir) = env::var_os("APPDATA") {
+                paths.push(PathBuf::from(dir).join(CONFIG_FILE_NAME));
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            paths.push(PathBuf::from("/etc").join(CONFIG_FILE_NAME));
+            if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+                paths.push(PathBuf::from(dir).join(CONFIG_FILE_NAME));
+            } else if let Some(dir) = env::var_os("HOME") {
+                paths.push(PathBuf::from(dir).join(".config").join(CONFIG_FILE_NAME));
+            }
+        }
+        paths
+    }
+
+    /// Load the system and user config files, if they exist
+    ///
+    /// Invalid files are reported and ignored.
+    #[cfg(feature = "std")]
+    pub fn load() -> Self {
+        let mut config = ConfigFile::default();
+        for path in Self::paths() {
+            let contents = if let Ok(contents) = std::fs::read_to_string(&path) {
+                contents
+            } else {
+                continue;
+            };
+            match Self::parse(&contents) {
+                Ok(file) => config = config.merge(file),
+                Err(err) => println!("Ignoring invalid config file {}: {}", path.display(), err),
+            }
+        }
+        config
+    }
+}
+
 /// Convert any type to a u8 slice (Like a C byte buffer)
 pub unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
     let len = ::std::mem::size_of::<T>();
@@ -131,37 +387,6 @@ pub fn print_buffer(buffer: &[u8]) {
     println!();
 }
 
-fn print_chunk(buffer: &[u8], newline: bool) {
-    for (i, byte) in buffer.iter().enumerate() {
-        if i % 2 == 0 {
-            print!(" ")
-        }
-        print!("{:02x}", byte);
-    }
-    if newline {
-        println!();
-    }
-}
-
-// Example:
-// Input: [0x00; 0x16]
-// Output: ................
-// Input: [a000 0036 626e 0300 c511 8035 0000 0000]
-// Output: ...6bn.....5....
-fn print_ascii_buffer(buffer: &[u8], newline: bool) {
-    for byte in buffer {
-        // If printable, print, else display a dot
-        if *byte >= 32 && *byte <= 127 {
-            print!("{}", *byte as char);
-        } else {
-            print!(".")
-        }
-    }
-    if newline {
-        println!();
-    }
-}
-
 /// Print a big byte buffer
 ///
 /// Because it's long it'll be printed in several lines, each 16 bytes
@@ -173,22 +398,130 @@ fn print_ascii_buffer(buffer: &[u8], newline: bool) {
 /// 00002000: a000 0036 626e 0300 c511 8035 0000 0000  ...6bn.....5....
 /// 00002010: 0000 0000 0000 0000 0000 0000 0000 00    ................
 pub fn print_multiline_buffer(buffer: &[u8], offset: usize) {
+    for line in format_multiline_buffer(buffer, offset) {
+        println!("{}", line);
+    }
+}
+
+/// Same as `print_multiline_buffer` but return the lines instead of printing them
+pub fn format_multiline_buffer(buffer: &[u8], offset: usize) -> Vec<String> {
     let chunk_size = 16;
+    let mut lines = vec![];
     for (i, chunk) in buffer.chunks(chunk_size).enumerate() {
-        print!("{:08x}:", offset + i * chunk_size);
-        print_chunk(chunk, false);
+        let mut line = format!("{:08x}:", offset + i * chunk_size);
+        for (i, byte) in chunk.iter().enumerate() {
+            if i % 2 == 0 {
+                line.push(' ');
+            }
+            line.push_str(&format!("{:02x}", byte));
+        }
 
         // Make sure ASCII section aligns, even if less than 16 byte chunks
         if chunk.len() < 16 {
             let byte_padding = 16 - chunk.len();
             let space_padding = byte_padding / 2;
             let padding = byte_padding * 2 + space_padding;
-            print!("{}", " ".repeat(padding));
+            line.push_str(&" ".repeat(padding));
+        }
+        line.push_str("  ");
+
+        // If printable, print, else display a dot
+        line.extend(chunk.iter().map(|byte| {
+            if *byte >= 32 && *byte <= 127 {
+                *byte as char
+            } else {
+                '.'
+            }
+        }));
+        lines.push(line);
+    }
+    lines
+}
+
+/// Parse a version like `3.0.4`, optionally prefixed with `v`
+///
+/// Missing minor and patch are treated as 0.
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn parse_semver(version: &str) -> Option<(u32, u32, u32)> {
+    let version = version.trim().trim_start_matches('v');
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().map(|x| x.parse()).unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().map(|x| x.parse()).unwrap_or(Ok(0)).ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// Operator to compare a firmware version against a target version
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum VersionOp {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+impl VersionOp {
+    /// Whether the result of comparing current to target version satisfies the operator
+    pub fn matches(&self, ordering: core::cmp::Ordering) -> bool {
+        use core::cmp::Ordering;
+        match self {
+            Self::Lt => ordering == Ordering::Less,
+            Self::Le => ordering != Ordering::Greater,
+            Self::Eq => ordering == Ordering::Equal,
+            Self::Ne => ordering != Ordering::Equal,
+            Self::Ge => ordering != Ordering::Less,
+            Self::Gt => ordering == Ordering::Greater,
         }
-        print!("  ");
+    }
+}
 
-        print_ascii_buffer(chunk, true);
+/// Split an operator like `>=` from the beginning of the version
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn parse_version_op(version: &str) -> (Option<VersionOp>, &str) {
+    // Two character operators first, so that `>=` isn't parsed as `>`
+    let ops = [
+        (">=", VersionOp::Ge),
+        ("<=", VersionOp::Le),
+        ("==", VersionOp::Eq),
+        ("!=", VersionOp::Ne),
+        (">", VersionOp::Gt),
+        ("<", VersionOp::Lt),
+    ];
+    for (prefix, op) in ops {
+        if let Some(rest) = version.strip_prefix(prefix) {
+            return (Some(op), rest.trim());
+        }
     }
+    (None, version)
+}
+
+/// Parse a dotted version like `3.0.4` into its parts, with the given radix
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn parse_version_parts(version: &str, radix: u32) -> Option<Vec<u32>> {
+    version
+        .trim()
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| u32::from_str_radix(part, radix).ok())
+        .collect()
+}
+
+/// Compare two versions part by part. Missing parts count as 0
+#[cfg(any(feature = "commandline", feature = "uefi"))]
+pub fn compare_version_parts(left: &[u32], right: &[u32]) -> core::cmp::Ordering {
+    let len = core::cmp::max(left.len(), right.len());
+    let part = |parts: &[u32], i: usize| parts.get(i).copied().unwrap_or(0);
+    (0..len)
+        .map(|i| part(left, i).cmp(&part(right, i)))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(core::cmp::Ordering::Equal)
 }
 
 /// Find a sequence of bytes in a long slice of bytes
@@ -198,18 +531,74 @@ pub fn find_sequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
         .position(|window| window == needle)
 }
 
-/// Assert length of an EC response from the windows driver
-/// It's always 20 more than expected. TODO: Figure out why
-pub fn assert_win_len<N: Num + std::fmt::Debug + Ord + NumCast + Copy>(left: N, right: N) {
-    #[cfg(feature = "win_driver")]
-    assert_eq!(left, right + NumCast::from(20).unwrap());
-    #[cfg(not(feature = "win_driver"))]
-    assert_eq!(left, right);
-}
-
+#[cfg(any(feature = "commandline", feature = "uefi"))]
 pub fn print_buffer_short(buffer: &[u8]) {
     for byte in buffer {
         print!("{:02x}", byte);
     }
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_buffer() {
+        let mut buffer = vec![
+            0xa0, 0x00, 0x00, 0x36, 0x62, 0x6e, 0x03, 0x00, 0xc5, 0x11, 0x80, 0x35,
+        ];
+        buffer.resize(31, 0);
+        assert_eq!(
+            format_multiline_buffer(&buffer, 0x2000),
+            vec![
+                "00002000: a000 0036 626e 0300 c511 8035 0000 0000  ...6bn.....5....",
+                "00002010: 0000 0000 0000 0000 0000 0000 0000 00    ...............",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_config_file() {
+        let config = ConfigFile::parse(
+            "# Comment\n\ndriver = \"portio\"\nunits = \"imperial\"\ncharge_limit = 80 # At boot\nfan_curve = 1\nwatch_interval_ms = 5000\naudit_log = false\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ConfigFile {
+                driver: Some("portio".to_string()),
+                units: Some("imperial".to_string()),
+                charge_limit: Some(80),
+                watch_interval_ms: Some(5000),
+                audit_log: Some(false),
+                ..Default::default()
+            }
+        );
+
+        assert!(ConfigFile::parse("driver = portio").is_err());
+        assert!(ConfigFile::parse("charge_limit = 101").is_err());
+        assert!(ConfigFile::parse("watch_interval_ms = 0").is_err());
+        assert!(ConfigFile::parse("telemetry_interval_s = -1").is_err());
+        assert!(ConfigFile::parse("fan_duty = 101").is_err());
+        assert!(ConfigFile::parse("audit_log = \"off\"").is_err());
+        assert!(ConfigFile::parse("kblight_sync = 1").is_err());
+        assert!(ConfigFile::parse("driver").is_err());
+    }
+
+    #[test]
+    fn merge_config_files() {
+        let system = ConfigFile {
+            driver: Some("portio".to_string()),
+            charge_limit: Some(80),
+            ..Default::default()
+        };
+        let user = ConfigFile {
+            driver: Some("cros-ec".to_string()),
+            ..Default::default()
+        };
+        let config = system.merge(user);
+        assert_eq!(config.driver.as_deref(), Some("cros-ec"));
+        assert_eq!(config.charge_limit, Some(80));
+    }
+}
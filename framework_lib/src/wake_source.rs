@@ -0,0 +1,176 @@
+//! Configure which events wake the system from sleep
+//!
+//! The EC has a wake mask for each sleep state, which it applies when the
+//! system enters that state. If the host never set one, the EC uses a default
+//! of the board that it doesn't report. The masks are lost when the EC reboots.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "uefi")]
+use core::prelude::rust_2021::derive;
+
+use crate::chromium_ec::commands::{HostEventCode, HostEventMaskType};
+use crate::chromium_ec::{CrosEc, EcResult};
+
+/// Sleep states that wake sources are configured for
+pub const SLEEP_STATES: &[(HostEventMaskType, &str)] = &[
+    (HostEventMaskType::LazyWakeMaskS0ix, "S0ix"),
+    (HostEventMaskType::LazyWakeMaskS3, "S3"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WakeSource {
+    LidOpen,
+    PowerButton,
+    AcConnected,
+    AcDisconnected,
+    Keyboard,
+    BatteryLow,
+    BatteryCritical,
+    Rtc,
+    /// Switching between laptop and tablet mode
+    ModeChange,
+}
+
+pub const ALL_SOURCES: &[WakeSource] = &[
+    WakeSource::LidOpen,
+    WakeSource::PowerButton,
+    WakeSource::AcConnected,
+    WakeSource::AcDisconnected,
+    WakeSource::Keyboard,
+    WakeSource::BatteryLow,
+    WakeSource::BatteryCritical,
+    WakeSource::Rtc,
+    WakeSource::ModeChange,
+];
+
+/// Mask to start from when the host hasn't set one yet
+///
+/// Enabling or disabling one source shouldn't silently turn off the others,
+/// so this has the sources that users expect to wake the system.
+const DEFAULT_WAKE_SOURCES: &[WakeSource] = &[
+    WakeSource::LidOpen,
+    WakeSource::PowerButton,
+    WakeSource::AcConnected,
+    WakeSource::AcDisconnected,
+    WakeSource::Keyboard,
+    WakeSource::BatteryCritical,
+];
+
+impl WakeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WakeSource::LidOpen => "lid_open",
+            WakeSource::PowerButton => "power_button",
+            WakeSource::AcConnected => "ac_connected",
+            WakeSource::AcDisconnected => "ac_disconnected",
+            WakeSource::Keyboard => "keyboard",
+            WakeSource::BatteryLow => "battery_low",
+            WakeSource::BatteryCritical => "battery_critical",
+            WakeSource::Rtc => "rtc",
+            WakeSource::ModeChange => "mode_change",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            WakeSource::LidOpen => "Opening the lid",
+            WakeSource::PowerButton => "Pressing the power button",
+            WakeSource::AcConnected => "Attaching a charger",
+            WakeSource::AcDisconnected => "Detaching the charger",
+            WakeSource::Keyboard => "Pressing a key",
+            WakeSource::BatteryLow => "Battery getting low",
+            WakeSource::BatteryCritical => "Battery getting critically low",
+            WakeSource::Rtc => "Alarm of the EC real-time clock",
+            WakeSource::ModeChange => "Switching between laptop and tablet mode",
+        }
+    }
+
+    fn event(&self) -> HostEventCode {
+        match self {
+            WakeSource::LidOpen => HostEventCode::LidOpen,
+            WakeSource::PowerButton => HostEventCode::PowerButton,
+            WakeSource::AcConnected => HostEventCode::AcConnected,
+            WakeSource::AcDisconnected => HostEventCode::AcDisconnected,
+            WakeSource::Keyboard => HostEventCode::KeyPressed,
+            WakeSource::BatteryLow => HostEventCode::BatteryLow,
+            WakeSource::BatteryCritical => HostEventCode::BatteryCritical,
+            WakeSource::Rtc => HostEventCode::Rtc,
+            WakeSource::ModeChange => HostEventCode::ModeChange,
+        }
+    }
+
+    pub fn mask(&self) -> u64 {
+        self.event().mask()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ALL_SOURCES.iter().find(|s| s.name() == name).copied()
+    }
+}
+
+/// Look up a wake source by name
+pub fn find_source(name: &str) -> Result<WakeSource, String> {
+    WakeSource::from_name(name).ok_or_else(|| {
+        let names: Vec<&str> = ALL_SOURCES.iter().map(|s| s.name()).collect();
+        format!(
+            "Unknown wake source '{}'. Known sources: {}",
+            name,
+            names.join(", ")
+        )
+    })
+}
+
+/// Wake mask of each sleep state, None if the host hasn't set it and the EC uses its default
+pub fn get_masks(ec: &CrosEc) -> EcResult<Vec<(&'static str, Option<u64>)>> {
+    let mut masks = Vec::with_capacity(SLEEP_STATES.len());
+    for (mask_type, state) in SLEEP_STATES {
+        let mask = ec.get_host_event_mask(*mask_type)?;
+        masks.push((*state, if mask == 0 { None } else { Some(mask) }));
+    }
+    Ok(masks)
+}
+
+/// Mask with the source turned on or off
+fn update_mask(mask: Option<u64>, source: WakeSource, enable: bool) -> u64 {
+    let mask = mask.unwrap_or_else(|| DEFAULT_WAKE_SOURCES.iter().map(|s| s.mask()).sum());
+    if enable {
+        mask | source.mask()
+    } else {
+        mask & !source.mask()
+    }
+}
+
+/// Let the source wake the system from all sleep states, or stop it
+pub fn set_source(ec: &CrosEc, source: WakeSource, enable: bool) -> EcResult<()> {
+    for (mask_type, _state) in SLEEP_STATES {
+        let mask = ec.get_host_event_mask(*mask_type)?;
+        let mask = update_mask(if mask == 0 { None } else { Some(mask) }, source, enable);
+        ec.set_host_event_mask(*mask_type, mask)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_masks() {
+        assert_eq!(WakeSource::LidOpen.mask(), 0x02);
+        assert_eq!(WakeSource::Keyboard.mask(), 0x1000);
+
+        let mask = update_mask(Some(0x1002), WakeSource::LidOpen, false);
+        assert_eq!(mask, 0x1000);
+        assert_eq!(update_mask(Some(mask), WakeSource::Rtc, true), 0x0200_1000);
+
+        // Starts from the default, instead of disabling everything else
+        let mask = update_mask(None, WakeSource::LidOpen, false);
+        assert_eq!(mask & WakeSource::LidOpen.mask(), 0);
+        assert_ne!(mask & WakeSource::PowerButton.mask(), 0);
+
+        assert!(find_source("keyboard").is_ok());
+        assert!(find_source("lid").is_err());
+    }
+}
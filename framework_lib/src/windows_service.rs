@@ -0,0 +1,505 @@
+//! Run as a Windows service that logs hardware events and telemetry
+//!
+//! Meant for fleets of laptops where nobody is logged in to watch
+//! `--watch-events`. The service runs as LocalSystem, so that it can access
+//! the EC, and writes to the Application event log, where the usual tools can
+//! collect it. Tasks can be triggered by the event IDs, like a hook would be.
+//!
+//! - `EVENT_SERVICE` - Service started or stopped
+//! - `EVENT_HARDWARE` - Hardware event, same as reported by `--watch-events`
+//! - `EVENT_TELEMETRY` - Battery, fan and temperature readings
+//!
+//! The poll interval and which events are reported are taken from the same
+//! config file settings as `--watch-events`. Telemetry is logged every
+//! `telemetry_interval_s` seconds.
+
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::time::{Duration, Instant};
+
+use windows::core::{HSTRING, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{
+    ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_DOES_NOT_EXIST, E_ACCESSDENIED, HANDLE, NO_ERROR,
+};
+use windows::Win32::Storage::FileSystem::DELETE;
+use windows::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+};
+use windows::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteTreeW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE,
+    KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+};
+use windows::Win32::System::Services::*;
+
+use crate::chromium_ec::CrosEc;
+use crate::commandline::ExitCode;
+use crate::events::{HardwareState, StateWaiter};
+use crate::power::{self, PowerInfo};
+use crate::util::ConfigFile;
+
+pub const SERVICE_NAME: &str = "FrameworkTool";
+const DISPLAY_NAME: &str = "Framework System Monitor";
+const DESCRIPTION: &str =
+    "Logs hardware events, battery health and temperatures of the Framework laptop to the event log";
+
+/// Event source of the service, under which `EventMessageFile` is registered
+const EVENT_SOURCE_KEY: &str =
+    "SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\FrameworkTool";
+/// Has messages for the IDs 1 to 1000 that just show the string of the event
+const EVENT_MESSAGE_FILE: &str = "%SystemRoot%\\System32\\EventCreate.exe";
+
+pub const EVENT_SERVICE: u32 = 1;
+pub const EVENT_HARDWARE: u32 = 2;
+pub const EVENT_TELEMETRY: u32 = 3;
+
+/// How often telemetry is logged, unless configured otherwise
+pub const TELEMETRY_INTERVAL_S: u64 = 15 * 60;
+
+/// How long stopping may take, the EC is only polled once per interval, by
+/// default up to `events::MKBP_POLL_INTERVAL_MS`
+const STOP_WAIT_HINT_MS: u32 = 15_000;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static STATUS_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+
+fn exit_code(err: &windows::core::Error) -> i32 {
+    if err.code() == E_ACCESSDENIED {
+        println!("Managing services needs to be run as administrator");
+        ExitCode::Permission.into()
+    } else {
+        ExitCode::Failure.into()
+    }
+}
+
+/// Handle of the event source, to write to the Application event log
+struct EventLog(HANDLE);
+
+impl EventLog {
+    fn open() -> Option<Self> {
+        match unsafe { RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(SERVICE_NAME)) } {
+            Ok(handle) => Some(EventLog(handle)),
+            Err(err) => {
+                error!("Failed to open event log: {}", err);
+                None
+            }
+        }
+    }
+
+    fn report(&self, kind: REPORT_EVENT_TYPE, id: u32, message: &str) {
+        let message = HSTRING::from(message);
+        let strings = [PCWSTR(message.as_ptr())];
+        let res = unsafe { ReportEventW(self.0, kind, 0, id, None, 0, Some(&strings), None) };
+        if let Err(err) = res {
+            error!("Failed to write to event log: {}", err);
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        let _ = unsafe { DeregisterEventSource(self.0) };
+    }
+}
+
+fn fmt_battery(info: &PowerInfo) -> String {
+    let ac = if info.ac_present {
+        "connected"
+    } else {
+        "disconnected"
+    };
+    let Some(battery) = &info.battery else {
+        return format!("AC {}, no battery", ac);
+    };
+    let state = if battery.charging {
+        "charging"
+    } else if battery.discharging {
+        "discharging"
+    } else {
+        "idle"
+    };
+    let health = if battery.design_capacity > 0 {
+        format!(
+            "{}%",
+            100 * battery.last_full_charge_capacity / battery.design_capacity
+        )
+    } else {
+        "unknown".to_string()
+    };
+    format!(
+        "AC {}, battery {}% {}, {} cycles, health {} ({}/{} mAh)",
+        ac,
+        battery.charge_percentage,
+        state,
+        battery.cycle_count,
+        health,
+        battery.last_full_charge_capacity,
+        battery.design_capacity
+    )
+}
+
+/// Readings for the telemetry event, one line each
+///
+/// Always in the units of the firmware, so that they can be processed further.
+fn fmt_telemetry(
+    power: Option<&PowerInfo>,
+    fans: Option<&[u16]>,
+    temps: Option<&[(&str, Option<u8>)]>,
+) -> String {
+    let power = power.map_or_else(|| "unknown".to_string(), fmt_battery);
+    let fans = fans.map_or_else(
+        || "unknown".to_string(),
+        |fans| {
+            let rpms: Vec<String> = fans.iter().map(|rpm| format!("{} RPM", rpm)).collect();
+            rpms.join(", ")
+        },
+    );
+    let temps = temps.map_or_else(
+        || "unknown".to_string(),
+        |temps| {
+            let temps: Vec<String> = temps
+                .iter()
+                .map(|(name, celsius)| match celsius {
+                    Some(celsius) => format!("{} {} C", name, celsius),
+                    None => format!("{} n/a", name),
+                })
+                .collect();
+            temps.join(", ")
+        },
+    );
+    format!("Power: {}\nFans: {}\nTemperatures: {}", power, fans, temps)
+}
+
+fn telemetry(ec: &CrosEc) -> Option<String> {
+    let power = power::power_info(ec);
+    let fans = power::get_fan_speeds(ec);
+    let temps = power::get_temperatures(ec);
+    if power.is_none() && fans.is_none() && temps.is_none() {
+        return None;
+    }
+    Some(fmt_telemetry(
+        power.as_ref(),
+        fans.as_deref(),
+        temps.as_deref(),
+    ))
+}
+
+fn set_status(state: SERVICE_STATUS_CURRENT_STATE) {
+    let handle = SERVICE_STATUS_HANDLE(STATUS_HANDLE.load(Ordering::SeqCst));
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        dwWaitHint: if state == SERVICE_STOP_PENDING {
+            STOP_WAIT_HINT_MS
+        } else {
+            0
+        },
+        ..Default::default()
+    };
+    if let Err(err) = unsafe { SetServiceStatus(handle, &status) } {
+        error!("Failed to set service status: {}", err);
+    }
+}
+
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            STOP.store(true, Ordering::SeqCst);
+            set_status(SERVICE_STOP_PENDING);
+            NO_ERROR.0
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR.0,
+        _ => ERROR_CALL_NOT_IMPLEMENTED.0,
+    }
+}
+
+/// Watch the EC until the service is stopped
+fn monitor(log: &EventLog) {
+    let config = ConfigFile::load();
+    let telemetry_interval =
+        Duration::from_secs(config.telemetry_interval_s.unwrap_or(TELEMETRY_INTERVAL_S));
+    let enabled: Option<Vec<String>> = config
+        .watch_events
+        .map(|names| names.split(',').map(|x| x.trim().to_string()).collect());
+
+    let ec = CrosEc::new();
+    let waiter = StateWaiter::new(&ec, config.watch_interval_ms);
+    let mut state = HardwareState::read(&ec);
+    let mut last_telemetry: Option<Instant> = None;
+    while !STOP.load(Ordering::SeqCst) {
+        if last_telemetry.map_or(true, |x| x.elapsed() >= telemetry_interval) {
+            match telemetry(&ec) {
+                Some(telemetry) => {
+                    log.report(EVENTLOG_INFORMATION_TYPE, EVENT_TELEMETRY, &telemetry)
+                }
+                None => log.report(
+                    EVENTLOG_WARNING_TYPE,
+                    EVENT_TELEMETRY,
+                    "Failed to read telemetry from the EC",
+                ),
+            }
+            last_telemetry = Some(Instant::now());
+        }
+
+        waiter.wait(&ec);
+
+        let new_state = HardwareState::read(&ec);
+        for event in state.events(&new_state).iter().filter(|e| {
+            enabled
+                .as_ref()
+                .map_or(true, |names| names.iter().any(|x| x == e.name()))
+        }) {
+            let message = format!("{}: {}", event.name(), event.state());
+            log.report(EVENTLOG_INFORMATION_TYPE, EVENT_HARDWARE, &message);
+        }
+        state = new_state;
+    }
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+    let handle = match RegisterServiceCtrlHandlerExW(
+        &HSTRING::from(SERVICE_NAME),
+        Some(control_handler),
+        None,
+    ) {
+        Ok(handle) => handle,
+        Err(err) => {
+            error!("Failed to register service control handler: {}", err);
+            return;
+        }
+    };
+    STATUS_HANDLE.store(handle.0, Ordering::SeqCst);
+    set_status(SERVICE_RUNNING);
+
+    if let Some(log) = EventLog::open() {
+        log.report(EVENTLOG_INFORMATION_TYPE, EVENT_SERVICE, "Service started");
+        monitor(&log);
+        log.report(EVENTLOG_INFORMATION_TYPE, EVENT_SERVICE, "Service stopped");
+    }
+    set_status(SERVICE_STOPPED);
+}
+
+/// Run the service, only works when started by the service control manager
+///
+/// Returns once the service is stopped.
+pub fn run() -> i32 {
+    let mut name: Vec<u16> = SERVICE_NAME.encode_utf16().chain(Some(0)).collect();
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: PWSTR(name.as_mut_ptr()),
+            lpServiceProc: Some(service_main),
+        },
+        // Terminates the table
+        SERVICE_TABLE_ENTRYW::default(),
+    ];
+    match unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } {
+        Ok(()) => ExitCode::Success.into(),
+        Err(err) => {
+            error!("Failed to connect to the service control manager: {}", err);
+            println!("The service can only be started by Windows, install it with --windows-service install");
+            ExitCode::Failure.into()
+        }
+    }
+}
+
+/// Register the event source, so that the event viewer can show the messages
+fn register_event_source() -> windows::core::Result<()> {
+    let message_file: Vec<u8> = EVENT_MESSAGE_FILE
+        .encode_utf16()
+        .chain(Some(0))
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    // Information, warning and error
+    let types_supported = 7u32.to_le_bytes();
+    unsafe {
+        let mut key = HKEY::default();
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            &HSTRING::from(EVENT_SOURCE_KEY),
+            None,
+            PCWSTR::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            None,
+            &mut key,
+            None,
+        )
+        .ok()?;
+        let res = RegSetValueExW(
+            key,
+            &HSTRING::from("EventMessageFile"),
+            None,
+            REG_EXPAND_SZ,
+            Some(&message_file),
+        )
+        .ok()
+        .and_then(|()| {
+            RegSetValueExW(
+                key,
+                &HSTRING::from("TypesSupported"),
+                None,
+                REG_DWORD,
+                Some(&types_supported),
+            )
+            .ok()
+        });
+        let _ = RegCloseKey(key);
+        res
+    }
+}
+
+fn install_service(command: &str) -> windows::core::Result<()> {
+    let mut description: Vec<u16> = DESCRIPTION.encode_utf16().chain(Some(0)).collect();
+    unsafe {
+        let manager = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CREATE_SERVICE)?;
+        let service = CreateServiceW(
+            manager,
+            &HSTRING::from(SERVICE_NAME),
+            &HSTRING::from(DISPLAY_NAME),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            &HSTRING::from(command),
+            PCWSTR::null(),
+            None,
+            PCWSTR::null(),
+            // LocalSystem, to be able to access the EC
+            PCWSTR::null(),
+            PCWSTR::null(),
+        );
+        let _ = CloseServiceHandle(manager);
+        let service = service?;
+
+        let info = SERVICE_DESCRIPTIONW {
+            lpDescription: PWSTR(description.as_mut_ptr()),
+        };
+        let res = ChangeServiceConfig2W(
+            service,
+            SERVICE_CONFIG_DESCRIPTION,
+            Some(&info as *const _ as *const c_void),
+        )
+        .and_then(|()| register_event_source())
+        .and_then(|()| StartServiceW(service, None));
+        let _ = CloseServiceHandle(service);
+        res
+    }
+}
+
+fn uninstall_service() -> windows::core::Result<()> {
+    unsafe {
+        let manager = OpenSCManagerW(PCWSTR::null(), PCWSTR::null(), SC_MANAGER_CONNECT)?;
+        let service = OpenServiceW(
+            manager,
+            &HSTRING::from(SERVICE_NAME),
+            SERVICE_STOP | SERVICE_QUERY_STATUS | DELETE.0,
+        );
+        let _ = CloseServiceHandle(manager);
+        let service = service?;
+
+        // Fails if it's not running, it's removed once stopped anyway
+        let mut status = SERVICE_STATUS::default();
+        let _ = ControlService(service, SERVICE_CONTROL_STOP, &mut status);
+        let res = DeleteService(service);
+        let _ = CloseServiceHandle(service);
+        res?;
+
+        // Keep the old events readable if the key can't be removed
+        let _ = RegDeleteTreeW(HKEY_LOCAL_MACHINE, &HSTRING::from(EVENT_SOURCE_KEY));
+    }
+    Ok(())
+}
+
+/// Install the service and start it, it's also started at every boot
+pub fn install() -> i32 {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            error!("Failed to find the path of framework_tool: {:?}", err);
+            return ExitCode::Failure.into();
+        }
+    };
+    let command = format!("\"{}\" --windows-service run", exe.display());
+    match install_service(&command) {
+        Ok(()) => {
+            println!("Installed and started service {}", SERVICE_NAME);
+            ExitCode::Success.into()
+        }
+        Err(err) => {
+            error!("Failed to install service: {}", err);
+            exit_code(&err)
+        }
+    }
+}
+
+/// Stop the service and remove it
+pub fn uninstall() -> i32 {
+    match uninstall_service() {
+        Ok(()) => {
+            println!("Removed service {}", SERVICE_NAME);
+            ExitCode::Success.into()
+        }
+        Err(err) if err.code() == ERROR_SERVICE_DOES_NOT_EXIST.to_hresult() => {
+            println!("Service {} is not installed", SERVICE_NAME);
+            ExitCode::Success.into()
+        }
+        Err(err) => {
+            error!("Failed to remove service: {}", err);
+            exit_code(&err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::power::BatteryInformation;
+
+    #[test]
+    fn format_telemetry() {
+        let battery = BatteryInformation {
+            present_voltage: 17_000,
+            present_rate: 1_000,
+            remaining_capacity: 2_000,
+            battery_count: 1,
+            current_battery_index: 0,
+            design_capacity: 3_572,
+            design_voltage: 15_480,
+            last_full_charge_capacity: 3_400,
+            cycle_count: 42,
+            charge_percentage: 58,
+            manufacturer: String::new(),
+            model_number: String::new(),
+            serial_number: String::new(),
+            battery_type: String::new(),
+            discharging: false,
+            charging: true,
+            level_critical: false,
+        };
+        let info = PowerInfo {
+            ac_present: true,
+            battery: Some(battery),
+        };
+        let temps = [("F75303_Local", Some(45)), ("Battery", None)];
+        assert_eq!(
+            fmt_telemetry(Some(&info), Some(&[2300, 0][..]), Some(&temps[..])),
+            "Power: AC connected, battery 58% charging, 42 cycles, health 95% (3400/3572 mAh)\n\
+             Fans: 2300 RPM, 0 RPM\n\
+             Temperatures: F75303_Local 45 C, Battery n/a"
+        );
+        assert_eq!(
+            fmt_telemetry(None, None, None),
+            "Power: unknown\nFans: unknown\nTemperatures: unknown"
+        );
+    }
+}
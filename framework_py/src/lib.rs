@@ -0,0 +1,177 @@
+//! Python bindings for the structured APIs of framework_lib
+//!
+//! Meant for scripts, like in lab automation, that would otherwise have to
+//! parse the output of `framework_tool`. Build and install into the current
+//! virtualenv with `maturin develop --release`, then:
+//!
+//! ```python
+//! import framework
+//!
+//! ec = framework.Ec()
+//! print(ec.power()["battery"]["charge_percentage"])
+//! ec.set_charge_limit(80)
+//! ```
+//!
+//! Failures to talk to the EC raise `OSError`, invalid arguments `ValueError`.
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use framework_lib::chromium_ec::{CrosEc, EcCurrentImage, EcResult};
+use framework_lib::power;
+use framework_lib::smbios;
+
+fn check<T>(res: EcResult<T>) -> PyResult<T> {
+    res.map_err(|err| PyOSError::new_err(format!("{:?}", err)))
+}
+
+fn require<T>(value: Option<T>, what: &str) -> PyResult<T> {
+    value.ok_or_else(|| PyOSError::new_err(format!("Failed to read {}", what)))
+}
+
+/// Connection to the embedded controller
+///
+/// Uses the best driver for the OS, just like `framework_tool`.
+#[pyclass(unsendable)]
+struct Ec {
+    ec: CrosEc,
+}
+
+#[pymethods]
+impl Ec {
+    #[new]
+    fn new() -> Self {
+        Ec { ec: CrosEc::new() }
+    }
+
+    /// AC and battery status, `battery` is None if there is none
+    fn power<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let info = require(power::power_info(&self.ec), "power info")?;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("ac_present", info.ac_present)?;
+        let battery = match info.battery {
+            Some(battery) => {
+                let b = PyDict::new_bound(py);
+                b.set_item("charge_percentage", battery.charge_percentage)?;
+                b.set_item("charging", battery.charging)?;
+                b.set_item("discharging", battery.discharging)?;
+                b.set_item("cycle_count", battery.cycle_count)?;
+                b.set_item("voltage_mv", battery.present_voltage)?;
+                b.set_item("rate_ma", battery.present_rate)?;
+                b.set_item("remaining_capacity_mah", battery.remaining_capacity)?;
+                b.set_item(
+                    "last_full_charge_capacity_mah",
+                    battery.last_full_charge_capacity,
+                )?;
+                b.set_item("design_capacity_mah", battery.design_capacity)?;
+                b.set_item("manufacturer", battery.manufacturer)?;
+                b.set_item("model_number", battery.model_number)?;
+                b.set_item("serial_number", battery.serial_number)?;
+                Some(b)
+            }
+            None => None,
+        };
+        dict.set_item("battery", battery)?;
+        Ok(dict)
+    }
+
+    /// Versions of BIOS, EC and PD firmware, None if they can't be read
+    fn versions<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+
+        let bios = smbios::get_smbios_info().and_then(|info| info.bios);
+        dict.set_item(
+            "bios_version",
+            bios.as_ref().and_then(|bios| bios.version.clone()),
+        )?;
+        dict.set_item(
+            "bios_date",
+            bios.as_ref().and_then(|bios| bios.release_date.clone()),
+        )?;
+
+        let (ec_ro, ec_rw, ec_current) = match self.ec.flash_version() {
+            Some((ro, rw, current)) => (Some(ro), Some(rw), Some(current)),
+            None => (None, None, None),
+        };
+        dict.set_item("ec_ro", ec_ro)?;
+        dict.set_item("ec_rw", ec_rw)?;
+        dict.set_item(
+            "ec_current",
+            ec_current.map(|current| match current {
+                EcCurrentImage::RO => "RO",
+                EcCurrentImage::RW => "RW",
+                EcCurrentImage::Unknown => "Unknown",
+            }),
+        )?;
+
+        let pd = power::read_pd_version(&self.ec).ok();
+        dict.set_item(
+            "pd01",
+            pd.as_ref().map(|pd| pd.controller01.app.to_string()),
+        )?;
+        dict.set_item(
+            "pd23",
+            pd.as_ref().map(|pd| pd.controller23.app.to_string()),
+        )?;
+        Ok(dict)
+    }
+
+    /// Minimum and maximum battery charge limit in percent
+    fn get_charge_limit(&self) -> PyResult<(u8, u8)> {
+        check(self.ec.get_charge_limit())
+    }
+
+    /// Set the maximum battery charge limit in percent, between 25 and 100
+    fn set_charge_limit(&self, max: u8) -> PyResult<()> {
+        // Same limits as --charge-limit
+        if !(25..=100).contains(&max) {
+            return Err(PyValueError::new_err(
+                "Charge limit must be between 25 and 100",
+            ));
+        }
+        let (min, _max) = check(self.ec.get_charge_limit())?;
+        check(self.ec.set_charge_limit(min, max))
+    }
+
+    /// Speed of each fan in RPM
+    fn fan_speeds(&self) -> PyResult<Vec<u16>> {
+        require(power::get_fan_speeds(&self.ec), "fan speeds")
+    }
+
+    /// Run the fans at a fixed duty cycle in percent, all of them if `fan` is None
+    #[pyo3(signature = (percent, fan=None))]
+    fn set_fan_duty(&self, percent: u32, fan: Option<u8>) -> PyResult<()> {
+        if percent > 100 {
+            return Err(PyValueError::new_err("Fan duty must be at most 100"));
+        }
+        check(self.ec.fan_set_duty(fan, percent))
+    }
+
+    /// Give control of the fans back to the EC, all of them if `fan` is None
+    #[pyo3(signature = (fan=None))]
+    fn auto_fan_control(&self, fan: Option<u8>) -> PyResult<()> {
+        check(self.ec.autofanctrl(fan))
+    }
+
+    /// Temperature of each sensor in Celsius, None if it can't be read
+    fn temperatures<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let temps = require(power::get_temperatures(&self.ec), "temperatures")?;
+        let dict = PyDict::new_bound(py);
+        for (name, celsius) in temps {
+            dict.set_item(name, celsius)?;
+        }
+        Ok(dict)
+    }
+
+    /// Reading of the ambient light sensor in lux
+    fn ambient_light(&self) -> PyResult<u32> {
+        require(power::get_als_reading(&self.ec), "ambient light sensor")
+    }
+}
+
+#[pymodule]
+fn framework(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Ec>()?;
+    Ok(())
+}
@@ -0,0 +1,113 @@
+//! Synthetic data of each Framework system, to test framework_lib against
+//!
+//! The fixtures are not captures from real systems. They were put together
+//! by hand, modeled on what the systems report, and only contain what the
+//! tests need. Every fixture in `fixtures/<name>/` has:
+//!
+//! - `smbios.bin`: Raw SMBIOS table, in the format of
+//!   `/sys/firmware/dmi/tables/DMI`, with only the structures that are parsed
+//! - `esrt/`: ESRT in the layout of `/sys/firmware/efi/esrt`
+//! - `ec.trace`: EC traffic in the format of `--trace-ec`, for the commands
+//!   that `--versions`, `--power` and `--gpio sleep_l` send
+//!
+//! Versions and other values are plausible, not necessarily ones that were
+//! released. Replacing a fixture with a real capture (`--trace-ec`,
+//! `/sys/firmware/dmi/tables/DMI` and `/sys/firmware/efi/esrt`, serial
+//! numbers replaced) is welcome. When adding a new system, provide the same
+//! files and add it to [`FIXTURES`].
+
+use std::path::PathBuf;
+
+/// Synthetic data of one system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixture {
+    /// Name of the directory in `fixtures/`
+    pub name: &'static str,
+    /// Product name in SMBIOS
+    pub product_name: &'static str,
+    /// Name of the `framework_lib::util::Platform` that the system is detected as
+    pub platform: &'static str,
+}
+
+impl Fixture {
+    pub fn dir(&self) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures")
+            .join(self.name)
+    }
+
+    /// Raw SMBIOS table, without the entrypoint structure
+    pub fn smbios_table(&self) -> Vec<u8> {
+        let path = self.dir().join("smbios.bin");
+        std::fs::read(&path).unwrap_or_else(|err| panic!("{}: {:?}", path.display(), err))
+    }
+
+    /// Directory to read the ESRT from, like `/sys/firmware/efi/esrt`
+    pub fn esrt_dir(&self) -> PathBuf {
+        self.dir().join("esrt")
+    }
+
+    /// Trace to replay the EC from, see `CrosEc::replay`
+    pub fn ec_trace(&self) -> PathBuf {
+        self.dir().join("ec.trace")
+    }
+}
+
+pub const FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "framework13-intel-11th-gen",
+        product_name: "Laptop",
+        platform: "IntelGen11",
+    },
+    Fixture {
+        name: "framework13-intel-12th-gen",
+        product_name: "Laptop (12th Gen Intel Core)",
+        platform: "IntelGen12",
+    },
+    Fixture {
+        name: "framework13-intel-13th-gen",
+        product_name: "Laptop (13th Gen Intel Core)",
+        platform: "IntelGen13",
+    },
+    Fixture {
+        name: "framework13-intel-core-ultra-1",
+        product_name: "Laptop 13 (Intel Core Ultra Series 1)",
+        platform: "IntelCoreUltra1",
+    },
+    Fixture {
+        name: "framework13-amd-7040",
+        product_name: "Laptop 13 (AMD Ryzen 7040Series)",
+        platform: "Framework13Amd",
+    },
+    Fixture {
+        name: "framework16-amd-7040",
+        product_name: "Laptop 16 (AMD Ryzen 7040 Series)",
+        platform: "Framework16",
+    },
+];
+
+/// Fixture by directory name, panics if there is none
+pub fn fixture(name: &str) -> &'static Fixture {
+    FIXTURES
+        .iter()
+        .find(|x| x.name == name)
+        .unwrap_or_else(|| panic!("No fixture named {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_complete() {
+        for fixture in FIXTURES {
+            assert!(!fixture.smbios_table().is_empty(), "{}", fixture.name);
+            assert!(
+                fixture.esrt_dir().join("entries").is_dir(),
+                "{}",
+                fixture.name
+            );
+            assert!(fixture.ec_trace().is_file(), "{}", fixture.name);
+        }
+    }
+}
@@ -5,10 +5,8 @@ fn get_args() -> Vec<String> {
     std::env::args().collect()
 }
 
-fn main() -> Result<(), &'static str> {
+fn main() {
     let args = commandline::parse(&get_args());
-    if (commandline::run_with_args(&args, false)) != 0 {
-        return Err("Fail");
-    }
-    Ok(())
+    // Scripts rely on the exit code, see `commandline::ExitCode`
+    std::process::exit(commandline::run_with_args(&args, false));
 }
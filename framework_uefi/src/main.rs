@@ -17,6 +17,11 @@ fn main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
     let bs = system_table.boot_services();
 
     let args = commandline::uefi::get_args(bs);
+    // Without arguments, let the user choose from a menu instead
+    if args.is_empty() {
+        commandline::uefi::run_menu();
+        return Status::SUCCESS;
+    }
     let args = commandline::parse(&args);
     if commandline::run_with_args(&args, false) == 0 {
         return Status::SUCCESS;
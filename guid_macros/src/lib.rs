@@ -18,11 +18,15 @@ macro_rules! err {
 
 /// Create a `Guid` at compile time.
 ///
+/// Hex digits can be upper or lower case and the GUID may be wrapped in
+/// braces, as it's often written on Windows.
+///
 /// # Example
 ///
 /// ```
 /// use uefi::{guid, Guid};
 /// const EXAMPLE_GUID: Guid = guid!("12345678-9abc-def0-1234-56789abcdef0");
+/// const SAME_GUID: Guid = guid!("{12345678-9ABC-DEF0-1234-56789ABCDEF0}");
 /// ```
 #[proc_macro]
 pub fn guid(args: TokenStream) -> TokenStream {
@@ -46,7 +50,18 @@ pub fn guid(args: TokenStream) -> TokenStream {
 }
 
 fn parse_guid(guid_lit: LitStr) -> Result<(u32, u16, u16, u16, u64), TokenStream2> {
-    let guid_str = guid_lit.value();
+    let value = guid_lit.value();
+    // 1 is for the starting quote
+    let mut offset = 1;
+    let guid_str = match value.strip_prefix('{') {
+        Some(inner) => {
+            offset += 1;
+            inner
+                .strip_suffix('}')
+                .ok_or_else(|| err!(guid_lit, "\"{}\" is missing the closing brace", value))?
+        }
+        None => value.as_str(),
+    };
 
     // We expect a canonical GUID string, such as "12345678-9abc-def0-fedc-ba9876543210"
     if guid_str.len() != 36 {
@@ -57,7 +72,6 @@ fn parse_guid(guid_lit: LitStr) -> Result<(u32, u16, u16, u16, u64), TokenStream
             guid_str.len()
         ));
     }
-    let mut offset = 1; // 1 is for the starting quote
     let mut guid_hex_iter = guid_str.split('-');
     let mut next_guid_int = |len: usize| -> Result<u64, TokenStream2> {
         let guid_hex_component = guid_hex_iter.next().unwrap();
@@ -67,7 +81,8 @@ fn parse_guid(guid_lit: LitStr) -> Result<(u32, u16, u16, u16, u64), TokenStream
             TokenTree::Literal(lit) => lit,
             _ => unreachable!(),
         };
-        // ..so that we can call subspan and nightly users (us) will get the fancy span
+        // ..so that we can call subspan. It only works on nightly, on stable the
+        // error points at the whole literal instead
         let span = lit
             .subspan(offset..offset + guid_hex_component.len())
             .unwrap_or_else(|| lit.span());